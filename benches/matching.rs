@@ -0,0 +1,53 @@
+extern crate browser_engine_suburi;
+
+use browser_engine_suburi::generate::{self, GeneratorOptions, Rng, SelectorMix};
+use browser_engine_suburi::{css, html, style};
+use std::time::{Duration, Instant};
+
+/**
+ * セレクターのマッチングのベンチマーク（cargo bench --features testing）。
+ * 同じドキュメントに、ルールの数を変えたスタイルシートを当てて、
+ * style_tree（RuleIndex で候補のルールだけを試す）と、すべてのルールをすべての要素で試すもの（前のやり方）の時間を比べる
+ */
+
+const RUNS: u32 = 5;
+
+fn main() {
+  let document_options = GeneratorOptions { max_depth: 7, max_children: 5, class_count: 2000, id_count: 500, ..GeneratorOptions::default() };
+  let root = html::parse(generate::document(&mut Rng::new(1), &document_options)).unwrap();
+
+  println!("{:>8} {:>14} {:>14} {:>8}", "rules", "indexed", "linear", "speedup");
+  for &rule_count in &[100, 1000, 5000] {
+    // 大きなスタイルシートのように、ほとんどのルールがクラスか ID で始まるもの
+    let selector_mix = SelectorMix { tag: 1, class: 8, id: 2, universal: 0, compound: 4 };
+    let options = GeneratorOptions { rule_count: rule_count, selector_mix: selector_mix, ..document_options.clone() };
+    let stylesheet = css::parse_with_diagnostics(generate::stylesheet(&mut Rng::new(2), &options)).0;
+
+    let indexed = measure(|| {
+      style::style_tree(&root, &stylesheet);
+    });
+    // すべてのルールのセレクターを、すべての要素で試す
+    let linear = measure(|| {
+      for rule in &stylesheet.rules {
+        style::query_selector_all(&root, &rule.selectors);
+      }
+    });
+    println!("{:>8} {:>12.2}ms {:>12.2}ms {:>7.1}x", rule_count, millis(indexed), millis(linear), millis(linear) / millis(indexed));
+  }
+}
+
+// RUNS 回のうち、いちばん速かった時間
+fn measure<F: FnMut()>(mut f: F) -> Duration {
+  return (0..RUNS)
+    .map(|_| {
+      let start = Instant::now();
+      f();
+      start.elapsed()
+    })
+    .min()
+    .unwrap_or_default();
+}
+
+fn millis(duration: Duration) -> f64 {
+  return duration.as_secs_f64() * 1000.0;
+}
@@ -0,0 +1,85 @@
+extern crate browser_engine_suburi;
+
+use browser_engine_suburi::generate::{self, GeneratorOptions, Rng};
+use browser_engine_suburi::css;
+use browser_engine_suburi::html::{self, Tokenizer, Tokens};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/**
+ * HTML のトークン化のベンチマーク（cargo bench --features testing）。
+ * 入力を借りたトークン（Tokens）と、トークンごとに文字列を作るもの（Tokenizer。前のやり方と同じ）の、時間とメモリの確保の回数を比べる。
+ * ツリーまで作る html::parse（入力を借りたトークンから、ノードの文字列だけを作る）と、スタイルシートを作る css::parse も測る。
+ * DOM のノードと CSS の値は入力を借りずに文字列を持つので、html::parse と css::parse の確保の回数はほぼノードと値の数になる
+ */
+
+const RUNS: u32 = 5;
+
+// メモリを確保した回数を数える
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    return System.alloc(layout);
+  }
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout);
+  }
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    return System.realloc(ptr, layout, new_size);
+  }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn main() {
+  println!("{:>8} {:>10} {:>22} {:>22} {:>22} {:>10} {:>22}", "children", "bytes", "owned tokens", "borrowed tokens", "html::parse", "css bytes", "css::parse");
+  for &max_children in &[4, 8, 12] {
+    let options = GeneratorOptions { max_depth: 6, max_children: max_children, rule_count: 100 * max_children, ..GeneratorOptions::default() };
+    let mut rng = Rng::new(1);
+    let source = generate::document(&mut rng, &options);
+    let stylesheet = generate::stylesheet(&mut rng, &options);
+
+    let owned = measure(|| Tokenizer::new(source.clone()).count());
+    let borrowed = measure(|| Tokens::new(&source).count());
+    let parsed = measure(|| html::parse(source.clone()).map(|_| 0).unwrap_or(0));
+    let rules = measure(|| css::parse_with_diagnostics(stylesheet.clone()).0.rules.len());
+    println!(
+      "{:>8} {:>10} {:>22} {:>22} {:>22} {:>10} {:>22}",
+      max_children,
+      source.len(),
+      format(owned),
+      format(borrowed),
+      format(parsed),
+      stylesheet.len(),
+      format(rules)
+    );
+  }
+}
+
+// RUNS 回のうち、いちばん速かった時間と、1 回あたりのメモリの確保の回数
+fn measure<F: FnMut() -> usize>(mut f: F) -> (Duration, usize) {
+  let mut allocations = 0;
+  let time = (0..RUNS)
+    .map(|_| {
+      let before = ALLOCATIONS.load(Ordering::Relaxed);
+      let start = Instant::now();
+      f();
+      let elapsed = start.elapsed();
+      allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+      elapsed
+    })
+    .min()
+    .unwrap_or_default();
+  return (time, allocations);
+}
+
+fn format((time, allocations): (Duration, usize)) -> String {
+  return format!("{:.2}ms {:>8} allocs", time.as_secs_f64() * 1000.0, allocations);
+}
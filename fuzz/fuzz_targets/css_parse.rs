@@ -0,0 +1,11 @@
+#![no_main]
+
+use browser_engine_suburi::css;
+use libfuzzer_sys::fuzz_target;
+
+// 壊れた CSS でもパニックせずに Err を返すこと
+fuzz_target!(|data: &[u8]| {
+  if let Ok(source) = std::str::from_utf8(data) {
+    let _ = css::parse(source.to_string());
+  }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use browser_engine_suburi::html;
+use libfuzzer_sys::fuzz_target;
+
+// 壊れた HTML でもパニックせずに Err を返すこと
+fuzz_target!(|data: &[u8]| {
+  if let Ok(source) = std::str::from_utf8(data) {
+    let _ = html::parse(source.to_string());
+  }
+});
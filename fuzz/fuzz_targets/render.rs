@@ -0,0 +1,21 @@
+#![no_main]
+
+use browser_engine_suburi::layout::Rect;
+use browser_engine_suburi::options::EngineOptions;
+use browser_engine_suburi::render_source;
+use libfuzzer_sys::fuzz_target;
+
+// 入力を最初の NUL で HTML と CSS に分けて、パイプライン全体を通す
+fuzz_target!(|data: &[u8]| {
+  let source = match std::str::from_utf8(data) {
+    Ok(source) => source,
+    Err(_) => return,
+  };
+  let (html, css) = match source.find('\0') {
+    Some(index) => (&source[..index], &source[index + 1..]),
+    None => (source, ""),
+  };
+  // 小さいビューポートで十分（大きいと 1 回の実行が遅くなる）
+  let viewport = Rect { x: 0.0, y: 0.0, width: 64.0, height: 64.0 };
+  let _ = render_source(html, css, viewport, &EngineOptions::default());
+});
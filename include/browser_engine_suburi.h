@@ -0,0 +1,51 @@
+#ifndef BROWSER_ENGINE_SUBURI_H
+#define BROWSER_ENGINE_SUBURI_H
+
+/* This file is generated by cbindgen. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum SuburiStatus {
+  SUBURI_STATUS_OK = 0,
+  SUBURI_STATUS_NULL_POINTER = 1,
+  SUBURI_STATUS_INVALID_UTF8 = 2,
+  SUBURI_STATUS_PARSE_ERROR = 3,
+  SUBURI_STATUS_NO_DOCUMENT = 4,
+  SUBURI_STATUS_BUFFER_TOO_SMALL = 5,
+  SUBURI_STATUS_RENDER_ERROR = 6,
+} SuburiStatus;
+
+typedef struct SuburiEngine SuburiEngine;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+struct SuburiEngine *suburi_engine_new(void);
+
+void suburi_engine_free(struct SuburiEngine *engine);
+
+enum SuburiStatus suburi_engine_load_html(struct SuburiEngine *engine, const char *source);
+
+enum SuburiStatus suburi_engine_load_css(struct SuburiEngine *engine, const char *source);
+
+enum SuburiStatus suburi_engine_set_viewport(struct SuburiEngine *engine,
+                                             uint32_t width,
+                                             uint32_t height);
+
+enum SuburiStatus suburi_engine_set_scroll(struct SuburiEngine *engine, float x, float y);
+
+enum SuburiStatus suburi_engine_render(struct SuburiEngine *engine,
+                                       uint8_t *buffer,
+                                       uintptr_t buffer_len);
+
+const char *suburi_engine_last_error(const struct SuburiEngine *engine);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BROWSER_ENGINE_SUBURI_H */
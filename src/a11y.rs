@@ -0,0 +1,231 @@
+use css::Value::Keyword;
+use dom::{ElementData, Node, NodeType};
+use std::collections::HashMap;
+use style::{Display, StyledNode};
+
+/**
+ * スタイルツリーからアクセシビリティツリーを作るところ。
+ * ロールはタグ名（role 属性があればそれ）から、名前は aria-label、aria-labelledby、<label for>、
+ * alt、中のテキスト、title の順に探す。
+ * display: none、visibility: hidden、aria-hidden="true"、hidden 属性の要素はツリーに入れない
+ * （visibility: hidden の中でも visibility: visible の子孫は入れる）
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessibleNode {
+  pub role: String,
+  pub name: Option<String>,
+  pub level: Option<u32>, // 見出しのレベル
+  pub children: Vec<AccessibleNode>,
+}
+
+// 中のテキストを名前にするロール
+const NAME_FROM_CONTENT: [&'static str; 9] = ["button", "cell", "checkbox", "columnheader", "heading", "link", "option", "radio", "rowheader"];
+
+struct Context<'a> {
+  ids: HashMap<&'a str, &'a Node>,     // aria-labelledby で参照する要素
+  labels: HashMap<&'a str, &'a Node>, // <label for="id"> の id から
+}
+
+pub fn accessibility_tree(root: &StyledNode) -> AccessibleNode {
+  let mut context = Context { ids: HashMap::new(), labels: HashMap::new() };
+  index(root.node, &mut context);
+
+  let mut nodes = build(root, false, &context);
+  if nodes.len() == 1 && nodes[0].role == "document" {
+    return nodes.remove(0);
+  }
+  return AccessibleNode { role: "document".to_string(), name: None, level: None, children: nodes };
+}
+
+fn index<'a>(node: &'a Node, context: &mut Context<'a>) {
+  if let NodeType::Element(ref elem) = node.node_type {
+    if let Some(id) = elem.id() {
+      context.ids.insert(id, node);
+    }
+    if elem.tag_name == "label" {
+      if let Some(target) = elem.attributes.get("for") {
+        context.labels.insert(target, node);
+      }
+    }
+  }
+  for child in &node.children {
+    index(child, context);
+  }
+}
+
+// node をツリーに入れるときのノード。飛ばす要素は子だけを返す
+fn build(node: &StyledNode, hidden: bool, context: &Context) -> Vec<AccessibleNode> {
+  if node.display() == Display::None {
+    return Vec::new();
+  }
+  let hidden = match node.value("visibility") {
+    Some(Keyword(ref value)) if value == "hidden" || value == "collapse" => true,
+    Some(Keyword(ref value)) if value == "visible" => false,
+    _ => hidden,
+  };
+
+  let elem = match node.node.node_type {
+    NodeType::Text(ref text) => {
+      let text = collapse_whitespace(text);
+      if hidden || text.is_empty() {
+        return Vec::new();
+      }
+      return vec![AccessibleNode { role: "text".to_string(), name: Some(text), level: None, children: Vec::new() }];
+    }
+    NodeType::Element(ref elem) => elem,
+    NodeType::Comment(_) => return Vec::new(),
+  };
+  if elem.attributes.get("aria-hidden").map_or(false, |value| value == "true") || elem.attributes.contains_key("hidden") {
+    return Vec::new();
+  }
+
+  let children: Vec<AccessibleNode> = node.children.iter().flat_map(|child| build(child, hidden, context)).collect();
+  let role = match role(elem) {
+    Some(role) => role,
+    None => return Vec::new(), // <input type="hidden"> など
+  };
+  if hidden || role == "none" || role == "presentation" {
+    return children;
+  }
+
+  let level = if role == "heading" { heading_level(elem) } else { None };
+  let name = accessible_name(node, elem, &role, context);
+  // 名前が中のテキストと同じなら、テキストのノードは重複するので入れない
+  let children = if NAME_FROM_CONTENT.contains(&&*role) && !elem.attributes.contains_key("aria-label") {
+    without_text(children)
+  } else {
+    children
+  };
+  return vec![AccessibleNode { role: role, name: name, level: level, children: children }];
+}
+
+// テキストと、テキストしか含まない名前のない generic を取り除く
+fn without_text(nodes: Vec<AccessibleNode>) -> Vec<AccessibleNode> {
+  return nodes
+    .into_iter()
+    .filter(|node| node.role != "text")
+    .map(|node| AccessibleNode { children: without_text(node.children), ..node })
+    .filter(|node| !(node.role == "generic" && node.name.is_none() && node.children.is_empty()))
+    .collect();
+}
+
+fn role(elem: &ElementData) -> Option<String> {
+  if let Some(role) = elem.attributes.get("role").and_then(|role| role.split_whitespace().next()) {
+    return Some(role.to_ascii_lowercase());
+  }
+  let role = match &*elem.tag_name {
+    "html" => "document",
+    "a" if elem.attributes.contains_key("href") => "link",
+    "article" => "article",
+    "aside" => "complementary",
+    "button" => "button",
+    "footer" => "contentinfo",
+    "form" => "form",
+    "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => "heading",
+    "header" => "banner",
+    "hr" => "separator",
+    "img" if elem.attributes.get("alt").map_or(false, |alt| alt.is_empty()) => "presentation",
+    "img" => "img",
+    "input" => return input_role(elem).map(|role| role.to_string()),
+    "li" => "listitem",
+    "main" => "main",
+    "nav" => "navigation",
+    "ol" | "ul" => "list",
+    "option" => "option",
+    "p" => "paragraph",
+    "section" => "region",
+    "select" => "combobox",
+    "table" => "table",
+    "td" => "cell",
+    "textarea" => "textbox",
+    "th" => "columnheader",
+    "tr" => "row",
+    _ => "generic",
+  };
+  return Some(role.to_string());
+}
+
+fn input_role(elem: &ElementData) -> Option<&'static str> {
+  let input_type = elem.attributes.get("type").map(|value| value.to_ascii_lowercase());
+  return match input_type.as_ref().map(|value| &**value) {
+    Some("hidden") => None,
+    Some("checkbox") => Some("checkbox"),
+    Some("radio") => Some("radio"),
+    Some("button") | Some("submit") | Some("reset") | Some("image") => Some("button"),
+    Some("range") => Some("slider"),
+    Some("number") => Some("spinbutton"),
+    Some("search") => Some("searchbox"),
+    _ => Some("textbox"),
+  };
+}
+
+fn heading_level(elem: &ElementData) -> Option<u32> {
+  if let Some(level) = elem.attributes.get("aria-level").and_then(|level| level.trim().parse().ok()) {
+    return Some(level);
+  }
+  return elem.tag_name.get(1..).and_then(|level| level.parse().ok());
+}
+
+fn accessible_name(node: &StyledNode, elem: &ElementData, role: &str, context: &Context) -> Option<String> {
+  if let Some(label) = elem.attributes.get("aria-label").map(|label| collapse_whitespace(label)).filter(|label| !label.is_empty()) {
+    return Some(label);
+  }
+  if let Some(ids) = elem.attributes.get("aria-labelledby") {
+    let label: Vec<String> = ids.split_whitespace().filter_map(|id| context.ids.get(id)).map(|node| dom_text(node)).collect();
+    let label = collapse_whitespace(&label.join(" "));
+    if !label.is_empty() {
+      return Some(label);
+    }
+  }
+  if let Some(label) = elem.id().and_then(|id| context.labels.get(&**id)).map(|node| collapse_whitespace(&dom_text(node))).filter(|label| !label.is_empty()) {
+    return Some(label);
+  }
+  if elem.tag_name == "img" || (elem.tag_name == "input" && role == "button") {
+    if let Some(alt) = elem.attributes.get("alt").filter(|alt| !alt.is_empty()) {
+      return Some(alt.clone());
+    }
+  }
+  if elem.tag_name == "input" && role == "button" {
+    let default = match elem.attributes.get("type").map(|value| &**value) {
+      Some("reset") => "Reset",
+      Some("submit") => "Submit",
+      _ => "",
+    };
+    let value = elem.attributes.get("value").map_or(default, |value| &**value);
+    if !value.is_empty() {
+      return Some(value.to_string());
+    }
+  }
+  if NAME_FROM_CONTENT.contains(&role) {
+    let text = collapse_whitespace(&styled_text(node));
+    if !text.is_empty() {
+      return Some(text);
+    }
+  }
+  return elem.attributes.get("title").cloned();
+}
+
+// 表示されているテキストだけをつなげる
+fn styled_text(node: &StyledNode) -> String {
+  if node.display() == Display::None {
+    return String::new();
+  }
+  return match node.node.node_type {
+    NodeType::Text(ref text) => text.clone(),
+    NodeType::Element(_) => node.children.iter().map(styled_text).collect::<Vec<String>>().join(" "),
+    NodeType::Comment(_) => String::new(),
+  };
+}
+
+fn dom_text(node: &Node) -> String {
+  return match node.node_type {
+    NodeType::Text(ref text) => text.clone(),
+    NodeType::Element(_) => node.children.iter().map(dom_text).collect::<Vec<String>>().join(" "),
+    NodeType::Comment(_) => String::new(),
+  };
+}
+
+fn collapse_whitespace(text: &str) -> String {
+  return text.split_whitespace().collect::<Vec<&str>>().join(" ");
+}
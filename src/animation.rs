@@ -0,0 +1,480 @@
+use css::{Color, Keyframes, StyleSheet, Value};
+use error::EngineError;
+use frame;
+use layout::{self, Rect};
+use paint::{self, Canvas};
+use profile::{self, Counts, Timings};
+use style::{self, PropertyMap};
+use {count, initial_containing_block, LoadedDocument};
+
+/**
+ * CSS アニメーション（animation と @keyframes）とトランジション（transition）を、時刻ごとの値にして描く。
+ *
+ *   .box { animation: slide 2s ease-in-out infinite alternate; }
+ *   @keyframes slide { from { margin-left: 0px; } to { margin-left: 200px; } }
+ *
+ * スタイルを適用した値（compute_values の、ドキュメント順のノードごとの PropertyMap）に、時刻 time 秒での
+ * アニメーションの値を上書きする（sample）。ルールとの照合は最初に 1 回だけ行い、フレームごとには
+ * レイアウトと描画だけをやり直す。値が前のフレームと同じなら前のキャンバスをそのまま使う。
+ *
+ * トランジションは、before の値から、スタイルシートを足した after の値に変わったときに、
+ * after の transition で指定したプロパティを時間をかけて変える。
+ *
+ * 補間するのは同じ単位の長さ、数値、色（と、同じ長さのそれらのリスト）。ほかの値は途中（50%）で切り替える。
+ * カンマで区切った複数のアニメーションやトランジションにはまだ対応していない
+ */
+
+// 時刻を決めなかったときの、infinite のアニメーションを描く回数
+const INFINITE_PREVIEW_ITERATIONS: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimingFunction {
+  Linear,
+  CubicBezier(f32, f32, f32, f32),
+  Steps(u32, bool), // 段の数と、各区間の最初で変わる（jump-start）か
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+  Normal,
+  Reverse,
+  Alternate,
+  AlternateReverse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FillMode {
+  None,
+  Forwards,
+  Backwards,
+  Both,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+  pub name: String,
+  pub duration: f32, // 秒
+  pub delay: f32,
+  pub timing: TimingFunction,
+  pub iterations: f32, // infinite なら f32::INFINITY
+  pub direction: Direction,
+  pub fill_mode: FillMode,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+  pub property: String, // all かプロパティ名
+  pub duration: f32,
+  pub delay: f32,
+  pub timing: TimingFunction,
+}
+
+impl TimingFunction {
+  pub fn from_value(value: &Value) -> Option<TimingFunction> {
+    return match *value {
+      Value::Keyword(ref keyword) => match &**keyword {
+        "linear" => Some(TimingFunction::Linear),
+        "ease" => Some(TimingFunction::CubicBezier(0.25, 0.1, 0.25, 1.0)),
+        "ease-in" => Some(TimingFunction::CubicBezier(0.42, 0.0, 1.0, 1.0)),
+        "ease-out" => Some(TimingFunction::CubicBezier(0.0, 0.0, 0.58, 1.0)),
+        "ease-in-out" => Some(TimingFunction::CubicBezier(0.42, 0.0, 0.58, 1.0)),
+        "step-start" => Some(TimingFunction::Steps(1, true)),
+        "step-end" => Some(TimingFunction::Steps(1, false)),
+        _ => None,
+      },
+      Value::Function(ref name, ref args) if name == "cubic-bezier" => match *args.as_slice() {
+        [Value::Number(x1), Value::Number(y1), Value::Number(x2), Value::Number(y2)] if (0.0..=1.0).contains(&x1) && (0.0..=1.0).contains(&x2) => {
+          Some(TimingFunction::CubicBezier(x1, y1, x2, y2))
+        }
+        _ => None,
+      },
+      Value::Function(ref name, ref args) if name == "steps" => {
+        let steps = match args.first() {
+          Some(&Value::Number(steps)) if steps >= 1.0 => steps as u32,
+          _ => return None,
+        };
+        let start = match args.get(1) {
+          Some(Value::Keyword(position)) => position == "start" || position == "jump-start",
+          _ => false,
+        };
+        Some(TimingFunction::Steps(steps, start))
+      }
+      _ => None,
+    };
+  }
+
+  // 進み具合 t（0 から 1）を、値の変わり具合にする
+  pub fn apply(&self, t: f32) -> f32 {
+    return match *self {
+      TimingFunction::Linear => t,
+      TimingFunction::CubicBezier(x1, y1, x2, y2) => cubic_bezier(x1, y1, x2, y2, t),
+      TimingFunction::Steps(steps, start) => {
+        let step = (t * steps as f32).floor() + if start { 1.0 } else { 0.0 };
+        (step / steps as f32).clamp(0.0, 1.0)
+      }
+    };
+  }
+}
+
+// (0, 0)、(x1, y1)、(x2, y2)、(1, 1) の曲線で、x が t になるところの y。x は単調に増えるので二分法で探す
+fn cubic_bezier(x1: f32, y1: f32, x2: f32, y2: f32, t: f32) -> f32 {
+  let bezier = |a: f32, b: f32, s: f32| 3.0 * a * s * (1.0 - s) * (1.0 - s) + 3.0 * b * s * s * (1.0 - s) + s * s * s;
+  let (mut low, mut high) = (0.0, 1.0);
+  for _ in 0..32 {
+    let middle = (low + high) / 2.0;
+    if bezier(x1, x2, middle) < t {
+      low = middle;
+    } else {
+      high = middle;
+    }
+  }
+  return bezier(y1, y2, (low + high) / 2.0);
+}
+
+// ショートハンドの値を 1 つずつに
+fn parts(value: Option<&Value>) -> Vec<&Value> {
+  return match value {
+    Some(Value::List(values)) => values.iter().collect(),
+    Some(value) => vec![value],
+    None => Vec::new(),
+  };
+}
+
+fn keyword(value: Option<&Value>) -> Option<&str> {
+  return match value {
+    Some(Value::Keyword(keyword)) => Some(keyword),
+    _ => None,
+  };
+}
+
+impl Animation {
+  // animation のショートハンドの後に animation-* で上書きする。名前がない（none の）ときは None
+  pub fn from_values(values: &PropertyMap) -> Option<Animation> {
+    let mut animation = Animation {
+      name: String::new(),
+      duration: 0.0,
+      delay: 0.0,
+      timing: TimingFunction::CubicBezier(0.25, 0.1, 0.25, 1.0),
+      iterations: 1.0,
+      direction: Direction::Normal,
+      fill_mode: FillMode::None,
+    };
+    let mut times = 0;
+    for part in parts(values.get("animation")) {
+      if let Some(seconds) = part.to_seconds() {
+        if times == 0 {
+          animation.duration = seconds;
+        } else {
+          animation.delay = seconds;
+        }
+        times += 1;
+      } else if let Some(timing) = TimingFunction::from_value(part) {
+        animation.timing = timing;
+      } else if let Value::Number(iterations) = *part {
+        animation.iterations = iterations;
+      } else if let Value::Keyword(ref keyword) = *part {
+        if keyword == "infinite" {
+          animation.iterations = f32::INFINITY;
+        } else if let Some(direction) = direction(keyword) {
+          animation.direction = direction;
+        } else if let Some(fill_mode) = fill_mode(keyword).filter(|_| keyword != "none" || !animation.name.is_empty()) {
+          animation.fill_mode = fill_mode;
+        } else if keyword != "running" && keyword != "paused" && animation.name.is_empty() {
+          animation.name = keyword.clone();
+        }
+      }
+    }
+
+    if let Some(name) = keyword(values.get("animation-name")) {
+      animation.name = name.to_string();
+    }
+    if let Some(duration) = values.get("animation-duration").and_then(Value::to_seconds) {
+      animation.duration = duration;
+    }
+    if let Some(delay) = values.get("animation-delay").and_then(Value::to_seconds) {
+      animation.delay = delay;
+    }
+    if let Some(timing) = values.get("animation-timing-function").and_then(TimingFunction::from_value) {
+      animation.timing = timing;
+    }
+    match values.get("animation-iteration-count") {
+      Some(&Value::Number(iterations)) => animation.iterations = iterations,
+      Some(Value::Keyword(infinite)) if infinite == "infinite" => animation.iterations = f32::INFINITY,
+      _ => {}
+    }
+    if let Some(direction) = keyword(values.get("animation-direction")).and_then(direction) {
+      animation.direction = direction;
+    }
+    if let Some(fill_mode) = keyword(values.get("animation-fill-mode")).and_then(fill_mode) {
+      animation.fill_mode = fill_mode;
+    }
+
+    if animation.name.is_empty() || animation.name == "none" || animation.iterations.is_nan() || animation.iterations < 0.0 {
+      return None;
+    }
+    animation.duration = animation.duration.max(0.0);
+    return Some(animation);
+  }
+
+  // 値が変わらなくなる時刻。infinite なら INFINITE_PREVIEW_ITERATIONS 回分まで
+  pub fn end_time(&self) -> f32 {
+    let iterations = if self.iterations.is_finite() { self.iterations } else { INFINITE_PREVIEW_ITERATIONS };
+    return self.delay.max(0.0) + self.duration * iterations;
+  }
+
+  // time 秒での @keyframes の中の位置（0 から 1、タイミング関数の前）。アニメーションの値を使わないときは None
+  pub fn progress(&self, time: f32) -> Option<f32> {
+    let elapsed = time - self.delay;
+    let fills_backwards = self.fill_mode == FillMode::Backwards || self.fill_mode == FillMode::Both;
+    let fills_forwards = self.fill_mode == FillMode::Forwards || self.fill_mode == FillMode::Both;
+    if elapsed < 0.0 {
+      return if fills_backwards { Some(self.directed(0, 0.0)) } else { None };
+    }
+    let total = self.duration * self.iterations;
+    if self.duration <= 0.0 || elapsed >= total {
+      if !fills_forwards {
+        return None;
+      }
+      // 最後の回の終わり。0.5 回のように途中で終わるならその位置
+      let fraction = self.iterations.fract();
+      return Some(if fraction == 0.0 { self.directed((self.iterations as usize).saturating_sub(1), 1.0) } else { self.directed(self.iterations as usize, fraction) });
+    }
+    let position = elapsed / self.duration;
+    return Some(self.directed(position.floor() as usize, position.fract()));
+  }
+
+  // iteration 回目（0 から）の fraction の位置を、向きに合わせる
+  fn directed(&self, iteration: usize, fraction: f32) -> f32 {
+    let reversed = match self.direction {
+      Direction::Normal => false,
+      Direction::Reverse => true,
+      Direction::Alternate => !iteration.is_multiple_of(2),
+      Direction::AlternateReverse => iteration.is_multiple_of(2),
+    };
+    return if reversed { 1.0 - fraction } else { fraction };
+  }
+}
+
+fn direction(keyword: &str) -> Option<Direction> {
+  return match keyword {
+    "normal" => Some(Direction::Normal),
+    "reverse" => Some(Direction::Reverse),
+    "alternate" => Some(Direction::Alternate),
+    "alternate-reverse" => Some(Direction::AlternateReverse),
+    _ => None,
+  };
+}
+
+fn fill_mode(keyword: &str) -> Option<FillMode> {
+  return match keyword {
+    "none" => Some(FillMode::None),
+    "forwards" => Some(FillMode::Forwards),
+    "backwards" => Some(FillMode::Backwards),
+    "both" => Some(FillMode::Both),
+    _ => None,
+  };
+}
+
+impl Transition {
+  // transition のショートハンドの後に transition-* で上書きする。時間が 0 なら None（すぐに変わる）
+  pub fn from_values(values: &PropertyMap) -> Option<Transition> {
+    let mut transition = Transition { property: "all".to_string(), duration: 0.0, delay: 0.0, timing: TimingFunction::CubicBezier(0.25, 0.1, 0.25, 1.0) };
+    let mut times = 0;
+    for part in parts(values.get("transition")) {
+      if let Some(seconds) = part.to_seconds() {
+        if times == 0 {
+          transition.duration = seconds;
+        } else {
+          transition.delay = seconds;
+        }
+        times += 1;
+      } else if let Some(timing) = TimingFunction::from_value(part) {
+        transition.timing = timing;
+      } else if let Value::Keyword(ref property) = *part {
+        transition.property = property.clone();
+      }
+    }
+    if let Some(property) = keyword(values.get("transition-property")) {
+      transition.property = property.to_string();
+    }
+    if let Some(duration) = values.get("transition-duration").and_then(Value::to_seconds) {
+      transition.duration = duration;
+    }
+    if let Some(delay) = values.get("transition-delay").and_then(Value::to_seconds) {
+      transition.delay = delay;
+    }
+    if let Some(timing) = values.get("transition-timing-function").and_then(TimingFunction::from_value) {
+      transition.timing = timing;
+    }
+    if transition.duration <= 0.0 || transition.property == "none" {
+      return None;
+    }
+    return Some(transition);
+  }
+
+  fn applies_to(&self, property: &str) -> bool {
+    if property.starts_with("animation") || property.starts_with("transition") {
+      return false;
+    }
+    return self.property == "all" || self.property == property;
+  }
+}
+
+// from から to へ t（0 から 1）だけ進んだ値
+pub fn interpolate(from: &Value, to: &Value, t: f32) -> Value {
+  let lerp = |a: f32, b: f32| a + (b - a) * t;
+  return match (from, to) {
+    (&Value::Length(a, ref unit), &Value::Length(b, ref other)) if unit == other => Value::Length(lerp(a, b), unit.clone()),
+    (&Value::Number(a), &Value::Number(b)) => Value::Number(lerp(a, b)),
+    (&Value::ColorValue(a), &Value::ColorValue(b)) => {
+      let channel = |a: u8, b: u8| lerp(a as f32, b as f32).round().clamp(0.0, 255.0) as u8;
+      Value::ColorValue(Color { r: channel(a.r, b.r), g: channel(a.g, b.g), b: channel(a.b, b.b), a: channel(a.a, b.a) })
+    }
+    (Value::List(a), Value::List(b)) if a.len() == b.len() => Value::List(a.iter().zip(b).map(|(a, b)| interpolate(a, b, t)).collect()),
+    _ => {
+      if t < 0.5 {
+        from.clone()
+      } else {
+        to.clone()
+      }
+    }
+  };
+}
+
+// 時刻 time 秒でのノードごとの値。before があれば、そこから values に変わるトランジションも進める
+pub fn sample(values: &[PropertyMap], before: Option<&[PropertyMap]>, stylesheet: &StyleSheet, time: f32) -> Vec<PropertyMap> {
+  return values
+    .iter()
+    .enumerate()
+    .map(|(index, after)| {
+      let mut sampled = after.clone();
+      if let (Some(before), Some(transition)) = (before.and_then(|before| before.get(index)), Transition::from_values(after)) {
+        let progress = ((time - transition.delay) / transition.duration).clamp(0.0, 1.0);
+        let t = transition.timing.apply(progress);
+        for (name, to) in after {
+          if let Some(from) = before.get(name).filter(|from| *from != to && transition.applies_to(name)) {
+            sampled.insert(name.clone(), interpolate(from, to, t));
+          }
+        }
+      }
+      if let Some(animation) = Animation::from_values(after) {
+        if let (Some(keyframes), Some(progress)) = (stylesheet.find_keyframes(&animation.name), animation.progress(time)) {
+          let base = sampled.clone();
+          apply_keyframes(keyframes, progress, animation.timing, &base, &mut sampled);
+        }
+      }
+      sampled
+    })
+    .collect();
+}
+
+// keyframes の progress の位置の値を out に書く。from（0%）か to（100%）がないプロパティは、その端では base の値にする。
+// キーフレームの animation-timing-function は、そのキーフレームから次までの区間に使う
+fn apply_keyframes(keyframes: &Keyframes, progress: f32, timing: TimingFunction, base: &PropertyMap, out: &mut PropertyMap) {
+  let mut names: Vec<&str> = Vec::new();
+  for frame in &keyframes.frames {
+    for declaration in &frame.declarations {
+      if !declaration.name.starts_with("animation") && !names.contains(&&*declaration.name) {
+        names.push(&declaration.name);
+      }
+    }
+  }
+  for name in names {
+    let mut stops: Vec<(f32, &Value, TimingFunction)> = Vec::new();
+    for frame in &keyframes.frames {
+      if let Some(declaration) = frame.declarations.iter().rev().find(|declaration| declaration.name == name) {
+        let frame_timing = frame.declarations.iter().rev().find(|declaration| declaration.name == "animation-timing-function").and_then(|declaration| TimingFunction::from_value(&declaration.value));
+        stops.push((frame.offset, &declaration.value, frame_timing.unwrap_or(timing)));
+      }
+    }
+    if let Some(value) = base.get(name) {
+      if stops.first().map_or(false, |stop| stop.0 > 0.0) {
+        stops.insert(0, (0.0, value, timing));
+      }
+      if stops.last().map_or(false, |stop| stop.0 < 1.0) {
+        stops.push((1.0, value, timing));
+      }
+    }
+    let value = match stops.iter().rposition(|stop| stop.0 <= progress) {
+      Some(index) if index + 1 < stops.len() => {
+        let (from, to) = (stops[index], stops[index + 1]);
+        let local = if to.0 > from.0 { (progress - from.0) / (to.0 - from.0) } else { 1.0 };
+        interpolate(from.1, to.1, from.2.apply(local))
+      }
+      Some(index) => stops[index].1.clone(),
+      None => match stops.first() {
+        Some(stop) => stop.1.clone(),
+        None => continue,
+      },
+    };
+    out.insert(name.to_string(), value);
+  }
+}
+
+// すべてのアニメーションとトランジションが終わる時刻（秒）。infinite のアニメーションは INFINITE_PREVIEW_ITERATIONS 回分
+pub fn duration(values: &[PropertyMap], before: Option<&[PropertyMap]>) -> f32 {
+  let mut end: f32 = 0.0;
+  for value in values {
+    if let Some(animation) = Animation::from_values(value) {
+      end = end.max(animation.end_time());
+    }
+    if let (Some(_), Some(transition)) = (before, Transition::from_values(value)) {
+      end = end.max(transition.delay.max(0.0) + transition.duration);
+    }
+  }
+  return end;
+}
+
+// 描いたフレーム。interval はフレームの間の時間（秒）
+pub struct Frames {
+  pub frames: Vec<Canvas>,
+  pub interval: f32,
+  pub timings: Timings,
+  pub counts: Counts, // pixels はすべてのフレームの合計
+}
+
+// 0 秒から duration 秒（None ならアニメーションとトランジションが終わるまで）を frame_count 枚に等分して描く。
+// transition_to があれば、0 秒でそれを足した値に変わったとしてトランジションを進める
+pub fn render_frames(document: &LoadedDocument, transition_to: Option<&StyleSheet>, viewport: Rect, frame_count: usize, duration: Option<f32>) -> Result<Frames, EngineError> {
+  let mut timings = Timings::default();
+  let (viewport, scale) = document.layout_viewport(viewport);
+  let stylesheet = match transition_to {
+    Some(sheet) => {
+      let mut stylesheet = document.stylesheet.clone();
+      stylesheet.append(sheet.clone());
+      stylesheet
+    }
+    None => document.stylesheet.clone(),
+  };
+  let (values, before) = profile::time(&mut timings.style, || {
+    let values = style::compute_values_with_viewport(&document.root_node, &stylesheet, Some(viewport));
+    let before = transition_to.map(|_| style::compute_values_with_viewport(&document.root_node, &document.stylesheet, Some(viewport)));
+    (values, before)
+  });
+  let duration = duration.unwrap_or_else(|| self::duration(&values, before.as_deref())).max(0.0);
+  let frame_count = frame_count.max(1);
+  let interval = if frame_count > 1 { duration / (frame_count - 1) as f32 } else { 0.0 };
+
+  let mut frames: Vec<Canvas> = Vec::with_capacity(frame_count);
+  let mut previous: Option<Vec<PropertyMap>> = None;
+  let mut counts = Counts::default();
+  for index in 0..frame_count {
+    let sampled = profile::time(&mut timings.style, || sample(&values, before.as_deref(), &stylesheet, interval * index as f32));
+    if let (Some(previous), Some(canvas)) = (previous.as_ref(), frames.last()) {
+      if *previous == sampled {
+        let canvas = canvas.clone();
+        frames.push(canvas);
+        continue;
+      }
+    }
+    let style_root = profile::time(&mut timings.style, || style::style_tree_from_values(&document.root_node, &sampled));
+    let layout_root = profile::time(&mut timings.layout, || layout::layout_tree_with(&style_root, initial_containing_block(viewport), document.fonts, &document.images))?;
+    let display_list = profile::time(&mut timings.display_list, || frame::build_display_list(&layout_root, &document.root_node, &document.frames, document.parallelism, &document.render));
+    let canvas = profile::time(&mut timings.raster, || paint::paint_display_list_with(&display_list, viewport, scale, document.tiling(), &document.render))?;
+    counts = count(&document.root_node, &stylesheet, &layout_root, &display_list, &canvas);
+    frames.push(canvas);
+    previous = Some(sampled);
+  }
+  counts.pixels = frames.iter().map(|frame| frame.pixels.len()).sum();
+  return Ok(Frames { frames: frames, interval: interval, timings: timings, counts: counts });
+}
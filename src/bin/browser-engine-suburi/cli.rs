@@ -0,0 +1,567 @@
+use clap::error::ErrorKind;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use browser_engine_suburi::dump;
+use browser_engine_suburi::options;
+use browser_engine_suburi::output::OutputFormat;
+use log::LevelFilter;
+use std::io::{self, IsTerminal};
+
+/**
+ * コマンドライン引数の定義と解釈
+ */
+
+// 入力をこれにすると標準入力から HTML を読む
+pub const STDIN: &str = "-";
+
+// --dump で指定できる段階。--dump-dom などのフラグの名前は dump- を付けたもの
+const DUMP_STAGES: [&str; 8] = ["dom", "style", "computed-style", "a11y", "layout", "boxes", "display-list", "terminal"];
+
+// --dump-* で書き出す段階
+#[derive(Debug)]
+pub struct DumpOptions {
+  pub dom: bool,
+  pub style: bool,
+  pub computed_style: bool,
+  pub a11y: bool,
+  pub layout: bool,
+  pub boxes: bool,
+  pub display_list: bool,
+  pub terminal: bool,
+  pub format: dump::Format,
+}
+
+impl DumpOptions {
+  pub fn any(&self) -> bool {
+    return self.dom || self.style || self.computed_style || self.a11y || self.layout || self.boxes || self.display_list || self.terminal;
+  }
+}
+
+// serve サブコマンドの引数
+#[derive(Debug)]
+pub struct ServeArgs {
+  pub address: String,   // 待ち受けるアドレス
+  pub workers: usize,    // ワーカーの数（0 なら CPU の数）
+  pub file_access: bool, // 描画する HTML から file:// を読み込めるようにする
+}
+
+#[derive(Debug)]
+pub struct Args {
+  pub input: String,            // HTML ドキュメント（パスか URL。STDIN なら標準入力）
+  pub stylesheets: Vec<String>, // 追加で適用するスタイルシート（指定順）
+  pub output: String,           // 出力先
+  pub format: OutputFormat,     // 出力フォーマット
+  pub width: Option<f32>,       // ビューポートの幅（省略時は設定ファイルの値）
+  pub height: Option<f32>,      // ビューポートの高さ（省略時は設定ファイルの値）
+  pub config: Option<String>,   // EngineOptions を読み込む TOML ファイル
+  pub deterministic: bool,      // 設定ファイルの deterministic を上書きする
+  pub log_level: LevelFilter,   // -v/-q で決まるログレベル
+  pub window: bool,             // 画像を書き出す代わりにウィンドウで表示する
+  pub watch: bool,              // 入力が変わるたびに描画し直す
+  pub select: Option<String>,   // この要素の範囲だけを書き出す（CSS セレクター）
+  pub full_page: bool,          // ビューポートではなくドキュメント全体の高さで書き出す
+  pub scroll: (f32, f32),       // 書き出すときのスクロール位置（x, y）
+  pub animate: Option<usize>,   // アニメーションをこの枚数のフレームに分けて書き出す
+  pub animate_duration: Option<f32>, // 描く時間（秒）
+  pub transition_to: Option<String>, // 0 秒に足すスタイルシート（トランジションの後の状態）
+  pub zoom: Option<f32>,        // 設定ファイルの zoom を上書きする
+  pub device_pixel_ratio: Option<f32>, // 設定ファイルの device_pixel_ratio を上書きする
+  pub media: Option<String>,    // 設定ファイルの media を上書きする
+  pub page_size: Option<(f32, f32)>, // 設定ファイルの page_width と page_height を上書きする
+  pub background: Option<String>, // 設定ファイルの background を上書きする
+  pub pixel_snap: bool,         // 設定ファイルの pixel_snap を上書きする
+  pub debug_boxes: bool,        // 設定ファイルの debug_boxes を上書きする
+  pub inspect: Option<String>,  // 画像の代わりに、一致した要素のボックスとルールを書き出す（CSS セレクター）
+  pub profile: Option<dump::Format>, // 段階ごとの時間と数を書き出す
+  pub memory: Option<dump::Format>,  // 各段階の結果のおおよそのメモリ量を書き出す
+  pub trace: Option<String>,    // 段階ごとの区間を書き出す Chrome のトレースイベントの JSON ファイル
+  pub snapshot: Option<String>, // 入力の代わりに読み込むスナップショット
+  pub save_snapshot: Option<String>, // 描画する代わりにパースした結果をスナップショットに書き出す
+  pub reftest: Option<String>,  // リファレンステストを実行するディレクトリ
+  pub bless: bool,              // リファレンス画像を今の描画結果で更新する
+  pub tolerance: u8,            // リファレンステストで許容するチャンネルごとの差
+  pub dump: DumpOptions,        // 画像を書き出す代わりに各段階の結果を書き出す
+  pub serve: Option<ServeArgs>, // serve サブコマンドなら、その引数
+}
+
+fn command() -> Command {
+  let command = Command::new("browser-engine-suburi")
+    .about("HTML と CSS をレンダリングして画像に出力する")
+    .arg(
+      Arg::new("input")
+        .value_name("HTML")
+        .help("入力する HTML ドキュメント（ファイルパスか http(s):// の URL、- なら標準入力）。省略時は、標準入力がパイプなら標準入力、そうでなければ test.html と test.css"),
+    )
+    .arg(
+      Arg::new("html")
+        .long("html")
+        .value_name("HTML")
+        .help("入力する HTML ドキュメント（HTML と同じ）")
+        .conflicts_with("input"),
+    )
+    .arg(
+      Arg::new("stylesheet")
+        .short('s')
+        .long("stylesheet")
+        .visible_alias("css")
+        .value_name("CSS")
+        .help("追加で適用するスタイルシート（複数指定可、<link> の後に指定順で適用）")
+        .action(ArgAction::Append),
+    )
+    .arg(
+      Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("FILE")
+        .help("出力する画像のパス")
+        .default_value("capture.png"),
+    )
+    .arg(
+      Arg::new("format")
+        .short('f')
+        .long("format")
+        .value_name("FORMAT")
+        .help("出力フォーマット（png, jpeg, gif, ppm, raw, svg, pdf, apng）。raw はヘッダーのない RGBA のピクセル。svg と pdf は文字や図形をベクターのまま書き出す。text と markdown では画像の代わりに本文のテキストを書き出す。省略時は出力パスの拡張子から決める")
+        .value_parser(["png", "jpg", "jpeg", "gif", "ppm", "raw", "svg", "pdf", "apng", "text", "markdown"]),
+    )
+    .arg(
+      Arg::new("width")
+        .short('W')
+        .long("width")
+        .value_name("PX")
+        .help("ビューポートの幅（省略時は 800）")
+        .value_parser(value_parser!(f32)),
+    )
+    .arg(
+      Arg::new("height")
+        .short('H')
+        .long("height")
+        .value_name("PX")
+        .help("ビューポートの高さ（省略時は 600）")
+        .value_parser(value_parser!(f32)),
+    )
+    .arg(
+      Arg::new("config")
+        .short('c')
+        .long("config")
+        .value_name("TOML")
+        .help("エンジンの設定ファイル（ビューポートの大きさ、組み込みスタイルシートの有無など）"),
+    )
+    .arg(
+      Arg::new("deterministic")
+        .long("deterministic")
+        .help("同じ入力からは毎回同じ出力にする（スクリプトの Math.random と Date、文字のフォントを固定する）")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("select")
+        .long("select")
+        .value_name("SELECTOR")
+        .help("セレクターに一致する最初の要素のボーダーボックスだけを画像に書き出す"),
+    )
+    .arg(
+      Arg::new("animate")
+        .long("animate")
+        .value_name("FRAMES")
+        .help("CSS アニメーションとトランジションを FRAMES 枚のフレームに分けて描く。APNG（.apng）なら 1 つのファイルに、画像なら capture-1.png、capture-2.png... に書き出す")
+        .value_parser(value_parser!(u64).range(1..=10000))
+        .conflicts_with_all(["select", "full-page"]),
+    )
+    .arg(
+      Arg::new("animate-duration")
+        .long("animate-duration")
+        .value_name("SECONDS")
+        .help("--animate で描く時間（省略時はアニメーションとトランジションが終わるまで。infinite は 1 回分）")
+        .value_parser(value_parser!(f32))
+        .requires("animate"),
+    )
+    .arg(
+      Arg::new("transition-to")
+        .long("transition-to")
+        .value_name("CSS")
+        .help("--animate で、0 秒にこのスタイルシートを足したとしてトランジションを描く")
+        .requires("animate"),
+    )
+    .arg(
+      Arg::new("zoom")
+        .long("zoom")
+        .value_name("FACTOR")
+        .help("ページのズーム（1.5 か 150%）。画像の大きさはそのままで、ビューポートを 1 / FACTOR の幅でレイアウトして拡大して描く")
+        .value_parser(parse_zoom),
+    )
+    .arg(
+      Arg::new("device-pixel-ratio")
+        .long("device-pixel-ratio")
+        .value_name("RATIO")
+        .help("1 CSS px あたりのピクセル数（2 なら高解像度の画面用）。レイアウトはそのままで、画像を RATIO 倍の大きさで描く")
+        .value_parser(parse_zoom),
+    )
+    .arg(
+      Arg::new("media")
+        .long("media")
+        .value_name("MEDIA")
+        .help("@media を評価するメディア。print ではページの大きさで区切って、PDF か 1 ページずつの画像（capture-1.png、capture-2.png...）に書き出す")
+        .value_parser(["screen", "print"]),
+    )
+    .arg(
+      Arg::new("page-size")
+        .long("page-size")
+        .value_name("SIZE")
+        .help("print のページの大きさ（a4、a5、letter、legal か、800x1000 のように px で）")
+        .value_parser(parse_page_size),
+    )
+    .arg(
+      Arg::new("background")
+        .long("background")
+        .value_name("COLOR")
+        .help("キャンバスを塗っておく色（CSS の色。transparent なら透明な画像）。ドキュメントの背景がないところに見える")
+        .value_parser(parse_background),
+    )
+    .arg(
+      Arg::new("pixel-snap")
+        .long("pixel-snap")
+        .help("ボックスの辺をピクセルの境目に合わせて描く（小数の位置で隣り合うボックスの間に隙間ができないように）")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("debug-boxes")
+        .long("debug-boxes")
+        .help("すべてのボックスの margin（橙）、padding（緑）、content（青）の範囲を 1px の枠で重ねて描く")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("full-page")
+        .long("full-page")
+        .help("画像の高さをビューポートではなくドキュメント全体の高さにする（設定ファイルの max_page_height まで）")
+        .action(ArgAction::SetTrue)
+        .conflicts_with("select"),
+    )
+    .arg(
+      Arg::new("scroll")
+        .long("scroll")
+        .value_name("X,Y")
+        .help("ドキュメントをスクロールした位置から描く（100 なら縦だけ、0,100 のように横と縦で。px）。ドキュメントの端より先にはスクロールしない")
+        .value_parser(parse_scroll)
+        .conflicts_with_all(["select", "full-page"]),
+    )
+    .arg(
+      Arg::new("inspect")
+        .long("inspect")
+        .value_name("SELECTOR")
+        .help("セレクターに一致した要素のボックス（content、padding、border、margin）と、一致したルール、最終的な値を標準出力に書き出して終了する（--dump-format json で JSON）"),
+    )
+    .arg(
+      Arg::new("profile")
+        .long("profile")
+        .value_name("FORMAT")
+        .help("書き出した後に、段階ごとにかかった時間とノードやボックスの数を標準出力に書き出す（--profile=json で JSON）")
+        .value_parser(["text", "json"])
+        .num_args(0..=1)
+        .require_equals(true)
+        .default_missing_value("text"),
+    )
+    .arg(
+      Arg::new("memory")
+        .long("memory")
+        .value_name("FORMAT")
+        .help("書き出した後に、DOM、スタイル、レイアウトツリー、キャンバスのおおよそのメモリ量を標準出力に書き出す（--memory=json で JSON）")
+        .value_parser(["text", "json"])
+        .num_args(0..=1)
+        .require_equals(true)
+        .default_missing_value("text"),
+    )
+    .arg(
+      Arg::new("snapshot")
+        .long("snapshot")
+        .value_name("FILE")
+        .help("HTML と CSS をパースする代わりに、--save-snapshot で書き出したスナップショットを読み込む")
+        .conflicts_with_all(["input", "html", "stylesheet"]),
+    )
+    .arg(
+      Arg::new("save-snapshot")
+        .long("save-snapshot")
+        .value_name("FILE")
+        .help("パースした DOM とスタイルシート、ノードごとの値をスナップショットに書き出して終了する"),
+    )
+    .arg(
+      Arg::new("verbose")
+        .short('v')
+        .long("verbose")
+        .help("ログを詳しく出す（-v で debug、-vv で trace）")
+        .action(ArgAction::Count),
+    )
+    .arg(
+      Arg::new("quiet")
+        .short('q')
+        .long("quiet")
+        .help("エラー以外のログを出さない")
+        .action(ArgAction::SetTrue)
+        .conflicts_with("verbose"),
+    )
+    .arg(
+      Arg::new("reftest")
+        .long("reftest")
+        .value_name("DIR")
+        .help("DIR 内の *.html を描画して、同名の .png（リファレンス画像）と比較する。同名の .css があれば適用する"),
+    )
+    .arg(
+      Arg::new("bless")
+        .long("bless")
+        .help("--reftest で、比較する代わりにリファレンス画像を今の描画結果で書き出す")
+        .action(ArgAction::SetTrue)
+        .requires("reftest"),
+    )
+    .arg(
+      Arg::new("tolerance")
+        .long("tolerance")
+        .value_name("N")
+        .help("--reftest で、ピクセルのチャンネルごとに許容する差")
+        .value_parser(value_parser!(u8))
+        .default_value("1"),
+    )
+    .arg(
+      Arg::new("dump")
+        .long("dump")
+        .value_name("STAGES")
+        .help("カンマ区切りの段階（dom、style、computed-style、a11y、layout、boxes、display-list、terminal）を標準出力に書き出して終了する（--dump-dom などと同じ）")
+        .value_parser(DUMP_STAGES)
+        .value_delimiter(',')
+        .action(ArgAction::Append),
+    )
+    .arg(dump_flag("dump-dom", "DOM ツリー"))
+    .arg(dump_flag("dump-style", "スタイルツリー"))
+    .arg(dump_flag("dump-computed-style", "要素ごとのすべてのプロパティの計算値"))
+    .arg(dump_flag("dump-a11y", "アクセシビリティツリー"))
+    .arg(dump_flag("dump-layout", "レイアウトツリー"))
+    .arg(dump_flag("dump-boxes", "ボックスの種類、タグ、位置と大きさだけの木"))
+    .arg(dump_flag("dump-display-list", "ディスプレイリスト"))
+    .arg(dump_flag("dump-terminal", "端末の背景色で描いたページ"))
+    .arg(
+      Arg::new("dump-format")
+        .long("dump-format")
+        .value_name("FORMAT")
+        .help("--dump-* の出力形式")
+        .value_parser(["text", "json"])
+        .default_value("text"),
+    )
+    .subcommand(
+      Command::new("serve")
+        .about("HTML を受け取って描画した PNG を返す HTTP サーバーを起動する（POST /render）")
+        .arg(
+          Arg::new("listen")
+            .short('l')
+            .long("listen")
+            .value_name("ADDRESS")
+            .help("待ち受けるアドレス")
+            .default_value("127.0.0.1:8080"),
+        )
+        .arg(
+          Arg::new("workers")
+            .long("workers")
+            .value_name("N")
+            .help("同時に描画するワーカーの数（0 なら CPU の数）")
+            .value_parser(value_parser!(usize))
+            .default_value("0"),
+        )
+        .arg(
+          Arg::new("allow-file-access")
+            .long("allow-file-access")
+            .help("描画する HTML から file:// のリソースを読み込めるようにする（省略時は読み込まない）")
+            .action(ArgAction::SetTrue),
+        ),
+    );
+
+  #[cfg(feature = "viewer")]
+  let command = command.arg(
+    Arg::new("window")
+      .long("window")
+      .help("画像を書き出す代わりにウィンドウを開いて表示する（幅と高さは初期サイズ）")
+      .action(ArgAction::SetTrue)
+      .conflicts_with("snapshot"),
+  );
+
+  #[cfg(feature = "watch")]
+  let command = command.arg(
+    Arg::new("watch")
+      .long("watch")
+      .help("入力の HTML と CSS を監視して、変更されるたびに描画し直す（Ctrl-C で終了）")
+      .action(ArgAction::SetTrue)
+      .conflicts_with("snapshot"),
+  );
+
+  #[cfg(feature = "tracing")]
+  let command = command.arg(
+    Arg::new("trace")
+      .long("trace")
+      .value_name("FILE")
+      .help("パース、スタイル、レイアウト、ペイントの区間（レイアウトはボックスごとにも）を Chrome のトレースイベントの JSON に書き出す（about:tracing や Perfetto で開ける）"),
+  );
+
+  return command;
+}
+
+fn dump_flag(name: &'static str, stage: &str) -> Arg {
+  return Arg::new(name)
+    .long(name)
+    .help(format!("{}を標準出力に書き出して終了する", stage))
+    .action(ArgAction::SetTrue);
+}
+
+pub fn parse() -> Args {
+  return from_matches(&command().get_matches());
+}
+
+fn from_matches(matches: &ArgMatches) -> Args {
+  let output = matches.get_one::<String>("output").unwrap().clone();
+
+  // --format が優先、なければ拡張子、それもなければ PNG
+  let format = matches
+    .get_one::<String>("format")
+    .and_then(|name| OutputFormat::from_name(name))
+    .or_else(|| OutputFormat::from_path(&output))
+    .unwrap_or(OutputFormat::Png);
+
+  let mut stylesheets: Vec<String> = match matches.get_many::<String>("stylesheet") {
+    Some(values) => values.cloned().collect(),
+    None => Vec::new(),
+  };
+
+  // 入力の指定がなければ、標準入力がパイプならそこから、そうでなければ今まで通り test.html と test.css
+  let input = match matches.get_one::<String>("input").or_else(|| matches.get_one::<String>("html")) {
+    Some(input) => input.clone(),
+    None if !io::stdin().is_terminal() => STDIN.to_string(),
+    None => {
+      if stylesheets.is_empty() {
+        stylesheets.push("test.css".to_string());
+      }
+      "test.html".to_string()
+    }
+  };
+
+  let log_level = if matches.get_flag("quiet") {
+    LevelFilter::Error
+  } else {
+    match matches.get_count("verbose") {
+      0 => LevelFilter::Info,
+      1 => LevelFilter::Debug,
+      _ => LevelFilter::Trace,
+    }
+  };
+
+  let window = matches.try_get_one::<bool>("window").ok().and_then(|window| window.cloned()).unwrap_or(false);
+  let watch = matches.try_get_one::<bool>("watch").ok().and_then(|watch| watch.cloned()).unwrap_or(false);
+  // 標準入力は一度しか読めないので、読み込み直すものには使えない
+  if input == STDIN && (window || watch) {
+    command().error(ErrorKind::ArgumentConflict, "--window and --watch cannot read the document from stdin").exit();
+  }
+
+  // --dump dom,layout は --dump-dom --dump-layout と同じ
+  let stages: Vec<&String> = matches.get_many::<String>("dump").map_or(Vec::new(), |stages| stages.collect());
+  let dumps = |stage: &str| matches.get_flag(&format!("dump-{}", stage)) || stages.iter().any(|&name| name == stage);
+
+  return Args {
+    input: input,
+    stylesheets: stylesheets,
+    output: output,
+    format: format,
+    width: matches.get_one::<f32>("width").cloned(),
+    height: matches.get_one::<f32>("height").cloned(),
+    config: matches.get_one::<String>("config").cloned(),
+    deterministic: matches.get_flag("deterministic"),
+    log_level: log_level,
+    window: window,
+    watch: watch,
+    select: matches.get_one::<String>("select").cloned(),
+    full_page: matches.get_flag("full-page"),
+    scroll: matches.get_one::<(f32, f32)>("scroll").cloned().unwrap_or((0.0, 0.0)),
+    animate: matches.get_one::<u64>("animate").map(|&frames| frames as usize),
+    animate_duration: matches.get_one::<f32>("animate-duration").cloned(),
+    transition_to: matches.get_one::<String>("transition-to").cloned(),
+    zoom: matches.get_one::<f32>("zoom").cloned(),
+    device_pixel_ratio: matches.get_one::<f32>("device-pixel-ratio").cloned(),
+    media: matches.get_one::<String>("media").cloned(),
+    page_size: matches.get_one::<(f32, f32)>("page-size").cloned(),
+    background: matches.get_one::<String>("background").cloned(),
+    pixel_snap: matches.get_flag("pixel-snap"),
+    debug_boxes: matches.get_flag("debug-boxes"),
+    inspect: matches.get_one::<String>("inspect").cloned(),
+    profile: matches.get_one::<String>("profile").map(|format| dump_format(format)),
+    memory: matches.get_one::<String>("memory").map(|format| dump_format(format)),
+    trace: matches.try_get_one::<String>("trace").ok().and_then(|path| path.cloned()),
+    snapshot: matches.get_one::<String>("snapshot").cloned(),
+    save_snapshot: matches.get_one::<String>("save-snapshot").cloned(),
+    reftest: matches.get_one::<String>("reftest").cloned(),
+    bless: matches.get_flag("bless"),
+    tolerance: *matches.get_one::<u8>("tolerance").unwrap(),
+    dump: DumpOptions {
+      dom: dumps("dom"),
+      style: dumps("style"),
+      computed_style: dumps("computed-style"),
+      a11y: dumps("a11y"),
+      layout: dumps("layout"),
+      boxes: dumps("boxes"),
+      display_list: dumps("display-list"),
+      terminal: dumps("terminal"),
+      format: dump_format(matches.get_one::<String>("dump-format").unwrap()),
+    },
+    serve: matches.subcommand_matches("serve").map(|serve| ServeArgs {
+      address: serve.get_one::<String>("listen").unwrap().clone(),
+      workers: *serve.get_one::<usize>("workers").unwrap(),
+      file_access: serve.get_flag("allow-file-access"),
+    }),
+  };
+}
+
+// 0.1 から 10 まで。150% のようにパーセントでもよい（--zoom と --device-pixel-ratio）
+fn parse_zoom(value: &str) -> Result<f32, String> {
+  let zoom = match value.trim().strip_suffix('%') {
+    Some(percent) => percent.trim().parse::<f32>().map(|percent| percent / 100.0),
+    None => value.trim().parse::<f32>(),
+  };
+  return match zoom {
+    Ok(zoom) if (0.1..=10.0).contains(&zoom) => Ok(zoom),
+    _ => Err(format!("expected a factor between 0.1 and 10, found {:?}", value)),
+  };
+}
+
+// 名前か、幅x高さ（px）
+fn parse_page_size(value: &str) -> Result<(f32, f32), String> {
+  let size = match &*value.to_ascii_lowercase() {
+    "a4" => (794.0, 1123.0),
+    "a5" => (559.0, 794.0),
+    "letter" => (816.0, 1056.0),
+    "legal" => (816.0, 1344.0),
+    size => {
+      let (width, height) = size.split_once('x').ok_or_else(|| format!("expected a4, a5, letter, legal or WIDTHxHEIGHT, found {:?}", value))?;
+      let parse = |length: &str| length.trim().parse::<f32>().ok().filter(|length| *length >= 1.0);
+      match (parse(width), parse(height)) {
+        (Some(width), Some(height)) => (width, height),
+        _ => return Err(format!("invalid page size {:?}", value)),
+      }
+    }
+  };
+  return Ok(size);
+}
+
+// 100 なら (0, 100)、20,100 なら (20, 100)
+fn parse_scroll(value: &str) -> Result<(f32, f32), String> {
+  let parse = |length: &str| length.trim().parse::<f32>().ok().filter(|length| length.is_finite() && *length >= 0.0);
+  let scroll = match value.split_once(',') {
+    Some((x, y)) => parse(x).zip(parse(y)),
+    None => parse(value).map(|y| (0.0, y)),
+  };
+  return scroll.ok_or_else(|| format!("invalid scroll position {:?}", value));
+}
+
+fn parse_background(value: &str) -> Result<String, String> {
+  return match options::parse_color(value) {
+    Some(_) => Ok(value.to_string()),
+    None => Err(format!("invalid color {:?}", value)),
+  };
+}
+
+fn dump_format(name: &str) -> dump::Format {
+  return match name {
+    "json" => dump::Format::Json,
+    _ => dump::Format::Text,
+  };
+}
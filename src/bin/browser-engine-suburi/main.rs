@@ -0,0 +1,309 @@
+extern crate browser_engine_suburi;
+extern crate clap;
+extern crate env_logger;
+extern crate image;
+#[macro_use]
+extern crate log;
+
+use browser_engine_suburi::error::EngineError;
+use browser_engine_suburi::loader::IoError;
+use browser_engine_suburi::memory;
+use browser_engine_suburi::options::EngineOptions;
+use browser_engine_suburi::output::{save_frames, save_pages, save_rendered, save_text};
+#[cfg(feature = "watch")]
+use browser_engine_suburi::url::Url;
+#[cfg(feature = "viewer")]
+use browser_engine_suburi::viewer;
+#[cfg(feature = "watch")]
+use browser_engine_suburi::watch::{self, Watcher};
+use browser_engine_suburi::profile::{self, Counts, Timings};
+use browser_engine_suburi::{a11y, animation, dump, inspect, layout, paint, print, reader, snapshot, style, trace};
+use browser_engine_suburi::{initial_containing_block, load_document, load_document_source, load_extra_stylesheet, render_document, render_element, render_full_page, LoadedDocument};
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+#[cfg(feature = "watch")]
+use std::sync::mpsc;
+#[cfg(feature = "watch")]
+use std::thread;
+
+mod cli;
+mod reftest;
+mod serve;
+
+fn main() {
+  let args = cli::parse();
+  init_logger(args.log_level);
+
+  if args.trace.is_some() {
+    trace::start();
+  }
+  let result = run(&args);
+  if let Some(ref path) = args.trace {
+    write_trace(path);
+  }
+  if let Err(error) = result {
+    error!("{}", error);
+    process::exit(1);
+  }
+}
+
+// --trace: start からの区間を path に書き出す。書き出せなくても描画の結果は変えない
+fn write_trace(path: &str) {
+  if let Some(json) = trace::finish() {
+    match fs::write(path, json) {
+      Ok(()) => info!("Saved trace as {}", path),
+      Err(error) => warn!("Failed to write trace to {}: {}", path, error),
+    }
+  }
+}
+
+fn run(args: &cli::Args) -> Result<(), EngineError> {
+  let options = engine_options(args)?;
+
+  if let Some(ref serve_args) = args.serve {
+    let engine = EngineOptions { file_access: serve_args.file_access, ..options };
+    return serve::run(&serve::Options { address: serve_args.address.clone(), workers: serve_args.workers, engine: engine });
+  }
+
+  if let Some(ref dir) = args.reftest {
+    let reftest_options = reftest::Options { engine: options, tolerance: args.tolerance, bless: args.bless };
+    if !reftest::run(dir, &reftest_options)? {
+      process::exit(1);
+    }
+    return Ok(());
+  }
+
+  if let Some(ref path) = args.save_snapshot {
+    let document = load(args, &options)?;
+    let (viewport, _) = document.layout_viewport(layout::Rect { x: 0.0, y: 0.0, width: options.viewport_width, height: options.viewport_height });
+    let values = style::compute_values_with_viewport(&document.root_node, &document.stylesheet, Some(viewport));
+    fs::write(path, snapshot::encode(&document, Some(&values))).map_err(|error| IoError::Write { path: path.clone(), source: error })?;
+    info!("Saved snapshot as {}", path);
+    return Ok(());
+  }
+
+  if let Some(ref selector) = args.inspect {
+    let document = load(args, &options)?;
+    let inspections = inspect::inspect(&document, selector, &options)?;
+    if inspections.is_empty() {
+      warn!("No element matches {}", selector);
+    }
+    print!("{}", dump::inspect(&inspections, args.dump.format));
+    return Ok(());
+  }
+
+  if args.dump.any() {
+    let document = load(args, &options)?;
+    return dump_stages(args, &options, &document);
+  }
+
+  #[cfg(feature = "viewer")]
+  {
+    if args.window {
+      return viewer::run(&args.input, &args.stylesheets, &options, args.watch);
+    }
+  }
+
+  #[cfg(feature = "watch")]
+  {
+    if args.watch {
+      return watch_and_render(args, &options);
+    }
+  }
+
+  let document = load(args, &options)?;
+  return render_to_file(args, &options, &document);
+}
+
+// --snapshot ならスナップショットから、そうでなければ入力の HTML と CSS をパースする（入力が - なら HTML は標準入力から）
+fn load(args: &cli::Args, options: &EngineOptions) -> Result<LoadedDocument, EngineError> {
+  if let Some(ref path) = args.snapshot {
+    let bytes = fs::read(path).map_err(|error| IoError::Load { url: path.clone(), source: error })?;
+    return Ok(snapshot::decode(&bytes, options)?.document);
+  }
+  if args.input == cli::STDIN {
+    let mut html = String::new();
+    io::stdin().read_to_string(&mut html).map_err(|error| IoError::Load { url: "stdin".to_string(), source: error })?;
+    return load_document_source(&html, &args.stylesheets, options);
+  }
+  return load_document(&args.input, &args.stylesheets, options);
+}
+
+// --config の設定を読み込んで、コマンドラインで指定された値で上書きする
+fn engine_options(args: &cli::Args) -> Result<EngineOptions, EngineError> {
+  let mut options = match args.config {
+    Some(ref path) => EngineOptions::from_file(path)?,
+    None => EngineOptions::default(),
+  };
+  if let Some(width) = args.width {
+    options.viewport_width = width;
+  }
+  if let Some(height) = args.height {
+    options.viewport_height = height;
+  }
+  if args.deterministic {
+    options.deterministic = true;
+  }
+  if let Some(zoom) = args.zoom {
+    options.zoom = zoom;
+  }
+  if let Some(ratio) = args.device_pixel_ratio {
+    options.device_pixel_ratio = ratio;
+  }
+  if let Some(ref media) = args.media {
+    options.media = media.clone();
+  }
+  if let Some((width, height)) = args.page_size {
+    options.page_width = width;
+    options.page_height = height;
+  }
+  if let Some(ref background) = args.background {
+    options.background = background.clone();
+  }
+  if args.pixel_snap {
+    options.pixel_snap = true;
+  }
+  if args.debug_boxes {
+    options.debug_boxes = true;
+  }
+  return Ok(options);
+}
+
+// 描画して --output に書き出す。テキストのフォーマットなら本文を取り出して書き出す。
+// --animate ならフレームごとに、メディアが print ならページに分けて書き出す（画像なら 1 枚ずつ番号を付けたファイルに）。
+// --profile なら読み込みからの時間と数を、--memory ならメモリ量を標準出力に書き出す
+fn render_to_file(args: &cli::Args, options: &EngineOptions, document: &LoadedDocument) -> Result<(), EngineError> {
+  let filename = &args.output;
+  let mut filenames = vec![filename.clone()];
+  let mut timings = document.timings;
+  let viewport = layout::Rect { x: 0.0, y: 0.0, width: options.viewport_width, height: options.viewport_height };
+  let scrolled = layout::Rect { x: args.scroll.0, y: args.scroll.1, ..viewport };
+  let counts = if let Some(format) = args.format.reader_format() {
+    let mut render_timings = Timings::default();
+    let (layout_viewport, _) = document.layout_viewport(viewport);
+    let style_root = profile::time(&mut render_timings.style, || style::style_tree_with_viewport(&document.root_node, &document.stylesheet, layout_viewport));
+    save_text(&reader::extract(&style_root, &document.base_url, format), filename)?;
+    timings.add(&render_timings);
+    Counts { dom_nodes: profile::count_nodes(&document.root_node), rules: document.stylesheet.rules.len(), ..Counts::default() }
+  } else if let Some(frame_count) = args.animate {
+    let transition_to = match args.transition_to {
+      Some(ref location) => Some(load_extra_stylesheet(document, location, options)?),
+      None => None,
+    };
+    let rendered = animation::render_frames(document, transition_to.as_ref(), viewport, frame_count, args.animate_duration)?;
+    filenames = save_frames(&rendered.frames, rendered.interval, filename, args.format)?;
+    info!("Rendered {} frames at {}s intervals", rendered.frames.len(), rendered.interval);
+    timings.add(&rendered.timings);
+    rendered.counts
+  } else if options.media == "print" && args.select.is_none() {
+    let printed = print::render_pages(document, options)?;
+    filenames = save_pages(&printed, filename, args.format)?;
+    info!("Printed {} pages", printed.pages.len());
+    timings.add(&printed.timings);
+    printed.counts
+  } else {
+    let rendered = match args.select {
+      Some(ref selector) => render_element(document, selector, viewport)?,
+      None if args.full_page => render_full_page(document, viewport, options.max_page_height)?,
+      None => render_document(document, scrolled)?,
+    };
+    save_rendered(&rendered, filename, args.format)?;
+    timings.add(&rendered.timings);
+    rendered.counts
+  };
+  info!("Saved output as {}", filenames.join(", "));
+  if let Some(format) = args.profile {
+    print!("{}", dump::profile(&timings, &counts, format));
+  }
+  if let Some(format) = args.memory {
+    print!("{}", dump::memory(&memory::measure_document(document, viewport)?, format));
+  }
+  return Ok(());
+}
+
+// --watch: 入力が変わるたびに読み込み直して書き出す。読み込みや描画に失敗しても監視は続ける
+#[cfg(feature = "watch")]
+fn watch_and_render(args: &cli::Args, options: &EngineOptions) -> Result<(), EngineError> {
+  let (sender, receiver) = mpsc::channel();
+  let mut watcher = Watcher::new(move || {
+    let _ = sender.send(());
+  })?;
+
+  // 最初の読み込みに失敗したときは、引数で指定されたファイルを監視する
+  let mut sources: Vec<Url> = Some(&args.input).into_iter().chain(&args.stylesheets).map(|location| Url::from_location(location)).collect();
+  info!("Watching for changes (Ctrl-C to stop)");
+  loop {
+    match load_document(&args.input, &args.stylesheets, options) {
+      Ok(document) => {
+        sources = document.sources.clone();
+        if let Err(error) = render_to_file(args, options, &document) {
+          error!("{}", error);
+        }
+      }
+      Err(error) => error!("{}", error),
+    }
+    watcher.watch(&sources)?;
+
+    if receiver.recv().is_err() {
+      return Ok(());
+    }
+    thread::sleep(watch::DEBOUNCE);
+    while receiver.try_recv().is_ok() {}
+  }
+}
+
+// ログの出力先は stderr。RUST_LOG が設定されていればそちらを優先する
+fn init_logger(level: log::LevelFilter) {
+  env_logger::Builder::new()
+    .filter_level(level)
+    .format_timestamp(None)
+    .parse_default_env()
+    .init();
+}
+
+
+// --dump-* で指定された段階の結果を、パイプラインの順に標準出力に書き出す
+fn dump_stages(args: &cli::Args, options: &EngineOptions, document: &LoadedDocument) -> Result<(), EngineError> {
+  let (root_node, stylesheet) = (&document.root_node, &document.stylesheet);
+  let format = args.dump.format;
+  if args.dump.dom {
+    print!("{}", dump::dom(root_node, format));
+  }
+  if !(args.dump.style || args.dump.computed_style || args.dump.a11y || args.dump.layout || args.dump.boxes || args.dump.display_list || args.dump.terminal) {
+    return Ok(());
+  }
+
+  let (viewport, _) = document.layout_viewport(layout::Rect { x: 0.0, y: 0.0, width: options.viewport_width, height: options.viewport_height });
+  let style_root = style::style_tree_with_viewport(root_node, stylesheet, viewport);
+  if args.dump.style {
+    print!("{}", dump::style(&style_root, format));
+  }
+  if args.dump.computed_style {
+    print!("{}", dump::computed_style(&style_root, Some(viewport), format));
+  }
+  if args.dump.a11y {
+    print!("{}", dump::a11y(&a11y::accessibility_tree(&style_root), format));
+  }
+  if !(args.dump.layout || args.dump.boxes || args.dump.display_list || args.dump.terminal) {
+    return Ok(());
+  }
+
+  let layout_root = layout::layout_tree_with(&style_root, initial_containing_block(viewport), document.fonts, &document.images)?;
+  if args.dump.layout {
+    print!("{}", dump::layout(&layout_root, format));
+  }
+  if args.dump.boxes {
+    print!("{}", dump::boxes(&layout_root, format));
+  }
+  if args.dump.display_list {
+    print!("{}", dump::display_list(&paint::build_display_list(&layout_root), format));
+  }
+  if args.dump.terminal {
+    // 端末の幅は COLUMNS で指定する（なければ 80 桁）
+    let columns = env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()).unwrap_or(80);
+    print!("{}", dump::terminal(&paint::paint(&layout_root, viewport)?, columns));
+  }
+  return Ok(());
+}
@@ -0,0 +1,125 @@
+use browser_engine_suburi::error::EngineError;
+use browser_engine_suburi::layout::Rect;
+use browser_engine_suburi::loader::IoError;
+use browser_engine_suburi::options::EngineOptions;
+use browser_engine_suburi::output::{canvas_to_image, save_canvas, OutputFormat};
+use browser_engine_suburi::paint::Canvas;
+use browser_engine_suburi::{load_document, render_document};
+use image;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/**
+ * リファレンステスト。
+ * ディレクトリ内の name.html（と name.css）を描画して、リファレンス画像 name.png とピクセル単位で比較する
+ */
+
+pub struct Options {
+  pub engine: EngineOptions, // ビューポートの大きさもここから
+  pub tolerance: u8, // チャンネルごとに許容する差
+  pub bless: bool,   // 比較せずにリファレンス画像を書き出す
+}
+
+struct Case {
+  name: String,
+  html: PathBuf,
+  css: Option<PathBuf>,
+  expected: PathBuf,
+}
+
+// 比較結果
+enum Outcome {
+  Pass,
+  Fail(String),
+  Blessed,
+}
+
+fn find_cases(dir: &Path) -> Result<Vec<Case>, EngineError> {
+  let io_error = |error| IoError::Load { url: dir.display().to_string(), source: error };
+  let mut cases = Vec::new();
+  for entry in fs::read_dir(dir).map_err(&io_error)? {
+    let path = entry.map_err(&io_error)?.path();
+    if path.extension().map_or(true, |ext| ext != "html") {
+      continue;
+    }
+    let css = path.with_extension("css");
+    cases.push(Case {
+      name: path.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+      css: if css.exists() { Some(css) } else { None },
+      expected: path.with_extension("png"),
+      html: path,
+    });
+  }
+  cases.sort_by(|a, b| a.name.cmp(&b.name));
+  return Ok(cases);
+}
+
+fn render_case(case: &Case, options: &Options) -> Result<Canvas, EngineError> {
+  let stylesheets: Vec<String> = case.css.iter().map(|css| css.display().to_string()).collect();
+  // リファレンス画像と比べるので、毎回同じ結果になるようにする
+  let engine_options = EngineOptions { deterministic: true, ..options.engine.clone() };
+  let document = load_document(&case.html.display().to_string(), &stylesheets, &engine_options)?;
+  let viewport = Rect { x: 0.0, y: 0.0, width: options.engine.viewport_width, height: options.engine.viewport_height };
+  return Ok(render_document(&document, viewport)?.canvas);
+}
+
+fn run_case(case: &Case, options: &Options) -> Result<Outcome, EngineError> {
+  let canvas = render_case(case, options)?;
+  if options.bless {
+    save_canvas(&canvas, &case.expected.display().to_string(), OutputFormat::Png)?;
+    return Ok(Outcome::Blessed);
+  }
+
+  let expected = match image::open(&case.expected) {
+    Ok(image) => image.to_rgba(),
+    Err(error) => return Ok(Outcome::Fail(format!("cannot read reference {}: {:?}", case.expected.display(), error))),
+  };
+  let actual = canvas_to_image(&canvas).to_rgba();
+  if expected.dimensions() != actual.dimensions() {
+    return Ok(Outcome::Fail(format!("size differs: expected {:?}, got {:?}", expected.dimensions(), actual.dimensions())));
+  }
+
+  // 許容範囲を超えて違うピクセルを数える
+  let mut mismatched = 0;
+  let mut max_difference = 0;
+  for (a, b) in expected.pixels().zip(actual.pixels()) {
+    let difference = a.data.iter().zip(b.data.iter()).map(|(&x, &y)| (x as i16 - y as i16).abs() as u8).max().unwrap_or(0);
+    max_difference = max_difference.max(difference);
+    if difference > options.tolerance {
+      mismatched += 1;
+    }
+  }
+  if mismatched == 0 {
+    return Ok(Outcome::Pass);
+  }
+
+  // 見比べられるように、実際の描画結果を横に書き出しておく
+  let actual_path = case.expected.with_extension("actual.png");
+  save_canvas(&canvas, &actual_path.display().to_string(), OutputFormat::Png)?;
+  return Ok(Outcome::Fail(format!(
+    "{} pixels differ (max channel difference {}), actual output written to {}",
+    mismatched, max_difference, actual_path.display()
+  )));
+}
+
+// すべてのケースを実行する。全部通れば true
+pub fn run(dir: &str, options: &Options) -> Result<bool, EngineError> {
+  let cases = find_cases(Path::new(dir))?;
+  let mut failures = 0;
+  for case in &cases {
+    match run_case(case, options) {
+      Ok(Outcome::Pass) => info!("PASS {}", case.name),
+      Ok(Outcome::Blessed) => info!("BLESS {}", case.name),
+      Ok(Outcome::Fail(reason)) => {
+        failures += 1;
+        error!("FAIL {}: {}", case.name, reason);
+      }
+      Err(error) => {
+        failures += 1;
+        error!("FAIL {}: {}", case.name, error);
+      }
+    }
+  }
+  info!("{} cases, {} passed, {} failed", cases.len(), cases.len() - failures, failures);
+  return Ok(failures == 0);
+}
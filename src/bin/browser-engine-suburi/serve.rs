@@ -0,0 +1,272 @@
+use browser_engine_suburi::engine::Engine;
+use browser_engine_suburi::error::EngineError;
+use browser_engine_suburi::loader::IoError;
+use browser_engine_suburi::options::EngineOptions;
+use browser_engine_suburi::output::{encode_canvas, OutputFormat};
+use browser_engine_suburi::url::percent_decode;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/**
+ * serve サブコマンド。HTML を受け取って、描画した PNG を返す小さな HTTP サーバー。
+ * スクリーンショットのサービスの裏で動かすためのもので、ワーカーのスレッドごとに Engine を 1 つ持ち続けて使い回す。
+ *
+ *   POST /render          本文が HTML（Content-Type: text/html など）。css、width、height、full_page はクエリで渡す
+ *   POST /render          application/x-www-form-urlencoded なら html、css、width、height、full_page のフィールド
+ *   GET  /render?html=... クエリだけで渡す
+ *
+ * 幅と高さを省略したら設定ファイル（-W/-H）の値。full_page=1 ならドキュメント全体の高さで描く（max_page_height まで）。HTML や CSS のパースに失敗したら 400 を返す。
+ * 接続は 1 つの応答ごとに閉じる（Keep-Alive には対応しない）
+ */
+
+// これより大きいビューポートはキャンバスが大きくなりすぎるので断る
+const MAX_VIEWPORT_SIZE: f32 = 8192.0;
+const MAX_HEADER_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct Options {
+  pub address: String, // 待ち受けるアドレス（127.0.0.1:8080 など）
+  pub workers: usize,  // ワーカーの数（0 なら CPU の数）
+  pub engine: EngineOptions,
+}
+
+struct Request {
+  method: String,
+  path: String,
+  query: String,
+  content_type: Option<String>,
+  body: Vec<u8>,
+}
+
+struct Response {
+  status: u16,
+  content_type: &'static str,
+  body: Vec<u8>,
+}
+
+impl Response {
+  fn text(status: u16, message: &str) -> Response {
+    return Response { status: status, content_type: "text/plain; charset=utf-8", body: format!("{}\n", message).into_bytes() };
+  }
+}
+
+// 受け付けた接続をワーカーに渡し続ける（戻るのは待ち受けに失敗したときだけ）
+pub fn run(options: &Options) -> Result<(), EngineError> {
+  let listener = TcpListener::bind(&options.address).map_err(|error| IoError::Listen { address: options.address.clone(), source: error })?;
+  let workers = if options.workers == 0 { thread::available_parallelism().map_or(1, |count| count.get()) } else { options.workers };
+  let address = listener.local_addr().map(|address| address.to_string()).unwrap_or_else(|_| options.address.clone());
+  info!("Listening on http://{}/render with {} worker(s)", address, workers);
+
+  let (sender, receiver) = mpsc::channel();
+  let receiver = Arc::new(Mutex::new(receiver));
+  for _ in 0..workers {
+    let receiver = receiver.clone();
+    let options = options.engine.clone();
+    thread::spawn(move || work(receiver, options));
+  }
+
+  for stream in listener.incoming() {
+    match stream {
+      Ok(stream) => {
+        let _ = sender.send(stream);
+      }
+      Err(error) => warn!("Failed to accept a connection: {}", error),
+    }
+  }
+  return Ok(());
+}
+
+fn work(receiver: Arc<Mutex<Receiver<TcpStream>>>, options: EngineOptions) {
+  let mut engine = Engine::new(options.clone());
+  loop {
+    let stream = match receiver.lock().unwrap().recv() {
+      Ok(stream) => stream,
+      Err(_) => return,
+    };
+    if let Err(error) = handle(&mut engine, stream, &options) {
+      debug!("Connection closed: {}", error);
+    }
+  }
+}
+
+fn handle(engine: &mut Engine, mut stream: TcpStream, options: &EngineOptions) -> std::io::Result<()> {
+  stream.set_read_timeout(Some(READ_TIMEOUT))?;
+  let response = match read_request(&mut stream, options.max_resource_size) {
+    Ok(request) => {
+      let response = respond(engine, &request, options);
+      info!("{} {} {}", request.method, request.path, response.status);
+      response
+    }
+    Err(response) => response,
+  };
+  return write_response(&mut stream, &response);
+}
+
+fn respond(engine: &mut Engine, request: &Request, options: &EngineOptions) -> Response {
+  if request.path != "/render" {
+    return Response::text(404, "not found (use /render)");
+  }
+  if request.method != "GET" && request.method != "POST" {
+    return Response::text(405, "method not allowed (use GET or POST)");
+  }
+
+  let mut params = parse_form(&request.query);
+  let form = request.content_type.as_ref().map_or(false, |content_type| content_type.starts_with("application/x-www-form-urlencoded"));
+  if form {
+    params.extend(parse_form(&String::from_utf8_lossy(&request.body)));
+  } else if !request.body.is_empty() {
+    params.push(("html".to_string(), String::from_utf8_lossy(&request.body).into_owned()));
+  }
+  let param = |name: &str| params.iter().rev().find(|(key, _)| key == name).map(|(_, value)| value.clone());
+
+  let html = match param("html") {
+    Some(html) => html,
+    None => return Response::text(400, "missing html"),
+  };
+  let width = match viewport_size(param("width"), options.viewport_width) {
+    Some(width) => width,
+    None => return Response::text(400, "invalid width"),
+  };
+  let height = match viewport_size(param("height"), options.viewport_height) {
+    Some(height) => height,
+    None => return Response::text(400, "invalid height"),
+  };
+
+  let full_page = param("full_page").map_or(false, |value| value == "1" || value == "true");
+
+  return match render(engine, &html, param("css"), width, height, full_page) {
+    Ok(png) => Response { status: 200, content_type: "image/png", body: png },
+    Err(error @ EngineError::Html(_)) | Err(error @ EngineError::Css(_)) => Response::text(400, &error.to_string()),
+    Err(error) => {
+      warn!("Failed to render: {}", error);
+      Response::text(500, &error.to_string())
+    }
+  };
+}
+
+// 前のリクエストのドキュメント、スタイルシート、キャッシュ（とクッキー）を持ち越さない
+fn render(engine: &mut Engine, html: &str, css: Option<String>, width: f32, height: f32, full_page: bool) -> Result<Vec<u8>, EngineError> {
+  engine.clear_cache();
+  engine.clear_stylesheets();
+  engine.set_html(html);
+  if let Some(css) = css {
+    engine.add_stylesheet(&css);
+  }
+  engine.set_viewport(width, height);
+  engine.set_scroll(0.0, 0.0);
+  engine.set_full_page(full_page);
+  engine.update()?;
+  let canvas = engine.canvas().ok_or(EngineError::NoDocument)?;
+  return encode_canvas(canvas, OutputFormat::Png);
+}
+
+fn viewport_size(value: Option<String>, default: f32) -> Option<f32> {
+  let size = match value {
+    Some(value) => value.trim().parse::<f32>().ok()?,
+    None => default,
+  };
+  if (1.0..=MAX_VIEWPORT_SIZE).contains(&size) {
+    return Some(size);
+  }
+  return None;
+}
+
+// a=1&b=2 を名前と値の組に（+ は空白）
+fn parse_form(input: &str) -> Vec<(String, String)> {
+  let decode = |part: &str| String::from_utf8_lossy(&percent_decode(&part.replace('+', " "))).into_owned();
+  return input
+    .split('&')
+    .filter(|pair| !pair.is_empty())
+    .map(|pair| match pair.find('=') {
+      Some(index) => (decode(&pair[..index]), decode(&pair[index + 1..])),
+      None => (decode(pair), String::new()),
+    })
+    .collect();
+}
+
+// リクエスト行、ヘッダ、Content-Length の長さの本文を読む。読めなければ返す応答
+fn read_request(stream: &mut TcpStream, max_body_size: u64) -> Result<Request, Response> {
+  let mut reader = BufReader::new(stream.try_clone().map_err(|_| Response::text(500, "internal error"))?);
+  let request_line = read_line(&mut reader)?;
+  let mut parts = request_line.split_whitespace();
+  let (method, target) = match (parts.next(), parts.next()) {
+    (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+    _ => return Err(Response::text(400, "malformed request line")),
+  };
+  let (path, query) = match target.find('?') {
+    Some(index) => (target[..index].to_string(), target[index + 1..].to_string()),
+    None => (target, String::new()),
+  };
+
+  let mut content_length = 0;
+  let mut content_type = None;
+  let mut expect_continue = false;
+  for count in 0.. {
+    let line = read_line(&mut reader)?;
+    if line.is_empty() {
+      break;
+    }
+    if count >= MAX_HEADERS {
+      return Err(Response::text(431, "too many headers"));
+    }
+    let (name, value) = match line.find(':') {
+      Some(index) => (line[..index].trim().to_ascii_lowercase(), line[index + 1..].trim().to_string()),
+      None => return Err(Response::text(400, "malformed header")),
+    };
+    match &*name {
+      "content-length" => content_length = value.parse::<u64>().map_err(|_| Response::text(400, "invalid content-length"))?,
+      "content-type" => content_type = Some(value.to_ascii_lowercase()),
+      "expect" => expect_continue = value.eq_ignore_ascii_case("100-continue"),
+      _ => {}
+    }
+  }
+  if content_length > max_body_size {
+    return Err(Response::text(413, "request body too large"));
+  }
+
+  // curl などは大きな本文を送る前に 100 Continue を待つ
+  if expect_continue && content_length > 0 {
+    let _ = stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+  }
+  let mut body = vec![0; content_length as usize];
+  reader.read_exact(&mut body).map_err(|_| Response::text(400, "incomplete request body"))?;
+
+  return Ok(Request { method: method, path: path, query: query, content_type: content_type, body: body });
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>) -> Result<String, Response> {
+  let mut line = Vec::new();
+  reader.by_ref().take(MAX_HEADER_LINE as u64).read_until(b'\n', &mut line).map_err(|_| Response::text(400, "failed to read request"))?;
+  if !line.ends_with(b"\n") {
+    return Err(Response::text(431, "header line too long"));
+  }
+  return Ok(String::from_utf8_lossy(&line).trim_end().to_string());
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> std::io::Result<()> {
+  let reason = match response.status {
+    200 => "OK",
+    400 => "Bad Request",
+    404 => "Not Found",
+    405 => "Method Not Allowed",
+    413 => "Payload Too Large",
+    431 => "Request Header Fields Too Large",
+    _ => "Internal Server Error",
+  };
+  write!(
+    stream,
+    "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+    response.status,
+    reason,
+    response.content_type,
+    response.body.len()
+  )?;
+  stream.write_all(&response.body)?;
+  return stream.flush();
+}
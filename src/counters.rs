@@ -0,0 +1,230 @@
+use css::Value;
+use dom::NodeType;
+use style::{Display, StyledNode};
+
+/**
+ * CSS のカウンター（counter-reset、counter-increment、counter-set）と、content の中の counter()、counters()、attr()。
+ *
+ *   ol { counter-reset: item; }
+ *   li { counter-increment: item; }
+ *   .number { content: counters(item, ".") ". "; }
+ *
+ * スタイルツリーを作った後に、ボックスを作るのと同じ順（ドキュメント順、display: none の中は飛ばす）でカウンターを数えて、
+ * content を描く文字列（Value::Str）に置き換える。content を持つ要素は、子の代わりにその文字列を描く。
+ *
+ * カウンターの範囲は、リセットした要素とその子孫、後の兄弟とその子孫。兄弟が前にリセットしたカウンターを
+ * もう一度リセットしたら、入れ子にせずに値を置き換える（h2 ごとに h3 の番号を振り直すときなど）
+ *
+ * display: list-item の要素は list-item カウンターを 1 ずつ増やし（<li value> ならその値にする）、その値を
+ * list-style-type の書式にしたマーカーの文字列を MARKER に入れる。<ul> と <ol> は組み込みのスタイルシートで
+ * list-item をリセットするので、番号は親のリストごとに 1 から（<ol start> ならその値から）になる
+ */
+
+// マーカーの文字列（Value::Str）を入れる specified_values の名前。CSS のプロパティにはならない名前にする
+pub const MARKER: &'static str = "::marker";
+
+struct Counter {
+  name: String,
+  value: i32,
+  depth: usize, // リセットした要素の深さ
+}
+
+// style_tree の後に呼ぶ。root は display: none でもよい（何もしない）
+pub fn apply(root: &mut StyledNode) {
+  let mut counters = Vec::new();
+  visit(root, 0, &mut counters);
+}
+
+fn visit(node: &mut StyledNode, depth: usize, counters: &mut Vec<Counter>) {
+  if node.display() == Display::None {
+    return;
+  }
+  if let NodeType::Element(ref elem) = node.node.node_type {
+    for (name, value) in counter_list(node.specified_values.get("counter-reset"), 0) {
+      reset(counters, &name, value, depth);
+    }
+    if elem.tag_name == "ol" {
+      if let Some(start) = elem.attributes.get("start").and_then(|start| start.trim().parse::<i32>().ok()) {
+        reset(counters, "list-item", start.saturating_sub(1), depth);
+      }
+    }
+    let list_item = node.display() == Display::ListItem;
+    let mut increments = counter_list(node.specified_values.get("counter-increment"), 1);
+    if list_item && !increments.iter().any(|&(ref name, _)| name == "list-item") {
+      increments.push(("list-item".to_string(), 1));
+    }
+    for (name, value) in increments {
+      match counters.iter_mut().rev().find(|counter| counter.name == name) {
+        Some(counter) => counter.value = counter.value.saturating_add(value),
+        None => reset(counters, &name, value, depth),
+      }
+    }
+    let mut sets = counter_list(node.specified_values.get("counter-set"), 0);
+    if list_item {
+      if let Some(value) = elem.attributes.get("value").and_then(|value| value.trim().parse::<i32>().ok()) {
+        sets.push(("list-item".to_string(), value));
+      }
+    }
+    for (name, value) in sets {
+      match counters.iter_mut().rev().find(|counter| counter.name == name) {
+        Some(counter) => counter.value = value,
+        None => reset(counters, &name, value, depth),
+      }
+    }
+    let marker = if list_item { counters.iter().rev().find(|counter| counter.name == "list-item").and_then(|counter| marker(counter.value, &node.list_style_type())) } else { None };
+    match marker {
+      Some(text) => node.specified_values.insert(MARKER.to_string(), Value::Str(text)),
+      // スタイルを計算し直したときに、前のマーカーを残さない
+      None => node.specified_values.remove(MARKER),
+    };
+    let content = node.specified_values.get("content").and_then(|content| resolve(content, node, depth, counters));
+    if let Some(text) = content {
+      node.specified_values.insert("content".to_string(), Value::Str(text));
+    }
+  }
+
+  // 子がリセットしたカウンターは、この要素の中を出たら使えない
+  let scope = counters.len();
+  for child in &mut node.children {
+    visit(child, depth + 1, counters);
+  }
+  counters.truncate(scope);
+}
+
+fn reset(counters: &mut Vec<Counter>, name: &str, value: i32, depth: usize) {
+  if let Some(counter) = counters.iter_mut().rev().find(|counter| counter.name == name).filter(|counter| counter.depth == depth) {
+    counter.value = value;
+    return;
+  }
+  counters.push(Counter { name: name.to_string(), value: value, depth: depth });
+}
+
+// "a 1 b" → [(a, 1), (b, default)]。none なら空
+fn counter_list(value: Option<&Value>, default: i32) -> Vec<(String, i32)> {
+  let values = match value {
+    Some(Value::List(values)) => values.iter().collect(),
+    Some(value) => vec![value],
+    None => Vec::new(),
+  };
+  let mut list: Vec<(String, i32)> = Vec::new();
+  for value in values {
+    match *value {
+      Value::Keyword(ref name) if name == "none" => return Vec::new(),
+      Value::Keyword(ref name) => list.push((name.clone(), default)),
+      Value::Number(number) => {
+        if let Some(last) = list.last_mut() {
+          last.1 = number as i32;
+        }
+      }
+      _ => {}
+    }
+  }
+  return list;
+}
+
+// content を文字列にする。none、normal や、文字列にできない値だけなら None（何も描かない）
+fn resolve(content: &Value, node: &StyledNode, depth: usize, counters: &mut Vec<Counter>) -> Option<String> {
+  let parts = match *content {
+    Value::List(ref values) => values.iter().collect(),
+    ref value => vec![value],
+  };
+  let mut text = String::new();
+  let mut any = false;
+  for part in parts {
+    match *part {
+      Value::Str(ref string) => text.push_str(string),
+      Value::Function(ref name, ref args) if name == "counter" => {
+        let name = keyword(args.first())?;
+        let style = keyword(args.get(1)).unwrap_or("decimal");
+        let value = match counters.iter().rev().find(|counter| counter.name == name) {
+          Some(counter) => counter.value,
+          None => {
+            reset(counters, name, 0, depth); // ない counter() は、この要素でリセットしたものとする
+            0
+          }
+        };
+        text.push_str(&format_counter(value, style));
+      }
+      Value::Function(ref name, ref args) if name == "counters" => {
+        let name = keyword(args.first())?;
+        let separator = match args.get(1) {
+          Some(Value::Str(separator)) => separator.as_str(),
+          _ => return None,
+        };
+        let style = keyword(args.get(2)).unwrap_or("decimal");
+        if !counters.iter().any(|counter| counter.name == name) {
+          reset(counters, name, 0, depth);
+        }
+        let values: Vec<String> = counters.iter().filter(|counter| counter.name == name).map(|counter| format_counter(counter.value, style)).collect();
+        text.push_str(&values.join(separator));
+      }
+      Value::Function(ref name, ref args) if name == "attr" => {
+        let attribute = keyword(args.first())?;
+        if let NodeType::Element(ref elem) = node.node.node_type {
+          text.push_str(elem.attributes.get(attribute).map_or("", |value| value.as_str()));
+        }
+      }
+      _ => return None,
+    }
+    any = true;
+  }
+  return if any { Some(text) } else { None };
+}
+
+// list-style-type が style のマーカー。記号なら後ろに空白、番号なら ". " を付ける。none なら None
+fn marker(value: i32, style: &str) -> Option<String> {
+  let text = format_counter(value, style);
+  return match style {
+    "none" => None,
+    "disc" | "circle" | "square" => Some(format!("{} ", text)),
+    _ => Some(format!("{}. ", text)),
+  };
+}
+
+fn keyword(value: Option<&Value>) -> Option<&str> {
+  return match value {
+    Some(Value::Keyword(keyword)) => Some(keyword),
+    _ => None,
+  };
+}
+
+// list-style-type と同じ名前の書式。知らないものは decimal
+pub fn format_counter(value: i32, style: &str) -> String {
+  return match style {
+    "none" => String::new(),
+    "disc" => "•".to_string(),
+    "circle" => "◦".to_string(),
+    "square" => "▪".to_string(),
+    "decimal-leading-zero" if (0..10).contains(&value) => format!("0{}", value),
+    "lower-roman" if (1..4000).contains(&value) => roman(value),
+    "upper-roman" if (1..4000).contains(&value) => roman(value).to_uppercase(),
+    "lower-alpha" | "lower-latin" if value >= 1 => alphabetic(value, &"abcdefghijklmnopqrstuvwxyz".chars().collect::<Vec<_>>()),
+    "upper-alpha" | "upper-latin" if value >= 1 => alphabetic(value, &"ABCDEFGHIJKLMNOPQRSTUVWXYZ".chars().collect::<Vec<_>>()),
+    "lower-greek" if value >= 1 => alphabetic(value, &"αβγδεζηθικλμνξοπρστυφχψω".chars().collect::<Vec<_>>()),
+    _ => value.to_string(),
+  };
+}
+
+fn roman(mut value: i32) -> String {
+  const NUMERALS: [(i32, &str); 13] =
+    [(1000, "m"), (900, "cm"), (500, "d"), (400, "cd"), (100, "c"), (90, "xc"), (50, "l"), (40, "xl"), (10, "x"), (9, "ix"), (5, "v"), (4, "iv"), (1, "i")];
+  let mut out = String::new();
+  for &(number, numeral) in NUMERALS.iter() {
+    while value >= number {
+      out.push_str(numeral);
+      value -= number;
+    }
+  }
+  return out;
+}
+
+// a, b, ..., z, aa, ab, ...
+fn alphabetic(mut value: i32, letters: &[char]) -> String {
+  let mut out = Vec::new();
+  while value > 0 {
+    value -= 1;
+    out.push(letters[value as usize % letters.len()]);
+    value /= letters.len() as i32;
+  }
+  return out.into_iter().rev().collect();
+}
@@ -1,33 +1,165 @@
-#[derive(Debug)]
+use std::fmt;
+use thiserror::Error;
+use trace;
+use url::Url;
+
+// CSS パース時のエラー。pos は入力中のバイト位置
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum CssError {
+  #[error("unexpected end of input")]
+  UnexpectedEof,
+  #[error("expected {expected:?} but found {found:?} at {pos}")]
+  UnexpectedChar { expected: char, found: char, pos: usize },
+  #[error("unexpected character {found:?} in selector list at {pos}")]
+  InvalidSelector { found: char, pos: usize },
+  #[error("invalid number {number:?} at {pos}")]
+  InvalidNumber { number: String, pos: usize },
+  #[error("unrecognized unit {unit:?} at {pos}")]
+  UnknownUnit { unit: String, pos: usize },
+  #[error("invalid color at {pos}")]
+  InvalidColor { pos: usize },
+  #[error("invalid gradient at {pos}")]
+  InvalidGradient { pos: usize },
+  #[error("invalid var() at {pos}")]
+  InvalidVar { pos: usize },
+  #[error("invalid calc() at {pos}")]
+  InvalidCalc { pos: usize },
+  #[error("unexpected character {found:?} in value at {pos}")]
+  InvalidValue { found: char, pos: usize },
+  #[error("nested too deeply at {pos}")]
+  TooDeep { pos: usize },
+  #[error("@import after other rules is ignored at {pos}")]
+  MisplacedImport { pos: usize },
+  #[error("negative value for {property:?} at {pos}")]
+  NegativeValue { property: String, pos: usize },
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct StyleSheet {
   pub rules: Vec<Rule>,
+  pub keyframes: Vec<Keyframes>, // @keyframes（同じ名前なら後のものを使う）
+  pub imports: Vec<Import>, // トップレベルの @import（書いた順）。読み込むのは呼び出し側
+}
+
+// @import url(a.css) print; の読み込むスタイルシート。url は書いたままで、media が空なら常に適用する
+#[derive(Debug, Clone)]
+pub struct Import {
+  pub url: String,
+  pub media: MediaQueryList,
+}
+
+// @keyframes name { from { ... } 50% { ... } to { ... } }
+#[derive(Debug, Clone)]
+pub struct Keyframes {
+  pub name: String,
+  pub frames: Vec<Keyframe>, // offset の順
+}
+
+// offset は 0（from）から 1（to）まで。"0%, 100%" のように複数書いたものは 1 つずつに分ける
+#[derive(Debug, Clone)]
+pub struct Keyframe {
+  pub offset: f32,
+  pub declarations: Vec<Declaration>,
 }
 
 // { prop: val } の 1 つか複数のセレクター
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Rule {
   pub selectors: Vec<Selector>,
   pub declarations: Vec<Declaration>,
+  pub media: Vec<MediaQueryList>, // 囲んでいる @media の条件（入れ子なら外側から順に）。すべてに合うときだけ適用する
+  pub origin: Origin,
+}
+
+// ルールがどこから来たか。カスケードでは !important かどうかと合わせて優先順位を決める
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Origin {
+  UserAgent, // 組み込みのスタイルシート
+  Author,    // ページのスタイルシート
+}
+
+// @media screen, not print のカンマ区切りのクエリ。どれか 1 つに合えば合う（空なら常に合う）
+pub type MediaQueryList = Vec<MediaQuery>;
+
+// @media の 1 つのクエリ（not print、only screen and (min-width: 600px) など）
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaQuery {
+  pub negated: bool,         // not
+  pub media_type: String,    // all、screen、print。読めないクエリは not all にする
+  pub features: Vec<String>, // and で続く (min-width: 600px) などのメディア特性（括弧の中身）
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Selector {
   Simple(SimpleSelector),
+  Complex(ComplexSelector),
+}
+
+// 結合子でつないだセレクター（div p、ul > li.a など）。
+// subject は一致させる要素（右端）で、ancestors はその左側を右から順に、左隣との結合子と組にしたもの
+#[derive(Debug, Clone)]
+pub struct ComplexSelector {
+  pub subject: SimpleSelector,
+  pub ancestors: Vec<(Combinator, SimpleSelector)>,
 }
 
-// とりあえずシンプルなセレクターを定義（タグ名、id, class）
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Combinator {
+  Descendant, // 空白。祖先のどれか
+  Child,      // >。親
+}
+
+// とりあえずシンプルなセレクターを定義（タグ名、id, class, 属性、疑似クラス、疑似要素）
+#[derive(Debug, Clone)]
 pub struct SimpleSelector {
   pub tag_name: Option<String>,
   pub id: Option<String>,
   pub class: Vec<String>,
+  pub attributes: Vec<AttributeSelector>,
+  pub pseudo_classes: Vec<PseudoClass>,
+  pub pseudo_element: Option<PseudoElement>, // 主体（いちばん右）の最後にだけ書ける
+}
+
+// [attr]、[attr="value"]、[attr~="value"]
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeSelector {
+  Exists(String),
+  Equals(String, String),
+  Includes(String, String), // 空白で区切った値のどれか
+}
+
+// :first-child、:last-child、:nth-child(an+b) は兄弟の要素の中での位置で、:hover はポインターの位置（dom::ElementData の hover）で一致させる
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PseudoClass {
+  FirstChild,
+  LastChild,
+  NthChild(i32, i32), // a と b。n = 0, 1, 2... で an+b 番目（1 から）
+  Hover,
+  Root, // ドキュメントのルート要素（祖先の要素がない）
+}
+
+// ::before と ::after（CSS 2 の :before と :after も）。要素の最初と最後の子として、content の文字列を描くボックスを作る
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PseudoElement {
+  Before,
+  After,
+}
+
+impl PseudoElement {
+  pub fn name(&self) -> &'static str {
+    return match *self {
+      PseudoElement::Before => "::before",
+      PseudoElement::After => "::after",
+    };
+  }
 }
 
 // 宣言（propName: value のセミコロンで終わるペア）
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Declaration {
   pub name: String,
   pub value: Value,
+  pub important: bool, // 値の後ろに !important があった
 }
 
 // 値
@@ -36,12 +168,78 @@ pub enum Value {
   Keyword(String),   // 文字列
   Length(f32, Unit), // 数値
   ColorValue(Color), // カラー値
+  Url(String),       // url(...)
+  Str(String),       // "..." か '...'
+  Number(f32),       // 単位のない数値
+  Function(String, Vec<Value>), // counter(item, upper-roman) など。引数はカンマ区切り
+  List(Vec<Value>),  // 空白で区切った複数の値（counter-reset: a 1 b など）
+  Gradient(Gradient), // linear-gradient(...)
+  Var(String, Option<Box<Value>>), // var(--name, fallback)。スタイルの計算でカスタムプロパティの値に置き換える
+  Calc(Box<Calc>), // calc(...)。% などはレイアウトで px にしてから計算する
+  Transform(Transform), // transform の関数を掛け合わせたもの。スタイルの計算で関数の並びから作る
+}
+
+// 2D のアフィン変換。点 (x, y) を (a x + c y + e, b x + d y + f) に移す
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix {
+  pub a: f32,
+  pub b: f32,
+  pub c: f32,
+  pub d: f32,
+  pub e: f32,
+  pub f: f32,
+}
+
+// transform の値。translate の % はボックスの大きさで決まるので、平行移動に足す幅と高さに掛ける係数を分けて持つ。
+// 変換の原点は transform-origin で、ここには含めない
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+  pub matrix: Matrix,
+  pub percent: [f32; 4], // e に足す幅と高さの係数、f に足す幅と高さの係数
+}
+
+// calc() の式
+#[derive(Debug, Clone, PartialEq)]
+pub enum Calc {
+  Value(Value), // 長さ、数値、var()、入れ子の calc()
+  Operation(Operator, Box<Calc>, Box<Calc>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+  Add,      // +
+  Subtract, // -
+  Multiply, // *
+  Divide,   // /
+}
+
+// linear-gradient() の値。angle は色が変わっていく向きの角度（0deg が上、90deg が右）
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+  pub angle: f32,
+  pub stops: Vec<ColorStop>,
+}
+
+// 色と、グラデーションの線の上の位置（% か長さ。なければ前後の位置の間に均等に置く）
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorStop {
+  pub color: Color,
+  pub position: Option<Value>,
 }
 
 // 単位
 #[derive(Debug, Clone, PartialEq)]
 pub enum Unit {
   Px,
+  Em,      // 要素の font-size（font-size では親の font-size）に対する倍率
+  Rem,     // ルート要素の font-size に対する倍率
+  Percent, // %。何に対する割合かはプロパティで決まる
+  Vw,      // ビューポートの幅の 1/100
+  Vh,      // ビューポートの高さの 1/100
+  S,  // 秒（animation-duration など）
+  Ms, // ミリ秒
+  Fr, // grid のトラックで、残りの幅を分ける比
+  Deg, // 角度（linear-gradient の向き）
 }
 
 // RGB
@@ -55,20 +253,346 @@ pub struct Color {
 
 impl Copy for Color {}
 
+// 色の名前（名前順。値は 0xrrggbb）
+const NAMED_COLORS: &[(&str, u32)] = &[
+  ("aliceblue", 0xf0f8ff),
+  ("antiquewhite", 0xfaebd7),
+  ("aqua", 0x00ffff),
+  ("aquamarine", 0x7fffd4),
+  ("azure", 0xf0ffff),
+  ("beige", 0xf5f5dc),
+  ("bisque", 0xffe4c4),
+  ("black", 0x000000),
+  ("blanchedalmond", 0xffebcd),
+  ("blue", 0x0000ff),
+  ("blueviolet", 0x8a2be2),
+  ("brown", 0xa52a2a),
+  ("burlywood", 0xdeb887),
+  ("cadetblue", 0x5f9ea0),
+  ("chartreuse", 0x7fff00),
+  ("chocolate", 0xd2691e),
+  ("coral", 0xff7f50),
+  ("cornflowerblue", 0x6495ed),
+  ("cornsilk", 0xfff8dc),
+  ("crimson", 0xdc143c),
+  ("cyan", 0x00ffff),
+  ("darkblue", 0x00008b),
+  ("darkcyan", 0x008b8b),
+  ("darkgoldenrod", 0xb8860b),
+  ("darkgray", 0xa9a9a9),
+  ("darkgreen", 0x006400),
+  ("darkgrey", 0xa9a9a9),
+  ("darkkhaki", 0xbdb76b),
+  ("darkmagenta", 0x8b008b),
+  ("darkolivegreen", 0x556b2f),
+  ("darkorange", 0xff8c00),
+  ("darkorchid", 0x9932cc),
+  ("darkred", 0x8b0000),
+  ("darksalmon", 0xe9967a),
+  ("darkseagreen", 0x8fbc8f),
+  ("darkslateblue", 0x483d8b),
+  ("darkslategray", 0x2f4f4f),
+  ("darkslategrey", 0x2f4f4f),
+  ("darkturquoise", 0x00ced1),
+  ("darkviolet", 0x9400d3),
+  ("deeppink", 0xff1493),
+  ("deepskyblue", 0x00bfff),
+  ("dimgray", 0x696969),
+  ("dimgrey", 0x696969),
+  ("dodgerblue", 0x1e90ff),
+  ("firebrick", 0xb22222),
+  ("floralwhite", 0xfffaf0),
+  ("forestgreen", 0x228b22),
+  ("fuchsia", 0xff00ff),
+  ("gainsboro", 0xdcdcdc),
+  ("ghostwhite", 0xf8f8ff),
+  ("gold", 0xffd700),
+  ("goldenrod", 0xdaa520),
+  ("gray", 0x808080),
+  ("green", 0x008000),
+  ("greenyellow", 0xadff2f),
+  ("grey", 0x808080),
+  ("honeydew", 0xf0fff0),
+  ("hotpink", 0xff69b4),
+  ("indianred", 0xcd5c5c),
+  ("indigo", 0x4b0082),
+  ("ivory", 0xfffff0),
+  ("khaki", 0xf0e68c),
+  ("lavender", 0xe6e6fa),
+  ("lavenderblush", 0xfff0f5),
+  ("lawngreen", 0x7cfc00),
+  ("lemonchiffon", 0xfffacd),
+  ("lightblue", 0xadd8e6),
+  ("lightcoral", 0xf08080),
+  ("lightcyan", 0xe0ffff),
+  ("lightgoldenrodyellow", 0xfafad2),
+  ("lightgray", 0xd3d3d3),
+  ("lightgreen", 0x90ee90),
+  ("lightgrey", 0xd3d3d3),
+  ("lightpink", 0xffb6c1),
+  ("lightsalmon", 0xffa07a),
+  ("lightseagreen", 0x20b2aa),
+  ("lightskyblue", 0x87cefa),
+  ("lightslategray", 0x778899),
+  ("lightslategrey", 0x778899),
+  ("lightsteelblue", 0xb0c4de),
+  ("lightyellow", 0xffffe0),
+  ("lime", 0x00ff00),
+  ("limegreen", 0x32cd32),
+  ("linen", 0xfaf0e6),
+  ("magenta", 0xff00ff),
+  ("maroon", 0x800000),
+  ("mediumaquamarine", 0x66cdaa),
+  ("mediumblue", 0x0000cd),
+  ("mediumorchid", 0xba55d3),
+  ("mediumpurple", 0x9370db),
+  ("mediumseagreen", 0x3cb371),
+  ("mediumslateblue", 0x7b68ee),
+  ("mediumspringgreen", 0x00fa9a),
+  ("mediumturquoise", 0x48d1cc),
+  ("mediumvioletred", 0xc71585),
+  ("midnightblue", 0x191970),
+  ("mintcream", 0xf5fffa),
+  ("mistyrose", 0xffe4e1),
+  ("moccasin", 0xffe4b5),
+  ("navajowhite", 0xffdead),
+  ("navy", 0x000080),
+  ("oldlace", 0xfdf5e6),
+  ("olive", 0x808000),
+  ("olivedrab", 0x6b8e23),
+  ("orange", 0xffa500),
+  ("orangered", 0xff4500),
+  ("orchid", 0xda70d6),
+  ("palegoldenrod", 0xeee8aa),
+  ("palegreen", 0x98fb98),
+  ("paleturquoise", 0xafeeee),
+  ("palevioletred", 0xdb7093),
+  ("papayawhip", 0xffefd5),
+  ("peachpuff", 0xffdab9),
+  ("peru", 0xcd853f),
+  ("pink", 0xffc0cb),
+  ("plum", 0xdda0dd),
+  ("powderblue", 0xb0e0e6),
+  ("purple", 0x800080),
+  ("rebeccapurple", 0x663399),
+  ("red", 0xff0000),
+  ("rosybrown", 0xbc8f8f),
+  ("royalblue", 0x4169e1),
+  ("saddlebrown", 0x8b4513),
+  ("salmon", 0xfa8072),
+  ("sandybrown", 0xf4a460),
+  ("seagreen", 0x2e8b57),
+  ("seashell", 0xfff5ee),
+  ("sienna", 0xa0522d),
+  ("silver", 0xc0c0c0),
+  ("skyblue", 0x87ceeb),
+  ("slateblue", 0x6a5acd),
+  ("slategray", 0x708090),
+  ("slategrey", 0x708090),
+  ("snow", 0xfffafa),
+  ("springgreen", 0x00ff7f),
+  ("steelblue", 0x4682b4),
+  ("tan", 0xd2b48c),
+  ("teal", 0x008080),
+  ("thistle", 0xd8bfd8),
+  ("tomato", 0xff6347),
+  ("turquoise", 0x40e0d0),
+  ("violet", 0xee82ee),
+  ("wheat", 0xf5deb3),
+  ("white", 0xffffff),
+  ("whitesmoke", 0xf5f5f5),
+  ("yellow", 0xffff00),
+  ("yellowgreen", 0x9acd32),
+];
+
+impl Color {
+  // red などの色の名前。transparent は透明な黒
+  pub fn named(name: &str) -> Option<Color> {
+    let name = name.to_ascii_lowercase();
+    if name == "transparent" {
+      return Some(Color { r: 0, g: 0, b: 0, a: 0 });
+    }
+    let index = NAMED_COLORS.binary_search_by(|&(key, _)| key.cmp(&*name)).ok()?;
+    let rgb = NAMED_COLORS[index].1;
+    return Some(Color { r: (rgb >> 16) as u8, g: (rgb >> 8) as u8, b: rgb as u8, a: 255 });
+  }
+}
+
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Value::Keyword(ref keyword) => write!(f, "{}", keyword),
+      Value::Length(length, ref unit) => write!(f, "{}{}", length, unit),
+      Value::ColorValue(ref color) => write!(f, "{}", color),
+      Value::Url(ref url) => write!(f, "url(\"{}\")", url),
+      Value::Str(ref text) => write!(f, "\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\a ")),
+      Value::Number(number) => write!(f, "{}", number),
+      Value::Function(ref name, ref args) => write!(f, "{}({})", name, args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().join(", ")),
+      Value::List(ref values) => write!(f, "{}", values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(" ")),
+      Value::Gradient(ref gradient) => {
+        let stops = gradient.stops.iter().map(|stop| match stop.position {
+          Some(ref position) => format!("{} {}", stop.color, position),
+          None => stop.color.to_string(),
+        });
+        write!(f, "linear-gradient({}deg, {})", gradient.angle, stops.collect::<Vec<_>>().join(", "))
+      }
+      Value::Var(ref name, Some(ref fallback)) => write!(f, "var({}, {})", name, fallback),
+      Value::Var(ref name, None) => write!(f, "var({})", name),
+      Value::Calc(ref calc) => write!(f, "calc({})", calc),
+      Value::Transform(ref transform) => {
+        // % の平行移動は幅と高さの係数ごとに calc() で書く
+        let offset = |px: f32, width: f32, height: f32| match (width, height) {
+          (0.0, 0.0) => format!("{}px", px),
+          _ => format!("calc({}px + {}% * width + {}% * height)", px, width * 100.0, height * 100.0),
+        };
+        let Matrix { a, b, c, d, e, f: y } = transform.matrix;
+        let [ew, eh, fw, fh] = transform.percent;
+        write!(f, "matrix({}, {}, {}, {}, {}, {})", a, b, c, d, offset(e, ew, eh), offset(y, fw, fh))
+      }
+    }
+  }
+}
+
+// 入れ子の式は括弧で囲む
+impl fmt::Display for Calc {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Calc::Value(ref value) => write!(f, "{}", value),
+      Calc::Operation(operator, ref left, ref right) => {
+        let operand = |calc: &Calc| match *calc {
+          Calc::Value(_) => calc.to_string(),
+          Calc::Operation(..) => format!("({})", calc),
+        };
+        let operator = match operator {
+          Operator::Add => "+",
+          Operator::Subtract => "-",
+          Operator::Multiply => "*",
+          Operator::Divide => "/",
+        };
+        write!(f, "{} {} {}", operand(left), operator, operand(right))
+      }
+    }
+  }
+}
+
+impl fmt::Display for Unit {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Unit::Px => write!(f, "px"),
+      Unit::Em => write!(f, "em"),
+      Unit::Rem => write!(f, "rem"),
+      Unit::Percent => write!(f, "%"),
+      Unit::Vw => write!(f, "vw"),
+      Unit::Vh => write!(f, "vh"),
+      Unit::S => write!(f, "s"),
+      Unit::Ms => write!(f, "ms"),
+      Unit::Fr => write!(f, "fr"),
+      Unit::Deg => write!(f, "deg"),
+    }
+  }
+}
+
+// #rrggbb、不透明でなければ #rrggbbaa
+impl fmt::Display for Color {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)?;
+    if self.a != 255 {
+      write!(f, "{:02x}", self.a)?;
+    }
+    return Ok(());
+  }
+}
+
+// div#a.b.c[href]:first-child::before のように書く。何も指定がなければ *
+impl fmt::Display for SimpleSelector {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.tag_name.is_none() && self.id.is_none() && self.class.is_empty() && self.attributes.is_empty() && self.pseudo_classes.is_empty() {
+      return write!(f, "*{}", self.pseudo_element.map_or("", |pseudo_element| pseudo_element.name()));
+    }
+    if let Some(ref tag_name) = self.tag_name {
+      write!(f, "{}", tag_name)?;
+    }
+    if let Some(ref id) = self.id {
+      write!(f, "#{}", id)?;
+    }
+    for class in &self.class {
+      write!(f, ".{}", class)?;
+    }
+    for attribute in &self.attributes {
+      match *attribute {
+        AttributeSelector::Exists(ref name) => write!(f, "[{}]", name)?,
+        AttributeSelector::Equals(ref name, ref value) => write!(f, "[{}={}]", name, Value::Str(value.clone()))?,
+        AttributeSelector::Includes(ref name, ref value) => write!(f, "[{}~={}]", name, Value::Str(value.clone()))?,
+      }
+    }
+    for pseudo_class in &self.pseudo_classes {
+      match *pseudo_class {
+        PseudoClass::FirstChild => write!(f, ":first-child")?,
+        PseudoClass::LastChild => write!(f, ":last-child")?,
+        PseudoClass::NthChild(0, b) => write!(f, ":nth-child({})", b)?,
+        PseudoClass::NthChild(a, b) => write!(f, ":nth-child({}n{:+})", a, b)?,
+        PseudoClass::Hover => write!(f, ":hover")?,
+        PseudoClass::Root => write!(f, ":root")?,
+      }
+    }
+    if let Some(pseudo_element) = self.pseudo_element {
+      write!(f, "{}", pseudo_element.name())?;
+    }
+    return Ok(());
+  }
+}
+
+// ul > li.a p のように書く
+impl fmt::Display for Selector {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Selector::Simple(ref simple) => write!(f, "{}", simple),
+      Selector::Complex(ref complex) => {
+        for &(combinator, ref simple) in complex.ancestors.iter().rev() {
+          write!(f, "{}{}", simple, if combinator == Combinator::Child { " > " } else { " " })?;
+        }
+        write!(f, "{}", complex.subject)
+      }
+    }
+  }
+}
+
 pub struct Parser {
   pub pos: usize,
   pub input: String,
+  pub diagnostics: Vec<CssError>, // 読み飛ばした宣言やルールのエラー
+  pub depth: usize, // 読んでいる値の中の関数、calc() と括弧の入れ子の数
 }
 
+// 値の中の関数と括弧、@media の入れ子の上限。深い入力で再帰がスタックをあふれさせないように、超えたものは読めない値やルールにする
+const MAX_NESTING: usize = 64;
+
 pub type Specificity = (usize, usize, usize);
 
 impl Selector {
+  // いちばん右の単純セレクター（一致させる要素）
+  pub fn subject(&self) -> &SimpleSelector {
+    return match *self {
+      Selector::Simple(ref simple) => simple,
+      Selector::Complex(ref complex) => &complex.subject,
+    };
+  }
+
   // 詳細度の計算
+  // 結合子でつないだものは、それぞれの詳細度の合計
   pub fn specificity(&self) -> Specificity {
-    let Selector::Simple(ref simple) = *self;
-    let a = simple.id.iter().count();
-    let b = simple.class.len();
-    let c = simple.tag_name.iter().count();
+    return match *self {
+      Selector::Simple(ref simple) => simple.specificity(),
+      Selector::Complex(ref complex) => complex.ancestors.iter().map(|(_, simple)| simple.specificity()).fold(complex.subject.specificity(), |(a, b, c), (x, y, z)| (a + x, b + y, c + z)),
+    };
+  }
+}
+
+impl SimpleSelector {
+  pub fn specificity(&self) -> Specificity {
+    let a = self.id.iter().count();
+    let b = self.class.len() + self.attributes.len() + self.pseudo_classes.len();
+    let c = self.tag_name.iter().count() + self.pseudo_element.iter().count(); // 疑似要素はタグ名と同じ
     return (a, b, c);
   }
 }
@@ -81,6 +605,35 @@ fn valid_identifier_char(c: char) -> bool {
   };
 }
 
+// 単純セレクターの始まりの文字
+fn starts_simple_selector(c: char) -> bool {
+  return c == '#' || c == '.' || c == '*' || c == '[' || c == ':' || valid_identifier_char(c);
+}
+
+// :nth-child() の引数（odd、even、3、2n+1、-n+3 など）を a と b にする
+fn parse_nth(argument: &str) -> Option<(i32, i32)> {
+  let argument: String = argument.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_ascii_lowercase();
+  match &*argument {
+    "odd" => return Some((2, 1)),
+    "even" => return Some((2, 0)),
+    _ => {}
+  }
+  let n = match argument.find('n') {
+    Some(n) => n,
+    None => return argument.parse().ok().map(|b| (0, b)),
+  };
+  let a = match &argument[..n] {
+    "" | "+" => 1,
+    "-" => -1,
+    a => a.parse().ok()?,
+  };
+  let b = match &argument[n + 1..] {
+    "" => 0,
+    b => b.trim_start_matches('+').parse().ok()?,
+  };
+  return Some((a, b));
+}
+
 impl Value {
   pub fn to_px(&self) -> f32 {
     match *self {
@@ -88,210 +641,1627 @@ impl Value {
       _ => 0.0
     }
   }
+
+  // 時間なら秒で
+  pub fn to_seconds(&self) -> Option<f32> {
+    return match *self {
+      Value::Length(time, Unit::S) => Some(time),
+      Value::Length(time, Unit::Ms) => Some(time / 1000.0),
+      _ => None,
+    };
+  }
 }
 
 impl Parser {
   /**
    * html のメソッドおさらい
    */
-  fn next_char(&self) -> char {
-    return self.input[self.pos..].chars().next().unwrap();
+  fn next_char(&self) -> Result<char, CssError> {
+    return self.input[self.pos..].chars().next().ok_or(CssError::UnexpectedEof);
   }
   fn eof(&self) -> bool {
     return self.pos >= self.input.len();
   }
-  fn consume_whitespace(&mut self) {
-    self.consume_while(char::is_whitespace);
+  fn starts_with(&self, s: &str) -> bool {
+    return self.input[self.pos..].starts_with(s);
   }
-  fn consume_while<F>(&mut self, test: F) -> String
+  // 空白と /* コメント */（閉じていなければ最後まで）
+  fn consume_whitespace(&mut self) -> Result<(), CssError> {
+    loop {
+      self.skip_while(char::is_whitespace);
+      if !self.starts_with("/*") {
+        return Ok(());
+      }
+      self.pos = match self.input[self.pos + "/*".len()..].find("*/") {
+        Some(end) => self.pos + "/*".len() + end + "*/".len(),
+        None => self.input.len(),
+      };
+    }
+  }
+  // test を満たす文字が続く間を読む。文字列は読んだところから一度に作る
+  fn consume_while<F>(&mut self, test: F) -> Result<String, CssError>
   where
     F: Fn(char) -> bool,
   {
-    let mut result = String::new();
-    while !self.eof() && test(self.next_char()) {
-      result.push(self.consume_char());
-    }
-    return result;
+    return Ok(self.skip_while(test).to_string());
   }
-  fn consume_char(&mut self) -> char {
-    let mut iter = self.input[self.pos..].char_indices();
-    let (_, cur_char) = iter.next().unwrap();
-    let (next_pos, _) = iter.next().unwrap_or((1, ' '));
-    self.pos += next_pos;
-    return cur_char;
+  // test を満たす文字が続く間を読み飛ばして、入力のその部分を返す（文字列を作らない）
+  fn skip_while<F>(&mut self, test: F) -> &str
+  where
+    F: Fn(char) -> bool,
+  {
+    let rest = &self.input[self.pos..];
+    let len = rest.find(|c: char| !test(c)).unwrap_or(rest.len());
+    self.pos += len;
+    return &rest[..len];
+  }
+  fn consume_char(&mut self) -> Result<char, CssError> {
+    let cur_char = self.next_char()?;
+    self.pos += cur_char.len_utf8();
+    return Ok(cur_char);
+  }
+  fn expect_char(&mut self, expected: char) -> Result<(), CssError> {
+    let pos = self.pos;
+    let found = self.consume_char()?;
+    if found != expected {
+      return Err(CssError::UnexpectedChar { expected: expected, found: found, pos: pos });
+    }
+    return Ok(());
   }
 
   /**
    * ここから
    */
 
-  fn parse_identifier(&mut self) -> String {
+  fn parse_identifier(&mut self) -> Result<String, CssError> {
     return self.consume_while(valid_identifier_char)
   }
 
-  fn parse_simple_selector(&mut self) -> SimpleSelector {
+  fn parse_simple_selector(&mut self) -> Result<SimpleSelector, CssError> {
     let mut selector = SimpleSelector {
       tag_name: None,
       id: None,          // id は一意なので 1 つ
       class: Vec::new(), // class は複数あるので配列
+      attributes: Vec::new(),
+      pseudo_classes: Vec::new(),
+      pseudo_element: None,
     };
     while !self.eof() {
-      match self.next_char() {
+      // 疑似要素の後ろには何も続けられない
+      if selector.pseudo_element.is_some() && starts_simple_selector(self.next_char()?) {
+        return Err(CssError::InvalidSelector { found: self.next_char()?, pos: self.pos });
+      }
+      match self.next_char()? {
         // ID セレクタ
         '#' => {
-          println!("css: found ID Selector");
-          self.consume_char();
-          selector.id = Some(self.parse_identifier());
+          trace!("found ID Selector");
+          self.consume_char()?;
+          selector.id = Some(self.parse_identifier()?);
         }
         // Class セレクタ
         '.' => {
-          println!("css: found class Selector");
-          self.consume_char();
-          selector.class.push(self.parse_identifier());
+          trace!("found class Selector");
+          self.consume_char()?;
+          selector.class.push(self.parse_identifier()?);
         }
         // * セレクタ
         '*' => {
-          println!("css: found universal Selector");
-          self.consume_char();
+          trace!("found universal Selector");
+          self.consume_char()?;
+        }
+        // 属性セレクタ
+        '[' => {
+          trace!("found attribute Selector");
+          selector.attributes.push(self.parse_attribute_selector()?);
         }
-        // タグ名
+        // 疑似要素と疑似クラス
+        ':' => match self.parse_pseudo_element()? {
+          Some(pseudo_element) => {
+            trace!("found pseudo-element Selector");
+            selector.pseudo_element = Some(pseudo_element);
+          }
+          None => {
+            trace!("found pseudo-class Selector");
+            selector.pseudo_classes.push(self.parse_pseudo_class()?);
+          }
+        },
+        // タグ名（要素と同じように小文字にする）
         c if valid_identifier_char(c) => {
-          println!("css: found tagName Selector");
-          selector.tag_name = Some(self.parse_identifier());
+          trace!("found tagName Selector");
+          selector.tag_name = Some(self.parse_identifier()?.to_ascii_lowercase());
         }
         _ => break,
       }
     }
-    return selector;
+    return Ok(selector);
   }
 
-  // ルール
-  fn parse_rule(&mut self) -> Rule {
-    return Rule {
-      selectors: self.parse_selectors(),
-      declarations: self.parse_declarations(),
+  // [attr]、[attr=value]、[attr~=value]。値はクォートしてもしなくてもよい。属性名は小文字にする
+  fn parse_attribute_selector(&mut self) -> Result<AttributeSelector, CssError> {
+    self.expect_char('[')?;
+    self.consume_whitespace()?;
+    let name = self.parse_identifier()?.to_ascii_lowercase();
+    self.consume_whitespace()?;
+    let includes = match self.consume_char()? {
+      ']' => return Ok(AttributeSelector::Exists(name)),
+      '=' => false,
+      '~' => {
+        self.expect_char('=')?;
+        true
+      }
+      c => return Err(CssError::InvalidSelector { found: c, pos: self.pos - c.len_utf8() }),
+    };
+    self.consume_whitespace()?;
+    let value = match self.next_char()? {
+      '"' | '\'' => match self.parse_string()? {
+        Value::Str(value) => value,
+        _ => String::new(),
+      },
+      _ => self.parse_identifier()?,
+    };
+    self.consume_whitespace()?;
+    self.expect_char(']')?;
+    return Ok(if includes { AttributeSelector::Includes(name, value) } else { AttributeSelector::Equals(name, value) });
+  }
+
+  // ::before、::after と、CSS 2 の書き方の :before、:after。疑似クラスなら読まずに None。
+  // ほかの疑似要素（::first-line など）はセレクターごと読めないものにする
+  fn parse_pseudo_element(&mut self) -> Result<Option<PseudoElement>, CssError> {
+    let pos = self.pos;
+    self.expect_char(':')?;
+    let double = self.starts_with(":");
+    if double {
+      self.consume_char()?;
+    }
+    let pseudo_element = match &*self.parse_identifier()?.to_ascii_lowercase() {
+      "before" => Some(PseudoElement::Before),
+      "after" => Some(PseudoElement::After),
+      _ if double => return Err(CssError::InvalidSelector { found: ':', pos: pos }),
+      _ => None,
+    };
+    if pseudo_element.is_none() {
+      self.pos = pos;
+    }
+    return Ok(pseudo_element);
+  }
+
+  // :first-child、:last-child、:nth-child(...)、:hover、:root。ほかの疑似クラスはセレクターごと読めないものにする
+  fn parse_pseudo_class(&mut self) -> Result<PseudoClass, CssError> {
+    let pos = self.pos;
+    self.expect_char(':')?;
+    let name = self.parse_identifier()?.to_ascii_lowercase();
+    let pseudo_class = match &*name {
+      "first-child" => Some(PseudoClass::FirstChild),
+      "last-child" => Some(PseudoClass::LastChild),
+      "hover" => Some(PseudoClass::Hover),
+      "root" => Some(PseudoClass::Root),
+      "nth-child" if self.starts_with("(") => {
+        self.consume_char()?;
+        let argument = self.consume_while(|c| c != ')')?;
+        self.expect_char(')')?;
+        parse_nth(&argument).map(|(a, b)| PseudoClass::NthChild(a, b))
+      }
+      _ => None,
     };
+    return pseudo_class.ok_or(CssError::InvalidSelector { found: ':', pos: pos });
+  }
+
+  // 空白か > でつないだ単純セレクターの並び
+  fn parse_selector(&mut self) -> Result<Selector, CssError> {
+    let mut subject = self.parse_simple_selector()?;
+    let mut ancestors = Vec::new();
+    loop {
+      let start = self.pos;
+      self.consume_whitespace()?;
+      let combinator = if self.starts_with(">") {
+        self.consume_char()?;
+        self.consume_whitespace()?;
+        Combinator::Child
+      } else if self.pos > start && !self.eof() && starts_simple_selector(self.next_char()?) {
+        Combinator::Descendant
+      } else {
+        self.pos = start;
+        break;
+      };
+      let c = self.next_char()?;
+      // 疑似要素は主体にしか書けない
+      if !starts_simple_selector(c) || subject.pseudo_element.is_some() {
+        return Err(CssError::InvalidSelector { found: c, pos: self.pos });
+      }
+      let next = self.parse_simple_selector()?;
+      ancestors.insert(0, (combinator, subject));
+      subject = next;
+    }
+    if ancestors.is_empty() {
+      return Ok(Selector::Simple(subject));
+    }
+    return Ok(Selector::Complex(ComplexSelector { subject: subject, ancestors: ancestors }));
+  }
+
+  // ルール
+  fn parse_rule(&mut self) -> Result<Rule, CssError> {
+    return Ok(Rule {
+      selectors: self.parse_selectors()?,
+      declarations: self.parse_declarations()?,
+      media: Vec::new(),
+      origin: Origin::Author,
+    });
+  }
+
+  // @media はその中のルールに条件を付けて足す。@keyframes は sheet.keyframes に足す。
+  // トップレベルの @import は sheet.imports に足す。ルールや @keyframes の後ろのものは、ブラウザと同じく使わない。
+  // ほかの @ ルール（@charset、@font-face など）は読み飛ばす
+  fn parse_at_rule(&mut self, media: &[MediaQueryList], sheet: &mut StyleSheet) -> Result<(), CssError> {
+    let start = self.pos;
+    self.expect_char('@')?;
+    let name = self.parse_identifier()?.to_ascii_lowercase();
+    let prelude = self.consume_while(|c| c != '{' && c != ';')?;
+    if self.consume_char()? == ';' {
+      if name == "import" && media.is_empty() {
+        if sheet.rules.is_empty() && sheet.keyframes.is_empty() {
+          sheet.imports.extend(parse_import(&prelude));
+        } else {
+          self.diagnostics.push(CssError::MisplacedImport { pos: start });
+        }
+      }
+      return Ok(());
+    }
+    match &*name {
+      "media" => {
+        if media.len() >= MAX_NESTING {
+          self.diagnostics.push(CssError::TooDeep { pos: self.pos });
+          return self.skip_block();
+        }
+        let mut media = media.to_vec();
+        media.push(parse_media_query_list(&prelude));
+        return self.parse_rule_list(&media, sheet);
+      }
+      "keyframes" | "-webkit-keyframes" => {
+        let name = prelude.trim().trim_matches(|c| c == '"' || c == '\'').to_string();
+        let frames = self.parse_keyframe_list()?;
+        sheet.keyframes.push(Keyframes { name: name, frames: frames });
+        return Ok(());
+      }
+      _ => return self.skip_block(),
+    }
+  }
+
+  // @keyframes の { の後から } まで。読めないセレクター（from、to、N% 以外）のキーフレームは飛ばす
+  fn parse_keyframe_list(&mut self) -> Result<Vec<Keyframe>, CssError> {
+    let mut frames = Vec::new();
+    loop {
+      self.consume_whitespace()?;
+      if self.next_char()? == '}' {
+        self.consume_char()?;
+        break;
+      }
+      let selectors = self.consume_while(|c| c != '{' && c != '}')?;
+      if self.next_char()? == '}' {
+        continue;
+      }
+      let declarations = self.parse_declarations()?;
+      let offsets: Vec<Option<f32>> = selectors.split(',').map(keyframe_offset).collect();
+      if offsets.iter().all(Option::is_some) {
+        for offset in offsets.into_iter().flatten() {
+          frames.push(Keyframe { offset: offset, declarations: declarations.clone() });
+        }
+      }
+    }
+    frames.sort_by(|a, b| a.offset.partial_cmp(&b.offset).unwrap_or(std::cmp::Ordering::Equal));
+    return Ok(frames);
+  }
+
+  // { の後から、対応する } までを読み飛ばす
+  fn skip_block(&mut self) -> Result<(), CssError> {
+    let mut depth = 1;
+    while depth > 0 {
+      match self.consume_char()? {
+        '{' => depth += 1,
+        '}' => depth -= 1,
+        _ => {}
+      }
+    }
+    return Ok(());
   }
 
   // セレクタ
-  fn parse_selectors(&mut self) -> Vec<Selector> {
+  fn parse_selectors(&mut self) -> Result<Vec<Selector>, CssError> {
     let mut selectors = Vec::new();
     loop {
-      selectors.push(Selector::Simple(self.parse_simple_selector()));
-      self.consume_whitespace();
-      match self.next_char() {
+      selectors.push(self.parse_selector()?);
+      self.consume_whitespace()?;
+      if self.eof() {
+        break; // セレクターだけをパースするとき
+      }
+      match self.next_char()? {
         // 複数
         ',' => {
-          self.consume_char();
-          self.consume_whitespace();
+          self.consume_char()?;
+          self.consume_whitespace()?;
         },
         // declaration
         '{' => break, 
-        c => panic!("Unexpected character {} in selector list", c),
+        c => return Err(CssError::InvalidSelector { found: c, pos: self.pos }),
       }
     }
     selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
-    return selectors;
+    return Ok(selectors);
   }
 
   // 値が float のパーサー
-  fn parse_float(&mut self) -> f32 {
-    let s = self.consume_while(|c| match c {
+  fn parse_float(&mut self) -> Result<f32, CssError> {
+    let pos = self.pos;
+    let sign = if self.starts_with("-") { self.consume_char()?.to_string() } else { String::new() };
+    let s = sign + &self.consume_while(|c| match c {
       '0'..='9' | '.' => true,  // 数値か小数点のみ
       _ => false
-    });
-    return s.parse().unwrap();
+    })?;
+    return s.parse().map_err(|_| CssError::InvalidNumber { number: s, pos: pos });
   }
 
   // 値が px などのパーサー
-  fn parse_unit(&mut self) -> Unit {
-    return match &*self.parse_identifier().to_ascii_lowercase() {
-      "px" => Unit::Px,
-      _ => panic!("unrecognized unit") // 対応していない単位には panic 置いとく
+  fn parse_unit(&mut self) -> Result<Unit, CssError> {
+    let pos = self.pos;
+    let unit = self.parse_identifier()?;
+    return match &*unit.to_ascii_lowercase() {
+      "px" => Ok(Unit::Px),
+      "em" => Ok(Unit::Em),
+      "rem" => Ok(Unit::Rem),
+      "vw" => Ok(Unit::Vw),
+      "vh" => Ok(Unit::Vh),
+      "s" => Ok(Unit::S),
+      "ms" => Ok(Unit::Ms),
+      "fr" => Ok(Unit::Fr),
+      "deg" => Ok(Unit::Deg),
+      _ => Err(CssError::UnknownUnit { unit: unit, pos: pos }) // 対応していない単位
     }
   }
 
-  // color
-  fn parse_color(&mut self) -> Value {
-    assert_eq!(self.consume_char(), '#');
-    Value::ColorValue(Color {
-      r: self.parse_hex_pair(),
-      g: self.parse_hex_pair(),
-      b: self.parse_hex_pair(),
-      a: 255,
-    })
+  // color。#rgb、#rgba、#rrggbb、#rrggbbaa
+  fn parse_color(&mut self) -> Result<Value, CssError> {
+    let pos = self.pos;
+    self.expect_char('#')?;
+    let hex = self.consume_while(|c| c.is_ascii_hexdigit())?;
+    let digits: Vec<u8> = hex.chars().map(|c| c.to_digit(16).unwrap_or(0) as u8).collect();
+    let channels: Vec<u8> = match digits.len() {
+      3 | 4 => digits.iter().map(|&digit| digit * 17).collect(), // f → ff
+      6 | 8 => digits.chunks(2).map(|pair| pair[0] * 16 + pair[1]).collect(),
+      _ => return Err(CssError::InvalidColor { pos: pos }),
+    };
+    return Ok(Value::ColorValue(Color { r: channels[0], g: channels[1], b: channels[2], a: *channels.get(3).unwrap_or(&255) }));
+  }
+
+  // 値が数値の時のパーサー。単位がなければ Number
+  fn parse_length(&mut self) -> Result<Value, CssError> {
+    let number = self.parse_float()?;
+    if !self.eof() && self.next_char()? == '%' {
+      self.consume_char()?;
+      return Ok(Value::Length(number, Unit::Percent));
+    }
+    if self.eof() || !valid_identifier_char(self.next_char()?) {
+      return Ok(Value::Number(number));
+    }
+    return Ok(Value::Length(number, self.parse_unit()?));
+  }
+
+  // "..." か '...'。\ の後の 16 進数（1 から 6 桁、後の空白 1 つまで）は文字コード、それ以外の文字はそのまま
+  fn parse_string(&mut self) -> Result<Value, CssError> {
+    let quote = self.consume_char()?;
+    let mut text = String::new();
+    loop {
+      match self.consume_char()? {
+        c if c == quote => break,
+        '\\' => {
+          let hex = self.consume_while_max(6, |c| c.is_ascii_hexdigit())?;
+          if hex.is_empty() {
+            text.push(self.consume_char()?);
+          } else {
+            text.push(u32::from_str_radix(&hex, 16).ok().and_then(std::char::from_u32).unwrap_or('\u{fffd}'));
+            if !self.eof() && self.next_char()?.is_whitespace() {
+              self.consume_char()?;
+            }
+          }
+        }
+        c => text.push(c),
+      }
+    }
+    return Ok(Value::Str(text));
+  }
+
+  fn consume_while_max<F: Fn(char) -> bool>(&mut self, max: usize, test: F) -> Result<String, CssError> {
+    let mut result = String::new();
+    while result.len() < max && !self.eof() && test(self.next_char()?) {
+      result.push(self.consume_char()?);
+    }
+    return Ok(result);
+  }
+
+  // name(...) の引数。カンマで区切り、それぞれ空白で区切った 1 つ以上の値
+  fn parse_function(&mut self, name: String) -> Result<Value, CssError> {
+    self.expect_char('(')?;
+    let mut args = Vec::new();
+    loop {
+      self.consume_whitespace()?;
+      if self.next_char()? == ')' && args.is_empty() {
+        self.consume_char()?;
+        break;
+      }
+      args.push(self.parse_value_list(&[',', ')'])?);
+      match self.consume_char()? {
+        ',' => continue,
+        ')' => break,
+        found => return Err(CssError::InvalidValue { found: found, pos: self.pos - found.len_utf8() }),
+      }
+    }
+    return Ok(Value::Function(name, args));
+  }
+
+  // 空白で区切った値を、terminators のどれかの前まで。1 つなら List にしない
+  fn parse_value_list(&mut self, terminators: &[char]) -> Result<Value, CssError> {
+    let mut values = Vec::new();
+    loop {
+      self.consume_whitespace()?;
+      if self.eof() || terminators.contains(&self.next_char()?) {
+        break;
+      }
+      values.push(self.parse_value()?);
+    }
+    return Ok(if values.len() == 1 { values.remove(0) } else { Value::List(values) });
+  }
+
+  // font-family のカンマ区切りのフォント名（2 つ以上なら List で、前のものほど優先）。
+  // 引用符のない 1 語の名前は Keyword（serif などの総称と inherit も）、引用符のあるものと空白を挟んだ複数の語は Str
+  fn parse_font_family(&mut self) -> Result<Value, CssError> {
+    let mut families = Vec::new();
+    loop {
+      self.consume_whitespace()?;
+      let family = match self.next_char()? {
+        '"' | '\'' => self.parse_string()?,
+        c => {
+          let mut words = Vec::new();
+          while !self.eof() && valid_identifier_char(self.next_char()?) {
+            words.push(self.parse_identifier()?);
+            self.consume_whitespace()?;
+          }
+          match words.len() {
+            0 => return Err(CssError::InvalidValue { found: c, pos: self.pos }),
+            1 => Value::Keyword(words.remove(0)),
+            _ => Value::Str(words.join(" ")),
+          }
+        }
+      };
+      families.push(family);
+      self.consume_whitespace()?;
+      if self.eof() || self.next_char()? != ',' {
+        break;
+      }
+      self.consume_char()?;
+    }
+    return Ok(if families.len() == 1 { families.remove(0) } else { Value::List(families) });
   }
 
-  // HEX 値
-  fn parse_hex_pair(&mut self) -> u8 {
-    let s = &self.input[self.pos .. self.pos + 2]; // 2 ずつ rga に取る
-    self.pos += 2;
-    return u8::from_str_radix(s, 16).unwrap();
+  // url(...) の中身。クォートはあってもなくてもよい
+  fn parse_url(&mut self) -> Result<Value, CssError> {
+    self.pos += "url(".len();
+    self.consume_whitespace()?;
+    let url = match self.next_char()? {
+      quote @ '"' | quote @ '\'' => {
+        self.consume_char()?;
+        let url = self.consume_while(|c| c != quote)?;
+        self.expect_char(quote)?;
+        url
+      }
+      _ => self.consume_while(|c| c != ')' && !c.is_whitespace())?,
+    };
+    self.consume_whitespace()?;
+    self.expect_char(')')?;
+    return Ok(Value::Url(url));
   }
 
-  // 値が数値の時のパーサー
-  fn parse_length(&mut self) -> Value {
-    return Value::Length(self.parse_float(), self.parse_unit());
+  // 値。関数と calc() の中身は入れ子にして読む
+  fn parse_value(&mut self) -> Result<Value, CssError> {
+    return self.nested(|parser| parser.parse_single_value());
   }
 
-  // 値
-  fn parse_value(&mut self) -> Value {
-    match self.next_char() {
-      '0'..='9' => self.parse_length(), // 数値
+  // 入れ子を 1 つ深くして read を読む。MAX_NESTING を超えるなら TooDeep
+  fn nested<T, F: FnOnce(&mut Parser) -> Result<T, CssError>>(&mut self, read: F) -> Result<T, CssError> {
+    if self.depth >= MAX_NESTING {
+      return Err(CssError::TooDeep { pos: self.pos });
+    }
+    self.depth += 1;
+    let result = read(self);
+    self.depth -= 1;
+    return result;
+  }
+
+  fn parse_single_value(&mut self) -> Result<Value, CssError> {
+    let negative_number = self.starts_with("-") && self.input[self.pos + 1..].starts_with(|c: char| c.is_ascii_digit() || c == '.');
+    match self.next_char()? {
+      '0'..='9' | '.' => self.parse_length(), // 数値
+      _ if negative_number => self.parse_length(),
       '#' => self.parse_color(), // カラー値
-      _ => Value::Keyword(self.parse_identifier()), // キーワード
+      '"' | '\'' => self.parse_string(), // 文字列
+      '/' => {
+        // background の位置と大きさの区切りなど。値を区切るキーワードとして読む
+        self.consume_char()?;
+        Ok(Value::Keyword("/".to_string()))
+      }
+      _ if self.starts_with("url(") => self.parse_url(), // URL
+      c if valid_identifier_char(c) => {
+        let pos = self.pos;
+        let identifier = self.parse_identifier()?;
+        if identifier.eq_ignore_ascii_case("calc") && !self.eof() && self.next_char()? == '(' {
+          return self.parse_calc(pos);
+        }
+        if !self.eof() && self.next_char()? == '(' {
+          let function = self.parse_function(identifier)?; // 関数
+          if let Value::Function(ref name, ref args) = function {
+            if name.eq_ignore_ascii_case("var") {
+              return var(args).ok_or(CssError::InvalidVar { pos: pos });
+            }
+            if args.iter().any(Value::contains_var) {
+              // rgb(var(--r), 0, 0) などは、置き換えてから function_value で読む
+              return Ok(function);
+            }
+            if is_color_function(name) {
+              // rgb() などはカラー値
+              return color_function(name, args).map(Value::ColorValue).ok_or(CssError::InvalidColor { pos: pos });
+            }
+            if name.eq_ignore_ascii_case("linear-gradient") {
+              return linear_gradient(args).map(Value::Gradient).ok_or(CssError::InvalidGradient { pos: pos });
+            }
+          }
+          return Ok(function);
+        }
+        match Color::named(&identifier) {
+          Some(color) => Ok(Value::ColorValue(color)), // 色の名前
+          None => Ok(Value::Keyword(identifier)), // キーワード
+        }
+      }
+      c => Err(CssError::InvalidValue { found: c, pos: self.pos }),
+    }
+  }
+
+  // calc( の ( から ) まで。pos は calc の位置
+  fn parse_calc(&mut self, pos: usize) -> Result<Value, CssError> {
+    self.expect_char('(')?;
+    let calc = self.parse_calc_sum(pos)?;
+    self.consume_whitespace()?;
+    self.expect_char(')')?;
+    // 単位の合わない式（10px + 5 など）は読めない。var() があれば置き換えてから確かめる
+    if !calc.contains_var() && !calc.is_valid() {
+      return Err(CssError::InvalidCalc { pos: pos });
+    }
+    return Ok(Value::Calc(Box::new(calc)));
+  }
+
+  // + と - でつないだ項。* と / を先に計算して、同じ優先順位なら左から
+  fn parse_calc_sum(&mut self, pos: usize) -> Result<Calc, CssError> {
+    let mut calc = self.parse_calc_product(pos)?;
+    loop {
+      self.consume_whitespace()?;
+      let operator = match self.next_char()? {
+        '+' => Operator::Add,
+        '-' => Operator::Subtract,
+        _ => return Ok(calc),
+      };
+      self.consume_char()?;
+      let right = self.parse_calc_product(pos)?;
+      calc = Calc::Operation(operator, Box::new(calc), Box::new(right));
     }
   }
 
+  // * と / でつないだ値
+  fn parse_calc_product(&mut self, pos: usize) -> Result<Calc, CssError> {
+    let mut calc = self.parse_calc_value(pos)?;
+    loop {
+      self.consume_whitespace()?;
+      let operator = match self.next_char()? {
+        '*' => Operator::Multiply,
+        '/' => Operator::Divide,
+        _ => return Ok(calc),
+      };
+      self.consume_char()?;
+      let right = self.parse_calc_value(pos)?;
+      calc = Calc::Operation(operator, Box::new(calc), Box::new(right));
+    }
+  }
+
+  // 括弧で囲んだ式か、長さ、数値、var()、calc()
+  fn parse_calc_value(&mut self, pos: usize) -> Result<Calc, CssError> {
+    self.consume_whitespace()?;
+    if self.next_char()? == '(' {
+      self.consume_char()?;
+      let calc = self.nested(|parser| parser.parse_calc_sum(pos))?;
+      self.consume_whitespace()?;
+      self.expect_char(')')?;
+      return Ok(calc);
+    }
+    return match self.parse_value()? {
+      value @ Value::Length(..) | value @ Value::Number(_) | value @ Value::Var(..) | value @ Value::Calc(_) => Ok(Calc::Value(value)),
+      _ => Err(CssError::InvalidCalc { pos: pos }),
+    };
+  }
+
   // 宣言
-  fn parse_declaration(&mut self) -> Declaration {
-    let property_name = self.parse_identifier(); // プロパティ名
-    self.consume_whitespace();
-    assert_eq!(self.consume_char(), ':'); // :
-    self.consume_whitespace();
-    let value = self.parse_value(); // 値
-    self.consume_whitespace();
-    assert_eq!(self.consume_char(), ';'); // ;
-
-    println!("css: found {}: {:?}", property_name, value);
-
-    return Declaration {
+  fn parse_declaration(&mut self) -> Result<Declaration, CssError> {
+    let pos = self.pos;
+    let property_name = self.parse_identifier()?; // プロパティ名
+    self.consume_whitespace()?;
+    self.expect_char(':')?; // :
+    self.consume_whitespace()?;
+    // 値（空白で区切って複数あれば List）。font-family はカンマ区切りのフォント名（var() で始まらなければ）
+    let font_family = property_name.eq_ignore_ascii_case("font-family") && !self.starts_with("var(");
+    let value = if font_family { self.parse_font_family()? } else { self.parse_value_list(&[';', '}', '!'])? };
+    let important = self.parse_important()?;
+    // 負の値を使えないプロパティに負の値を書いた宣言は、読めない宣言と同じように捨てる
+    if value.is_negative() && !allows_negative(&property_name.to_ascii_lowercase()) {
+      return Err(CssError::NegativeValue { property: property_name, pos: pos });
+    }
+    if !self.eof() && self.next_char()? == ';' { // ;（} の前の最後の宣言にはなくてもよい）
+      self.consume_char()?;
+    }
+
+    debug!("found {}: {:?}", property_name, value);
+
+    return Ok(Declaration {
       name: property_name,
       value: value,
-    };
+      important: important,
+    });
+  }
+
+  // 値の後ろの !important（! と important の間に空白があってもよい）
+  fn parse_important(&mut self) -> Result<bool, CssError> {
+    if self.eof() || self.next_char()? != '!' {
+      return Ok(false);
+    }
+    let pos = self.pos;
+    self.consume_char()?;
+    self.consume_whitespace()?;
+    if !self.parse_identifier()?.eq_ignore_ascii_case("important") {
+      return Err(CssError::InvalidValue { found: '!', pos: pos });
+    }
+    self.consume_whitespace()?;
+    return Ok(true);
   }
 
   // 全宣言
-  fn parse_declarations(&mut self) -> Vec<Declaration> {
-    assert_eq!(self.consume_char(), '{');
+  fn parse_declarations(&mut self) -> Result<Vec<Declaration>, CssError> {
+    self.expect_char('{')?;
     let mut declarations = Vec::new();
     loop {
-      self.consume_whitespace();
-      if self.next_char() == '}' {
+      self.consume_whitespace()?;
+      if self.eof() {
+        // 閉じの } がないまま終われば、そこで閉じたことにする
+        self.unexpected_eof();
+        break;
+      }
+      if self.next_char()? == '}' {
         // } ならスコープの閉じなので終わり
-        self.consume_char();
+        self.consume_char()?;
         break;
       }
-      declarations.push(self.parse_declaration())
+      let pos = self.pos;
+      match self.parse_declaration() {
+        Ok(declaration) => declarations.extend(expand_shorthand(declaration)),
+        Err(CssError::UnexpectedEof) => {
+          // 途中で終わった宣言は捨てて、ブロックを閉じたことにする
+          self.unexpected_eof();
+          break;
+        }
+        Err(error) => {
+          // 読めない宣言は次の ; か、ブロックの閉じの } の前まで飛ばす
+          self.diagnostics.push(error);
+          self.pos = pos;
+          self.skip_declaration()?;
+        }
+      }
+    }
+    return Ok(declarations);
+  }
+
+  // 入力が途中で終わったことを記録する。閉じていないブロックが重なっていても 1 つだけ
+  fn unexpected_eof(&mut self) {
+    if let Some(CssError::UnexpectedEof) = self.diagnostics.last() {
+      return;
+    }
+    self.diagnostics.push(CssError::UnexpectedEof);
+  }
+
+  // 宣言の終わりの ; まで（; も読む）。括弧の中と文字列の中の ; と } は数えない
+  fn skip_declaration(&mut self) -> Result<(), CssError> {
+    let mut depth = 0;
+    loop {
+      match self.next_char()? {
+        ';' if depth == 0 => {
+          self.consume_char()?;
+          break;
+        }
+        '}' if depth == 0 => break,
+        '(' | '[' | '{' => depth += 1,
+        ')' | ']' | '}' => depth -= 1,
+        quote @ '"' | quote @ '\'' => {
+          self.consume_char()?;
+          self.skip_while(|c| c != quote);
+        }
+        _ => {}
+      }
+      self.consume_char()?;
+    }
+    return Ok(());
+  }
+
+  // 読めないルールを、そのブロックの終わりまで（@ ルールなら ; までのこともある）読み飛ばす。
+  // @media の中なら、その閉じの } は残す
+  fn skip_rule(&mut self, nested: bool) -> Result<(), CssError> {
+    self.skip_while(|c| c != '{' && c != ';' && c != '}');
+    match self.consume_char()? {
+      '{' => self.skip_block()?,
+      '}' if nested => self.pos -= 1,
+      _ => {}
+    }
+    return Ok(());
+  }
+
+  // 全ルール。入力の終わりまで読めなければ、それまでのルールでエラーにする
+  fn parse_stylesheet(&mut self) -> StyleSheet {
+    let mut sheet = StyleSheet::default();
+    if let Err(error) = self.parse_rule_list(&[], &mut sheet) {
+      self.diagnostics.push(error);
     }
-    return declarations;
+    return sheet;
   }
 
-  // 全ルール
-  fn parse_rules(&mut self) -> Vec<Rule> {
-    let mut rules = Vec::new();
+  // media が空ならトップレベルで、入力の最後まで。@media の中なら閉じの } まで（} も読む）
+  fn parse_rule_list(&mut self, media: &[MediaQueryList], sheet: &mut StyleSheet) -> Result<(), CssError> {
     loop {
-      self.consume_whitespace();
+      self.consume_whitespace()?;
       if self.eof() {
+        if !media.is_empty() {
+          self.unexpected_eof(); // @media の閉じがなければ、そこで閉じたことにする
+        }
+        break;
+      }
+      if !media.is_empty() && self.starts_with("}") {
+        self.consume_char()?;
         break;
       }
-      rules.push(self.parse_rule());
+      let pos = self.pos;
+      let result = if self.starts_with("@") {
+        self.parse_at_rule(media, sheet)
+      } else {
+        self.parse_rule().map(|mut rule| {
+          rule.media = media.to_vec();
+          sheet.rules.push(rule);
+        })
+      };
+      match result {
+        Ok(()) => {}
+        Err(CssError::UnexpectedEof) => return Err(CssError::UnexpectedEof),
+        Err(error) => {
+          // 読めないセレクターのルールは、宣言のブロックごと捨てる
+          self.diagnostics.push(error);
+          self.pos = pos;
+          self.skip_rule(!media.is_empty())?;
+        }
+      }
+    }
+    return Ok(());
+  }
+}
+
+// from → 0、to → 1、50% → 0.5
+// var() の引数。1 つ目はカスタムプロパティの名前（-- で始まる）で、2 つ目があれば代わりの値
+fn var(args: &[Value]) -> Option<Value> {
+  return match *args {
+    [Value::Keyword(ref name)] if is_custom_property(name) => Some(Value::Var(name.clone(), None)),
+    [Value::Keyword(ref name), ref fallback] if is_custom_property(name) => Some(Value::Var(name.clone(), Some(Box::new(fallback.clone())))),
+    _ => None,
+  };
+}
+
+// カスタムプロパティ（--main-color など）の名前か
+pub fn is_custom_property(name: &str) -> bool {
+  return name.starts_with("--") && name.len() > 2;
+}
+
+// 関数の値。rgb() などはカラー値、linear-gradient() は Gradient にする（読めなければ None）。
+// 引数の var() を置き換えてから読み直すのに使う
+pub fn function_value(name: String, args: Vec<Value>) -> Option<Value> {
+  if is_color_function(&name) {
+    return color_function(&name, &args).map(Value::ColorValue);
+  }
+  if name.eq_ignore_ascii_case("linear-gradient") {
+    return linear_gradient(&args).map(Value::Gradient);
+  }
+  return Some(Value::Function(name, args));
+}
+
+impl Value {
+  // 負の数か、負の数を含む空白区切りの値か（関数の引数は見ない）
+  fn is_negative(&self) -> bool {
+    return match *self {
+      Value::Length(number, _) | Value::Number(number) => number < 0.0,
+      Value::List(ref values) => values.iter().any(Value::is_negative),
+      _ => false,
+    };
+  }
+
+  // var() を含むか
+  pub fn contains_var(&self) -> bool {
+    return match *self {
+      Value::Var(..) => true,
+      Value::Function(_, ref values) | Value::List(ref values) => values.iter().any(Value::contains_var),
+      Value::Calc(ref calc) => calc.contains_var(),
+      _ => false,
+    };
+  }
+}
+
+impl Calc {
+  fn contains_var(&self) -> bool {
+    return match *self {
+      Calc::Value(ref value) => value.contains_var(),
+      Calc::Operation(_, ref left, ref right) => left.contains_var() || right.contains_var(),
+    };
+  }
+
+  // 単位が合っていて計算できる式か（長さはどれも 1px とみなす）
+  pub fn is_valid(&self) -> bool {
+    fn kind(value: &Value) -> Option<Value> {
+      return match *value {
+        Value::Length(..) => Some(Value::Length(1.0, Unit::Px)),
+        Value::Number(number) => Some(Value::Number(number)),
+        Value::Calc(ref calc) => calc.evaluate(&kind),
+        _ => None,
+      };
+    }
+    return self.evaluate(&kind).is_some();
+  }
+
+  // 式の中の値のそれぞれに f を呼ぶ（em を px にするときなど）
+  pub fn for_each_value_mut(&mut self, f: &mut dyn FnMut(&mut Value)) {
+    match *self {
+      Calc::Value(ref mut value) => f(value),
+      Calc::Operation(_, ref mut left, ref mut right) => {
+        left.for_each_value_mut(f);
+        right.for_each_value_mut(f);
+      }
+    }
+  }
+
+  // 式を計算する。値は resolve で px の長さか数値にする（できなければ None）。
+  // 足し引きは同じ種類どうし、掛けるのは片方が数値、割るのは 0 でない数値でだけで、ほかは None
+  pub fn evaluate(&self, resolve: &dyn Fn(&Value) -> Option<Value>) -> Option<Value> {
+    let (operator, left, right) = match *self {
+      Calc::Value(ref value) => return resolve(value),
+      Calc::Operation(operator, ref left, ref right) => (operator, left.evaluate(resolve)?, right.evaluate(resolve)?),
+    };
+    return match (operator, left, right) {
+      (Operator::Add, Value::Length(a, Unit::Px), Value::Length(b, Unit::Px)) => Some(Value::Length(a + b, Unit::Px)),
+      (Operator::Add, Value::Number(a), Value::Number(b)) => Some(Value::Number(a + b)),
+      (Operator::Subtract, Value::Length(a, Unit::Px), Value::Length(b, Unit::Px)) => Some(Value::Length(a - b, Unit::Px)),
+      (Operator::Subtract, Value::Number(a), Value::Number(b)) => Some(Value::Number(a - b)),
+      (Operator::Multiply, Value::Length(a, Unit::Px), Value::Number(b)) | (Operator::Multiply, Value::Number(b), Value::Length(a, Unit::Px)) => Some(Value::Length(a * b, Unit::Px)),
+      (Operator::Multiply, Value::Number(a), Value::Number(b)) => Some(Value::Number(a * b)),
+      (Operator::Divide, Value::Length(a, Unit::Px), Value::Number(b)) if b != 0.0 => Some(Value::Length(a / b, Unit::Px)),
+      (Operator::Divide, Value::Number(a), Value::Number(b)) if b != 0.0 => Some(Value::Number(a / b)),
+      _ => None,
+    };
+  }
+}
+
+fn keyframe_offset(selector: &str) -> Option<f32> {
+  let selector = selector.trim().to_ascii_lowercase();
+  return match &*selector {
+    "from" => Some(0.0),
+    "to" => Some(1.0),
+    _ => selector.strip_suffix('%').and_then(|percent| percent.trim().parse::<f32>().ok()).filter(|percent| (0.0..=100.0).contains(percent)).map(|percent| percent / 100.0),
+  };
+}
+
+// linear-gradient(45deg, red, blue 80%) の引数。向きは角度か to と辺（to right など。to top right などの角は 45deg きざみとみなす）で、
+// なければ to bottom。色は 2 つ以上
+fn linear_gradient(args: &[Value]) -> Option<Gradient> {
+  let (angle, stops) = match *args {
+    [Value::Length(angle, Unit::Deg), ref stops @ ..] => (angle, stops),
+    [Value::List(ref words), ref stops @ ..] if words.first() == Some(&Value::Keyword("to".to_string())) => (side_angle(&words[1..])?, stops),
+    ref stops => (180.0, stops),
+  };
+  let stops = stops
+    .iter()
+    .map(|stop| match *stop {
+      Value::ColorValue(color) => Some(ColorStop { color: color, position: None }),
+      Value::List(ref parts) => match parts[..] {
+        [Value::ColorValue(color), ref position @ Value::Length(..)] => Some(ColorStop { color: color, position: Some(position.clone()) }),
+        _ => None,
+      },
+      _ => None,
+    })
+    .collect::<Option<Vec<ColorStop>>>()?;
+  if stops.len() < 2 {
+    return None;
+  }
+  return Some(Gradient { angle: angle, stops: stops });
+}
+
+impl Matrix {
+  pub const IDENTITY: Matrix = Matrix { a: 1.0, b: 0.0, c: 0.0, d: 1.0, e: 0.0, f: 0.0 };
+
+  pub fn translate(x: f32, y: f32) -> Matrix {
+    return Matrix { e: x, f: y, ..Matrix::IDENTITY };
+  }
+
+  pub fn scale(x: f32, y: f32) -> Matrix {
+    return Matrix { a: x, d: y, ..Matrix::IDENTITY };
+  }
+
+  // 時計回り（y が下向きなので）に degrees 度回す
+  pub fn rotate(degrees: f32) -> Matrix {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    return Matrix { a: cos, b: sin, c: -sin, d: cos, e: 0.0, f: 0.0 };
+  }
+
+  pub fn skew(x_degrees: f32, y_degrees: f32) -> Matrix {
+    return Matrix { b: y_degrees.to_radians().tan(), c: x_degrees.to_radians().tan(), ..Matrix::IDENTITY };
+  }
+
+  // other を当ててから self を当てる変換
+  pub fn multiply(&self, other: &Matrix) -> Matrix {
+    return Matrix {
+      a: self.a * other.a + self.c * other.b,
+      b: self.b * other.a + self.d * other.b,
+      c: self.a * other.c + self.c * other.d,
+      d: self.b * other.c + self.d * other.d,
+      e: self.a * other.e + self.c * other.f + self.e,
+      f: self.b * other.e + self.d * other.f + self.f,
+    };
+  }
+
+  pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+    return (self.a * x + self.c * y + self.e, self.b * x + self.d * y + self.f);
+  }
+
+  // 逆の変換。つぶれていて（scale(0) など）戻せなければ None
+  pub fn invert(&self) -> Option<Matrix> {
+    let determinant = self.a * self.d - self.b * self.c;
+    if determinant.abs() < 1e-6 || !determinant.is_finite() {
+      return None;
+    }
+    let (a, b, c, d) = (self.d / determinant, -self.b / determinant, -self.c / determinant, self.a / determinant);
+    return Some(Matrix { a: a, b: b, c: c, d: d, e: -(a * self.e + c * self.f), f: -(b * self.e + d * self.f) });
+  }
+
+  pub fn is_identity(&self) -> bool {
+    return *self == Matrix::IDENTITY;
+  }
+}
+
+impl Transform {
+  // 幅 width、高さ height のボックスでの変換（原点はボックスの左上）
+  pub fn resolve(&self, width: f32, height: f32) -> Matrix {
+    let [ew, eh, fw, fh] = self.percent;
+    return Matrix { e: self.matrix.e + ew * width + eh * height, f: self.matrix.f + fw * width + fh * height, ..self.matrix };
+  }
+
+  // other を当ててから self を当てる変換。other の % の平行移動も self の拡大や回転で動かす
+  fn multiply(&self, other: &Transform) -> Transform {
+    let matrix = self.matrix.multiply(&other.matrix);
+    let m = &self.matrix;
+    let [ew, eh, fw, fh] = other.percent;
+    let percent = [
+      m.a * ew + m.c * fw + self.percent[0],
+      m.a * eh + m.c * fh + self.percent[1],
+      m.b * ew + m.d * fw + self.percent[2],
+      m.b * eh + m.d * fh + self.percent[3],
+    ];
+    return Transform { matrix: matrix, percent: percent };
+  }
+}
+
+// transform の関数の並び（translate(10px, 50%) rotate(45deg) など）を左から掛け合わせる。
+// 長さは px と %（translate だけ）、角度は deg か 0 で、読めない関数があれば None
+pub fn transform(value: &Value) -> Option<Transform> {
+  let functions = match *value {
+    Value::List(ref values) => &values[..],
+    ref value => std::slice::from_ref(value),
+  };
+  let mut result = Transform { matrix: Matrix::IDENTITY, percent: [0.0; 4] };
+  for function in functions {
+    let (name, args) = match *function {
+      Value::Function(ref name, ref args) => (name.to_ascii_lowercase(), args),
+      _ => return None,
+    };
+    let number = |value: &Value| match *value {
+      Value::Number(number) => Some(number),
+      Value::Length(percent, Unit::Percent) => Some(percent / 100.0),
+      _ => None,
+    };
+    let angle = |value: &Value| match *value {
+      Value::Length(degrees, Unit::Deg) => Some(degrees),
+      Value::Number(number) if number == 0.0 => Some(0.0),
+      _ => None,
+    };
+    // 平行移動の px と、% なら割合
+    let offset = |value: &Value| match *value {
+      Value::Length(px, Unit::Px) => Some((px, 0.0)),
+      Value::Length(percent, Unit::Percent) => Some((0.0, percent / 100.0)),
+      Value::Number(number) if number == 0.0 => Some((0.0, 0.0)),
+      _ => None,
+    };
+    let translate = |x: (f32, f32), y: (f32, f32)| Transform { matrix: Matrix::translate(x.0, y.0), percent: [x.1, 0.0, 0.0, y.1] };
+    let plain = |matrix: Matrix| Transform { matrix: matrix, percent: [0.0; 4] };
+    let own = match (&*name, &args[..]) {
+      ("matrix", [ref a, ref b, ref c, ref d, ref e, ref f]) => {
+        let number = |value: &Value| match *value {
+          Value::Number(number) => Some(number),
+          _ => None,
+        };
+        plain(Matrix { a: number(a)?, b: number(b)?, c: number(c)?, d: number(d)?, e: number(e)?, f: number(f)? })
+      }
+      ("translate", [ref x]) => translate(offset(x)?, (0.0, 0.0)),
+      ("translate", [ref x, ref y]) => translate(offset(x)?, offset(y)?),
+      ("translatex", [ref x]) => translate(offset(x)?, (0.0, 0.0)),
+      ("translatey", [ref y]) => translate((0.0, 0.0), offset(y)?),
+      ("scale", [ref x]) => plain(Matrix::scale(number(x)?, number(x)?)),
+      ("scale", [ref x, ref y]) => plain(Matrix::scale(number(x)?, number(y)?)),
+      ("scalex", [ref x]) => plain(Matrix::scale(number(x)?, 1.0)),
+      ("scaley", [ref y]) => plain(Matrix::scale(1.0, number(y)?)),
+      ("rotate", [ref a]) => plain(Matrix::rotate(angle(a)?)),
+      ("skew", [ref x]) => plain(Matrix::skew(angle(x)?, 0.0)),
+      ("skew", [ref x, ref y]) => plain(Matrix::skew(angle(x)?, angle(y)?)),
+      ("skewx", [ref x]) => plain(Matrix::skew(angle(x)?, 0.0)),
+      ("skewy", [ref y]) => plain(Matrix::skew(0.0, angle(y)?)),
+      _ => return None,
+    };
+    result = result.multiply(&own);
+  }
+  return Some(result);
+}
+
+// to の後の辺（1 つか 2 つ）の向きの角度
+fn side_angle(sides: &[Value]) -> Option<f32> {
+  let angle = |side: &Value| match *side {
+    Value::Keyword(ref side) => match &*side.to_ascii_lowercase() {
+      "top" => Some(0.0),
+      "right" => Some(90.0),
+      "bottom" => Some(180.0),
+      "left" => Some(270.0),
+      _ => None,
+    },
+    _ => None,
+  };
+  return match *sides {
+    [ref side] => angle(side),
+    [ref first, ref second] => {
+      let (first, second) = (angle(first)?, angle(second)?);
+      // to left top は 315deg（0deg と 270deg の間）
+      let difference = (first - second).abs();
+      if difference == 90.0 {
+        Some((first + second) / 2.0)
+      } else if difference == 270.0 {
+        Some(315.0)
+      } else {
+        None
+      }
     }
-    return rules;
+    _ => None,
+  };
+}
+
+fn is_color_function(name: &str) -> bool {
+  return ["rgb", "rgba", "hsl", "hsla"].contains(&&*name.to_ascii_lowercase());
+}
+
+// rgb(255, 0, 0)、rgba(0, 0, 0, 0.5)、hsl(120, 100%, 50%) など。カンマのない rgb(255 0 0) も読む。
+// rgb と rgba、hsl と hsla は同じで、4 つ目の値があれば不透明度（0 から 1 か %）
+fn color_function(name: &str, args: &[Value]) -> Option<Color> {
+  let args: Vec<&Value> = match *args {
+    [Value::List(ref values)] => values.iter().collect(),
+    _ => args.iter().collect(),
+  };
+  if args.len() != 3 && args.len() != 4 {
+    return None;
+  }
+  let number = |value: &Value| match *value {
+    Value::Number(number) => Some(number),
+    _ => None,
+  };
+  let percent = |value: &Value| match *value {
+    Value::Length(percent, Unit::Percent) => Some(percent / 100.0),
+    _ => None,
+  };
+  let alpha = match args.get(3) {
+    Some(value) => number(value).or_else(|| percent(value))?,
+    None => 1.0,
+  };
+  let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+  let (r, g, b) = if name.to_ascii_lowercase().starts_with("rgb") {
+    let component = |value: &Value| number(value).map(|number| number / 255.0).or_else(|| percent(value));
+    (component(args[0])?, component(args[1])?, component(args[2])?)
+  } else {
+    hsl_to_rgb(number(args[0])?, percent(args[1])?, percent(args[2])?)
+  };
+  return Some(Color { r: channel(r), g: channel(g), b: channel(b), a: channel(alpha) });
+}
+
+// 色相（度）、彩度、明度（0 から 1）から、RGB（0 から 1）
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (f32, f32, f32) {
+  let saturation = saturation.clamp(0.0, 1.0);
+  let lightness = lightness.clamp(0.0, 1.0);
+  let hue = hue.rem_euclid(360.0) / 30.0;
+  let a = saturation * lightness.min(1.0 - lightness);
+  let f = |n: f32| {
+    let k = (n + hue) % 12.0;
+    lightness - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0)
+  };
+  return (f(0.0), f(8.0), f(4.0));
+}
+
+// 負の値を使えないプロパティ（と、それを分けるショートハンド）。margin と top などのオフセットのほか、
+// letter-spacing や text-indent、z-index のように負の値に意味があるものは入れない
+const NON_NEGATIVE_PROPERTIES: &[&str] = &[
+  "width", "height", "min-width", "min-height", "max-width", "max-height", "font-size", "line-height",
+  "padding", "padding-top", "padding-right", "padding-bottom", "padding-left",
+  "border", "border-top", "border-right", "border-bottom", "border-left",
+  "border-width", "border-top-width", "border-right-width", "border-bottom-width", "border-left-width",
+  "border-radius", "border-top-left-radius", "border-top-right-radius", "border-bottom-right-radius", "border-bottom-left-radius",
+  "outline-width", "gap", "grid-gap", "row-gap", "column-gap", "flex", "flex-grow", "flex-shrink", "flex-basis",
+  "animation-duration", "transition-duration", "tab-size",
+];
+
+fn allows_negative(property: &str) -> bool {
+  return !NON_NEGATIVE_PROPERTIES.contains(&property);
+}
+
+// 上、右、下、左の順の 4 辺
+const SIDES: [&str; 4] = ["top", "right", "bottom", "left"];
+
+// border-style のキーワード
+const BORDER_STYLES: &[&str] = &["none", "hidden", "dotted", "dashed", "solid", "double", "groove", "ridge", "inset", "outset"];
+
+// text-decoration-line と text-decoration-style のキーワード
+const TEXT_DECORATION_LINES: &[&str] = &["none", "underline", "overline", "line-through", "blink"];
+const TEXT_DECORATION_STYLES: &[&str] = &["solid", "double", "dotted", "dashed", "wavy"];
+
+// background のショートハンドを分けた個別のプロパティと、background-repeat、background-position のキーワード
+const BACKGROUND_LONGHANDS: [&str; 5] = ["background-color", "background-image", "background-repeat", "background-position", "background-size"];
+const BACKGROUND_REPEATS: &[&str] = &["repeat", "repeat-x", "repeat-y", "no-repeat", "space", "round"];
+const BACKGROUND_POSITIONS: &[&str] = &["left", "center", "right", "top", "bottom"];
+
+// 左上、右上、右下、左下の順の 4 つの角
+const CORNERS: [&str; 4] = ["top-left", "top-right", "bottom-right", "bottom-left"];
+
+// ショートハンドのプロパティを、レイアウトと描画が読む個別のプロパティに分ける。
+// margin、padding、border-width、border-style、border-color は 1 から 4 つの値（上、右、下、左。足りない辺は向かいの辺と同じ）、
+// border-radius も同じように左上、右上、右下、左下の角に分ける。
+// gap は row-gap と column-gap（1 つなら両方）に分ける。
+// border と border-top などは幅、線の種類、色（順不同）、background は色と画像、flex は flex-grow、flex-shrink、flex-basis、
+// text-decoration は線（underline overline のように複数書ける）、線の種類、色、
+// background は色、画像、繰り返し、位置と / の後の大きさで、書かなかったものは初期値にする。どれでもなければそのまま返す。
+// var() を含む値は置き換えるまで分けられないので、スタイルの計算で置き換えてから分ける
+pub fn expand_shorthand(declaration: Declaration) -> Vec<Declaration> {
+  if declaration.value.contains_var() {
+    return vec![declaration];
+  }
+  let important = declaration.important;
+  let longhand = |name: String, value: Value| Declaration { name: name, value: value, important: important };
+  let values = match declaration.value {
+    Value::List(ref values) => values.clone(),
+    ref value => vec![value.clone()],
+  };
+  let sides = |names: &[&str; 4], format: &dyn Fn(&str) -> String, values: &[Value]| -> Option<Vec<Declaration>> {
+    let (top, right, bottom, left) = match *values {
+      [ref all] => (all, all, all, all),
+      [ref vertical, ref horizontal] => (vertical, horizontal, vertical, horizontal),
+      [ref top, ref horizontal, ref bottom] => (top, horizontal, bottom, horizontal),
+      [ref top, ref right, ref bottom, ref left] => (top, right, bottom, left),
+      _ => return None,
+    };
+    return Some(names.iter().zip(&[top, right, bottom, left]).map(|(side, value)| longhand(format(side), (*value).clone())).collect());
+  };
+  let border_side = |side: &str| -> Option<Vec<Declaration>> {
+    let (width, style, color) = border_parts(&values)?;
+    return Some(vec![
+      longhand(format!("border-{}-width", side), width),
+      longhand(format!("border-{}-style", side), style),
+      longhand(format!("border-{}-color", side), color),
+    ]);
+  };
+  let expanded = match &*declaration.name {
+    "margin" => sides(&SIDES, &|side| format!("margin-{}", side), &values),
+    "padding" => sides(&SIDES, &|side| format!("padding-{}", side), &values),
+    "border-width" => sides(&SIDES, &|side| format!("border-{}-width", side), &values),
+    "border-style" => sides(&SIDES, &|side| format!("border-{}-style", side), &values),
+    "border-color" => sides(&SIDES, &|side| format!("border-{}-color", side), &values),
+    "border-radius" => sides(&CORNERS, &|corner| format!("border-{}-radius", corner), &values),
+    "border" => SIDES.iter().map(|side| border_side(side)).collect::<Option<Vec<Vec<Declaration>>>>().map(|sides| sides.concat()),
+    "border-top" | "border-right" | "border-bottom" | "border-left" => border_side(&declaration.name["border-".len()..]),
+    "gap" | "grid-gap" => match *values {
+      [ref gap] => Some(vec![longhand("row-gap".to_string(), gap.clone()), longhand("column-gap".to_string(), gap.clone())]),
+      [ref row, ref column] => Some(vec![longhand("row-gap".to_string(), row.clone()), longhand("column-gap".to_string(), column.clone())]),
+      _ => None,
+    },
+    "flex" => flex_parts(&values).map(|(grow, shrink, basis)| vec![
+      longhand("flex-grow".to_string(), grow),
+      longhand("flex-shrink".to_string(), shrink),
+      longhand("flex-basis".to_string(), basis),
+    ]),
+    // list-style-image はまだ使わない
+    "list-style" => {
+      let mut style_type = Value::Keyword("disc".to_string());
+      let mut position = Value::Keyword("outside".to_string());
+      for value in &values {
+        match *value {
+          Value::Keyword(ref keyword) if keyword == "inherit" || keyword == "initial" || keyword == "unset" => {
+            style_type = value.clone();
+            position = value.clone();
+          }
+          Value::Keyword(ref keyword) if keyword == "inside" || keyword == "outside" => position = value.clone(),
+          Value::Keyword(_) => style_type = value.clone(),
+          _ => {}
+        }
+      }
+      Some(vec![longhand("list-style-type".to_string(), style_type), longhand("list-style-position".to_string(), position)])
+    }
+    "text-decoration" => text_decoration_parts(&values).map(|(line, style, color)| vec![
+      longhand("text-decoration-line".to_string(), line),
+      longhand("text-decoration-style".to_string(), style),
+      longhand("text-decoration-color".to_string(), color),
+    ]),
+    "background" => background_parts(&values).map(|parts| {
+      BACKGROUND_LONGHANDS.iter().zip(parts.into_iter()).map(|(name, value)| longhand(name.to_string(), value)).collect()
+    }),
+    _ => None,
+  };
+  return expanded.unwrap_or_else(|| vec![declaration]);
+}
+
+// border の値の幅、線の種類、色（順不同）。書かなかったものは初期値（0、none、currentcolor）。読めなければ None
+fn border_parts(values: &[Value]) -> Option<(Value, Value, Value)> {
+  let mut width = None;
+  let mut style = None;
+  let mut color = None;
+  for value in values {
+    match *value {
+      Value::Length(..) | Value::Number(_) if width.is_none() => width = Some(value.clone()),
+      Value::Keyword(ref keyword) if width.is_none() && border_width_keyword(keyword).is_some() => width = border_width_keyword(keyword),
+      Value::Keyword(ref keyword) if style.is_none() && BORDER_STYLES.contains(&&**keyword) => style = Some(value.clone()),
+      Value::ColorValue(_) if color.is_none() => color = Some(value.clone()),
+      _ if values.len() == 1 => {
+        // inherit などはすべての個別のプロパティに
+        width = Some(value.clone());
+        style = Some(value.clone());
+        color = Some(value.clone());
+      }
+      _ => return None,
+    }
+  }
+  return Some((
+    width.unwrap_or(Value::Length(0.0, Unit::Px)),
+    style.unwrap_or(Value::Keyword("none".to_string())),
+    color.unwrap_or(Value::Keyword("currentcolor".to_string())),
+  ));
+}
+
+// text-decoration の値の線（2 つ以上なら List）、線の種類、色（順不同）。書かなかったものは初期値（none、solid、currentcolor）。読めなければ None
+fn text_decoration_parts(values: &[Value]) -> Option<(Value, Value, Value)> {
+  let mut lines = Vec::new();
+  let mut style = None;
+  let mut color = None;
+  for value in values {
+    match *value {
+      Value::Keyword(ref keyword) if TEXT_DECORATION_LINES.contains(&&**keyword) => lines.push(value.clone()),
+      Value::Keyword(ref keyword) if style.is_none() && TEXT_DECORATION_STYLES.contains(&&**keyword) => style = Some(value.clone()),
+      Value::ColorValue(_) if color.is_none() => color = Some(value.clone()),
+      Value::Keyword(ref keyword) if color.is_none() && keyword == "currentcolor" => color = Some(value.clone()),
+      _ if values.len() == 1 => return Some((value.clone(), value.clone(), value.clone())), // inherit など
+      _ => return None,
+    }
+  }
+  let line = match lines.len() {
+    0 => Value::Keyword("none".to_string()),
+    1 => lines.remove(0),
+    _ => Value::List(lines),
+  };
+  return Some((line, style.unwrap_or(Value::Keyword("solid".to_string())), color.unwrap_or(Value::Keyword("currentcolor".to_string()))));
+}
+
+// background の値の background-color、background-image、background-repeat、background-position、background-size（BACKGROUND_LONGHANDS の順）。
+// 位置（right 10px top 5px のような辺からのずらしも書ける）と大きさは、2 つ以上なら List。書かなかったものは初期値（transparent、none、repeat、0% 0%、auto）。読めなければ None
+fn background_parts(values: &[Value]) -> Option<Vec<Value>> {
+  if let [Value::Keyword(ref keyword)] = *values {
+    if keyword != "none" && !BACKGROUND_REPEATS.contains(&&**keyword) && !BACKGROUND_POSITIONS.contains(&&**keyword) {
+      return Some(vec![values[0].clone(); BACKGROUND_LONGHANDS.len()]); // inherit など
+    }
+  }
+  let mut color = None;
+  let mut image = None;
+  let mut repeat = Vec::new();
+  let mut position = Vec::new();
+  let mut size = Vec::new();
+  let mut after_slash = false;
+  for value in values {
+    match *value {
+      Value::Keyword(ref keyword) if keyword == "/" && !position.is_empty() && !after_slash => after_slash = true,
+      Value::Keyword(ref keyword) if after_slash && size.len() < 2 && (keyword == "auto" || ((keyword == "cover" || keyword == "contain") && size.is_empty())) => size.push(value.clone()),
+      Value::Length(..) | Value::Calc(_) if after_slash && size.len() < 2 => size.push(value.clone()),
+      _ if after_slash && size.is_empty() => return None,
+      Value::Length(..) | Value::Calc(_) if position.len() < 4 => position.push(value.clone()),
+      Value::Keyword(ref keyword) if BACKGROUND_POSITIONS.contains(&&**keyword) && position.len() < 4 => position.push(value.clone()),
+      Value::Keyword(ref keyword) if BACKGROUND_REPEATS.contains(&&**keyword) && repeat.len() < 2 => repeat.push(value.clone()),
+      Value::Url(_) | Value::Gradient(_) if image.is_none() => image = Some(value.clone()),
+      Value::Keyword(ref keyword) if keyword == "none" && image.is_none() => image = Some(value.clone()),
+      Value::ColorValue(_) if color.is_none() => color = Some(value.clone()),
+      Value::Keyword(ref keyword) if keyword == "currentcolor" && color.is_none() => color = Some(value.clone()),
+      _ => return None,
+    }
+  }
+  let list = |mut values: Vec<Value>, default: Value| match values.len() {
+    0 => default,
+    1 => values.remove(0),
+    _ => Value::List(values),
+  };
+  return Some(vec![
+    color.unwrap_or(Value::Keyword("transparent".to_string())),
+    image.unwrap_or(Value::Keyword("none".to_string())),
+    list(repeat, Value::Keyword("repeat".to_string())),
+    list(position, Value::List(vec![Value::Length(0.0, Unit::Percent), Value::Length(0.0, Unit::Percent)])),
+    list(size, Value::Keyword("auto".to_string())),
+  ]);
+}
+
+// flex の値の flex-grow、flex-shrink、flex-basis。none は 0 0 auto、auto は 1 1 auto、数だけなら basis は 0。
+// 書かなかったものは grow が 1、shrink が 1、basis が 0。読めなければ None
+fn flex_parts(values: &[Value]) -> Option<(Value, Value, Value)> {
+  let auto = Value::Keyword("auto".to_string());
+  match *values {
+    [Value::Keyword(ref keyword)] if keyword == "none" => return Some((Value::Number(0.0), Value::Number(0.0), auto)),
+    [Value::Keyword(ref keyword)] if keyword == "auto" => return Some((Value::Number(1.0), Value::Number(1.0), auto)),
+    [Value::Keyword(ref keyword)] if keyword == "initial" => return Some((Value::Number(0.0), Value::Number(1.0), auto)),
+    _ => {}
+  }
+  let mut factors = Vec::new();
+  let mut basis = None;
+  for value in values {
+    match *value {
+      Value::Number(number) if basis.is_none() || factors.is_empty() => factors.push(Value::Number(number)),
+      Value::Length(..) if basis.is_none() => basis = Some(value.clone()),
+      Value::Keyword(ref keyword) if basis.is_none() && (keyword == "auto" || keyword == "content") => basis = Some(value.clone()),
+      _ => return None,
+    }
+  }
+  if factors.len() > 2 || (factors.is_empty() && basis.is_none()) {
+    return None;
+  }
+  return Some((
+    factors.get(0).cloned().unwrap_or(Value::Number(1.0)),
+    factors.get(1).cloned().unwrap_or(Value::Number(1.0)),
+    basis.unwrap_or(Value::Length(0.0, Unit::Px)),
+  ));
+}
+
+// thin、medium、thick の幅
+fn border_width_keyword(keyword: &str) -> Option<Value> {
+  return match keyword {
+    "thin" => Some(Value::Length(1.0, Unit::Px)),
+    "medium" => Some(Value::Length(3.0, Unit::Px)),
+    "thick" => Some(Value::Length(5.0, Unit::Px)),
+    _ => None,
+  };
+}
+
+// @import の前置き（url("a.css") screen か "a.css" screen）。URL が読めなければ None
+fn parse_import(prelude: &str) -> Option<Import> {
+  let prelude = prelude.trim();
+  let (url, rest) = match prelude.get(..4) {
+    Some(function) if function.eq_ignore_ascii_case("url(") => {
+      let end = prelude.find(')')?;
+      (prelude[4..end].trim().trim_matches(|c| c == '"' || c == '\''), &prelude[end + 1..])
+    }
+    _ => {
+      let quote = prelude.chars().next().filter(|&c| c == '"' || c == '\'')?;
+      let end = prelude[1..].find(quote)? + 1;
+      (&prelude[1..end], &prelude[end + 1..])
+    }
+  };
+  return Some(Import { url: url.to_string(), media: parse_media_query_list(rest) });
+}
+
+// "screen, not print" → 2 つのクエリ（<link> や <style> の media 属性にも使う）
+pub fn parse_media_query_list(prelude: &str) -> MediaQueryList {
+  return prelude.split(',').map(str::trim).filter(|query| !query.is_empty()).map(parse_media_query).collect();
+}
+
+// [only | not] 型 [and (特性)]... か (特性) [and (特性)]...
+fn parse_media_query(source: &str) -> MediaQuery {
+  let source = source.to_ascii_lowercase();
+  let (head, features) = source.split_at(source.find('(').unwrap_or(source.len()));
+  let mut words: Vec<&str> = head.split_whitespace().collect();
+  if !features.is_empty() && words.last() == Some(&"and") {
+    words.pop();
+  }
+  let negated = words.first() == Some(&"not");
+  if negated || words.first() == Some(&"only") {
+    words.remove(0);
+  }
+  if words.len() > 1 || (words.is_empty() && negated) {
+    return MediaQuery { negated: true, media_type: "all".to_string(), features: Vec::new() };
+  }
+  let features = features
+    .split(" and ")
+    .map(|feature| feature.trim().trim_start_matches('(').trim_end_matches(')').trim().to_string())
+    .filter(|feature| !feature.is_empty())
+    .collect();
+  return MediaQuery { negated: negated, media_type: words.first().unwrap_or(&"all").to_string(), features: features };
+}
+
+impl MediaQuery {
+  // media（screen か print）で描くときに、種類だけで合わないと決まらないか。メディア特性は見ない
+  // （ビューポートの大きさで決まるので、retain_media の後で matches_viewport で比べる）
+  pub fn matches(&self, media: &str) -> bool {
+    return self.for_media(media).is_some();
+  }
+
+  // media で描くときに、メディア特性だけで合うかが決まるクエリにする（種類を all にする）。決して合わなければ None
+  fn for_media(&self, media: &str) -> Option<MediaQuery> {
+    let type_matches = self.media_type == "all" || self.media_type == media;
+    return match (self.negated, type_matches) {
+      (false, true) => Some(MediaQuery { media_type: "all".to_string(), ..self.clone() }),
+      (false, false) => None,
+      // not print は screen なら特性によらず合う
+      (true, false) => Some(MediaQuery { negated: false, media_type: "all".to_string(), features: Vec::new() }),
+      (true, true) if self.features.is_empty() => None,
+      (true, true) => Some(MediaQuery { media_type: "all".to_string(), ..self.clone() }),
+    };
+  }
+
+  // width x height のビューポートの画面に合うか（not なら逆）。種類は all か screen なら合う
+  // （retain_media で描くメディアに合うものは all にしてあるので、retain_media していないスタイルシートは画面に描くものとして見る）。
+  // 読めるメディア特性は width、height と min-、max- の付いたもの（px、em、rem）と orientation で、ほかのものがあれば合わない
+  pub fn matches_viewport(&self, width: f32, height: f32) -> bool {
+    let type_matches = self.media_type == "all" || self.media_type == "screen";
+    return (type_matches && self.features.iter().all(|feature| feature_matches(feature, width, height))) != self.negated;
+  }
+}
+
+// (min-width: 600px) の括弧の中身が width x height のビューポートに合うか
+fn feature_matches(feature: &str, width: f32, height: f32) -> bool {
+  let (name, value) = match feature.find(':') {
+    Some(colon) => (feature[..colon].trim(), feature[colon + 1..].trim()),
+    None => return false,
+  };
+  if name == "orientation" {
+    return match value {
+      "portrait" => height >= width,
+      "landscape" => width > height,
+      _ => false,
+    };
+  }
+  let mut parser = Parser { pos: 0, input: value.to_string(), diagnostics: Vec::new(), depth: 0 };
+  let length = match parser.parse_length() {
+    Ok(Value::Length(length, Unit::Px)) => length,
+    Ok(Value::Length(length, Unit::Em)) | Ok(Value::Length(length, Unit::Rem)) => length * 16.0,
+    Ok(Value::Number(length)) if length == 0.0 => 0.0,
+    _ => return false,
+  };
+  return match name {
+    "width" => width == length,
+    "min-width" => width >= length,
+    "max-width" => width <= length,
+    "height" => height == length,
+    "min-height" => height >= length,
+    "max-height" => height <= length,
+    _ => false,
+  };
+}
+
+impl Rule {
+  // viewport（幅と高さ）で描くときに、囲んでいる @media の種類とメディア特性に合うか（MediaQuery::matches_viewport）。
+  // viewport がなければ、メディア特性のあるクエリは合わない（種類だけで決まるので、大きさは何でもよい）
+  pub fn matches_viewport(&self, viewport: Option<(f32, f32)>) -> bool {
+    return self.media.iter().all(|list| {
+      list.is_empty()
+        || list.iter().any(|query| match viewport {
+          Some((width, height)) => query.matches_viewport(width, height),
+          None => query.features.is_empty() && query.matches_viewport(0.0, 0.0),
+        })
+    });
+  }
+}
+
+impl StyleSheet {
+  // other のルールと @keyframes を後ろに足す（後のものほど優先）
+  pub fn append(&mut self, other: StyleSheet) {
+    self.rules.extend(other.rules);
+    self.keyframes.extend(other.keyframes);
+    self.imports.extend(other.imports);
+  }
+
+  // すべてのルールの出どころを origin にする
+  pub fn set_origin(&mut self, origin: Origin) {
+    for rule in &mut self.rules {
+      rule.origin = origin;
+    }
+  }
+
+  // name の @keyframes。同じ名前が複数あれば最後のもの
+  pub fn find_keyframes(&self, name: &str) -> Option<&Keyframes> {
+    return self.keyframes.iter().rev().find(|keyframes| keyframes.name == name);
+  }
+
+  // 描くメディアに合わない @media の中のルールを取り除く。メディア特性のあるクエリは種類だけを見て残し、
+  // スタイルを計算するときにビューポートの大きさと比べる（Rule::matches_viewport）
+  pub fn retain_media(&mut self, media: &str) {
+    self.rules.retain_mut(|rule| {
+      for list in &mut rule.media {
+        if list.is_empty() {
+          continue;
+        }
+        *list = list.iter().filter_map(|query| query.for_media(media)).collect();
+        if list.is_empty() {
+          return false;
+        }
+      }
+      return true;
+    });
+  }
+
+  // すべてのルールを media の条件の中に入れる（@import の media。いちばん外側の @media と同じ扱い）
+  pub fn restrict_media(&mut self, media: &MediaQueryList) {
+    if media.is_empty() {
+      return;
+    }
+    for rule in &mut self.rules {
+      rule.media.insert(0, media.clone());
+    }
+  }
+
+  // url(...) の相対参照を、スタイルシート自身の URL を基準に解決する
+  pub fn resolve_urls(&mut self, base: &Url) {
+    for rule in &mut self.rules {
+      for declaration in &mut rule.declarations {
+        if let Value::Url(ref mut href) = declaration.value {
+          if let Some(url) = base.join(href) {
+            *href = url.to_string();
+          }
+        }
+      }
+    }
+  }
+}
+
+// querySelector などに渡すセレクターのリスト（"div.a, #b"）
+pub fn parse_selectors(source: &str) -> Result<Vec<Selector>, CssError> {
+  let mut parser = Parser { pos: 0, input: source.trim().to_string(), diagnostics: Vec::new(), depth: 0 };
+  let selectors = parser.parse_selectors()?;
+  if !parser.eof() {
+    return Err(CssError::InvalidSelector { found: parser.next_char()?, pos: parser.pos });
+  }
+  return Ok(selectors);
+}
+
+// style="" 属性の中身（{ } で囲まない宣言の並び）。読めない宣言は読み飛ばす
+pub fn parse_inline_style(source: &str) -> Vec<Declaration> {
+  let mut parser = Parser { pos: 0, input: format!("{{{}}}", source), diagnostics: Vec::new(), depth: 0 };
+  return parser.parse_declarations().unwrap_or_default();
+}
+
+// 読めない宣言やルールがあれば、最初のものをエラーにする
+pub fn parse(source: String) -> Result<StyleSheet, CssError> {
+  let (sheet, mut diagnostics) = parse_with_diagnostics(source);
+  if !diagnostics.is_empty() {
+    return Err(diagnostics.remove(0));
   }
+  return Ok(sheet);
 }
 
-pub fn parse(source: String) -> StyleSheet {
-  let mut parser = Parser { pos: 0, input: source };
-  return StyleSheet { rules: parser.parse_rules() }
+// 読めない宣言とルールを読み飛ばしてスタイルシートを作り、読み飛ばしたところのエラーと一緒に返す
+pub fn parse_with_diagnostics(source: String) -> (StyleSheet, Vec<CssError>) {
+  let mut span = trace::span("parse", "css");
+  span.arg("bytes", source.len() as f64);
+  let mut parser = Parser { pos: 0, input: source, diagnostics: Vec::new(), depth: 0 };
+  let sheet = parser.parse_stylesheet();
+  return (sheet, parser.diagnostics);
 }
\ No newline at end of file
@@ -0,0 +1,84 @@
+use url::percent_decode;
+
+/**
+ * data: URL（RFC 2397）のデコードとエンコード
+ * data:[<mediatype>][;base64],<data>
+ */
+
+pub struct DataUrl {
+  pub mime_type: String,
+  pub data: Vec<u8>,
+}
+
+pub fn is_data_url(location: &str) -> bool {
+  return location.len() >= 5 && location[..5].eq_ignore_ascii_case("data:");
+}
+
+pub fn parse(url: &str) -> Option<DataUrl> {
+  if !is_data_url(url) {
+    return None;
+  }
+  let rest = &url[5..];
+  let comma = rest.find(',')?;
+  let (header, payload) = (&rest[..comma], &rest[comma + 1..]);
+
+  // ;base64 は mediatype の最後のパラメータとして付く
+  let mut params: Vec<&str> = header.split(';').map(|p| p.trim()).collect();
+  let is_base64 = params.last().map_or(false, |p| p.eq_ignore_ascii_case("base64"));
+  if is_base64 {
+    params.pop();
+  }
+
+  // mediatype が省略された場合は text/plain;charset=US-ASCII
+  let mime_type = if params.is_empty() || params[0].is_empty() {
+    "text/plain;charset=US-ASCII".to_string()
+  } else {
+    params.join(";").to_ascii_lowercase()
+  };
+
+  let bytes = percent_decode(payload);
+  let data = if is_base64 { base64_decode(&bytes)? } else { bytes };
+  return Some(DataUrl { mime_type: mime_type, data: data });
+}
+
+// base64（空白は無視、末尾の = は省略可）
+fn base64_decode(input: &[u8]) -> Option<Vec<u8>> {
+  let mut result = Vec::with_capacity(input.len() * 3 / 4);
+  let mut buffer: u32 = 0;
+  let mut bits = 0;
+  for &b in input {
+    let value = match b {
+      b'A'..=b'Z' => b - b'A',
+      b'a'..=b'z' => b - b'a' + 26,
+      b'0'..=b'9' => b - b'0' + 52,
+      b'+' | b'-' => 62,
+      b'/' | b'_' => 63,
+      b'=' => break,
+      b' ' | b'\t' | b'\n' | b'\r' | b'\x0c' => continue,
+      _ => return None,
+    };
+    buffer = buffer << 6 | value as u32;
+    bits += 6;
+    if bits >= 8 {
+      bits -= 8;
+      result.push((buffer >> bits) as u8);
+      buffer &= (1 << bits) - 1;
+    }
+  }
+  return Some(result);
+}
+
+// mime_type と data から data URL を作る（base64 にする）。SVG に画像を埋め込むのに使う
+#[cfg(feature = "image")]
+pub fn encode(mime_type: &str, data: &[u8]) -> String {
+  const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+  let mut out = format!("data:{};base64,", mime_type);
+  for chunk in data.chunks(3) {
+    let buffer = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+    for i in 0..4 {
+      // 3 バイトに満たない分は = で埋める
+      out.push(if i <= chunk.len() { ALPHABET[(buffer >> (18 - i * 6)) as usize & 63] as char } else { '=' });
+    }
+  }
+  return out;
+}
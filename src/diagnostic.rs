@@ -0,0 +1,121 @@
+use css::CssError;
+use html::HtmlError;
+use std::fmt;
+use unicode_width::UnicodeWidthChar;
+
+/**
+ * パーサーが補ったり読み飛ばしたりしたところを、入力の中の位置と一緒に表す。
+ *
+ * html::parse_with_diagnostics と css::parse_with_diagnostics が返す HtmlError と CssError はバイト位置しか持たないので、
+ * from_html と from_css で入力と合わせて、重さ（Severity）、メッセージ、範囲（Span。行と列も）にする。
+ * render は rustc のように、場所の行を引いて、その下に ^ で範囲を示す
+ */
+
+// 読み飛ばして描かれないものがあれば Error、補って描けるものは Warning
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+// 入力の中の範囲。start と end はバイト位置、line と column は start の行と列（1 から。列は文字の数）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+  pub line: usize,
+  pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub message: String, // 位置を含まないメッセージ
+  pub span: Span,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    return write!(f, "{}", if *self == Severity::Error { "error" } else { "warning" });
+  }
+}
+
+impl Diagnostic {
+  // source の pos から len バイトの範囲の診断。message の中の " at {pos}" は span で表すので外す
+  fn new(source: &str, severity: Severity, message: String, pos: usize, len: usize) -> Diagnostic {
+    let start = char_boundary(source, pos);
+    let end = char_boundary(source, pos + len).max(start);
+    let line = source[..start].matches('\n').count() + 1;
+    let column = source[line_start(source, start)..start].chars().count() + 1;
+    let at = format!(" at {}", pos);
+    let message = match message.rfind(&at) {
+      Some(index) => format!("{}{}", &message[..index], &message[index + at.len()..]),
+      None => message,
+    };
+    return Diagnostic { severity: severity, message: message, span: Span { start: start, end: end, line: line, column: column } };
+  }
+
+  // html::parse_with_diagnostics に source を渡して返ってきたエラー
+  pub fn from_html(source: &str, error: &HtmlError) -> Diagnostic {
+    let (severity, pos, len) = match *error {
+      HtmlError::UnexpectedEof => (Severity::Error, source.len(), 0),
+      HtmlError::UnexpectedChar { found, pos, .. } => (Severity::Error, pos, found.len_utf8()),
+      HtmlError::MismatchedTag { ref found, pos, .. } => (Severity::Warning, pos, found.len()),
+      HtmlError::StrayEndTag { ref name, pos } | HtmlError::UnclosedElement { ref name, pos } | HtmlError::TooDeep { ref name, pos } => (Severity::Warning, pos, name.len()),
+    };
+    return Diagnostic::new(source, severity, error.to_string(), pos, len);
+  }
+
+  // css::parse_with_diagnostics に source を渡して返ってきたエラー。どれも宣言かルールを読み飛ばしている
+  pub fn from_css(source: &str, error: &CssError) -> Diagnostic {
+    let (pos, len) = match *error {
+      CssError::UnexpectedEof => (source.len(), 0),
+      CssError::UnexpectedChar { found, pos, .. } | CssError::InvalidSelector { found, pos } | CssError::InvalidValue { found, pos } => (pos, found.len_utf8()),
+      CssError::InvalidNumber { number: ref text, pos } | CssError::UnknownUnit { unit: ref text, pos } => (pos, text.len()),
+      CssError::MisplacedImport { pos } => (pos, "@import".len()),
+      CssError::NegativeValue { ref property, pos } => (pos, property.len()),
+      CssError::InvalidColor { pos } | CssError::InvalidGradient { pos } | CssError::InvalidVar { pos } | CssError::InvalidCalc { pos } | CssError::TooDeep { pos } => {
+        (pos, source[char_boundary(source, pos)..].chars().next().map_or(0, char::len_utf8))
+      }
+    };
+    return Diagnostic::new(source, Severity::Error, error.to_string(), pos, len);
+  }
+
+  // name（ファイル名や URL）の source での診断を、場所の行と ^ の印と一緒に書く（最後は改行しない）。
+  //
+  //   warning: closing tag </b> has no open element
+  //    --> index.html:3:13
+  //     |
+  //   3 | <p><i>text</b></p>
+  //     |             ^
+  pub fn render(&self, name: &str, source: &str) -> String {
+    let span = self.span;
+    let start = line_start(source, span.start);
+    let text = source[start..].split('\n').next().unwrap_or("").trim_end_matches('\r');
+    let before = &text[..(span.start - start).min(text.len())];
+    let marked = &text[before.len()..(span.end - start).min(text.len()).max(before.len())];
+    // タブはそのまま、ほかの文字は幅の分の空白にして ^ の位置をそろえる（全角の文字は 2 つ）
+    let indent: String = before.chars().map(|c| if c == '\t' { "\t".to_string() } else { " ".repeat(c.width().unwrap_or(0)) }).collect();
+    let carets = marked.chars().map(|c| c.width().unwrap_or(0)).sum::<usize>().max(1);
+    let number = span.line.to_string();
+    let gutter = " ".repeat(number.len());
+    return format!(
+      "{}: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+      self.severity, self.message, gutter, name, span.line, span.column, gutter, number, text, gutter, indent, "^".repeat(carets)
+    );
+  }
+}
+
+// pos を超えない、文字の境目のバイト位置（入力の終わりまで）
+fn char_boundary(source: &str, pos: usize) -> usize {
+  let mut pos = pos.min(source.len());
+  while !source.is_char_boundary(pos) {
+    pos -= 1;
+  }
+  return pos;
+}
+
+// pos のある行の始まりのバイト位置
+fn line_start(source: &str, pos: usize) -> usize {
+  return source[..pos].rfind('\n').map_or(0, |newline| newline + 1);
+}
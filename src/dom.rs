@@ -1,39 +1,64 @@
-use std::collections::{HashMap, HashSet};
+mod arena;
+
+pub use self::arena::{Arena, DomError};
+pub use query::Descendants;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// ノードの番号。作ったときに振る、プロセスの中で一意な番号。
+// スタイルやレイアウトを残しておいて、変わったところだけ計算し直すとき（StyledTree、LayoutTree）にノードを指すのに使う
+pub type NodeId = usize;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn next_id() -> NodeId {
+  return NEXT_ID.fetch_add(1, Ordering::Relaxed);
+}
 
 // Node
 #[derive(Debug)]
 pub struct Node {
+  pub id: NodeId,
   pub children: Vec<Node>,
   pub node_type: NodeType,
 }
 
-// NodeType - テキストか要素が入るとしてのもの
+// NodeType - テキストか要素が入るとしてのもの。コメントは残すが、描画やテキストの取り出しでは飛ばす
 #[derive(Debug)]
 pub enum NodeType {
   Text(String),
   Element(ElementData),
+  Comment(String),
 }
 
-// 要素のデータ、タグ名と属性名を格納する
-pub type AttrMap = HashMap<String, String>;
+// 要素のデータ、タグ名と属性名を格納する（属性は名前順に並ぶ）
+pub type AttrMap = BTreeMap<String, String>;
 
 #[derive(Debug)]
 pub struct ElementData {
   pub tag_name: String,
   pub attributes: AttrMap,
+  pub hover: bool, // ポインターが自身か子孫の上にある（:hover）。ビューアのイベントで変わる
 }
 
 // ノードを作成するコンストラクタ関数
 pub fn text(data: String) -> Node {
-  return Node { children: vec![], node_type: NodeType::Text(data) }
+  return Node { id: next_id(), children: vec![], node_type: NodeType::Text(data) }
+}
+
+pub fn comment(data: String) -> Node {
+  return Node { id: next_id(), children: vec![], node_type: NodeType::Comment(data) }
 }
 
+// HTML のタグ名と属性名は大文字小文字を区別しないので、小文字にそろえる（属性の値はそのまま）
 pub fn elem(name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
   return Node {
+    id: next_id(),
     children: children,
     node_type: NodeType::Element(ElementData {
-      tag_name: name,
-      attributes: attrs,
+      tag_name: name.to_ascii_lowercase(),
+      attributes: attrs.into_iter().map(|(name, value)| (name.to_ascii_lowercase(), value)).collect(),
+      hover: false,
     })
   }
 }
@@ -49,4 +74,85 @@ impl ElementData {
       None => HashSet::new()
     }
   }
-}
\ No newline at end of file
+}
+impl Node {
+  // ドキュメント順（先行順）の番号が index のノードとその祖先の要素を hover にして、ほかの要素の hover を外す（None ならすべて外す）。
+  // hover が変わった要素があれば true
+  pub fn set_hover(&mut self, index: Option<usize>) -> bool {
+    fn visit(node: &mut Node, index: Option<usize>, next: &mut usize, changed: &mut bool) -> bool {
+      let mut hover = index == Some(*next);
+      *next += 1;
+      for child in &mut node.children {
+        hover |= visit(child, index, next, changed);
+      }
+      if let NodeType::Element(ref mut elem) = node.node_type {
+        if elem.hover != hover {
+          elem.hover = hover;
+          *changed = true;
+        }
+      }
+      return hover;
+    }
+    let mut changed = false;
+    visit(self, index, &mut 0, &mut changed);
+    return changed;
+  }
+
+  // ドキュメント順（先行順）の番号が index のノードか、いちばん近い祖先の <a href> の href（リンクの中をクリックしたとき）
+  pub fn link_href(&self, index: usize) -> Option<&String> {
+    // index のノードを含む部分木なら Some（リンクの外なら Some(None)）
+    fn visit<'a>(node: &'a Node, index: usize, next: &mut usize) -> Option<Option<&'a String>> {
+      let found = if *next == index {
+        Some(None)
+      } else {
+        *next += 1;
+        node.children.iter().filter_map(|child| visit(child, index, next)).next()
+      };
+      return found.map(|href| {
+        href.or_else(|| match node.node_type {
+          NodeType::Element(ref elem) if elem.tag_name == "a" => elem.attributes.get("href"),
+          NodeType::Element(_) | NodeType::Text(_) | NodeType::Comment(_) => None,
+        })
+      });
+    }
+    return visit(self, index, &mut 0).and_then(|href| href);
+  }
+
+  // ドキュメント順で最初の <base href="..."> の値
+  pub fn base_href(&self) -> Option<&String> {
+    if let NodeType::Element(ref elem) = self.node_type {
+      if elem.tag_name == "base" {
+        if let Some(href) = elem.attributes.get("href") {
+          return Some(href);
+        }
+      }
+    }
+    return self.children.iter().filter_map(|child| child.base_href()).next();
+  }
+
+  // ドキュメント順で最初の <title> のテキスト。空白はまとめて前後を取る
+  pub fn title(&self) -> Option<String> {
+    if let NodeType::Element(ref elem) = self.node_type {
+      if elem.tag_name == "title" {
+        let text: Vec<&str> = self.children.iter().filter_map(|child| if let NodeType::Text(ref text) = child.node_type { Some(&**text) } else { None }).collect();
+        return Some(text.concat().split_whitespace().collect::<Vec<&str>>().join(" "));
+      }
+    }
+    return self.children.iter().filter_map(|child| child.title()).next();
+  }
+
+  // 子孫（自身を含む）の中で番号が id のノード
+  pub fn find(&self, id: NodeId) -> Option<&Node> {
+    if self.id == id {
+      return Some(self);
+    }
+    return self.children.iter().filter_map(|child| child.find(id)).next();
+  }
+
+  pub fn find_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+    if self.id == id {
+      return Some(self);
+    }
+    return self.children.iter_mut().filter_map(|child| child.find_mut(id)).next();
+  }
+}
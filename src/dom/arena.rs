@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use thiserror::Error;
+
+use super::{comment, elem, text, AttrMap, ElementData, Node, NodeId, NodeType};
+
+/**
+ * 書き換えられる DOM（アリーナ）。
+ *
+ * Node の木は親を持たないので、祖先にたどったり、ノードをつなぎ替えたりできない。
+ * Arena はノードを NodeId で引ける形で持ち、親と子をつないでおく（兄弟は親の子の並びでたどる）。
+ * NodeId は Node を作ったときの番号のままなので、into_node で木に戻した後も、スタイルやレイアウトのキャッシュで同じノードを指せる。
+ * 新しいノードは dom::elem や dom::text で作った木を adopt して入れる（create_element と create_text はそのラッパー）。
+ * 木から外したノードもアリーナには残り、また入れられる。into_node で木に戻すときに、どこにもつながっていないノードは捨てる
+ */
+
+#[derive(Debug, Error, PartialEq)]
+pub enum DomError {
+  #[error("no node {0} in the document")]
+  NotFound(NodeId),
+  #[error("node {0} is not an element")]
+  NotAnElement(NodeId),
+  #[error("node {0} cannot be inserted here")]
+  HierarchyRequest(NodeId),
+  #[error("node {0} is not a child of this node")]
+  NotAChild(NodeId),
+}
+
+#[derive(Debug)]
+struct Slot {
+  node_type: NodeType,
+  parent: Option<NodeId>,
+  children: Vec<NodeId>,
+}
+
+#[derive(Debug)]
+pub struct Arena {
+  slots: HashMap<NodeId, Slot>,
+  root: NodeId,
+}
+
+impl Arena {
+  // root の木をアリーナに移す
+  pub fn new(root: Node) -> Arena {
+    let mut arena = Arena { slots: HashMap::new(), root: root.id };
+    arena.adopt(root);
+    return arena;
+  }
+
+  pub fn root(&self) -> NodeId {
+    return self.root;
+  }
+
+  pub fn contains(&self, id: NodeId) -> bool {
+    return self.slots.contains_key(&id);
+  }
+
+  // node の木を、どこにもつながっていないノードとして入れる。返すのは node の番号
+  pub fn adopt(&mut self, node: Node) -> NodeId {
+    let id = node.id;
+    let mut children = Vec::with_capacity(node.children.len());
+    for child in node.children {
+      let child = self.adopt(child);
+      self.slot_mut(child).expect("adopted node").parent = Some(id);
+      children.push(child);
+    }
+    self.slots.insert(id, Slot { node_type: node.node_type, parent: None, children: children });
+    return id;
+  }
+
+  pub fn create_element(&mut self, tag_name: &str, attributes: AttrMap) -> NodeId {
+    return self.adopt(elem(tag_name.to_string(), attributes, Vec::new()));
+  }
+
+  pub fn create_text(&mut self, data: &str) -> NodeId {
+    return self.adopt(text(data.to_string()));
+  }
+
+  pub fn create_comment(&mut self, data: &str) -> NodeId {
+    return self.adopt(comment(data.to_string()));
+  }
+
+  fn slot(&self, id: NodeId) -> Result<&Slot, DomError> {
+    return self.slots.get(&id).ok_or(DomError::NotFound(id));
+  }
+
+  fn slot_mut(&mut self, id: NodeId) -> Result<&mut Slot, DomError> {
+    return self.slots.get_mut(&id).ok_or(DomError::NotFound(id));
+  }
+
+  pub fn node_type(&self, id: NodeId) -> Option<&NodeType> {
+    return self.slots.get(&id).map(|slot| &slot.node_type);
+  }
+
+  pub fn element(&self, id: NodeId) -> Option<&ElementData> {
+    return match self.node_type(id) {
+      Some(&NodeType::Element(ref elem)) => Some(elem),
+      Some(&NodeType::Text(_)) | Some(&NodeType::Comment(_)) | None => None,
+    };
+  }
+
+  pub fn element_mut(&mut self, id: NodeId) -> Option<&mut ElementData> {
+    return match self.slots.get_mut(&id).map(|slot| &mut slot.node_type) {
+      Some(&mut NodeType::Element(ref mut elem)) => Some(elem),
+      Some(&mut NodeType::Text(_)) | Some(&mut NodeType::Comment(_)) | None => None,
+    };
+  }
+
+  pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+    return self.slots.get(&id).and_then(|slot| slot.parent);
+  }
+
+  pub fn children(&self, id: NodeId) -> &[NodeId] {
+    return self.slots.get(&id).map_or(&[], |slot| &slot.children[..]);
+  }
+
+  pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+    let siblings = self.children(self.parent(id)?);
+    let index = siblings.iter().position(|&sibling| sibling == id)?;
+    return siblings.get(index + 1).cloned();
+  }
+
+  pub fn previous_sibling(&self, id: NodeId) -> Option<NodeId> {
+    let siblings = self.children(self.parent(id)?);
+    let index = siblings.iter().position(|&sibling| sibling == id)?;
+    return if index == 0 { None } else { Some(siblings[index - 1]) };
+  }
+
+  // ancestor が id 自身かその祖先か
+  pub fn is_inclusive_ancestor(&self, ancestor: NodeId, id: NodeId) -> bool {
+    let mut current = Some(id);
+    while let Some(node) = current {
+      if node == ancestor {
+        return true;
+      }
+      current = self.parent(node);
+    }
+    return false;
+  }
+
+  // id とその子孫で、ドキュメント順で最初に test を満たす要素
+  pub fn find<F>(&self, id: NodeId, test: &F) -> Option<NodeId>
+  where
+    F: Fn(&ElementData) -> bool,
+  {
+    if self.element(id).map_or(false, |elem| test(elem)) {
+      return Some(id);
+    }
+    return self.children(id).iter().filter_map(|&child| self.find(child, test)).next();
+  }
+
+  // テキストならその文字列、要素なら子孫のテキストをつなげたもの（コメントは飛ばす）
+  pub fn text_content(&self, id: NodeId) -> String {
+    return match self.node_type(id) {
+      Some(&NodeType::Text(ref data)) => data.clone(),
+      Some(&NodeType::Element(_)) => self.children(id).iter().map(|&child| self.text_content(child)).collect(),
+      Some(&NodeType::Comment(_)) | None => String::new(),
+    };
+  }
+
+  // テキストとコメントなら文字列を変え、要素なら子をすべて外して text のテキストだけにする（空なら子なし）
+  pub fn set_text_content(&mut self, id: NodeId, data: &str) -> Result<(), DomError> {
+    match self.slot_mut(id)?.node_type {
+      NodeType::Text(ref mut value) | NodeType::Comment(ref mut value) => {
+        *value = data.to_string();
+        return Ok(());
+      }
+      NodeType::Element(_) => {}
+    }
+    for child in self.slot_mut(id)?.children.split_off(0) {
+      self.slot_mut(child)?.parent = None;
+    }
+    if !data.is_empty() {
+      let child = self.create_text(data);
+      self.append_child(id, child)?;
+    }
+    return Ok(());
+  }
+
+  pub fn append_child(&mut self, parent: NodeId, child: NodeId) -> Result<(), DomError> {
+    return self.insert_before(parent, child, None);
+  }
+
+  // child を parent の子の reference の前（None なら末尾）に入れる。child がほかの場所にあれば外してから入れる。
+  // parent が要素でないときと、child が parent 自身か祖先のときは HierarchyRequest
+  pub fn insert_before(&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) -> Result<(), DomError> {
+    self.slot(parent)?;
+    self.slot(child)?;
+    if self.element(parent).is_none() || self.is_inclusive_ancestor(child, parent) {
+      return Err(DomError::HierarchyRequest(child));
+    }
+    if let Some(reference) = reference {
+      if self.parent(reference) != Some(parent) {
+        return Err(DomError::NotAChild(reference));
+      }
+    }
+    // 自分の前に入れるなら、外した後の次の兄弟の前に入れる
+    let reference = if reference == Some(child) { self.next_sibling(child) } else { reference };
+    self.remove(child)?;
+    let slot = self.slot_mut(parent)?;
+    let index = reference.and_then(|reference| slot.children.iter().position(|&id| id == reference)).unwrap_or(slot.children.len());
+    slot.children.insert(index, child);
+    self.slot_mut(child)?.parent = Some(parent);
+    return Ok(());
+  }
+
+  // id を親から外す（アリーナには残る）。親がなければ何もしない
+  pub fn remove(&mut self, id: NodeId) -> Result<(), DomError> {
+    if let Some(parent) = self.slot_mut(id)?.parent.take() {
+      self.slot_mut(parent)?.children.retain(|&child| child != id);
+    }
+    return Ok(());
+  }
+
+  // 属性名は dom::elem と同じく小文字にそろえる
+  pub fn set_attribute(&mut self, id: NodeId, name: &str, value: &str) -> Result<(), DomError> {
+    self.slot(id)?;
+    let elem = self.element_mut(id).ok_or(DomError::NotAnElement(id))?;
+    elem.attributes.insert(name.to_ascii_lowercase(), value.to_string());
+    return Ok(());
+  }
+
+  pub fn remove_attribute(&mut self, id: NodeId, name: &str) -> Result<(), DomError> {
+    self.slot(id)?;
+    let elem = self.element_mut(id).ok_or(DomError::NotAnElement(id))?;
+    elem.attributes.remove(&name.to_ascii_lowercase());
+    return Ok(());
+  }
+
+  // Node の木に戻す。どこにもつながっていないノードは捨てる
+  pub fn into_node(mut self) -> Node {
+    let root = self.root;
+    return self.take(root);
+  }
+
+  fn take(&mut self, id: NodeId) -> Node {
+    let slot = self.slots.remove(&id).expect("node appears twice in the tree");
+    let children = slot.children.into_iter().map(|child| self.take(child)).collect();
+    return Node { id: id, children: children, node_type: slot.node_type };
+  }
+}
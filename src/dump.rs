@@ -0,0 +1,827 @@
+use a11y::AccessibleNode;
+use dom::{ElementData, Node, NodeType};
+use inspect::{Inspection, MatchedDeclaration};
+use layout::{AnonymousBlock, BlockNode, BoxType, Dimensions, EdgeSizes, InlineBlockNode, InlineNode, LayoutBox, Rect, ReplacedNode};
+use memory::{MemoryUsage, Usage};
+use paint::{Canvas, DisplayCommand, DisplayList};
+use profile::{Counts, Timings};
+use std::time::Duration;
+use style::{self, BorderStyle, StyledNode};
+
+/**
+ * パイプラインの各段階（DOM、スタイル、レイアウト、ディスプレイリスト）を
+ * 人が読めるテキストか JSON で書き出す
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+  Text,
+  Json,
+}
+
+pub fn dom(root: &Node, format: Format) -> String {
+  return match format {
+    Format::Text => {
+      let mut out = String::new();
+      dom_text(root, 0, &mut out);
+      out
+    }
+    Format::Json => dom_json(root).to_pretty_string(),
+  };
+}
+
+pub fn style(root: &StyledNode, format: Format) -> String {
+  return match format {
+    Format::Text => {
+      let mut out = String::new();
+      style_text(root, 0, &mut out);
+      out
+    }
+    Format::Json => style_json(root).to_pretty_string(),
+  };
+}
+
+// 要素ごとのすべてのプロパティの計算値（style::computed_values）。vw と vh は viewport で px にする
+pub fn computed_style(root: &StyledNode, viewport: Option<Rect>, format: Format) -> String {
+  return match format {
+    Format::Text => {
+      let mut out = String::new();
+      computed_style_text(root, viewport, 0, &mut out);
+      out
+    }
+    Format::Json => computed_style_json(root, viewport).to_pretty_string(),
+  };
+}
+
+pub fn layout(root: &LayoutBox, format: Format) -> String {
+  return match format {
+    Format::Text => {
+      let mut out = String::new();
+      layout_text(root, 0, &mut out);
+      out
+    }
+    Format::Json => layout_json(root).to_pretty_string(),
+  };
+}
+
+// レイアウトツリーを罫線で描いた木にして、ボックスごとに種類、タグ、ボーダーボックスだけを 1 行で書き出す（--dump-boxes）
+pub fn boxes(root: &LayoutBox, format: Format) -> String {
+  return match format {
+    Format::Text => {
+      let mut out = String::new();
+      boxes_text(root, "", "", &mut out);
+      out
+    }
+    Format::Json => boxes_json(root).to_pretty_string(),
+  };
+}
+
+// キャンバスを端末に描く（--dump-terminal）。columns 文字の幅に縮め、1 文字を縦が横の 2 倍のピクセルの平均の色にして、
+// 24 ビットカラーの背景色を付けた空白で描く（前の文字と同じ色なら色を付け直さない）
+pub fn terminal(canvas: &Canvas, columns: usize) -> String {
+  let mut out = String::new();
+  if canvas.width == 0 || canvas.height == 0 || columns == 0 {
+    return out;
+  }
+  // 1 文字の幅（ピクセル）。キャンバスが columns より狭ければ 1 ピクセルを 1 文字にする
+  let cell = (canvas.width as f32 / columns as f32).max(1.0);
+  let columns = (canvas.width as f32 / cell).ceil() as usize;
+  let rows = (canvas.height as f32 / (cell * 2.0)).ceil() as usize;
+  let span = |i: usize, size: f32, limit: usize| {
+    let start = ((i as f32 * size) as usize).min(limit - 1);
+    return (start, (((i + 1) as f32 * size) as usize).min(limit).max(start + 1));
+  };
+  for row in 0..rows {
+    let (top, bottom) = span(row, cell * 2.0, canvas.height);
+    let mut previous = None;
+    for column in 0..columns {
+      let (left, right) = span(column, cell, canvas.width);
+      let mut sum = [0u32; 3];
+      for y in top..bottom {
+        for color in &canvas.pixels[y * canvas.width + left..y * canvas.width + right] {
+          sum[0] += color.r as u32;
+          sum[1] += color.g as u32;
+          sum[2] += color.b as u32;
+        }
+      }
+      let count = ((bottom - top) * (right - left)) as u32;
+      let color = (sum[0] / count, sum[1] / count, sum[2] / count);
+      if previous != Some(color) {
+        out.push_str(&format!("\x1b[48;2;{};{};{}m", color.0, color.1, color.2));
+        previous = Some(color);
+      }
+      out.push(' ');
+    }
+    out.push_str("\x1b[0m\n");
+  }
+  return out;
+}
+
+pub fn a11y(root: &AccessibleNode, format: Format) -> String {
+  return match format {
+    Format::Text => {
+      let mut out = String::new();
+      a11y_text(root, 0, &mut out);
+      out
+    }
+    Format::Json => a11y_json(root).to_pretty_string(),
+  };
+}
+
+pub fn display_list(list: &DisplayList, format: Format) -> String {
+  return match format {
+    Format::Text => {
+      let mut out = String::new();
+      for item in list {
+        match *item {
+          DisplayCommand::SolidColor(color, rect) => out.push_str(&format!("SolidColor {} {}\n", color, rect_text(rect))),
+          DisplayCommand::RoundedRect { color, rect, radii, clip } => {
+            out.push_str(&format!("RoundedRect {} {} radii: {} clip: {}\n", color, rect_text(rect), radii_text(radii), rect_text(clip)))
+          }
+          DisplayCommand::Border { rect, widths, colors, styles, radii, clip } => {
+            // 辺ごとの値は widths と同じ上、右、下、左の順に書く
+            let colors = format!("({} {} {} {})", colors[2], colors[1], colors[3], colors[0]);
+            let styles = format!("({} {} {} {})", border_style_name(styles[2]), border_style_name(styles[1]), border_style_name(styles[3]), border_style_name(styles[0]));
+            out.push_str(&format!(
+              "Border {} widths: {} colors: {} styles: {} radii: {} clip: {}\n",
+              rect_text(rect),
+              edges_text(widths),
+              colors,
+              styles,
+              radii_text(radii),
+              rect_text(clip)
+            ))
+          }
+          DisplayCommand::Text { ref text, color, x, y, font_size, clip, .. } => {
+            out.push_str(&format!("Text {:?} {} (x: {}, y: {}) {}px clip: {}\n", text, color, x, y, font_size, rect_text(clip)))
+          }
+          DisplayCommand::Checkbox { rect, checked, clip } => out.push_str(&format!("Checkbox {} checked: {} clip: {}\n", rect_text(rect), checked, rect_text(clip))),
+          DisplayCommand::Radio { rect, checked, clip } => out.push_str(&format!("Radio {} checked: {} clip: {}\n", rect_text(rect), checked, rect_text(clip))),
+          DisplayCommand::Image { ref image, rect, repeat, clip } => {
+            out.push_str(&format!("Image {}x{} {} repeat: {} clip: {}\n", image.width, image.height, rect_text(rect), repeat, rect_text(clip)))
+          }
+          DisplayCommand::Gradient { rect, angle, ref stops, clip } => {
+            let stops: Vec<String> = stops.iter().map(|&(color, position)| format!("{} {}", color, position)).collect();
+            out.push_str(&format!("Gradient {} angle: {} stops: ({}) clip: {}\n", rect_text(rect), angle, stops.join(", "), rect_text(clip)))
+          }
+          DisplayCommand::PushOpacity(opacity) => out.push_str(&format!("PushOpacity {}\n", opacity)),
+          DisplayCommand::PopOpacity => out.push_str("PopOpacity\n"),
+          DisplayCommand::PushTransform { matrix, bounds, clip } => out.push_str(&format!(
+            "PushTransform matrix({}, {}, {}, {}, {}, {}) bounds: {} clip: {}\n",
+            matrix.a,
+            matrix.b,
+            matrix.c,
+            matrix.d,
+            matrix.e,
+            matrix.f,
+            rect_text(bounds),
+            rect_text(clip)
+          )),
+          DisplayCommand::PopTransform => out.push_str("PopTransform\n"),
+        }
+      }
+      out
+    }
+    Format::Json => Json::Array(list.iter().map(display_command_json).collect()).to_pretty_string(),
+  };
+}
+
+// --profile の結果。時間はミリ秒
+pub fn profile(timings: &Timings, counts: &Counts, format: Format) -> String {
+  let phases = [
+    ("fetch", timings.fetch),
+    ("parse", timings.parse),
+    ("script", timings.script),
+    ("style", timings.style),
+    ("layout", timings.layout),
+    ("display_list", timings.display_list),
+    ("raster", timings.raster),
+    ("total", timings.total()),
+  ];
+  let counts = [
+    ("dom_nodes", counts.dom_nodes),
+    ("rules", counts.rules),
+    ("layout_boxes", counts.layout_boxes),
+    ("display_items", counts.display_items),
+    ("pixels", counts.pixels),
+  ];
+  return match format {
+    Format::Text => {
+      let mut out = String::new();
+      for &(name, duration) in phases.iter() {
+        out.push_str(&format!("{:<14}{:>10.3} ms\n", name, milliseconds(duration)));
+      }
+      for &(name, count) in counts.iter() {
+        out.push_str(&format!("{:<14}{:>10}\n", name, count));
+      }
+      out
+    }
+    Format::Json => Json::object(vec![
+      ("timings_ms", Json::object(phases.iter().map(|&(name, duration)| (name, Json::Number(milliseconds(duration)))).collect())),
+      ("counts", Json::object(counts.iter().map(|&(name, count)| (name, Json::Number(count as f64))).collect())),
+    ])
+    .to_pretty_string(),
+  };
+}
+
+// --inspect の結果
+pub fn inspect(inspections: &[Inspection], format: Format) -> String {
+  return match format {
+    Format::Text => {
+      let mut out = String::new();
+      for inspection in inspections {
+        inspect_text(inspection, &mut out);
+      }
+      out
+    }
+    Format::Json => Json::Array(inspections.iter().map(inspection_json).collect()).to_pretty_string(),
+  };
+}
+
+// --memory の結果。バイト数はおおよその値
+pub fn memory(usage: &MemoryUsage, format: Format) -> String {
+  let parts = [
+    ("dom", usage.dom),
+    ("styles", usage.styles),
+    ("layout", usage.layout),
+    ("display_list", usage.display_list),
+    ("canvas", usage.canvas),
+  ];
+  return match format {
+    Format::Text => {
+      let mut out = String::new();
+      for &(name, part) in parts.iter() {
+        out.push_str(&format!("{:<14}{:>10} items {:>12} bytes\n", name, part.count, part.bytes));
+      }
+      out.push_str(&format!("{:<14}{:>29} bytes\n", "total", usage.total_bytes()));
+      out.push_str(&format!("{:<14}{:>29} bytes\n", "peak", usage.peak_bytes));
+      out
+    }
+    Format::Json => {
+      let mut members: Vec<(&str, Json)> = parts.iter().map(|&(name, part)| (name, usage_json(part))).collect();
+      members.push(("total_bytes", Json::Number(usage.total_bytes() as f64)));
+      members.push(("peak_bytes", Json::Number(usage.peak_bytes as f64)));
+      Json::object(members).to_pretty_string()
+    }
+  };
+}
+
+fn usage_json(usage: Usage) -> Json {
+  return Json::object(vec![("count", Json::Number(usage.count as f64)), ("bytes", Json::Number(usage.bytes as f64))]);
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+  return duration.as_secs_f64() * 1000.0;
+}
+
+/**
+ * テキスト
+ */
+
+fn indent(depth: usize, out: &mut String) {
+  for _ in 0..depth {
+    out.push_str("  ");
+  }
+}
+
+// <div id="a" class="b"> のような開始タグ（属性は名前順）
+fn start_tag(elem: &ElementData) -> String {
+  let mut names: Vec<&String> = elem.attributes.keys().collect();
+  names.sort();
+  let mut tag = format!("<{}", elem.tag_name);
+  for name in names {
+    tag.push_str(&format!(" {}=\"{}\"", name, elem.attributes[name]));
+  }
+  tag.push('>');
+  return tag;
+}
+
+pub fn node_label(node: &Node) -> String {
+  return match node.node_type {
+    NodeType::Element(ref elem) => start_tag(elem),
+    NodeType::Text(ref text) => format!("{:?}", text),
+    NodeType::Comment(ref data) => format!("<!--{}-->", data),
+  };
+}
+
+fn dom_text(node: &Node, depth: usize, out: &mut String) {
+  indent(depth, out);
+  out.push_str(&node_label(node));
+  out.push('\n');
+  for child in &node.children {
+    dom_text(child, depth + 1, out);
+  }
+}
+
+fn style_text(node: &StyledNode, depth: usize, out: &mut String) {
+  indent(depth, out);
+  out.push_str(&node_label(node.node));
+  let mut names: Vec<&String> = node.specified_values.keys().collect();
+  names.sort();
+  if !names.is_empty() {
+    let declarations: Vec<String> = names.iter().map(|name| format!("{}: {}", name, node.specified_values[*name])).collect();
+    out.push_str(&format!(" {{ {}; }}", declarations.join("; ")));
+  }
+  out.push('\n');
+  for child in &node.children {
+    style_text(child, depth + 1, out);
+  }
+}
+
+// テキストとコメントは計算値を書かない
+fn computed_style_text(node: &StyledNode, viewport: Option<Rect>, depth: usize, out: &mut String) {
+  indent(depth, out);
+  out.push_str(&node_label(node.node));
+  if let NodeType::Element(_) = node.node.node_type {
+    let declarations: Vec<String> = style::computed_values(node, viewport).iter().map(|&(ref name, ref value)| format!("{}: {}", name, value)).collect();
+    out.push_str(&format!(" {{ {}; }}", declarations.join("; ")));
+  }
+  out.push('\n');
+  for child in &node.children {
+    computed_style_text(child, viewport, depth + 1, out);
+  }
+}
+
+fn rect_text(rect: Rect) -> String {
+  return format!("(x: {}, y: {}, width: {}, height: {})", rect.x, rect.y, rect.width, rect.height);
+}
+
+fn edges_text(edges: EdgeSizes) -> String {
+  return format!("({} {} {} {})", edges.top, edges.right, edges.bottom, edges.left);
+}
+
+// 角の半径（左上、右上、右下、左下）
+fn radii_text(radii: [f32; 4]) -> String {
+  return format!("({} {} {} {})", radii[0], radii[1], radii[2], radii[3]);
+}
+
+fn border_style_name(style: BorderStyle) -> &'static str {
+  return match style {
+    BorderStyle::None => "none",
+    BorderStyle::Solid => "solid",
+    BorderStyle::Dashed => "dashed",
+    BorderStyle::Dotted => "dotted",
+  };
+}
+
+pub fn box_type_label(box_type: &BoxType) -> String {
+  return match *box_type {
+    BlockNode(node) => format!("BlockNode {}", node_label(node.node)),
+    InlineNode(node) => format!("InlineNode {}", node_label(node.node)),
+    InlineBlockNode(node) => format!("InlineBlockNode {}", node_label(node.node)),
+    ReplacedNode(node) => format!("ReplacedNode {}", node_label(node.node)),
+    AnonymousBlock => "AnonymousBlock".to_string(),
+  };
+}
+
+// BlockNode <div class="a"> 0,40 86.67x20
+// |-- AnonymousBlock 0,40 86.67x16
+// |   `-- InlineNode "A" 0,40 8x16
+fn boxes_text(layout_box: &LayoutBox, prefix: &str, child_prefix: &str, out: &mut String) {
+  let rect = layout_box.dimensions.border_box();
+  out.push_str(&format!("{}{} {},{} {}x{}\n", prefix, box_type_label(&layout_box.box_type), round(rect.x), round(rect.y), round(rect.width), round(rect.height)));
+  for (i, child) in layout_box.children.iter().enumerate() {
+    if i + 1 == layout_box.children.len() {
+      boxes_text(child, &format!("{}`-- ", child_prefix), &format!("{}    ", child_prefix), out);
+    } else {
+      boxes_text(child, &format!("{}|-- ", child_prefix), &format!("{}|   ", child_prefix), out);
+    }
+  }
+}
+
+// 小数第 2 位まで（86.666664 は 86.67）
+fn round(length: f32) -> f32 {
+  return (length * 100.0).round() / 100.0;
+}
+
+fn layout_text(layout_box: &LayoutBox, depth: usize, out: &mut String) {
+  let d = layout_box.dimensions;
+  indent(depth, out);
+  out.push_str(&format!(
+    "{} content: {} padding: {} border: {} margin: {}\n",
+    box_type_label(&layout_box.box_type),
+    rect_text(d.content),
+    edges_text(d.padding),
+    edges_text(d.border),
+    edges_text(d.margin)
+  ));
+  for line in &layout_box.lines {
+    indent(depth + 1, out);
+    out.push_str(&format!("line {}\n", rect_text(*line)));
+  }
+  if let Some(ref image) = layout_box.image {
+    indent(depth + 1, out);
+    out.push_str(&format!("image {}x{}\n", image.width, image.height));
+  }
+  // <img> を行に置いた場所は文字のない断片なので出さない
+  for fragment in layout_box.fragments.iter().filter(|fragment| !fragment.text.is_empty()) {
+    indent(depth + 1, out);
+    out.push_str(&format!("text {:?} {}\n", fragment.text, rect_text(fragment.rect)));
+  }
+  for child in &layout_box.children {
+    layout_text(child, depth + 1, out);
+  }
+}
+
+// <div class="a"> (node 3)
+//   content: (...) padding: (...) border: (...) margin: (...)
+//   rules:
+//     #12 .a (0, 1, 0)
+//       width: 50px;
+//       height: 10px; /* overridden */
+//   style:
+//     color: red;
+//   values:
+//     width: 50px;
+fn inspect_text(inspection: &Inspection, out: &mut String) {
+  out.push_str(&format!("{} (node {})\n", inspection.tag, inspection.node_index));
+  match inspection.dimensions {
+    Some(d) => {
+      out.push_str(&format!("  content: {}\n", rect_text(d.content)));
+      out.push_str(&format!("  padding: {} {}\n", edges_text(d.padding), rect_text(d.padding_box())));
+      out.push_str(&format!("  border: {} {}\n", edges_text(d.border), rect_text(d.border_box())));
+      out.push_str(&format!("  margin: {} {}\n", edges_text(d.margin), rect_text(d.margin_box())));
+    }
+    None => out.push_str("  (no box)\n"),
+  }
+  out.push_str("  rules:\n");
+  for rule in &inspection.rules {
+    let origin = if rule.user_agent { " (user agent)" } else { "" };
+    let (a, b, c) = rule.specificity;
+    out.push_str(&format!("    #{} {} ({}, {}, {}){}\n", rule.index, rule.selector, a, b, c, origin));
+    for declaration in &rule.declarations {
+      declaration_text(declaration, 3, out);
+    }
+  }
+  if !inspection.inline.is_empty() {
+    out.push_str("  style:\n");
+    for declaration in &inspection.inline {
+      declaration_text(declaration, 2, out);
+    }
+  }
+  out.push_str("  values:\n");
+  for (name, value) in &inspection.values {
+    out.push_str(&format!("    {}: {};\n", name, value));
+  }
+}
+
+fn declaration_text(declaration: &MatchedDeclaration, depth: usize, out: &mut String) {
+  let important = if declaration.important { " !important" } else { "" };
+  let overridden = if declaration.applied { "" } else { " /* overridden */" };
+  indent(depth, out);
+  out.push_str(&format!("{}: {}{};{}\n", declaration.name, declaration.value, important, overridden));
+}
+
+// heading "タイトル" level=1
+fn a11y_text(node: &AccessibleNode, depth: usize, out: &mut String) {
+  indent(depth, out);
+  out.push_str(&node.role);
+  if let Some(ref name) = node.name {
+    out.push_str(&format!(" {:?}", name));
+  }
+  if let Some(level) = node.level {
+    out.push_str(&format!(" level={}", level));
+  }
+  out.push('\n');
+  for child in &node.children {
+    a11y_text(child, depth + 1, out);
+  }
+}
+
+/**
+ * JSON
+ */
+
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>), // キーの順番を保つ
+}
+
+impl Json {
+  fn object(members: Vec<(&str, Json)>) -> Json {
+    return Json::Object(members.into_iter().map(|(key, value)| (key.to_string(), value)).collect());
+  }
+
+  pub fn to_pretty_string(&self) -> String {
+    let mut out = String::new();
+    self.write(0, &mut out);
+    out.push('\n');
+    return out;
+  }
+
+  fn write(&self, depth: usize, out: &mut String) {
+    match *self {
+      Json::Null => out.push_str("null"),
+      Json::Bool(value) => out.push_str(if value { "true" } else { "false" }),
+      Json::Number(value) => {
+        if value.is_finite() {
+          out.push_str(&value.to_string())
+        } else {
+          out.push_str("null") // JSON に NaN や Infinity はない
+        }
+      }
+      Json::String(ref value) => write_json_string(value, out),
+      Json::Array(ref items) => {
+        if items.is_empty() {
+          out.push_str("[]");
+          return;
+        }
+        out.push_str("[\n");
+        for (i, item) in items.iter().enumerate() {
+          indent(depth + 1, out);
+          item.write(depth + 1, out);
+          out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+        }
+        indent(depth, out);
+        out.push(']');
+      }
+      Json::Object(ref members) => {
+        if members.is_empty() {
+          out.push_str("{}");
+          return;
+        }
+        out.push_str("{\n");
+        for (i, &(ref key, ref value)) in members.iter().enumerate() {
+          indent(depth + 1, out);
+          write_json_string(key, out);
+          out.push_str(": ");
+          value.write(depth + 1, out);
+          out.push_str(if i + 1 < members.len() { ",\n" } else { "\n" });
+        }
+        indent(depth, out);
+        out.push('}');
+      }
+    }
+  }
+}
+
+fn write_json_string(value: &str, out: &mut String) {
+  out.push('"');
+  for c in value.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+}
+
+fn node_json_fields(node: &Node) -> Vec<(&'static str, Json)> {
+  return match node.node_type {
+    NodeType::Element(ref elem) => {
+      let mut names: Vec<&String> = elem.attributes.keys().collect();
+      names.sort();
+      let attributes = names.into_iter().map(|name| (name.clone(), Json::String(elem.attributes[name].clone()))).collect();
+      vec![
+        ("type", Json::String("element".to_string())),
+        ("tag_name", Json::String(elem.tag_name.clone())),
+        ("attributes", Json::Object(attributes)),
+      ]
+    }
+    NodeType::Text(ref text) => vec![("type", Json::String("text".to_string())), ("text", Json::String(text.clone()))],
+    NodeType::Comment(ref data) => vec![("type", Json::String("comment".to_string())), ("text", Json::String(data.clone()))],
+  };
+}
+
+fn dom_json(node: &Node) -> Json {
+  let mut fields = node_json_fields(node);
+  fields.push(("children", Json::Array(node.children.iter().map(dom_json).collect())));
+  return Json::object(fields);
+}
+
+fn style_json(node: &StyledNode) -> Json {
+  let mut names: Vec<&String> = node.specified_values.keys().collect();
+  names.sort();
+  let values = names.into_iter().map(|name| (name.clone(), Json::String(node.specified_values[name].to_string()))).collect();
+  let mut fields = node_json_fields(node.node);
+  fields.push(("specified_values", Json::Object(values)));
+  fields.push(("children", Json::Array(node.children.iter().map(style_json).collect())));
+  return Json::object(fields);
+}
+
+fn computed_style_json(node: &StyledNode, viewport: Option<Rect>) -> Json {
+  let mut fields = node_json_fields(node.node);
+  if let NodeType::Element(_) = node.node.node_type {
+    let values = style::computed_values(node, viewport).into_iter().map(|(name, value)| (name, Json::String(value.to_string()))).collect();
+    fields.push(("computed_values", Json::Object(values)));
+  }
+  fields.push(("children", Json::Array(node.children.iter().map(|child| computed_style_json(child, viewport)).collect())));
+  return Json::object(fields);
+}
+
+fn rect_json(rect: Rect) -> Json {
+  return Json::object(vec![
+    ("x", Json::Number(rect.x as f64)),
+    ("y", Json::Number(rect.y as f64)),
+    ("width", Json::Number(rect.width as f64)),
+    ("height", Json::Number(rect.height as f64)),
+  ]);
+}
+
+fn edges_json(edges: EdgeSizes) -> Json {
+  return Json::object(vec![
+    ("top", Json::Number(edges.top as f64)),
+    ("right", Json::Number(edges.right as f64)),
+    ("bottom", Json::Number(edges.bottom as f64)),
+    ("left", Json::Number(edges.left as f64)),
+  ]);
+}
+
+fn radii_json(radii: [f32; 4]) -> Json {
+  return Json::object(vec![
+    ("top_left", Json::Number(radii[0] as f64)),
+    ("top_right", Json::Number(radii[1] as f64)),
+    ("bottom_right", Json::Number(radii[2] as f64)),
+    ("bottom_left", Json::Number(radii[3] as f64)),
+  ]);
+}
+
+fn inspection_json(inspection: &Inspection) -> Json {
+  let rules = inspection.rules.iter().map(|rule| {
+    let (a, b, c) = rule.specificity;
+    Json::object(vec![
+      ("index", Json::Number(rule.index as f64)),
+      ("user_agent", Json::Bool(rule.user_agent)),
+      ("selector", Json::String(rule.selector.clone())),
+      ("specificity", Json::Array(vec![Json::Number(a as f64), Json::Number(b as f64), Json::Number(c as f64)])),
+      (
+        "declarations",
+        Json::Array(rule.declarations.iter().map(declaration_json).collect()),
+      ),
+    ])
+  });
+  return Json::object(vec![
+    ("node_index", Json::Number(inspection.node_index as f64)),
+    ("tag", Json::String(inspection.tag.clone())),
+    (
+      "box",
+      match inspection.dimensions {
+        Some(d) => Json::object(vec![
+          ("dimensions", dimensions_json(d)),
+          ("padding_box", rect_json(d.padding_box())),
+          ("border_box", rect_json(d.border_box())),
+          ("margin_box", rect_json(d.margin_box())),
+        ]),
+        None => Json::Null,
+      },
+    ),
+    ("rules", Json::Array(rules.collect())),
+    ("inline", Json::Array(inspection.inline.iter().map(declaration_json).collect())),
+    ("values", Json::Object(inspection.values.iter().map(|(name, value)| (name.clone(), Json::String(value.clone()))).collect())),
+  ]);
+}
+
+fn declaration_json(declaration: &MatchedDeclaration) -> Json {
+  return Json::object(vec![
+    ("name", Json::String(declaration.name.clone())),
+    ("value", Json::String(declaration.value.clone())),
+    ("important", Json::Bool(declaration.important)),
+    ("applied", Json::Bool(declaration.applied)),
+  ]);
+}
+
+fn dimensions_json(d: Dimensions) -> Json {
+  return Json::object(vec![
+    ("content", rect_json(d.content)),
+    ("padding", edges_json(d.padding)),
+    ("border", edges_json(d.border)),
+    ("margin", edges_json(d.margin)),
+  ]);
+}
+
+fn boxes_json(layout_box: &LayoutBox) -> Json {
+  let (box_type, node) = box_type_json(&layout_box.box_type);
+  return Json::object(vec![
+    ("box_type", Json::String(box_type.to_string())),
+    ("node", node.map_or(Json::Null, |node| Json::String(node_label(node)))),
+    ("border_box", rect_json(layout_box.dimensions.border_box())),
+    ("children", Json::Array(layout_box.children.iter().map(boxes_json).collect())),
+  ]);
+}
+
+fn box_type_json<'a>(box_type: &BoxType<'a>) -> (&'static str, Option<&'a Node>) {
+  return match *box_type {
+    BlockNode(node) => ("block", Some(node.node)),
+    InlineNode(node) => ("inline", Some(node.node)),
+    InlineBlockNode(node) => ("inline-block", Some(node.node)),
+    ReplacedNode(node) => ("replaced", Some(node.node)),
+    AnonymousBlock => ("anonymous", None),
+  };
+}
+
+fn layout_json(layout_box: &LayoutBox) -> Json {
+  let (box_type, node) = box_type_json(&layout_box.box_type);
+  return Json::object(vec![
+    ("box_type", Json::String(box_type.to_string())),
+    ("node", node.map_or(Json::Null, |node| Json::String(node_label(node)))),
+    ("dimensions", dimensions_json(layout_box.dimensions)),
+    ("lines", Json::Array(layout_box.lines.iter().map(|line| rect_json(*line)).collect())),
+    ("image", layout_box.image.as_ref().map_or(Json::Null, |image| Json::object(vec![
+      ("width", Json::Number(image.width as f64)),
+      ("height", Json::Number(image.height as f64)),
+    ]))),
+    ("fragments", Json::Array(layout_box.fragments.iter().filter(|fragment| !fragment.text.is_empty()).map(|fragment| Json::object(vec![
+      ("text", Json::String(fragment.text.clone())),
+      ("rect", rect_json(fragment.rect)),
+      ("font_size", Json::Number(fragment.font_size as f64)),
+    ])).collect())),
+    ("children", Json::Array(layout_box.children.iter().map(layout_json).collect())),
+  ]);
+}
+
+fn a11y_json(node: &AccessibleNode) -> Json {
+  return Json::object(vec![
+    ("role", Json::String(node.role.clone())),
+    ("name", node.name.as_ref().map_or(Json::Null, |name| Json::String(name.clone()))),
+    ("level", node.level.map_or(Json::Null, |level| Json::Number(level as f64))),
+    ("children", Json::Array(node.children.iter().map(a11y_json).collect())),
+  ]);
+}
+
+fn display_command_json(item: &DisplayCommand) -> Json {
+  return match *item {
+    DisplayCommand::SolidColor(color, rect) => Json::object(vec![
+      ("command", Json::String("SolidColor".to_string())),
+      ("color", Json::String(color.to_string())),
+      ("rect", rect_json(rect)),
+    ]),
+    DisplayCommand::RoundedRect { color, rect, radii, clip } => Json::object(vec![
+      ("command", Json::String("RoundedRect".to_string())),
+      ("color", Json::String(color.to_string())),
+      ("rect", rect_json(rect)),
+      ("radii", radii_json(radii)),
+      ("clip", rect_json(clip)),
+    ]),
+    DisplayCommand::Border { rect, widths, colors, styles, radii, clip } => {
+      let sides = |values: [Json; 4]| {
+        let [left, right, top, bottom] = values;
+        Json::object(vec![("top", top), ("right", right), ("bottom", bottom), ("left", left)])
+      };
+      Json::object(vec![
+        ("command", Json::String("Border".to_string())),
+        ("rect", rect_json(rect)),
+        ("widths", edges_json(widths)),
+        ("colors", sides(colors.map(|color| Json::String(color.to_string())))),
+        ("styles", sides(styles.map(|style| Json::String(border_style_name(style).to_string())))),
+        ("radii", radii_json(radii)),
+        ("clip", rect_json(clip)),
+      ])
+    }
+    DisplayCommand::Text { ref text, ref glyphs, color, x, y, font_size, clip } => Json::object(vec![
+      ("command", Json::String("Text".to_string())),
+      ("text", Json::String(text.clone())),
+      ("color", Json::String(color.to_string())),
+      ("x", Json::Number(x as f64)),
+      ("y", Json::Number(y as f64)),
+      ("font_size", Json::Number(font_size as f64)),
+      ("clip", rect_json(clip)),
+      ("glyphs", glyphs.as_ref().map_or(Json::Null, |run| Json::Array(run.glyphs.iter().map(|glyph| Json::object(vec![
+        ("id", Json::Number(glyph.id as f64)),
+        ("x", Json::Number(glyph.x as f64)),
+        ("y", Json::Number(glyph.y as f64)),
+      ])).collect()))),
+    ]),
+    DisplayCommand::Checkbox { rect, checked, clip } | DisplayCommand::Radio { rect, checked, clip } => Json::object(vec![
+      ("command", Json::String(if let DisplayCommand::Checkbox { .. } = *item { "Checkbox" } else { "Radio" }.to_string())),
+      ("rect", rect_json(rect)),
+      ("checked", Json::Bool(checked)),
+      ("clip", rect_json(clip)),
+    ]),
+    DisplayCommand::Image { ref image, rect, repeat, clip } => Json::object(vec![
+      ("command", Json::String("Image".to_string())),
+      ("width", Json::Number(image.width as f64)),
+      ("height", Json::Number(image.height as f64)),
+      ("rect", rect_json(rect)),
+      ("repeat", Json::Bool(repeat)),
+      ("clip", rect_json(clip)),
+    ]),
+    DisplayCommand::Gradient { rect, angle, ref stops, clip } => Json::object(vec![
+      ("command", Json::String("Gradient".to_string())),
+      ("rect", rect_json(rect)),
+      ("angle", Json::Number(angle as f64)),
+      ("stops", Json::Array(stops.iter().map(|&(color, position)| Json::object(vec![
+        ("color", Json::String(color.to_string())),
+        ("position", Json::Number(position as f64)),
+      ])).collect())),
+      ("clip", rect_json(clip)),
+    ]),
+    DisplayCommand::PushOpacity(opacity) => Json::object(vec![("command", Json::String("PushOpacity".to_string())), ("opacity", Json::Number(opacity as f64))]),
+    DisplayCommand::PopOpacity => Json::object(vec![("command", Json::String("PopOpacity".to_string()))]),
+    DisplayCommand::PushTransform { matrix, bounds, clip } => Json::object(vec![
+      ("command", Json::String("PushTransform".to_string())),
+      ("matrix", Json::Array([matrix.a, matrix.b, matrix.c, matrix.d, matrix.e, matrix.f].iter().map(|&value| Json::Number(value as f64)).collect())),
+      ("bounds", rect_json(bounds)),
+      ("clip", rect_json(clip)),
+    ]),
+    DisplayCommand::PopTransform => Json::object(vec![("command", Json::String("PopTransform".to_string()))]),
+  };
+}
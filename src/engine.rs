@@ -0,0 +1,589 @@
+use a11y::{self, AccessibleNode};
+use css;
+use dom::{Node, NodeType};
+use error::EngineError;
+use frame;
+use layout::{self, Dimensions, LayoutBox, Rect};
+use loader::{Loader, Source};
+use memory::{self, MemoryUsage};
+use options::EngineOptions;
+use paint::{self, Canvas, DisplayList, Page};
+use profile::{self, Counts, Timings};
+use snapshot::{self, Snapshot};
+use std::collections::HashMap;
+use style::{self, PropertyMap};
+use url::Url;
+use {initial_containing_block, load_stylesheet, load_with, page_bounds, scrolled_bounds, LoadedDocument};
+
+/**
+ * パース → スタイル → レイアウト → 描画 を行うための入り口。
+ *
+ *   let result = Engine::new(EngineOptions::default())
+ *     .html("<div class=\"a\"></div>")
+ *     .stylesheet(".a { display: block; height: 10px; }")
+ *     .viewport(800.0, 600.0)
+ *     .render()?;
+ *
+ * url / stylesheet_url ではファイルパスか URL から読み込む。
+ *
+ * Engine は DOM とスタイルシート、各段階の結果を持ち続けるので、続けて描画するときは
+ * 変わったところから先だけをやり直す。
+ *   set_html / set_url / reload        → 読み込みから
+ *   navigate（別のドキュメントへ）       → 読み込みから
+ *   add_stylesheet / mutate_dom       → スタイルから
+ *   set_hover（:hover が変わったとき）  → スタイルから
+ *   set_viewport（幅が変わったとき）    → レイアウトから
+ *   set_viewport（高さだけ）/ set_scroll → 描画だけ
+ *   set_full_page                     → 描画だけ
+ *   set_highlight                     → 描画だけ
+ *   load_snapshot                     → スタイルから（ノードごとの値があればレイアウトから）
+ */
+
+pub struct Engine {
+  options: EngineOptions,
+  loader: Loader, // キャッシュを読み込みの間で共有する（reload で作り直す）
+  document: Option<Source>,
+  stylesheets: Vec<Source>, // <link> の後に指定順で適用する
+  viewport: Rect,
+  full_page: bool, // キャンバスの高さをドキュメント全体の高さにする（options.max_page_height まで）
+  highlight: Option<usize>, // ボックスモデルを重ねて描く要素（ドキュメント順の番号）
+
+  // 各段階の結果。None ならその段階から先をやり直す
+  loaded: Option<LoadedDocument>,
+  applied_stylesheets: usize, // loaded に適用済みの stylesheets の数
+  values: Option<Vec<PropertyMap>>,
+  laid_out: Option<LaidOut>,
+  canvas: Option<Canvas>,
+  stale_canvas: Option<Canvas>, // やり直すことになった前の canvas。次の update で変わったところだけを描き直して使う
+  painted: Option<Page>,        // canvas か stale_canvas に描いたもの
+  timings: Timings,
+  counts: Counts,
+  peak_bytes: usize, // memory_usage の peak_bytes
+}
+
+// レイアウトした結果のうち、描画とその後に必要なもの
+struct LaidOut {
+  display_list: DisplayList,
+  layout: LayoutNode,
+  scrollable_overflow: Rect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoxKind {
+  Block,
+  Inline,
+  InlineBlock,
+  Replaced,
+  Anonymous,
+}
+
+// レイアウトツリーのコピー。LayoutBox は DOM とスタイルツリーを借りているので、描画後も残せる形にしたもの
+#[derive(Debug, Clone)]
+pub struct LayoutNode {
+  pub kind: BoxKind,
+  pub tag_name: Option<String>, // テキストや anonymous なら None
+  pub node_index: Option<usize>, // ボックスを作った DOM ノードのドキュメント順（先行順）の番号
+  pub dimensions: Dimensions,
+  pub children: Vec<LayoutNode>,
+}
+
+impl LayoutNode {
+  // root_node は layout_root を作った DOM のルート
+  pub fn new(layout_root: &LayoutBox, root_node: &Node) -> LayoutNode {
+    let mut indices = HashMap::new();
+    number_nodes(root_node, &mut indices);
+    return LayoutNode::from_box(layout_root, &indices);
+  }
+
+  fn from_box(layout_box: &LayoutBox, indices: &HashMap<*const Node, usize>) -> LayoutNode {
+    let kind = match layout_box.box_type {
+      layout::BlockNode(_) => BoxKind::Block,
+      layout::InlineNode(_) => BoxKind::Inline,
+      layout::InlineBlockNode(_) => BoxKind::InlineBlock,
+      layout::ReplacedNode(_) => BoxKind::Replaced,
+      layout::AnonymousBlock => BoxKind::Anonymous,
+    };
+    let tag_name = layout_box.box_type.style_node().and_then(|style_node| match style_node.node.node_type {
+      NodeType::Element(ref elem) => Some(elem.tag_name.clone()),
+      NodeType::Text(_) | NodeType::Comment(_) => None,
+    });
+    let node_index = layout_box.box_type.style_node().and_then(|style_node| indices.get(&(style_node.node as *const Node)).cloned());
+    return LayoutNode {
+      kind: kind,
+      tag_name: tag_name,
+      node_index: node_index,
+      dimensions: layout_box.dimensions,
+      children: layout_box.children.iter().map(|child| LayoutNode::from_box(child, indices)).collect(),
+    };
+  }
+
+  // ツリー順で最初の、node_indices のどれかのノードのボックス
+  pub fn find_node(&self, node_indices: &[usize]) -> Option<&LayoutNode> {
+    if self.node_index.map_or(false, |index| node_indices.contains(&index)) {
+      return Some(self);
+    }
+    return self.children.iter().filter_map(|child| child.find_node(node_indices)).next();
+  }
+
+  // ドキュメント順で最初の tag_name のボックス
+  pub fn find(&self, tag_name: &str) -> Option<&LayoutNode> {
+    if self.tag_name.as_ref().map_or(false, |name| name == tag_name) {
+      return Some(self);
+    }
+    return self.children.iter().filter_map(|child| child.find(tag_name)).next();
+  }
+}
+
+fn number_nodes(node: &Node, indices: &mut HashMap<*const Node, usize>) {
+  let index = indices.len();
+  indices.insert(node as *const Node, index);
+  for child in &node.children {
+    number_nodes(child, indices);
+  }
+}
+
+pub struct RenderResult {
+  pub canvas: Canvas,
+  pub layout: LayoutNode,
+  pub scrollable_overflow: Rect,
+  pub timings: Timings, // 直近の update でやり直した段階の時間
+  pub counts: Counts,
+}
+
+impl Engine {
+  // ビューポートの初期値は options のもの
+  pub fn new(options: EngineOptions) -> Engine {
+    let viewport = Rect { x: 0.0, y: 0.0, width: options.viewport_width, height: options.viewport_height };
+    return Engine {
+      loader: Loader::new(&options),
+      options: options,
+      document: None,
+      stylesheets: Vec::new(),
+      viewport: viewport,
+      full_page: false,
+      highlight: None,
+      loaded: None,
+      applied_stylesheets: 0,
+      values: None,
+      laid_out: None,
+      canvas: None,
+      stale_canvas: None,
+      painted: None,
+      timings: Timings::default(),
+      counts: Counts::default(),
+      peak_bytes: 0,
+    };
+  }
+
+  // HTML の文字列。相対 URL はカレントディレクトリ基準
+  pub fn html(mut self, html: &str) -> Engine {
+    self.set_html(html);
+    return self;
+  }
+
+  // ファイルパスか URL から読み込むドキュメント
+  pub fn url(mut self, location: &str) -> Engine {
+    self.set_url(location);
+    return self;
+  }
+
+  pub fn stylesheet(mut self, css: &str) -> Engine {
+    self.add_stylesheet(css);
+    return self;
+  }
+
+  pub fn stylesheet_url(mut self, location: &str) -> Engine {
+    self.add_stylesheet_url(location);
+    return self;
+  }
+
+  pub fn viewport(mut self, width: f32, height: f32) -> Engine {
+    self.set_viewport(width, height);
+    return self;
+  }
+
+  // スクロール位置（描画する範囲の左上）
+  pub fn scroll(mut self, x: f32, y: f32) -> Engine {
+    self.set_scroll(x, y);
+    return self;
+  }
+
+  // ビューポートではなくドキュメント全体を描く（フルページのスクリーンショット）
+  pub fn full_page(mut self, full_page: bool) -> Engine {
+    self.set_full_page(full_page);
+    return self;
+  }
+
+  pub fn set_html(&mut self, html: &str) {
+    self.document = Some(Source::Text(html.to_string()));
+    self.loaded = None;
+  }
+
+  pub fn set_url(&mut self, location: &str) {
+    self.document = Some(Source::Url(Url::from_location(location)));
+    self.loaded = None;
+  }
+
+  // 読み込み済みのドキュメントには、次に描画するときに足す（ドキュメントは読み込み直さない）
+  pub fn add_stylesheet(&mut self, css: &str) {
+    self.stylesheets.push(Source::Text(css.to_string()));
+  }
+
+  pub fn add_stylesheet_url(&mut self, location: &str) {
+    self.stylesheets.push(Source::Url(Url::from_location(location)));
+  }
+
+  // 足したスタイルシートをすべて外す。次に描画するときにドキュメントを読み込み直す
+  pub fn clear_stylesheets(&mut self) {
+    self.stylesheets.clear();
+    self.loaded = None;
+  }
+
+  // 読み込んだリソースのキャッシュ（と HTTP のクッキー）を捨てる。今のドキュメントはそのまま
+  pub fn clear_cache(&mut self) {
+    self.loader = Loader::new(&self.options);
+  }
+
+  // パースした結果をスナップショットから読み込む。読み込み直す元がないので reload はできない
+  pub fn load_snapshot(&mut self, snapshot: Snapshot) {
+    self.document = None;
+    self.stylesheets.clear();
+    self.counts.dom_nodes = profile::count_nodes(&snapshot.document.root_node);
+    self.counts.rules = snapshot.document.stylesheet.rules.len();
+    self.set_loaded(snapshot.document);
+    self.values = snapshot.values;
+  }
+
+  // 今のドキュメントのスナップショット。まだ読み込んでいなければ先に読み込む。
+  // with_values ならノードごとの値も入れる（読み込んだ後にスタイルを計算し直さない）
+  pub fn snapshot(&mut self, with_values: bool) -> Result<Vec<u8>, EngineError> {
+    self.load()?;
+    self.apply_stylesheets()?;
+    let document = self.loaded.as_ref().ok_or(EngineError::NoDocument)?;
+    if with_values && self.values.is_none() {
+      let (viewport, _) = document.layout_viewport(self.viewport);
+      self.values = Some(profile::time(&mut self.timings.style, || style::compute_values_with_viewport(&document.root_node, &document.stylesheet, Some(viewport))));
+      self.laid_out = None;
+      self.counts.dom_nodes = profile::count_nodes(&document.root_node);
+      self.counts.rules = document.stylesheet.rules.len();
+    }
+    let values = if with_values { self.values.as_ref().map(|values| &values[..]) } else { None };
+    return Ok(snapshot::encode(document, values));
+  }
+
+  // ドキュメントを書き換える。まだ読み込んでいなければ先に読み込む。
+  // iframe の中身は読み込み直さない
+  pub fn mutate_dom<F: FnOnce(&mut Node)>(&mut self, mutate: F) -> Result<(), EngineError> {
+    self.load()?;
+    if let Some(ref mut document) = self.loaded {
+      mutate(&mut document.root_node);
+    }
+    self.values = None;
+    return Ok(());
+  }
+
+  pub fn set_viewport(&mut self, width: f32, height: f32) {
+    // @media の width や height で値が変わるので、大きさが変われば値から計算し直す
+    if width != self.viewport.width || height != self.viewport.height {
+      self.values = None;
+      self.laid_out = None;
+      self.invalidate_canvas();
+    }
+    self.viewport.width = width;
+    self.viewport.height = height;
+  }
+
+  pub fn set_scroll(&mut self, x: f32, y: f32) {
+    if x != self.viewport.x || y != self.viewport.y {
+      self.invalidate_canvas();
+    }
+    self.viewport.x = x;
+    self.viewport.y = y;
+  }
+
+  // 縦のスクロール位置は無視して、ページの先頭から描く
+  pub fn set_full_page(&mut self, full_page: bool) {
+    if full_page != self.full_page {
+      self.invalidate_canvas();
+    }
+    self.full_page = full_page;
+  }
+
+  // ドキュメント順の番号が node_index の要素とその祖先を :hover にする（None ですべて外す）。
+  // :hover が変わった要素があれば true で、次の update でスタイルから計算し直す
+  pub fn set_hover(&mut self, node_index: Option<usize>) -> bool {
+    let changed = match self.loaded {
+      Some(ref mut document) => document.root_node.set_hover(node_index),
+      None => false,
+    };
+    if changed {
+      self.values = None;
+    }
+    return changed;
+  }
+
+  // ドキュメント順の番号が node_index の要素のボックスの content、padding、border、margin をページの上に色を付けて描く
+  // （インスペクターのハイライト）。None で消す
+  pub fn set_highlight(&mut self, node_index: Option<usize>) {
+    if node_index != self.highlight {
+      self.invalidate_canvas();
+    }
+    self.highlight = node_index;
+  }
+
+  // ファイルが変わったときなどに、キャッシュを捨てて読み込み直す。
+  // 失敗したら前のドキュメントのまま
+  pub fn reload(&mut self) -> Result<(), EngineError> {
+    let loader = Loader::new(&self.options);
+    let input = self.document.as_ref().ok_or(EngineError::NoDocument)?;
+    let document = load_with(input, &self.stylesheets, &self.options, &loader, 0)?;
+    self.loader = loader;
+    self.set_loaded(document);
+    return Ok(());
+  }
+
+  // url のドキュメントを読み込んで今のドキュメントにする（リンクをたどる）。足したスタイルシートはそのまま当てる。
+  // 今のドキュメントと fragment だけが違うなら読み込み直さない。失敗したら前のドキュメントのまま
+  pub fn navigate(&mut self, url: &Url) -> Result<(), EngineError> {
+    let without_fragment = |url: &Url| Url { fragment: None, ..url.clone() };
+    if let Some(ref document) = self.loaded {
+      if document.sources.first().map(&without_fragment) == Some(without_fragment(url)) {
+        return Ok(());
+      }
+    }
+    let input = Source::Url(url.clone());
+    let document = load_with(&input, &self.stylesheets, &self.options, &self.loader, 0)?;
+    self.timings.add(&document.timings);
+    self.document = Some(input);
+    self.set_loaded(document);
+    return Ok(());
+  }
+
+  // 読み込んだドキュメント（まだなら None）
+  pub fn document(&self) -> Option<&LoadedDocument> {
+    return self.loaded.as_ref();
+  }
+
+  pub fn viewport_rect(&self) -> Rect {
+    return self.viewport;
+  }
+
+  fn set_loaded(&mut self, document: LoadedDocument) {
+    self.loaded = Some(document);
+    self.applied_stylesheets = self.stylesheets.len();
+    self.values = None;
+  }
+
+  fn load(&mut self) -> Result<(), EngineError> {
+    if self.loaded.is_some() {
+      return Ok(());
+    }
+    let input = self.document.as_ref().ok_or(EngineError::NoDocument)?;
+    let document = load_with(input, &self.stylesheets, &self.options, &self.loader, 0)?;
+    self.timings.add(&document.timings);
+    self.set_loaded(document);
+    return Ok(());
+  }
+
+  // 足されたスタイルシートを読み込み済みのドキュメントに適用する
+  fn apply_stylesheets(&mut self) -> Result<(), EngineError> {
+    let document = match self.loaded {
+      Some(ref mut document) => document,
+      None => return Ok(()),
+    };
+    for input in &self.stylesheets[self.applied_stylesheets..] {
+      let sheet = load_stylesheet(input, &document.base_url, &self.options, &self.loader, &mut document.sources, &mut self.timings)?;
+      document.stylesheet.append(sheet);
+      self.applied_stylesheets += 1;
+      self.values = None;
+    }
+    return Ok(());
+  }
+
+  // 必要な段階だけをやり直して、今の状態を描画した結果にする
+  pub fn update(&mut self) -> Result<(), EngineError> {
+    self.timings = Timings::default();
+
+    self.load()?;
+    self.apply_stylesheets()?;
+    let document = match self.loaded {
+      Some(ref document) => document,
+      None => return Err(EngineError::NoDocument),
+    };
+
+    if self.values.is_none() {
+      let (viewport, _) = document.layout_viewport(self.viewport);
+      self.values = Some(profile::time(&mut self.timings.style, || style::compute_values_with_viewport(&document.root_node, &document.stylesheet, Some(viewport))));
+      self.laid_out = None;
+      self.counts.dom_nodes = profile::count_nodes(&document.root_node);
+      self.counts.rules = document.stylesheet.rules.len();
+    }
+
+    // レイアウトの間だけ存在するスタイルツリーとレイアウトツリーの量
+    let mut transient_bytes = 0;
+    if self.laid_out.is_none() {
+      let values = self.values.as_ref().map_or(&[][..], |values| &values[..]);
+      let (viewport, _) = document.layout_viewport(self.viewport);
+      let style_root = profile::time(&mut self.timings.layout, || style::style_tree_from_values(&document.root_node, values));
+      let layout_root = profile::time(&mut self.timings.layout, || layout::layout_tree_with(&style_root, initial_containing_block(viewport), document.fonts, &document.images))?;
+      let display_list = profile::time(&mut self.timings.display_list, || frame::build_display_list(&layout_root, &document.root_node, &document.frames, document.parallelism, &document.render));
+      self.counts.layout_boxes = profile::count_boxes(&layout_root);
+      transient_bytes = memory::styled(&style_root).bytes + memory::layout_boxes(&layout_root).bytes;
+      self.counts.display_items = display_list.len();
+      self.laid_out = Some(LaidOut {
+        display_list: display_list,
+        layout: LayoutNode::new(&layout_root, &document.root_node),
+        scrollable_overflow: layout_root.scrollable_overflow(),
+      });
+      self.stale_canvas = self.canvas.take().or(self.stale_canvas.take());
+    }
+
+    if self.canvas.is_none() {
+      if let Some(ref laid_out) = self.laid_out {
+        let (viewport, scale) = document.layout_viewport(self.viewport);
+        let bounds = if self.full_page { page_bounds(viewport, laid_out.scrollable_overflow, scale, self.options.max_page_height) } else { scrolled_bounds(viewport, laid_out.scrollable_overflow) };
+        let overlay = self.highlight.and_then(|index| laid_out.layout.find_node(&[index])).map(|node| paint::box_model_overlay(node.dimensions));
+        let page = Page { display_list: laid_out.display_list.iter().cloned().chain(overlay.into_iter().flatten()).collect(), bounds: bounds, scale: scale };
+        // 前に描いたキャンバスがあれば、スクロールした分をずらして、変わったところだけを描き直す
+        let mut repainted = None;
+        if let (Some(mut canvas), Some(previous)) = (self.stale_canvas.take(), self.painted.take()) {
+          if profile::time(&mut self.timings.raster, || page.repaint(&mut canvas, &previous))? {
+            repainted = Some(canvas);
+          }
+        }
+        let canvas = match repainted {
+          Some(canvas) => canvas,
+          None => profile::time(&mut self.timings.raster, || page.paint_with(document.tiling(), &document.render))?,
+        };
+        self.counts.pixels = canvas.pixels.len();
+        self.canvas = Some(canvas);
+        self.painted = Some(page);
+      }
+    }
+    self.peak_bytes = self.peak_bytes.max(self.memory_usage().total_bytes() + transient_bytes);
+    return Ok(());
+  }
+
+  // 次の update で描き直す。今のキャンバスは、変わったところだけを描き直すために残しておく
+  fn invalidate_canvas(&mut self) {
+    self.stale_canvas = self.canvas.take().or(self.stale_canvas.take());
+  }
+
+  // selector に一致する最初の要素（ボックスを持つもの）のボーダーボックス
+  pub fn element_rect(&mut self, selector: &str) -> Result<Rect, EngineError> {
+    let selectors = css::parse_selectors(selector)?;
+    self.update()?;
+    let matched = match self.loaded {
+      Some(ref document) => style::query_selector_all(&document.root_node, &selectors),
+      None => Vec::new(),
+    };
+    return self
+      .layout()
+      .and_then(|layout| layout.find_node(&matched))
+      .map(|node| node.dimensions.border_box())
+      .ok_or_else(|| EngineError::NoMatchingElement(selector.to_string()));
+  }
+
+  // selector に一致する要素のボーダーボックスだけを描画する（スクロール位置は関係ない）
+  pub fn render_element(&mut self, selector: &str) -> Result<Canvas, EngineError> {
+    let rect = self.element_rect(selector)?;
+    return match (self.laid_out.as_ref(), self.loaded.as_ref()) {
+      (Some(laid_out), Some(document)) => Ok(paint::paint_display_list_with(&laid_out.display_list, rect, document.layout_viewport(self.viewport).1, document.tiling(), &document.render)?),
+      _ => Err(EngineError::NoDocument),
+    };
+  }
+
+  // キャンバスの (x, y) のピクセルにある、いちばん手前の要素のドキュメント順の番号（テキストならそれを含む要素）。
+  // 何もなければ None。スタイルの値からレイアウトし直して layout::hit_test で調べる
+  pub fn element_at(&mut self, x: f32, y: f32) -> Result<Option<usize>, EngineError> {
+    self.update()?;
+    let (document, values, laid_out) = match (self.loaded.as_ref(), self.values.as_ref(), self.laid_out.as_ref()) {
+      (Some(document), Some(values), Some(laid_out)) => (document, values, laid_out),
+      _ => return Err(EngineError::NoDocument),
+    };
+    let (viewport, scale) = document.layout_viewport(self.viewport);
+    let bounds = if self.full_page { page_bounds(viewport, laid_out.scrollable_overflow, scale, self.options.max_page_height) } else { scrolled_bounds(viewport, laid_out.scrollable_overflow) };
+    let style_root = style::style_tree_from_values(&document.root_node, values);
+    let layout_root = layout::layout_tree_with(&style_root, initial_containing_block(viewport), document.fonts, &document.images)?;
+
+    let mut indices = HashMap::new();
+    number_nodes(&document.root_node, &mut indices);
+    // 疑似要素は DOM にないので、それを作った要素（後ろに続く祖先）にする
+    let hit = layout::hit_test(&layout_root, bounds.x + x / scale, bounds.y + y / scale).into_iter().find(|style_node| match style_node.node.node_type {
+      NodeType::Element(_) => style_node.pseudo_element().is_none(),
+      NodeType::Text(_) | NodeType::Comment(_) => false,
+    });
+    return Ok(hit.and_then(|style_node| indices.get(&(style_node.node as *const Node)).cloned()));
+  }
+
+  // キャンバスの (x, y) のピクセルにあるリンク（<a href> かその中）の、ドキュメントの基準で解決した URL。なければ None
+  pub fn link_at(&mut self, x: f32, y: f32) -> Result<Option<Url>, EngineError> {
+    let index = match self.element_at(x, y)? {
+      Some(index) => index,
+      None => return Ok(None),
+    };
+    let document = self.loaded.as_ref().ok_or(EngineError::NoDocument)?;
+    return Ok(document.root_node.link_href(index).and_then(|href| document.base_url.join(href)));
+  }
+
+  // 今のドキュメントのアクセシビリティツリー
+  pub fn accessibility_tree(&mut self) -> Result<AccessibleNode, EngineError> {
+    self.update()?;
+    return match (self.loaded.as_ref(), self.values.as_ref()) {
+      (Some(document), Some(values)) => Ok(a11y::accessibility_tree(&style::style_tree_from_values(&document.root_node, values))),
+      _ => Err(EngineError::NoDocument),
+    };
+  }
+
+  // 直近の update の結果（まだ描画していなければ None）
+  pub fn canvas(&self) -> Option<&Canvas> {
+    return self.canvas.as_ref();
+  }
+
+  pub fn layout(&self) -> Option<&LayoutNode> {
+    return self.laid_out.as_ref().map(|laid_out| &laid_out.layout);
+  }
+
+  pub fn scrollable_overflow(&self) -> Rect {
+    return self.laid_out.as_ref().map_or(Rect::default(), |laid_out| laid_out.scrollable_overflow);
+  }
+
+  pub fn timings(&self) -> Timings {
+    return self.timings;
+  }
+
+  // 今持っている DOM、値、レイアウト、キャンバスのおおよそのメモリ量。
+  // peak_bytes はこの Engine で update したときの最大
+  pub fn memory_usage(&self) -> MemoryUsage {
+    let mut usage = MemoryUsage {
+      dom: self.loaded.as_ref().map_or(memory::Usage::default(), memory::document_dom),
+      styles: self.values.as_ref().map_or(memory::Usage::default(), |values| memory::property_maps(values)),
+      layout: self.laid_out.as_ref().map_or(memory::Usage::default(), |laid_out| memory::layout_nodes(&laid_out.layout)),
+      display_list: self.laid_out.as_ref().map_or(memory::Usage::default(), |laid_out| memory::display_commands(&laid_out.display_list)),
+      canvas: self.canvas.as_ref().map_or(memory::Usage::default(), memory::pixels),
+      peak_bytes: self.peak_bytes,
+    };
+    usage.peak_bytes = usage.peak_bytes.max(usage.total_bytes());
+    return usage;
+  }
+
+  // 今の状態のノードやボックスの数
+  pub fn counts(&self) -> Counts {
+    return self.counts;
+  }
+
+  // update して、結果をコピーして返す
+  pub fn render(&mut self) -> Result<RenderResult, EngineError> {
+    self.update()?;
+    return match (self.canvas.as_ref(), self.laid_out.as_ref()) {
+      (Some(canvas), Some(laid_out)) => Ok(RenderResult {
+        canvas: canvas.clone(),
+        layout: laid_out.layout.clone(),
+        scrollable_overflow: laid_out.scrollable_overflow,
+        timings: self.timings,
+        counts: self.counts,
+      }),
+      _ => Err(EngineError::NoDocument),
+    };
+  }
+}
@@ -0,0 +1,44 @@
+use css::CssError;
+use html::HtmlError;
+use layout::LayoutError;
+use loader::IoError;
+use options::ConfigError;
+use paint::PaintError;
+use snapshot::SnapshotError;
+use thiserror::Error;
+#[cfg(feature = "viewer")]
+use viewer::ViewerError;
+#[cfg(feature = "watch")]
+use watch::WatchError;
+
+/**
+ * パイプライン全体のエラー。各段階のエラーをまとめる
+ */
+
+#[derive(Debug, Error)]
+pub enum EngineError {
+  #[error("HTML parse error: {0}")]
+  Html(#[from] HtmlError),
+  #[error("CSS parse error: {0}")]
+  Css(#[from] CssError),
+  #[error("no document to render")]
+  NoDocument,
+  #[error("no element with a box matches {0}")]
+  NoMatchingElement(String),
+  #[error("layout error: {0}")]
+  Layout(#[from] LayoutError),
+  #[error("paint error: {0}")]
+  Paint(#[from] PaintError),
+  #[error(transparent)]
+  Io(#[from] IoError),
+  #[error(transparent)]
+  Config(#[from] ConfigError),
+  #[error("snapshot error: {0}")]
+  Snapshot(#[from] SnapshotError),
+  #[cfg(feature = "viewer")]
+  #[error("viewer error: {0}")]
+  Viewer(#[from] ViewerError),
+  #[cfg(feature = "watch")]
+  #[error(transparent)]
+  Watch(#[from] WatchError),
+}
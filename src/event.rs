@@ -0,0 +1,24 @@
+use engine::Engine;
+use error::EngineError;
+
+/**
+ * ビューアの入力のイベントをドキュメントに届けるところ。
+ *
+ * ポインターが動いたら、その位置をヒットテストして（Engine::element_at）いちばん手前の要素とその祖先を :hover にし、
+ * ウィンドウから出たら :hover を外す。:hover が変わった要素があれば、次の Engine::update でスタイルから計算し直して描き直す
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+  PointerMove { x: f32, y: f32 }, // キャンバスの中の位置（ピクセル）
+  PointerLeave,
+}
+
+// event をドキュメントの状態に反映する。描き直しが要るなら true
+pub fn dispatch(engine: &mut Engine, event: Event) -> Result<bool, EngineError> {
+  let hover = match event {
+    Event::PointerMove { x, y } => engine.element_at(x, y)?,
+    Event::PointerLeave => None,
+  };
+  return Ok(engine.set_hover(hover));
+}
@@ -0,0 +1,239 @@
+use css;
+use dom;
+use layout::Rect;
+use options::EngineOptions;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+use std::slice;
+use {parse_css, parse_html, render, user_agent_stylesheet};
+
+/**
+ * C から呼ぶための API。ヘッダは include/browser_engine_suburi.h（cbindgen で生成）
+ *
+ *   SuburiEngine *engine = suburi_engine_new();
+ *   suburi_engine_load_html(engine, "<html>...</html>");
+ *   suburi_engine_load_css(engine, "div { ... }");
+ *   suburi_engine_set_viewport(engine, 800, 600);
+ *   suburi_engine_set_scroll(engine, 0, 0);
+ *   suburi_engine_render(engine, buffer, 800 * 600 * 4);
+ *   suburi_engine_free(engine);
+ */
+
+// 各関数の戻り値
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SuburiStatus {
+  Ok = 0,
+  NullPointer = 1,
+  InvalidUtf8 = 2,
+  ParseError = 3,
+  NoDocument = 4,
+  BufferTooSmall = 5,
+  RenderError = 6,
+}
+
+// C 側からは中身の見えないハンドルとして扱う
+pub struct SuburiEngine {
+  options: EngineOptions,
+  document: Option<dom::Node>,
+  stylesheet: css::StyleSheet,
+  width: u32,
+  height: u32,
+  scroll_x: f32,
+  scroll_y: f32,
+  last_error: Option<CString>,
+}
+
+impl SuburiEngine {
+  fn fail(&mut self, status: SuburiStatus, message: String) -> SuburiStatus {
+    // メッセージに NUL が入っていると CString にできないので取り除く
+    self.last_error = CString::new(message.replace('\0', "")).ok();
+    return status;
+  }
+}
+
+#[no_mangle]
+pub extern "C" fn suburi_engine_new() -> *mut SuburiEngine {
+  let options = EngineOptions::default();
+  let engine = SuburiEngine {
+    document: None,
+    stylesheet: css::StyleSheet::default(),
+    width: options.viewport_width as u32,
+    height: options.viewport_height as u32,
+    scroll_x: 0.0,
+    scroll_y: 0.0,
+    last_error: None,
+    options: options,
+  };
+  return Box::into_raw(Box::new(engine));
+}
+
+/// suburi_engine_new で作ったものを解放する。NULL なら何もしない
+///
+/// # Safety
+///
+/// engine は NULL か、suburi_engine_new が返してまだ解放していないもの。
+/// 解放した後は、engine も suburi_engine_last_error が返した文字列も使ってはいけない
+#[no_mangle]
+pub unsafe extern "C" fn suburi_engine_free(engine: *mut SuburiEngine) {
+  if !engine.is_null() {
+    drop(Box::from_raw(engine));
+  }
+}
+
+/// NUL 終端の UTF-8 文字列の HTML をパースして、描画するドキュメントにする
+///
+/// # Safety
+///
+/// engine は NULL か、suburi_engine_new が返してまだ suburi_engine_free していないもの。
+/// 同じ engine を複数のスレッドから同時に使ってはいけない。
+/// source は NULL か、NUL で終わる文字列を指すもの（呼んでいる間だけ読む）
+#[no_mangle]
+pub unsafe extern "C" fn suburi_engine_load_html(engine: *mut SuburiEngine, source: *const c_char) -> SuburiStatus {
+  let engine = match engine.as_mut() {
+    Some(engine) => engine,
+    None => return SuburiStatus::NullPointer,
+  };
+  let source = match read_str(engine, source) {
+    Ok(source) => source,
+    Err(status) => return status,
+  };
+  return match parse_html(source, "HTML", &engine.options) {
+    Ok(node) => {
+      engine.document = Some(node);
+      SuburiStatus::Ok
+    }
+    Err(error) => engine.fail(SuburiStatus::ParseError, error.to_string()),
+  };
+}
+
+/// NUL 終端の UTF-8 文字列の CSS をパースして、スタイルシートを置き換える（組み込みのスタイルシートの後に適用する）
+///
+/// # Safety
+///
+/// engine は NULL か、suburi_engine_new が返してまだ suburi_engine_free していないもの。
+/// 同じ engine を複数のスレッドから同時に使ってはいけない。
+/// source は NULL か、NUL で終わる文字列を指すもの（呼んでいる間だけ読む）
+#[no_mangle]
+pub unsafe extern "C" fn suburi_engine_load_css(engine: *mut SuburiEngine, source: *const c_char) -> SuburiStatus {
+  let engine = match engine.as_mut() {
+    Some(engine) => engine,
+    None => return SuburiStatus::NullPointer,
+  };
+  let source = match read_str(engine, source) {
+    Ok(source) => source,
+    Err(status) => return status,
+  };
+  let result = parse_css(source, "CSS", &engine.options).and_then(|mut sheet| {
+    sheet.retain_media(&engine.options.media);
+    let mut stylesheet = user_agent_stylesheet(&engine.options)?;
+    stylesheet.append(sheet);
+    Ok(stylesheet)
+  });
+  return match result {
+    Ok(stylesheet) => {
+      engine.stylesheet = stylesheet;
+      SuburiStatus::Ok
+    }
+    Err(error) => engine.fail(SuburiStatus::ParseError, error.to_string()),
+  };
+}
+
+/// 描画するビューポートの大きさ（px）
+///
+/// # Safety
+///
+/// engine は NULL か、suburi_engine_new が返してまだ suburi_engine_free していないもの。
+/// 同じ engine を複数のスレッドから同時に使ってはいけない
+#[no_mangle]
+pub unsafe extern "C" fn suburi_engine_set_viewport(engine: *mut SuburiEngine, width: u32, height: u32) -> SuburiStatus {
+  let engine = match engine.as_mut() {
+    Some(engine) => engine,
+    None => return SuburiStatus::NullPointer,
+  };
+  engine.width = width;
+  engine.height = height;
+  return SuburiStatus::Ok;
+}
+
+/// 描画するスクロール位置（px）。ドキュメントの端より先にはスクロールしない
+///
+/// # Safety
+///
+/// engine は NULL か、suburi_engine_new が返してまだ suburi_engine_free していないもの。
+/// 同じ engine を複数のスレッドから同時に使ってはいけない
+#[no_mangle]
+pub unsafe extern "C" fn suburi_engine_set_scroll(engine: *mut SuburiEngine, x: f32, y: f32) -> SuburiStatus {
+  let engine = match engine.as_mut() {
+    Some(engine) => engine,
+    None => return SuburiStatus::NullPointer,
+  };
+  engine.scroll_x = x;
+  engine.scroll_y = y;
+  return SuburiStatus::Ok;
+}
+
+/// ビューポートの大きさで描画して、呼び出し側が用意したバッファに RGBA で書き込む。
+/// バッファは width * height * 4 バイト以上必要
+///
+/// # Safety
+///
+/// engine は NULL か、suburi_engine_new が返してまだ suburi_engine_free していないもの。
+/// 同じ engine を複数のスレッドから同時に使ってはいけない。
+/// buffer は NULL か、書き込める buffer_len バイトの領域を指すもの（呼んでいる間だけ書く）
+#[no_mangle]
+pub unsafe extern "C" fn suburi_engine_render(engine: *mut SuburiEngine, buffer: *mut u8, buffer_len: usize) -> SuburiStatus {
+  let engine = match engine.as_mut() {
+    Some(engine) => engine,
+    None => return SuburiStatus::NullPointer,
+  };
+  if buffer.is_null() {
+    return engine.fail(SuburiStatus::NullPointer, "buffer is NULL".to_string());
+  }
+  let required = engine.width as usize * engine.height as usize * 4;
+  if buffer_len < required {
+    let message = format!("buffer is too small: {} bytes required, {} given", required, buffer_len);
+    return engine.fail(SuburiStatus::BufferTooSmall, message);
+  }
+
+  let viewport = Rect { x: engine.scroll_x, y: engine.scroll_y, width: engine.width as f32, height: engine.height as f32 };
+  let result = match engine.document {
+    Some(ref document) => render(document, &engine.stylesheet, viewport),
+    None => return engine.fail(SuburiStatus::NoDocument, "no HTML has been loaded".to_string()),
+  };
+  return match result {
+    Ok(rendered) => {
+      let pixels = rendered.canvas.to_rgba_bytes();
+      slice::from_raw_parts_mut(buffer, pixels.len()).copy_from_slice(&pixels);
+      SuburiStatus::Ok
+    }
+    Err(error) => engine.fail(SuburiStatus::RenderError, error.to_string()),
+  };
+}
+
+/// 最後に失敗したときのメッセージ。なければ NULL。
+/// 返した文字列は、次にこのエンジンで失敗するか解放するまで有効
+///
+/// # Safety
+///
+/// engine は NULL か、suburi_engine_new が返してまだ suburi_engine_free していないもの。
+/// 同じ engine を複数のスレッドから同時に使ってはいけない。
+/// 返した文字列は書き換えたり解放したりしてはいけない
+#[no_mangle]
+pub unsafe extern "C" fn suburi_engine_last_error(engine: *const SuburiEngine) -> *const c_char {
+  return match engine.as_ref().and_then(|engine| engine.last_error.as_ref()) {
+    Some(message) => message.as_ptr(),
+    None => ptr::null(),
+  };
+}
+
+unsafe fn read_str(engine: &mut SuburiEngine, source: *const c_char) -> Result<String, SuburiStatus> {
+  if source.is_null() {
+    return Err(engine.fail(SuburiStatus::NullPointer, "source is NULL".to_string()));
+  }
+  return match CStr::from_ptr(source).to_str() {
+    Ok(source) => Ok(source.to_string()),
+    Err(error) => Err(engine.fail(SuburiStatus::InvalidUtf8, error.to_string())),
+  };
+}
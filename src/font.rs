@@ -0,0 +1,466 @@
+use ab_glyph::{point, Font, FontArc, GlyphId, OutlineCurve, Point, PxScale, ScaleFont};
+use layout::{FontFace, FontMetrics, Rect};
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use rustybuzz;
+use shaping;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use text;
+use thiserror::Error;
+
+/**
+ * フォントの読み込みと字形の配置。
+ *
+ * FontContext は TrueType/OpenType のフォントを 1 つ持ち、layout::FontMetrics として文字の幅と行の高さを測る。
+ * レイアウトは行に置いた文字列ごとに glyph_run で字形の番号と位置（GlyphRun）を作り、キャンバスはそのアウトラインを
+ * アンチエイリアスして color で重ねる。字形の番号と位置は shaping で決める（合字、カーニング、結合文字、右から左の文字）ので、
+ * 幅もその送り幅の合計で測る。
+ * フォントにない文字（日本語など）は固定幅の Monospace で測り、字形の代わりに矩形を描く。
+ * フォントが 1 つも読み込めなければ、すべて Monospace と矩形になる（shared は最初に呼んだときにシステムのフォントを探す）。
+ * EngineOptions::deterministic のときは、システムのフォントを使わずに builtin（Monospace と矩形）で測って描く
+ *
+ * システムのフォントは SYSTEM_FACES の字体（family、weight、italic）ごとに持ち、FontMetrics::select で font-family の順に
+ * 名前（serif などの総称は GENERIC_FAMILIES の名前）が合うものを探して、その中で font-style と font-weight のいちばん近いものを選ぶ。
+ * どの名前も合わなければ、既定のフォントの family から選ぶ。ファイルは初めて選んだときに読み込む
+ */
+
+// ラスタライズする字形の一辺の上限（px）。ab_glyph は字形全体の被覆率のバッファを確保するので、これより大きいものは描かない
+const MAX_GLYPH_SIZE: f32 = 4096.0;
+
+// システムのフォントを探すディレクトリ
+const SYSTEM_FONT_DIRS: &[&str] = &[
+  "/usr/share/fonts/truetype/dejavu",
+  "/usr/share/fonts/TTF",
+  "/usr/share/fonts/dejavu",
+  "/usr/share/fonts/truetype/liberation",
+  "/usr/share/fonts/liberation",
+  "/usr/share/fonts/noto",
+  "/usr/share/fonts/truetype/noto",
+  "/System/Library/Fonts/Supplemental",
+  "/Library/Fonts",
+  "C:\\Windows\\Fonts",
+];
+
+// システムのフォントのファイル名と、その family、weight、italic
+const SYSTEM_FACES: &[(&str, &str, f32, bool)] = &[
+  ("DejaVuSans.ttf", "DejaVu Sans", 400.0, false),
+  ("DejaVuSans-Bold.ttf", "DejaVu Sans", 700.0, false),
+  ("DejaVuSans-Oblique.ttf", "DejaVu Sans", 400.0, true),
+  ("DejaVuSans-BoldOblique.ttf", "DejaVu Sans", 700.0, true),
+  ("DejaVuSans-ExtraLight.ttf", "DejaVu Sans", 200.0, false),
+  ("DejaVuSerif.ttf", "DejaVu Serif", 400.0, false),
+  ("DejaVuSerif-Bold.ttf", "DejaVu Serif", 700.0, false),
+  ("DejaVuSerif-Italic.ttf", "DejaVu Serif", 400.0, true),
+  ("DejaVuSerif-BoldItalic.ttf", "DejaVu Serif", 700.0, true),
+  ("DejaVuSansMono.ttf", "DejaVu Sans Mono", 400.0, false),
+  ("DejaVuSansMono-Bold.ttf", "DejaVu Sans Mono", 700.0, false),
+  ("DejaVuSansMono-Oblique.ttf", "DejaVu Sans Mono", 400.0, true),
+  ("DejaVuSansMono-BoldOblique.ttf", "DejaVu Sans Mono", 700.0, true),
+  ("LiberationSans-Regular.ttf", "Liberation Sans", 400.0, false),
+  ("LiberationSans-Bold.ttf", "Liberation Sans", 700.0, false),
+  ("LiberationSans-Italic.ttf", "Liberation Sans", 400.0, true),
+  ("LiberationSans-BoldItalic.ttf", "Liberation Sans", 700.0, true),
+  ("LiberationSerif-Regular.ttf", "Liberation Serif", 400.0, false),
+  ("LiberationSerif-Bold.ttf", "Liberation Serif", 700.0, false),
+  ("LiberationSerif-Italic.ttf", "Liberation Serif", 400.0, true),
+  ("LiberationSerif-BoldItalic.ttf", "Liberation Serif", 700.0, true),
+  ("LiberationMono-Regular.ttf", "Liberation Mono", 400.0, false),
+  ("LiberationMono-Bold.ttf", "Liberation Mono", 700.0, false),
+  ("NotoSans-Regular.ttf", "Noto Sans", 400.0, false),
+  ("NotoSans-Bold.ttf", "Noto Sans", 700.0, false),
+  ("NotoSans-Italic.ttf", "Noto Sans", 400.0, true),
+  ("NotoSerif-Regular.ttf", "Noto Serif", 400.0, false),
+  ("NotoSerif-Bold.ttf", "Noto Serif", 700.0, false),
+  ("NotoSansMono-Regular.ttf", "Noto Sans Mono", 400.0, false),
+  ("Arial.ttf", "Arial", 400.0, false),
+  ("Arial Bold.ttf", "Arial", 700.0, false), // macOS
+  ("arialbd.ttf", "Arial", 700.0, false),    // Windows
+  ("Arial Italic.ttf", "Arial", 400.0, true),
+  ("ariali.ttf", "Arial", 400.0, true),
+  ("Times New Roman.ttf", "Times New Roman", 400.0, false),
+  ("times.ttf", "Times New Roman", 400.0, false),
+  ("Courier New.ttf", "Courier New", 400.0, false),
+  ("cour.ttf", "Courier New", 400.0, false),
+];
+
+// 総称の font-family に当てる名前（先にあるものから探す）。sans-serif の最初に見つかったものが既定のフォントになる
+const GENERIC_FAMILIES: &[(&str, &[&str])] = &[
+  ("sans-serif", &["DejaVu Sans", "Liberation Sans", "Noto Sans", "Arial"]),
+  ("serif", &["DejaVu Serif", "Liberation Serif", "Noto Serif", "Times New Roman"]),
+  ("monospace", &["DejaVu Sans Mono", "Liberation Mono", "Noto Sans Mono", "Courier New"]),
+];
+
+#[derive(Debug, Error)]
+pub enum FontError {
+  #[error("failed to read font {path}: {source}")]
+  Read { path: String, source: io::Error },
+  #[error("invalid font data")]
+  Invalid,
+}
+
+// 字形。x は文字列の左端からの位置、y はベースラインから下向きのずれ（結合文字など）。
+// id が 0 ならフォントにない文字で、advance の幅の矩形を描く
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Glyph {
+  pub id: u16,
+  pub x: f32,
+  pub y: f32,
+  pub advance: f32,
+}
+
+// 行に置いた文字列の字形。baseline は行の上端からベースラインまで
+#[derive(Debug, Clone)]
+pub struct GlyphRun {
+  pub face: FontArc,
+  pub baseline: f32,
+  pub glyphs: Vec<Glyph>,
+}
+
+// 同じ face かは比べられないので、位置と字形だけを比べる（face は同じ文字列とスタイルから選ばれる）
+impl PartialEq for GlyphRun {
+  fn eq(&self, other: &GlyphRun) -> bool {
+    return self.baseline == other.baseline && self.glyphs == other.glyphs;
+  }
+}
+
+impl GlyphRun {
+  // 原点を中心に factor 倍する（字形の大きさは描くときの font_size で決まる）
+  pub fn scaled(&self, factor: f32) -> GlyphRun {
+    let glyphs = self.glyphs.iter().map(|glyph| Glyph { id: glyph.id, x: glyph.x * factor, y: glyph.y * factor, advance: glyph.advance * factor }).collect();
+    return GlyphRun { face: self.face.clone(), baseline: self.baseline * factor, glyphs: glyphs };
+  }
+}
+
+pub struct FontContext {
+  face: Option<FontArc>,        // None なら Monospace と同じ
+  shaper: Option<Shaper>,       // face の shaping に使うもの（作れなければ文字ごとに字形を選ぶ）
+  family: Option<&'static str>, // face の family（システムのフォントのとき）
+  faces: Vec<Face>,             // select で選べるフォント
+}
+
+// shaping に使うフォント
+#[derive(Clone)]
+enum Shaper {
+  Static(rustybuzz::Face<'static>), // システムのフォント。ファイルの中身はプロセスが終わるまで持つので、Face を作っておける
+  Owned(Arc<Vec<u8>>),              // from_bytes に渡されたもの。形を作るたびに Face を作る
+}
+
+impl Shaper {
+  fn with_face<R, F: FnOnce(&rustybuzz::Face) -> R>(&self, f: F) -> Option<R> {
+    return match *self {
+      Shaper::Static(ref face) => Some(f(face)),
+      Shaper::Owned(ref data) => rustybuzz::Face::from_slice(data, 0).map(|face| f(&face)),
+    };
+  }
+}
+
+// システムのフォントの 1 つの字体
+struct Face {
+  family: &'static str,
+  weight: f32,
+  italic: bool,
+  path: PathBuf,
+  context: OnceLock<Option<FontContext>>, // 初めて選んだときに読み込む（読み込めなければ None）
+}
+
+impl Face {
+  fn context(&self) -> Option<&FontContext> {
+    return self
+      .context
+      .get_or_init(|| match FontContext::load_static(&self.path.to_string_lossy()) {
+        Ok(context) => Some(context),
+        Err(e) => {
+          warn!("Skipped font {}: {}", self.path.display(), e);
+          None
+        }
+      })
+      .as_ref();
+  }
+
+  // face とどれだけ違うか（小さいほど近い）。font-style が合うものを先に、太さは CSS のフォントの選び方と同じ向きで近いもの
+  fn distance(&self, face: &FontFace) -> (bool, f32) {
+    let desired = face.weight;
+    // 400 から 500 なら 500 までは太い方、それ以外は 500 より上なら太い方、400 より下なら細い方を先に探す
+    let heavier_first = desired > 500.0 || (desired >= 400.0 && self.weight <= 500.0);
+    let difference = (self.weight - desired).abs();
+    let weight = if self.weight == desired || (self.weight > desired) == heavier_first { difference } else { 1000.0 + difference };
+    return (self.italic != face.italic, weight);
+  }
+}
+
+impl FontContext {
+  // 字形を持たない（Monospace で測って矩形を描く）もの
+  pub fn monospace() -> FontContext {
+    return FontContext { face: None, shaper: None, family: None, faces: Vec::new() };
+  }
+
+  pub fn from_bytes(data: Vec<u8>) -> Result<FontContext, FontError> {
+    let face = FontArc::try_from_vec(data.clone()).map_err(|_| FontError::Invalid)?;
+    return Ok(FontContext { face: Some(face), shaper: Some(Shaper::Owned(Arc::new(data))), family: None, faces: Vec::new() });
+  }
+
+  pub fn load(path: &str) -> Result<FontContext, FontError> {
+    return FontContext::from_bytes(FontContext::read(path)?);
+  }
+
+  // load と同じだが、ファイルの中身をプロセスが終わるまで持ち、shaping の Face を先に作っておく（システムのフォント用）
+  fn load_static(path: &str) -> Result<FontContext, FontError> {
+    let data: &'static [u8] = Box::leak(FontContext::read(path)?.into_boxed_slice());
+    let face = FontArc::try_from_slice(data).map_err(|_| FontError::Invalid)?;
+    let shaper = rustybuzz::Face::from_slice(data, 0).map(Shaper::Static);
+    return Ok(FontContext { face: Some(face), shaper: shaper, family: None, faces: Vec::new() });
+  }
+
+  fn read(path: &str) -> Result<Vec<u8>, FontError> {
+    return fs::read(path).map_err(|source| FontError::Read { path: path.to_string(), source: source });
+  }
+
+  // SYSTEM_FACES のうち見つかったもの。既定は sans-serif の標準の太さで最初に読み込めたもの（なければ monospace）
+  pub fn system() -> FontContext {
+    let faces: Vec<Face> = SYSTEM_FACES
+      .iter()
+      .filter_map(|&(file, family, weight, italic)| {
+        let path = SYSTEM_FONT_DIRS.iter().map(|dir| Path::new(dir).join(file)).find(|path| path.is_file())?;
+        return Some(Face { family: family, weight: weight, italic: italic, path: path, context: OnceLock::new() });
+      })
+      .collect();
+    let regular = FontFace { families: &["sans-serif"], weight: 400.0, italic: false };
+    let default = generic_names("sans-serif")
+      .iter()
+      .flat_map(|&family| faces.iter().filter(move |face| face.family == family))
+      .filter(|face| face.distance(&regular) == (false, 0.0))
+      .filter_map(|face| Some((face, face.context()?.face.clone()?, face.context()?.shaper.clone())))
+      .next()
+      .map(|(face, font, shaper)| (face.family, font, shaper, face.path.display().to_string()));
+    return match default {
+      Some((family, font, shaper, path)) => {
+        debug!("Using font {}", path);
+        FontContext { face: Some(font), shaper: shaper, family: Some(family), faces: faces }
+      }
+      None => {
+        debug!("No system font found, drawing text as boxes");
+        FontContext::monospace()
+      }
+    };
+  }
+
+  // family（総称なら GENERIC_FAMILIES の名前を順に）の字体のうち、face にいちばん近いもの
+  fn find(&self, family: &str, face: &FontFace) -> Option<&Face> {
+    for &name in generic_names(family).iter().chain(Some(&family)) {
+      let best = self
+        .faces
+        .iter()
+        .filter(|candidate| candidate.family.eq_ignore_ascii_case(name))
+        .min_by(|a, b| a.distance(face).partial_cmp(&b.distance(face)).unwrap_or(Ordering::Equal));
+      if best.is_some() {
+        return best;
+      }
+    }
+    return None;
+  }
+
+  // font_size（1 em の px）で描くときの、ab_glyph の大きさ（ascent - descent の px）
+  fn px_scale(face: &FontArc, font_size: f32) -> PxScale {
+    let units_per_em = face.units_per_em().unwrap_or(1000.0);
+    return PxScale::from(font_size * face.height_unscaled() / units_per_em);
+  }
+
+  // shaping で並べた字形の番号と位置。フォントにない文字は、書記素クラスタごとに 1 つの矩形にする
+  fn glyphs(&self, face: &FontArc, text: &str, font_size: f32) -> Vec<Glyph> {
+    let shaped = self.shaper.as_ref().and_then(|shaper| shaper.with_face(|shaper| (shaping::shape(shaper, text), shaper.units_per_em())));
+    let (shaped, units_per_em) = match shaped {
+      Some(shaped) => shaped,
+      None => return self.unshaped_glyphs(face, text, font_size),
+    };
+    let scale = font_size / units_per_em as f32;
+    let mut glyphs: Vec<Glyph> = Vec::new();
+    let mut x = 0.0;
+    let mut missing = None; // 直前に矩形にしたクラスタ
+    for shaped in shaped {
+      let glyph = if shaped.id != 0 {
+        Glyph { id: shaped.id, x: x + shaped.x_offset as f32 * scale, y: -shaped.y_offset as f32 * scale, advance: shaped.x_advance as f32 * scale }
+      } else if missing != Some(shaped.cluster) {
+        missing = Some(shaped.cluster);
+        let grapheme = text::graphemes(&text[shaped.cluster..]).first().cloned().unwrap_or("");
+        Glyph { id: 0, x: x, y: 0.0, advance: text::monospace_width(grapheme, font_size) }
+      } else {
+        continue; // フォントにない結合文字などは、クラスタの先頭の矩形に含める
+      };
+      x += glyph.advance;
+      glyphs.push(glyph);
+    }
+    return glyphs;
+  }
+
+  // shaping できないフォントのときの、文字ごとの字形の番号と送り幅
+  fn unshaped_glyphs(&self, face: &FontArc, text: &str, font_size: f32) -> Vec<Glyph> {
+    let scaled = face.as_scaled(FontContext::px_scale(face, font_size));
+    let mut glyphs = Vec::new();
+    let mut x = 0.0;
+    for grapheme in text::graphemes(text) {
+      for (i, c) in grapheme.chars().enumerate() {
+        let id = face.glyph_id(c);
+        let glyph = if id.0 != 0 {
+          Glyph { id: id.0, x: x, y: 0.0, advance: scaled.h_advance(id) }
+        } else if i == 0 {
+          Glyph { id: 0, x: x, y: 0.0, advance: text::monospace_width(grapheme, font_size) }
+        } else {
+          continue; // フォントにない結合文字などは、クラスタの先頭の矩形に含める
+        };
+        x += glyph.advance;
+        glyphs.push(glyph);
+      }
+    }
+    return glyphs;
+  }
+}
+
+impl FontMetrics for FontContext {
+  fn text_width(&self, text: &str, font_size: f32) -> f32 {
+    return match self.face {
+      Some(ref face) => self.glyphs(face, text, font_size).iter().map(|glyph| glyph.advance).sum(),
+      None => text::monospace_width(text, font_size),
+    };
+  }
+
+  // フォントの ascent - descent + line gap（line-height: normal）
+  fn line_height(&self, font_size: f32) -> f32 {
+    return match self.face {
+      Some(ref face) => {
+        let scaled = face.as_scaled(FontContext::px_scale(face, font_size));
+        (scaled.height() + scaled.line_gap()).round()
+      }
+      None => (font_size * 1.2).round(),
+    };
+  }
+
+  // 行の高さと文字の高さの差（ハーフレディング）と ascent
+  fn baseline(&self, font_size: f32) -> f32 {
+    return match self.face {
+      Some(ref face) => {
+        let scaled = face.as_scaled(FontContext::px_scale(face, font_size));
+        (self.line_height(font_size) - scaled.height()) / 2.0 + scaled.ascent()
+      }
+      None => font_size,
+    };
+  }
+
+  fn glyph_run(&self, text: &str, font_size: f32) -> Option<GlyphRun> {
+    let face = self.face.as_ref()?;
+    return Some(GlyphRun { face: face.clone(), baseline: self.baseline(font_size), glyphs: self.glyphs(face, text, font_size) });
+  }
+
+  // font-family の順に探して、見つからなければ既定のフォントの family から選ぶ
+  fn select(&self, face: &FontFace) -> Option<&dyn FontMetrics> {
+    let found = face.families.iter().filter_map(|family| self.find(family, face)).next();
+    let found = found.or_else(|| self.family.and_then(|family| self.find(family, face)))?;
+    return found.context().map(|context| context as &dyn FontMetrics);
+  }
+}
+
+// 総称の font-family（大文字小文字は区別しない）なら当てる名前。ほかの名前なら空
+fn generic_names(family: &str) -> &'static [&'static str] {
+  return GENERIC_FAMILIES.iter().find(|&&(generic, _)| generic.eq_ignore_ascii_case(family)).map_or(&[], |&(_, names)| names);
+}
+
+// システムのフォント。最初に呼んだときに探して、その後は同じものを使う
+pub fn shared() -> &'static FontContext {
+  static SHARED: OnceLock<FontContext> = OnceLock::new();
+  return SHARED.get_or_init(FontContext::system);
+}
+
+// 字形を持たない Monospace だけのもの。システムにどのフォントがあっても、文字の幅と描く矩形は変わらない
+pub fn builtin() -> &'static FontContext {
+  static BUILTIN: OnceLock<FontContext> = OnceLock::new();
+  return BUILTIN.get_or_init(FontContext::monospace);
+}
+
+// ドキュメントのレイアウトに使うフォント。deterministic なら builtin、そうでなければシステムのフォント（shared）
+pub fn for_document(deterministic: bool) -> &'static FontContext {
+  return if deterministic { builtin() } else { shared() };
+}
+
+// run の字形のアウトラインを、(x, y) を行の左上、font_size の大きさとして、ピクセルごとの被覆率を plot に渡す。
+// area（描く範囲）にかからない字形と、MAX_GLYPH_SIZE より大きい字形は飛ばす。フォントにない文字は、その x と幅を missing に渡す
+pub fn rasterize<F: FnMut(i32, i32, f32), R: FnMut(f32, f32)>(run: &GlyphRun, x: f32, y: f32, font_size: f32, area: Rect, mut plot: F, mut missing: R) {
+  let scale = FontContext::px_scale(&run.face, font_size);
+  for glyph in &run.glyphs {
+    if glyph.id == 0 {
+      missing(x + glyph.x, glyph.advance);
+      continue;
+    }
+    let positioned = GlyphId(glyph.id).with_scale_and_position(scale, point(x + glyph.x, y + run.baseline + glyph.y));
+    if let Some(outlined) = run.face.outline_glyph(positioned) {
+      let bounds = outlined.px_bounds();
+      let rect = Rect { x: bounds.min.x, y: bounds.min.y, width: bounds.width(), height: bounds.height() };
+      if rect.width > MAX_GLYPH_SIZE || rect.height > MAX_GLYPH_SIZE || rect.intersection(area).is_none() {
+        continue;
+      }
+      outlined.draw(|gx, gy, coverage| plot(bounds.min.x as i32 + gx as i32, bounds.min.y as i32 + gy as i32, coverage));
+    }
+  }
+}
+
+// パスの 1 区間。座標は px で、y は下向き
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathSegment {
+  MoveTo(f32, f32),
+  LineTo(f32, f32),
+  CubicTo(f32, f32, f32, f32, f32, f32), // 2 つの制御点と終点
+  Close,
+}
+
+// rasterize と同じ位置に置いた run の字形のアウトライン（ベクターの出力用）。塗るときは非ゼロ規則。
+// フォントにない文字は、その x と幅を missing に渡す
+pub fn outline<R: FnMut(f32, f32)>(run: &GlyphRun, x: f32, y: f32, font_size: f32, mut missing: R) -> Vec<PathSegment> {
+  let scaled = run.face.as_scaled(FontContext::px_scale(&run.face, font_size));
+  let (sx, sy) = (scaled.h_scale_factor(), scaled.v_scale_factor());
+  let mut path = Vec::new();
+  for glyph in &run.glyphs {
+    if glyph.id == 0 {
+      missing(x + glyph.x, glyph.advance);
+      continue;
+    }
+    let outline = match run.face.outline(GlyphId(glyph.id)) {
+      Some(outline) => outline,
+      None => continue,
+    };
+    // フォントの座標は y が上向き
+    let (ox, oy) = (x + glyph.x, y + run.baseline + glyph.y);
+    let position = |p: Point| (ox + p.x * sx, oy - p.y * sy);
+    let mut end = None;
+    for curve in &outline.curves {
+      let (start, segment) = match *curve {
+        OutlineCurve::Line(a, b) => (a, PathSegment::LineTo(position(b).0, position(b).1)),
+        OutlineCurve::Quad(a, c, b) => {
+          // 2 次の制御点を 2/3 ずつ両端から寄せたものが 3 次の制御点
+          let (c1, c2) = (point(a.x + (c.x - a.x) * 2.0 / 3.0, a.y + (c.y - a.y) * 2.0 / 3.0), point(b.x + (c.x - b.x) * 2.0 / 3.0, b.y + (c.y - b.y) * 2.0 / 3.0));
+          (a, cubic_to(position(c1), position(c2), position(b)))
+        }
+        OutlineCurve::Cubic(a, c1, c2, b) => (a, cubic_to(position(c1), position(c2), position(b))),
+      };
+      // 前の曲線の終点から続いていなければ、新しい輪郭
+      if end != Some(start) {
+        if end.is_some() {
+          path.push(PathSegment::Close);
+        }
+        let (px, py) = position(start);
+        path.push(PathSegment::MoveTo(px, py));
+      }
+      path.push(segment);
+      end = Some(match *curve {
+        OutlineCurve::Line(_, b) | OutlineCurve::Quad(_, _, b) | OutlineCurve::Cubic(_, _, _, b) => b,
+      });
+    }
+    if end.is_some() {
+      path.push(PathSegment::Close);
+    }
+  }
+  return path;
+}
+
+fn cubic_to(c1: (f32, f32), c2: (f32, f32), to: (f32, f32)) -> PathSegment {
+  return PathSegment::CubicTo(c1.0, c1.1, c2.0, c2.1, to.0, to.1);
+}
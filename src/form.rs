@@ -0,0 +1,292 @@
+use css::Unit::Px;
+use css::Value::{self, ColorValue, Keyword, Length};
+use css::Color;
+use dom::{ElementData, Node, NodeType};
+use font;
+use layout::{FontMetrics, LayoutBox, Rect};
+use paint::{DisplayCommand, DisplayList};
+use style::PropertyMap;
+use text;
+
+/**
+ * フォームのコントロール（<input>、<button>、<textarea>、<select>）の既定の大きさと見た目。
+ *
+ * 種類によらないもの（display、font-size、<button> などのボーダー）は組み込みのスタイルシートで、
+ * type や size、cols、rows、中のテキストで決まる大きさは default_style で、どのルールも指定していない
+ * プロパティにだけ足す（作者のスタイルシートで上書きできる）。
+ *
+ * 中身（値やプレースホルダーのテキスト、チェックボックスとラジオボタンの印、<select> の矢印）は
+ * 背景とボーダーの後に display_list の項目として描く。文字の大きさはテキストと同じく、半角を font-size の半分として測る
+ */
+
+// font-size がないときの大きさ（組み込みのスタイルシートと同じ）
+const DEFAULT_FONT_SIZE: f32 = 13.333;
+// <input size> と <textarea cols rows> の既定値
+const DEFAULT_SIZE: usize = 20;
+const DEFAULT_ROWS: usize = 2;
+// チェックボックスとラジオボタンの一辺
+const CHECK_SIZE: f32 = 13.0;
+// <select> の右端の矢印の幅
+const ARROW_WIDTH: f32 = 16.0;
+
+const BORDER_COLOR: Color = Color { r: 0x76, g: 0x76, b: 0x76, a: 255 };
+const FIELD_BACKGROUND: Color = Color { r: 255, g: 255, b: 255, a: 255 };
+const BUTTON_BACKGROUND: Color = Color { r: 0xef, g: 0xef, b: 0xef, a: 255 };
+const TEXT_COLOR: Color = Color { r: 0, g: 0, b: 0, a: 255 };
+const PLACEHOLDER_COLOR: Color = Color { r: 0x75, g: 0x75, b: 0x75, a: 255 };
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Control {
+  TextField { value: String, placeholder: bool }, // 1 行の入力欄。placeholder なら value はプレースホルダー
+  TextArea { value: String, placeholder: bool },
+  Button { label: String },
+  Checkbox { checked: bool },
+  Radio { checked: bool },
+  Select { label: String, options: Vec<String> }, // label は選ばれている <option>
+  Hidden,
+}
+
+impl Control {
+  // node がフォームのコントロールなら、その種類と中身
+  pub fn from_node(node: &Node) -> Option<Control> {
+    let elem = match node.node_type {
+      NodeType::Element(ref elem) => elem,
+      NodeType::Text(_) | NodeType::Comment(_) => return None,
+    };
+    return match &*elem.tag_name {
+      "input" => Some(input(elem)),
+      "button" => Some(Control::Button { label: text_content(node) }),
+      "textarea" => Some(field(raw_text(node), elem, |value, placeholder| Control::TextArea { value: value, placeholder: placeholder })),
+      "select" => {
+        let mut options = Vec::new();
+        let mut selected = None;
+        collect_options(node, &mut options, &mut selected);
+        let label = selected.or_else(|| options.first().cloned()).unwrap_or_default();
+        Some(Control::Select { label: label, options: options })
+      }
+      _ => None,
+    };
+  }
+}
+
+fn input(elem: &ElementData) -> Control {
+  let kind = elem.attributes.get("type").map_or(String::new(), |kind| kind.trim().to_ascii_lowercase());
+  let value = elem.attributes.get("value").cloned();
+  return match &*kind {
+    "hidden" => Control::Hidden,
+    "checkbox" => Control::Checkbox { checked: elem.attributes.contains_key("checked") },
+    "radio" => Control::Radio { checked: elem.attributes.contains_key("checked") },
+    "submit" => Control::Button { label: value.unwrap_or_else(|| "Submit".to_string()) },
+    "reset" => Control::Button { label: value.unwrap_or_else(|| "Reset".to_string()) },
+    "button" => Control::Button { label: value.unwrap_or_default() },
+    "password" => {
+      let masked = value.map(|value| "•".repeat(text::grapheme_count(&value)));
+      field(masked.unwrap_or_default(), elem, |value, placeholder| Control::TextField { value: value, placeholder: placeholder })
+    }
+    _ => field(value.unwrap_or_default(), elem, |value, placeholder| Control::TextField { value: value, placeholder: placeholder }),
+  };
+}
+
+// 値が空ならプレースホルダー
+fn field<F: Fn(String, bool) -> Control>(value: String, elem: &ElementData, make: F) -> Control {
+  if value.is_empty() {
+    if let Some(placeholder) = elem.attributes.get("placeholder") {
+      return make(placeholder.clone(), true);
+    }
+  }
+  return make(value, false);
+}
+
+fn collect_options(node: &Node, options: &mut Vec<String>, selected: &mut Option<String>) {
+  for child in &node.children {
+    if let NodeType::Element(ref elem) = child.node_type {
+      if elem.tag_name == "option" {
+        let label = elem.attributes.get("label").cloned().unwrap_or_else(|| text_content(child));
+        if selected.is_none() && elem.attributes.contains_key("selected") {
+          *selected = Some(label.clone());
+        }
+        options.push(label);
+      } else {
+        collect_options(child, options, selected); // <optgroup>
+      }
+    }
+  }
+}
+
+// 中のテキストをつないで、空白をまとめたもの
+fn text_content(node: &Node) -> String {
+  return raw_text(node).split_whitespace().collect::<Vec<_>>().join(" ");
+}
+
+// 中のテキストをそのままつないだもの（<textarea> の値は改行を残す。開始タグの直後の改行は除く）
+fn raw_text(node: &Node) -> String {
+  fn collect(node: &Node, out: &mut String) {
+    match node.node_type {
+      NodeType::Text(ref text) => out.push_str(text),
+      NodeType::Element(_) => node.children.iter().for_each(|child| collect(child, out)),
+      NodeType::Comment(_) => {}
+    }
+  }
+  let mut out = String::new();
+  collect(node, &mut out);
+  if out.starts_with('\n') {
+    out.remove(0);
+  }
+  return out;
+}
+
+// values（ルールで決まった値）にない、コントロールの既定の値を足す
+pub fn default_style(node: &Node, values: &mut PropertyMap) {
+  let control = match Control::from_node(node) {
+    Some(control) => control,
+    None => return,
+  };
+  let font_size = match values.get("font-size") {
+    Some(&Length(size, Px)) => size,
+    _ => DEFAULT_FONT_SIZE,
+  };
+  let line_height = line_height(font_size);
+  let px = |length: f32| Length(length.round(), Px);
+
+  let mut defaults: Vec<(&str, Value)> = Vec::new();
+  match control {
+    Control::Hidden => defaults.push(("display", Keyword("none".to_string()))),
+    Control::Checkbox { .. } | Control::Radio { .. } => {
+      defaults.push(("width", px(CHECK_SIZE)));
+      defaults.push(("height", px(CHECK_SIZE)));
+      defaults.extend(vec![("margin-top", px(3.0)), ("margin-right", px(3.0)), ("margin-bottom", px(3.0)), ("margin-left", px(4.0))]);
+    }
+    Control::TextField { .. } => {
+      let size = attribute_number(node, "size").unwrap_or(DEFAULT_SIZE);
+      defaults.push(("width", px(size as f32 * font_size / 2.0)));
+      defaults.push(("height", px(line_height)));
+      defaults.extend(vec![("border-width", px(2.0)), ("border-color", ColorValue(BORDER_COLOR)), ("background-color", ColorValue(FIELD_BACKGROUND))]);
+      defaults.extend(vec![("padding-top", px(1.0)), ("padding-bottom", px(1.0)), ("padding-left", px(2.0)), ("padding-right", px(2.0))]);
+    }
+    Control::TextArea { .. } => {
+      let cols = attribute_number(node, "cols").unwrap_or(DEFAULT_SIZE);
+      let rows = attribute_number(node, "rows").unwrap_or(DEFAULT_ROWS);
+      defaults.push(("width", px(cols as f32 * font_size / 2.0)));
+      defaults.push(("height", px(rows as f32 * line_height)));
+    }
+    Control::Button { ref label } => {
+      defaults.push(("width", px(font::shared().text_width(label, font_size))));
+      defaults.push(("height", px(line_height)));
+      defaults.extend(vec![("border-width", px(2.0)), ("border-color", ColorValue(BORDER_COLOR)), ("background-color", ColorValue(BUTTON_BACKGROUND))]);
+      defaults.extend(vec![("padding-top", px(1.0)), ("padding-bottom", px(1.0)), ("padding-left", px(6.0)), ("padding-right", px(6.0))]);
+    }
+    Control::Select { ref options, .. } => {
+      let widest = options.iter().map(|option| font::shared().text_width(option, font_size)).fold(0.0, f32::max);
+      defaults.push(("width", px(widest + ARROW_WIDTH)));
+      defaults.push(("height", px(line_height)));
+    }
+  }
+  // 既定の width と height はコンテンツボックスの大きさなので、box-sizing: border-box なら border と padding を足す
+  let (sizes, others): (Vec<(&str, Value)>, Vec<(&str, Value)>) = defaults.into_iter().partition(|&(name, _)| name == "width" || name == "height");
+  for (name, value) in others {
+    values.entry(name.to_string()).or_insert(value);
+  }
+  let border_box = values.get("box-sizing") == Some(&Keyword("border-box".to_string()));
+  for (name, value) in sizes {
+    let value = match value {
+      Length(length, Px) if border_box => px(length + edges(values, name)),
+      value => value,
+    };
+    values.entry(name.to_string()).or_insert(value);
+  }
+}
+
+// size（width か height）の向きの border と padding の px の合計
+fn edges(values: &PropertyMap, size: &str) -> f32 {
+  let sides = if size == "width" { ["left", "right"] } else { ["top", "bottom"] };
+  let length = |name: &str, shorthand: &str| match values.get(name).or_else(|| values.get(shorthand)) {
+    Some(&Length(length, Px)) => length,
+    _ => 0.0,
+  };
+  return sides.iter().map(|side| length(&format!("border-{}-width", side), "border-width") + length(&format!("padding-{}", side), "padding")).sum();
+}
+
+fn attribute_number(node: &Node, name: &str) -> Option<usize> {
+  return match node.node_type {
+    NodeType::Element(ref elem) => elem.attributes.get(name).and_then(|value| value.trim().parse().ok()).filter(|&number| number > 0),
+    NodeType::Text(_) | NodeType::Comment(_) => None,
+  };
+}
+
+fn line_height(font_size: f32) -> f32 {
+  return font::shared().line_height(font_size);
+}
+
+// コントロールの中身。コンテンツボックスに描いて、パディングボックスの外ははみ出さない
+pub fn display_list(layout_box: &LayoutBox) -> DisplayList {
+  let mut list = Vec::new();
+  let style = match layout_box.box_type.style_node() {
+    Some(style) => style,
+    None => return list,
+  };
+  let control = match Control::from_node(style.node) {
+    Some(control) => control,
+    None => return list,
+  };
+  let content = layout_box.dimensions.content;
+  let clip = layout_box.dimensions.padding_box();
+  let font_size = match style.value("font-size") {
+    Some(Length(size, Px)) => size,
+    _ => DEFAULT_FONT_SIZE,
+  };
+  let color = match style.value("color") {
+    Some(ColorValue(color)) => color,
+    _ => TEXT_COLOR,
+  };
+
+  // 1 行のテキストを content の中で縦に中央揃えにする
+  let line = |text: &str, color: Color, x: f32, width: f32| {
+    let y = content.y + ((content.height - line_height(font_size)) / 2.0).max(0.0);
+    let glyphs = font::shared().glyph_run(text, font_size);
+    DisplayCommand::Text { text: text.to_string(), glyphs: glyphs, color: color, x: x, y: y, font_size: font_size, clip: intersect(clip, Rect { x: x, y: content.y, width: width, height: content.height }) }
+  };
+
+  match control {
+    Control::Hidden => {}
+    Control::Checkbox { checked } => list.push(DisplayCommand::Checkbox { rect: content, checked: checked, clip: clip }),
+    Control::Radio { checked } => list.push(DisplayCommand::Radio { rect: content, checked: checked, clip: clip }),
+    Control::TextField { ref value, placeholder } => {
+      list.push(line(value, if placeholder { PLACEHOLDER_COLOR } else { color }, content.x, content.width));
+    }
+    Control::Button { ref label } => {
+      // ラベルは中央揃え
+      let width = font::shared().text_width(label, font_size);
+      list.push(line(label, color, content.x + ((content.width - width) / 2.0).max(0.0), content.width));
+    }
+    Control::Select { ref label, .. } => {
+      list.push(line(label, color, content.x, (content.width - ARROW_WIDTH).max(0.0)));
+      render_arrow(&mut list, content, color);
+    }
+    Control::TextArea { ref value, placeholder } => {
+      // 改行文字と、幅に収まらないところで折り返す
+      let color = if placeholder { PLACEHOLDER_COLOR } else { color };
+      let line_height = line_height(font_size);
+      for (i, text_line) in text::wrap(value, content.width, |line| font::shared().text_width(line, font_size)).iter().enumerate() {
+        let y = content.y + i as f32 * line_height;
+        let glyphs = font::shared().glyph_run(text_line.text, font_size);
+        list.push(DisplayCommand::Text { text: text_line.text.to_string(), glyphs: glyphs, color: color, x: content.x, y: y, font_size: font_size, clip: clip });
+      }
+    }
+  }
+  return list;
+}
+
+// <select> の右端の下向きの三角（1 ピクセルの高さの帯を重ねる）
+fn render_arrow(list: &mut DisplayList, content: Rect, color: Color) {
+  let half = 4.0;
+  let center_x = content.x + content.width - ARROW_WIDTH / 2.0;
+  let top = content.y + ((content.height - half) / 2.0).floor();
+  for row in 0..half as usize {
+    let width = (half - row as f32) * 2.0;
+    list.push(DisplayCommand::SolidColor(color, Rect { x: center_x - width / 2.0, y: top + row as f32, width: width, height: 1.0 }));
+  }
+}
+
+fn intersect(a: Rect, b: Rect) -> Rect {
+  return a.intersection(b).unwrap_or(Rect { width: 0.0, height: 0.0, ..a });
+}
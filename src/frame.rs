@@ -0,0 +1,119 @@
+use dom::{Node, NodeType};
+use error::EngineError;
+use layout::{self, LayoutBox, Rect};
+use loader::{Loader, Source};
+use options::EngineOptions;
+use paint::{self, DisplayList, RenderOptions};
+use parallel;
+use std::collections::HashMap;
+use std::ptr;
+use style;
+use url::Url;
+use {load_with, LoadedDocument};
+
+/**
+ * <iframe> の中身を入れ子のドキュメントとして扱うところ。
+ * 読み込むときに src のドキュメントも（親と同じ Loader で、親のスタイルシートと同時に）読み込んでおき、
+ * 描画するときに iframe のコンテンツボックスをビューポートにしてレイアウトし、
+ * そのディスプレイリストを iframe の位置にずらして親のディスプレイリストに差し込む
+ */
+
+// iframe の中の iframe を読み込む深さの上限（自分自身を読み込むページで止まるように）
+const MAX_DEPTH: usize = 4;
+
+// ドキュメント順に <iframe> 要素を集める
+pub fn collect_iframes<'a>(node: &'a Node, iframes: &mut Vec<&'a Node>) {
+  if let NodeType::Element(ref elem) = node.node_type {
+    if elem.tag_name == "iframe" {
+      iframes.push(node);
+    }
+  }
+  for child in &node.children {
+    collect_iframes(child, iframes);
+  }
+}
+
+// iframe ごとに（ドキュメント順で）src のドキュメントを読み込む（iframe どうしは同時に）。
+// src がない、または読み込めなかった iframe は None（何も描画しない）
+pub fn load_frames(root_node: &Node, base_url: &Url, loader: &Loader, options: &EngineOptions, depth: usize) -> Vec<Option<LoadedDocument>> {
+  let mut iframes = Vec::new();
+  collect_iframes(root_node, &mut iframes);
+  let srcs: Vec<Option<Url>> = iframes
+    .iter()
+    .map(|iframe| match iframe.node_type {
+      NodeType::Element(ref elem) => elem.attributes.get("src").and_then(|src| base_url.join(src)),
+      NodeType::Text(_) | NodeType::Comment(_) => None,
+    })
+    .collect();
+
+  return parallel::map(options.parallelism, &srcs, |src| match *src {
+    Some(_) if depth >= MAX_DEPTH => {
+      warn!("Skipped iframe nested deeper than {}", MAX_DEPTH);
+      None
+    }
+    Some(ref src) => match load_with(&Source::Url(src.clone()), &[], options, loader, depth + 1) {
+      Ok(document) => Some(document),
+      Err(error) => {
+        warn!("Failed to load iframe {}: {}", src, error);
+        None
+      }
+    },
+    None => None,
+  });
+}
+
+// layout_root のディスプレイリスト。iframe のボックスの中には対応する frames のドキュメントを描く。
+// iframe の中身は先に（iframe どうしは同時に）レイアウトしておく。options の debug_boxes なら、最後にボックスの枠を重ねる
+pub fn build_display_list(layout_root: &LayoutBox, root_node: &Node, frames: &[Option<LoadedDocument>], parallelism: usize, options: &RenderOptions) -> DisplayList {
+  let mut iframes = Vec::new();
+  collect_iframes(root_node, &mut iframes);
+
+  let mut boxes = Vec::new();
+  let mut contents = Vec::new();
+  collect_frame_boxes(layout_root, &iframes, frames, &mut boxes, &mut contents);
+  let lists = parallel::map(parallelism, &contents, |&(index, content)| {
+    let document = frames[index].as_ref()?;
+    match frame_display_list(document, content) {
+      Ok(list) => Some(list),
+      Err(error) => {
+        warn!("Failed to render iframe: {}", error);
+        None
+      }
+    }
+  });
+  let mut lists: HashMap<*const (), DisplayList> = boxes.into_iter().zip(lists).filter_map(|(layout_box, list)| Some((layout_box, list?))).collect();
+
+  let mut list = paint::build_display_list_with(layout_root, &mut |layout_box| lists.remove(&box_key(layout_box)));
+  if options.debug_boxes {
+    list.extend(paint::debug_boxes(layout_root));
+  }
+  return list;
+}
+
+// 中身のある iframe のボックスと、その（frames の）番号とコンテンツボックス
+fn collect_frame_boxes(layout_box: &LayoutBox, iframes: &[&Node], frames: &[Option<LoadedDocument>], boxes: &mut Vec<*const ()>, contents: &mut Vec<(usize, Rect)>) {
+  if let Some(style_node) = layout_box.box_type.style_node() {
+    let index = iframes.iter().position(|iframe| ptr::eq(*iframe, style_node.node));
+    if let Some(index) = index.filter(|&index| frames.get(index).map_or(false, |frame| frame.is_some())) {
+      boxes.push(box_key(layout_box));
+      contents.push((index, layout_box.dimensions.content));
+    }
+  }
+  for child in &layout_box.children {
+    collect_frame_boxes(child, iframes, frames, boxes, contents);
+  }
+}
+
+// ボックスを区別するためのアドレス（ライフタイムを持たない形にする）
+fn box_key(layout_box: &LayoutBox) -> *const () {
+  return layout_box as *const LayoutBox as *const ();
+}
+
+// 入れ子のドキュメントを content の大きさでレイアウトして、content の位置に置いたディスプレイリストにする
+fn frame_display_list(document: &LoadedDocument, content: Rect) -> Result<DisplayList, EngineError> {
+  let viewport = Rect { x: 0.0, y: 0.0, width: content.width, height: content.height };
+  let style_root = style::style_tree_with_viewport(&document.root_node, &document.stylesheet, viewport);
+  let layout_root = layout::layout_tree_with(&style_root, ::initial_containing_block(viewport), document.fonts, &document.images)?;
+  let list = build_display_list(&layout_root, &document.root_node, &document.frames, document.parallelism, &document.render);
+  return Ok(paint::clip_display_list(list.iter().map(|item| item.translated(content.x, content.y)), content));
+}
@@ -0,0 +1,227 @@
+/**
+ * テスト用の入力を作るところ（testing フィーチャー）。
+ * このエンジンのパーサーで必ずパースできる、ランダムな HTML ドキュメントとスタイルシートを作る。
+ * 長さは負にもなり、margin 以外の負の値の宣言は、パーサーが CssError::NegativeValue の診断を出して捨てる（ほかの診断は出ない）。
+ * 同じシードと GeneratorOptions からは常に同じものができるので、失敗したケースはシードで再現できる。
+ * 「レイアウトが NaN や負の大きさを作らない」のようなプロパティテストや、ベンチマーク用の入力に使う
+ *
+ *   let mut rng = Rng::new(42);
+ *   let options = GeneratorOptions::default();
+ *   let html = generate::document(&mut rng, &options);
+ *   let css = generate::stylesheet(&mut rng, &options);
+ */
+
+// 要素に使うタグ名。ブロックとインラインを混ぜる（display はスタイルシートでも変える）
+const TAGS: [&'static str; 10] = ["div", "p", "section", "ul", "li", "span", "a", "em", "strong", "b"];
+
+// TAGS のうち、開いている <p> をパーサーが閉じてしまうタグ（<p> の中には置かない）
+const CLOSES_P: [&'static str; 5] = ["div", "p", "section", "ul", "li"];
+
+const WORDS: [&'static str; 8] = ["lorem", "ipsum", "dolor", "sit", "amet", "suburi", "browser", "engine"];
+
+// 乱数（xorshift64*）。外部のクレートに頼らず、どの環境でも同じ列になる
+#[derive(Debug, Clone)]
+pub struct Rng {
+  state: u64,
+}
+
+impl Rng {
+  pub fn new(seed: u64) -> Rng {
+    // 0 だと 0 しか出ないので、シードを混ぜてから使う
+    return Rng { state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ 0x2545_f491_4f6c_dd1d };
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state ^= self.state >> 12;
+    self.state ^= self.state << 25;
+    self.state ^= self.state >> 27;
+    return self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+  }
+
+  // 0 以上 n 未満（n が 0 なら 0）
+  pub fn below(&mut self, n: usize) -> usize {
+    if n == 0 {
+      return 0;
+    }
+    return (self.next_u64() % n as u64) as usize;
+  }
+
+  // probability（0.0 〜 1.0）の確率で true
+  pub fn chance(&mut self, probability: f64) -> bool {
+    return ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability;
+  }
+
+  pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+    return &items[self.below(items.len())];
+  }
+
+  // 重みに比例した確率で番号を選ぶ（重みがすべて 0 なら 0）
+  pub fn weighted(&mut self, weights: &[u32]) -> usize {
+    let total: u64 = weights.iter().map(|&weight| weight as u64).sum();
+    if total == 0 {
+      return 0;
+    }
+    let mut point = self.next_u64() % total;
+    for (index, &weight) in weights.iter().enumerate() {
+      if point < weight as u64 {
+        return index;
+      }
+      point -= weight as u64;
+    }
+    return weights.len() - 1;
+  }
+}
+
+// セレクターの種類ごとの重み
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectorMix {
+  pub tag: u32,       // div
+  pub class: u32,     // .c1
+  pub id: u32,        // #i1
+  pub universal: u32, // *
+  pub compound: u32,  // div.c1.c2#i1
+}
+
+impl Default for SelectorMix {
+  fn default() -> SelectorMix {
+    return SelectorMix { tag: 3, class: 4, id: 1, universal: 1, compound: 2 };
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorOptions {
+  pub max_depth: usize,           // 要素の入れ子の深さ（html と body を除く）
+  pub max_children: usize,        // 1 つの要素の子の数の上限
+  pub text_probability: f64,      // 子がテキストノードになる確率
+  pub class_count: usize,         // 使うクラス名の種類（c0, c1, ...）
+  pub id_count: usize,            // 使う ID の種類（i0, i1, ...）。ドキュメントでは重複しない
+  pub rule_count: usize,          // スタイルシートのルールの数
+  pub max_selectors: usize,       // 1 つのルールのセレクターの数（, 区切り）の上限
+  pub max_declarations: usize,    // 1 つのルールの宣言の数の上限
+  pub max_length: u32,            // 長さ（px）の上限
+  pub negative_probability: f64,  // 長さを負にする確率（負の値を使えないプロパティの宣言は、パーサーが診断を出して捨てる）
+  pub selector_mix: SelectorMix,
+}
+
+impl Default for GeneratorOptions {
+  fn default() -> GeneratorOptions {
+    return GeneratorOptions {
+      max_depth: 6,
+      max_children: 4,
+      text_probability: 0.3,
+      class_count: 8,
+      id_count: 8,
+      rule_count: 12,
+      max_selectors: 3,
+      max_declarations: 5,
+      max_length: 200,
+      negative_probability: 0.2,
+      selector_mix: SelectorMix::default(),
+    };
+  }
+}
+
+// <html><body>...</body></html> のドキュメント
+pub fn document(rng: &mut Rng, options: &GeneratorOptions) -> String {
+  let mut out = String::from("<html><body>");
+  let mut next_id = 0;
+  let children = 1 + rng.below(options.max_children.max(1));
+  for _ in 0..children {
+    element(rng, options, options.max_depth, (false, false), &mut next_id, &mut out);
+  }
+  out.push_str("</body></html>");
+  return out;
+}
+
+// inside は (<p> の中か, <ul> を挟まずに <li> の中か)。その中では、パーサーが祖先を閉じてしまうタグを使わない
+fn element(rng: &mut Rng, options: &GeneratorOptions, depth: usize, inside: (bool, bool), next_id: &mut usize, out: &mut String) {
+  let (in_p, in_li) = inside;
+  let tags: Vec<&str> = TAGS.iter().cloned().filter(|tag| !(in_p && CLOSES_P.contains(tag)) && !(in_li && *tag == "li")).collect();
+  let tag = *rng.choose(&tags);
+  let inside = (in_p || tag == "p", (in_li || tag == "li") && tag != "ul");
+  out.push('<');
+  out.push_str(tag);
+  if options.class_count > 0 && rng.chance(0.6) {
+    let classes: Vec<String> = (0..1 + rng.below(3)).map(|_| format!("c{}", rng.below(options.class_count))).collect();
+    out.push_str(&format!(" class=\"{}\"", classes.join(" ")));
+  }
+  if *next_id < options.id_count && rng.chance(0.2) {
+    out.push_str(&format!(" id=\"i{}\"", next_id));
+    *next_id += 1;
+  }
+  out.push('>');
+
+  if depth > 0 {
+    for _ in 0..rng.below(options.max_children + 1) {
+      if rng.chance(options.text_probability) {
+        text(rng, out);
+      } else {
+        element(rng, options, depth - 1, inside, next_id, out);
+      }
+    }
+  } else if rng.chance(options.text_probability) {
+    text(rng, out);
+  }
+
+  out.push_str(&format!("</{}>", tag));
+}
+
+// テキストノード。パーサーが先頭の空白を落とすので、単語から始める
+fn text(rng: &mut Rng, out: &mut String) {
+  let words: Vec<&str> = (0..1 + rng.below(5)).map(|_| *rng.choose(&WORDS)).collect();
+  out.push_str(&words.join(" "));
+}
+
+pub fn stylesheet(rng: &mut Rng, options: &GeneratorOptions) -> String {
+  let mut out = String::new();
+  for _ in 0..options.rule_count {
+    let selectors: Vec<String> = (0..1 + rng.below(options.max_selectors.max(1))).map(|_| selector(rng, options)).collect();
+    let declarations: Vec<String> = (0..1 + rng.below(options.max_declarations.max(1))).map(|_| declaration(rng, options)).collect();
+    out.push_str(&format!("{} {{ {} }}\n", selectors.join(", "), declarations.join(" ")));
+  }
+  return out;
+}
+
+fn selector(rng: &mut Rng, options: &GeneratorOptions) -> String {
+  let mix = &options.selector_mix;
+  let class = |rng: &mut Rng| format!(".c{}", rng.below(options.class_count.max(1)));
+  let id = |rng: &mut Rng| format!("#i{}", rng.below(options.id_count.max(1)));
+  return match rng.weighted(&[mix.tag, mix.class, mix.id, mix.universal, mix.compound]) {
+    0 => rng.choose(&TAGS).to_string(),
+    1 => class(rng),
+    2 => id(rng),
+    3 => "*".to_string(),
+    _ => {
+      let mut selector = if rng.chance(0.5) { rng.choose(&TAGS).to_string() } else { String::new() };
+      for _ in 0..1 + rng.below(2) {
+        selector.push_str(&class(rng));
+      }
+      if rng.chance(0.3) {
+        selector.push_str(&id(rng));
+      }
+      selector
+    }
+  };
+}
+
+fn declaration(rng: &mut Rng, options: &GeneratorOptions) -> String {
+  let length = |rng: &mut Rng| {
+    let sign = if rng.chance(options.negative_probability) { "-" } else { "" };
+    format!("{}{}px", sign, rng.below(options.max_length as usize + 1))
+  };
+  let color = |rng: &mut Rng| format!("#{:02x}{:02x}{:02x}", rng.below(256), rng.below(256), rng.below(256));
+  let (name, value) = match rng.below(9) {
+    0 => ("display", rng.choose(&["block", "inline", "none"]).to_string()),
+    1 => ("width", if rng.chance(0.3) { "auto".to_string() } else { length(rng) }),
+    2 => ("height", length(rng)),
+    3 => {
+      let name = *rng.choose(&["margin", "margin-left", "margin-right", "margin-top", "margin-bottom"]);
+      (name, if rng.chance(0.3) { "auto".to_string() } else { length(rng) })
+    }
+    4 => (*rng.choose(&["padding", "padding-left", "padding-right", "padding-top", "padding-bottom"]), length(rng)),
+    5 => (*rng.choose(&["border-width", "border-left-width", "border-right-width", "border-top-width", "border-bottom-width"]), length(rng)),
+    6 => ("border-color", color(rng)),
+    _ => ("background", color(rng)),
+  };
+  return format!("{}: {};", name, value);
+}
@@ -0,0 +1,45 @@
+use url::Url;
+
+/**
+ * たどったドキュメントの履歴（戻る・進む）。
+ * リンクをたどると今より先のエントリーを捨てて足し、戻る・進むでは位置だけを動かす
+ */
+
+#[derive(Debug, Clone, Default)]
+pub struct History {
+  entries: Vec<Url>,
+  current: usize, // 今のエントリーの位置（entries が空なら 0）
+}
+
+impl History {
+  pub fn new(url: Url) -> History {
+    return History { entries: vec![url], current: 0 };
+  }
+
+  // リンクをたどったとき。今より先（進む）のエントリーは捨てる
+  pub fn push(&mut self, url: Url) {
+    self.entries.truncate(self.current + 1);
+    self.entries.push(url);
+    self.current = self.entries.len() - 1;
+  }
+
+  pub fn current(&self) -> Option<&Url> {
+    return self.entries.get(self.current);
+  }
+
+  // 今から delta だけ離れたエントリー（-1 で 1 つ前）。なければ None
+  pub fn entry(&self, delta: isize) -> Option<&Url> {
+    let index = self.current as isize + delta;
+    if index < 0 {
+      return None;
+    }
+    return self.entries.get(index as usize);
+  }
+
+  // delta だけ戻るか進む（entry で読み込めたときに呼ぶ）。エントリーがなければ動かない
+  pub fn go(&mut self, delta: isize) {
+    if self.entry(delta).is_some() {
+      self.current = (self.current as isize + delta) as usize;
+    }
+  }
+}
@@ -1,163 +1,244 @@
+use thiserror::Error;
 use dom;
-use std::collections::HashMap;
-
-struct Parser {
-  pos: usize, // 文字列内の現在の位置。usize は C++ の `size_t`
-  input: String, // 入力された文字列
+use trace;
+
+pub mod tokenizer;
+
+use self::tokenizer::is_raw_text_element;
+pub use self::tokenizer::{Token, Tokenizer, Tokens};
+
+/**
+ * HTML のパーサー。
+ *
+ * 入力は tokenizer でトークンに分け、TreeBuilder がそれを順に受け取ってツリーを作る。入力を少しずつ読むときは、
+ * Tokenizer::streaming に feed したところで TreeBuilder::read を呼び、最後に Tokenizer::finish と read の後で TreeBuilder::finish を呼ぶ。
+ * 壊れた入力でも止まらずに、できるところまでツリーを作る（parse_with_diagnostics）。
+ * 対応する開始タグのない閉じタグは読み飛ばし、外側の要素の閉じタグが来たら開いている要素をそこで閉じ、
+ * 入力の終わりで閉じていない要素も閉じる。<li> の中の <li> や <p> の中の <div> のように、終了タグを省略できる要素は
+ * それを閉じる開始タグが来たところで閉じる（ブラウザと同じツリーになるように、HTML の規則の一部だけ）。読めない属性は飛ばし、要素として読めないところは次の > まで飛ばす。
+ * 開いている要素が MAX_DEPTH を超える開始タグは、いちばん内側の要素を閉じてから開く。
+ * 補ったところは HtmlError として順に返す。parse は最初の 1 つがあればエラーにする（EngineOptions::strict）
+ *
+ * テキストと属性の値の文字参照（&amp;、&nbsp; のような名前と、&#12354;、&#x3042; のような番号）は文字にしてツリーに入れる。
+ * 知らない名前と ; のない名前はそのまま残す
+ */
+
+// HTML パース時のエラー。pos は入力中のバイト位置
+#[derive(Debug, Error, PartialEq)]
+pub enum HtmlError {
+  #[error("unexpected end of input")]
+  UnexpectedEof,
+  #[error("expected {expected:?} but found {found:?} at {pos}")]
+  UnexpectedChar { expected: char, found: char, pos: usize },
+  #[error("closing tag </{found}> does not match <{expected}> at {pos}")]
+  MismatchedTag { expected: String, found: String, pos: usize },
+  #[error("closing tag </{name}> has no open element at {pos}")]
+  StrayEndTag { name: String, pos: usize },
+  #[error("element <{name}> at {pos} is not closed before the end of its parent or input")]
+  UnclosedElement { name: String, pos: usize },
+  #[error("element <{name}> is nested too deeply at {pos}")]
+  TooDeep { name: String, pos: usize },
 }
 
-impl Parser {
-  // char の読み取り
-  fn next_char(&self) -> char {
-    return self.input[self.pos..].chars().next().unwrap()
-  }
+// 開いている要素の数の上限（Blink と同じ）。これより深い開始タグは、いちばん内側の要素を閉じてその兄弟にする。
+// 入れ子の深いドキュメントで、スタイルやレイアウトの再帰がスタックをあふれさせないように
+const MAX_DEPTH: usize = 512;
 
-  // 次の文字が、引数 s で始まるか
-  fn starts_with(&self, s: &str) -> bool {
-    return self.input[self.pos..].starts_with(s)
-  }
+// 子も閉じタグも持たない要素
+const VOID_ELEMENTS: &[&str] = &["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr"];
 
-  // EOF
-  fn eof(&self) -> bool {
-    return self.pos >= self.input.len()
-  }
+// 終了タグを省略できる要素。閉じタグがないまま閉じても警告しない
+const OPTIONAL_END_TAG_ELEMENTS: &[&str] = &["body", "dd", "dt", "head", "html", "li", "option", "p", "tbody", "td", "tfoot", "th", "thead", "tr"];
 
-  // マルチバイト文字に対応するためのメソッド
-  fn consume_char(&mut self) -> char {
-    // `char_indices()`で文字列の開始位置を入れる
-    let mut iter = self.input[self.pos..].char_indices();
-    // 次の char をとる
-    let (_, cur_char) = iter.next().unwrap();
-    let (next_pos, _) = iter.next().unwrap_or((1, ' '));
+// 開いている <p> を閉じる開始タグ
+const CLOSES_P: &[&str] = &[
+  "address", "article", "aside", "blockquote", "dd", "details", "div", "dl", "dt", "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2", "h3", "h4",
+  "h5", "h6", "header", "hr", "li", "main", "menu", "nav", "ol", "p", "pre", "section", "table", "ul",
+];
 
-    // advance
-    self.pos += next_pos;
+// この中の要素を閉じる開始タグが来ても、この外の要素は閉じない
+const SCOPE_ELEMENTS: &[&str] = &["button", "caption", "table", "td", "th"];
 
-    println!("html: cur_char:  {}", cur_char);
+// トークンからツリーを作る。開いている要素を積んでおき、閉じたところで親の子にする
+pub struct TreeBuilder {
+  open: Vec<(String, dom::AttrMap, Vec<dom::Node>, usize)>, // 開いている要素のタグ名、属性、それまでの子、開始タグのタグ名の位置（外側から）
+  nodes: Vec<dom::Node>, // どの要素にも入っていないノード
+  after_pre: bool, // 直前のトークンが <pre> の開始タグか
+  diagnostics: Vec<HtmlError>, // 読み飛ばしたり補ったりしたところ
+}
 
-    // 現在の文字を返す
-    return cur_char;
+impl TreeBuilder {
+  pub fn new() -> TreeBuilder {
+    return TreeBuilder { open: Vec::new(), nodes: Vec::new(), after_pre: false, diagnostics: Vec::new() };
   }
 
-  // 連続する文字列を返すためのメソッド
-  fn consume_while<F>(&mut self, test: F) -> String
-    // test には bool が入る関数
-    where F: Fn(char) -> bool {
-      println!("html: consume_while_start");
-      let mut result = String::new();
-
-      // EOF でなく、次の char が test の条件を満たす間、`consume_char()` の返り値を追加
-      while !self.eof() && test(self.next_char()) {
-        result.push(self.consume_char());
-      }
-
-      println!("html: consume_while_end");
-      return result;
+  // tokenizer から読めるだけトークンを読んで足す。トークナイザーの diagnostics もここに移す
+  pub fn read(&mut self, tokenizer: &mut Tokenizer) {
+    while let Some(token) = tokenizer.next() {
+      self.diagnostics.append(&mut tokenizer.diagnostics);
+      self.push(token, tokenizer.token_start());
     }
-
-  // スペース文字
-  fn consume_whitespace(&mut self) {
-    self.consume_while(char::is_whitespace);
-  }
-
-  // タグ
-  fn parse_tag_name(&mut self) -> String {
-    return self.consume_while(|c| match c {
-      'a'..='z' | 'A'..='Z' | '0'..='9' => true,
-      _ => false
-    })
-  }
-
-  // テキスト
-  fn parse_text(&mut self) -> dom::Node {
-    return dom::text(self.consume_while(|c| c != '<'))
-  }
-
-  // 属性の値
-  fn parse_attr_value(&mut self) -> String {
-    let open_quote = self.consume_char();
-    assert!(open_quote == '"' || open_quote == '\''); // " か ' が含まれるため
-    let value = self.consume_while(|c| c != open_quote);
-    assert_eq!(self.consume_char(), open_quote);
-    return value;
-  }
-
-  // 属性
-  fn parse_attr(&mut self) -> (String, String) { // (属性名、値)を返す
-    let name = self.parse_tag_name();
-    assert_eq!(self.consume_char(), '=');
-    let value = self.parse_attr_value();
-    return (name, value);
+    self.diagnostics.append(&mut tokenizer.diagnostics);
   }
 
-  // 全属性
-  fn parse_attributes(&mut self) -> dom::AttrMap {
-    let mut attributes = HashMap::new();
-    loop {
-      self.consume_whitespace(); // スペースは除外
-      if self.next_char() == '>' {
-        break;
+  // トークンを 1 つ足す。pos は入力の中でのトークンのバイト位置。
+  // ノードの間の空白は捨てるが、<pre> の中では空白もテキストにする
+  pub fn push(&mut self, token: Token, pos: usize) {
+    let after_pre = self.after_pre;
+    self.after_pre = false;
+    match token {
+      Token::StartTag { name, attributes, self_closing } => {
+        let name = name.into_owned();
+        // 同じ名前の属性は後のもの
+        let attributes = attributes.into_iter().map(|(name, value)| (name.into_owned(), value.into_owned())).collect();
+        // 開いている要素を閉じる開始タグ（<li> の中の <li> や、<td> の中の <tr> など）なら、閉じた外側に入れる
+        while let Some(index) = self.implied_end(&name) {
+          trace!("implied end tag before <{}>", name);
+          self.close(index, &|name, start| HtmlError::UnclosedElement { name: name, pos: start });
+        }
+        // <br> のような空要素と、<tag/> は子も閉じタグもない
+        if self_closing || is_void_element(&name) {
+          self.append(dom::elem(name, attributes, Vec::new()));
+        } else {
+          if self.open.len() >= MAX_DEPTH {
+            self.diagnostics.push(HtmlError::TooDeep { name: name.clone(), pos: pos + "<".len() });
+            let innermost = self.open.len() - 1;
+            self.close(innermost, &|name, start| HtmlError::UnclosedElement { name: name, pos: start });
+          }
+          self.after_pre = name == "pre";
+          self.open.push((name, attributes, Vec::new(), pos + "<".len()));
+        }
+      }
+      Token::EndTag { name } => match self.open.iter().rposition(|open| open.0 == name) {
+        Some(index) => {
+          self.close(index, &|open, _| HtmlError::MismatchedTag { expected: open, found: name.to_string(), pos: pos + "</".len() });
+        }
+        // 開いている要素のどれにも対応しない閉じタグは読み飛ばす
+        None => self.diagnostics.push(HtmlError::StrayEndTag { name: name.into_owned(), pos: pos + "</".len() }),
+      },
+      Token::Text(text) => {
+        let raw_text = self.open.last().map_or(false, |open| is_raw_text_element(&open.0));
+        let preformatted = self.open.iter().any(|open| open.0 == "pre");
+        let text = if raw_text {
+          if text.trim().is_empty() { "" } else { &*text }
+        } else if preformatted {
+          // <pre> の直後の改行は中身に入れない
+          if after_pre { text.trim_start_matches("\r\n").trim_start_matches('\n') } else { &*text }
+        } else {
+          text.trim_start()
+        };
+        if !text.is_empty() {
+          self.append(dom::text(text.to_string()));
+        }
       }
-      let (name, value) = self.parse_attr();
-      attributes.insert(name, value);
+      Token::Comment(data) => self.append(dom::comment(data.into_owned())),
+      // <!DOCTYPE html> などはノードを作らない
+      Token::Doctype(_) => {}
     }
-    return attributes;
   }
 
-  // 要素
-  fn parse_element(&mut self) -> dom::Node {
-
-    // 開始の開始〜終了
-    assert_eq!(self.consume_char(), '<'); // 開始
-    let tag_name = self.parse_tag_name(); // タグ名
-    let attrs = self.parse_attributes(); // 属性
-    assert_eq!(self.consume_char(), '>'); //　終了
+  // 入力の終わり。閉じていない要素を閉じてルートを返す
+  pub fn finish(mut self) -> (dom::Node, Vec<HtmlError>) {
+    while let Some((name, attributes, children, start)) = self.open.pop() {
+      if !OPTIONAL_END_TAG_ELEMENTS.contains(&&*name) {
+        self.diagnostics.push(HtmlError::UnclosedElement { name: name.clone(), pos: start });
+      }
+      self.append(dom::elem(name, attributes, children));
+    }
 
-    // 子
-    let children = self.parse_nodes(); // children
+    // ルートの外のコメントは残さない
+    let mut nodes = self.nodes;
+    nodes.retain(|node| !matches!(node.node_type, dom::NodeType::Comment(_)));
 
-    // 閉じの開始〜終了
-    assert_eq!(self.consume_char(), '<'); // 開始
-    assert_eq!(self.consume_char(), '/'); // slash
-    assert_eq!(self.parse_tag_name(), tag_name); // 開始時とタグ名が一致しているか
-    assert_eq!(self.consume_char(), '>'); // 終了
+    let root = if nodes.len() == 1 {
+      nodes.swap_remove(0)
+    } else {
+      dom::elem("html".to_string(), dom::AttrMap::new(), nodes)
+    };
+    return (root, self.diagnostics);
+  }
 
-    return dom::elem(tag_name, attrs, children);
+  // いちばん内側の開いている要素（なければルートの外）に node を入れる
+  fn append(&mut self, node: dom::Node) {
+    match self.open.last_mut() {
+      Some(open) => open.2.push(node),
+      None => self.nodes.push(node),
+    }
   }
 
-  // Node
-  fn parse_node(&mut self) -> dom::Node {
-    return match self.next_char() {
-      '<' => self.parse_element(),
-      _ => self.parse_text()
+  // self.open の index 番目の要素とその内側を閉じる。内側の要素のうち終了タグを省略できないものは、タグ名と開始タグの位置から作る error で警告する
+  fn close(&mut self, index: usize, error: &dyn Fn(String, usize) -> HtmlError) {
+    while self.open.len() > index {
+      if let Some((name, attributes, children, start)) = self.open.pop() {
+        if self.open.len() > index && !OPTIONAL_END_TAG_ELEMENTS.contains(&&*name) {
+          self.diagnostics.push(error(name.clone(), start));
+        }
+        self.append(dom::elem(name, attributes, children));
+      }
     }
   }
 
-  // 全 Node
-  fn parse_nodes(&mut self) -> Vec<dom::Node> {
-    let mut nodes = Vec::new();
-    loop {
-      println!("html: nodes_start");
-      self.consume_whitespace(); // スペースは除外
-      if self.eof() || self.starts_with("</") {
-        println!("html: nodes_end");
-        break;
+  // 開始タグ name で閉じる、開いている要素の self.open での位置。内側から探して、SCOPE_ELEMENTS
+  // （<li> なら <ul> と <ol> も、<dt> と <dd> なら <dl> も）より外は探さない
+  fn implied_end(&self, name: &str) -> Option<usize> {
+    for (i, &(ref open, _, _, _)) in self.open.iter().enumerate().rev() {
+      if closed_by(open, name) {
+        return Some(i);
+      }
+      let list = match name {
+        "li" => open == "ul" || open == "ol",
+        "dt" | "dd" => open == "dl",
+        "option" | "optgroup" => open == "select",
+        _ => false,
+      };
+      if list || SCOPE_ELEMENTS.contains(&&**open) {
+        return None;
       }
-      nodes.push(self.parse_node());
     }
-    return nodes;
+    return None;
   }
 }
 
-// Parse
-pub fn parse(source: String) -> dom::Node {
-  println!("html: start");
-  let mut nodes = Parser { pos: 0, input: source }.parse_nodes();
-  println!("html: end");
+// 開いている open の要素を、開始タグ name で閉じるか
+fn closed_by(open: &str, name: &str) -> bool {
+  return match open {
+    "p" => CLOSES_P.contains(&name),
+    "li" => name == "li",
+    "dt" | "dd" => name == "dt" || name == "dd",
+    "option" => name == "option" || name == "optgroup",
+    "td" | "th" => ["td", "th", "tr", "thead", "tbody", "tfoot"].contains(&name),
+    "tr" => ["tr", "thead", "tbody", "tfoot"].contains(&name),
+    "thead" | "tbody" => name == "tbody" || name == "tfoot",
+    _ => false,
+  };
+}
+
+fn is_void_element(tag_name: &str) -> bool {
+  return VOID_ELEMENTS.iter().any(|name| name.eq_ignore_ascii_case(tag_name));
+}
+
+// Parse。壊れたところがあれば、最初のものをエラーにする
+pub fn parse(source: String) -> Result<dom::Node, HtmlError> {
+  let (root, mut diagnostics) = parse_with_diagnostics(source);
+  if !diagnostics.is_empty() {
+    return Err(diagnostics.remove(0));
+  }
+  return Ok(root);
+}
 
-  if nodes.len() == 1 {
-    return nodes.swap_remove(0) 
-  } else {
-    return dom::elem("html".to_string(), HashMap::new(), nodes)
+// 壊れたところを補ってツリーを作り、補ったところと一緒に返す
+pub fn parse_with_diagnostics(source: String) -> (dom::Node, Vec<HtmlError>) {
+  debug!("start");
+  let mut span = trace::span("parse", "html");
+  span.arg("bytes", source.len() as f64);
+  let mut builder = TreeBuilder::new();
+  // 入力を借りたトークンから、ノードの文字列だけを作る
+  let mut tokens = Tokens::new(&source);
+  while let Some(token) = tokens.next() {
+    builder.diagnostics.append(&mut tokens.diagnostics);
+    builder.push(token, tokens.token_start());
   }
-}
\ No newline at end of file
+  builder.diagnostics.append(&mut tokens.diagnostics);
+  debug!("end");
+  return builder.finish();
+}
@@ -0,0 +1,640 @@
+use std::borrow::Cow;
+
+use super::HtmlError;
+
+/**
+ * HTML のトークナイザー。入力を開始タグ、閉じタグ、テキスト、コメント、DOCTYPE のトークンに分ける（ツリーは作らない）。
+ *
+ * 入力は一度に渡しても（Tokenizer::new）、少しずつ足してもよい（Tokenizer::streaming と feed）。少しずつ足すときは、
+ * 最後まで来ていないトークン（> のないタグや、まだ続くかもしれないテキスト）は、続きが feed されるか finish が呼ばれるまで返さない。
+ * タグ名と属性名は小文字にし、テキストと属性の値の文字参照は文字にする。<script> や <textarea> などの中身は閉じタグまでを 1 つのテキストにする。
+ * 読めない属性は飛ばし、タグとして読めないところは次の > まで飛ばして、diagnostics に入れる
+ */
+
+// 中身を HTML として解釈せずにそのままテキストにする要素（"<" や "&&"、"a > b" を含むので）
+const RAW_TEXT_ELEMENTS: &[&str] = &["iframe", "noembed", "noframes", "script", "style", "xmp"];
+
+// 中身をテキストとして読み、文字参照だけを文字にする要素
+const ESCAPABLE_RAW_TEXT_ELEMENTS: &[&str] = &["textarea", "title"];
+
+// 名前で書ける文字参照（名前順）
+const NAMED_ENTITIES: &[(&str, char)] = &[
+  ("AElig", '\u{c6}'),
+  ("Aacute", '\u{c1}'),
+  ("Agrave", '\u{c0}'),
+  ("Alpha", '\u{391}'),
+  ("Beta", '\u{392}'),
+  ("Ccedil", '\u{c7}'),
+  ("Delta", '\u{394}'),
+  ("Eacute", '\u{c9}'),
+  ("Gamma", '\u{393}'),
+  ("Lambda", '\u{39b}'),
+  ("Ntilde", '\u{d1}'),
+  ("Omega", '\u{3a9}'),
+  ("Ouml", '\u{d6}'),
+  ("Pi", '\u{3a0}'),
+  ("Sigma", '\u{3a3}'),
+  ("Uuml", '\u{dc}'),
+  ("aacute", '\u{e1}'),
+  ("acute", '\u{b4}'),
+  ("aelig", '\u{e6}'),
+  ("agrave", '\u{e0}'),
+  ("alpha", '\u{3b1}'),
+  ("amp", '&'),
+  ("apos", '\''),
+  ("auml", '\u{e4}'),
+  ("beta", '\u{3b2}'),
+  ("brvbar", '\u{a6}'),
+  ("bull", '\u{2022}'),
+  ("ccedil", '\u{e7}'),
+  ("cedil", '\u{b8}'),
+  ("cent", '\u{a2}'),
+  ("clubs", '\u{2663}'),
+  ("copy", '\u{a9}'),
+  ("curren", '\u{a4}'),
+  ("dagger", '\u{2020}'),
+  ("darr", '\u{2193}'),
+  ("deg", '\u{b0}'),
+  ("delta", '\u{3b4}'),
+  ("diams", '\u{2666}'),
+  ("divide", '\u{f7}'),
+  ("eacute", '\u{e9}'),
+  ("ecirc", '\u{ea}'),
+  ("egrave", '\u{e8}'),
+  ("emsp", '\u{2003}'),
+  ("ensp", '\u{2002}'),
+  ("epsilon", '\u{3b5}'),
+  ("euml", '\u{eb}'),
+  ("euro", '\u{20ac}'),
+  ("frac12", '\u{bd}'),
+  ("frac14", '\u{bc}'),
+  ("frac34", '\u{be}'),
+  ("gamma", '\u{3b3}'),
+  ("ge", '\u{2265}'),
+  ("gt", '>'),
+  ("harr", '\u{2194}'),
+  ("hearts", '\u{2665}'),
+  ("hellip", '\u{2026}'),
+  ("iacute", '\u{ed}'),
+  ("iexcl", '\u{a1}'),
+  ("infin", '\u{221e}'),
+  ("iquest", '\u{bf}'),
+  ("iuml", '\u{ef}'),
+  ("lambda", '\u{3bb}'),
+  ("laquo", '\u{ab}'),
+  ("larr", '\u{2190}'),
+  ("ldquo", '\u{201c}'),
+  ("le", '\u{2264}'),
+  ("lsaquo", '\u{2039}'),
+  ("lsquo", '\u{2018}'),
+  ("lt", '<'),
+  ("macr", '\u{af}'),
+  ("mdash", '\u{2014}'),
+  ("micro", '\u{b5}'),
+  ("middot", '\u{b7}'),
+  ("minus", '\u{2212}'),
+  ("mu", '\u{3bc}'),
+  ("nbsp", '\u{a0}'),
+  ("ndash", '\u{2013}'),
+  ("ne", '\u{2260}'),
+  ("not", '\u{ac}'),
+  ("ntilde", '\u{f1}'),
+  ("oacute", '\u{f3}'),
+  ("omega", '\u{3c9}'),
+  ("ordf", '\u{aa}'),
+  ("ordm", '\u{ba}'),
+  ("ouml", '\u{f6}'),
+  ("para", '\u{b6}'),
+  ("permil", '\u{2030}'),
+  ("pi", '\u{3c0}'),
+  ("plusmn", '\u{b1}'),
+  ("pound", '\u{a3}'),
+  ("prime", '\u{2032}'),
+  ("quot", '"'),
+  ("raquo", '\u{bb}'),
+  ("rarr", '\u{2192}'),
+  ("rdquo", '\u{201d}'),
+  ("reg", '\u{ae}'),
+  ("rsaquo", '\u{203a}'),
+  ("rsquo", '\u{2019}'),
+  ("sbquo", '\u{201a}'),
+  ("sect", '\u{a7}'),
+  ("shy", '\u{ad}'),
+  ("sigma", '\u{3c3}'),
+  ("spades", '\u{2660}'),
+  ("sup1", '\u{b9}'),
+  ("sup2", '\u{b2}'),
+  ("sup3", '\u{b3}'),
+  ("szlig", '\u{df}'),
+  ("theta", '\u{3b8}'),
+  ("thinsp", '\u{2009}'),
+  ("times", '\u{d7}'),
+  ("trade", '\u{2122}'),
+  ("uacute", '\u{fa}'),
+  ("uarr", '\u{2191}'),
+  ("uml", '\u{a8}'),
+  ("uuml", '\u{fc}'),
+  ("yen", '\u{a5}'),
+  ("zwj", '\u{200d}'),
+  ("zwnj", '\u{200c}'),
+];
+
+// トークン。文字列は入力を借りたもので、文字参照を文字にしたときと、タグ名や属性名を小文字にしたときだけ作り直す
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token<'a> {
+  StartTag { name: Cow<'a, str>, attributes: Vec<(Cow<'a, str>, Cow<'a, str>)>, self_closing: bool }, // attributes は書いた順。self_closing は <name/>
+  EndTag { name: Cow<'a, str> },
+  Text(Cow<'a, str>),
+  Comment(Cow<'a, str>), // <!-- と --> の間
+  Doctype(Cow<'a, str>), // <! と > の間（"DOCTYPE html" など）
+}
+
+impl<'a> Token<'a> {
+  // 入力を借りないトークンにする
+  pub fn into_owned(self) -> Token<'static> {
+    return match self {
+      Token::StartTag { name, attributes, self_closing } => Token::StartTag {
+        name: owned(name),
+        attributes: attributes.into_iter().map(|(name, value)| (owned(name), owned(value))).collect(),
+        self_closing: self_closing,
+      },
+      Token::EndTag { name } => Token::EndTag { name: owned(name) },
+      Token::Text(text) => Token::Text(owned(text)),
+      Token::Comment(data) => Token::Comment(owned(data)),
+      Token::Doctype(content) => Token::Doctype(owned(content)),
+    };
+  }
+}
+
+fn owned(s: Cow<str>) -> Cow<'static, str> {
+  return Cow::Owned(s.into_owned());
+}
+
+// 入力の文字列のほかに、読んでいるところについて持つもの
+struct State {
+  pos: usize, // 入力の中の現在の位置
+  offset: usize, // 入力の先頭の、入力全体でのバイト位置
+  finished: bool, // 入力がこれで終わりか
+  raw_text: Option<&'static str>, // 中身をテキストにする要素の開始タグの後なら、そのタグ名
+  token_start: usize, // 最後に返したトークンの、入力全体でのバイト位置
+}
+
+impl State {
+  fn new(finished: bool) -> State {
+    return State { pos: 0, offset: 0, finished: finished, raw_text: None, token_start: 0 };
+  }
+}
+
+// 入力全体を借りて、入力を借りたトークンを返す。ツリーを作らずにトークンだけを見るとき用で、文字列をほとんど作らない
+pub struct Tokens<'a> {
+  input: &'a str,
+  state: State,
+  pub diagnostics: Vec<HtmlError>, // 読み飛ばしたところ。pos は入力でのバイト位置
+}
+
+impl<'a> Tokens<'a> {
+  pub fn new(source: &'a str) -> Tokens<'a> {
+    return Tokens { input: source, state: State::new(true), diagnostics: Vec::new() };
+  }
+
+  // 最後に返したトークンの、入力でのバイト位置
+  pub fn token_start(&self) -> usize {
+    return self.state.token_start;
+  }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+  type Item = Token<'a>;
+
+  fn next(&mut self) -> Option<Token<'a>> {
+    return Lexer { input: self.input, state: &mut self.state, diagnostics: &mut self.diagnostics }.next_token();
+  }
+}
+
+// 入力を持っておき、入力を借りないトークンを返す。入力を少しずつ足せる
+pub struct Tokenizer {
+  input: String, // まだ読み終えていない入力（読み終えたところは feed で捨てる）
+  state: State,
+  pub diagnostics: Vec<HtmlError>, // 読み飛ばしたところ。pos は入力全体でのバイト位置
+}
+
+impl Tokenizer {
+  // source がすべての入力
+  pub fn new(source: String) -> Tokenizer {
+    return Tokenizer { input: source, state: State::new(true), diagnostics: Vec::new() };
+  }
+
+  // 入力を feed で少しずつ足す。足し終えたら finish を呼ぶ
+  pub fn streaming() -> Tokenizer {
+    return Tokenizer { input: String::new(), state: State::new(false), diagnostics: Vec::new() };
+  }
+
+  // 入力の続きを足す
+  pub fn feed(&mut self, chunk: &str) {
+    self.input.drain(..self.state.pos);
+    self.state.offset += self.state.pos;
+    self.state.pos = 0;
+    self.input.push_str(chunk);
+  }
+
+  // 入力の終わり。残りのトークンを返すようになる
+  pub fn finish(&mut self) {
+    self.state.finished = true;
+  }
+
+  // 最後に返したトークンの、入力全体でのバイト位置
+  pub fn token_start(&self) -> usize {
+    return self.state.token_start;
+  }
+}
+
+// 次のトークン。feed された分を読み終えたら None（finish の後なら入力の終わり）
+impl Iterator for Tokenizer {
+  type Item = Token<'static>;
+
+  fn next(&mut self) -> Option<Token<'static>> {
+    return Lexer { input: &self.input, state: &mut self.state, diagnostics: &mut self.diagnostics }.next_token().map(Token::into_owned);
+  }
+}
+
+// 入力を借りてトークンを読むところ。Tokens と Tokenizer の両方で使う
+struct Lexer<'a, 's> {
+  input: &'a str,
+  state: &'s mut State,
+  diagnostics: &'s mut Vec<HtmlError>,
+}
+
+impl<'a, 's> Lexer<'a, 's> {
+  fn next_token(&mut self) -> Option<Token<'a>> {
+    loop {
+      if self.eof() {
+        return None;
+      }
+      let start = self.state.pos;
+      let diagnostics = self.diagnostics.len();
+      self.state.token_start = self.source_pos();
+      match self.parse_token() {
+        Ok(Some(token)) => return Some(token),
+        Ok(None) => {}
+        Err(HtmlError::UnexpectedEof) if !self.state.finished => {
+          // 続きが feed されてから読み直す
+          self.state.pos = start;
+          self.diagnostics.truncate(diagnostics);
+          return None;
+        }
+        Err(HtmlError::UnexpectedEof) => {
+          self.diagnostics.push(HtmlError::UnexpectedEof);
+          self.state.pos = self.input.len();
+        }
+        Err(error) => {
+          // 読めなかったタグは次の > まで飛ばす
+          self.state.pos = start + "<".len();
+          if self.skip_tag().is_err() {
+            self.state.pos = start;
+            self.diagnostics.truncate(diagnostics);
+            return None;
+          }
+          self.diagnostics.push(error);
+        }
+      }
+    }
+  }
+
+  // 入力全体での現在の位置
+  fn source_pos(&self) -> usize {
+    return self.state.offset + self.state.pos;
+  }
+
+  // 現在の位置からの入力
+  fn rest(&self) -> &'a str {
+    let input: &'a str = self.input;
+    return &input[self.state.pos..];
+  }
+
+  // char の読み取り
+  fn next_char(&self) -> Result<char, HtmlError> {
+    return self.rest().chars().next().ok_or(HtmlError::UnexpectedEof)
+  }
+
+  // 次の文字が、引数 s で始まるか
+  fn starts_with(&self, s: &str) -> bool {
+    return self.rest().starts_with(s)
+  }
+
+  // EOF（feed された分の終わり）
+  fn eof(&self) -> bool {
+    return self.state.pos >= self.input.len()
+  }
+
+  // マルチバイト文字に対応するためのメソッド
+  fn consume_char(&mut self) -> Result<char, HtmlError> {
+    let cur_char = self.next_char()?;
+
+    // advance（マルチバイト文字のぶんだけ進める）
+    self.state.pos += cur_char.len_utf8();
+
+    trace!("cur_char: {}", cur_char);
+
+    // 現在の文字を返す
+    return Ok(cur_char);
+  }
+
+  // 次の文字が expected であることを確かめて進める
+  fn expect_char(&mut self, expected: char) -> Result<(), HtmlError> {
+    let pos = self.source_pos();
+    let found = self.consume_char()?;
+    if found != expected {
+      return Err(HtmlError::UnexpectedChar { expected: expected, found: found, pos: pos });
+    }
+    return Ok(());
+  }
+
+  // test を満たす文字が続く間を読んで、入力のその部分を返す
+  fn consume_while<F>(&mut self, test: F) -> &'a str
+    where F: Fn(char) -> bool {
+      let rest = self.rest();
+      let len = rest.find(|c: char| !test(c)).unwrap_or(rest.len());
+      self.state.pos += len;
+      return &rest[..len];
+    }
+
+  // スペース文字
+  fn consume_whitespace(&mut self) {
+    self.consume_while(char::is_whitespace);
+  }
+
+  // タグ名（小文字にそろえる）
+  fn parse_tag_name(&mut self) -> Cow<'a, str> {
+    return lowercase(self.consume_while(|c| c.is_ascii_alphanumeric()));
+  }
+
+  // 属性の値。クォートがなければ空白か > まで
+  fn parse_attr_value(&mut self) -> Result<Cow<'a, str>, HtmlError> {
+    let open_quote = self.next_char()?;
+    if open_quote != '"' && open_quote != '\'' { // " か ' が含まれるため
+      return Ok(decode_entities(self.consume_while(|c| !c.is_whitespace() && c != '>')));
+    }
+    self.consume_char()?;
+    let value = self.consume_while(|c| c != open_quote);
+    self.expect_char(open_quote)?;
+    return Ok(decode_entities(value));
+  }
+
+  // 属性
+  // 属性名は aria-label や data-* のように - などを含められる
+  fn parse_attr_name(&mut self) -> &'a str {
+    return self.consume_while(|c| match c {
+      'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | ':' | '.' => true,
+      _ => false
+    })
+  }
+
+  // (属性名、値)を返す。disabled のように値のない属性は空の値
+  fn parse_attr(&mut self) -> Result<(Cow<'a, str>, Cow<'a, str>), HtmlError> {
+    let pos = self.source_pos();
+    let name = self.parse_attr_name();
+    if name.is_empty() {
+      return Err(HtmlError::UnexpectedChar { expected: '>', found: self.next_char()?, pos: pos });
+    }
+    self.consume_whitespace();
+    if !self.starts_with("=") {
+      return Ok((lowercase(name), Cow::Borrowed("")));
+    }
+    self.expect_char('=')?;
+    self.consume_whitespace();
+    let value = self.parse_attr_value()?;
+    return Ok((lowercase(name), value));
+  }
+
+  // 全属性。読めない文字は 1 つずつ飛ばす
+  fn parse_attributes(&mut self) -> Result<Vec<(Cow<'a, str>, Cow<'a, str>)>, HtmlError> {
+    let mut attributes = Vec::new();
+    loop {
+      self.consume_whitespace(); // スペースは除外
+      if self.starts_with(">") || self.starts_with("/>") {
+        break;
+      }
+      match self.parse_attr() {
+        Ok(attribute) => attributes.push(attribute),
+        Err(HtmlError::UnexpectedEof) => return Err(HtmlError::UnexpectedEof),
+        Err(error) => {
+          self.diagnostics.push(error);
+          self.consume_char()?;
+        }
+      }
+    }
+    return Ok(attributes);
+  }
+
+  // 開始タグ。<script> などの後は、閉じタグまでを中身のテキストとして読む
+  fn parse_start_tag(&mut self) -> Result<Token<'a>, HtmlError> {
+    self.expect_char('<')?;
+    let name = self.parse_tag_name();
+    let attributes = self.parse_attributes()?;
+    let self_closing = self.starts_with("/");
+    if self_closing {
+      self.expect_char('/')?;
+    }
+    self.expect_char('>')?;
+    if !self_closing {
+      self.state.raw_text = raw_text_element(&name);
+    }
+    return Ok(Token::StartTag { name: name, attributes: attributes, self_closing: self_closing });
+  }
+
+  // 閉じタグ。タグ名の後は > まで読み飛ばす（入力の終わりまで > がなくてもよい）
+  fn parse_end_tag(&mut self) -> Result<Token<'a>, HtmlError> {
+    self.state.pos += "</".len();
+    let name = self.parse_tag_name();
+    self.skip_tag()?;
+    return Ok(Token::EndTag { name: name });
+  }
+
+  // 次の > までを読み飛ばす（> も読む）。続きの入力があるなら、> が来るまで待つ
+  fn skip_tag(&mut self) -> Result<(), HtmlError> {
+    self.consume_while(|c| c != '>');
+    if self.eof() && !self.state.finished {
+      return Err(HtmlError::UnexpectedEof);
+    }
+    if !self.eof() {
+      self.consume_char()?;
+    }
+    return Ok(());
+  }
+
+  // tag_name の閉じタグ（大文字小文字は区別しない。</scripts> などは閉じタグではない）の前までのテキスト。
+  // 閉じタグがなければ最後まで。空なら None
+  fn parse_raw_text(&mut self, tag_name: &str) -> Result<Option<Token<'a>>, HtmlError> {
+    let rest = self.rest();
+    let end = rest.match_indices("</").map(|(index, _)| index).find(|&index| {
+      let name = &rest[index + "</".len()..];
+      let boundary = name.get(tag_name.len()..).map_or(false, |after| after.is_empty() || after.starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()));
+      return boundary && name[..tag_name.len()].eq_ignore_ascii_case(tag_name);
+    });
+    let len = match end {
+      Some(len) => len,
+      None if !self.state.finished => return Err(HtmlError::UnexpectedEof),
+      None => rest.len(),
+    };
+    let text = &rest[..len];
+    self.state.pos += len;
+    if text.is_empty() {
+      return Ok(None);
+    }
+    let escapable = ESCAPABLE_RAW_TEXT_ELEMENTS.contains(&tag_name);
+    return Ok(Some(Token::Text(if escapable { decode_entities(text) } else { Cow::Borrowed(text) })));
+  }
+
+  // テキスト。タグにならない < （a < b など）も文字にする
+  fn parse_text(&mut self) -> Result<Token<'a>, HtmlError> {
+    let start = self.state.pos;
+    self.consume_char()?;
+    loop {
+      match self.rest().find('<') {
+        Some(index) => self.state.pos += index,
+        None => {
+          self.state.pos = self.input.len();
+          break;
+        }
+      }
+      if self.markup_starts()? {
+        break;
+      }
+      self.state.pos += "<".len();
+    }
+    if self.eof() && !self.state.finished {
+      return Err(HtmlError::UnexpectedEof);
+    }
+    let input: &'a str = self.input;
+    return Ok(Token::Text(decode_entities(&input[start..self.state.pos])));
+  }
+
+  // 今の位置の < がタグやコメントの始まりか。< の後に英字か / か ! がなければテキスト
+  fn markup_starts(&self) -> Result<bool, HtmlError> {
+    let rest = &self.rest()["<".len()..];
+    if rest.is_empty() && !self.state.finished {
+      return Err(HtmlError::UnexpectedEof);
+    }
+    return Ok(rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!'));
+  }
+
+  // コメント。<!-- から --> までをそのまま持つ。閉じていなければ最後まで
+  fn parse_comment(&mut self) -> Result<Token<'a>, HtmlError> {
+    self.state.pos += "<!--".len();
+    let rest = self.rest();
+    let (len, end) = match rest.find("-->") {
+      Some(len) => (len, "-->".len()),
+      None if !self.state.finished => return Err(HtmlError::UnexpectedEof),
+      None => {
+        self.diagnostics.push(HtmlError::UnexpectedEof);
+        (rest.len(), 0)
+      }
+    };
+    self.state.pos += len + end;
+    return Ok(Token::Comment(Cow::Borrowed(&rest[..len])));
+  }
+
+  // <!DOCTYPE html> など
+  fn parse_doctype(&mut self) -> Result<Token<'a>, HtmlError> {
+    self.state.pos += "<!".len();
+    let content = self.consume_while(|c| c != '>');
+    self.expect_char('>')?;
+    return Ok(Token::Doctype(Cow::Borrowed(content)));
+  }
+
+  // 今の位置からトークンを 1 つ読む。読み飛ばしただけなら None
+  fn parse_token(&mut self) -> Result<Option<Token<'a>>, HtmlError> {
+    if let Some(tag_name) = self.state.raw_text {
+      let text = self.parse_raw_text(tag_name)?;
+      self.state.raw_text = None;
+      return Ok(text);
+    }
+    let rest = self.rest();
+    if !self.state.finished && "<!--".starts_with(rest) {
+      // <!-- の途中で切れていて、コメントか DOCTYPE かテキストか分からない
+      return Err(HtmlError::UnexpectedEof);
+    }
+    if rest.starts_with("<!--") {
+      return self.parse_comment().map(Some);
+    }
+    if rest.starts_with("</") {
+      return self.parse_end_tag().map(Some);
+    }
+    if rest.starts_with("<!") {
+      return self.parse_doctype().map(Some);
+    }
+    if rest.strip_prefix('<').map_or(false, |rest| rest.starts_with(|c: char| c.is_ascii_alphabetic())) {
+      return self.parse_start_tag().map(Some);
+    }
+    return self.parse_text().map(Some);
+  }
+}
+
+// 大文字を含むときだけ、小文字にしたものを作る
+fn lowercase(s: &str) -> Cow<'_, str> {
+  if s.bytes().any(|b| b.is_ascii_uppercase()) {
+    return Cow::Owned(s.to_ascii_lowercase());
+  }
+  return Cow::Borrowed(s);
+}
+
+// 中身をテキストとして読む要素なら、そのタグ名
+fn raw_text_element(tag_name: &str) -> Option<&'static str> {
+  return RAW_TEXT_ELEMENTS.iter().chain(ESCAPABLE_RAW_TEXT_ELEMENTS).find(|&&name| name == tag_name).cloned();
+}
+
+// 中身をテキストとして読む要素か
+pub(super) fn is_raw_text_element(tag_name: &str) -> bool {
+  return raw_text_element(tag_name).is_some();
+}
+
+// 文字参照を文字にする。番号の ; は省略でき、0、サロゲート、U+10FFFF より大きい番号は U+FFFD にする。
+// 文字参照がなければ text をそのまま返す
+fn decode_entities(text: &str) -> Cow<'_, str> {
+  if !text.contains('&') {
+    return Cow::Borrowed(text);
+  }
+  let mut decoded = String::with_capacity(text.len());
+  let mut rest = text;
+  while let Some(start) = rest.find('&') {
+    decoded.push_str(&rest[..start]);
+    rest = &rest[start..];
+    match entity(rest) {
+      Some((c, len)) => {
+        decoded.push(c);
+        rest = &rest[len..];
+      }
+      None => {
+        decoded.push('&');
+        rest = &rest[1..];
+      }
+    }
+  }
+  decoded.push_str(rest);
+  return Cow::Owned(decoded);
+}
+
+// & で始まる source の先頭の文字参照の文字と、読んだバイト数。文字参照でなければ None
+fn entity(source: &str) -> Option<(char, usize)> {
+  let body = &source[1..];
+  if let Some(number) = body.strip_prefix('#') {
+    let (digits, radix, prefix) = match number.strip_prefix('x').or_else(|| number.strip_prefix('X')) {
+      Some(hex) => (hex.chars().take_while(|c| c.is_ascii_hexdigit()).count(), 16, "#x".len()),
+      None => (number.chars().take_while(|c| c.is_ascii_digit()).count(), 10, "#".len()),
+    };
+    if digits == 0 {
+      return None;
+    }
+    let digits_text = &body[prefix..prefix + digits];
+    let semicolon = if body[prefix + digits..].starts_with(';') { 1 } else { 0 };
+    let c = u32::from_str_radix(digits_text, radix).ok().and_then(|code| if code == 0 { None } else { std::char::from_u32(code) }).unwrap_or('\u{fffd}');
+    return Some((c, 1 + prefix + digits + semicolon));
+  }
+  let name_len = body.chars().take_while(|c| c.is_ascii_alphanumeric()).count();
+  if !body[name_len..].starts_with(';') {
+    return None;
+  }
+  let name = &body[..name_len];
+  let index = NAMED_ENTITIES.binary_search_by(|&(entity, _)| entity.cmp(name)).ok()?;
+  return Some((NAMED_ENTITIES[index].1, 1 + name_len + 1));
+}
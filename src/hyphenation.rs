@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/**
+ * パターンによるハイフネーション（Liang のアルゴリズム。TeX と同じ形式のパターンを使う）。
+ * hyphens: auto の要素の中で、lang 属性の言語のパターンで単語の中に改行できる位置を足すのに使う（text::line_breaks_hyphenated）。
+ *
+ *   let patterns = Patterns::parse("hy3ph he2n hena4 hen5at 1na n2at 1tio 2io o2n");
+ *   patterns.hyphenate("hyphenation") // [2, 6] → hy-phen-ation
+ *
+ * 組み込みのパターンは英語の小さなものだけ。ほかの言語や完全なパターン（hyph-en-us.pat.txt など）は
+ * Patterns::parse で読み込んで渡す
+ */
+
+// 英語の接頭辞、接尾辞と、よく使われる並びのパターン（TeX の hyph-en-us から一部を選んだもの）
+const ENGLISH: &'static str = "
+  .ach4 .ad4der .af1t .al3t .am5at .an5c .ang4 .ani5m .ant4 .an3te .anti5s .ar5s .ar4tie .ar4ty .as3c .as1p .as1s .aster5
+  .atom5 .au1d .av4i .awn4 .ba4g .ba5na .bas4e .ber4 .be5ra .be3sm .be5sto .bri2 .but4ti .cam4pe .can5c .capa5b .car5ol
+  .ca4t .ce4la .ch4 .chill5i .ci2 .cit5r .co3e .co4r .cor5ner .de4moi .de3o .de3ra .de3ri .des4c .dictio5 .do4t .du4c
+  .dumb5 .earth5 .eas3i .eb4 .eer4 .eg2 .el5d .el3em .enam3 .en3g .en3s .eq5ui5t .er4ri .es3 .eu3 .eye5 .fes3 .for5mer
+  .ga2 .ge2 .gen3t4 .ge5og .gi5a .gi4b .go4r .hand5i .han5k .he2 .hero5i .hes3 .het3 .hi3b .hi3er .hon5ey .hon3o .hov5
+  .id4l .idol3 .im3m .im5pin .in1 .in3ci .ine2 .in2k .in3s .ir5r .is4i .ju3r .la4cy .la4m .lat5er .lath5 .le2 .leg5e
+  .len4 .lep5 .lev1 .li4g .lig5a .li2n .li3o .li4t .mag5a5 .mal5o .man5a .mar5ti .me2 .mer3c .me5ter .mis1 .mist5i
+  .mon3e .mo3ro .mu5ta .muta5b .ni4c .od2 .odd5 .of5te .or5ato .or3c .or1d .or3t .os3 .os4tl .oth3 .out3 .ped5al .pe5te
+  .pe5tit .pi4e .pio5n .pi2t .pre3m .ra4c .ran4t .ratio5na .ree2 .re5mit .res2 .re5stat .ri4g .rit5u .ro4q .ros5t
+  .row5d .ru4d .sci3e .self5 .sell5 .se2n .se5rie .sh2 .si2 .sing4 .st4 .sta5bl .sy2 .ta4 .te4 .ten5an .th2 .ti2 .til4
+  .tim5o5 .ting4 .tin5k .ton4a .to4p .top5i .tou5s .trib5ut .un1a .un3ce .under5 .un1e .un5k .un5o .un3u .up3 .ure3
+  .us5a .ven4de .ve5ra .wil5i .ye4
+  hy3ph he2n hena4 hen5at 1na n2at 1tio 2io o2n
+  1ment 1ness 4ness. 1less 4less. 5tions. 1sion 1tial 1cial 1tious 1cious
+  1ty. 4ity 2i1ty 1ble. 4able. a5ble. 4ible. i5ble.
+  2ed. 4es. 2s.
+  4bl 4br 4ch 4cl 4cr 4dr 4fl 4fr 4gl 4gr 4pl 4pr 4sc 4sh 4sk 4sl 4sm 4sn 4sp 4st 4sw 4th 4tr 4tw 4wh 4wr 4gh 4ck
+  b1b c1c d1d f1f g1g l1l m1m n1n p1p r1r s1s t1t z1z
+  n1c n1d n1g n1s n1t n1v r1b r1c r1d r1f r1g r1k r1l r1m r1n r1p r1s r1t r1v l1b l1c l1d l1f l1g l1m l1p l1t l1v
+  m1b m1p s1c s1p s1t c1t p1t
+";
+
+// 単語の先頭と末尾にこれより短い部分を残さない（TeX の \lefthyphenmin と \righthyphenmin）
+const LEFT_MIN: usize = 2;
+const RIGHT_MIN: usize = 3;
+
+#[derive(Debug, Clone, Default)]
+pub struct Patterns {
+  values: HashMap<String, Vec<u8>>, // 文字の並びと、その間の値（文字数 + 1 個）
+  exceptions: HashMap<String, Vec<usize>>, // 例外の単語と、ハイフンを入れる位置（文字数）
+  max_length: usize,
+}
+
+impl Patterns {
+  // 空白で区切った TeX のパターン（% から行末まではコメント）。- を含むものは例外の単語（ta-ble など）
+  pub fn parse(source: &str) -> Patterns {
+    let mut patterns = Patterns::default();
+    for line in source.lines() {
+      let line = line.split('%').next().unwrap_or("");
+      for pattern in line.split_whitespace() {
+        if pattern.contains('-') {
+          patterns.add_exception(pattern);
+        } else {
+          patterns.add_pattern(pattern);
+        }
+      }
+    }
+    return patterns;
+  }
+
+  fn add_pattern(&mut self, pattern: &str) {
+    let mut letters = String::new();
+    let mut values = vec![0];
+    for c in pattern.chars() {
+      match c.to_digit(10) {
+        Some(value) => *values.last_mut().unwrap() = value as u8,
+        None => {
+          letters.extend(c.to_lowercase());
+          values.push(0);
+        }
+      }
+    }
+    self.max_length = self.max_length.max(letters.chars().count());
+    self.values.insert(letters, values);
+  }
+
+  fn add_exception(&mut self, word: &str) {
+    let mut positions = Vec::new();
+    let mut letters = String::new();
+    let mut count = 0;
+    for c in word.chars() {
+      if c == '-' {
+        positions.push(count);
+      } else {
+        letters.extend(c.to_lowercase());
+        count += 1;
+      }
+    }
+    self.exceptions.insert(letters, positions);
+  }
+
+  // 単語の中でハイフンを入れて改行できる位置（word のバイト位置）。英字以外を含む単語は分けない
+  pub fn hyphenate(&self, word: &str) -> Vec<usize> {
+    let chars: Vec<(usize, char)> = word.char_indices().collect();
+    if chars.len() < LEFT_MIN + RIGHT_MIN || !chars.iter().all(|&(_, c)| c.is_alphabetic()) {
+      return Vec::new();
+    }
+    let lower: String = word.chars().flat_map(|c| c.to_lowercase()).collect();
+    if lower.chars().count() != chars.len() {
+      return Vec::new(); // 小文字にすると長さが変わる文字は扱わない
+    }
+    if let Some(positions) = self.exceptions.get(&lower) {
+      return positions.iter().map(|&position| chars[position].0).collect();
+    }
+
+    // .word. のすべての部分文字列をパターンと照らして、文字の間ごとに最大の値を取る
+    let text: Vec<char> = format!(".{}.", lower).chars().collect();
+    let mut points = vec![0u8; text.len() + 1];
+    for start in 0..text.len() {
+      let mut key = String::new();
+      for end in start..text.len().min(start + self.max_length) {
+        key.push(text[end]);
+        if let Some(values) = self.values.get(&key) {
+          for (offset, &value) in values.iter().enumerate() {
+            points[start + offset] = points[start + offset].max(value);
+          }
+        }
+      }
+    }
+
+    // 単語の k 文字目の前は points[k + 1]（先頭の . の分ずれる）。奇数ならハイフンを入れられる
+    return (LEFT_MIN..=chars.len() - RIGHT_MIN).filter(|&k| points[k + 1] % 2 == 1).map(|k| chars[k].0).collect();
+  }
+}
+
+// lang 属性（en、en-US など）に合う組み込みのパターン
+pub fn patterns_for(lang: &str) -> Option<&'static Patterns> {
+  let primary = lang.split(|c| c == '-' || c == '_').next().unwrap_or("").to_ascii_lowercase();
+  return match &*primary {
+    "en" => {
+      static ENGLISH_PATTERNS: OnceLock<Patterns> = OnceLock::new();
+      Some(ENGLISH_PATTERNS.get_or_init(|| Patterns::parse(ENGLISH)))
+    }
+    _ => None,
+  };
+}
@@ -0,0 +1,154 @@
+use css::{Color, StyleSheet, Value};
+use dom::{Node, NodeType};
+use loader::Loader;
+use options::EngineOptions;
+use parallel;
+use profile::{self, Timings};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ptr;
+use std::sync::Arc;
+use thiserror::Error;
+use url::Url;
+
+/**
+ * 画像（<img src>、<video poster> と background-image: url(...)）を読み込んでピクセルにしておくところ。
+ *
+ * ドキュメントを読み込むときに、スタイルシートの後で（画像どうしは同時に）読み込んでデコードし、URL ごとに持っておく。
+ * レイアウトツリーを作るときに、<img> と <video> のボックスにはその画像を、background-image のあるボックスには背景の画像を付ける。
+ * 大きさは置換要素として決める（layout/replaced.rs）。描くときは <img> の画像と <video> のポスターはコンテンツボックスに合わせて拡大縮小し、
+ * 背景は画像の大きさのままパディングボックスに敷きつめる。
+ * デコードは image フィーチャーのときだけ（なければ画像は描かない）
+ */
+
+#[derive(Debug, Error)]
+pub enum ImageError {
+  #[error("failed to decode image: {0}")]
+  Decode(String),
+  #[error("image decoding is not supported in this build")]
+  Unsupported,
+}
+
+// デコードした画像。pixels は左上から行ごと
+pub struct Image {
+  pub width: usize,
+  pub height: usize,
+  pub pixels: Vec<Color>,
+}
+
+impl Image {
+  // (x, y) のピクセル。範囲外なら端のもの
+  pub fn pixel(&self, x: usize, y: usize) -> Color {
+    return self.pixels[y.min(self.height - 1) * self.width + x.min(self.width - 1)];
+  }
+}
+
+// 同じ画像を指していればピクセルは比べない
+impl PartialEq for Image {
+  fn eq(&self, other: &Image) -> bool {
+    return ptr::eq(self, other) || (self.width == other.width && self.height == other.height && self.pixels == other.pixels);
+  }
+}
+
+// ディスプレイリストのダンプなどにピクセルを並べないように、大きさだけにする
+impl fmt::Debug for Image {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Image({}x{})", self.width, self.height)
+  }
+}
+
+// 読み込んだ画像。<img> の src の相対 URL は base_url で解決する（スタイルシートの url(...) は解決済み）
+#[derive(Default)]
+pub struct Images {
+  base_url: Option<Url>,
+  decoded: HashMap<String, Arc<Image>>,
+}
+
+impl Images {
+  // <img src> と <video poster> の画像。読み込めなかったものやどちらでもなければ None
+  pub fn for_node(&self, node: &Node) -> Option<Arc<Image>> {
+    let url = self.base_url.as_ref()?.join(image_source(node)?)?;
+    return self.decoded.get(&url.to_string()).cloned();
+  }
+
+  // background-image の値の画像
+  pub fn background(&self, value: &Value) -> Option<Arc<Image>> {
+    return match *value {
+      Value::Url(ref url) => self.decoded.get(url).cloned(),
+      _ => None,
+    };
+  }
+}
+
+// ドキュメントの <img src>、<video poster> とスタイルシートの background-image の画像を（同じ URL は 1 回だけ）読み込む。
+// 読み込めなかったものやデコードできなかったものは警告して飛ばす。かかった時間は timings に足す
+pub fn load_images(root_node: &Node, stylesheet: &StyleSheet, base_url: &Url, loader: &Loader, options: &EngineOptions, sources: &mut Vec<Url>, timings: &mut Timings) -> Images {
+  let mut urls = Vec::new();
+  collect_image_sources(root_node, base_url, &mut urls);
+  for declaration in stylesheet.rules.iter().flat_map(|rule| &rule.declarations) {
+    if let ("background-image", Value::Url(ref href)) = (&*declaration.name, &declaration.value) {
+      urls.extend(Url::parse(href));
+    }
+  }
+  let mut seen = HashSet::new();
+  urls.retain(|url| seen.insert(url.to_string()));
+
+  let results = parallel::map(options.parallelism, &urls, |url| {
+    let mut image_timings = Timings::default();
+    let result = match profile::time(&mut image_timings.fetch, || loader.load(url)) {
+      Ok(resource) => {
+        let image = profile::time(&mut image_timings.parse, || decode(&resource.data));
+        image.map(|image| (image, resource.url.clone())).map_err(|error| error.to_string())
+      }
+      Err(error) => Err(error.to_string()),
+    };
+    (result, image_timings)
+  });
+
+  let mut images = Images { base_url: Some(base_url.clone()), decoded: HashMap::new() };
+  for (url, (result, image_timings)) in urls.iter().zip(results) {
+    timings.add(&image_timings);
+    match result {
+      Ok((image, resource_url)) => {
+        sources.push(resource_url);
+        images.decoded.insert(url.to_string(), Arc::new(image));
+      }
+      Err(error) => warn!("Failed to load image {}: {}", url, error),
+    }
+  }
+  return images;
+}
+
+// <img> の src と <video> の poster
+fn image_source(node: &Node) -> Option<&String> {
+  return match node.node_type {
+    NodeType::Element(ref elem) if elem.tag_name == "img" => elem.attributes.get("src"),
+    NodeType::Element(ref elem) if elem.tag_name == "video" => elem.attributes.get("poster"),
+    _ => None,
+  };
+}
+
+// ドキュメント順に <img> の src と <video> の poster を集める
+fn collect_image_sources(node: &Node, base_url: &Url, urls: &mut Vec<Url>) {
+  urls.extend(image_source(node).and_then(|src| base_url.join(src)));
+  for child in &node.children {
+    collect_image_sources(child, base_url, urls);
+  }
+}
+
+// PNG、JPEG、GIF などのバイト列を RGBA のピクセルにする
+#[cfg(feature = "image")]
+pub fn decode(data: &[u8]) -> Result<Image, ImageError> {
+  let decoded = ::image::load_from_memory(data).map_err(|error| ImageError::Decode(error.to_string()))?.to_rgba();
+  let (width, height) = (decoded.width() as usize, decoded.height() as usize);
+  if width == 0 || height == 0 {
+    return Err(ImageError::Decode("image has no pixels".to_string()));
+  }
+  let pixels = decoded.into_raw().chunks(4).map(|p| Color { r: p[0], g: p[1], b: p[2], a: p[3] }).collect();
+  return Ok(Image { width: width, height: height, pixels: pixels });
+}
+
+#[cfg(not(feature = "image"))]
+pub fn decode(_data: &[u8]) -> Result<Image, ImageError> {
+  return Err(ImageError::Unsupported);
+}
@@ -0,0 +1,115 @@
+use css;
+use dom::Node;
+use dump;
+use engine::LayoutNode;
+use error::EngineError;
+use layout::{self, Dimensions, Rect};
+use options::EngineOptions;
+use std::collections::BTreeMap;
+use style;
+use {initial_containing_block, LoadedDocument};
+
+/**
+ * --inspect で、セレクターに一致した要素のボックスモデルとカスケードの結果を調べるところ。
+ * テキストで見る開発者ツールのようなもの（書き出しは dump::inspect）
+ */
+
+#[derive(Debug, Clone)]
+pub struct Inspection {
+  pub node_index: usize,                // ドキュメント順（先行順）の番号
+  pub tag: String,                      // <div class="a"> のような開始タグ
+  pub dimensions: Option<Dimensions>,   // ボックスがなければ None（display: none など）
+  pub rules: Vec<MatchedRule>,          // 適用する順（後のものほど優先）
+  pub inline: Vec<MatchedDeclaration>,  // style="" の宣言
+  pub values: BTreeMap<String, String>, // 最終的な値
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchedRule {
+  pub index: usize,     // スタイルシートの中でのルールの番号
+  pub user_agent: bool, // 組み込みのスタイルシートのルールか
+  pub selector: String, // 一致したセレクター
+  pub specificity: css::Specificity,
+  pub declarations: Vec<MatchedDeclaration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchedDeclaration {
+  pub name: String,
+  pub value: String,
+  pub important: bool,
+  pub applied: bool, // false ならほかの宣言で上書きされた
+}
+
+// selector に一致するすべての要素（ドキュメント順）。レイアウトは options のビューポートの大きさで行う
+pub fn inspect(document: &LoadedDocument, selector: &str, options: &EngineOptions) -> Result<Vec<Inspection>, EngineError> {
+  let selectors = css::parse_selectors(selector)?;
+  let indices = style::query_selector_all(&document.root_node, &selectors);
+  return inspect_nodes(document, &indices, Rect { x: 0.0, y: 0.0, width: options.viewport_width, height: options.viewport_height });
+}
+
+// ドキュメント順の番号が indices の要素（ビューアでクリックした要素など）。レイアウトは viewport の大きさで行う
+pub fn inspect_nodes(document: &LoadedDocument, indices: &[usize], viewport: Rect) -> Result<Vec<Inspection>, EngineError> {
+  let (viewport, _) = document.layout_viewport(viewport);
+
+  let style_root = style::style_tree_with_viewport(&document.root_node, &document.stylesheet, viewport);
+  let layout_root = layout::layout_tree_with(&style_root, initial_containing_block(viewport), document.fonts, &document.images)?;
+  let layout = LayoutNode::new(&layout_root, &document.root_node);
+
+  let mut nodes = Vec::new();
+  collect_nodes(&document.root_node, &mut nodes);
+
+  let mut inspections = Vec::new();
+  for &index in indices {
+    let (ancestors, elem) = match style::element_path(&document.root_node, index) {
+      (ancestors, Some(elem)) => (ancestors, elem),
+      (_, None) => continue,
+    };
+    let trace = style::cascade_trace(elem, &ancestors, &document.stylesheet, Some(viewport));
+    let rules: Vec<MatchedRule> = trace
+      .rules
+      .iter()
+      .map(|entry| MatchedRule {
+        index: entry.rule_index,
+        user_agent: entry.rule.origin == css::Origin::UserAgent,
+        selector: entry.selector.to_string(),
+        specificity: entry.selector.specificity(),
+        declarations: entry.rule.declarations.iter().zip(&entry.applied).map(|(declaration, &applied)| matched_declaration(declaration, applied)).collect(),
+      })
+      .collect();
+    let inline: Vec<MatchedDeclaration> = trace.inline.iter().map(|&(ref declaration, applied)| matched_declaration(declaration, applied)).collect();
+    let values = rules
+      .iter()
+      .flat_map(|rule| rule.declarations.iter())
+      .chain(&inline)
+      .filter(|declaration| declaration.applied)
+      .map(|declaration| (declaration.name.clone(), declaration.value.clone()))
+      .collect();
+
+    inspections.push(Inspection {
+      node_index: index,
+      tag: dump::node_label(nodes[index]),
+      dimensions: layout.find_node(&[index]).map(|node| node.dimensions),
+      rules: rules,
+      inline: inline,
+      values: values,
+    });
+  }
+  return Ok(inspections);
+}
+
+fn matched_declaration(declaration: &css::Declaration, applied: bool) -> MatchedDeclaration {
+  return MatchedDeclaration {
+    name: declaration.name.clone(),
+    value: declaration.value.to_string(),
+    important: declaration.important,
+    applied: applied,
+  };
+}
+
+fn collect_nodes<'a>(node: &'a Node, nodes: &mut Vec<&'a Node>) {
+  nodes.push(node);
+  for child in &node.children {
+    collect_nodes(child, nodes);
+  }
+}
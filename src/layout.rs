@@ -1,9 +1,91 @@
-pub use self::BoxType::{AnonymousBlock, BlockNode, InlineNode};
-use css::Unit::Px;
-use css::Value;
-use css::Value::{Keyword, Length};
+mod block;
+mod flex;
+mod grid;
+mod hit_test;
+mod inline;
+mod replaced;
+mod table;
+
+pub use self::hit_test::hit_test;
+use self::inline::{collapse_whitespace, expand_tabs, Font, WhiteSpace};
+pub use self::BoxType::{AnonymousBlock, BlockNode, InlineBlockNode, InlineNode, ReplacedNode};
+use css::Unit::{Percent, Px, Vh, Vw};
+use css::{self, Color, Matrix, Value};
+use css::Value::{Keyword, Length, Number};
+use dom::{NodeId, NodeType};
+use form;
+use font::{self, GlyphRun};
+use images::{Image, Images};
+use std::collections::{HashMap, HashSet};
 use std::default::Default;
-use style::{StyledNode, Display};
+use std::sync::Arc;
+use std::vec;
+use dump;
+use profile;
+use style::{Clear, Display, Float, Overflow, Position, StyledNode};
+use thiserror::Error;
+use trace;
+
+/**
+ * ブロックとインラインのレイアウト。
+ *
+ * ブロックの中に並んだインラインの子は anonymous ブロックにまとめられ、そこでインライン整形コンテキストを作って行に並べる（inline.rs）。
+ * ブロックの幅と高さ、流れの中での位置は block.rs で決める。文字の幅は FontMetrics で測る（layout_tree はシステムのフォントの font::shared）。
+ * 置換要素（<img>、<canvas>、<video>）は ReplacedNode にして、インラインなら固有の大きさの箱を inline-block と同じように行に置き
+ * （下端をベースラインにそろえる）、ブロックなら width と height の auto を固有の大きさにする（replaced.rs）
+ *
+ * float するボックスは流れから外して、ブロック整形コンテキスト（ルートと float するボックスが作る）ごとの Floats に置く。
+ * 前の float より上には置かず、左右の float の間に入る高さまで下げる。行はその高さの float を除いた幅に並べ、
+ * clear のあるボックスはその側の float の下まで下げる。float の width の auto は中身を折り返さない幅（入らなければ包含ブロックの幅）
+ *
+ * position: absolute と fixed のボックスも流れから外し、流れの中にあったときの位置（静的位置）だけを覚えておく。
+ * 流れのレイアウトが終わったら、包含ブロック（absolute は position が static でないいちばん近い祖先のパディングボックス、
+ * fixed とどちらもないときはビューポート）の中に top、right、bottom、left で置く。auto の辺は静的位置のまま。
+ * width の auto は float と同じく中身の幅で、left と right の両方があれば残りの幅。
+ * relative のボックスは流れの中でレイアウトしてから、子孫ごと top、left（なければ -bottom、-right）だけずらす
+ *
+ * display: flex のボックスは子を flex アイテムにして、flex-direction の向きに 1 行に並べる（flex.rs）。
+ * display: grid のボックスは子を grid-template-columns と grid-template-rows のトラックのセルに順に置く（grid.rs）
+ *
+ * 長さの vw と vh は layout_tree に渡した包含ブロック（ビューポート）、margin、padding、width の % は包含ブロックの幅で px にする。
+ * height の % は包含ブロックの height が決まっているとき（containing_height）だけ、その高さで px にする（決まっていなければ auto）。
+ * em と rem はスタイルツリーで px になっている
+ */
+
+// 文字の大きさを測るもの
+pub trait FontMetrics {
+  // font_size の大きさで text を描いたときの幅
+  fn text_width(&self, text: &str, font_size: f32) -> f32;
+  // 行の高さ
+  fn line_height(&self, font_size: f32) -> f32;
+  // 行の上端からベースラインまで
+  fn baseline(&self, font_size: f32) -> f32;
+  // 描く字形とその位置。None なら字形の代わりに矩形を描く
+  fn glyph_run(&self, _text: &str, _font_size: f32) -> Option<GlyphRun> {
+    return None;
+  }
+  // face の字体で測って描くもの。None ならこれで測る（字体を区別しない）
+  fn select(&self, _face: &FontFace) -> Option<&dyn FontMetrics> {
+    return None;
+  }
+}
+
+// 文字の字体（font-family、font-weight、font-style）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FontFace<'a> {
+  pub families: &'a [&'a str], // font-family の順（serif などの総称もそのまま）
+  pub weight: f32,             // 1 から 1000（normal は 400、bold は 700）
+  pub italic: bool,            // italic か oblique
+}
+
+// レイアウト時のエラー
+#[derive(Debug, Error, PartialEq)]
+pub enum LayoutError {
+  #[error("root node has display: none")]
+  RootDisplayNone,
+  #[error("anonymous block box has no style node")]
+  AnonymousBlockStyle,
+}
 
 #[derive(Clone, Copy, Default, Debug)]
 pub struct Dimensions {
@@ -13,7 +95,7 @@ pub struct Dimensions {
   pub margin: EdgeSizes,
 }
 
-#[derive(Clone, Copy, Default, Debug)]
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
 pub struct Rect {
   pub x: f32,
   pub y: f32,
@@ -21,7 +103,7 @@ pub struct Rect {
   pub height: f32,
 }
 
-#[derive(Clone, Copy, Default, Debug)]
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
 pub struct EdgeSizes {
   pub left: f32,
   pub right: f32,
@@ -35,6 +117,44 @@ pub struct LayoutBox<'a> {
   pub dimensions: Dimensions,
   pub box_type: BoxType<'a>,
   pub children: Vec<LayoutBox<'a>>,
+  pub lines: Vec<Rect>, // anonymous ブロックのラインボックス
+  pub fragments: Vec<TextFragment>, // テキストと content の文字列を行ごとに分けたもの
+  pub image: Option<Arc<Image>>,            // <img> の画像（コンテンツボックスに描く）
+  pub background_image: Option<Arc<Image>>, // background-image の画像
+  pub float: Float,
+  pub position: Position,
+  pub visible: bool, // visibility が hidden でない（hidden なら場所だけ取って、背景やボーダーを描かない）
+  flex_width: Option<f32>, // flex アイテムと表のセルの width（flex-grow と flex-shrink や、列の幅で決めた幅）
+  collapse_first_child: bool, // 最初の子の上の margin を自分の上の margin とまとめた（子はコンテンツボックスの上端に置く）
+  containing_height: Option<f32>, // height などの % の基準になる包含ブロックの高さ。高さが中身で決まる（auto）なら None
+}
+
+// 行に置いた文字列。rect はフォントの行の高さ（line-height: normal）の分の箱で、文字はその中でベースラインに合わせて描く
+#[derive(Debug, Clone)]
+pub struct TextFragment {
+  pub text: String,
+  pub rect: Rect,
+  pub font_size: f32,
+  pub color: Color,
+  pub glyphs: Option<GlyphRun>,
+  pub visible: bool, // 文字列の要素の visibility が hidden でない
+  pub decorations: Vec<TextDecoration>,
+}
+
+// 文字列に引く線（text-decoration）。offset は断片の上端から線の上端まで。線の幅は断片の幅
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextDecoration {
+  pub line: DecorationLine,
+  pub offset: f32,
+  pub thickness: f32,
+  pub color: Color,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecorationLine {
+  Underline,
+  Overline,
+  LineThrough,
 }
 
 // block か、inline か
@@ -44,38 +164,103 @@ pub struct LayoutBox<'a> {
 pub enum BoxType<'a> {
   BlockNode(&'a StyledNode<'a>),
   InlineNode(&'a StyledNode<'a>),
+  InlineBlockNode(&'a StyledNode<'a>), // display: inline-block。行の中に 1 つの箱として置き、中はブロックとしてレイアウトする
+  ReplacedNode(&'a StyledNode<'a>),    // 置換要素。子を持たず、固有の大きさでレイアウトする
   AnonymousBlock,
 }
 
-pub fn layout_tree<'a>(node: &'a StyledNode<'a>, mut containing_block: Dimensions) -> LayoutBox<'a> {
+impl<'a> BoxType<'a> {
+  // ボックスを作った StyledNode。anonymous なら None
+  pub fn style_node(&self) -> Option<&'a StyledNode<'a>> {
+    match *self {
+      BlockNode(node) | InlineNode(node) | InlineBlockNode(node) | ReplacedNode(node) => Some(node),
+      AnonymousBlock => None,
+    }
+  }
+}
+
+// images は読み込んだ画像（<img> と background-image）。なければ Images::default()
+pub fn layout_tree<'a>(node: &'a StyledNode<'a>, containing_block: Dimensions, images: &Images) -> Result<LayoutBox<'a>, LayoutError> {
+  return layout_tree_with(node, containing_block, font::shared(), images);
+}
+
+// metrics で文字を測ってレイアウトする
+pub fn layout_tree_with<'a>(node: &'a StyledNode<'a>, containing_block: Dimensions, metrics: &dyn FontMetrics, images: &Images) -> Result<LayoutBox<'a>, LayoutError> {
+  return layout_root(node, containing_block, metrics, images, None);
+}
+
+// cache があれば、流れの中のブロックで前のレイアウトから変わらないものはレイアウトせずに前の形を使う
+fn layout_root<'a>(node: &'a StyledNode<'a>, mut containing_block: Dimensions, metrics: &dyn FontMetrics, images: &Images, cache: Option<&mut LayoutCache>) -> Result<LayoutBox<'a>, LayoutError> {
+  let _span = trace::span("layout", "layout");
+  let viewport = containing_block.content;
   containing_block.content.height = 0.0;
-  let mut root_box = build_layout_tree(node);
-  root_box.layout(containing_block);
-  return root_box;
+  let mut root_box = build_layout_tree(node, images)?;
+  root_box.containing_height = Some(viewport.height); // 初期包含ブロックの高さはビューポートの高さ
+  let mut floats = Floats::default();
+  root_box.layout(containing_block, node, metrics, viewport, &mut floats, cache)?;
+  root_box.contain_floats(&floats, viewport);
+  root_box.layout_positioned(viewport, metrics, viewport)?;
+  return Ok(root_box);
 }
 
 // レイアウトツリーの作成
-fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
+fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>, images: &Images) -> Result<LayoutBox<'a>, LayoutError> {
   // ルートのレイアウトを格納
-  let mut root = LayoutBox::new(match style_node.display() {
-    Block => BlockNode(style_node),
-    Inline => InlineNode(style_node),
-    DisplayNone => panic!("Root node has display: none."),
+  let root = LayoutBox::new(match style_node.display() {
+    Display::None => return Err(LayoutError::RootDisplayNone),
+    _ if replaced::is_replaced(style_node.node) => ReplacedNode(style_node),
+    Display::Block | Display::ListItem | Display::FlowRoot | Display::Flex | Display::Grid | Display::Table | Display::TableRowGroup | Display::TableRow | Display::TableCell => BlockNode(style_node),
+    Display::InlineBlock => InlineBlockNode(style_node),
+    Display::Inline => InlineNode(style_node),
   });
+  return build_children(root, style_node, images);
+}
+
+// flex アイテムや表のセルのように、display によらずブロックにするボックス。置換要素なら ReplacedNode
+fn block_node<'a>(style_node: &'a StyledNode<'a>) -> BoxType<'a> {
+  return if replaced::is_replaced(style_node.node) { ReplacedNode(style_node) } else { BlockNode(style_node) };
+}
+
+// root（style_node のボックス）に子のボックスを入れる
+fn build_children<'a>(mut root: LayoutBox<'a>, style_node: &'a StyledNode<'a>, images: &Images) -> Result<LayoutBox<'a>, LayoutError> {
+  root.image = images.for_node(style_node.node);
+  root.background_image = style_node.value("background-image").and_then(|value| images.background(&value));
+  root.float = style_node.float();
+  root.position = style_node.position();
+  root.visible = style_node.visible();
+
+  // content があれば、子の代わりにその文字列を描く（ボックスは作らない）
+  if style_node.content().is_some() {
+    return Ok(root);
+  }
+  // 置換要素は子を持たない。フォームのコントロールも置換要素として扱い、中身（<button> のラベルや <option>）は form::display_list で描く
+  if replaced::is_replaced(style_node.node) || form::Control::from_node(style_node.node).is_some() {
+    return Ok(root);
+  }
+
+  // flex コンテナと grid コンテナの子はアイテムにする
+  if style_node.display() == Display::Flex || style_node.display() == Display::Grid {
+    return flex::build_items(root, style_node, images);
+  }
+  // 表の子は行にする
+  if style_node.display() == Display::Table {
+    return table::build_rows(root, style_node, images);
+  }
 
   // 子のレイアウトを格納
   for child in &style_node.children {
     match child.display() {
-      Block => root.children.push(build_layout_tree(child)),
-      Inline => root
+      Display::Block | Display::ListItem | Display::FlowRoot | Display::Flex | Display::Grid | Display::Table | Display::TableRowGroup | Display::TableRow | Display::TableCell if child.float() != Float::None || child.position().is_out_of_flow() => root.get_out_of_flow_container().children.push(build_layout_tree(child, images)?),
+      Display::Block | Display::ListItem | Display::FlowRoot | Display::Flex | Display::Grid | Display::Table | Display::TableRowGroup | Display::TableRow | Display::TableCell => root.children.push(build_layout_tree(child, images)?),
+      Display::Inline | Display::InlineBlock => root
         .get_inline_container()
         .children
-        .push(build_layout_tree(child)),
-      DisplayNone => {} // 何もしない
+        .push(build_layout_tree(child, images)?),
+      Display::None => {} // 何もしない
     }
   }
 
-  return root;
+  return Ok(root);
 }
 
 impl Rect {
@@ -88,8 +273,36 @@ impl Rect {
         height: self.height + edge.top + edge.bottom,
     }
   }
-}
 
+  // 両方を含む最小の rect
+  pub fn union(self, other: Rect) -> Rect {
+    let x = self.x.min(other.x);
+    let y = self.y.min(other.y);
+    Rect {
+      x: x,
+      y: y,
+      width: (self.x + self.width).max(other.x + other.width) - x,
+      height: (self.y + self.height).max(other.y + other.height) - y,
+    }
+  }
+
+  // 重なっている部分。重なっていなければ None
+  pub fn intersection(self, other: Rect) -> Option<Rect> {
+    let x0 = self.x.max(other.x);
+    let y0 = self.y.max(other.y);
+    let x1 = (self.x + self.width).min(other.x + other.width);
+    let y1 = (self.y + self.height).min(other.y + other.height);
+    if x1 <= x0 || y1 <= y0 {
+      return None;
+    }
+    return Some(Rect { x: x0, y: y0, width: x1 - x0, height: y1 - y0 });
+  }
+
+  // 点 (x, y) が中にあるか（右端と下端は含まない）
+  pub fn contains(self, x: f32, y: f32) -> bool {
+    return x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height;
+  }
+}
 
 impl Dimensions {
   // padding 部分の rect を出す
@@ -114,158 +327,303 @@ impl<'a> LayoutBox<'a> {
       box_type: box_type,
       dimensions: Default::default(),
       children: Vec::new(),
+      lines: Vec::new(),
+      fragments: Vec::new(),
+      image: None,
+      background_image: None,
+      float: Float::None,
+      position: Position::Static,
+      visible: true,
+      flex_width: None,
+      collapse_first_child: false,
+      containing_height: None,
+    }
+  }
+
+  // スクロール可能なオーバーフロー領域（自身と子孫のマージンボックスを合わせた範囲）
+  pub fn scrollable_overflow(&self) -> Rect {
+    return self.children.iter().fold(self.dimensions.margin_box(), |rect, child| rect.union(child.scrollable_overflow()));
+  }
+
+  // overflow が visible でなければ、子孫を描く範囲（パディングボックス）
+  pub fn overflow_clip(&self) -> Option<Rect> {
+    return match self.box_type.style_node().map(|style| style.overflow()) {
+      Some(Overflow::Hidden) | Some(Overflow::Clip) => Some(self.dimensions.padding_box()),
+      _ => None,
+    };
+  }
+
+  // transform があれば、描くときにボックスと子孫に当てる変換（ページの座標から、ページの座標へ）。
+  // 変換の原点は transform-origin（ボーダーボックスの中の位置。既定は中心）で、レイアウトは変換する前のまま。
+  // インラインのボックス（InlineNode）には当てない
+  pub fn transform(&self) -> Option<Matrix> {
+    let style = match self.box_type {
+      BlockNode(style) | InlineBlockNode(style) | ReplacedNode(style) => style,
+      InlineNode(_) | AnonymousBlock => return None,
+    };
+    let transform = match style.value("transform")? {
+      Value::Transform(transform) => transform,
+      Keyword(_) => return None,
+      value => css::transform(&value)?, // アニメーションの値は関数の並びのまま
+    };
+    let border_box = self.dimensions.border_box();
+    let matrix = transform.resolve(border_box.width, border_box.height);
+    if matrix.is_identity() {
+      return None;
     }
+    let values = match style.value("transform-origin") {
+      Some(Value::List(values)) => values,
+      Some(value) => vec![value],
+      None => Vec::new(),
+    };
+    let offset = |value: Option<&Value>, length: f32| match value {
+      Some(&Length(px, Px)) => px,
+      Some(&Length(percent, Percent)) => length * percent / 100.0,
+      Some(&Keyword(ref keyword)) if keyword == "left" || keyword == "top" => 0.0,
+      Some(&Keyword(ref keyword)) if keyword == "right" || keyword == "bottom" => length,
+      _ => length / 2.0, // center
+    };
+    // top や bottom が先なら縦の位置
+    let vertical_first = match values.first() {
+      Some(&Keyword(ref keyword)) => keyword == "top" || keyword == "bottom",
+      _ => false,
+    };
+    let (horizontal, vertical) = if vertical_first { (values.get(1), values.first()) } else { (values.first(), values.get(1)) };
+    let origin_x = border_box.x + offset(horizontal, border_box.width);
+    let origin_y = border_box.y + offset(vertical, border_box.height);
+    return Some(Matrix::translate(origin_x, origin_y).multiply(&matrix).multiply(&Matrix::translate(-origin_x, -origin_y)));
   }
 
-  fn get_style_node(&self) -> &'a StyledNode<'a> {
+  fn get_style_node(&self) -> Result<&'a StyledNode<'a>, LayoutError> {
     match self.box_type {
-      BlockNode(node) | InlineNode(node) => node,
-      AnonymousBlock => panic!("Anonymous block box has no style node"),
+      BlockNode(node) | InlineNode(node) | InlineBlockNode(node) | ReplacedNode(node) => Ok(node),
+      AnonymousBlock => Err(LayoutError::AnonymousBlockStyle),
     }
   }
 }
 
 impl<'a> LayoutBox<'a> {
-  fn layout(&mut self, containing_block: Dimensions) {
-    match self.box_type {
-      BlockNode(_) => self.layout_block(containing_block),
-      InlineNode(_) | AnonymousBlock => {} // TODO
+  // parent は親のブロック（anonymous ブロックの文字の大きさと色に使う）。floats は今のブロック整形コンテキストの float
+  // cache は前のレイアウトの形（LayoutTree）。float や絶対配置のものの中、インラインの中のブロックでは使わない
+  fn layout(&mut self, containing_block: Dimensions, parent: &'a StyledNode<'a>, metrics: &dyn FontMetrics, viewport: Rect, floats: &mut Floats, cache: Option<&mut LayoutCache>) -> Result<(), LayoutError> {
+    // トレースには、ボックスごとの区間と部分木のボックスの数を残す
+    let mut span = trace::span_with("layout", || dump::box_type_label(&self.box_type));
+    let result = match self.box_type {
+      BlockNode(_) | InlineBlockNode(_) | ReplacedNode(_) => self.layout_block(containing_block, metrics, viewport, floats, cache),
+      AnonymousBlock => self.layout_anonymous(containing_block, parent, metrics, viewport, floats),
+      // ルートが inline のときは anonymous ブロックと同じように並べる
+      InlineNode(style) => self.layout_anonymous(containing_block, style, metrics, viewport, floats),
+    };
+    if span.recording() {
+      span.arg("boxes", profile::count_boxes(self) as f64);
     }
+    return result;
   }
 
-  fn layout_block(&mut self, containing_block: Dimensions) {
-    self.calculate_block_width(containing_block);
-    self.calculate_block_position(containing_block);
-    self.layout_block_children();
-    self.calculate_block_height();
+  // float するボックスを、自分のブロック整形コンテキストで area の左上にレイアウトする（置く場所は place_float で決める）
+  fn layout_float(&mut self, area: Rect, metrics: &dyn FontMetrics, viewport: Rect) -> Result<(), LayoutError> {
+    let containing_block = Dimensions { content: Rect { height: 0.0, ..area }, ..Default::default() };
+    let mut floats = Floats::default();
+    self.layout_block(containing_block, metrics, viewport, &mut floats, None)?;
+    self.contain_floats(&floats, viewport);
+    return Ok(());
   }
 
-  fn calculate_block_width(&mut self, containing_block: Dimensions) {
-    let style = self.get_style_node();
-
-    // width(default: auto)
-    let auto = Keyword("auto".to_string());
-    let mut width = style.value("width").unwrap_or(auto.clone());
-
-    // margin, border, padding(default: 0)
-    let zero = Length(0.0, Px);
-    let mut margin_left = style.lookup("margin-left", "margin", &zero);
-    let mut margin_right = style.lookup("margin-right", "margin", &zero);
-
-    let mut border_left = style.lookup("border-left-width", "border-width", &zero);
-    let mut border_right = style.lookup("border-right-width", "border-width", &zero);
-
-    let padding_left = style.lookup("padding-left", "padding", &zero);
-    let padding_right = style.lookup("padding-right", "padding", &zero);
-
-    let total = sum(
-      [
-        &margin_left,
-        &margin_right,
-        &border_left,
-        &border_right,
-        &padding_left,
-        &padding_right,
-        &width,
-      ]
-      .iter()
-      .map(|v| v.to_px()),
-    );
+  // flex と grid のアイテムを、自分のブロック整形コンテキストで area の左上にレイアウトする。
+  // anonymous のアイテムは area の幅に行を並べる。container はコンテナ（anonymous のアイテムの文字の大きさと色に使う）
+  fn layout_item(&mut self, area: Rect, container: &'a StyledNode<'a>, metrics: &dyn FontMetrics, viewport: Rect) -> Result<(), LayoutError> {
+    let containing_block = Dimensions { content: Rect { height: 0.0, ..area }, ..Default::default() };
+    let mut floats = Floats::default();
+    self.layout(containing_block, container, metrics, viewport, &mut floats, None)?;
+    self.contain_floats(&floats, viewport);
+    return Ok(());
+  }
 
-    if width != auto && total > containing_block.content.width {
-      if margin_left == auto {
-        margin_left = Length(0.0, Px);
-      }
-      if margin_right == auto {
-        margin_right = Length(0.0, Px);
-      }
-    }
-    let underflow = containing_block.content.width - total;
+  // レイアウトした float を、y より下の area の中で入るところに動かす。clear があれば、その側の float より下にする
+  fn place_float(&mut self, floats: &mut Floats, area: Rect, y: f32) {
+    let clear = self.box_type.style_node().map_or(Clear::None, |style| style.clear());
+    let y = floats.clearance(clear).map_or(y, |bottom| y.max(bottom));
+    let margin_box = self.dimensions.margin_box();
+    let (x, y) = floats.place(self.float, margin_box.width, margin_box.height, y, area);
+    self.translate(x - margin_box.x, y - margin_box.y);
+  }
 
-    match (width == auto, margin_left == auto, margin_right == auto) {
-      (false, false, false) => margin_right = Length(margin_right.to_px() + underflow, Px),
-      (false, false, true) => {
-        margin_right = Length(underflow, Px);
-      }
-      (false, true, false) => {
-        margin_left = Length(underflow, Px);
-      }
-      (true, _, _) => {
-        if margin_left == auto {
-          margin_left = Length(0.0, Px);
-        }
-        if margin_right == auto {
-          margin_right = Length(0.0, Px);
-        }
-        if underflow >= 0.0 {
-          width = Length(underflow, Px);
-        } else {
-          width = Length(0.0, Px);
-          margin_right = Length(margin_right.to_px() + underflow, Px);
-        }
-      }
-      (false, true, true) => {
-        margin_left = Length(underflow / 2.0, Px);
-        margin_right = Length(underflow / 2.0, Px);
+  // 流れのレイアウトが終わった子孫のうち、relative のものをずらして、絶対配置のものを置く。
+  // containing_block は absolute の子の包含ブロック
+  fn layout_positioned(&mut self, containing_block: Rect, metrics: &dyn FontMetrics, viewport: Rect) -> Result<(), LayoutError> {
+    for child in &mut self.children {
+      match child.position {
+        Position::Static => {}
+        Position::Relative => child.offset_relative(containing_block, viewport),
+        Position::Absolute => child.layout_absolute(containing_block, metrics, viewport)?,
+        Position::Fixed => child.layout_absolute(viewport, metrics, viewport)?,
       }
+      let containing_block = match child.position {
+        Position::Static => containing_block,
+        _ => child.dimensions.padding_box(),
+      };
+      child.layout_positioned(containing_block, metrics, viewport)?;
     }
-
-    let d = &mut self.dimensions;
-    d.content.width = width.to_px();
-    d.padding.left = padding_left.to_px();
-    d.padding.right = padding_right.to_px();
-    d.border.left = border_left.to_px();
-    d.border.right = border_right.to_px();
-    d.margin.left = margin_left.to_px();
-    d.margin.right = margin_right.to_px();
+    return Ok(());
   }
 
-  fn calculate_block_position(&mut self, containing_block: Dimensions) {
-    let style = self.get_style_node();
-    let d = &mut self.dimensions;
-
-    let zero = Length(0.0, Px);
-
-    d.margin.top = style.lookup("margin-top", "margin", &zero).to_px();
-    d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_px();
+  // relative のボックスを子孫ごとずらす。% は包含ブロックの幅と高さに対する割合
+  fn offset_relative(&mut self, containing_block: Rect, viewport: Rect) {
+    let style = match self.box_type.style_node() {
+      Some(style) => style,
+      None => return,
+    };
+    let offset = |name: &str, base: f32| match style.value(name).map(|value| resolve_length(value, Some(base), viewport)) {
+      Some(Length(length, Px)) => Some(length),
+      _ => None,
+    };
+    let dx = offset("left", containing_block.width).or_else(|| offset("right", containing_block.width).map(|right| -right)).unwrap_or(0.0);
+    let dy = offset("top", containing_block.height).or_else(|| offset("bottom", containing_block.height).map(|bottom| -bottom)).unwrap_or(0.0);
+    self.translate(dx, dy);
+  }
 
-    d.border.top = style
-      .lookup("border-top-width", "border-width", &zero)
-      .to_px();
-    d.border.bottom = style
-      .lookup("border-bottom-width", "border-width", &zero)
-      .to_px();
+  // 絶対配置のボックスを、自分のブロック整形コンテキストで containing_block の中にレイアウトする。
+  // dimensions.content の x, y は流れのレイアウトで覚えた静的位置
+  fn layout_absolute(&mut self, containing_block: Rect, metrics: &dyn FontMetrics, viewport: Rect) -> Result<(), LayoutError> {
+    let style = self.get_style_node()?;
+    let offset = |name: &str, base: f32| match style.value(name).map(|value| resolve_length(value, Some(base), viewport)) {
+      Some(Length(length, Px)) => Some(length),
+      _ => None,
+    };
+    let cb = containing_block;
+    let (left, right) = (offset("left", cb.width), offset("right", cb.width));
+    let (top, bottom) = (offset("top", cb.height), offset("bottom", cb.height));
+    let static_position = self.dimensions.content;
+    self.containing_height = Some(cb.height);
+
+    let area = Rect { x: cb.x, y: cb.y, width: (cb.width - left.unwrap_or(0.0) - right.unwrap_or(0.0)).max(0.0), height: 0.0 };
+    self.layout_float(area, metrics, viewport)?;
+
+    // top と bottom の両方があれば、height の auto は残りの高さ
+    if let (Some(top), Some(bottom), None) = (top, bottom, self.specified_height(viewport)) {
+      let d = &mut self.dimensions;
+      let edges = d.margin.top + d.border.top + d.padding.top + d.padding.bottom + d.border.bottom + d.margin.bottom;
+      d.content.height = (cb.height - top - bottom - edges).max(0.0);
+      self.clamp_height(viewport);
+    }
 
-    d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
-    d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
+    let margin_box = self.dimensions.margin_box();
+    let x = match (left, right) {
+      (Some(left), _) => cb.x + left,
+      (None, Some(right)) => cb.x + cb.width - right - margin_box.width,
+      (None, None) => static_position.x,
+    };
+    let y = match (top, bottom) {
+      (Some(top), _) => cb.y + top,
+      (None, Some(bottom)) => cb.y + cb.height - bottom - margin_box.height,
+      (None, None) => static_position.y,
+    };
+    self.translate(x - margin_box.x, y - margin_box.y);
+    return Ok(());
+  }
 
-    d.content.x = containing_block.content.x + d.margin.left + d.border.left + d.padding.left;
-    d.content.y = containing_block.content.height
-      + containing_block.content.y
-      + d.margin.top
-      + d.border.top
-      + d.padding.top;
+  // ブロック整形コンテキストを作るボックスの auto の高さには、中の float も含める
+  fn contain_floats(&mut self, floats: &Floats, viewport: Rect) {
+    if self.specified_height(viewport).is_some() {
+      return;
+    }
+    if let Some(bottom) = floats.bottom() {
+      let content = &mut self.dimensions.content;
+      content.height = content.height.max(bottom - content.y);
+    }
   }
 
-  fn layout_block_children(&mut self) {
-    let d = &mut self.dimensions;
+  // 子孫ごと (dx, dy) 動かす
+  fn translate(&mut self, dx: f32, dy: f32) {
+    self.dimensions.content.x += dx;
+    self.dimensions.content.y += dy;
+    for line in &mut self.lines {
+      line.x += dx;
+      line.y += dy;
+    }
+    for fragment in &mut self.fragments {
+      fragment.rect.x += dx;
+      fragment.rect.y += dy;
+    }
     for child in &mut self.children {
-      child.layout(*d);
-      d.content.height = d.content.height + child.dimensions.margin_box().height;
+      child.translate(dx, dy);
     }
   }
 
-  fn calculate_block_height(&mut self) {
-    if let Some(Length(h, Px)) = self.get_style_node().value("height") {
-        self.dimensions.content.height = h;
+  // 折り返さずに並べたときの中身の幅（max-content）。float の width の auto に使う。% の長さは 0 とする
+  fn max_content_width(&self, metrics: &dyn FontMetrics, viewport: Rect) -> f32 {
+    let style = match self.box_type {
+      // anonymous ブロックの中のインラインは 1 行に並べる
+      AnonymousBlock => return sum(self.children.iter().filter(|child| !child.position.is_out_of_flow()).map(|child| child.outer_max_content_width(metrics, viewport))),
+      BlockNode(style) | InlineNode(style) | InlineBlockNode(style) | ReplacedNode(style) => style,
+    };
+    match self.box_type {
+      BlockNode(_) | InlineBlockNode(_) => {
+        if let Some(Length(width, Px)) = style.value("width").map(|width| resolve_length(width, None, viewport)) {
+          return (width - self.border_box_width_edges(None, viewport)).max(0.0);
+        }
+      }
+      InlineNode(_) | ReplacedNode(_) | AnonymousBlock => {}
+    }
+    let font = Font::default().inherit(style, viewport);
+    let text_metrics = font.metrics(metrics);
+    let mut width = match style.node.node_type {
+      NodeType::Text(ref text) if font.white_space == WhiteSpace::Pre => text.split('\n').map(|line| font.text_width(text_metrics, &expand_tabs(line))).fold(0.0, f32::max),
+      NodeType::Text(ref text) => font.text_width(text_metrics, &collapse_whitespace(text)),
+      _ => style.content().map_or(0.0, |content| font.text_width(text_metrics, content)),
+    };
+    if let Some((replaced_width, _)) = self.replaced_size(None, viewport) {
+      width += replaced_width;
+    }
+    let children = self.children.iter().filter(|child| !child.position.is_out_of_flow()).map(|child| child.outer_max_content_width(metrics, viewport));
+    return match self.box_type {
+      InlineNode(_) => width + sum(children),
+      // 横に並べる flex コンテナはアイテムの幅の合計
+      BlockNode(style) if flex::is_row(style) => width + sum(children),
+      BlockNode(style) if style.display() == Display::Table => width + table::max_content_width(self, style, metrics, viewport),
+      _ => children.fold(width, f32::max),
+    };
+  }
+
+  // max_content_width に margin、border、padding（px のもの）を足した幅
+  fn outer_max_content_width(&self, metrics: &dyn FontMetrics, viewport: Rect) -> f32 {
+    let width = self.max_content_width(metrics, viewport);
+    let style = match self.box_type.style_node() {
+      Some(style) => style,
+      None => return width,
+    };
+    let zero = Length(0.0, Px);
+    let length = |value: Value| match resolve_length(value, None, viewport) {
+      Length(length, Px) => length,
+      _ => 0.0,
+    };
+    let edge = |name: &str, fallback_name: &str| length(style.lookup(name, fallback_name, &zero));
+    return width
+      + edge("margin-left", "margin")
+      + edge("margin-right", "margin")
+      + length(style.border_width("left"))
+      + length(style.border_width("right"))
+      + edge("padding-left", "padding")
+      + edge("padding-right", "padding");
+  }
+
+  // float する子と絶対配置の子を入れるところ。インラインの子が続いていれば、その anonymous ブロックに入れて行の間に置く
+  fn get_out_of_flow_container(&mut self) -> &mut LayoutBox<'a> {
+    let after_inline = match self.children.last() {
+      Some(&LayoutBox { box_type: AnonymousBlock, .. }) => true,
+      _ => false,
+    };
+    if let (BlockNode(_), true) | (InlineBlockNode(_), true) = (&self.box_type, after_inline) {
+      return self.children.last_mut().unwrap();
     }
+    return self;
   }
 
   fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
     match self.box_type {
       // inline の子が含まれる Node はそれを含む anonymous ブロックを作成
       InlineNode(_) | AnonymousBlock => self,
-      BlockNode(_) => {
+      BlockNode(_) | InlineBlockNode(_) | ReplacedNode(_) => {
         match self.children.last() {
           Some(&LayoutBox {
             box_type: AnonymousBlock,
@@ -280,6 +638,250 @@ impl<'a> LayoutBox<'a> {
   }
 }
 
+// レイアウトの結果を残しておいて、変わったところだけレイアウトし直すもの。
+// 流れの中のブロックの形をノードの番号ごとに覚えておき、次の relayout_dirty では、自身と子孫が mark_dirty されておらず、
+// 包含ブロックの幅が同じで横に float がないブロックは、レイアウトせずに前の形を新しい位置に動かして使う。
+// ビューポートの大きさが変わったら（vw、vh や fixed があるので）すべてレイアウトし直す
+#[derive(Debug, Default)]
+pub struct LayoutTree {
+  blocks: HashMap<NodeId, CachedBlock>,
+  dirty: HashSet<NodeId>,
+  viewport: Option<Rect>,
+}
+
+impl LayoutTree {
+  pub fn new() -> LayoutTree {
+    return LayoutTree::default();
+  }
+
+  // id のノードのスタイルか中身が変わった（StyledTree::restyle の結果や、書き換えたテキストのノード）
+  pub fn mark_dirty(&mut self, id: NodeId) {
+    self.dirty.insert(id);
+  }
+
+  pub fn relayout_dirty<'a>(&mut self, node: &'a StyledNode<'a>, containing_block: Dimensions, images: &Images) -> Result<LayoutBox<'a>, LayoutError> {
+    return self.relayout_dirty_with(node, containing_block, font::shared(), images);
+  }
+
+  // metrics は前のレイアウトと同じものを渡す
+  pub fn relayout_dirty_with<'a>(&mut self, node: &'a StyledNode<'a>, containing_block: Dimensions, metrics: &dyn FontMetrics, images: &Images) -> Result<LayoutBox<'a>, LayoutError> {
+    let previous = if self.viewport == Some(containing_block.content) { std::mem::take(&mut self.blocks) } else { HashMap::new() };
+    let mut cache = LayoutCache { previous: previous, blocks: HashMap::new(), dirty: &self.dirty };
+    let result = layout_root(node, containing_block, metrics, images, Some(&mut cache));
+    self.blocks = cache.blocks;
+    self.dirty.clear();
+    self.viewport = if result.is_ok() { Some(containing_block.content) } else { None };
+    return result;
+  }
+}
+
+// 前のレイアウトで流れの中に置いたブロック。origin は置いた位置（包含ブロックの中で次に置く左上）、width は包含ブロックの幅
+#[derive(Debug)]
+struct CachedBlock {
+  width: f32,
+  containing_height: Option<f32>,
+  origin: (f32, f32),
+  geometry: Geometry,
+}
+
+// ボックスと子孫の位置と大きさ（LayoutBox と同じ形）
+#[derive(Debug)]
+struct Geometry {
+  dimensions: Dimensions,
+  lines: Vec<Rect>,
+  fragments: Vec<TextFragment>,
+  children: Vec<Geometry>,
+}
+
+// relayout_dirty の間の、前のレイアウトの形（previous）と今のレイアウトの形（blocks）
+struct LayoutCache<'c> {
+  previous: HashMap<NodeId, CachedBlock>,
+  blocks: HashMap<NodeId, CachedBlock>,
+  dirty: &'c HashSet<NodeId>,
+}
+
+impl<'c> LayoutCache<'c> {
+  // 前の形が使えれば、layout_box を origin に置いて true を返す。子孫の前の形も次に使えるように残す。
+  // 疑似要素はどれも同じノードを指すので、ノードの番号では覚えない（要素ごと覚えた形に含める）
+  fn reuse(&mut self, layout_box: &mut LayoutBox, width: f32, origin: (f32, f32)) -> bool {
+    let style = match layout_box.box_type {
+      BlockNode(style) if style.pseudo_element().is_none() => style,
+      _ => return false,
+    };
+    let cached = match self.previous.remove(&style.node.id) {
+      Some(cached) => cached,
+      None => return false,
+    };
+    if cached.width != width || cached.containing_height != layout_box.containing_height || self.is_dirty(style) || !layout_box.has_shape(&cached.geometry) {
+      return false;
+    }
+    layout_box.restore(&cached.geometry);
+    layout_box.translate(origin.0 - cached.origin.0, origin.1 - cached.origin.1);
+    self.keep_descendants(style);
+    self.blocks.insert(style.node.id, cached);
+    return true;
+  }
+
+  // 今レイアウトした layout_box の形を覚える。中に float があるものは、外の行にも影響するので覚えない
+  fn store(&mut self, layout_box: &LayoutBox, width: f32, origin: (f32, f32)) {
+    if let BlockNode(style) = layout_box.box_type {
+      if !layout_box.has_floats() && style.pseudo_element().is_none() {
+        self.blocks.insert(style.node.id, CachedBlock { width: width, containing_height: layout_box.containing_height, origin: origin, geometry: layout_box.geometry() });
+      }
+    }
+  }
+
+  fn is_dirty(&self, style: &StyledNode) -> bool {
+    return self.dirty.contains(&style.node.id) || style.children.iter().any(|child| self.is_dirty(child));
+  }
+
+  fn keep_descendants(&mut self, style: &StyledNode) {
+    for child in &style.children {
+      if let Some(cached) = self.previous.remove(&child.node.id) {
+        self.blocks.insert(child.node.id, cached);
+      }
+      self.keep_descendants(child);
+    }
+  }
+}
+
+impl<'a> LayoutBox<'a> {
+  fn geometry(&self) -> Geometry {
+    return Geometry {
+      dimensions: self.dimensions,
+      lines: self.lines.clone(),
+      fragments: self.fragments.clone(),
+      children: self.children.iter().map(|child| child.geometry()).collect(),
+    };
+  }
+
+  // geometry と子の数が同じか（スタイルが変わっていなければ同じになる）
+  fn has_shape(&self, geometry: &Geometry) -> bool {
+    return self.children.len() == geometry.children.len() && self.children.iter().zip(&geometry.children).all(|(child, geometry)| child.has_shape(geometry));
+  }
+
+  fn restore(&mut self, geometry: &Geometry) {
+    self.dimensions = geometry.dimensions;
+    self.lines = geometry.lines.clone();
+    self.fragments = geometry.fragments.clone();
+    for (child, geometry) in self.children.iter_mut().zip(&geometry.children) {
+      child.restore(geometry);
+    }
+  }
+
+  fn has_floats(&self) -> bool {
+    return self.children.iter().any(|child| child.float != Float::None || child.has_floats());
+  }
+}
+
+// ブロック整形コンテキストに置いた float のマージンボックス
+#[derive(Default)]
+struct Floats {
+  boxes: Vec<(Float, Rect)>,
+}
+
+impl Floats {
+  // width x height の float を、y より下（前の float より上にはしない）で area の中の入るところに置き、その左上を返す。
+  // 左右の float の間に入らなければ、どれかの float の下まで下げる（それでも入らなければ float のない高さ）
+  fn place(&mut self, float: Float, width: f32, height: f32, y: f32, area: Rect) -> (f32, f32) {
+    let mut y = self.boxes.last().map_or(y, |&(_, rect)| y.max(rect.y));
+    loop {
+      let (left, right) = self.band(y, height, area);
+      let next = self.boxes.iter().map(|&(_, rect)| rect.y + rect.height).filter(|&bottom| bottom > y).fold(None, |next: Option<f32>, bottom| Some(next.map_or(bottom, |next| next.min(bottom))));
+      if right - left >= width || next.is_none() {
+        let x = if float == Float::Right { right - width } else { left };
+        self.boxes.push((float, Rect { x: x, y: y, width: width, height: height }));
+        return (x, y);
+      }
+      y = next.unwrap();
+    }
+  }
+
+  // y の高さで行を並べられる左端と幅
+  fn line_space(&self, y: f32, area: Rect) -> (f32, f32) {
+    let (left, right) = self.band(y, 0.0, area);
+    return (left, (right - left).max(0.0));
+  }
+
+  // y から height の高さの範囲で、area のうち float のない左右の端
+  fn band(&self, y: f32, height: f32, area: Rect) -> (f32, f32) {
+    let mut left = area.x;
+    let mut right = area.x + area.width;
+    for &(float, rect) in &self.boxes {
+      // 高さ 0 の範囲は y を含む float と重なる
+      let overlaps = if height > 0.0 { rect.y < y + height } else { rect.y <= y };
+      if rect.y + rect.height <= y || !overlaps {
+        continue;
+      }
+      match float {
+        Float::Left => left = left.max(rect.x + rect.width),
+        Float::Right => right = right.min(rect.x),
+        Float::None => {}
+      }
+    }
+    return (left, right);
+  }
+
+  // clear で下げる位置（その側の float の下端）。その側に float がなければ None
+  fn clearance(&self, clear: Clear) -> Option<f32> {
+    return self
+      .boxes
+      .iter()
+      .filter(|&&(float, _)| match clear {
+        Clear::Left => float == Float::Left,
+        Clear::Right => float == Float::Right,
+        Clear::Both => true,
+        Clear::None => false,
+      })
+      .map(|&(_, rect)| rect.y + rect.height)
+      .fold(None, |bottom: Option<f32>, b| Some(bottom.map_or(b, |bottom| bottom.max(b))));
+  }
+
+  // いちばん下の float の下端
+  fn bottom(&self) -> Option<f32> {
+    return self.clearance(Clear::Both);
+  }
+}
+
+// vw と vh を viewport の大きさで、% を base（なければそのまま）に対する割合で px にする。単位のない 0 は 0px。
+// calc() は中の値を px にしてから計算する（% を px にできないなどで計算できなければそのまま）
+fn resolve_length(value: Value, base: Option<f32>, viewport: Rect) -> Value {
+  return match value {
+    Value::Calc(calc) => {
+      let resolve = |value: &Value| match *value {
+        Number(number) => Some(Number(number)),
+        ref value => match resolve_length(value.clone(), base, viewport) {
+          Length(length, Px) => Some(Length(length, Px)),
+          _ => None,
+        },
+      };
+      calc.evaluate(&resolve).unwrap_or(Value::Calc(calc))
+    }
+    Number(number) if number == 0.0 => Length(0.0, Px),
+    Length(length, Vw) => Length(length * viewport.width / 100.0, Px),
+    Length(length, Vh) => Length(length * viewport.height / 100.0, Px),
+    Length(percent, Percent) => match base {
+      Some(base) => Length(percent * base / 100.0, Px),
+      None => Length(percent, Percent),
+    },
+    value => value,
+  };
+}
+
+// 重なる 2 つの margin の大きさ。正の margin はいちばん大きいもの、負の margin はいちばん小さいものを足す
+fn collapse_margins(a: f32, b: f32) -> f32 {
+  return a.max(b).max(0.0) + a.min(b).min(0.0);
+}
+
+// name の長さを指定しているか（auto でないか）
+fn is_specified(style: &StyledNode, name: &str) -> bool {
+  return match style.value(name) {
+    Some(Keyword(ref keyword)) => keyword != "auto",
+    Some(_) => true,
+    None => false,
+  };
+}
+
 fn sum<I>(iter: I) -> f32
 where
   I: Iterator<Item = f32>,
@@ -0,0 +1,450 @@
+use css::Unit::Px;
+use css::Value;
+use css::Value::{Keyword, Length};
+use style::{Display, Float, Overflow};
+use text;
+
+use super::inline::Font;
+use super::{collapse_margins, is_specified, resolve_length, sum, AnonymousBlock, BlockNode, Dimensions, Floats, FontMetrics, InlineBlockNode, InlineNode, LayoutBox, LayoutCache, LayoutError, Rect, ReplacedNode, TextFragment};
+
+/**
+ * ブロックの幅と高さ、流れの中での位置。
+ *
+ * width と左右の margin は包含ブロックの幅に合うように決め、height の auto は子（行か、流れの中のブロック）の高さの合計にする。
+ * 流れの中で隣り合うブロックの上下の margin は重ね、上に border と padding のないブロックは最初の子の上の margin も自分の margin と重ねる
+ */
+
+// ブロックの width と左右の margin を、包含ブロックの幅 containing_width に合うように決める（CSS 2.1 の 10.3.3）。
+// edges は左右の border と padding の合計。keep_margins なら（float と絶対配置のもの）余った幅を margin にしない
+fn solve_block_width(containing_width: f32, mut width: Value, mut margin_left: Value, mut margin_right: Value, edges: f32, keep_margins: bool) -> (Value, Value, Value) {
+  let auto = Keyword("auto".to_string());
+  let total = margin_left.to_px() + margin_right.to_px() + edges + width.to_px();
+
+  if width != auto && total > containing_width {
+    if margin_left == auto {
+      margin_left = Length(0.0, Px);
+    }
+    if margin_right == auto {
+      margin_right = Length(0.0, Px);
+    }
+  }
+  let underflow = containing_width - total;
+
+  match (width == auto, margin_left == auto, margin_right == auto) {
+    (false, false, false) if keep_margins => {}
+    (false, false, false) => margin_right = Length(margin_right.to_px() + underflow, Px),
+    (false, false, true) => {
+      margin_right = Length(underflow, Px);
+    }
+    (false, true, false) => {
+      margin_left = Length(underflow, Px);
+    }
+    (true, _, _) => {
+      if margin_left == auto {
+        margin_left = Length(0.0, Px);
+      }
+      if margin_right == auto {
+        margin_right = Length(0.0, Px);
+      }
+      if underflow >= 0.0 {
+        width = Length(underflow, Px);
+      } else {
+        width = Length(0.0, Px);
+        margin_right = Length(margin_right.to_px() + underflow, Px);
+      }
+    }
+    (false, true, true) => {
+      margin_left = Length(underflow / 2.0, Px);
+      margin_right = Length(underflow / 2.0, Px);
+    }
+  }
+  return (width, margin_left, margin_right);
+}
+
+impl<'a> LayoutBox<'a> {
+  pub(super) fn layout_block(&mut self, mut containing_block: Dimensions, metrics: &dyn FontMetrics, viewport: Rect, floats: &mut Floats, cache: Option<&mut LayoutCache>) -> Result<(), LayoutError> {
+    let display = self.box_type.style_node().map(|style| style.display());
+    let formatting_context = match display {
+      Some(Display::FlowRoot) | Some(Display::Flex) | Some(Display::Grid) | Some(Display::Table) | Some(Display::TableCell) => true,
+      _ => false,
+    };
+    if !formatting_context {
+      self.calculate_block_width(containing_block, metrics, viewport)?;
+      self.calculate_block_position(containing_block, viewport, floats)?;
+      self.layout_block_children(metrics, viewport, floats, cache)?;
+      self.calculate_block_height(metrics, viewport)?;
+      self.place_marker(metrics, viewport)?;
+      return Ok(());
+    }
+    // flow-root、flex と grid のコンテナは外の float に重ならないように横に置いて、中の float は自分の高さに含める
+    let cb = containing_block.content;
+    let (left, width) = floats.line_space(cb.y + cb.height, cb);
+    containing_block.content = Rect { x: left, width: width, ..cb };
+    self.calculate_block_width(containing_block, metrics, viewport)?;
+    self.calculate_block_position(containing_block, viewport, floats)?;
+    let mut own_floats = Floats::default();
+    match display {
+      Some(Display::Flex) => self.layout_flex_items(metrics, viewport)?,
+      Some(Display::Grid) => self.layout_grid_items(metrics, viewport)?,
+      Some(Display::Table) => self.layout_table(metrics, viewport)?,
+      _ => self.layout_block_children(metrics, viewport, &mut own_floats, cache)?,
+    }
+    self.calculate_block_height(metrics, viewport)?;
+    self.contain_floats(&own_floats, viewport);
+    return Ok(());
+  }
+
+  fn calculate_block_width(&mut self, containing_block: Dimensions, metrics: &dyn FontMetrics, viewport: Rect) -> Result<(), LayoutError> {
+    let style = self.get_style_node()?;
+    let base = Some(containing_block.content.width);
+    let resolve = |value: Value| resolve_length(value, base, viewport);
+    // box-sizing: border-box なら、width、min-width、max-width から左右の border と padding を引いてコンテンツボックスの幅にする
+    let sizing = self.border_box_width_edges(base, viewport);
+
+    // width(default: auto)。置換要素の auto は固有の大きさ
+    let auto = Keyword("auto".to_string());
+    let mut width = match resolve(style.value("width").unwrap_or(auto.clone())) {
+      Length(width, Px) => Length((width - sizing).max(0.0), Px),
+      width => width,
+    };
+    if width == auto {
+      if let Some((replaced_width, _)) = self.replaced_size(base, viewport) {
+        width = Length(replaced_width, Px);
+      }
+    }
+
+    // margin, border, padding(default: 0)
+    let zero = Length(0.0, Px);
+    let mut margin_left = resolve(style.lookup("margin-left", "margin", &zero));
+    let mut margin_right = resolve(style.lookup("margin-right", "margin", &zero));
+
+    let mut border_left = resolve(style.border_width("left"));
+    let mut border_right = resolve(style.border_width("right"));
+
+    let padding_left = resolve(style.lookup("padding-left", "padding", &zero));
+    let padding_right = resolve(style.lookup("padding-right", "padding", &zero));
+
+    // float、inline-block と絶対配置のものは auto の margin を 0 にして、width の auto を中身の幅にする（入らなければ包含ブロックの幅）
+    let inline_block = self.is_atomic_inline();
+    // flex アイテムは width を flex コンテナで決めた幅にして、auto の margin を 0 にする
+    let flex_item = self.flex_width.is_some();
+    if let Some(flex_width) = self.flex_width {
+      width = Length(flex_width, Px);
+    }
+    let shrink_to_fit = self.float != Float::None || inline_block || flex_item || (self.position.is_out_of_flow() && !(is_specified(style, "left") && is_specified(style, "right")));
+    if shrink_to_fit {
+      for margin in [&mut margin_left, &mut margin_right] {
+        if *margin == auto {
+          *margin = Length(0.0, Px);
+        }
+      }
+      if width == auto {
+        let edges = sum([&margin_left, &margin_right, &border_left, &border_right, &padding_left, &padding_right].iter().map(|v| v.to_px()));
+        width = Length(self.max_content_width(metrics, viewport).min(containing_block.content.width - edges).max(0.0), Px);
+      }
+    }
+    // 表の width の auto も中身の幅にする（入らなければ包含ブロックの幅）。auto の margin は残すので、margin: auto で中央に置ける
+    if width == auto && style.display() == Display::Table {
+      let edges = sum([&margin_left, &margin_right, &border_left, &border_right, &padding_left, &padding_right].iter().map(|v| v.to_px()));
+      width = Length(self.max_content_width(metrics, viewport).min(containing_block.content.width - edges).max(0.0), Px);
+    }
+
+    // width を決めて、余った幅を auto の margin に分ける。
+    // max-width より広ければ width を max-width に、min-width より狭ければ min-width にして、もう一度 margin を決める
+    let edges = [&border_left, &border_right, &padding_left, &padding_right].iter().map(|v| v.to_px()).sum::<f32>();
+    let out_of_flow = self.position.is_out_of_flow();
+    let solve = |width: Value| solve_block_width(containing_block.content.width, width, margin_left.clone(), margin_right.clone(), edges, shrink_to_fit || out_of_flow);
+    let constraint = |name: &str| match style.value(name).map(|value| resolve(value)) {
+      _ if flex_item => None, // flex アイテムの幅は flex コンテナで制約に合わせてある
+      Some(Length(length, Px)) => Some((length - sizing).max(0.0)),
+      _ => None, // none と auto は制約なし
+    };
+    let (mut width, mut margin_left, mut margin_right) = solve(width);
+    if let Some(max_width) = constraint("max-width") {
+      if width.to_px() > max_width {
+        let solved = solve(Length(max_width, Px));
+        width = solved.0;
+        margin_left = solved.1;
+        margin_right = solved.2;
+      }
+    }
+    if let Some(min_width) = constraint("min-width") {
+      if width.to_px() < min_width {
+        let solved = solve(Length(min_width, Px));
+        width = solved.0;
+        margin_left = solved.1;
+        margin_right = solved.2;
+      }
+    }
+
+    let d = &mut self.dimensions;
+    d.content.width = width.to_px();
+    d.padding.left = padding_left.to_px();
+    d.padding.right = padding_right.to_px();
+    d.border.left = border_left.to_px();
+    d.border.right = border_right.to_px();
+    d.margin.left = margin_left.to_px();
+    d.margin.right = margin_right.to_px();
+    return Ok(());
+  }
+
+  // 上下の margin と padding の % も包含ブロックの幅に対する割合
+  // clear があれば、その側の float の下までボーダーボックスを下げる
+  fn calculate_block_position(&mut self, containing_block: Dimensions, viewport: Rect, floats: &Floats) -> Result<(), LayoutError> {
+    let style = self.get_style_node()?;
+    let d = &mut self.dimensions;
+
+    let zero = Length(0.0, Px);
+    let px = |value: Value| resolve_length(value, Some(containing_block.content.width), viewport).to_px();
+
+    d.margin.top = px(style.lookup("margin-top", "margin", &zero));
+    d.margin.bottom = px(style.lookup("margin-bottom", "margin", &zero));
+
+    d.border.top = px(style.border_width("top"));
+    d.border.bottom = px(style.border_width("bottom"));
+
+    d.padding.top = px(style.lookup("padding-top", "padding", &zero));
+    d.padding.bottom = px(style.lookup("padding-bottom", "padding", &zero));
+
+    d.content.x = containing_block.content.x + d.margin.left + d.border.left + d.padding.left;
+    d.content.y = containing_block.content.height
+      + containing_block.content.y
+      + d.margin.top
+      + d.border.top
+      + d.padding.top;
+    if let Some(bottom) = floats.clearance(style.clear()) {
+      let border_top = d.content.y - d.padding.top - d.border.top;
+      if border_top < bottom {
+        d.content.y += bottom - border_top;
+      }
+    }
+    return Ok(());
+  }
+
+  // 子を上から積む。float は今の高さに置いて、高さには入れない。
+  // 隣り合う子の上下の margin と、最初の子の上の margin（自分の上の margin とまとめたとき）は重ねる
+  fn layout_block_children(&mut self, metrics: &dyn FontMetrics, viewport: Rect, floats: &mut Floats, mut cache: Option<&mut LayoutCache>) -> Result<(), LayoutError> {
+    let style = self.get_style_node()?;
+    let collapse_first_child = self.collapse_first_child;
+    let containing_height = self.specified_height(viewport);
+    let d = &mut self.dimensions;
+    let mut previous: Option<(f32, f32)> = None; // 前の流れの中の子のボーダーボックスの下端と、下の margin
+    for child in &mut self.children {
+      child.containing_height = containing_height;
+      if child.position.is_out_of_flow() {
+        child.dimensions.content = Rect { x: d.content.x, y: d.content.y + d.content.height, width: 0.0, height: 0.0 };
+        continue;
+      }
+      if child.float != Float::None {
+        child.layout_float(d.content, metrics, viewport)?;
+        child.place_float(floats, d.content, d.content.y + d.content.height);
+        continue;
+      }
+      // 上の margin を前の子の下の margin とまとめて、ボーダーボックスの上端を決める
+      let margin_top = child.collapse_margin_top(d.content.width, viewport);
+      let border_top = match previous {
+        Some((bottom, margin_bottom)) => bottom + collapse_margins(margin_bottom, margin_top),
+        None if collapse_first_child => d.content.y,
+        None => d.content.y + margin_top,
+      };
+      d.content.height = border_top - child.own_margin_top(d.content.width, viewport) - d.content.y;
+
+      // 横に float がなければ、置く位置と幅だけで形が決まるので、前のレイアウトを使える
+      let origin = (d.content.x, d.content.y + d.content.height);
+      let reused = match cache.as_deref_mut() {
+        Some(cache) if floats.boxes.is_empty() => cache.reuse(child, d.content.width, origin),
+        _ => false,
+      };
+      if !reused {
+        let cacheable = floats.boxes.is_empty();
+        child.layout(*d, style, metrics, viewport, floats, cache.as_deref_mut())?;
+        if let (Some(cache), true) = (cache.as_deref_mut(), cacheable) {
+          cache.store(child, d.content.width, origin);
+        }
+      }
+      let margin_box = child.dimensions.margin_box();
+      d.content.height = margin_box.y + margin_box.height - d.content.y;
+      let border_box = child.dimensions.border_box();
+      previous = Some((border_box.y + border_box.height, child.dimensions.margin.bottom));
+    }
+    // 負の margin で子の下端が上端より上に来ても、高さは負にしない
+    d.content.height = d.content.height.max(0.0);
+    return Ok(());
+  }
+
+  // 流れの中に置くときの上の margin。最初の子の margin とまとめられるなら、まとめた大きさにしてそのことを覚えておく。
+  // % は containing_width に対する割合（子孫の % にも同じ幅を使う）
+  fn collapse_margin_top(&mut self, containing_width: f32, viewport: Rect) -> f32 {
+    let margin = self.own_margin_top(containing_width, viewport);
+    self.collapse_first_child = self.collapses_with_first_child(containing_width, viewport);
+    if !self.collapse_first_child {
+      return margin;
+    }
+    return match self.children.iter_mut().find(|child| child.float == Float::None && !child.position.is_out_of_flow()) {
+      Some(child) => collapse_margins(margin, child.collapse_margin_top(containing_width, viewport)),
+      None => margin,
+    };
+  }
+
+  // margin-top。anonymous ブロックは 0
+  fn own_margin_top(&self, containing_width: f32, viewport: Rect) -> f32 {
+    let zero = Length(0.0, Px);
+    return self.box_type.style_node().map_or(0.0, |style| resolve_length(style.lookup("margin-top", "margin", &zero), Some(containing_width), viewport).to_px());
+  }
+
+  // 上の margin を最初の子の上の margin とまとめられるか。
+  // 上の border と padding がなく、新しいブロック整形コンテキストを作らない流れの中のブロックだけ
+  fn collapses_with_first_child(&self, containing_width: f32, viewport: Rect) -> bool {
+    let style = match self.box_type {
+      BlockNode(style) => style,
+      _ => return false,
+    };
+    if self.float != Float::None || self.position.is_out_of_flow() || style.content().is_some() || style.overflow() != Overflow::Visible {
+      return false;
+    }
+    match style.display() {
+      Display::FlowRoot | Display::Flex | Display::Grid | Display::Table | Display::TableCell => return false,
+      _ => {}
+    }
+    let zero = Length(0.0, Px);
+    let px = |value: Value| resolve_length(value, Some(containing_width), viewport).to_px();
+    return px(style.border_width("top")) == 0.0 && px(style.lookup("padding-top", "padding", &zero)) == 0.0;
+  }
+
+  // display: list-item のマーカー（counters::apply で作った文字列）を、最初の行の左のコンテンツボックスの外（パディングかマージンの上）に置く。
+  // 断片として持つので、テキストと同じように描いて、レイアウトのキャッシュで一緒に動かす。行がなければコンテンツボックスの上端に置く
+  fn place_marker(&mut self, metrics: &dyn FontMetrics, viewport: Rect) -> Result<(), LayoutError> {
+    let style = self.get_style_node()?;
+    let text = match style.marker() {
+      Some(text) if style.display() == Display::ListItem => text.to_string(),
+      _ => return Ok(()),
+    };
+    let font = Font::default().inherit(style, viewport);
+    let metrics = font.metrics(metrics);
+    let width = metrics.text_width(&text, font.size);
+    let content = self.dimensions.content;
+    let y = self.first_line().map_or(content.y, |line| line.y);
+    let rect = Rect { x: content.x - width, y: y, width: width, height: metrics.line_height(font.size) };
+    let glyphs = metrics.glyph_run(&text, font.size);
+    self.fragments.push(TextFragment { text: text, rect: rect, font_size: font.size, color: font.color, glyphs: glyphs, visible: font.visible, decorations: Vec::new() });
+    return Ok(());
+  }
+
+  // 流れの中の子孫で最初のラインボックス
+  fn first_line(&self) -> Option<Rect> {
+    if let Some(&line) = self.lines.first() {
+      return Some(line);
+    }
+    return self.children.iter().filter(|child| child.float == Float::None && !child.position.is_out_of_flow()).find_map(|child| child.first_line());
+  }
+
+  fn calculate_block_height(&mut self, metrics: &dyn FontMetrics, viewport: Rect) -> Result<(), LayoutError> {
+    let style = self.get_style_node()?;
+    if let Some(content) = style.content() {
+      // content の文字列をコンテンツボックスの幅で折り返して、左上から行を重ねる（はみ出した行も切らない）
+      let font = Font::default().inherit(style, viewport);
+      let metrics = font.metrics(metrics);
+      let line_height = font.line_height(metrics);
+      let height = metrics.line_height(font.size);
+      let rect = self.dimensions.content;
+      let content = font.transform(content);
+      for (i, line) in text::wrap(&content, rect.width, |line| font.text_width(metrics, line)).iter().enumerate() {
+        let text = if line.hyphen { format!("{}-", line.text) } else { line.text.to_string() };
+        let width = font.text_width(metrics, &text);
+        let rect = Rect { x: rect.x, y: rect.y + i as f32 * line_height + (line_height - height) / 2.0, width: width, height: height };
+        let glyphs = font.spacing.glyph_run(metrics, &text, font.size);
+        let decorations = font.text_decorations(metrics.baseline(font.size));
+        self.fragments.push(TextFragment { text: text, rect: rect, font_size: font.size, color: font.color, glyphs: glyphs, visible: font.visible, decorations: decorations });
+      }
+      self.dimensions.content.height = self.fragments.len() as f32 * line_height;
+    }
+    // 置換要素は、決まった幅から縦横比を保った高さ
+    if let ReplacedNode(_) = self.box_type {
+      self.dimensions.content.height = self.replaced_height(self.dimensions.content.width, viewport);
+    }
+    if let Some(height) = self.specified_height(viewport) {
+      self.dimensions.content.height = height;
+    }
+    self.clamp_height(viewport);
+    return Ok(());
+  }
+
+  // 高さを max-height 以下、min-height 以上にする（両方に反するなら min-height）。
+  // % は包含ブロックの高さが決まっているときだけ使う（決まっていなければ制限しない）
+  pub(super) fn clamp_height(&mut self, viewport: Rect) {
+    let base = self.containing_height;
+    let style = match self.box_type.style_node() {
+      Some(style) => style,
+      None => return,
+    };
+    let sizing = self.border_box_height_edges();
+    let constraint = |name: &str| match style.value(name).map(|value| resolve_length(value, base, viewport)) {
+      Some(Length(length, Px)) => Some((length - sizing).max(0.0)),
+      _ => None,
+    };
+    let content = &mut self.dimensions.content;
+    if let Some(max_height) = constraint("max-height") {
+      content.height = content.height.min(max_height);
+    }
+    if let Some(min_height) = constraint("min-height") {
+      content.height = content.height.max(min_height);
+    }
+  }
+
+  // flex と grid のアイテムの高さを cross（margin、border、padding を含む）いっぱいにする（align-items: stretch）。height があれば変えない
+  pub(super) fn stretch_height(&mut self, cross: f32, viewport: Rect) {
+    if self.specified_height(viewport).is_some() {
+      return;
+    }
+    let d = &mut self.dimensions;
+    let edges = d.margin.top + d.border.top + d.padding.top + d.padding.bottom + d.border.bottom + d.margin.bottom;
+    d.content.height = (cross - edges).max(0.0);
+    self.clamp_height(viewport);
+  }
+
+  // 行の中に 1 つの箱として置くもの（inline-block と、float も絶対配置もしないインラインの置換要素）
+  pub(super) fn is_atomic_inline(&self) -> bool {
+    return match self.box_type {
+      InlineBlockNode(_) => true,
+      ReplacedNode(style) => (style.display() == Display::Inline || style.display() == Display::InlineBlock) && self.float == Float::None && !self.position.is_out_of_flow(),
+      BlockNode(_) | InlineNode(_) | AnonymousBlock => false,
+    };
+  }
+
+  // height の px の値（コンテンツボックスの高さ）。% は包含ブロックの高さが決まっているときだけで、決まっていなければ auto と同じ（None）
+  pub(super) fn specified_height(&self, viewport: Rect) -> Option<f32> {
+    return match self.box_type.style_node()?.value("height").map(|height| resolve_length(height, self.containing_height, viewport)) {
+      Some(Length(height, Px)) => Some((height - self.border_box_height_edges()).max(0.0)),
+      _ => None,
+    };
+  }
+
+  // box-sizing: border-box なら、width と height（min- と max- も）は border と padding を含めた大きさ
+  fn is_border_box(&self) -> bool {
+    return self.box_type.style_node().map_or(false, |style| style.value("box-sizing") == Some(Keyword("border-box".to_string())));
+  }
+
+  // border-box のときに width からコンテンツボックスの幅を出すために引く、左右の border と padding（px のもの）。
+  // content-box なら 0。% の padding は base に対する割合
+  pub(super) fn border_box_width_edges(&self, base: Option<f32>, viewport: Rect) -> f32 {
+    let style = match self.box_type.style_node() {
+      Some(style) if self.is_border_box() => style,
+      _ => return 0.0,
+    };
+    let zero = Length(0.0, Px);
+    let px = |value: Value| match resolve_length(value, base, viewport) {
+      Length(length, Px) => length,
+      _ => 0.0,
+    };
+    return px(style.border_width("left")) + px(style.border_width("right")) + px(style.lookup("padding-left", "padding", &zero)) + px(style.lookup("padding-right", "padding", &zero));
+  }
+
+  // height について同じもの。上下の border と padding は calculate_block_position で決めたもの
+  pub(super) fn border_box_height_edges(&self) -> f32 {
+    if !self.is_border_box() {
+      return 0.0;
+    }
+    let d = &self.dimensions;
+    return d.border.top + d.padding.top + d.padding.bottom + d.border.bottom;
+  }
+}
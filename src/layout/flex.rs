@@ -0,0 +1,334 @@
+use css::Unit::Px;
+use css::Value;
+use css::Value::{Keyword, Length, Number};
+use dom::NodeType;
+use images::Images;
+use style::{Display, Float, StyledNode};
+
+use super::{block_node, build_children, build_layout_tree, is_specified, resolve_length, sum, AnonymousBlock, FontMetrics, LayoutBox, LayoutError, Rect};
+
+/**
+ * flex コンテナ（display: flex）のレイアウト。
+ *
+ * 子の要素はそれぞれ flex アイテム（ブロック）になり、続いたテキストは anonymous の flex アイテムにまとめる。
+ * アイテムは flex-direction の向き（主軸）に折り返さずに 1 行に並べ、それぞれ自分のブロック整形コンテキストでレイアウトする。
+ * 主軸の大きさは flex-basis（auto なら width か height、それもなければ中身の大きさ）から始めて、
+ * 余った分を flex-grow の比で足し、足りない分を flex-shrink と flex-basis の積の比で引く（min と max の制約は最後に 1 度だけ）。
+ * 残った場所は justify-content で配り、交差軸では align-items（アイテムに align-self があればそれ）でそろえる。
+ * column の主軸の大きさを変えるのは、コンテナに height があるときだけ
+ */
+
+// justify-content、align-items、align-self の値
+#[derive(Clone, Copy, PartialEq)]
+enum Align {
+  Start,
+  End,
+  Center,
+  Stretch,
+  SpaceBetween,
+  SpaceAround,
+  SpaceEvenly,
+}
+
+impl Align {
+  // row-reverse と column-reverse では主軸の始まりと終わりが入れ替わる
+  fn reversed(self) -> Align {
+    return match self {
+      Align::Start => Align::End,
+      Align::End => Align::Start,
+      align => align,
+    };
+  }
+}
+
+// flex コンテナ root に style_node の子を flex アイテムとして入れる。アイテムは float しない
+pub(super) fn build_items<'a>(mut root: LayoutBox<'a>, style_node: &'a StyledNode<'a>, images: &Images) -> Result<LayoutBox<'a>, LayoutError> {
+  for child in &style_node.children {
+    if child.display() == Display::None {
+      continue;
+    }
+    match child.node.node_type {
+      // 空白だけのテキストはアイテムにしない
+      NodeType::Text(ref text) if text.chars().all(|c| c == ' ' || c == '\t' || c == '\n' || c == '\r' || c == '\u{0c}') => {}
+      NodeType::Text(_) => {
+        match root.children.last() {
+          Some(&LayoutBox { box_type: AnonymousBlock, .. }) => {}
+          _ => root.children.push(LayoutBox::new(AnonymousBlock)),
+        }
+        root.children.last_mut().unwrap().children.push(build_layout_tree(child, images)?);
+      }
+      _ => {
+        // inline と inline-block の要素もブロックにする
+        let mut item = build_children(LayoutBox::new(block_node(child)), child, images)?;
+        item.float = Float::None;
+        root.children.push(item);
+      }
+    }
+  }
+  return Ok(root);
+}
+
+// style が横（row か row-reverse）に並べる flex コンテナか
+pub(super) fn is_row(style: &StyledNode) -> bool {
+  return style.display() == Display::Flex && !direction(style).0;
+}
+
+// flex-direction。縦に並べるか、逆順か
+fn direction(style: &StyledNode) -> (bool, bool) {
+  return match style.value("flex-direction") {
+    Some(Keyword(ref keyword)) => match &**keyword {
+      "row-reverse" => (false, true),
+      "column" => (true, false),
+      "column-reverse" => (true, true),
+      _ => (false, false),
+    },
+    _ => (false, false),
+  };
+}
+
+// name の並べ方。知らない値（normal など）と指定のないときは initial。baseline は flex-start と同じにする
+fn align(style: &StyledNode, name: &str, initial: Align) -> Align {
+  return match style.value(name) {
+    Some(Keyword(ref keyword)) => match &**keyword {
+      "flex-start" | "start" | "self-start" | "left" | "baseline" => Align::Start,
+      "flex-end" | "end" | "self-end" | "right" => Align::End,
+      "center" => Align::Center,
+      "stretch" => Align::Stretch,
+      "space-between" => Align::SpaceBetween,
+      "space-around" => Align::SpaceAround,
+      "space-evenly" => Align::SpaceEvenly,
+      _ => initial,
+    },
+    _ => initial,
+  };
+}
+
+// justify-content で、主軸に free だけ余った n 個のアイテムの前に置く間と、アイテムの間。
+// 足りないときの space-* は flex-start（space-between）か center と同じ
+fn spacing(justify: Align, free: f32, n: usize) -> (f32, f32) {
+  let n = n as f32;
+  return match justify {
+    Align::End => (free, 0.0),
+    Align::Center => (free / 2.0, 0.0),
+    Align::SpaceBetween if free > 0.0 && n > 1.0 => (0.0, free / (n - 1.0)),
+    Align::SpaceAround if free > 0.0 => (free / n / 2.0, free / n),
+    Align::SpaceEvenly if free > 0.0 => (free / (n + 1.0), free / (n + 1.0)),
+    Align::SpaceAround | Align::SpaceEvenly => (free / 2.0, 0.0),
+    _ => (0.0, 0.0),
+  };
+}
+
+// 交差軸で、free だけ余ったアイテムをずらす量
+fn cross_offset(align: Align, free: f32) -> f32 {
+  return match align {
+    Align::End => free,
+    Align::Center => free / 2.0,
+    _ => 0.0,
+  };
+}
+
+// sizes（主軸の大きさ）に edges（margin、border、padding）を足したものが available になるように、
+// 余りを grow の比で足す（grow の合計が 1 より小さければ余りのその割合だけ）か、足りない分を shrink と大きさの積の比で引く
+fn flex(sizes: &mut [f32], edges: &[f32], grow: &[f32], shrink: &[f32], available: f32) {
+  let free = available - sum(sizes.iter().chain(edges).cloned());
+  if free > 0.0 {
+    let total = sum(grow.iter().cloned());
+    if total > 0.0 {
+      for (size, grow) in sizes.iter_mut().zip(grow) {
+        *size += free * grow / total.max(1.0);
+      }
+    }
+  } else if free < 0.0 {
+    let weights: Vec<f32> = sizes.iter().zip(shrink).map(|(size, shrink)| size * shrink).collect();
+    let total = sum(weights.iter().cloned());
+    if total > 0.0 {
+      for (size, weight) in sizes.iter_mut().zip(weights) {
+        *size = (*size + free * weight / total).max(0.0);
+      }
+    }
+  }
+}
+
+// name の長さ（px になるもの）。% は base に対する割合
+fn length(style: &StyledNode, name: &str, base: Option<f32>, viewport: Rect) -> Option<f32> {
+  return match style.value(name).map(|value| resolve_length(value, base, viewport)) {
+    Some(Length(length, Px)) => Some(length),
+    _ => None,
+  };
+}
+
+impl<'a> LayoutBox<'a> {
+  // flex アイテムを並べて、コンテンツボックスの auto の高さを決める（height があれば calculate_block_height で変える）
+  pub(super) fn layout_flex_items(&mut self, metrics: &dyn FontMetrics, viewport: Rect) -> Result<(), LayoutError> {
+    let style = self.get_style_node()?;
+    let content = self.dimensions.content;
+    let (column, reverse) = direction(style);
+    let justify = align(style, "justify-content", Align::Start);
+    let justify = if reverse { justify.reversed() } else { justify };
+    let align_items = align(style, "align-items", Align::Stretch);
+    let height = self.specified_height(viewport);
+
+    let mut items = Vec::new();
+    for child in &mut self.children {
+      child.containing_height = height;
+      if child.position.is_out_of_flow() {
+        // 絶対配置の子はコンテンツボックスの左上を静的位置にする
+        child.dimensions.content = Rect { x: content.x, y: content.y, width: 0.0, height: 0.0 };
+      } else {
+        items.push(child);
+      }
+    }
+    if reverse {
+      items.reverse();
+    }
+    self.dimensions.content.height = if column {
+      layout_column(&mut items, style, content, height, justify, align_items, metrics, viewport)?
+    } else {
+      layout_row(&mut items, style, content, height, justify, align_items, metrics, viewport)?
+    };
+    return Ok(());
+  }
+
+  // flex-grow か flex-shrink。anonymous のアイテムは initial
+  fn flex_factor(&self, name: &str, initial: f32) -> f32 {
+    return match self.box_type.style_node().and_then(|style| style.value(name)) {
+      Some(Number(factor)) if factor >= 0.0 => factor,
+      _ => initial,
+    };
+  }
+
+  // 交差軸の並べ方。align-self が auto でなければそれ
+  fn align_self(&self, align_items: Align) -> Align {
+    return match self.box_type.style_node() {
+      Some(style) if is_specified(style, "align-self") => align(style, "align-self", align_items),
+      _ => align_items,
+    };
+  }
+
+  // 主軸の大きさを min と max（column なら min-height と max-height）の間にする。% は base に対する割合
+  fn clamp_main(&self, size: f32, column: bool, base: Option<f32>, viewport: Rect) -> f32 {
+    let style = match self.box_type.style_node() {
+      Some(style) => style,
+      None => return size,
+    };
+    let (min, max) = if column { ("min-height", "max-height") } else { ("min-width", "max-width") };
+    let sizing = if column { self.border_box_height_edges() } else { self.border_box_width_edges(base, viewport) };
+    let bound = |name: &str| length(style, name, base, viewport).map(|length| length - sizing);
+    let size = bound(max).map_or(size, |max| size.min(max));
+    return bound(min).map_or(size, |min| size.max(min)).max(0.0);
+  }
+
+  // 左右の margin、border、padding の合計。% は base に対する割合で、auto の margin は 0
+  fn horizontal_edges(&self, base: f32, viewport: Rect) -> f32 {
+    let style = match self.box_type.style_node() {
+      Some(style) => style,
+      None => return 0.0,
+    };
+    let zero = Length(0.0, Px);
+    let px = |value: Value| match resolve_length(value, Some(base), viewport) {
+      Length(length, Px) => length,
+      _ => 0.0,
+    };
+    return px(style.lookup("margin-left", "margin", &zero))
+      + px(style.lookup("margin-right", "margin", &zero))
+      + px(style.border_width("left"))
+      + px(style.border_width("right"))
+      + px(style.lookup("padding-left", "padding", &zero))
+      + px(style.lookup("padding-right", "padding", &zero));
+  }
+}
+
+// 横に並べる。交差軸の大きさ（コンテナの height がなければいちばん高いアイテム）を返す
+fn layout_row<'a>(items: &mut [&mut LayoutBox<'a>], container: &'a StyledNode<'a>, content: Rect, height: Option<f32>, justify: Align, align_items: Align, metrics: &dyn FontMetrics, viewport: Rect) -> Result<f32, LayoutError> {
+  // flex-basis は auto なら width、それもなければ中身の幅。% はコンテナの幅に対する割合で、box-sizing: border-box なら border と padding を含む
+  let mut sizes: Vec<f32> = items
+    .iter()
+    .map(|item| {
+      let basis = item.box_type.style_node().and_then(|style| length(style, "flex-basis", Some(content.width), viewport).or_else(|| length(style, "width", Some(content.width), viewport)));
+      let basis = basis.map(|basis| (basis - item.border_box_width_edges(Some(content.width), viewport)).max(0.0));
+      return item.clamp_main(basis.unwrap_or_else(|| item.max_content_width(metrics, viewport)), false, Some(content.width), viewport);
+    })
+    .collect();
+  let edges: Vec<f32> = items.iter().map(|item| item.horizontal_edges(content.width, viewport)).collect();
+  let grow: Vec<f32> = items.iter().map(|item| item.flex_factor("flex-grow", 0.0)).collect();
+  let shrink: Vec<f32> = items.iter().map(|item| item.flex_factor("flex-shrink", 1.0)).collect();
+  flex(&mut sizes, &edges, &grow, &shrink, content.width);
+
+  for (item, &size) in items.iter_mut().zip(&sizes) {
+    let size = item.clamp_main(size, false, Some(content.width), viewport);
+    let width = match item.box_type {
+      AnonymousBlock => size,
+      _ => content.width,
+    };
+    item.flex_width = Some(size);
+    item.layout_item(Rect { width: width, ..content }, container, metrics, viewport)?;
+  }
+
+  let cross = height.unwrap_or_else(|| items.iter().map(|item| item.dimensions.margin_box().height).fold(0.0, f32::max));
+  let free = content.width - sum(items.iter().map(|item| item.dimensions.margin_box().width));
+  let (mut x, between) = spacing(justify, free, items.len());
+  x += content.x;
+  for item in items.iter_mut() {
+    let align = item.align_self(align_items);
+    if align == Align::Stretch {
+      item.stretch_height(cross, viewport);
+    }
+    let margin_box = item.dimensions.margin_box();
+    let y = content.y + cross_offset(align, cross - margin_box.height);
+    item.translate(x - margin_box.x, y - margin_box.y);
+    x += margin_box.width + between;
+  }
+  return Ok(cross);
+}
+
+// 縦に並べる。主軸の大きさ（コンテナの height がなければアイテムの高さの合計）を返す
+fn layout_column<'a>(items: &mut [&mut LayoutBox<'a>], container: &'a StyledNode<'a>, content: Rect, height: Option<f32>, justify: Align, align_items: Align, metrics: &dyn FontMetrics, viewport: Rect) -> Result<f32, LayoutError> {
+  // width の auto は stretch ならコンテナの幅いっぱい、そうでなければ中身の幅（入らなければコンテナの幅）
+  for item in items.iter_mut() {
+    let stretch = item.align_self(align_items) == Align::Stretch;
+    if let AnonymousBlock = item.box_type {
+      let width = if stretch { content.width } else { item.max_content_width(metrics, viewport).min(content.width) };
+      item.layout_item(Rect { width: width, ..content }, container, metrics, viewport)?;
+      continue;
+    }
+    let edges = item.horizontal_edges(content.width, viewport);
+    let width = match item.box_type.style_node().and_then(|style| length(style, "width", Some(content.width), viewport)) {
+      Some(width) => (width - item.border_box_width_edges(Some(content.width), viewport)).max(0.0),
+      None if stretch => (content.width - edges).max(0.0),
+      None => item.max_content_width(metrics, viewport).min(content.width - edges).max(0.0),
+    };
+    item.flex_width = Some(item.clamp_main(width, false, Some(content.width), viewport));
+    item.layout_item(content, container, metrics, viewport)?;
+  }
+
+  // flex-basis は auto なら height、それもなければレイアウトした高さ。% はコンテナの height に対する割合
+  let mut sizes: Vec<f32> = items
+    .iter()
+    .map(|item| {
+      let basis = item.box_type.style_node().and_then(|style| length(style, "flex-basis", height, viewport)).map(|basis| (basis - item.border_box_height_edges()).max(0.0));
+      let basis = basis.or_else(|| item.specified_height(viewport));
+      return item.clamp_main(basis.unwrap_or(item.dimensions.content.height), true, None, viewport);
+    })
+    .collect();
+  if let Some(height) = height {
+    let edges: Vec<f32> = items.iter().map(|item| item.dimensions.margin_box().height - item.dimensions.content.height).collect();
+    let grow: Vec<f32> = items.iter().map(|item| item.flex_factor("flex-grow", 0.0)).collect();
+    let shrink: Vec<f32> = items.iter().map(|item| item.flex_factor("flex-shrink", 1.0)).collect();
+    flex(&mut sizes, &edges, &grow, &shrink, height);
+  }
+  for (item, &size) in items.iter_mut().zip(&sizes) {
+    item.dimensions.content.height = item.clamp_main(size, true, None, viewport);
+  }
+
+  let main = height.unwrap_or_else(|| sum(items.iter().map(|item| item.dimensions.margin_box().height)));
+  let free = main - sum(items.iter().map(|item| item.dimensions.margin_box().height));
+  let (mut y, between) = spacing(justify, free, items.len());
+  y += content.y;
+  for item in items.iter_mut() {
+    let margin_box = item.dimensions.margin_box();
+    let x = content.x + cross_offset(item.align_self(align_items), content.width - margin_box.width);
+    item.translate(x - margin_box.x, y - margin_box.y);
+    y += margin_box.height + between;
+  }
+  return Ok(main);
+}
@@ -0,0 +1,171 @@
+use css::Unit::{Fr, Px};
+use css::Value;
+use css::Value::{Function, Keyword, Length, List, Number};
+use style::StyledNode;
+
+use super::{resolve_length, sum, FontMetrics, LayoutBox, LayoutError, Rect};
+
+/**
+ * grid コンテナ（display: grid）のレイアウト。
+ *
+ * アイテムは flex コンテナと同じように作り（子の要素と、続いたテキスト）、grid-template-columns の列に左上から行ごとに順に置く。
+ * トラックの大きさは px と % ならその長さ、auto ならそのトラックのアイテムの中身の大きさで、残りを fr の比で分ける
+ * （fr のトラックがなければ auto のトラックに等しく分ける）。grid-template-rows の行で足りなければ auto の行を足す。
+ * fr の行に残りを分けるのはコンテナに height があるときだけで、なければ auto の行と同じにする。
+ * トラックの間は column-gap と row-gap だけ空け、アイテムはセルの幅でレイアウトして、高さを行の高さに伸ばす
+ */
+
+// トラックの大きさの指定
+#[derive(Clone, Copy)]
+enum Track {
+  Fixed(f32),    // px
+  Fraction(f32), // fr
+  Auto,          // 中身の大きさ
+}
+
+// name（grid-template-columns など）のトラック。% は base に対する割合（なければ auto）。none なら空
+fn tracks(style: &StyledNode, name: &str, base: Option<f32>, viewport: Rect) -> Vec<Track> {
+  let mut tracks = Vec::new();
+  if let Some(value) = style.value(name) {
+    push_tracks(&mut tracks, value, base, viewport);
+  }
+  return tracks;
+}
+
+// value のトラックを tracks に足す。repeat(3, 1fr) は 3 つに広げる
+fn push_tracks(tracks: &mut Vec<Track>, value: Value, base: Option<f32>, viewport: Rect) {
+  match value {
+    List(values) => {
+      for value in values {
+        push_tracks(tracks, value, base, viewport);
+      }
+    }
+    Function(ref name, ref args) if name.eq_ignore_ascii_case("repeat") => {
+      if let [Number(count), ref track] = args[..] {
+        for _ in 0..count.max(0.0) as usize {
+          push_tracks(tracks, track.clone(), base, viewport);
+        }
+      }
+    }
+    Length(fraction, Fr) => tracks.push(Track::Fraction(fraction.max(0.0))),
+    Keyword(ref keyword) if keyword == "none" => {}
+    value => tracks.push(match resolve_length(value, base, viewport) {
+      Length(length, Px) => Track::Fixed(length.max(0.0)),
+      _ => Track::Auto, // auto、min-content、max-content など
+    }),
+  }
+}
+
+// column-gap か row-gap。% は base に対する割合。normal は 0
+fn gap(style: &StyledNode, name: &str, base: Option<f32>, viewport: Rect) -> f32 {
+  return match style.value(name).map(|value| resolve_length(value, base, viewport)) {
+    Some(Length(gap, Px)) => gap.max(0.0),
+    _ => 0.0,
+  };
+}
+
+// トラックの大きさ。content は auto のトラックの中身の大きさ、available はトラックに分ける大きさ（gap を除く）。
+// available がなければ fr のトラックも中身の大きさにする
+fn size_tracks(tracks: &[Track], content: &[f32], available: Option<f32>) -> Vec<f32> {
+  let mut sizes: Vec<f32> = tracks
+    .iter()
+    .zip(content)
+    .map(|(track, &content)| match *track {
+      Track::Fixed(size) => size,
+      Track::Fraction(_) if available.is_some() => 0.0,
+      Track::Fraction(_) | Track::Auto => content,
+    })
+    .collect();
+  let available = match available {
+    Some(available) => available,
+    None => return sizes,
+  };
+  let free = (available - sum(sizes.iter().cloned())).max(0.0);
+  let fractions = sum(tracks.iter().map(|track| if let Track::Fraction(fraction) = *track { fraction } else { 0.0 }));
+  let autos = tracks.iter().filter(|track| if let Track::Auto = **track { true } else { false }).count();
+  for (size, track) in sizes.iter_mut().zip(tracks) {
+    match *track {
+      // fr の合計が 1 より小さければ、残りのその割合だけ分ける
+      Track::Fraction(fraction) if fractions > 0.0 => *size = free * fraction / fractions.max(1.0),
+      Track::Auto if fractions == 0.0 => *size += free / autos as f32,
+      _ => {}
+    }
+  }
+  return sizes;
+}
+
+// start から gap ずつ空けて並べたトラックの始まり
+pub(super) fn offsets(start: f32, sizes: &[f32], gap: f32) -> Vec<f32> {
+  let mut position = start;
+  return sizes
+    .iter()
+    .map(|size| {
+      let offset = position;
+      position += size + gap;
+      return offset;
+    })
+    .collect();
+}
+
+impl<'a> LayoutBox<'a> {
+  // grid アイテムをセルに置いて、コンテンツボックスの auto の高さを決める（height があれば calculate_block_height で変える）
+  pub(super) fn layout_grid_items(&mut self, metrics: &dyn FontMetrics, viewport: Rect) -> Result<(), LayoutError> {
+    let style = self.get_style_node()?;
+    let content = self.dimensions.content;
+    let height = self.specified_height(viewport);
+    let column_gap = gap(style, "column-gap", Some(content.width), viewport);
+    let row_gap = gap(style, "row-gap", height, viewport);
+
+    let mut items = Vec::new();
+    for child in &mut self.children {
+      if child.position.is_out_of_flow() {
+        // 絶対配置の子はコンテンツボックスの左上を静的位置にする
+        child.dimensions.content = Rect { x: content.x, y: content.y, width: 0.0, height: 0.0 };
+      } else {
+        items.push(child);
+      }
+    }
+
+    // 列がなければ、幅いっぱいの 1 列
+    let mut columns = tracks(style, "grid-template-columns", Some(content.width), viewport);
+    if columns.is_empty() {
+      columns.push(Track::Fraction(1.0));
+    }
+    let mut rows = tracks(style, "grid-template-rows", height, viewport);
+    let row_count = (items.len() + columns.len() - 1) / columns.len();
+    while rows.len() < row_count {
+      rows.push(Track::Auto);
+    }
+
+    // 列の幅を決めて、アイテムをその幅でレイアウトする
+    let mut column_content = vec![0.0; columns.len()];
+    for (i, item) in items.iter().enumerate() {
+      let column = &mut column_content[i % columns.len()];
+      *column = item.outer_max_content_width(metrics, viewport).max(*column);
+    }
+    let column_gaps = column_gap * (columns.len() - 1) as f32;
+    let widths = size_tracks(&columns, &column_content, Some((content.width - column_gaps).max(0.0)));
+    let xs = offsets(content.x, &widths, column_gap);
+    for (i, item) in items.iter_mut().enumerate() {
+      let column = i % columns.len();
+      item.layout_item(Rect { x: xs[column], y: content.y, width: widths[column], height: 0.0 }, style, metrics, viewport)?;
+    }
+
+    // 行の高さを決めて、アイテムを行の高さに伸ばしてその行に動かす
+    let mut row_content = vec![0.0; rows.len()];
+    for (i, item) in items.iter().enumerate() {
+      let row = &mut row_content[i / columns.len()];
+      *row = item.dimensions.margin_box().height.max(*row);
+    }
+    let row_gaps = row_gap * rows.len().saturating_sub(1) as f32;
+    let heights = size_tracks(&rows, &row_content, height.map(|height| (height - row_gaps).max(0.0)));
+    let ys = offsets(content.y, &heights, row_gap);
+    for (i, item) in items.iter_mut().enumerate() {
+      let row = i / columns.len();
+      item.stretch_height(heights[row], viewport);
+      item.translate(0.0, ys[row] - content.y);
+    }
+    self.dimensions.content.height = sum(heights.iter().cloned()) + row_gaps;
+    return Ok(());
+  }
+}
@@ -0,0 +1,44 @@
+use paint::{self, PaintStep};
+use style::StyledNode;
+
+use super::LayoutBox;
+
+/**
+ * ヒットテスト。点の下にあるボックスを、手前から順に返す（インスペクターやプレビューのウィンドウでのクリックに使う）。
+ *
+ * 描く順（paint::paint_order。z-index と重ね合わせコンテキストを含む）を後ろから見て、ボーダーボックスに点が入るものを集める。
+ * transform のあるボックスの中では、点を逆に変換してから調べる（レイアウトは変換する前のままなので）。
+ * 祖先の overflow で切り取られたところと、visibility: hidden のボックスには当たらない。anonymous ブロックは StyledNode がないので飛ばす。
+ * border-radius の角と、テキストの断片の外の InlineNode の余白は区別しない
+ */
+
+// layout_root の中で (x, y) の下にあるボックスの StyledNode。手前（後で描くもの）から順
+pub fn hit_test<'a>(layout_root: &LayoutBox<'a>, x: f32, y: f32) -> Vec<&'a StyledNode<'a>> {
+  let mut hits = Vec::new();
+  // 描く順に、変換する前の座標にした点（変換した範囲の外なら None）
+  let mut points = vec![Some((x, y))];
+  for step in paint::paint_order(layout_root) {
+    let point = *points.last().unwrap();
+    match step {
+      PaintStep::Box(layout_box, clip) => {
+        let (x, y) = match point {
+          Some(point) => point,
+          None => continue,
+        };
+        if !layout_box.visible || !clip.map_or(true, |clip| clip.contains(x, y)) || !layout_box.dimensions.border_box().contains(x, y) {
+          continue;
+        }
+        hits.extend(layout_box.box_type.style_node());
+      }
+      PaintStep::PushTransform { matrix, clip, .. } => {
+        points.push(point.filter(|&(x, y)| clip.contains(x, y)).and_then(|(x, y)| Some(matrix.invert()?.apply(x, y))));
+      }
+      PaintStep::PopTransform if points.len() > 1 => {
+        points.pop();
+      }
+      PaintStep::PushOpacity(_) | PaintStep::PopOpacity | PaintStep::PopTransform => {}
+    }
+  }
+  hits.reverse();
+  return hits;
+}
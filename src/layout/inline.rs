@@ -0,0 +1,899 @@
+use css::Unit::Px;
+use css::{Color, Value};
+use css::Value::{ColorValue, Keyword, Length, Number};
+use dom::NodeType;
+use font::GlyphRun;
+use hyphenation;
+use std::iter::Peekable;
+use std::vec;
+use style::{StyledNode, MAX_FONT_SIZE};
+use text::{self, Break, WordBreak};
+
+use super::{resolve_length, sum, AnonymousBlock, BlockNode, DecorationLine, Dimensions, EdgeSizes, Float, Floats, FontFace, FontMetrics, InlineBlockNode, InlineNode, LayoutBox, LayoutError, Rect, ReplacedNode, TextDecoration, TextFragment};
+
+/**
+ * インライン整形コンテキスト。
+ *
+ * ブロックの中に並んだインラインの子は anonymous ブロックにまとめられ、そこでインライン整形コンテキストを作る。
+ * テキストを改行できる位置（text::line_breaks）で区切って左から並べ、幅を超えたら次の行（ラインボックス）に送る。
+ * 改行できる位置は word-break で変わり、hyphens: auto なら lang 属性の言語のハイフネーションの位置を足す（none ならソフトハイフンでも改行しない）。
+ * 単語の中で改行したときは行末にハイフンを出す。overflow-wrap: break-word（anywhere）なら、1 語で行に収まらないときだけ文字の間で改行する。
+ * text-transform は行に置く前の文字列に当て、text-decoration の線は断片ごとにベースラインからの位置を決めておく（描くのは paint）。
+ * text-decoration は引き継がないプロパティだが、線はその要素の中のテキストすべてに、その要素の色で引く
+ * 行の高さはその行に置いた文字の line-height（文字の上下に差の半分ずつを足す）の中でいちばん大きいもの（ベースラインをそろえる）で、
+ * 行の高さの合計がブロックの高さになる。letter-spacing と word-spacing は文字の幅に足して、字形もその分ずらして並べる。
+ * 行の残りの幅は text-align で断片の左（right、center）か空白の後ろ（justify）に分け、縦は vertical-align でそろえる
+ */
+
+// font-size がないときの文字の大きさ
+const DEFAULT_FONT_SIZE: f32 = 16.0;
+
+// color がないときの文字の色
+const DEFAULT_COLOR: Color = Color { r: 0, g: 0, b: 0, a: 255 };
+
+// text-decoration の線と、その中心のベースラインからの高さ（em）。フォントの値は見ずに、よくある欧文フォントの位置にする。
+// line-through は x-height（0.5em とする）の半分
+const DECORATION_LINES: [(&str, DecorationLine, f32); 3] = [
+  ("underline", DecorationLine::Underline, -0.1),
+  ("overline", DecorationLine::Overline, 0.9),
+  ("line-through", DecorationLine::LineThrough, 0.25),
+];
+
+// white-space: pre のタブの間隔（文字数）
+const TAB_SIZE: usize = 8;
+
+// 行の中で断片を横に寄せる位置（text-align）。start と end は left と right とみなす
+#[derive(Clone, Copy, PartialEq)]
+enum TextAlign {
+  Left,
+  Right,
+  Center,
+  Justify, // 最後の行と、ブロックの前で閉じた行は Left
+}
+
+impl TextAlign {
+  // 行を並べるブロックの text-align
+  fn of(style: &StyledNode) -> TextAlign {
+    return match style.value("text-align") {
+      Some(Keyword(ref keyword)) => match &**keyword {
+        "right" | "end" => TextAlign::Right,
+        "center" => TextAlign::Center,
+        "justify" => TextAlign::Justify,
+        _ => TextAlign::Left,
+      },
+      _ => TextAlign::Left,
+    };
+  }
+}
+
+// 行の中での断片の縦の位置（vertical-align）。Middle は親のベースラインから x-height（0.5em とする）の半分だけ上に中央をそろえる。
+// その半分の高さを持つ
+#[derive(Clone, Copy, PartialEq)]
+enum VerticalAlign {
+  Baseline,
+  Middle(f32),
+  Top,    // 行の上端
+  Bottom, // 行の下端
+}
+
+// テキストの空白と改行の扱い（white-space）。pre-wrap などはほかのものとして扱わず normal にする
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum WhiteSpace {
+  Normal, // 空白と改行の並びをひとつの空白にまとめ、幅を超えたら改行する
+  Pre,    // 空白をそのまま置き、改行文字でだけ改行する
+  Nowrap, // normal と同じくまとめるが、改行しない
+}
+
+// text-transform の値
+#[derive(Clone, Copy, PartialEq)]
+enum TextTransform {
+  None,
+  Uppercase,
+  Lowercase,
+  Capitalize, // 空白の後の最初の文字を大文字にする
+}
+
+// hyphens の値。Manual はソフトハイフンの位置だけで単語の中を改行する
+#[derive(Clone, Copy, PartialEq)]
+enum Hyphens {
+  None,
+  Manual,
+  Auto,
+}
+
+// line-height の値。% と em はスタイルツリーで px になっている
+#[derive(Clone, Copy, PartialEq)]
+enum LineHeight {
+  Normal,      // フォントの行の高さ（FontMetrics::line_height）
+  Number(f32), // font-size に掛ける（引き継いだ子は自分の font-size に掛ける）
+  Px(f32),
+}
+
+// letter-spacing と word-spacing（px）。letter は書記素クラスタごとに、word は空白ごとに、その後ろに足す
+#[derive(Clone, Copy, Default, PartialEq)]
+pub(super) struct Spacing {
+  letter: f32,
+  word: f32,
+}
+
+impl Spacing {
+  // 字形を続けて並べる単位。letter-spacing があれば書記素クラスタごと（合字にしない）、word-spacing だけなら空白の後ろで区切る
+  fn pieces(self, text: &str) -> Vec<&str> {
+    if self.letter != 0.0 {
+      return text::graphemes(text);
+    }
+    return text.split_inclusive(is_word_separator).collect();
+  }
+
+  // pieces の 1 つの後ろに足す幅
+  fn after(self, piece: &str) -> f32 {
+    return self.letter + self.word * piece.matches(is_word_separator).count() as f32;
+  }
+
+  // 間隔を足した text の幅
+  fn text_width(self, metrics: &dyn FontMetrics, text: &str, font_size: f32) -> f32 {
+    if self == Spacing::default() {
+      return metrics.text_width(text, font_size);
+    }
+    return sum(self.pieces(text).into_iter().map(|piece| metrics.text_width(piece, font_size) + self.after(piece)));
+  }
+
+  // 間隔を空けて並べた text の字形。区切りごとに作った字形をその位置までずらしてつなげる
+  pub(super) fn glyph_run(self, metrics: &dyn FontMetrics, text: &str, font_size: f32) -> Option<GlyphRun> {
+    if self == Spacing::default() {
+      return metrics.glyph_run(text, font_size);
+    }
+    let mut run: Option<GlyphRun> = None;
+    let mut x = 0.0;
+    for piece in self.pieces(text) {
+      let mut piece_run = metrics.glyph_run(piece, font_size)?;
+      for glyph in &mut piece_run.glyphs {
+        glyph.x += x;
+      }
+      x += metrics.text_width(piece, font_size) + self.after(piece);
+      match run {
+        Some(ref mut run) => run.glyphs.extend(piece_run.glyphs),
+        None => run = Some(piece_run),
+      }
+    }
+    return run.or_else(|| metrics.glyph_run(text, font_size));
+  }
+}
+
+// テキストを描く大きさと色と字体。インラインの要素と、行を並べるブロックの値を引き継ぐ
+#[derive(Clone, Copy)]
+pub(super) struct Font<'a> {
+  pub(super) size: f32,
+  pub(super) color: Color,
+  family: Option<&'a Value>, // font-family の値（List なら前のものほど優先）
+  weight: f32,
+  italic: bool,
+  valign: VerticalAlign, // 引き継がないプロパティだが、中の断片も要素の箱と一緒に動かす
+  pub(super) white_space: WhiteSpace,
+  word_break: WordBreak,
+  overflow_wrap: bool, // overflow-wrap（word-wrap）が break-word か anywhere
+  hyphens: Hyphens,
+  lang: Option<&'a str>, // lang 属性（行を並べるブロックとインラインの要素のもの）
+  transform: TextTransform,
+  decorations: [Option<Color>; 3], // DECORATION_LINES の線ごとの色（引かなければ None）。祖先の線も引く
+  pub(super) visible: bool,
+  pub(super) spacing: Spacing,
+  line_height: LineHeight,
+}
+
+impl<'a> Default for Font<'a> {
+  fn default() -> Font<'a> {
+    return Font { size: DEFAULT_FONT_SIZE, color: DEFAULT_COLOR, family: None, weight: 400.0, italic: false, valign: VerticalAlign::Baseline, white_space: WhiteSpace::Normal, word_break: WordBreak::Normal, overflow_wrap: false, hyphens: Hyphens::Manual, lang: None, transform: TextTransform::None, decorations: [None; 3], visible: true, spacing: Spacing::default(), line_height: LineHeight::Normal };
+  }
+}
+
+impl<'a> Font<'a> {
+  // style に font-size や color、font-family などがあればそれに変える
+  pub(super) fn inherit(self, style: &'a StyledNode, viewport: Rect) -> Font<'a> {
+    // vw などで決まる大きさも MAX_FONT_SIZE まで
+    let size = match style.value("font-size").map(|size| resolve_length(size, None, viewport)) {
+      Some(Length(size, Px)) => size.min(MAX_FONT_SIZE),
+      _ => self.size,
+    };
+    let color = match style.value("color") {
+      Some(ColorValue(color)) => color,
+      _ => self.color,
+    };
+    let weight = match style.value("font-weight") {
+      Some(Number(weight)) => weight,
+      _ => self.weight,
+    };
+    let italic = match style.value("font-style") {
+      Some(Keyword(ref keyword)) => keyword == "italic" || keyword == "oblique",
+      _ => self.italic,
+    };
+    let white_space = match style.value("white-space") {
+      Some(Keyword(ref keyword)) => match &**keyword {
+        "pre" => WhiteSpace::Pre,
+        "nowrap" => WhiteSpace::Nowrap,
+        _ => WhiteSpace::Normal,
+      },
+      _ => self.white_space,
+    };
+    let word_break = match style.value("word-break") {
+      Some(Keyword(ref keyword)) => match &**keyword {
+        "break-all" => WordBreak::BreakAll,
+        "keep-all" => WordBreak::KeepAll,
+        _ => WordBreak::Normal,
+      },
+      _ => self.word_break,
+    };
+    let overflow_wrap = match style.value("overflow-wrap").or(style.value("word-wrap")) {
+      Some(Keyword(ref keyword)) => keyword == "break-word" || keyword == "anywhere",
+      _ => self.overflow_wrap,
+    };
+    let hyphens = match style.value("hyphens") {
+      Some(Keyword(ref keyword)) => match &**keyword {
+        "none" => Hyphens::None,
+        "auto" => Hyphens::Auto,
+        _ => Hyphens::Manual,
+      },
+      _ => self.hyphens,
+    };
+    let lang = match style.node.node_type {
+      NodeType::Element(ref elem) => elem.attributes.get("lang").map(|lang| &**lang).or(self.lang),
+      NodeType::Text(_) | NodeType::Comment(_) => self.lang,
+    };
+    let transform = match style.value("text-transform") {
+      Some(Keyword(ref keyword)) => match &**keyword {
+        "uppercase" => TextTransform::Uppercase,
+        "lowercase" => TextTransform::Lowercase,
+        "capitalize" => TextTransform::Capitalize,
+        _ => TextTransform::None,
+      },
+      _ => self.transform,
+    };
+    // 線の色は text-decoration-color（currentcolor なら要素の color）
+    let mut decorations = self.decorations;
+    let lines = match style.value("text-decoration-line") {
+      Some(Value::List(lines)) => lines,
+      Some(line) => vec![line],
+      None => Vec::new(),
+    };
+    let decoration_color = match style.value("text-decoration-color") {
+      Some(ColorValue(decoration_color)) => decoration_color,
+      _ => color,
+    };
+    for line in lines {
+      if let Keyword(ref keyword) = line {
+        if let Some(index) = DECORATION_LINES.iter().position(|&(name, _, _)| name == keyword) {
+          decorations[index] = Some(decoration_color);
+        }
+      }
+    }
+    let family = style.specified_values.get("font-family").or(self.family);
+    let spacing_length = |name: &str| match style.value(name).map(|value| resolve_length(value, None, viewport)) {
+      Some(Length(length, Px)) => Some(length),
+      Some(Keyword(ref keyword)) if keyword == "normal" => Some(0.0),
+      _ => None,
+    };
+    let spacing = Spacing { letter: spacing_length("letter-spacing").unwrap_or(self.spacing.letter), word: spacing_length("word-spacing").unwrap_or(self.spacing.word) };
+    let line_height = match style.value("line-height").map(|value| resolve_length(value, None, viewport)) {
+      Some(Keyword(ref keyword)) if keyword == "normal" => LineHeight::Normal,
+      Some(Number(factor)) => LineHeight::Number(factor),
+      Some(Length(height, Px)) => LineHeight::Px(height),
+      _ => self.line_height,
+    };
+    return Font {
+      size: size,
+      color: color,
+      family: family,
+      weight: weight,
+      italic: italic,
+      valign: self.valign,
+      white_space: white_space,
+      word_break: word_break,
+      overflow_wrap: overflow_wrap,
+      hyphens: hyphens,
+      lang: lang,
+      transform: transform,
+      decorations: decorations,
+      visible: style.visible(),
+      spacing: spacing,
+      line_height: line_height,
+    };
+  }
+
+  // インラインの要素 style に vertical-align があればそれに変える。self は親の字体
+  fn vertical_align(self, style: &StyledNode) -> Font<'a> {
+    let valign = match style.value("vertical-align") {
+      Some(Keyword(ref keyword)) => match &**keyword {
+        "baseline" => VerticalAlign::Baseline,
+        "middle" => VerticalAlign::Middle(self.size * 0.25),
+        "top" => VerticalAlign::Top,
+        "bottom" => VerticalAlign::Bottom,
+        _ => return self,
+      },
+      _ => return self,
+    };
+    return Font { valign: valign, ..self };
+  }
+
+  // 上端から height の断片の上端からベースラインまで。ascent はベースラインにそろえるときのもの
+  fn baseline(&self, ascent: f32, height: f32) -> f32 {
+    return match self.valign {
+      VerticalAlign::Middle(half) => height / 2.0 + half,
+      _ => ascent,
+    };
+  }
+
+  // text-transform を当てた文字列
+  pub(super) fn transform(&self, text: &str) -> String {
+    return match self.transform {
+      TextTransform::None => text.to_string(),
+      TextTransform::Uppercase => text.to_uppercase(),
+      TextTransform::Lowercase => text.to_lowercase(),
+      TextTransform::Capitalize => {
+        let mut capitalized = String::with_capacity(text.len());
+        let mut word_start = true;
+        for c in text.chars() {
+          if word_start && c.is_alphanumeric() {
+            capitalized.extend(c.to_uppercase());
+          } else {
+            capitalized.push(c);
+          }
+          word_start = c.is_whitespace();
+        }
+        capitalized
+      }
+    };
+  }
+
+  // baseline（断片の上端からベースラインまで）に合わせて引く text-decoration の線。太さは font-size の 1/16（1px 以上）
+  pub(super) fn text_decorations(&self, baseline: f32) -> Vec<TextDecoration> {
+    let thickness = (self.size / 16.0).max(1.0);
+    return DECORATION_LINES
+      .iter()
+      .zip(&self.decorations)
+      .filter_map(|(&(_, line, position), &color)| Some(TextDecoration { line: line, offset: baseline - position * self.size - thickness / 2.0, thickness: thickness, color: color? }))
+      .collect();
+  }
+
+  // text を改行できる位置と種類。hyphens: auto なら lang のパターンで単語の中の位置を足し、none なら単語の中の位置を除く
+  fn line_breaks(&self, text: &str) -> Vec<(usize, Break)> {
+    let breaks = text::line_breaks_with(text, self.word_break);
+    return match self.hyphens {
+      Hyphens::None => breaks.into_iter().filter(|&(_, kind)| kind != Break::Hyphen).collect(),
+      Hyphens::Manual => breaks,
+      Hyphens::Auto => match self.lang.and_then(hyphenation::patterns_for) {
+        Some(patterns) => text::hyphenate_breaks(text, breaks, patterns),
+        None => breaks,
+      },
+    };
+  }
+
+  // letter-spacing と word-spacing を足した text の幅。metrics はこの字体で測るもの（Font::metrics）
+  pub(super) fn text_width(&self, metrics: &dyn FontMetrics, text: &str) -> f32 {
+    return self.spacing.text_width(metrics, text, self.size);
+  }
+
+  // 行の中でとる高さ（line-height）。metrics はこの字体で測るもの
+  pub(super) fn line_height(&self, metrics: &dyn FontMetrics) -> f32 {
+    return match self.line_height {
+      LineHeight::Normal => metrics.line_height(self.size),
+      LineHeight::Number(factor) => factor * self.size,
+      LineHeight::Px(height) => height,
+    };
+  }
+
+  // metrics の中から、この字体で測って描くもの（選べなければ metrics）
+  pub(super) fn metrics<'m>(&self, metrics: &'m dyn FontMetrics) -> &'m dyn FontMetrics {
+    let mut families = Vec::new();
+    let values = match self.family {
+      Some(&Value::List(ref values)) => &values[..],
+      Some(value) => std::slice::from_ref(value),
+      None => &[],
+    };
+    for value in values {
+      if let Keyword(ref family) | Value::Str(ref family) = *value {
+        families.push(&**family);
+      }
+    }
+    let face = FontFace { families: &families, weight: self.weight, italic: self.italic };
+    return metrics.select(&face).unwrap_or(metrics);
+  }
+}
+
+// 行に置いた断片。owner は持ち主の InlineNode の番号、baseline は断片の上端からベースラインまで。
+// leading は line-height と断片の高さ（文字の高さ）の差の半分で、行の中では断片の上下にこれだけ場所をとる（負なら詰める）。
+// metrics と spacing は断片の字体で測って描くもの
+struct Placed<'m> {
+  owner: usize,
+  fragment: TextFragment,
+  baseline: f32,
+  leading: f32,
+  metrics: &'m dyn FontMetrics,
+  spacing: Spacing,
+  valign: VerticalAlign,
+}
+
+// インライン整形コンテキスト。今の行に断片を左から置いていき、行を閉じたときに高さと縦の位置を決める
+struct InlineContext<'m> {
+  metrics: &'m dyn FontMetrics,
+  viewport: Rect,
+  floats: &'m mut Floats,    // ブロック整形コンテキストの float
+  area: Rect,                // 行を並べる範囲（幅と左上）
+  y: f32,                    // 今の行の上端
+  left: f32,                 // 今の行の左端（左の float の右）
+  width: f32,                // 今の行の幅（float を除いた幅）
+  cursor: f32,               // 今の行で次に置く位置（left から）
+  line: Vec<Placed<'m>>,     // 今の行に置いた断片
+  fragments: Vec<Placed<'m>>, // 閉じた行の断片
+  lines: Vec<Rect>,
+  starts: Vec<(f32, f32)>,   // InlineNode を置き始めた位置（番号順）
+  breakable: bool,           // 今の位置で改行できるか
+  space: bool,               // 直前が空白か（行頭でも true）。続く空白を詰める
+  align: TextAlign,          // 行を並べるブロックの text-align
+}
+
+impl<'m> InlineContext<'m> {
+  fn new(area: Rect, metrics: &'m dyn FontMetrics, viewport: Rect, floats: &'m mut Floats) -> InlineContext<'m> {
+    let (left, width) = floats.line_space(area.y, area);
+    return InlineContext {
+      metrics: metrics,
+      viewport: viewport,
+      floats: floats,
+      area: area,
+      y: area.y,
+      left: left,
+      width: width,
+      cursor: 0.0,
+      line: Vec::new(),
+      fragments: Vec::new(),
+      lines: Vec::new(),
+      starts: Vec::new(),
+      breakable: false,
+      space: true,
+      align: TextAlign::Left,
+    };
+  }
+
+  // テキストを改行できる位置で区切って置く。空白の並びはひとつの空白にまとめ、行頭の空白は捨てる。
+  // 単語の中の位置で改行したら行末にハイフンを置く（ソフトハイフンは改行しなければ描かない）。
+  // nowrap なら幅を超えても改行しない
+  fn place_text(&mut self, text: &str, owner: usize, font: Font) {
+    let text = font.transform(text);
+    if font.white_space == WhiteSpace::Pre {
+      self.place_preformatted(&text, owner, font);
+      return;
+    }
+    let text = collapse_whitespace(&text);
+    let mut breaks = font.line_breaks(&text);
+    breaks.push((text.len(), Break::Mandatory));
+    let mut start = 0;
+    let mut hyphen = false; // 今の位置が単語の中か
+    for (end, kind) in breaks {
+      let segment = text[start..end].replace(text::SOFT_HYPHEN, "");
+      let mut segment = &*segment;
+      start = end;
+      if self.space {
+        segment = segment.trim_start_matches(' ');
+      }
+      if segment.is_empty() {
+        continue;
+      }
+      // 行末の空白ははみ出してもよい
+      let width = font.text_width(font.metrics(self.metrics), segment.trim_end_matches(' '));
+      if font.white_space == WhiteSpace::Normal && self.breakable && !self.line.is_empty() && self.cursor + width > self.width {
+        if hyphen {
+          self.push("-", owner, font);
+        }
+        self.wrap_line();
+        segment = segment.trim_start_matches(' ');
+        if segment.is_empty() {
+          continue;
+        }
+      }
+      if font.white_space == WhiteSpace::Normal && font.overflow_wrap {
+        self.place_breaking_word(segment, owner, font);
+      } else {
+        self.push(segment, owner, font);
+      }
+      self.space = segment.ends_with(' ');
+      // 区切りは改行できる位置。テキストの終わりは空白の後だけ
+      self.breakable = end < text.len() || self.space;
+      hyphen = kind == Break::Hyphen;
+    }
+  }
+
+  // segment を置く。行に収まらなければ、収まるところまでの書記素クラスタで区切って次の行に送る（overflow-wrap: break-word）。
+  // 前に何か置いた行にひとつも収まらなければ先に改行し、空の行にもひとつも収まらなければ 1 つだけ置く
+  fn place_breaking_word(&mut self, segment: &str, owner: usize, font: Font) {
+    let metrics = font.metrics(self.metrics);
+    let mut rest = segment;
+    while self.cursor + font.text_width(metrics, rest.trim_end_matches(' ')) > self.width {
+      let mut end = 0;
+      for grapheme in text::graphemes(rest) {
+        if self.cursor + font.text_width(metrics, &rest[..end + grapheme.len()]) > self.width {
+          break;
+        }
+        end += grapheme.len();
+      }
+      if end == 0 {
+        if !self.line.is_empty() {
+          self.wrap_line();
+          continue;
+        }
+        end = text::graphemes(rest)[0].len();
+      }
+      self.push(&rest[..end], owner, font);
+      self.wrap_line();
+      rest = &rest[end..];
+    }
+    if !rest.is_empty() {
+      self.push(rest, owner, font);
+    }
+  }
+
+  // white-space: pre のテキストを置く。空白はそのまま置き（タブは TAB_SIZE 文字ごとの位置までの空白にする）、改行文字でだけ行を閉じる
+  fn place_preformatted(&mut self, text: &str, owner: usize, font: Font) {
+    for (i, line) in text.split('\n').enumerate() {
+      if i > 0 {
+        // 何も置いていない行も 1 行の高さをとる
+        if self.line.is_empty() {
+          self.push("", owner, font);
+        }
+        self.finish_line();
+      }
+      let line = expand_tabs(line.trim_end_matches('\r'));
+      if !line.is_empty() {
+        self.push(&line, owner, font);
+      }
+    }
+    self.breakable = false;
+    self.space = false;
+  }
+
+  // 画像のような、中で改行しない width x height の箱を置く。下端をベースラインにそろえ、
+  // 文字のない断片として持ち主のコンテンツボックスになる
+  fn place_box(&mut self, width: f32, height: f32, owner: usize, font: Font) {
+    // 箱の前後は改行できる位置
+    if !self.line.is_empty() && self.cursor + width > self.width {
+      self.wrap_line();
+    }
+    let rect = Rect { x: self.left + self.cursor, y: 0.0, width: width, height: height };
+    let fragment = TextFragment { text: String::new(), rect: rect, font_size: font.size, color: font.color, glyphs: None, visible: font.visible, decorations: Vec::new() };
+    self.line.push(Placed { owner: owner, fragment: fragment, baseline: font.baseline(height, height), leading: 0.0, metrics: self.metrics, spacing: Spacing::default(), valign: font.valign });
+    self.cursor += width;
+    self.breakable = true;
+    self.space = false;
+  }
+
+  // 今の行に置く。同じ持ち主の断片が続いていればつなげる（justify では空白の幅を広げるので、区切りごとに分けておく）
+  fn push(&mut self, segment: &str, owner: usize, font: Font) {
+    let metrics = font.metrics(self.metrics);
+    let advance = font.text_width(metrics, segment);
+    let x = self.left + self.cursor;
+    self.cursor += advance;
+    if let Some(last) = self.line.last_mut() {
+      let rect = last.fragment.rect;
+      if last.owner == owner && rect.x + rect.width == x && self.align != TextAlign::Justify {
+        last.fragment.text.push_str(segment);
+        last.fragment.rect.width += advance;
+        return;
+      }
+    }
+    let height = metrics.line_height(font.size);
+    let rect = Rect { x: x, y: 0.0, width: advance, height: height };
+    let decorations = font.text_decorations(metrics.baseline(font.size));
+    let fragment = TextFragment { text: segment.to_string(), rect: rect, font_size: font.size, color: font.color, glyphs: None, visible: font.visible, decorations: decorations };
+    let baseline = font.baseline(metrics.baseline(font.size), height);
+    let leading = (font.line_height(metrics) - height) / 2.0;
+    self.line.push(Placed { owner: owner, fragment: fragment, baseline: baseline, leading: leading, metrics: metrics, spacing: font.spacing, valign: font.valign });
+  }
+
+  // 幅を超えたので行を閉じる。justify ならこの行の空白を広げて両端をそろえる
+  fn wrap_line(&mut self) {
+    self.close_line(self.align);
+  }
+
+  // 段落の最後などで行を閉じる。justify でも広げない
+  fn finish_line(&mut self) {
+    let align = if self.align == TextAlign::Justify { TextAlign::Left } else { self.align };
+    self.close_line(align);
+  }
+
+  // 行を閉じる。断片を align で横に寄せ、ベースラインをそろえて縦の位置を決め、次の行へ進む
+  fn close_line(&mut self, align: TextAlign) {
+    self.cursor = 0.0;
+    self.breakable = false;
+    self.space = true;
+    if self.line.is_empty() {
+      return;
+    }
+    // 行末の空白は幅に入れない
+    if let Some(last) = self.line.last_mut() {
+      let trimmed = last.fragment.text.trim_end_matches(' ').len();
+      let spaces = last.fragment.text.split_off(trimmed);
+      last.fragment.rect.width -= last.spacing.text_width(last.metrics, &spaces, last.fragment.font_size);
+    }
+    self.align_line(align);
+
+    // top と bottom のものを除いてベースラインをそろえ、その高さより大きければ行を広げる。高さは断片の上下に leading を足したもの
+    let aligned = |placed: &&Placed| placed.valign != VerticalAlign::Top && placed.valign != VerticalAlign::Bottom;
+    let baseline = self.line.iter().filter(aligned).map(|placed| placed.baseline + placed.leading).fold(0.0, f32::max);
+    let below = self.line.iter().filter(aligned).map(|placed| placed.fragment.rect.height - placed.baseline + placed.leading).fold(0.0, f32::max);
+    let tallest = |valign: VerticalAlign| self.line.iter().filter(|placed| placed.valign == valign).map(|placed| placed.fragment.rect.height + placed.leading * 2.0).fold(0.0, f32::max);
+    let (top, bottom) = (tallest(VerticalAlign::Top), tallest(VerticalAlign::Bottom));
+    // bottom のものが高ければ、ベースラインにそろえたものはその分下に置く
+    let offset = (bottom - baseline - below).max(0.0);
+    let height = (baseline + below + offset).max(top);
+    for mut placed in self.line.drain(..) {
+      placed.fragment.rect.y = match placed.valign {
+        VerticalAlign::Top => self.y + placed.leading,
+        VerticalAlign::Bottom => self.y + height - placed.fragment.rect.height - placed.leading,
+        _ => self.y + offset + baseline - placed.baseline,
+      };
+      placed.fragment.glyphs = placed.spacing.glyph_run(placed.metrics, &placed.fragment.text, placed.fragment.font_size);
+      self.fragments.push(placed);
+    }
+    self.lines.push(Rect { x: self.left, y: self.y, width: self.width, height: height });
+    self.y += height;
+    self.start_line();
+  }
+
+  // 行の残りの幅を、right と center なら断片の左に、justify なら空白で終わる断片の後ろに分けて横にずらす
+  fn align_line(&mut self, align: TextAlign) {
+    let right = self.line.iter().map(|placed| placed.fragment.rect.x + placed.fragment.rect.width).fold(self.left, f32::max);
+    let free = self.width - (right - self.left);
+    if free <= 0.0 {
+      return;
+    }
+    match align {
+      TextAlign::Left => {}
+      TextAlign::Right | TextAlign::Center => {
+        let dx = if align == TextAlign::Right { free } else { free / 2.0 };
+        for placed in &mut self.line {
+          placed.fragment.rect.x += dx;
+        }
+      }
+      TextAlign::Justify => {
+        let last = self.line.len() - 1;
+        let gaps = self.line[..last].iter().filter(|placed| placed.fragment.text.ends_with(' ')).count();
+        if gaps == 0 {
+          return;
+        }
+        let mut dx = 0.0;
+        for placed in &mut self.line {
+          placed.fragment.rect.x += dx;
+          if placed.fragment.text.ends_with(' ') {
+            dx += free / gaps as f32;
+          }
+        }
+      }
+    }
+  }
+
+  // 新しい行の左端と幅を、その高さにある float を除いて決める
+  fn start_line(&mut self) {
+    let (left, width) = self.floats.line_space(self.y, self.area);
+    self.left = left;
+    self.width = width;
+  }
+
+  // 今の行に float を置いたあと、行の幅を縮める。左の float なら置いてある断片を右にずらす
+  fn narrow_line(&mut self) {
+    let (left, width) = self.floats.line_space(self.y, self.area);
+    let dx = left - self.left;
+    for placed in &mut self.line {
+      placed.fragment.rect.x += dx;
+    }
+    self.left = left;
+    self.width = width;
+  }
+}
+
+impl<'a> LayoutBox<'a> {
+  // インラインの子を行に並べる。幅は包含ブロックいっぱい（float のあるところはそれを除いた幅）で、高さは行の高さの合計
+  pub(super) fn layout_anonymous(&mut self, containing_block: Dimensions, parent: &'a StyledNode<'a>, metrics: &dyn FontMetrics, viewport: Rect, floats: &mut Floats) -> Result<(), LayoutError> {
+    let cb = containing_block.content;
+    let content = Rect { x: cb.x, y: cb.y + cb.height, width: cb.width, height: 0.0 };
+    let mut context = InlineContext::new(content, metrics, viewport, floats);
+    context.align = TextAlign::of(parent);
+    let font = Font::default().inherit(parent, viewport);
+    if let InlineNode(_) = self.box_type {
+      self.place_inline(&mut context, font)?;
+    } else {
+      for child in &mut self.children {
+        child.place_inline(&mut context, font)?;
+      }
+    }
+    context.finish_line();
+
+    let mut fragments = context.fragments.into_iter().peekable();
+    let mut index = 0;
+    if let InlineNode(_) = self.box_type {
+      self.assign_fragments(&mut fragments, &context.starts, &mut index);
+    } else {
+      for child in &mut self.children {
+        child.assign_fragments(&mut fragments, &context.starts, &mut index);
+      }
+    }
+    self.lines = context.lines;
+    // インラインの中のブロックの負の margin で上に戻っても、高さは負にしない
+    self.dimensions.content = Rect { height: (context.y - content.y).max(0.0), ..content };
+    return Ok(());
+  }
+
+  // インライン整形コンテキストに自身を並べる。InlineNode には先行順に番号を付ける（assign_fragments と同じ順）
+  fn place_inline(&mut self, context: &mut InlineContext, font: Font) -> Result<(), LayoutError> {
+    let style = match self.box_type {
+      InlineNode(style) => style,
+      BlockNode(_) | ReplacedNode(_) if self.position.is_out_of_flow() => {
+        // 絶対配置のものは今の位置を静的位置として覚えるだけ
+        self.dimensions.content = Rect { x: context.left + context.cursor, y: context.y, width: 0.0, height: 0.0 };
+        return Ok(());
+      }
+      BlockNode(_) | ReplacedNode(_) if self.float != Float::None => {
+        // float は今の行に入ればその行の上端に、入らなければ行を閉じて次の行に置く
+        self.layout_float(context.area, context.metrics, context.viewport)?;
+        if !context.line.is_empty() && self.dimensions.margin_box().width > context.width - context.cursor {
+          context.wrap_line();
+        }
+        self.place_float(context.floats, context.area, context.y);
+        context.narrow_line();
+        return Ok(());
+      }
+      BlockNode(style) => return self.place_block_in_inline(context, style),
+      ReplacedNode(style) if !self.is_atomic_inline() => return self.place_block_in_inline(context, style),
+      AnonymousBlock => {
+        for child in &mut self.children {
+          child.place_inline(context, font)?;
+        }
+        return Ok(());
+      }
+      InlineBlockNode(_) | ReplacedNode(_) => {
+        // 中を自分のブロック整形コンテキストでレイアウトしてから、マージンボックスを 1 つの箱として行に置く。
+        // 下端をベースラインにそろえる（位置は assign_fragments で決まる）
+        self.layout_float(context.area, context.metrics, context.viewport)?;
+        let owner = context.starts.len();
+        context.starts.push((context.left + context.cursor, context.y));
+        let margin_box = self.dimensions.margin_box();
+        context.place_box(margin_box.width, margin_box.height, owner, font.vertical_align(self.get_style_node()?));
+        return Ok(());
+      }
+    };
+    let owner = context.starts.len();
+    context.starts.push((context.left + context.cursor, context.y));
+    match style.node.node_type {
+      NodeType::Text(ref text) => context.place_text(text, owner, font),
+      NodeType::Comment(_) => {}
+      NodeType::Element(_) => {
+        let font = font.vertical_align(style).inherit(style, context.viewport);
+        self.calculate_inline_edges(context.area.width, context.viewport);
+        let d = self.dimensions;
+        context.cursor += d.margin.left + d.border.left + d.padding.left;
+        if let Some(content) = style.content() {
+          context.place_text(content, owner, font);
+        }
+        for child in &mut self.children {
+          child.place_inline(context, font)?;
+        }
+        context.cursor += d.margin.right + d.border.right + d.padding.right;
+      }
+    }
+    return Ok(());
+  }
+
+  // インラインの中のブロックは、行を閉じてその下に置く
+  fn place_block_in_inline(&mut self, context: &mut InlineContext, style: &'a StyledNode<'a>) -> Result<(), LayoutError> {
+    context.finish_line();
+    let area = context.area;
+    let containing_block = Dimensions { content: Rect { height: context.y - area.y, ..area }, ..Default::default() };
+    self.layout(containing_block, style, context.metrics, context.viewport, context.floats, None)?;
+    let margin_box = self.dimensions.margin_box();
+    context.y = margin_box.y + margin_box.height;
+    context.start_line();
+    return Ok(());
+  }
+
+  // インラインボックスの margin, border, padding。左右は行の中で場所をとるが、上下は行の高さに入らない。
+  // % は行を並べる幅に対する割合
+  fn calculate_inline_edges(&mut self, width: f32, viewport: Rect) {
+    let style = match self.box_type {
+      InlineNode(style) => style,
+      _ => return,
+    };
+    let zero = Length(0.0, Px);
+    let edge = |name: &str, fallback_name: &str| resolve_length(style.lookup(name, fallback_name, &zero), Some(width), viewport).to_px();
+    let border = |side: &str| resolve_length(style.border_width(side), Some(width), viewport).to_px();
+    let d = &mut self.dimensions;
+    d.margin = EdgeSizes { left: edge("margin-left", "margin"), right: edge("margin-right", "margin"), top: edge("margin-top", "margin"), bottom: edge("margin-bottom", "margin") };
+    d.border = EdgeSizes {
+      left: border("left"),
+      right: border("right"),
+      top: border("top"),
+      bottom: border("bottom"),
+    };
+    d.padding = EdgeSizes { left: edge("padding-left", "padding"), right: edge("padding-right", "padding"), top: edge("padding-top", "padding"), bottom: edge("padding-bottom", "padding") };
+  }
+
+  // 位置の決まった断片を持ち主のボックスに移して、InlineNode のコンテンツボックスを断片と子を囲む範囲にする。
+  // 返すのはマージンボックス（インラインの中のブロックは含めない）
+  fn assign_fragments(&mut self, fragments: &mut Peekable<vec::IntoIter<Placed>>, starts: &[(f32, f32)], index: &mut usize) -> Option<Rect> {
+    match self.box_type {
+      InlineNode(_) => {}
+      BlockNode(_) => return None,
+      ReplacedNode(_) if !self.is_atomic_inline() => return None,
+      InlineBlockNode(_) | ReplacedNode(_) => {
+        // 置いた箱の位置まで、中身ごと動かす
+        let owner = *index;
+        *index += 1;
+        let margin_box = self.dimensions.margin_box();
+        while fragments.peek().map_or(false, |placed| placed.owner == owner) {
+          let rect = fragments.next().unwrap().fragment.rect;
+          self.translate(rect.x - margin_box.x, rect.y - margin_box.y);
+        }
+        return Some(self.dimensions.margin_box());
+      }
+      AnonymousBlock => {
+        return self.children.iter_mut().filter_map(|child| child.assign_fragments(fragments, starts, index)).fold(None, |rect: Option<Rect>, child| Some(rect.map_or(child, |rect| rect.union(child))));
+      }
+    }
+    let owner = *index;
+    *index += 1;
+    let mut bounds: Option<Rect> = None;
+    while fragments.peek().map_or(false, |placed| placed.owner == owner) {
+      let fragment = fragments.next().unwrap().fragment;
+      bounds = Some(bounds.map_or(fragment.rect, |rect| rect.union(fragment.rect)));
+      self.fragments.push(fragment);
+    }
+    for child in &mut self.children {
+      if let Some(child) = child.assign_fragments(fragments, starts, index) {
+        bounds = Some(bounds.map_or(child, |rect| rect.union(child)));
+      }
+    }
+    let d = &mut self.dimensions;
+    // 負の margin の子のマージンボックスは幅が負になりうるが、囲む範囲は負にしない
+    d.content = bounds.map(|rect| Rect { width: rect.width.max(0.0), height: rect.height.max(0.0), ..rect }).unwrap_or_else(|| {
+      let (x, y) = starts[owner];
+      Rect { x: x + d.margin.left + d.border.left + d.padding.left, y: y, width: 0.0, height: 0.0 }
+    });
+    return Some(d.margin_box());
+  }
+}
+
+// white-space: pre のタブを、次の TAB_SIZE 文字ごとの位置までの空白にする
+pub(super) fn expand_tabs(text: &str) -> String {
+  let mut expanded = String::with_capacity(text.len());
+  let mut column = 0;
+  for c in text.chars() {
+    if c == '\t' {
+      let spaces = TAB_SIZE - column % TAB_SIZE;
+      expanded.extend(std::iter::repeat(' ').take(spaces));
+      column += spaces;
+    } else {
+      expanded.push(c);
+      column += 1;
+    }
+  }
+  return expanded;
+}
+
+// 空白と改行の並びをひとつの空白にする（white-space: normal）
+pub(super) fn collapse_whitespace(text: &str) -> String {
+  let mut collapsed = String::with_capacity(text.len());
+  let mut space = false;
+  for c in text.chars() {
+    if c == ' ' || c == '\t' || c == '\n' || c == '\r' || c == '\u{0c}' {
+      if !space {
+        collapsed.push(' ');
+      }
+      space = true;
+    } else {
+      collapsed.push(c);
+      space = false;
+    }
+  }
+  return collapsed;
+}
+
+// word-spacing を足す空白
+fn is_word_separator(c: char) -> bool {
+  return c == ' ' || c == '\u{a0}';
+}
@@ -0,0 +1,124 @@
+use css::Unit::Px;
+use css::Value::Length;
+use dom::{Node, NodeType};
+
+use super::{resolve_length, LayoutBox, Rect, ReplacedNode};
+
+/**
+ * 置換要素（<img>、<canvas>、<video>）のボックス。
+ *
+ * 中身は固有の大きさを持ち、子のボックスは作らない。<img> は画像の大きさ、<canvas> は width と height の属性（既定は 300x150）、
+ * <video> は poster の画像の大きさ（読み込めなければ <canvas> と同じ）。画像を読み込めなかった <img> には固有の大きさがない。
+ * width と height は、片方だけ指定すれば縦横比を保ち、どちらも auto なら固有の大きさを min-width、max-width、min-height、max-height に
+ * 縦横比を保ったまま合わせる（CSS 2.1 の 10.4 の表）。
+ * display がインラインなら inline-block と同じく行の中の 1 つの箱に、ブロックならブロックとしてレイアウトする
+ */
+
+const REPLACED_ELEMENTS: &[&str] = &["img", "canvas", "video"];
+// <canvas> と <video> の width と height の既定値
+const DEFAULT_WIDTH: f32 = 300.0;
+const DEFAULT_HEIGHT: f32 = 150.0;
+
+// node が置換要素か
+pub(super) fn is_replaced(node: &Node) -> bool {
+  return match node.node_type {
+    NodeType::Element(ref elem) => REPLACED_ELEMENTS.contains(&&*elem.tag_name),
+    NodeType::Text(_) | NodeType::Comment(_) => false,
+  };
+}
+
+// 固有の幅と高さ。どちらも 0 より大きいときだけ縦横比がある
+fn intrinsic_size(layout_box: &LayoutBox) -> Option<(f32, f32)> {
+  if let Some(ref image) = layout_box.image {
+    return Some((image.width as f32, image.height as f32));
+  }
+  let elem = match layout_box.box_type.style_node()?.node.node_type {
+    NodeType::Element(ref elem) => elem,
+    NodeType::Text(_) | NodeType::Comment(_) => return None,
+  };
+  let attribute = |name: &str, default: f32| elem.attributes.get(name).and_then(|value| value.trim().parse::<f32>().ok()).filter(|&length| length >= 0.0).unwrap_or(default);
+  return match &*elem.tag_name {
+    "canvas" | "video" => Some((attribute("width", DEFAULT_WIDTH), attribute("height", DEFAULT_HEIGHT))),
+    _ => None,
+  };
+}
+
+fn has_ratio(width: f32, height: f32) -> bool {
+  return width > 0.0 && height > 0.0;
+}
+
+// width と height が auto のときの大きさ。固有の大きさ (width, height) を縦横比を保ったまま制約に合わせる（CSS 2.1 の 10.4 の表）。
+// max-width が min-width より小さければ min-width にする（高さも同じ）
+fn constrain((width, height): (f32, f32), min_width: f32, max_width: Option<f32>, min_height: f32, max_height: Option<f32>) -> (f32, f32) {
+  let max_width = max_width.map_or(f32::INFINITY, |max_width| max_width.max(min_width));
+  let max_height = max_height.map_or(f32::INFINITY, |max_height| max_height.max(min_height));
+  if !has_ratio(width, height) {
+    return (width.max(min_width).min(max_width), height.max(min_height).min(max_height));
+  }
+  return match (width > max_width, width < min_width, height > max_height, height < min_height) {
+    (true, _, true, _) if max_width / width <= max_height / height => (max_width, (max_width * height / width).max(min_height)),
+    (true, _, true, _) => ((max_height * width / height).max(min_width), max_height),
+    (_, true, _, true) if min_width / width <= min_height / height => ((min_height * width / height).min(max_width), min_height),
+    (_, true, _, true) => (min_width, (min_width * height / width).min(max_height)),
+    (_, true, true, _) => (min_width, max_height),
+    (true, _, _, true) => (max_width, min_height),
+    (true, _, _, _) => (max_width, (max_width * height / width).max(min_height)),
+    (_, true, _, _) => (min_width, (min_width * height / width).min(max_height)),
+    (_, _, true, _) => ((max_height * width / height).max(min_width), max_height),
+    (_, _, _, true) => ((min_height * width / height).min(max_width), min_height),
+    _ => (width, height),
+  };
+}
+
+impl<'a> LayoutBox<'a> {
+  // width や height の CSS の値（px になるもの）。CSS で指定していなければ同じ名前の属性（<img width="100">）。
+  // <canvas> の width と height の属性は固有の大きさなので読まない。box-sizing: border-box なら border と padding を引いた大きさ
+  fn specified_length(&self, name: &str, base: Option<f32>, viewport: Rect) -> Option<f32> {
+    let style = self.box_type.style_node()?;
+    let sizing = if name.ends_with("width") { self.border_box_width_edges(base, viewport) } else { self.border_box_height_edges() };
+    let length = match style.value(name) {
+      Some(value) => match resolve_length(value, base, viewport) {
+        Length(length, Px) => Some(length),
+        _ => None,
+      },
+      None => match style.node.node_type {
+        NodeType::Element(ref elem) if elem.tag_name != "canvas" => elem.attributes.get(name).and_then(|value| value.trim().trim_end_matches("px").parse().ok()),
+        NodeType::Element(_) | NodeType::Text(_) | NodeType::Comment(_) => None,
+      },
+    };
+    return length.map(|length| (length - sizing).max(0.0));
+  }
+
+  // 置換要素のコンテンツボックスの大きさ。width と min-width、max-width の % は base に対する割合。置換要素でなければ None
+  pub(super) fn replaced_size(&self, base: Option<f32>, viewport: Rect) -> Option<(f32, f32)> {
+    match self.box_type {
+      ReplacedNode(_) => {}
+      _ => return None,
+    }
+    let width = self.specified_length("width", base, viewport);
+    let height = self.specified_length("height", self.containing_height, viewport);
+    return Some(match (width, height, intrinsic_size(self)) {
+      (Some(width), Some(height), _) => (width, height),
+      (Some(width), None, Some((intrinsic_width, intrinsic_height))) if has_ratio(intrinsic_width, intrinsic_height) => (width, width * intrinsic_height / intrinsic_width),
+      (None, Some(height), Some((intrinsic_width, intrinsic_height))) if has_ratio(intrinsic_width, intrinsic_height) => (height * intrinsic_width / intrinsic_height, height),
+      (None, None, Some(intrinsic)) => {
+        let constraint = |name: &str, base: Option<f32>| self.specified_length(name, base, viewport).map(|length| length.max(0.0));
+        let containing_height = self.containing_height;
+        constrain(intrinsic, constraint("min-width", base).unwrap_or(0.0), constraint("max-width", base), constraint("min-height", containing_height).unwrap_or(0.0), constraint("max-height", containing_height))
+      }
+      (width, height, intrinsic) => (width.or(intrinsic.map(|size| size.0)).unwrap_or(0.0), height.or(intrinsic.map(|size| size.1)).unwrap_or(0.0)),
+    });
+  }
+
+  // 幅が width に決まったときのコンテンツボックスの高さ。height が auto なら縦横比を保つ（min-height と max-height は後で合わせる）
+  pub(super) fn replaced_height(&self, width: f32, viewport: Rect) -> f32 {
+    if let Some(height) = self.specified_length("height", self.containing_height, viewport) {
+      return height;
+    }
+    return match intrinsic_size(self) {
+      Some((intrinsic_width, intrinsic_height)) if has_ratio(intrinsic_width, intrinsic_height) => width * intrinsic_height / intrinsic_width,
+      Some((_, intrinsic_height)) => intrinsic_height,
+      None => 0.0,
+    };
+  }
+}
@@ -0,0 +1,212 @@
+use css::Unit::Px;
+use css::Value;
+use css::Value::{Keyword, Length, List};
+use dom::NodeType;
+use images::Images;
+use style::{Display, Float, StyledNode};
+
+use super::grid::offsets;
+use super::{block_node, build_children, build_layout_tree, resolve_length, sum, AnonymousBlock, BlockNode, FontMetrics, LayoutBox, LayoutError, Rect};
+
+/**
+ * 表（display: table）のレイアウト。
+ *
+ * 表のボックスの子は行（table-row）で、行グループ（table-row-group など。<tbody>）の中の行も表の直下に並べる。
+ * 行の子の要素はそれぞれセル（ブロック）になり、続いたテキストは anonymous のセルにまとめる。行の外にあるセルは anonymous の行に入れる。
+ * 列の数はいちばんセルの多い行のセルの数（colspan と rowspan はまだ読まない）。
+ *
+ * table-layout: fixed なら、最初の行のセルの width を列の幅にして、残りを width のない列で等しく分ける。
+ * そうでなければ、列のセルの中身の幅（max-content）の最大を、その比で表の幅に合わせる。width が auto の表は中身の幅になる。
+ * 行は上から積み、セルは列の幅で自分のブロック整形コンテキストとしてレイアウトして、高さを行でいちばん高いセルに伸ばす。
+ * セルの間と外側は border-spacing だけ空ける（border-collapse はまだ読まない）
+ */
+
+// 表 table に style_node の子を行として入れる
+pub(super) fn build_rows<'a>(mut table: LayoutBox<'a>, style_node: &'a StyledNode<'a>, images: &Images) -> Result<LayoutBox<'a>, LayoutError> {
+  for child in &style_node.children {
+    if child.display() == Display::TableRowGroup {
+      for row in &child.children {
+        push_row(&mut table, row, images)?;
+      }
+    } else {
+      push_row(&mut table, child, images)?;
+    }
+  }
+  return Ok(table);
+}
+
+// child が行ならそのまま、そうでなければ最後の anonymous の行のセルにする
+fn push_row<'a>(table: &mut LayoutBox<'a>, child: &'a StyledNode<'a>, images: &Images) -> Result<(), LayoutError> {
+  match child.display() {
+    Display::None => {}
+    Display::TableRow => {
+      let mut row = LayoutBox::new(BlockNode(child));
+      for cell in &child.children {
+        push_cell(&mut row, cell, images)?;
+      }
+      table.children.push(row);
+    }
+    _ if is_whitespace(child) => {}
+    _ => {
+      match table.children.last() {
+        Some(&LayoutBox { box_type: AnonymousBlock, .. }) => {}
+        _ => table.children.push(LayoutBox::new(AnonymousBlock)),
+      }
+      push_cell(table.children.last_mut().unwrap(), child, images)?;
+    }
+  }
+  return Ok(());
+}
+
+// 行 row に child をセルとして足す。テキストとインラインの要素は anonymous のセルにまとめる
+fn push_cell<'a>(row: &mut LayoutBox<'a>, child: &'a StyledNode<'a>, images: &Images) -> Result<(), LayoutError> {
+  match child.display() {
+    Display::None => {}
+    _ if is_whitespace(child) => {}
+    Display::Inline | Display::InlineBlock => {
+      match row.children.last() {
+        Some(&LayoutBox { box_type: AnonymousBlock, .. }) => {}
+        _ => row.children.push(LayoutBox::new(AnonymousBlock)),
+      }
+      row.children.last_mut().unwrap().children.push(build_layout_tree(child, images)?);
+    }
+    _ => {
+      let mut cell = build_children(LayoutBox::new(block_node(child)), child, images)?;
+      cell.float = Float::None;
+      row.children.push(cell);
+    }
+  }
+  return Ok(());
+}
+
+// 空白だけのテキスト（行とセルの間の改行など）
+fn is_whitespace(node: &StyledNode) -> bool {
+  return match node.node.node_type {
+    NodeType::Text(ref text) => text.chars().all(|c| c == ' ' || c == '\t' || c == '\n' || c == '\r' || c == '\u{0c}'),
+    _ => false,
+  };
+}
+
+// border-spacing の横と縦。1 つなら両方、なければ 0
+fn border_spacing(style: &StyledNode, viewport: Rect) -> (f32, f32) {
+  let px = |value: &Value| match resolve_length(value.clone(), None, viewport) {
+    Length(length, Px) => length.max(0.0),
+    _ => 0.0,
+  };
+  return match style.value("border-spacing") {
+    Some(List(ref values)) if values.len() == 2 => (px(&values[0]), px(&values[1])),
+    Some(ref value) => (px(value), px(value)),
+    None => (0.0, 0.0),
+  };
+}
+
+fn column_count(table: &LayoutBox) -> usize {
+  return table.children.iter().map(|row| row.children.len()).max().unwrap_or(0);
+}
+
+// 列のセルの max-content に左右の margin、border、padding を足した幅の、列ごとの最大
+fn content_widths(table: &LayoutBox, columns: usize, metrics: &dyn FontMetrics, viewport: Rect) -> Vec<f32> {
+  let mut widths = vec![0.0; columns];
+  for row in &table.children {
+    for (width, cell) in widths.iter_mut().zip(&row.children) {
+      *width = cell.outer_max_content_width(metrics, viewport).max(*width);
+    }
+  }
+  return widths;
+}
+
+// table-layout: fixed の列の幅。最初の行のセルの width（% は available に対する割合。border-box ならコンテンツボックスの幅にしてから）に左右の border と padding を足したものにして、
+// 残りを width のない列で等しく分ける。すべての列に width があれば、その比で available に合わせる
+fn fixed_widths(table: &LayoutBox, columns: usize, available: f32, viewport: Rect) -> Vec<f32> {
+  let mut widths: Vec<Option<f32>> = vec![None; columns];
+  if let Some(row) = table.children.first() {
+    for (width, cell) in widths.iter_mut().zip(&row.children) {
+      *width = match cell.box_type.style_node().and_then(|style| style.value("width")).map(|width| resolve_length(width, Some(available), viewport)) {
+        Some(Length(width, Px)) => Some((width - cell.border_box_width_edges(Some(available), viewport)).max(0.0) + horizontal_edges(cell, viewport)),
+        _ => None,
+      };
+    }
+  }
+  let autos = widths.iter().filter(|width| width.is_none()).count();
+  if autos == 0 {
+    return fit(&widths.iter().map(|width| width.unwrap_or(0.0)).collect::<Vec<_>>(), available);
+  }
+  let share = (available - sum(widths.iter().filter_map(|width| *width))).max(0.0) / autos as f32;
+  return widths.iter().map(|width| width.unwrap_or(share)).collect();
+}
+
+// widths をその比で伸び縮みさせて、合計を available にする（すべて 0 なら等しく分ける）
+fn fit(widths: &[f32], available: f32) -> Vec<f32> {
+  let total = sum(widths.iter().cloned());
+  if total <= 0.0 {
+    return vec![available / widths.len() as f32; widths.len()];
+  }
+  return widths.iter().map(|width| width * available / total).collect();
+}
+
+// セルの左右の margin、border、padding（px のもの）
+fn horizontal_edges(cell: &LayoutBox, viewport: Rect) -> f32 {
+  let style = match cell.box_type.style_node() {
+    Some(style) => style,
+    None => return 0.0,
+  };
+  let zero = Length(0.0, Px);
+  let px = |value: Value| match resolve_length(value, None, viewport) {
+    Length(length, Px) => length,
+    _ => 0.0,
+  };
+  return px(style.lookup("margin-left", "margin", &zero))
+    + px(style.lookup("margin-right", "margin", &zero))
+    + px(style.border_width("left"))
+    + px(style.border_width("right"))
+    + px(style.lookup("padding-left", "padding", &zero))
+    + px(style.lookup("padding-right", "padding", &zero));
+}
+
+// 表の中身の幅（max-content）。列の中身の幅と border-spacing の合計
+pub(super) fn max_content_width(table: &LayoutBox, style: &StyledNode, metrics: &dyn FontMetrics, viewport: Rect) -> f32 {
+  let columns = column_count(table);
+  let (spacing, _) = border_spacing(style, viewport);
+  return sum(content_widths(table, columns, metrics, viewport).into_iter()) + spacing * (columns + 1) as f32;
+}
+
+impl<'a> LayoutBox<'a> {
+  // 列の幅を決めて行を上から積み、コンテンツボックスの auto の高さを決める（height があれば calculate_block_height で変える）
+  pub(super) fn layout_table(&mut self, metrics: &dyn FontMetrics, viewport: Rect) -> Result<(), LayoutError> {
+    let style = self.get_style_node()?;
+    let content = self.dimensions.content;
+    let (column_spacing, row_spacing) = border_spacing(style, viewport);
+    let columns = column_count(self);
+    if columns == 0 {
+      self.dimensions.content.height = 0.0;
+      return Ok(());
+    }
+    let available = (content.width - column_spacing * (columns + 1) as f32).max(0.0);
+    let fixed = match style.value("table-layout") {
+      Some(Keyword(ref keyword)) => keyword == "fixed",
+      _ => false,
+    };
+    let widths = if fixed { fixed_widths(self, columns, available, viewport) } else { fit(&content_widths(self, columns, metrics, viewport), available) };
+    let xs = offsets(content.x + column_spacing, &widths, column_spacing);
+
+    let mut y = content.y + row_spacing;
+    for row in &mut self.children {
+      // anonymous のセルの文字の大きさと色は行の値（anonymous の行なら表の値）
+      let container = row.box_type.style_node().unwrap_or(style);
+      let mut height: f32 = row.specified_height(viewport).unwrap_or(0.0);
+      for (i, cell) in row.children.iter_mut().enumerate() {
+        // セルは width によらず列の幅いっぱいにする
+        cell.flex_width = Some((widths[i] - horizontal_edges(cell, viewport)).max(0.0));
+        cell.layout_item(Rect { x: xs[i], y: y, width: widths[i], height: 0.0 }, container, metrics, viewport)?;
+        height = height.max(cell.dimensions.margin_box().height);
+      }
+      for cell in &mut row.children {
+        cell.stretch_height(height, viewport);
+      }
+      row.dimensions.content = Rect { x: content.x + column_spacing, y: y, width: (content.width - column_spacing * 2.0).max(0.0), height: height };
+      y += height + row_spacing;
+    }
+    self.dimensions.content.height = y - content.y;
+    return Ok(());
+  }
+}
@@ -0,0 +1,547 @@
+/*!
+ * HTML と CSS を読み込んで、スタイル適用 → レイアウト → 描画 までを行うブラウザエンジン
+ *
+ * ふつうは Engine に入力とビューポートを渡して render する。
+ *
+ * ```
+ * use browser_engine_suburi::{Engine, EngineOptions};
+ *
+ * let result = Engine::new(EngineOptions::default())
+ *   .html("<div class=\"a\">hello</div>")
+ *   .stylesheet(".a { height: 10px; background: red; }")
+ *   .viewport(320.0, 240.0)
+ *   .render()
+ *   .unwrap();
+ * assert_eq!((result.canvas.width, result.canvas.height), (320, 240));
+ * ```
+ *
+ * 各段階の型（DOM、スタイルシート、スタイルツリー、レイアウトツリー、キャンバス）もここから使えるので、
+ * html、css、style、layout、paint の関数を直接つないでもよい。
+ *
+ * ```
+ * use browser_engine_suburi::{css, html, images, layout, paint, style, Dimensions, Rect};
+ *
+ * let root = html::parse("<p>hello</p>".to_string()).unwrap();
+ * let stylesheet = css::parse("p { display: block; height: 20px; }".to_string()).unwrap();
+ * let styled = style::style_tree(&root, &stylesheet);
+ * let mut viewport = Dimensions::default();
+ * viewport.content.width = 200.0;
+ * let layout_root = layout::layout_tree(&styled, viewport, &images::Images::default()).unwrap();
+ * let display_list = paint::build_display_list(&layout_root);
+ * let canvas = paint::paint_display_list(&display_list, Rect { x: 0.0, y: 0.0, width: 200.0, height: 100.0 }).unwrap();
+ * assert_eq!(canvas.width, 200);
+ * ```
+ *
+ * ここで pub use している名前と関数、公開しているモジュールの pub な関数と型が公開 API で、バージョンは semver に従う
+ * （0.x のうちは、互換性のない変更でマイナーバージョンを上げる）。text、shaping、form、frame などの pub(crate) のモジュールは
+ * 実装のためのもので、公開の型から見えるもの（ViewportMeta、dom::Descendants、output::encode_svg など）だけを pub use している。
+ * style、layout、paint の中も style::{cascade, matcher}、layout::{block, inline}、paint::{display_list, raster} に分けてあるが、
+ * これらは非公開のモジュールで、中の名前は親のモジュールから pub use したものだけを使う。
+ * コマンドライン（src/bin/browser-engine-suburi）はこの公開 API だけで書いてある
+ */
+
+extern crate ab_glyph;
+#[cfg(feature = "js")]
+extern crate boa_engine;
+#[cfg(feature = "image")]
+extern crate image;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "watch")]
+extern crate notify;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "http")]
+extern crate reqwest;
+extern crate rustybuzz;
+extern crate serde;
+#[cfg(feature = "viewer")]
+extern crate softbuffer;
+extern crate thiserror;
+extern crate toml;
+extern crate unicode_bidi;
+extern crate unicode_script;
+extern crate unicode_segmentation;
+extern crate unicode_width;
+#[cfg(target_arch = "wasm32")]
+extern crate wasm_bindgen;
+#[cfg(feature = "viewer")]
+extern crate winit;
+
+use diagnostic::Diagnostic;
+use images::Images;
+use loader::{Loader, Source};
+use profile::{Counts, Timings};
+use url::Url;
+
+pub use css::{Color, StyleSheet};
+pub use dom::Node;
+pub use engine::{Engine, RenderResult};
+pub use error::EngineError;
+pub use layout::{Dimensions, LayoutBox, Rect};
+pub use options::EngineOptions;
+#[cfg(feature = "image")]
+pub use output::render_to_image;
+pub use paint::{Canvas, DisplayList};
+pub use style::StyledNode;
+pub use viewport::{ViewportMeta, ViewportWidth};
+
+pub mod a11y;
+pub mod animation;
+pub(crate) mod counters;
+pub mod css;
+pub(crate) mod data_url;
+pub mod diagnostic;
+pub mod dom;
+pub mod dump;
+pub mod engine;
+pub mod error;
+pub mod event;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ffi;
+pub mod font;
+pub(crate) mod form;
+pub(crate) mod frame;
+#[cfg(feature = "testing")]
+pub mod generate;
+pub mod history;
+pub mod html;
+pub(crate) mod hyphenation;
+pub mod images;
+pub mod inspect;
+pub mod layout;
+pub mod loader;
+pub mod memory;
+#[cfg(feature = "http")]
+pub(crate) mod net;
+pub mod options;
+#[cfg(feature = "image")]
+pub mod output;
+pub mod paint;
+pub(crate) mod parallel;
+pub mod print;
+pub mod profile;
+pub(crate) mod query;
+pub mod reader;
+#[cfg(feature = "js")]
+pub(crate) mod script;
+pub(crate) mod shaping;
+pub mod snapshot;
+pub mod style;
+pub(crate) mod text;
+pub mod trace;
+pub mod url;
+#[cfg(feature = "image")]
+pub(crate) mod vector;
+pub(crate) mod viewport;
+#[cfg(feature = "viewer")]
+pub mod viewer;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+// 組み込みのスタイルシート。EngineOptions::user_agent_stylesheet が true なら作者のものより前に適用する
+pub const USER_AGENT_STYLESHEET: &'static str = include_str!("ua.css");
+
+// 読み込んだドキュメントとスタイルシート
+pub struct LoadedDocument {
+  pub root_node: dom::Node,
+  pub stylesheet: css::StyleSheet,
+  pub sources: Vec<Url>, // 読み込んだリソースの URL（ドキュメントが先頭。読み込めなかった @import も含む）。--watch で監視する
+  pub base_url: Url,     // 相対 URL の基準
+  pub frames: Vec<Option<LoadedDocument>>, // <iframe> の中身（ドキュメント順）。読み込めなかったものは None
+  pub images: Images,                      // <img> と background-image の画像
+  pub timings: Timings,                    // 読み込み、パース、スクリプトにかかった時間（iframe の中身を含む）
+  pub parallelism: usize,                  // 描画するときに iframe の中身を同時にレイアウトし、タイルを同時に描くスレッドの数
+  pub tile_size: usize,                    // ラスタライズするタイルの一辺（EngineOptions::tile_size）
+  pub viewport_meta: Option<ViewportMeta>, // <meta name="viewport">（トップレベルのドキュメントで、meta_viewport のときだけ）
+  pub zoom: f32,                           // ページのズーム（トップレベルのドキュメントだけ。iframe の中は 1）
+  pub device_pixel_ratio: f32,             // 描画の倍率（トップレベルのドキュメントだけ。iframe の中は 1）
+  pub render: paint::RenderOptions,        // 背景の色、辺をピクセルに合わせるか、ボックスの枠を描くか（EngineOptions::render_options）
+  pub fonts: &'static font::FontContext,   // 文字を測って字形を並べるフォント（deterministic なら font::builtin）
+}
+
+impl LoadedDocument {
+  // キャンバスをタイルに分けて描くときの設定
+  pub fn tiling(&self) -> paint::Tiling {
+    return paint::Tiling { tile_size: self.tile_size, parallelism: self.parallelism };
+  }
+
+  // 端末のビューポートに <meta name="viewport"> とズームを当てた、レイアウトに使うビューポートと描画の倍率。
+  // ズームはレイアウトに使うビューポートを 1 / zoom にして、その分だけ拡大して描く（キャンバスの大きさは変わらない）。
+  // device_pixel_ratio はレイアウトを変えずに倍率だけに掛ける（キャンバスが device_pixel_ratio 倍になる）
+  pub fn layout_viewport(&self, viewport: layout::Rect) -> (layout::Rect, f32) {
+    let (viewport, scale) = match self.viewport_meta {
+      Some(ref meta) => meta.resolve(viewport),
+      None => (viewport, 1.0),
+    };
+    if self.zoom == 1.0 {
+      return (viewport, scale * self.device_pixel_ratio);
+    }
+    return (layout::Rect { width: viewport.width / self.zoom, height: viewport.height / self.zoom, ..viewport }, scale * self.zoom * self.device_pixel_ratio);
+  }
+}
+
+// ドキュメントと、適用するスタイルシートを読み込んでパースする
+pub fn load_document(input: &str, stylesheets: &[String], options: &EngineOptions) -> Result<LoadedDocument, EngineError> {
+  let loader = Loader::new(options);
+  let stylesheets: Vec<Source> = stylesheets.iter().map(|location| Source::Url(Url::from_location(location))).collect();
+  return load_with(&Source::Url(Url::from_location(input)), &stylesheets, options, &loader, 0);
+}
+
+// HTML の文字列（標準入力から読んだものなど）と、適用するスタイルシートを読み込んでパースする。
+// 相対 URL はカレントディレクトリにあるものとして解決する
+pub fn load_document_source(html: &str, stylesheets: &[String], options: &EngineOptions) -> Result<LoadedDocument, EngineError> {
+  let loader = Loader::new(options);
+  let stylesheets: Vec<Source> = stylesheets.iter().map(|location| Source::Url(Url::from_location(location))).collect();
+  return load_with(&Source::Text(html.to_string()), &stylesheets, options, &loader, 0);
+}
+
+// depth は iframe の入れ子の深さ（トップレベルのドキュメントが 0）。
+// 文字列で渡されたドキュメントはカレントディレクトリにあるものとして相対 URL を解決する
+fn load_with(input: &Source, stylesheets: &[Source], options: &EngineOptions, loader: &Loader, depth: usize) -> Result<LoadedDocument, EngineError> {
+  let mut sources = Vec::new();
+  let mut timings = Timings::default();
+  let (text, document_url) = match *input {
+    Source::Text(ref text) => (text.clone(), Url::from_location(".")),
+    Source::Url(ref url) => {
+      let document = profile::time(&mut timings.fetch, || loader.load(url))?;
+      sources.push(document.url.clone());
+      (document.text(), document.url.clone())
+    }
+  };
+
+  let root_node = profile::time(&mut timings.parse, || parse_html(text, &document_url.to_string(), options))?;
+
+  // 相対 URL の基準。<base href> があればそれを優先する
+  let base_url = match root_node.base_href() {
+    Some(href) => document_url.join(href).unwrap_or_else(|| document_url.clone()),
+    None => document_url.clone(),
+  };
+
+  // スクリプトで書き換えた後のドキュメントを描画する
+  #[cfg(feature = "js")]
+  let root_node = if options.scripting {
+    profile::time(&mut timings.script, || script::execute(root_node, &base_url, loader, options.deterministic, &mut sources))
+  } else {
+    root_node
+  };
+
+  // <link rel="stylesheet"> と <style> の後に引数のスタイルシートを続ける
+  let mut linked_sheets = Vec::new();
+  collect_stylesheets(&root_node, &base_url, &options.media, &mut linked_sheets);
+  let (mut inputs, media_lists): (Vec<Source>, Vec<css::MediaQueryList>) = linked_sheets.into_iter().unzip();
+  let linked = inputs.len();
+  inputs.extend(stylesheets.iter().cloned());
+
+  // スタイルシートどうしと iframe の中身は互いに関係ないので、同時に読み込んでパースする
+  let (sheets, frames) = parallel::join(
+    options.parallelism,
+    || {
+      parallel::map(options.parallelism, &inputs, |input| {
+        let mut sheet_sources = Vec::new();
+        let mut sheet_timings = Timings::default();
+        let sheet = load_stylesheet(input, &base_url, options, loader, &mut sheet_sources, &mut sheet_timings);
+        (sheet, sheet_sources, sheet_timings)
+      })
+    },
+    || frame::load_frames(&root_node, &base_url, loader, options, depth),
+  );
+
+  // 順番に連結する（後のものほど優先）。文字列のスタイルシートの相対 URL はドキュメント基準
+  let mut stylesheet = profile::time(&mut timings.parse, || user_agent_stylesheet(options))?;
+  for (index, (sheet, sheet_sources, sheet_timings)) in sheets.into_iter().enumerate() {
+    sources.extend(sheet_sources);
+    timings.add(&sheet_timings);
+    match (sheet, &inputs[index]) {
+      // media 属性のメディア特性は、スタイルを計算するときにビューポートの大きさと比べる（@media の中と同じ）
+      (Ok(mut sheet), _) if index < linked => {
+        sheet.restrict_media(&media_lists[index]);
+        sheet.retain_media(&options.media);
+        stylesheet.append(sheet);
+      }
+      (Ok(sheet), _) => stylesheet.append(sheet),
+      // ドキュメントの <link> で読み込めないものは、ブラウザと同じように警告して飛ばす（引数で渡したものはエラーにする）。
+      // --watch でファイルが作られたら読み込み直せるように、sources には足しておく
+      (Err(EngineError::Io(error)), &Source::Url(ref url)) if index < linked => {
+        warn!("Skipped stylesheet {}: {}", url, error);
+        sources.push(url.clone());
+      }
+      (Err(error), _) => return Err(error),
+    }
+  }
+  for document in frames.iter().flatten() {
+    sources.extend(document.sources.iter().cloned());
+    timings.add(&document.timings);
+  }
+  // background-image はスタイルシートが揃ってから分かるので、画像はその後でまとめて読み込む
+  let images = images::load_images(&root_node, &stylesheet, &base_url, loader, options, &mut sources, &mut timings);
+  let viewport_meta = if depth == 0 && options.meta_viewport { viewport::find(&root_node) } else { None };
+  let zoom = if depth == 0 { viewport::zoom_factor(options.zoom) } else { 1.0 };
+  let device_pixel_ratio = if depth == 0 { viewport::zoom_factor(options.device_pixel_ratio) } else { 1.0 };
+  return Ok(LoadedDocument {
+    root_node: root_node,
+    stylesheet: stylesheet,
+    sources: sources,
+    base_url: base_url,
+    frames: frames,
+    images: images,
+    timings: timings,
+    parallelism: options.parallelism,
+    tile_size: options.tile_size,
+    viewport_meta: viewport_meta,
+    zoom: zoom,
+    device_pixel_ratio: device_pixel_ratio,
+    render: options.render_options(),
+    fonts: font::for_document(options.deterministic),
+  });
+}
+
+// ドキュメントとは別に、スタイルシートを 1 つ読み込む（--transition-to など）。適用はしない
+pub fn load_extra_stylesheet(document: &LoadedDocument, location: &str, options: &EngineOptions) -> Result<css::StyleSheet, EngineError> {
+  let loader = Loader::new(options);
+  let mut sources = Vec::new();
+  let mut timings = Timings::default();
+  return load_stylesheet(&Source::Url(Url::from_location(location)), &document.base_url, options, &loader, &mut sources, &mut timings);
+}
+
+// HTML をパースする。補ったところは場所の行と一緒に警告し、strict なら最初のものをエラーにする
+fn parse_html(text: String, location: &str, options: &EngineOptions) -> Result<dom::Node, EngineError> {
+  let (root_node, diagnostics) = html::parse_with_diagnostics(text.clone());
+  for error in &diagnostics {
+    warn!("{}", Diagnostic::from_html(&text, error).render(location, &text));
+  }
+  return match diagnostics.into_iter().next() {
+    Some(error) if options.strict => Err(error.into()),
+    _ => Ok(root_node),
+  };
+}
+
+// CSS をパースする。読み飛ばした宣言やルールは場所の行と一緒に警告し、strict なら最初のものをエラーにする
+fn parse_css(text: String, location: &str, options: &EngineOptions) -> Result<css::StyleSheet, EngineError> {
+  let (sheet, diagnostics) = css::parse_with_diagnostics(text.clone());
+  for error in &diagnostics {
+    warn!("{}", Diagnostic::from_css(&text, error).render(location, &text));
+  }
+  return match diagnostics.into_iter().next() {
+    Some(error) if options.strict => Err(error.into()),
+    _ => Ok(sheet),
+  };
+}
+
+// スタイルシートを 1 つ読み込んでパースする。かかった時間は timings に足す
+fn load_stylesheet(input: &Source, base_url: &Url, options: &EngineOptions, loader: &Loader, sources: &mut Vec<Url>, timings: &mut Timings) -> Result<css::StyleSheet, EngineError> {
+  let mut sheet = load_imported_stylesheet(input, base_url, options, loader, sources, timings, &[])?;
+  sheet.retain_media(&options.media);
+  return Ok(sheet);
+}
+
+// load_stylesheet の中身。@import のスタイルシートも読み込んで、そのルールを自分のルールの前に置く。
+// importers は @import でここまでたどってきたスタイルシートの URL（循環したら読み込まない）
+fn load_imported_stylesheet(input: &Source, base_url: &Url, options: &EngineOptions, loader: &Loader, sources: &mut Vec<Url>, timings: &mut Timings, importers: &[Url]) -> Result<css::StyleSheet, EngineError> {
+  let (text, sheet_url) = match *input {
+    Source::Text(ref text) => (text.clone(), base_url.clone()),
+    Source::Url(ref location) => {
+      let resource = profile::time(&mut timings.fetch, || loader.load(location))?;
+      sources.push(resource.url.clone());
+      (resource.text(), resource.url.clone())
+    }
+  };
+  let mut sheet = profile::time(&mut timings.parse, || parse_css(text, &sheet_url.to_string(), options))?;
+  sheet.resolve_urls(&sheet_url);
+  if sheet.imports.is_empty() {
+    return Ok(sheet);
+  }
+
+  let mut importers = importers.to_vec();
+  importers.push(sheet_url.clone());
+  let mut imported = css::StyleSheet::default();
+  for import in std::mem::replace(&mut sheet.imports, Vec::new()) {
+    let url = match sheet_url.join(&import.url) {
+      Some(ref url) if importers.contains(url) => {
+        warn!("{}: @import {} is circular", sheet_url, url);
+        continue;
+      }
+      Some(url) => url,
+      None => continue,
+    };
+    // 読み込めない @import は警告して飛ばす（ほかのルールは使う）。
+    // --watch でファイルが作られたら読み込み直せるように、sources には足しておく
+    match load_imported_stylesheet(&Source::Url(url.clone()), base_url, options, loader, sources, timings, &importers) {
+      Ok(mut child) => {
+        child.restrict_media(&import.media);
+        imported.append(child);
+      }
+      Err(error) => {
+        warn!("{}: @import {}: {}", sheet_url, url, error);
+        sources.push(url);
+      }
+    }
+  }
+  imported.append(sheet);
+  return Ok(imported);
+}
+
+// 描画結果。scrollable_overflow はドキュメント全体の範囲（スクロールできる範囲の計算に使う）。
+// page は canvas に描いたディスプレイリストと範囲（SVG や PDF に書き出すときに使う）
+pub struct Rendered {
+  pub canvas: paint::Canvas,
+  pub page: paint::Page,
+  pub scrollable_overflow: layout::Rect,
+  pub timings: Timings, // スタイル適用から描画まで（読み込みの時間は LoadedDocument の方）
+  pub counts: Counts,
+}
+
+// スタイル適用 → レイアウト → 描画。ビューポートの大きさが変わったらここからやり直す
+// viewport の x, y はスクロール位置で、レイアウトには影響しない
+pub fn render(root_node: &dom::Node, stylesheet: &css::StyleSheet, viewport: layout::Rect) -> Result<Rendered, EngineError> {
+  return render_with_frames(root_node, stylesheet, None, font::shared(), viewport, 1.0, None);
+}
+
+// 読み込んだドキュメントを描画する。render と違って <iframe> の中身も描き、<meta name="viewport"> に従う
+pub fn render_document(document: &LoadedDocument, viewport: layout::Rect) -> Result<Rendered, EngineError> {
+  let (viewport, scale) = document.layout_viewport(viewport);
+  return render_with_frames(&document.root_node, &document.stylesheet, Some(document), document.fonts, viewport, scale, None);
+}
+
+// render_document と同じだが、キャンバスの高さをビューポートではなくドキュメント全体の高さにする（フルページのスクリーンショット）。
+// レイアウトはビューポートの大きさのまま。キャンバスの高さは max_height px まで（それより下は切る）
+pub fn render_full_page(document: &LoadedDocument, viewport: layout::Rect, max_height: f32) -> Result<Rendered, EngineError> {
+  let (viewport, scale) = document.layout_viewport(viewport);
+  return render_with_frames(&document.root_node, &document.stylesheet, Some(document), document.fonts, viewport, scale, Some(max_height));
+}
+
+// viewport はレイアウトに使うもの。キャンバスはその scale 倍の大きさになる。
+// document があればその iframe の中身と画像も描く。文字は fonts で測る。max_height があればドキュメント全体の高さで描き（page_bounds）、
+// なければ viewport の x, y までスクロールしたところを描く（scrolled_bounds）
+fn render_with_frames(root_node: &dom::Node, stylesheet: &css::StyleSheet, document: Option<&LoadedDocument>, fonts: &font::FontContext, viewport: layout::Rect, scale: f32, max_height: Option<f32>) -> Result<Rendered, EngineError> {
+  let no_images = Images::default();
+  let default_render = paint::RenderOptions::default();
+  let (frames, images, parallelism, tiling, render) = match document {
+    Some(document) => (&document.frames[..], &document.images, document.parallelism, document.tiling(), &document.render),
+    None => (&[][..], &no_images, 1, paint::Tiling::NONE, &default_render),
+  };
+  let mut timings = Timings::default();
+  let style_root = profile::time(&mut timings.style, || style::style_tree_with_viewport(root_node, stylesheet, viewport));
+  let layout_root = profile::time(&mut timings.layout, || layout::layout_tree_with(&style_root, initial_containing_block(viewport), fonts, images))?;
+  let display_list = profile::time(&mut timings.display_list, || frame::build_display_list(&layout_root, root_node, frames, parallelism, render));
+  let bounds = match max_height {
+    Some(max_height) => page_bounds(viewport, layout_root.scrollable_overflow(), scale, max_height),
+    None => scrolled_bounds(viewport, layout_root.scrollable_overflow()),
+  };
+  let page = paint::Page { display_list: display_list, bounds: bounds, scale: scale };
+  let canvas = profile::time(&mut timings.raster, || page.paint_with(tiling, render))?;
+
+  let counts = count(root_node, stylesheet, &layout_root, &page.display_list, &canvas);
+  return Ok(Rendered { scrollable_overflow: layout_root.scrollable_overflow(), canvas: canvas, page: page, timings: timings, counts: counts });
+}
+
+// selector に一致する最初の要素（ボックスを持つもの）の、ボーダーボックスの範囲だけを描画する。
+// レイアウトは viewport の大きさで行う
+pub fn render_element(document: &LoadedDocument, selector: &str, viewport: layout::Rect) -> Result<Rendered, EngineError> {
+  let selectors = css::parse_selectors(selector)?;
+  let (viewport, scale) = document.layout_viewport(viewport);
+  let mut timings = Timings::default();
+  let style_root = profile::time(&mut timings.style, || style::style_tree_with_viewport(&document.root_node, &document.stylesheet, viewport));
+  let layout_root = profile::time(&mut timings.layout, || layout::layout_tree_with(&style_root, initial_containing_block(viewport), document.fonts, &document.images))?;
+  let matched = style::query_selector_all(&document.root_node, &selectors);
+  let rect = engine::LayoutNode::new(&layout_root, &document.root_node)
+    .find_node(&matched)
+    .map(|node| node.dimensions.border_box())
+    .ok_or_else(|| EngineError::NoMatchingElement(selector.to_string()))?;
+  let display_list = profile::time(&mut timings.display_list, || frame::build_display_list(&layout_root, &document.root_node, &document.frames, document.parallelism, &document.render));
+  let page = paint::Page { display_list: display_list, bounds: rect, scale: scale };
+  let canvas = profile::time(&mut timings.raster, || page.paint_with(document.tiling(), &document.render))?;
+
+  let counts = count(&document.root_node, &document.stylesheet, &layout_root, &page.display_list, &canvas);
+  return Ok(Rendered { scrollable_overflow: layout_root.scrollable_overflow(), canvas: canvas, page: page, timings: timings, counts: counts });
+}
+
+fn count(root_node: &dom::Node, stylesheet: &css::StyleSheet, layout_root: &layout::LayoutBox, display_list: &paint::DisplayList, canvas: &paint::Canvas) -> Counts {
+  return Counts {
+    dom_nodes: profile::count_nodes(root_node),
+    rules: stylesheet.rules.len(),
+    layout_boxes: profile::count_boxes(layout_root),
+    display_items: display_list.len(),
+    pixels: canvas.pixels.len(),
+  };
+}
+
+// ファイルやネットワークを使わずに、HTML と CSS の文字列から描画する。
+// レイアウトは viewport（CSS px）で行い、キャンバスは options.device_pixel_ratio 倍の大きさで描く
+pub fn render_source(html: &str, css: &str, viewport: layout::Rect, options: &EngineOptions) -> Result<Rendered, EngineError> {
+  let root_node = parse_html(html.to_string(), "HTML", options)?;
+  let mut stylesheet = user_agent_stylesheet(options)?;
+  let mut sheet = parse_css(css.to_string(), "CSS", options)?;
+  sheet.retain_media(&options.media);
+  stylesheet.append(sheet);
+  return render_with_frames(&root_node, &stylesheet, None, font::for_document(options.deterministic), viewport, viewport::zoom_factor(options.device_pixel_ratio), None);
+}
+
+// 作者のスタイルシートを足していく土台。ルールの出どころは組み込みなので、!important でなければ作者のルールが勝つ
+pub fn user_agent_stylesheet(options: &EngineOptions) -> Result<css::StyleSheet, EngineError> {
+  if !options.user_agent_stylesheet {
+    return Ok(css::StyleSheet::default());
+  }
+  let mut stylesheet = css::parse(USER_AGENT_STYLESHEET.to_string())?;
+  // 設定の既定のフォントは、ルート要素に指定して引き継がせる
+  stylesheet.append(css::parse(format!("html {{ font-family: {}; font-size: {}px; }}", options.default_font_family, options.default_font_size))?);
+  stylesheet.retain_media(&options.media);
+  stylesheet.set_origin(css::Origin::UserAgent);
+  return Ok(stylesheet);
+}
+
+// スクロールした後に描く範囲。viewport の x, y（スクロール位置）を、ドキュメントの右端と下端より先を見せないところまでに収める
+pub fn scrolled_bounds(viewport: layout::Rect, overflow: layout::Rect) -> layout::Rect {
+  let max_x = (overflow.x + overflow.width - viewport.width).max(0.0);
+  let max_y = (overflow.y + overflow.height - viewport.height).max(0.0);
+  return layout::Rect { x: viewport.x.max(0.0).min(max_x), y: viewport.y.max(0.0).min(max_y), ..viewport };
+}
+
+// フルページで描く範囲。横はビューポートのまま、縦はページの先頭からドキュメントの下端まで
+// （ビューポートより低ければビューポートの高さ）。キャンバスが max_height px を超えないように切る
+pub fn page_bounds(viewport: layout::Rect, overflow: layout::Rect, scale: f32, max_height: f32) -> layout::Rect {
+  let height = (overflow.y + overflow.height).max(viewport.height).min(max_height / scale).max(1.0);
+  return layout::Rect { x: viewport.x, y: 0.0, width: viewport.width, height: height };
+}
+
+pub fn initial_containing_block(viewport: layout::Rect) -> layout::Dimensions {
+  let mut containing_block: layout::Dimensions = Default::default();
+  containing_block.content.width = viewport.width;
+  containing_block.content.height = viewport.height;
+  return containing_block;
+}
+
+// <link rel="stylesheet" href="..."> の URL と <style> の中身をドキュメント順に集める。media は描くメディア（screen か print）
+fn collect_stylesheets(node: &dom::Node, base_url: &Url, media: &str, inputs: &mut Vec<(Source, css::MediaQueryList)>) {
+  if let dom::NodeType::Element(ref elem) = node.node_type {
+    // rel="alternate stylesheet" は選ばれたときだけ使うものなので読み込まない
+    let is_stylesheet = elem.attributes.get("rel").map_or(false, |rel| {
+      let has = |name: &str| rel.split_whitespace().any(|token| token.eq_ignore_ascii_case(name));
+      has("stylesheet") && !has("alternate")
+    });
+    // media 属性の種類が描くメディアに合わないものは読み込まない。メディア特性はビューポートが決まってから見る
+    let media_list = elem.attributes.get("media").map_or_else(Vec::new, |list| css::parse_media_query_list(list));
+    let matches_media = media_list.is_empty() || media_list.iter().any(|query| query.matches(media));
+    if elem.tag_name == "link" && is_stylesheet && matches_media {
+      if let Some(url) = elem.attributes.get("href").and_then(|href| base_url.join(href)) {
+        inputs.push((Source::Url(url), media_list.clone()));
+      }
+    }
+    if elem.tag_name == "style" {
+      if !matches_media {
+        return;
+      }
+      let text: String = node.children.iter().filter_map(|child| match child.node_type {
+        dom::NodeType::Text(ref text) => Some(&**text),
+        _ => None,
+      }).collect();
+      inputs.push((Source::Text(text), media_list));
+      return;
+    }
+  }
+  for child in &node.children {
+    collect_stylesheets(child, base_url, media, inputs);
+  }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+use data_url;
+#[cfg(feature = "http")]
+use net::{HttpClient, HttpOptions};
+use options::EngineOptions;
+use url::Url;
+
+// 読み書きのエラー。どの場所で失敗したかを持つ
+#[derive(Debug, Error)]
+pub enum IoError {
+  #[error("failed to load {url}: {source}")]
+  Load { url: String, source: io::Error },
+  #[error("failed to write {path}: {source}")]
+  Write { path: String, source: io::Error },
+  #[error("failed to listen on {address}: {source}")]
+  Listen { address: String, source: io::Error },
+}
+
+/**
+ * ドキュメントやサブリソース（スタイルシート、スクリプト、画像、フォント）を読み込むところ。
+ * どれもこの Loader を通して読み込み、同じ URL は一度だけ読み込む（キャッシュは Loader ごと）。
+ * URL のスキームごとに Backend があって、file:// と http(s)://、data: に対応する。
+ * HTTP のクライアント（とクッキー）も Loader ごとなので、1 回のページの読み込みの間だけ共有される。
+ * サブリソースを並列に読み込むので、Loader と Backend はスレッド間で共有できる
+ */
+
+// ドキュメントやスタイルシートの入力。文字列そのものか、読み込む場所
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+  Text(String),
+  Url(Url),
+}
+
+pub struct Resource {
+  pub url: Url,                  // 実際に読み込んだ場所（リダイレクト後の URL など）
+  pub mime_type: Option<String>, // data: URL や Content-Type、分からなければ中身から推測したもの
+  pub data: Vec<u8>,
+}
+
+impl Resource {
+  pub fn text(&self) -> String {
+    return String::from_utf8_lossy(&self.data).into_owned();
+  }
+}
+
+// URL から読み込む方法。Loader::add_backend で足せる
+pub trait Backend: Send + Sync {
+  fn handles(&self, url: &Url) -> bool;
+  // max_size バイトより大きいリソースはエラーにする
+  fn load(&self, url: &Url, max_size: u64) -> io::Result<Resource>;
+}
+
+pub struct Loader {
+  backends: Vec<Box<dyn Backend>>,
+  cache: Mutex<HashMap<String, Arc<Resource>>>, // 要求された URL から
+  max_size: u64,
+}
+
+impl Loader {
+  // file://（fs フィーチャーで、file_access のとき）、http(s)://（http フィーチャー）、data: を読み込める Loader
+  pub fn new(options: &EngineOptions) -> Loader {
+    let mut loader = Loader { backends: Vec::new(), cache: Mutex::new(HashMap::new()), max_size: options.max_resource_size };
+    loader.backends.push(Box::new(DataBackend));
+    #[cfg(feature = "http")]
+    loader.backends.push(Box::new(HttpBackend::new(HttpOptions::from_engine_options(options))));
+    #[cfg(feature = "fs")]
+    {
+      if options.file_access {
+        loader.backends.push(Box::new(FileBackend));
+      }
+    }
+    return loader;
+  }
+
+  // 後から足したものが先に試される
+  pub fn add_backend(&mut self, backend: Box<dyn Backend>) {
+    self.backends.insert(0, backend);
+  }
+
+  // 同じ URL を別のスレッドが読み込み中なら、両方が読み込む（先に終わった方がキャッシュに入る）
+  pub fn load(&self, url: &Url) -> Result<Arc<Resource>, IoError> {
+    let key = url.to_string();
+    if let Some(resource) = self.cache.lock().unwrap().get(&key) {
+      debug!("Loaded {} from cache", key);
+      return Ok(resource.clone());
+    }
+
+    let mut resource = self.load_uncached(url).map_err(|error| IoError::Load { url: key.clone(), source: error })?;
+    if resource.mime_type.is_none() {
+      resource.mime_type = sniff_mime_type(&resource.data, &resource.url).map(|mime_type| mime_type.to_string());
+    }
+    let resource = Arc::new(resource);
+    return Ok(self.cache.lock().unwrap().entry(key).or_insert(resource).clone());
+  }
+
+  fn load_uncached(&self, url: &Url) -> io::Result<Resource> {
+    return match self.backends.iter().find(|backend| backend.handles(url)) {
+      Some(backend) => backend.load(url, self.max_size),
+      None => Err(io::Error::new(io::ErrorKind::Other, format!("unsupported URL scheme: {}", url))),
+    };
+  }
+}
+
+pub struct DataBackend;
+
+impl Backend for DataBackend {
+  fn handles(&self, url: &Url) -> bool {
+    return url.is_data();
+  }
+
+  fn load(&self, url: &Url, max_size: u64) -> io::Result<Resource> {
+    let data = data_url::parse(&url.to_string()).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed data: URL"))?;
+    check_size(data.data.len() as u64, max_size)?;
+    return Ok(Resource { url: url.clone(), mime_type: Some(data.mime_type), data: data.data });
+  }
+}
+
+// クライアントは最初に HTTP のリソースを読み込むときに作る
+#[cfg(feature = "http")]
+pub struct HttpBackend {
+  options: HttpOptions,
+  client: Mutex<Option<Arc<HttpClient>>>,
+}
+
+#[cfg(feature = "http")]
+impl HttpBackend {
+  pub fn new(options: HttpOptions) -> HttpBackend {
+    return HttpBackend { options: options, client: Mutex::new(None) };
+  }
+
+  fn client(&self) -> io::Result<Arc<HttpClient>> {
+    let mut client = self.client.lock().unwrap();
+    if let Some(ref client) = *client {
+      return Ok(client.clone());
+    }
+    let created = Arc::new(HttpClient::new(&self.options)?);
+    *client = Some(created.clone());
+    return Ok(created);
+  }
+}
+
+#[cfg(feature = "http")]
+impl Backend for HttpBackend {
+  fn handles(&self, url: &Url) -> bool {
+    return url.is_http();
+  }
+
+  fn load(&self, url: &Url, max_size: u64) -> io::Result<Resource> {
+    let response = self.client()?.fetch(&url.to_string(), max_size)?;
+    let final_url = Url::parse(&response.url).unwrap_or_else(|| url.clone());
+    return Ok(Resource { url: final_url, mime_type: response.content_type, data: response.body });
+  }
+}
+
+#[cfg(feature = "fs")]
+pub struct FileBackend;
+
+#[cfg(feature = "fs")]
+impl Backend for FileBackend {
+  fn handles(&self, url: &Url) -> bool {
+    return url.is_file();
+  }
+
+  fn load(&self, url: &Url, max_size: u64) -> io::Result<Resource> {
+    let path = url.to_file_path().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("not a local path: {}", url)))?;
+    let data = read_limited(File::open(path)?, max_size)?;
+    return Ok(Resource { url: url.clone(), mime_type: None, data: data });
+  }
+}
+
+// 読みながら大きさを確かめる（巨大なファイルやレスポンスを全部読み込まないように）
+pub fn read_limited<R: Read>(reader: R, max_size: u64) -> io::Result<Vec<u8>> {
+  let mut data = Vec::new();
+  reader.take(max_size.saturating_add(1)).read_to_end(&mut data)?;
+  check_size(data.len() as u64, max_size)?;
+  return Ok(data);
+}
+
+fn check_size(size: u64, max_size: u64) -> io::Result<()> {
+  if size > max_size {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("resource is larger than {} bytes", max_size)));
+  }
+  return Ok(());
+}
+
+// 中身の先頭のバイト列と URL の拡張子から MIME タイプを推測する
+pub fn sniff_mime_type(data: &[u8], url: &Url) -> Option<&'static str> {
+  let signatures: [(&[u8], &'static str); 8] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"\x00\x01\x00\x00", "font/ttf"),
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+    (b"OTTO", "font/otf"),
+  ];
+  if let Some(&(_, mime_type)) = signatures.iter().find(|&&(signature, _)| data.starts_with(signature)) {
+    return Some(mime_type);
+  }
+  if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+    return Some("image/webp");
+  }
+
+  let extension = url.path.rsplit('/').next().and_then(|name| name.rsplit_once('.')).map(|(_, extension)| extension.to_ascii_lowercase());
+  let by_extension = match extension.as_ref().map(|extension| &**extension) {
+    Some("html") | Some("htm") => Some("text/html"),
+    Some("css") => Some("text/css"),
+    Some("js") | Some("mjs") => Some("text/javascript"),
+    Some("svg") => Some("image/svg+xml"),
+    Some("txt") => Some("text/plain"),
+    _ => None,
+  };
+  if by_extension.is_some() {
+    return by_extension;
+  }
+
+  // 拡張子がなければ、マークアップっぽいかどうかだけ見る
+  let head = String::from_utf8_lossy(&data[..data.len().min(512)]).trim_start().to_ascii_lowercase();
+  if head.starts_with("<!doctype html") || head.starts_with("<html") {
+    return Some("text/html");
+  }
+  if head.starts_with("<svg") {
+    return Some("image/svg+xml");
+  }
+  return None;
+}
@@ -0,0 +1,176 @@
+use css::{Calc, Color, ColorStop, Value};
+use dom::{Node, NodeType};
+use engine::LayoutNode;
+use error::EngineError;
+use layout::{self, LayoutBox, Rect, TextFragment};
+use paint::{self, Canvas, DisplayCommand, DisplayList};
+use std::mem::size_of;
+use style::{self, PropertyMap, StyledNode};
+use {frame, initial_containing_block, LoadedDocument};
+
+/**
+ * DOM、スタイル、レイアウトツリー、キャンバスが使っているメモリのおおよその量。
+ * 構造体の大きさと、Vec や String などが確保している容量を足したもので、
+ * アロケーターのオーバーヘッドや HashMap / BTreeMap の内部の管理領域は正確には数えない
+ */
+
+// 数と、それが使っているバイト数
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+  pub count: usize,
+  pub bytes: usize,
+}
+
+impl Usage {
+  fn add(&mut self, other: Usage) {
+    self.count += other.count;
+    self.bytes += other.bytes;
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MemoryUsage {
+  pub dom: Usage,          // ノード（iframe の中身を含む）
+  pub styles: Usage,       // ノードごとの値（count はノードの数）
+  pub layout: Usage,       // ボックス
+  pub display_list: Usage, // 描画コマンド
+  pub canvas: Usage,       // ピクセル
+  pub peak_bytes: usize,   // 同時に存在した量の最大（途中で作って捨てるスタイルツリーなどを含む）
+}
+
+impl MemoryUsage {
+  pub fn total_bytes(&self) -> usize {
+    return self.dom.bytes + self.styles.bytes + self.layout.bytes + self.display_list.bytes + self.canvas.bytes;
+  }
+}
+
+// 読み込んだドキュメントを描画し直して、各段階の結果がすべて残っている状態で測る
+pub fn measure_document(document: &LoadedDocument, viewport: Rect) -> Result<MemoryUsage, EngineError> {
+  let (viewport, scale) = document.layout_viewport(viewport);
+  let style_root = style::style_tree_with_viewport(&document.root_node, &document.stylesheet, viewport);
+  let layout_root = layout::layout_tree_with(&style_root, initial_containing_block(viewport), document.fonts, &document.images)?;
+  let display_list = frame::build_display_list(&layout_root, &document.root_node, &document.frames, document.parallelism, &document.render);
+  let canvas = paint::paint_display_list_with(&display_list, viewport, scale, document.tiling(), &document.render)?;
+
+  let mut usage = MemoryUsage {
+    dom: document_dom(document),
+    styles: styled(&style_root),
+    layout: layout_boxes(&layout_root),
+    display_list: display_commands(&display_list),
+    canvas: pixels(&canvas),
+    peak_bytes: 0,
+  };
+  usage.peak_bytes = usage.total_bytes();
+  return Ok(usage);
+}
+
+pub fn document_dom(document: &LoadedDocument) -> Usage {
+  let mut usage = dom(&document.root_node);
+  for frame in document.frames.iter().flatten() {
+    usage.add(document_dom(frame));
+  }
+  return usage;
+}
+
+// 子の Node は親の children が持っているので、ルート以外は children の容量で数える
+pub fn dom(root: &Node) -> Usage {
+  let mut usage = Usage { count: 0, bytes: size_of::<Node>() };
+  dom_heap(root, &mut usage);
+  return usage;
+}
+
+fn dom_heap(node: &Node, usage: &mut Usage) {
+  usage.count += 1;
+  usage.bytes += node.children.capacity() * size_of::<Node>();
+  usage.bytes += match node.node_type {
+    NodeType::Text(ref text) | NodeType::Comment(ref text) => text.capacity(),
+    NodeType::Element(ref elem) => {
+      elem.tag_name.capacity()
+        + elem.attributes.iter().map(|(name, value)| size_of::<(String, String)>() + name.capacity() + value.capacity()).sum::<usize>()
+    }
+  };
+  for child in &node.children {
+    dom_heap(child, usage);
+  }
+}
+
+// Engine が持っている、ノードごとの値
+pub fn property_maps(values: &[PropertyMap]) -> Usage {
+  let bytes = values.iter().map(property_map_bytes).sum::<usize>();
+  return Usage { count: values.len(), bytes: values.len() * size_of::<PropertyMap>() + bytes };
+}
+
+pub fn styled(root: &StyledNode) -> Usage {
+  let mut usage = Usage { count: 1, bytes: size_of::<StyledNode>() + property_map_bytes(&root.specified_values) };
+  usage.bytes += root.children.capacity() * size_of::<StyledNode>();
+  for child in &root.children {
+    let child_usage = styled(child);
+    usage.count += child_usage.count;
+    usage.bytes += child_usage.bytes - size_of::<StyledNode>();
+  }
+  return usage;
+}
+
+fn property_map_bytes(values: &PropertyMap) -> usize {
+  let table = values.capacity() * (size_of::<String>() + size_of::<Value>() + 1);
+  let heap = values.iter().map(|(name, value)| name.capacity() + value_heap(value)).sum::<usize>();
+  return table + heap;
+}
+
+fn value_heap(value: &Value) -> usize {
+  return match *value {
+    Value::Keyword(ref keyword) | Value::Str(ref keyword) => keyword.capacity(),
+    Value::Url(ref url) => url.capacity(),
+    Value::Function(ref name, ref values) => name.capacity() + values_heap(values),
+    Value::List(ref values) => values_heap(values),
+    Value::Gradient(ref gradient) => {
+      gradient.stops.capacity() * size_of::<ColorStop>() + gradient.stops.iter().filter_map(|stop| stop.position.as_ref()).map(value_heap).sum::<usize>()
+    }
+    Value::Var(ref name, ref fallback) => name.capacity() + fallback.as_ref().map_or(0, |fallback| size_of::<Value>() + value_heap(fallback)),
+    Value::Calc(ref calc) => size_of::<Calc>() + calc_heap(calc),
+    Value::Length(..) | Value::ColorValue(_) | Value::Number(_) | Value::Transform(_) => 0,
+  };
+}
+
+fn calc_heap(calc: &Calc) -> usize {
+  return match *calc {
+    Calc::Value(ref value) => value_heap(value),
+    Calc::Operation(_, ref left, ref right) => 2 * size_of::<Calc>() + calc_heap(left) + calc_heap(right),
+  };
+}
+
+fn values_heap(values: &Vec<Value>) -> usize {
+  return values.capacity() * size_of::<Value>() + values.iter().map(value_heap).sum::<usize>();
+}
+
+pub fn layout_boxes(root: &LayoutBox) -> Usage {
+  let mut usage = Usage { count: 1, bytes: size_of::<LayoutBox>() + root.children.capacity() * size_of::<LayoutBox>() };
+  usage.bytes += root.lines.capacity() * size_of::<Rect>() + root.fragments.capacity() * size_of::<TextFragment>();
+  usage.bytes += root.fragments.iter().map(|fragment| fragment.text.capacity()).sum::<usize>();
+  for child in &root.children {
+    let child_usage = layout_boxes(child);
+    usage.count += child_usage.count;
+    usage.bytes += child_usage.bytes - size_of::<LayoutBox>();
+  }
+  return usage;
+}
+
+// Engine が描画後も持っている、レイアウトツリーのコピー
+pub fn layout_nodes(root: &LayoutNode) -> Usage {
+  let mut usage = Usage { count: 1, bytes: size_of::<LayoutNode>() + root.children.capacity() * size_of::<LayoutNode>() };
+  usage.bytes += root.tag_name.as_ref().map_or(0, |tag_name| tag_name.capacity());
+  for child in &root.children {
+    let child_usage = layout_nodes(child);
+    usage.count += child_usage.count;
+    usage.bytes += child_usage.bytes - size_of::<LayoutNode>();
+  }
+  return usage;
+}
+
+pub fn display_commands(list: &DisplayList) -> Usage {
+  return Usage { count: list.len(), bytes: size_of::<DisplayList>() + list.capacity() * size_of::<DisplayCommand>() };
+}
+
+pub fn pixels(canvas: &Canvas) -> Usage {
+  return Usage { count: canvas.pixels.len(), bytes: size_of::<Canvas>() + canvas.pixels.capacity() * size_of::<Color>() };
+}
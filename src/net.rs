@@ -0,0 +1,94 @@
+use loader::read_limited;
+use options::EngineOptions;
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::redirect::Policy;
+use std::collections::BTreeMap;
+use std::io;
+use std::time::Duration;
+
+/**
+ * HTTP(S) でリソースを取得するところ。
+ * gzip/deflate の展開とリダイレクトは reqwest に任せる。クッキーは HttpClient ごとに持つ
+ */
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10); // 接続まで
+const TIMEOUT: Duration = Duration::from_secs(30); // レスポンスを読み終わるまで
+
+// 取得結果。リダイレクトされた場合 url は最終的な URL になる
+pub struct Response {
+  pub url: String,
+  pub content_type: Option<String>,
+  pub body: Vec<u8>,
+}
+
+// クライアントの設定（EngineOptions から必要なものだけ）
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+  pub user_agent: String,
+  pub headers: BTreeMap<String, String>, // すべてのリクエストに付けるヘッダ
+  pub max_redirects: usize,
+  pub cookies: bool, // Set-Cookie を覚えて、以降のリクエストで送る
+}
+
+impl HttpOptions {
+  pub fn from_engine_options(options: &EngineOptions) -> HttpOptions {
+    return HttpOptions {
+      user_agent: options.user_agent.clone(),
+      headers: options.http_headers.clone(),
+      max_redirects: options.max_redirects,
+      cookies: options.cookies,
+    };
+  }
+}
+
+pub struct HttpClient {
+  client: Client,
+}
+
+impl HttpClient {
+  pub fn new(options: &HttpOptions) -> io::Result<HttpClient> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &options.headers {
+      let name = HeaderName::from_bytes(name.as_bytes()).map_err(|error| invalid_header(name, error))?;
+      let value = HeaderValue::from_str(value).map_err(|error| invalid_header(name.as_str(), error))?;
+      headers.insert(name, value);
+    }
+    let client = Client::builder()
+      .user_agent(options.user_agent.as_str())
+      .default_headers(headers)
+      .cookie_store(options.cookies)
+      .connect_timeout(CONNECT_TIMEOUT)
+      .timeout(TIMEOUT)
+      .redirect(Policy::limited(options.max_redirects))
+      .build()
+      .map_err(to_io_error)?;
+    return Ok(HttpClient { client: client });
+  }
+
+  // GET して、成功ステータスならボディを返す（展開後 max_size バイトまで）
+  pub fn fetch(&self, url: &str, max_size: u64) -> io::Result<Response> {
+    let response = self.client.get(url).send().map_err(to_io_error)?;
+    let status = response.status();
+    if !status.is_success() {
+      return Err(io::Error::new(io::ErrorKind::Other, format!("{} responded with {}", url, status)));
+    }
+    let final_url = response.url().to_string();
+    let content_type = response
+      .headers()
+      .get(reqwest::header::CONTENT_TYPE)
+      .and_then(|value| value.to_str().ok())
+      .map(|value| value.to_string());
+    let body = read_limited(response, max_size)?;
+    return Ok(Response { url: final_url, content_type: content_type, body: body });
+  }
+}
+
+fn invalid_header<E: ToString>(name: &str, error: E) -> io::Error {
+  return io::Error::new(io::ErrorKind::InvalidInput, format!("invalid header {}: {}", name, error.to_string()));
+}
+
+fn to_io_error(error: reqwest::Error) -> io::Error {
+  let kind = if error.is_timeout() { io::ErrorKind::TimedOut } else { io::ErrorKind::Other };
+  return io::Error::new(kind, error);
+}
@@ -0,0 +1,154 @@
+use css::{self, Color, Value};
+use paint::RenderOptions;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+#[cfg(feature = "fs")]
+use std::fs;
+use std::io;
+use thiserror::Error;
+
+/**
+ * エンジン全体の設定。ライブラリからは構造体を直接作り、CLI からは --config の TOML で指定する。
+ *
+ *   viewport_width = 1024
+ *   viewport_height = 768
+ *   default_font_family = "serif"
+ *   default_font_size = 16.0
+ *   user_agent_stylesheet = true
+ *   load_images = true
+ *   max_resource_size = 16777216
+ *   strict = false
+ *   scripting = true
+ *   user_agent = "Mozilla/5.0 (compatible; suburi)"
+ *   max_redirects = 5
+ *   cookies = false
+ *   deterministic = true
+ *   parallelism = 4
+ *   tile_size = 256
+ *   file_access = true
+ *   meta_viewport = true
+ *   max_page_height = 16384.0
+ *   media = "print"
+ *   page_width = 816.0
+ *   page_height = 1056.0
+ *   page_margin = 48.0
+ *   zoom = 1.5
+ *   device_pixel_ratio = 2.0
+ *   background = "transparent"
+ *   pixel_snap = true
+ *   debug_boxes = false
+ *
+ *   [http_headers]
+ *   Accept-Language = "ja"
+ *
+ * 書かなかった項目はデフォルトのまま。background は CSS の色で、読めなければ from_toml がエラーにする
+ */
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+  #[error("failed to read config {path}: {source}")]
+  Read { path: String, source: io::Error },
+  #[error("invalid config: {0}")]
+  Parse(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineOptions {
+  pub viewport_width: f32,         // CLI で -W を省略したときのビューポートの幅
+  pub viewport_height: f32,        // 同じく -H を省略したときの高さ
+  pub default_font_family: String, // font-family が指定されていないときのフォント
+  pub default_font_size: f32,      // font-size が指定されていないときの大きさ（px）
+  pub user_agent_stylesheet: bool, // 組み込みのスタイルシートを作者のものより前に適用する
+  pub load_images: bool,           // 画像を読み込むか
+  pub max_resource_size: u64,      // 1 つのリソースとして読み込む最大のバイト数
+  pub strict: bool,                // HTML や CSS の壊れたところでエラーにする（false なら補うか読み飛ばして警告する）
+  pub scripting: bool,             // <script> を実行する（js フィーチャーが必要）
+  pub user_agent: String,          // HTTP の User-Agent ヘッダ
+  pub http_headers: BTreeMap<String, String>, // すべての HTTP リクエストに付けるヘッダ
+  pub max_redirects: usize,        // HTTP のリダイレクトをたどる回数の上限
+  pub cookies: bool,               // 1 回の読み込みの間、HTTP のクッキーを覚えて送り返す
+  pub deterministic: bool,         // 同じ入力からは毎回同じ結果にする（スクリプトの乱数と時刻、文字のフォントを固定する）
+  pub parallelism: usize,          // 読み込みや iframe のレイアウトに使うスレッドの数（0 なら CPU の数、1 なら並列にしない）
+  pub tile_size: usize,            // キャンバスを分けるタイルの一辺（px）。タイルは parallelism のスレッドで同時に描く（0 ならタイルに分けない）
+  pub file_access: bool,           // file:// を読み込めるようにする（fs フィーチャーが必要）
+  pub meta_viewport: bool,         // <meta name="viewport"> の幅と倍率でレイアウトして描く（false ならデスクトップのブラウザのように無視する）
+  pub max_page_height: f32,        // フルページで描くときのキャンバスの高さの上限（px。これより下は切る）
+  pub media: String,               // 描くメディア（screen か print）。@media はこれで評価する
+  pub page_width: f32,             // print で描くときの 1 ページの幅（px）
+  pub page_height: f32,            // 同じく高さ
+  pub page_margin: f32,            // 同じくページの四辺の余白（px）
+  pub zoom: f32,                   // ページのズーム。ビューポートを 1 / zoom の CSS px でレイアウトして、zoom 倍で描く
+  pub device_pixel_ratio: f32,     // 1 CSS px あたりの端末のピクセル数。レイアウトはビューポートの CSS px のまま、キャンバスをこの倍の大きさで描く
+  pub background: String,          // キャンバスを塗っておく色（CSS の色。transparent なら透明な画像になる）
+  pub pixel_snap: bool,            // ボックスの辺をピクセルの境目に合わせて描く（小数の位置で隣り合うボックスの間に隙間や重なりができないように）
+  pub debug_boxes: bool,           // すべてのボックスの margin、padding、content の範囲を枠で描く（レイアウトのデバッグ用）
+}
+
+impl Default for EngineOptions {
+  fn default() -> EngineOptions {
+    return EngineOptions {
+      viewport_width: 800.0,
+      viewport_height: 600.0,
+      default_font_family: "sans-serif".to_string(),
+      default_font_size: 16.0,
+      user_agent_stylesheet: true,
+      load_images: true,
+      max_resource_size: 16 * 1024 * 1024,
+      strict: false,
+      scripting: true,
+      user_agent: format!("browser-engine-suburi/{}", env!("CARGO_PKG_VERSION")),
+      http_headers: BTreeMap::new(),
+      max_redirects: 10,
+      cookies: true,
+      deterministic: false,
+      parallelism: 0,
+      tile_size: 256,
+      file_access: true,
+      meta_viewport: true,
+      max_page_height: 16384.0,
+      media: "screen".to_string(),
+      page_width: 794.0, // A4（96 dpi）
+      page_height: 1123.0,
+      page_margin: 38.0,
+      zoom: 1.0,
+      device_pixel_ratio: 1.0,
+      background: "white".to_string(),
+      pixel_snap: false,
+      debug_boxes: false,
+    };
+  }
+}
+
+impl EngineOptions {
+  pub fn from_toml(source: &str) -> Result<EngineOptions, ConfigError> {
+    let options: EngineOptions = toml::from_str(source).map_err(|error| ConfigError::Parse(error.to_string()))?;
+    if parse_color(&options.background).is_none() {
+      return Err(ConfigError::Parse(format!("invalid background color: {}", options.background)));
+    }
+    return Ok(options);
+  }
+
+  #[cfg(feature = "fs")]
+  pub fn from_file(path: &str) -> Result<EngineOptions, ConfigError> {
+    let source = fs::read_to_string(path).map_err(|error| ConfigError::Read { path: path.to_string(), source: error })?;
+    return EngineOptions::from_toml(&source);
+  }
+
+  // 描き方の設定。background が色として読めなければ白にする
+  pub fn render_options(&self) -> RenderOptions {
+    let background = parse_color(&self.background).unwrap_or_else(|| {
+      warn!("Invalid background color: {}", self.background);
+      return RenderOptions::default().background;
+    });
+    return RenderOptions { background: background, pixel_snap: self.pixel_snap, debug_boxes: self.debug_boxes };
+  }
+}
+
+// CSS の色（color プロパティの値として読む）。読めなければ None
+pub fn parse_color(source: &str) -> Option<Color> {
+  return css::parse_inline_style(&format!("color: {}", source)).into_iter().find_map(|declaration| match declaration.value {
+    Value::ColorValue(color) => Some(color),
+    _ => None,
+  });
+}
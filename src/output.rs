@@ -0,0 +1,330 @@
+use css::Color;
+use error::EngineError;
+use images::Image;
+use layout::Rect;
+use loader::IoError;
+use options::EngineOptions;
+use paint::{Canvas, DisplayCommand, Page, PaintError};
+use print::Printed;
+use reader::ReaderFormat;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use vector;
+pub use vector::{encode_pdf, encode_svg};
+use {render_source, Rendered};
+
+/**
+ * 描画結果を画像ファイルにエンコードする。
+ * PNG などの画像は Canvas から、SVG と PDF はディスプレイリストから（vector）書き出す。
+ * ディスプレイリストのないもの（アニメーションのフレームや serve の応答）を SVG や PDF にするときは、キャンバスを 1 枚の画像として貼る。
+ * APNG はアニメーションのフレームを 1 つのファイルにしたもの
+ */
+
+// 出力するフォーマット。Raw はヘッダーのない RGBA のピクセル（Canvas::to_rgba_bytes）。
+// Text と Markdown は画像の代わりにリーダーモードのテキストを書き出す
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+  Png,
+  Jpeg,
+  Gif,
+  Ppm,
+  Raw,
+  Svg,
+  Pdf,
+  Apng,
+  Text,
+  Markdown,
+}
+
+impl OutputFormat {
+  // --format に渡された名前から
+  pub fn from_name(name: &str) -> Option<OutputFormat> {
+    return match &*name.to_ascii_lowercase() {
+      "png" => Some(OutputFormat::Png),
+      "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+      "gif" => Some(OutputFormat::Gif),
+      "ppm" => Some(OutputFormat::Ppm),
+      "raw" => Some(OutputFormat::Raw),
+      "svg" => Some(OutputFormat::Svg),
+      "pdf" => Some(OutputFormat::Pdf),
+      "apng" => Some(OutputFormat::Apng),
+      "txt" | "text" => Some(OutputFormat::Text),
+      "md" | "markdown" => Some(OutputFormat::Markdown),
+      _ => None,
+    };
+  }
+
+  // 出力パスの拡張子から
+  pub fn from_path(path: &str) -> Option<OutputFormat> {
+    return path.rsplit('.').next().and_then(OutputFormat::from_name);
+  }
+
+  // 画像ではないフォーマットなら、そのリーダーモードの形式
+  pub fn reader_format(&self) -> Option<ReaderFormat> {
+    return match *self {
+      OutputFormat::Text => Some(ReaderFormat::Text),
+      OutputFormat::Markdown => Some(ReaderFormat::Markdown),
+      _ => None,
+    };
+  }
+}
+
+// ファイルやネットワークを使わずに、HTML と CSS の文字列からビューポートのスクリーンショットを撮る（ヘッドレス）。
+// レイアウトは options のビューポートの大きさ（CSS px）で行い、画像はその options.device_pixel_ratio 倍の大きさ（2 なら縦横 2 倍のピクセルで描く）
+pub fn render_to_image(html: &str, css: &str, options: &EngineOptions) -> Result<image::RgbaImage, EngineError> {
+  let viewport = Rect { x: 0.0, y: 0.0, width: options.viewport_width, height: options.viewport_height };
+  let rendered = render_source(html, css, viewport, options)?;
+  return Ok(pixels_to_rgba(rendered.canvas.width, rendered.canvas.height, &rendered.canvas.pixels));
+}
+
+pub fn canvas_to_image(canvas: &Canvas) -> image::DynamicImage {
+  return pixels_to_image(canvas.width, canvas.height, &canvas.pixels);
+}
+
+// 左上から行ごとに並んだピクセルを RGBA の画像にする
+pub fn pixels_to_image(width: usize, height: usize, pixels: &[Color]) -> image::DynamicImage {
+  return image::ImageRgba8(pixels_to_rgba(width, height, pixels));
+}
+
+fn pixels_to_rgba(width: usize, height: usize, pixels: &[Color]) -> image::RgbaImage {
+  let (w, h) = (width as u32, height as u32);
+  return image::ImageBuffer::from_fn(w, h, |x, y| {
+    let color = pixels[(y * w + x) as usize];
+    image::Pixel::from_channels(color.r, color.g, color.b, color.a)
+  });
+}
+
+pub fn save_canvas(canvas: &Canvas, filename: &str, format: OutputFormat) -> Result<(), EngineError> {
+  match format {
+    OutputFormat::Svg | OutputFormat::Pdf | OutputFormat::Apng => return save_bytes(&encode_canvas(canvas, format)?, filename),
+    _ => {}
+  }
+  let file = File::create(filename).map_err(|error| IoError::Write { path: filename.to_string(), source: error })?;
+  let mut file = BufWriter::new(file);
+  write_canvas(canvas, &mut file, format)?;
+  return Ok(());
+}
+
+impl Canvas {
+  // PNG にして path に書き出す
+  pub fn save_png(&self, path: &str) -> Result<(), EngineError> {
+    return save_canvas(self, path, OutputFormat::Png);
+  }
+}
+
+// ファイルに書き出さずに、エンコードしたバイト列にする（serve の応答など）
+pub fn encode_canvas(canvas: &Canvas, format: OutputFormat) -> Result<Vec<u8>, EngineError> {
+  match format {
+    OutputFormat::Svg => return Ok(vector::encode_svg(&canvas_page(canvas))?.into_bytes()),
+    OutputFormat::Pdf => return Ok(vector::encode_pdf(&[&canvas_page(canvas)])?),
+    OutputFormat::Apng => return Ok(encode_apng(&[canvas], 0.0)?),
+    _ => {}
+  }
+  let mut bytes = Vec::new();
+  write_canvas(canvas, &mut bytes, format)?;
+  return Ok(bytes);
+}
+
+// PPM と Raw は image を通さずにキャンバスから書く
+fn write_canvas<W: Write>(canvas: &Canvas, out: &mut W, format: OutputFormat) -> Result<(), PaintError> {
+  let result = match format {
+    OutputFormat::Ppm => canvas.write_ppm(out),
+    OutputFormat::Raw => out.write_all(&canvas.to_rgba_bytes()),
+    _ => return save_image(canvas_to_image(canvas), out, format),
+  };
+  return result.map_err(|error| PaintError::Encode(error.to_string()));
+}
+
+// render_document などの描画結果。SVG と PDF ならディスプレイリストから、画像ならキャンバスから書き出す
+pub fn save_rendered(rendered: &Rendered, filename: &str, format: OutputFormat) -> Result<(), EngineError> {
+  return match format {
+    OutputFormat::Svg => save_bytes(vector::encode_svg(&rendered.page)?.as_bytes(), filename),
+    OutputFormat::Pdf => save_bytes(&vector::encode_pdf(&[&rendered.page])?, filename),
+    _ => save_canvas(&rendered.canvas, filename, format),
+  };
+}
+
+// 印刷のページ。PDF なら 1 つのファイルに、SVG や画像なら capture-1.png、capture-2.png... のように 1 ページずつ書き出す。
+// 書き出したファイルのパスを返す
+pub fn save_pages(printed: &Printed, filename: &str, format: OutputFormat) -> Result<Vec<String>, EngineError> {
+  if format == OutputFormat::Pdf {
+    save_bytes(&vector::encode_pdf(&printed.display_lists.iter().collect::<Vec<_>>())?, filename)?;
+    return Ok(vec![filename.to_string()]);
+  }
+  let mut filenames = Vec::new();
+  for (index, page) in printed.pages.iter().enumerate() {
+    let page_filename = numbered_filename(filename, index + 1);
+    match format {
+      OutputFormat::Svg => save_bytes(vector::encode_svg(&printed.display_lists[index])?.as_bytes(), &page_filename)?,
+      _ => save_canvas(page, &page_filename, format)?,
+    }
+    filenames.push(page_filename);
+  }
+  return Ok(filenames);
+}
+
+// アニメーションのフレーム。APNG なら interval 秒ごとに切り替える 1 つのファイルに、PDF なら 1 フレーム 1 ページの 1 つのファイルに、
+// ほかの形式なら save_pages と同じく 1 フレームずつ番号を付けたファイルに書き出す
+pub fn save_frames(frames: &[Canvas], interval: f32, filename: &str, format: OutputFormat) -> Result<Vec<String>, EngineError> {
+  match format {
+    OutputFormat::Apng => save_bytes(&encode_apng(&frames.iter().collect::<Vec<_>>(), interval)?, filename)?,
+    OutputFormat::Pdf => {
+      let pages: Vec<Page> = frames.iter().map(canvas_page).collect();
+      save_bytes(&vector::encode_pdf(&pages.iter().collect::<Vec<_>>())?, filename)?
+    }
+    _ => {
+      let mut filenames = Vec::new();
+      for (index, frame) in frames.iter().enumerate() {
+        let frame_filename = numbered_filename(filename, index + 1);
+        save_canvas(frame, &frame_filename, format)?;
+        filenames.push(frame_filename);
+      }
+      return Ok(filenames);
+    }
+  }
+  return Ok(vec![filename.to_string()]);
+}
+
+// キャンバスを 1 枚の画像として貼ったページ（ディスプレイリストのないものを SVG や PDF にするとき）
+fn canvas_page(canvas: &Canvas) -> Page {
+  let rect = Rect { x: 0.0, y: 0.0, width: canvas.width as f32, height: canvas.height as f32 };
+  let image = Image { width: canvas.width, height: canvas.height, pixels: canvas.pixels.clone() };
+  let display_list = if canvas.pixels.is_empty() { Vec::new() } else { vec![DisplayCommand::Image { image: Arc::new(image), rect: rect, repeat: false, clip: rect }] };
+  return Page { display_list: display_list, bounds: rect, scale: 1.0 };
+}
+
+// capture.png → capture-2.png（拡張子がなければ末尾に付ける）
+fn numbered_filename(filename: &str, number: usize) -> String {
+  let name_start = filename.rfind(['/', '\\']).map_or(0, |slash| slash + 1);
+  return match filename[name_start..].rfind('.') {
+    Some(dot) if dot > 0 => format!("{}-{}{}", &filename[..name_start + dot], number, &filename[name_start + dot..]),
+    _ => format!("{}-{}", filename, number),
+  };
+}
+
+pub fn save_text(text: &str, filename: &str) -> Result<(), EngineError> {
+  return save_bytes(text.as_bytes(), filename);
+}
+
+fn save_bytes(bytes: &[u8], filename: &str) -> Result<(), EngineError> {
+  let mut file = File::create(filename).map_err(|error| IoError::Write { path: filename.to_string(), source: error })?;
+  file.write_all(bytes).map_err(|error| IoError::Write { path: filename.to_string(), source: error })?;
+  return Ok(());
+}
+
+// APNG。最初のフレームが普通の PNG の画像（IDAT）で、APNG に対応していないビューアーではそれだけを表示する。
+// 2 枚目からは fdAT に入れる。どのフレームもキャンバス全体を置き換え、最後まで描いたら繰り返す
+fn encode_apng(frames: &[&Canvas], interval: f32) -> Result<Vec<u8>, PaintError> {
+  let first = match frames.first() {
+    Some(first) => first,
+    None => return Err(PaintError::Encode("no frames to encode".to_string())),
+  };
+  let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+  let header = png_chunks(&encode_png(rgb(first))?).into_iter().find(|chunk| &chunk.0 == b"IHDR").ok_or_else(|| PaintError::Encode("PNG encoder wrote no header".to_string()))?;
+  write_chunk(&mut out, b"IHDR", &header.1);
+  let mut control = Vec::new();
+  control.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+  control.extend_from_slice(&0u32.to_be_bytes()); // 0 なら繰り返す
+  write_chunk(&mut out, b"acTL", &control);
+
+  let delay = (interval * 1000.0).round().clamp(0.0, u16::MAX as f32) as u16; // ミリ秒
+  let mut sequence: u32 = 0;
+  for (index, frame) in frames.iter().enumerate() {
+    if frame.width != first.width || frame.height != first.height {
+      return Err(PaintError::Encode("all frames must have the same size".to_string()));
+    }
+    let mut frame_control = Vec::new();
+    for number in &[sequence, frame.width as u32, frame.height as u32, 0, 0] {
+      frame_control.extend_from_slice(&number.to_be_bytes());
+    }
+    frame_control.extend_from_slice(&delay.to_be_bytes());
+    frame_control.extend_from_slice(&1000u16.to_be_bytes());
+    frame_control.extend_from_slice(&[0, 0]); // 前のフレームを残して、上書きする
+    write_chunk(&mut out, b"fcTL", &frame_control);
+    sequence += 1;
+
+    let data = png_image_data(&encode_png(rgb(frame))?)?;
+    if index == 0 {
+      write_chunk(&mut out, b"IDAT", &data);
+    } else {
+      let mut frame_data = sequence.to_be_bytes().to_vec();
+      frame_data.extend_from_slice(&data);
+      write_chunk(&mut out, b"fdAT", &frame_data);
+      sequence += 1;
+    }
+  }
+  write_chunk(&mut out, b"IEND", &[]);
+  return Ok(out);
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+  out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  let start = out.len();
+  out.extend_from_slice(kind);
+  out.extend_from_slice(data);
+  let crc = crc32(&out[start..]);
+  out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// PNG のチャンクの CRC（多項式 0xedb88320）
+fn crc32(bytes: &[u8]) -> u32 {
+  let mut crc = 0xffff_ffffu32;
+  for &byte in bytes {
+    crc ^= byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+    }
+  }
+  return !crc;
+}
+
+// チャンクの種類と中身
+type PngChunk = ([u8; 4], Vec<u8>);
+
+fn rgb(canvas: &Canvas) -> image::DynamicImage {
+  return image::ImageRgb8(canvas_to_image(canvas).to_rgb());
+}
+
+pub fn encode_png(img: image::DynamicImage) -> Result<Vec<u8>, PaintError> {
+  let mut png = Vec::new();
+  save_image(img, &mut png, OutputFormat::Png)?;
+  return Ok(png);
+}
+
+// PNG をチャンクに分ける
+fn png_chunks(png: &[u8]) -> Vec<PngChunk> {
+  let mut chunks = Vec::new();
+  let mut pos = 8; // シグネチャ
+  while pos + 8 <= png.len() {
+    let length = u32::from_be_bytes([png[pos], png[pos + 1], png[pos + 2], png[pos + 3]]) as usize;
+    let end = pos + 8 + length;
+    if end > png.len() {
+      break;
+    }
+    chunks.push(([png[pos + 4], png[pos + 5], png[pos + 6], png[pos + 7]], png[pos + 8..end].to_vec()));
+    pos = end + 4; // CRC
+  }
+  return chunks;
+}
+
+// PNG の IDAT チャンクの中身（zlib で圧縮した、行ごとにフィルターを付けたピクセル）をつなげる。
+// PDF の画像は同じ圧縮（FlateDecode と PNG の予測子）なので、そのまま使える
+pub fn png_image_data(png: &[u8]) -> Result<Vec<u8>, PaintError> {
+  let data: Vec<u8> = png_chunks(png).iter().filter(|chunk| &chunk.0 == b"IDAT").flat_map(|chunk| chunk.1.iter().cloned()).collect();
+  if data.is_empty() {
+    return Err(PaintError::Encode("PNG encoder wrote no image data".to_string()));
+  }
+  return Ok(data);
+}
+
+// フォーマットに合わせてエンコードする（JPEG はアルファなしの RGB で書き出す）
+fn save_image<W: Write>(img: image::DynamicImage, file: &mut W, format: OutputFormat) -> Result<(), PaintError> {
+  let result = match format {
+    OutputFormat::Png => img.save(file, image::PNG),
+    OutputFormat::Gif => img.save(file, image::GIF),
+    OutputFormat::Jpeg => image::ImageRgb8(img.to_rgb()).save(file, image::JPEG),
+    OutputFormat::Ppm | OutputFormat::Raw | OutputFormat::Svg | OutputFormat::Pdf | OutputFormat::Apng | OutputFormat::Text | OutputFormat::Markdown => return Err(PaintError::Encode(format!("{:?} is not an image format", format))),
+  };
+  return result.map_err(|error| PaintError::Encode(format!("{:?}", error)));
+}
@@ -1,150 +1,110 @@
-use css::{Color, Value};
-use layout::BoxType::{AnonymousBlock, BlockNode, InlineNode};
-use layout::{LayoutBox, Rect};
-
-pub struct Canvas {
-  pub pixels: Vec<Color>,
-  pub width: usize,
-  pub height: usize,
+use css::Color;
+use layout::Rect;
+use thiserror::Error;
+
+/**
+ * ペイント。display_list.rs でレイアウトツリーをディスプレイリストにして、raster.rs でそれをキャンバスに描く。
+ * ここには両方を使う Page と描き方の設定 RenderOptions、エラーの PaintError を置き、外から使うものは paint:: の下にまとめて出す
+ */
+
+mod display_list;
+mod raster;
+
+pub use self::display_list::{
+  box_model_overlay, build_display_list, build_display_list_with, clip_display_list, damage, debug_boxes, paint_order, transformed_rect, DisplayCommand, DisplayList, PaintStep,
+};
+pub use self::raster::{
+  border_sides, gradient_color, gradient_image, missing_glyph_box, paint, paint_display_list, paint_display_list_scaled, paint_display_list_tiled, paint_display_list_with, text_boxes, Canvas, Tiling,
+  CONTROL_ACCENT, CONTROL_BACKGROUND, CONTROL_BORDER,
+};
+use self::display_list::extents;
+use self::raster::canvas_size;
+
+// 描画時のエラー
+#[derive(Debug, Error)]
+pub enum PaintError {
+  #[error("invalid canvas size {width}x{height}")]
+  InvalidCanvasSize { width: f32, height: f32 },
+  #[error("failed to encode image: {0}")]
+  Encode(String),
 }
 
-impl Canvas {
-  pub fn new(width: usize, height: usize) -> Canvas {
-    let white = Color {
-      r: 255,
-      g: 255,
-      b: 255,
-      a: 255,
-    };
-    return Canvas {
-      pixels: vec![white; width * height],
-      width,
-      height,
-    };
-  }
-  pub fn paint_item(&mut self, item: &DisplayCommand) {
-    match *item {
-      DisplayCommand::SolidColor(color, rect) => {
-        let x0 = rect.x.clamp(0.0, self.width as f32) as usize;
-        let y0 = rect.y.clamp(0.0, self.height as f32) as usize;
-        let x1 = (rect.x + rect.width).clamp(0.0, self.width as f32) as usize;
-        let y1 = (rect.y + rect.height).clamp(0.0, self.height as f32) as usize;
-
-        for y in y0..y1 {
-          for x in x0..x1 {
-            self.pixels[y * self.width + x] = color;
-          }
-        }
-      }
-    }
-  }
+// 描き方の設定。EngineOptions の background、pixel_snap、debug_boxes から作る（EngineOptions::render_options）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderOptions {
+  pub background: Color, // キャンバスを塗っておく色（ドキュメントの背景がなければこれが見える）
+  pub pixel_snap: bool,  // 矩形の辺をピクセルの境目に合わせてから描く（DisplayCommand::snapped）
+  pub debug_boxes: bool, // すべてのボックスの margin、padding、content の範囲を枠で描く（debug_boxes）
 }
 
-type DisplayList = Vec<DisplayCommand>;
-
-pub enum DisplayCommand {
-  SolidColor(Color, Rect),
-}
-
-fn build_display_list(layout_root: &LayoutBox) -> DisplayList {
-  let mut list = Vec::new();
-  render_layout_box(&mut list, layout_root);
-  return list;
-}
-
-fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox) {
-  render_background(list, layout_box);
-  render_borders(list, layout_box);
-
-  for child in &layout_box.children {
-    render_layout_box(list, child);
+impl Default for RenderOptions {
+  fn default() -> RenderOptions {
+    return RenderOptions { background: Color { r: 255, g: 255, b: 255, a: 255 }, pixel_snap: false, debug_boxes: false };
   }
 }
 
-fn render_background(list: &mut DisplayList, layout_box: &LayoutBox) {
-  get_color(layout_box, "background").map(|color| {
-    list.push(DisplayCommand::SolidColor(
-      color,
-      layout_box.dimensions.border_box(),
-    ))
-  });
+// 描く範囲 bounds と倍率 scale を付けたディスプレイリスト（1 枚の画像や 1 ページ分）。
+// ラスタライズしない出力（SVG と PDF）は、キャンバスの代わりにこれを書き出す
+#[derive(Debug, Clone)]
+pub struct Page {
+  pub display_list: DisplayList,
+  pub bounds: Rect,
+  pub scale: f32,
 }
 
-fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
-  match layout_box.box_type {
-    BlockNode(style) | InlineNode(style) => match style.value(name) {
-      Some(Value::ColorValue(color)) => Some(color),
-      _ => None,
-    },
-    AnonymousBlock => None,
+impl Page {
+  // 書き出す大きさ（paint_display_list_scaled のキャンバスと同じ）
+  pub fn size(&self) -> Result<(f32, f32), PaintError> {
+    return canvas_size(self.bounds, self.scale);
   }
-}
-
-fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
-  let color = match get_color(layout_box, "border-color") {
-    Some(color) => color,
-    _ => return,
-  };
 
-  let d = &layout_box.dimensions;
-  let border_box = d.border_box();
-
-  list.push(DisplayCommand::SolidColor(
-    color,
-    Rect {
-      x: border_box.x,
-      y: border_box.y,
-      width: d.border.left,
-      height: border_box.height,
-    },
-  ));
-
-  list.push(DisplayCommand::SolidColor(
-    color,
-    Rect {
-      x: border_box.x + border_box.width - d.border.right,
-      y: border_box.y,
-      width: d.border.right,
-      height: border_box.height,
-    },
-  ));
+  // bounds の左上を原点にして scale 倍にしたコマンド
+  pub fn commands(&self) -> Vec<DisplayCommand> {
+    return self.display_list.iter().map(|item| item.translated(-self.bounds.x, -self.bounds.y).scaled(self.scale)).collect();
+  }
 
-  list.push(DisplayCommand::SolidColor(
-    color,
-    Rect {
-      x: border_box.x,
-      y: border_box.y,
-      width: border_box.width,
-      height: d.border.top,
-    },
-  ));
+  pub fn paint(&self) -> Result<Canvas, PaintError> {
+    return paint_display_list_scaled(&self.display_list, self.bounds, self.scale);
+  }
 
-  list.push(DisplayCommand::SolidColor(
-    color,
-    Rect {
-      x: border_box.x,
-      y: border_box.y + border_box.height - d.border.bottom,
-      width: border_box.width,
-      height: d.border.bottom,
-    },
-  ));
-}
+  // tiling のタイルに分けて描く
+  pub fn paint_tiled(&self, tiling: Tiling) -> Result<Canvas, PaintError> {
+    return paint_display_list_tiled(&self.display_list, self.bounds, self.scale, tiling);
+  }
 
-trait Clamp {
-  fn clamp(self, lower: Self, upper: Self) -> Self;
-}
-impl Clamp for f32 {
-  fn clamp(self, lower: f32, upper: f32) -> f32 {
-    self.max(lower).min(upper)
+  // paint_tiled と同じだが、options の背景の色で、辺を合わせるなら合わせて描く
+  pub fn paint_with(&self, tiling: Tiling, options: &RenderOptions) -> Result<Canvas, PaintError> {
+    return paint_display_list_with(&self.display_list, self.bounds, self.scale, tiling, options);
   }
-}
 
-// 描画
-pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
-  let display_list = build_display_list(layout_root);
-  let mut canvas = Canvas::new(bounds.width as usize, bounds.height as usize);
-  for item in display_list {
-    canvas.paint_item(&item);
+  // previous を描いたキャンバス canvas を、このページを描いたものにする。描いてあるピクセルをスクロールした分だけずらして使い、
+  // 空いたところと、ディスプレイリストで変わったところ（damage）だけを描き直す。
+  // 大きさか倍率が違うか、ずらす量がピクセルの整数倍でなければ何もせずに false
+  pub fn repaint(&self, canvas: &mut Canvas, previous: &Page) -> Result<bool, PaintError> {
+    let (width, height) = self.size()?;
+    if canvas.width != width as usize || canvas.height != height as usize || canvas.origin != (0, 0) || self.scale != previous.scale {
+      return Ok(false);
+    }
+    let (dx, dy) = ((previous.bounds.x - self.bounds.x) * self.scale, (previous.bounds.y - self.bounds.y) * self.scale);
+    if dx.fract() != 0.0 || dy.fract() != 0.0 || dx.abs() > width || dy.abs() > height {
+      return Ok(false);
+    }
+    let mut regions = canvas.shift(dx as isize, dy as isize);
+    let mut changed = damage(&previous.display_list, &self.display_list);
+    // 変換した中身は、ずらすと補間の丸めが変わるので描き直す
+    if dx != 0.0 || dy != 0.0 {
+      changed.extend(extents(&self.display_list).into_iter().zip(&self.display_list).filter_map(|(extent, item)| match *item {
+        DisplayCommand::PushTransform { .. } => extent,
+        _ => None,
+      }));
+    }
+    let (bounds, scale) = (self.bounds, self.scale);
+    regions.extend(
+      changed
+        .into_iter()
+        .map(|rect| Rect { x: (rect.x - bounds.x) * scale, y: (rect.y - bounds.y) * scale, width: rect.width * scale, height: rect.height * scale }),
+    );
+    canvas.repaint(&self.commands(), &regions);
+    return Ok(true);
   }
-  return canvas
-}
\ No newline at end of file
+}
@@ -0,0 +1,741 @@
+use css::{Color, ColorStop, Matrix, Unit, Value};
+use font::GlyphRun;
+use form;
+use images::Image;
+use layout::BoxType::{AnonymousBlock, BlockNode, InlineBlockNode, InlineNode, ReplacedNode};
+use layout::{DecorationLine, Dimensions, EdgeSizes, LayoutBox, Rect, TextDecoration};
+use std::sync::Arc;
+use style::{BorderStyle, Position};
+use trace;
+use super::raster::border_sides;
+
+/**
+ * レイアウトツリーからディスプレイリスト（描くコマンドの並び）を作る。
+ *
+ * paint_order が重ね合わせの文脈（z-index、opacity、transform）の順に描くボックスを並べ、
+ * build_display_list_with がそれぞれのボックスの背景、枠線、中身を DisplayCommand にする。
+ * damage と extents は、2 つのディスプレイリストを比べて描き直す範囲を求める
+ */
+
+pub type DisplayList = Vec<DisplayCommand>;
+
+// インスペクターのハイライトの色（開発者ツールと同じ）
+const OVERLAY_CONTENT: Color = Color { r: 111, g: 168, b: 220, a: 168 };
+const OVERLAY_PADDING: Color = Color { r: 147, g: 196, b: 125, a: 140 };
+const OVERLAY_BORDER: Color = Color { r: 255, g: 229, b: 153, a: 168 };
+const OVERLAY_MARGIN: Color = Color { r: 246, g: 178, b: 107, a: 168 };
+
+// ボックスの content、padding、border、margin をそれぞれの色で塗る半透明の矩形（インスペクターのハイライト）。ページの後に重ねて描く
+pub fn box_model_overlay(d: Dimensions) -> DisplayList {
+  let mut list = vec![DisplayCommand::SolidColor(OVERLAY_CONTENT, d.content)];
+  push_ring(&mut list, OVERLAY_PADDING, d.padding_box(), d.padding);
+  push_ring(&mut list, OVERLAY_BORDER, d.border_box(), d.border);
+  push_ring(&mut list, OVERLAY_MARGIN, d.margin_box(), d.margin);
+  return list;
+}
+
+// RenderOptions::debug_boxes で重ねる枠の色（margin は橙、padding は緑、content は青）
+const DEBUG_MARGIN: Color = Color { r: 246, g: 138, b: 30, a: 255 };
+const DEBUG_PADDING: Color = Color { r: 76, g: 175, b: 80, a: 255 };
+const DEBUG_CONTENT: Color = Color { r: 33, g: 118, b: 210, a: 255 };
+
+// すべてのボックスの margin、padding、content の範囲を 1px の枠で描く（レイアウトを目で確かめる用）。ページの後に重ねて描く
+pub fn debug_boxes(layout_root: &LayoutBox) -> DisplayList {
+  let mut list = Vec::new();
+  push_debug_boxes(&mut list, layout_root);
+  return list;
+}
+
+fn push_debug_boxes(list: &mut DisplayList, layout_box: &LayoutBox) {
+  let d = layout_box.dimensions;
+  let hairline = EdgeSizes { left: 1.0, right: 1.0, top: 1.0, bottom: 1.0 };
+  push_ring(list, DEBUG_MARGIN, d.margin_box(), hairline);
+  push_ring(list, DEBUG_PADDING, d.padding_box(), hairline);
+  push_ring(list, DEBUG_CONTENT, d.content, hairline);
+  for child in &layout_box.children {
+    push_debug_boxes(list, child);
+  }
+}
+
+// rect の内側の幅 widths の枠。角は左右の辺に含めて、重ねて塗らない
+fn push_ring(list: &mut DisplayList, color: Color, rect: Rect, widths: EdgeSizes) {
+  let [left, right, top, bottom] = border_sides(rect, widths);
+  let (inner_x, inner_width) = (rect.x + widths.left, (rect.width - widths.left - widths.right).max(0.0));
+  for side in [left, right, Rect { x: inner_x, width: inner_width, ..top }, Rect { x: inner_x, width: inner_width, ..bottom }] {
+    if side.width > 0.0 && side.height > 0.0 {
+      list.push(DisplayCommand::SolidColor(color, side));
+    }
+  }
+}
+
+// RoundedRect、Border、Text、Checkbox、Radio、Image は clip の外には描かない。
+// radii は角の半径（左上、右上、右下、左下）、Border の colors と styles は border_sides と同じ左、右、上、下の順。
+// PushOpacity から対応する PopOpacity までのコマンドは、透明な面に描いてから不透明度を掛けてまとめて重ねる。
+// PushTransform から対応する PopTransform までのコマンドは変換する前の座標で、透明な面に描いてから matrix で変換して clip の中に重ねる。
+// bounds はその中身が描かれる範囲（変換する前）
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisplayCommand {
+  SolidColor(Color, Rect),
+  RoundedRect { color: Color, rect: Rect, radii: [f32; 4], clip: Rect },
+  Border { rect: Rect, widths: EdgeSizes, colors: [Color; 4], styles: [BorderStyle; 4], radii: [f32; 4], clip: Rect }, // rect はボーダーボックス、widths は辺ごとの太さ
+  Text { text: String, glyphs: Option<GlyphRun>, color: Color, x: f32, y: f32, font_size: f32, clip: Rect }, // (x, y) は行の左上
+  Checkbox { rect: Rect, checked: bool, clip: Rect },
+  Radio { rect: Rect, checked: bool, clip: Rect },
+  Image { image: Arc<Image>, rect: Rect, repeat: bool, clip: Rect }, // rect に合わせて描く。repeat なら rect を 1 枚分として敷きつめる
+  Gradient { rect: Rect, angle: f32, stops: Vec<(Color, f32)>, clip: Rect }, // stops は色とグラデーションの線の上の位置（0 から 1 の順）
+  PushOpacity(f32),
+  PopOpacity,
+  PushTransform { matrix: Matrix, bounds: Rect, clip: Rect },
+  PopTransform,
+}
+
+impl DisplayCommand {
+  // キャンバスの原点に合わせて平行移動する
+  pub fn translated(&self, dx: f32, dy: f32) -> DisplayCommand {
+    let translate = |rect: Rect| Rect { x: rect.x + dx, y: rect.y + dy, ..rect };
+    match *self {
+      DisplayCommand::SolidColor(color, rect) => DisplayCommand::SolidColor(color, translate(rect)),
+      DisplayCommand::RoundedRect { color, rect, radii, clip } => DisplayCommand::RoundedRect { color: color, rect: translate(rect), radii: radii, clip: translate(clip) },
+      DisplayCommand::Border { rect, widths, colors, styles, radii, clip } => {
+        DisplayCommand::Border { rect: translate(rect), widths: widths, colors: colors, styles: styles, radii: radii, clip: translate(clip) }
+      }
+      DisplayCommand::Text { ref text, ref glyphs, color, x, y, font_size, clip } => {
+        DisplayCommand::Text { text: text.clone(), glyphs: glyphs.clone(), color: color, x: x + dx, y: y + dy, font_size: font_size, clip: translate(clip) }
+      }
+      DisplayCommand::Checkbox { rect, checked, clip } => DisplayCommand::Checkbox { rect: translate(rect), checked: checked, clip: translate(clip) },
+      DisplayCommand::Radio { rect, checked, clip } => DisplayCommand::Radio { rect: translate(rect), checked: checked, clip: translate(clip) },
+      DisplayCommand::Image { ref image, rect, repeat, clip } => DisplayCommand::Image { image: image.clone(), rect: translate(rect), repeat: repeat, clip: translate(clip) },
+      DisplayCommand::Gradient { rect, angle, ref stops, clip } => DisplayCommand::Gradient { rect: translate(rect), angle: angle, stops: stops.clone(), clip: translate(clip) },
+      DisplayCommand::PushTransform { matrix, bounds, clip } => {
+        // 動かした座標で、元に戻してから変換してまた動かす
+        let matrix = Matrix::translate(dx, dy).multiply(&matrix).multiply(&Matrix::translate(-dx, -dy));
+        DisplayCommand::PushTransform { matrix: matrix, bounds: translate(bounds), clip: translate(clip) }
+      }
+      DisplayCommand::PushOpacity(_) | DisplayCommand::PopOpacity | DisplayCommand::PopTransform => self.clone(),
+    }
+  }
+
+  // 原点を中心に factor 倍する
+  pub fn scaled(&self, factor: f32) -> DisplayCommand {
+    let scale = |rect: Rect| Rect { x: rect.x * factor, y: rect.y * factor, width: rect.width * factor, height: rect.height * factor };
+    match *self {
+      DisplayCommand::SolidColor(color, rect) => DisplayCommand::SolidColor(color, scale(rect)),
+      DisplayCommand::RoundedRect { color, rect, radii, clip } => DisplayCommand::RoundedRect { color: color, rect: scale(rect), radii: radii.map(|radius| radius * factor), clip: scale(clip) },
+      DisplayCommand::Border { rect, widths, colors, styles, radii, clip } => {
+        let widths = EdgeSizes { left: widths.left * factor, right: widths.right * factor, top: widths.top * factor, bottom: widths.bottom * factor };
+        DisplayCommand::Border { rect: scale(rect), widths: widths, colors: colors, styles: styles, radii: radii.map(|radius| radius * factor), clip: scale(clip) }
+      }
+      DisplayCommand::Text { ref text, ref glyphs, color, x, y, font_size, clip } => {
+        let glyphs = glyphs.as_ref().map(|run| run.scaled(factor));
+        DisplayCommand::Text { text: text.clone(), glyphs: glyphs, color: color, x: x * factor, y: y * factor, font_size: font_size * factor, clip: scale(clip) }
+      }
+      DisplayCommand::Checkbox { rect, checked, clip } => DisplayCommand::Checkbox { rect: scale(rect), checked: checked, clip: scale(clip) },
+      DisplayCommand::Radio { rect, checked, clip } => DisplayCommand::Radio { rect: scale(rect), checked: checked, clip: scale(clip) },
+      DisplayCommand::Image { ref image, rect, repeat, clip } => DisplayCommand::Image { image: image.clone(), rect: scale(rect), repeat: repeat, clip: scale(clip) },
+      DisplayCommand::Gradient { rect, angle, ref stops, clip } => DisplayCommand::Gradient { rect: scale(rect), angle: angle, stops: stops.clone(), clip: scale(clip) },
+      DisplayCommand::PushTransform { matrix, bounds, clip } => {
+        let matrix = Matrix { e: matrix.e * factor, f: matrix.f * factor, ..matrix };
+        DisplayCommand::PushTransform { matrix: matrix, bounds: scale(bounds), clip: scale(clip) }
+      }
+      DisplayCommand::PushOpacity(_) | DisplayCommand::PopOpacity | DisplayCommand::PopTransform => self.clone(),
+    }
+  }
+
+  // 矩形の辺を近いピクセルの境目に合わせる（RenderOptions::pixel_snap）。隣り合うボックスの間に隙間や重なりができないように、
+  // 幅ではなく両端をそれぞれ丸める。枠線の太さも丸めるが、0 でなければ 1 ピクセルは残す。
+  // 文字の位置と、PushTransform の行列はそのまま
+  pub fn snapped(&self) -> DisplayCommand {
+    let snap = |rect: Rect| {
+      let (x, y) = (rect.x.round(), rect.y.round());
+      return Rect { x: x, y: y, width: (rect.x + rect.width).round() - x, height: (rect.y + rect.height).round() - y };
+    };
+    let snap_width = |width: f32| if width > 0.0 { width.round().max(1.0) } else { width };
+    match *self {
+      DisplayCommand::SolidColor(color, rect) => DisplayCommand::SolidColor(color, snap(rect)),
+      DisplayCommand::RoundedRect { color, rect, radii, clip } => DisplayCommand::RoundedRect { color: color, rect: snap(rect), radii: radii, clip: snap(clip) },
+      DisplayCommand::Border { rect, widths, colors, styles, radii, clip } => {
+        let widths = EdgeSizes { left: snap_width(widths.left), right: snap_width(widths.right), top: snap_width(widths.top), bottom: snap_width(widths.bottom) };
+        DisplayCommand::Border { rect: snap(rect), widths: widths, colors: colors, styles: styles, radii: radii, clip: snap(clip) }
+      }
+      DisplayCommand::Text { ref text, ref glyphs, color, x, y, font_size, clip } => {
+        DisplayCommand::Text { text: text.clone(), glyphs: glyphs.clone(), color: color, x: x, y: y, font_size: font_size, clip: snap(clip) }
+      }
+      DisplayCommand::Checkbox { rect, checked, clip } => DisplayCommand::Checkbox { rect: snap(rect), checked: checked, clip: snap(clip) },
+      DisplayCommand::Radio { rect, checked, clip } => DisplayCommand::Radio { rect: snap(rect), checked: checked, clip: snap(clip) },
+      DisplayCommand::Image { ref image, rect, repeat, clip } => DisplayCommand::Image { image: image.clone(), rect: snap(rect), repeat: repeat, clip: snap(clip) },
+      DisplayCommand::Gradient { rect, angle, ref stops, clip } => DisplayCommand::Gradient { rect: snap(rect), angle: angle, stops: stops.clone(), clip: snap(clip) },
+      DisplayCommand::PushTransform { matrix, bounds, clip } => DisplayCommand::PushTransform { matrix: matrix, bounds: bounds, clip: snap(clip) },
+      DisplayCommand::PushOpacity(_) | DisplayCommand::PopOpacity | DisplayCommand::PopTransform => self.clone(),
+    }
+  }
+
+  // clip の内側だけにする。はみ出す部分しかなければ None。PushOpacity と PopOpacity、PushTransform と PopTransform は対が崩れないように残す
+  // （PushTransform は clip を狭めるだけで、その中のコマンドは変換する前の座標なので、切り取るのは中身を描く側で決める）
+  pub fn clipped(&self, clip: Rect) -> Option<DisplayCommand> {
+    match *self {
+      DisplayCommand::SolidColor(color, rect) => rect.intersection(clip).map(|rect| DisplayCommand::SolidColor(color, rect)),
+      DisplayCommand::RoundedRect { color, rect, radii, clip: own } => own.intersection(clip).map(|clip| DisplayCommand::RoundedRect { color: color, rect: rect, radii: radii, clip: clip }),
+      DisplayCommand::Border { rect, widths, colors, styles, radii, clip: own } => {
+        own.intersection(clip).map(|clip| DisplayCommand::Border { rect: rect, widths: widths, colors: colors, styles: styles, radii: radii, clip: clip })
+      }
+      DisplayCommand::Text { ref text, ref glyphs, color, x, y, font_size, clip: own } => {
+        own.intersection(clip).map(|clip| DisplayCommand::Text { text: text.clone(), glyphs: glyphs.clone(), color: color, x: x, y: y, font_size: font_size, clip: clip })
+      }
+      DisplayCommand::Checkbox { rect, checked, clip: own } => own.intersection(clip).map(|clip| DisplayCommand::Checkbox { rect: rect, checked: checked, clip: clip }),
+      DisplayCommand::Radio { rect, checked, clip: own } => own.intersection(clip).map(|clip| DisplayCommand::Radio { rect: rect, checked: checked, clip: clip }),
+      DisplayCommand::Image { ref image, rect, repeat, clip: own } => {
+        own.intersection(clip).map(|clip| DisplayCommand::Image { image: image.clone(), rect: rect, repeat: repeat, clip: clip })
+      }
+      DisplayCommand::Gradient { rect, angle, ref stops, clip: own } => {
+        own.intersection(clip).map(|clip| DisplayCommand::Gradient { rect: rect, angle: angle, stops: stops.clone(), clip: clip })
+      }
+      DisplayCommand::PushTransform { matrix, bounds, clip: own } => {
+        let clip = own.intersection(clip).unwrap_or(Rect { width: 0.0, height: 0.0, ..own });
+        Some(DisplayCommand::PushTransform { matrix: matrix, bounds: bounds, clip: clip })
+      }
+      DisplayCommand::PushOpacity(_) | DisplayCommand::PopOpacity | DisplayCommand::PopTransform => Some(self.clone()),
+    }
+  }
+
+  // コマンドが描くかもしれない範囲。PushTransform はその中身が変換されて描かれる範囲（clip）で、
+  // PushOpacity、PopOpacity と PopTransform は自分では描かないので None
+  pub fn bounds(&self) -> Option<Rect> {
+    return match *self {
+      DisplayCommand::SolidColor(_, rect) => Some(rect),
+      DisplayCommand::RoundedRect { rect, clip, .. }
+      | DisplayCommand::Border { rect, clip, .. }
+      | DisplayCommand::Checkbox { rect, clip, .. }
+      | DisplayCommand::Radio { rect, clip, .. }
+      | DisplayCommand::Gradient { rect, clip, .. }
+      | DisplayCommand::Image { rect, repeat: false, clip, .. } => rect.intersection(clip),
+      DisplayCommand::Text { clip, .. } | DisplayCommand::Image { clip, .. } | DisplayCommand::PushTransform { clip, .. } => Some(clip),
+      DisplayCommand::PushOpacity(_) | DisplayCommand::PopOpacity | DisplayCommand::PopTransform => None,
+    };
+  }
+}
+
+// コマンドを clip の内側だけにする（DisplayCommand::clipped）。PushTransform の中のコマンドは変換する前の座標なので、
+// PushTransform の clip を狭めるだけで、中は切り取らない
+pub fn clip_display_list<I: IntoIterator<Item = DisplayCommand>>(items: I, clip: Rect) -> DisplayList {
+  let mut depth = 0;
+  let mut list = Vec::new();
+  for item in items {
+    let inside = depth > 0;
+    match item {
+      DisplayCommand::PushTransform { .. } => depth += 1,
+      DisplayCommand::PopTransform if depth > 0 => depth -= 1,
+      _ => {}
+    }
+    let item = if inside { Some(item) } else { item.clipped(clip) };
+    list.extend(item);
+  }
+  return list;
+}
+
+// old を描いたキャンバスを new を描いたものにするために、描き直さなければならない範囲（damage）。
+// 長さが同じなら同じ位置のコマンドどうしを比べ、違えば前と後ろの同じ並びを除いた間をすべて変わったものとして、
+// 変わったコマンドが old と new で描く範囲を集める
+pub fn damage(old: &[DisplayCommand], new: &[DisplayCommand]) -> Vec<Rect> {
+  let (old_extents, new_extents) = (extents(old), extents(new));
+  let changed: Vec<Option<Rect>> = if old.len() == new.len() {
+    (0..old.len()).filter(|&i| old[i] != new[i]).flat_map(|i| vec![old_extents[i], new_extents[i]]).collect()
+  } else {
+    let prefix = old.iter().zip(new).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|&(a, b)| a == b).count();
+    old_extents[prefix..old.len() - suffix].iter().chain(&new_extents[prefix..new.len() - suffix]).cloned().collect()
+  };
+  let mut rects = Vec::new();
+  for rect in changed.into_iter().flatten() {
+    if !rects.contains(&rect) {
+      rects.push(rect);
+    }
+  }
+  return rects;
+}
+
+// コマンドごとの、そのコマンドが変わったときに描き直す範囲。PushTransform の中のコマンドは変換する前の座標なので、
+// 一番外の PushTransform の範囲にする。PushOpacity と PopOpacity は不透明度が中身すべてにかかるので、中身の範囲を合わせたもの
+pub(super) fn extents(items: &[DisplayCommand]) -> Vec<Option<Rect>> {
+  let union = |a: Option<Rect>, b: Option<Rect>| match (a, b) {
+    (Some(a), Some(b)) => Some(a.union(b)),
+    (a, b) => a.or(b),
+  };
+  let mut extents = vec![None; items.len()];
+  let mut depth = 0; // 入っている PushTransform の数
+  let mut outer = None; // 一番外の PushTransform の clip
+  let mut groups: Vec<(usize, Option<Rect>)> = Vec::new(); // 入っている PushOpacity の位置と、ここまでの中身の範囲
+  for (i, item) in items.iter().enumerate() {
+    let extent = match *item {
+      DisplayCommand::PushTransform { clip, .. } => {
+        depth += 1;
+        if depth == 1 {
+          outer = Some(clip);
+        }
+        outer
+      }
+      DisplayCommand::PopTransform if depth > 0 => {
+        depth -= 1;
+        outer
+      }
+      DisplayCommand::PushOpacity(_) => {
+        groups.push((i, None));
+        continue;
+      }
+      DisplayCommand::PopOpacity => match groups.pop() {
+        Some((start, area)) => {
+          extents[start] = area;
+          area
+        }
+        None => None,
+      },
+      _ if depth > 0 => outer,
+      _ => item.bounds(),
+    };
+    extents[i] = extent;
+    if let Some(group) = groups.last_mut() {
+      group.1 = union(group.1, extent);
+    }
+  }
+  // 閉じていない PushOpacity は、最後までを中身とする
+  for (start, area) in groups {
+    extents[start] = area;
+  }
+  return extents;
+}
+
+pub fn build_display_list(layout_root: &LayoutBox) -> DisplayList {
+  return build_display_list_with(layout_root, &mut |_| None);
+}
+
+// contents はボックスの背景とボーダーの後（子より前）に描く中身を返す（iframe のドキュメントなど）
+pub fn build_display_list_with(layout_root: &LayoutBox, contents: &mut dyn FnMut(&LayoutBox) -> Option<DisplayList>) -> DisplayList {
+  let mut span = trace::span("paint", "display list");
+  let mut list = Vec::new();
+  for step in paint_order(layout_root) {
+    match step {
+      PaintStep::Box(layout_box, clip) => render_box(&mut list, layout_box, clip, contents),
+      PaintStep::PushOpacity(opacity) => list.push(DisplayCommand::PushOpacity(opacity)),
+      PaintStep::PopOpacity => list.push(DisplayCommand::PopOpacity),
+      PaintStep::PushTransform { matrix, bounds, clip } => list.push(DisplayCommand::PushTransform { matrix: matrix, bounds: bounds, clip: clip }),
+      PaintStep::PopTransform => list.push(DisplayCommand::PopTransform),
+    }
+  }
+  span.arg("items", list.len() as f64);
+  return list;
+}
+
+// 描く順に並べたボックスと、不透明度を掛ける範囲と変換する範囲の始まりと終わり。
+// PushTransform から PopTransform までのボックスの切り取る範囲は、変換する前の座標
+pub enum PaintStep<'b, 'a: 'b> {
+  Box(&'b LayoutBox<'a>, Option<Rect>), // ボックス自身（子孫は含まない）と、切り取る範囲
+  PushOpacity(f32),
+  PopOpacity,
+  PushTransform { matrix: Matrix, bounds: Rect, clip: Rect }, // DisplayCommand::PushTransform と同じ
+  PopTransform,
+}
+
+// layout_root のボックスを描く順（重ね合わせの順）に並べる。ヒットテスト（layout::hit_test）はこれを後ろから見る
+pub fn paint_order<'b, 'a>(layout_root: &'b LayoutBox<'a>) -> Vec<PaintStep<'b, 'a>> {
+  let mut steps = Vec::new();
+  push_stacking_context(&mut steps, layout_root, None);
+  return steps;
+}
+
+// 重ね合わせコンテキスト（ルートと、z-index が auto でない position が static でないボックスが作る）を並べる。
+// 自身の背景とボーダーの上に、z-index が負のコンテキスト、流れの中身、z-index が auto か 0 のボックス、
+// z-index が正のコンテキストの順に重ねる。z-index が同じならドキュメント順。
+// clip は祖先の overflow で切り取る範囲（なければ None）。overflow が visible でないボックスの子孫は、そのパディングボックスの中だけに描く。
+// opacity が 1 より小さいボックスもコンテキストを作り、その中身をまとめて不透明度を掛けて重ねる。
+// transform のあるボックスもコンテキストを作り、その中身を変換して重ねる。中身は変換する前の座標で描くので、
+// 祖先の overflow の範囲は変換した後に当てて、中では自身の overflow だけで切り取る
+fn push_stacking_context<'b, 'a>(steps: &mut Vec<PaintStep<'b, 'a>>, layout_box: &'b LayoutBox<'a>, clip: Option<Rect>) {
+  let opacity = opacity(layout_box);
+  if opacity < 1.0 {
+    steps.push(PaintStep::PushOpacity(opacity));
+  }
+  let transform = layout_box.transform();
+  let outer_clip = clip;
+  let clip = match transform {
+    Some(matrix) => {
+      let bounds = content_bounds(layout_box);
+      let screen = transformed_rect(&matrix, bounds);
+      let clip = narrow_clip(outer_clip, Some(screen)).unwrap_or(screen);
+      steps.push(PaintStep::PushTransform { matrix: matrix, bounds: bounds, clip: clip });
+      None
+    }
+    None => clip,
+  };
+  let inner = narrow_clip(clip, layout_box.overflow_clip());
+  let mut positioned = Vec::new();
+  collect_positioned(layout_box, inner, inner, &mut positioned);
+  let mut negative: Vec<(i32, &LayoutBox, Option<Rect>)> = positioned.iter().filter(|&&(z, _, _)| z.map_or(false, |z| z < 0)).map(|&(z, child, clip)| (z.unwrap(), child, clip)).collect();
+  let mut positive: Vec<(i32, &LayoutBox, Option<Rect>)> = positioned.iter().filter(|&&(z, _, _)| z.map_or(false, |z| z > 0)).map(|&(z, child, clip)| (z.unwrap(), child, clip)).collect();
+  negative.sort_by_key(|&(z, _, _)| z);
+  positive.sort_by_key(|&(z, _, _)| z);
+
+  steps.push(PaintStep::Box(layout_box, clip));
+  for (_, child, clip) in negative {
+    push_stacking_context(steps, child, clip);
+  }
+  for child in &layout_box.children {
+    if child.position == Position::Static {
+      push_layout_box(steps, child, inner);
+    }
+  }
+  for &(z, child, clip) in &positioned {
+    match z {
+      None => push_layout_box(steps, child, clip),
+      Some(0) => push_stacking_context(steps, child, clip),
+      Some(_) => {}
+    }
+  }
+  for (_, child, clip) in positive {
+    push_stacking_context(steps, child, clip);
+  }
+  if transform.is_some() {
+    steps.push(PaintStep::PopTransform);
+  }
+  if opacity < 1.0 {
+    steps.push(PaintStep::PopOpacity);
+  }
+}
+
+// 中身をまとめて重ねるボックス（opacity が 1 より小さいか transform がある）。自身の重ね合わせコンテキストで描く
+fn is_group(layout_box: &LayoutBox) -> bool {
+  return opacity(layout_box) < 1.0 || layout_box.transform().is_some();
+}
+
+// ボックスと子孫のボーダーボックスをすべて含む矩形（変換する中身の範囲）
+fn content_bounds(layout_box: &LayoutBox) -> Rect {
+  return layout_box.children.iter().fold(layout_box.dimensions.border_box(), |bounds, child| bounds.union(content_bounds(child)));
+}
+
+// rect の 4 つの角を matrix で移したものを含む矩形
+pub fn transformed_rect(matrix: &Matrix, rect: Rect) -> Rect {
+  let corners = [(rect.x, rect.y), (rect.x + rect.width, rect.y), (rect.x, rect.y + rect.height), (rect.x + rect.width, rect.y + rect.height)];
+  let points: Vec<(f32, f32)> = corners.iter().map(|&(x, y)| matrix.apply(x, y)).collect();
+  let (x0, y0) = points.iter().fold((f32::INFINITY, f32::INFINITY), |(x0, y0), &(x, y)| (x0.min(x), y0.min(y)));
+  let (x1, y1) = points.iter().fold((f32::NEG_INFINITY, f32::NEG_INFINITY), |(x1, y1), &(x, y)| (x1.max(x), y1.max(y)));
+  return Rect { x: x0, y: y0, width: x1 - x0, height: y1 - y0 };
+}
+
+// opacity の値（0 から 1）。なければ 1
+fn opacity(layout_box: &LayoutBox) -> f32 {
+  let opacity = match layout_box.box_type.style_node().and_then(|style| style.value("opacity")) {
+    Some(Value::Number(opacity)) => opacity,
+    Some(Value::Length(percent, Unit::Percent)) => percent / 100.0,
+    _ => 1.0,
+  };
+  return opacity.max(0.0).min(1.0);
+}
+
+// コンテキストの中で、流れとは別に重ねる子孫（position が static でないもの）とその z-index、切り取る範囲をドキュメント順に集める。
+// z-index が auto のものはコンテキストを作らないので、その中も同じコンテキストに集める。
+// clip は流れの中の子孫を、absolute_clip は包含ブロック（いちばん近い position が static でない祖先）の中を切り取る範囲。
+// absolute のものは包含ブロックの外の祖先の overflow では切り取らない。fixed のものはビューポートが包含ブロックなので切り取らない
+fn collect_positioned<'b, 'a>(layout_box: &'b LayoutBox<'a>, clip: Option<Rect>, absolute_clip: Option<Rect>, positioned: &mut Vec<(Option<i32>, &'b LayoutBox<'a>, Option<Rect>)>) {
+  for child in &layout_box.children {
+    if child.position == Position::Static {
+      // opacity か transform のあるボックスの中はそのコンテキストで描く
+      if !is_group(child) {
+        collect_positioned(child, narrow_clip(clip, child.overflow_clip()), absolute_clip, positioned);
+      }
+      continue;
+    }
+    let own_clip = match child.position {
+      Position::Absolute => absolute_clip,
+      Position::Fixed => None,
+      _ => clip,
+    };
+    let z_index = child.box_type.style_node().and_then(|style| style.z_index());
+    positioned.push((z_index, child, own_clip));
+    if z_index.is_none() && !is_group(child) {
+      let inner = narrow_clip(own_clip, child.overflow_clip());
+      collect_positioned(child, inner, inner, positioned);
+    }
+  }
+}
+
+// clip の中をさらに overflow_clip の中に狭める。重ならなければ何も描かない大きさ 0 の範囲
+fn narrow_clip(clip: Option<Rect>, overflow_clip: Option<Rect>) -> Option<Rect> {
+  return match (clip, overflow_clip) {
+    (Some(clip), Some(own)) => Some(clip.intersection(own).unwrap_or(Rect { width: 0.0, height: 0.0, ..own })),
+    (clip, None) => clip,
+    (None, own) => own,
+  };
+}
+
+// layout_box と、その中の流れの子孫を並べる（position が static でない子孫は collect_positioned で集めて別に並べる）
+fn push_layout_box<'b, 'a>(steps: &mut Vec<PaintStep<'b, 'a>>, layout_box: &'b LayoutBox<'a>, clip: Option<Rect>) {
+  if is_group(layout_box) {
+    push_stacking_context(steps, layout_box, clip);
+    return;
+  }
+  steps.push(PaintStep::Box(layout_box, clip));
+  let inner = narrow_clip(clip, layout_box.overflow_clip());
+  for child in &layout_box.children {
+    if child.position == Position::Static {
+      push_layout_box(steps, child, inner);
+    }
+  }
+}
+
+// ボックス自身の背景、ボーダー、中身。clip があればその中だけにする。
+// visibility: hidden のボックスは何も描かない（行の中のテキストは断片ごとに決める）
+fn render_box(list: &mut DisplayList, layout_box: &LayoutBox, clip: Option<Rect>, contents: &mut dyn FnMut(&LayoutBox) -> Option<DisplayList>) {
+  let start = list.len();
+  if layout_box.visible {
+    render_background(list, layout_box);
+    render_borders(list, layout_box);
+    list.extend(form::display_list(layout_box));
+    render_image(list, layout_box);
+  }
+  render_text(list, layout_box);
+  if layout_box.visible {
+    if let Some(items) = contents(layout_box) {
+      list.extend(items);
+    }
+  }
+  if let Some(clip) = clip {
+    let items = clip_display_list(list.drain(start..), clip);
+    list.extend(items);
+  }
+}
+
+// 背景の色の上に、背景の画像を画像の大きさのままパディングボックスの左上から敷きつめる。border-radius があれば背景の色は角を丸める
+fn render_background(list: &mut DisplayList, layout_box: &LayoutBox) {
+  let border_box = layout_box.dimensions.border_box();
+  let radii = border_radii(layout_box);
+  get_color(layout_box, "background-color").map(|color| {
+    if radii == [0.0; 4] {
+      list.push(DisplayCommand::SolidColor(
+        color,
+        border_box,
+      ))
+    } else {
+      list.push(DisplayCommand::RoundedRect { color: color, rect: border_box, radii: radii, clip: border_box })
+    }
+  });
+  // linear-gradient() はボーダーボックスいっぱいに描く
+  if let Some(Value::Gradient(gradient)) = layout_box.box_type.style_node().and_then(|style| style.value("background-image")) {
+    let (dx, dy) = (gradient.angle.to_radians().sin(), gradient.angle.to_radians().cos());
+    let length = (border_box.width * dx).abs() + (border_box.height * dy).abs();
+    let stops = resolve_stops(&gradient.stops, length);
+    list.push(DisplayCommand::Gradient { rect: border_box, angle: gradient.angle, stops: stops, clip: border_box });
+  }
+  if let Some(ref image) = layout_box.background_image {
+    render_background_image(list, layout_box, image);
+  }
+}
+
+// 背景の画像をパディングボックスの中に置く。background-size で 1 枚の大きさを、background-position で位置を決め、
+// background-repeat の向きに敷きつめる（repeat-x と repeat-y は敷きつめる範囲を 1 行か 1 列に狭める）
+fn render_background_image(list: &mut DisplayList, layout_box: &LayoutBox, image: &Arc<Image>) {
+  let style = match layout_box.box_type.style_node() {
+    Some(style) => style,
+    None => return,
+  };
+  let area = layout_box.dimensions.padding_box();
+  let (width, height) = background_size(style.value("background-size"), image, area);
+  if width <= 0.0 || height <= 0.0 {
+    return;
+  }
+  let (x, y) = background_position(style.value("background-position"), area, width, height);
+  let (repeat_x, repeat_y) = background_repeat(style.value("background-repeat"));
+  let tile = Rect { x: x, y: y, width: width, height: height };
+  let band = Rect {
+    x: if repeat_x { area.x } else { x },
+    y: if repeat_y { area.y } else { y },
+    width: if repeat_x { area.width } else { width },
+    height: if repeat_y { area.height } else { height },
+  };
+  if let Some(clip) = band.intersection(area) {
+    list.push(DisplayCommand::Image { image: image.clone(), rect: tile, repeat: repeat_x || repeat_y, clip: clip });
+  }
+}
+
+// background-size の 1 枚の幅と高さ。auto は画像の大きさ、片方だけ auto なら縦横比を保つ。
+// % はパディングボックスに対する割合で、cover は area を覆う最小、contain は area に収まる最大に縦横比を保って伸ばす
+fn background_size(value: Option<Value>, image: &Image, area: Rect) -> (f32, f32) {
+  let (natural_width, natural_height) = (image.width as f32, image.height as f32);
+  let resolve = |value: &Value, length: f32| match *value {
+    Value::Length(px, Unit::Px) => Some(px),
+    Value::Length(percent, Unit::Percent) => Some(length * percent / 100.0),
+    _ => None, // auto
+  };
+  let (width, height) = match value {
+    Some(Value::Keyword(ref keyword)) if (keyword == "cover" || keyword == "contain") && natural_width > 0.0 && natural_height > 0.0 => {
+      let (sx, sy) = (area.width / natural_width, area.height / natural_height);
+      let scale = if keyword == "cover" { sx.max(sy) } else { sx.min(sy) };
+      return (natural_width * scale, natural_height * scale);
+    }
+    Some(Value::List(ref values)) if values.len() == 2 => (resolve(&values[0], area.width), resolve(&values[1], area.height)),
+    Some(ref value) => (resolve(value, area.width), None),
+    None => (None, None),
+  };
+  return match (width, height) {
+    (Some(width), Some(height)) => (width, height),
+    (Some(width), None) if natural_width > 0.0 => (width, width * natural_height / natural_width),
+    (None, Some(height)) if natural_height > 0.0 => (height * natural_width / natural_height, height),
+    (Some(width), None) => (width, natural_height),
+    (None, Some(height)) => (natural_width, height),
+    (None, None) => (natural_width, natural_height),
+  };
+}
+
+// background-position の 1 枚目の左上。キーワード（left、center、right、top、bottom）と % は area の残りに対する割合、
+// 長さは area の左上からのずらし。1 つだけならもう片方は center。
+// 4 つ（right 10px bottom 5px）なら、キーワードの辺からのずらし
+fn background_position(value: Option<Value>, area: Rect, width: f32, height: f32) -> (f32, f32) {
+  let values = match value {
+    Some(Value::List(values)) => values,
+    Some(value) => vec![value],
+    None => Vec::new(),
+  };
+  let (space_x, space_y) = (area.width - width, area.height - height);
+  if let [Value::Keyword(ref first), Value::Length(first_offset, Unit::Px), Value::Keyword(ref second), Value::Length(second_offset, Unit::Px)] = *values {
+    let (x, y) = if first == "top" || first == "bottom" { ((second, second_offset), (first, first_offset)) } else { ((first, first_offset), (second, second_offset)) };
+    let x = if x.0 == "right" { space_x - x.1 } else { x.1 };
+    let y = if y.0 == "bottom" { space_y - y.1 } else { y.1 };
+    return (area.x + x, area.y + y);
+  }
+  let center = Value::Keyword("center".to_string());
+  let is_vertical = |value: &Value| match *value {
+    Value::Keyword(ref keyword) => keyword == "top" || keyword == "bottom",
+    _ => false,
+  };
+  let is_horizontal = |value: &Value| match *value {
+    Value::Keyword(ref keyword) => keyword == "left" || keyword == "right",
+    _ => false,
+  };
+  let (horizontal, vertical) = match values.len() {
+    0 => (Value::Length(0.0, Unit::Percent), Value::Length(0.0, Unit::Percent)),
+    1 if is_vertical(&values[0]) => (center, values[0].clone()),
+    1 => (values[0].clone(), center),
+    _ if is_vertical(&values[0]) || is_horizontal(&values[1]) => (values[1].clone(), values[0].clone()),
+    _ => (values[0].clone(), values[1].clone()),
+  };
+  let offset = |value: &Value, space: f32| match *value {
+    Value::Length(px, Unit::Px) => px,
+    Value::Length(percent, Unit::Percent) => space * percent / 100.0,
+    Value::Keyword(ref keyword) if keyword == "center" => space / 2.0,
+    Value::Keyword(ref keyword) if keyword == "right" || keyword == "bottom" => space,
+    _ => 0.0, // left と top
+  };
+  return (area.x + offset(&horizontal, space_x), area.y + offset(&vertical, space_y));
+}
+
+// background-repeat で横と縦に敷きつめるか。space と round は repeat と同じに敷きつめる
+fn background_repeat(value: Option<Value>) -> (bool, bool) {
+  let repeats = |value: &Value| match *value {
+    Value::Keyword(ref keyword) => keyword != "no-repeat",
+    _ => true,
+  };
+  return match value {
+    Some(Value::List(ref values)) if values.len() == 2 => (repeats(&values[0]), repeats(&values[1])),
+    Some(Value::Keyword(ref keyword)) if keyword == "repeat-x" => (true, false),
+    Some(Value::Keyword(ref keyword)) if keyword == "repeat-y" => (false, true),
+    Some(ref value) => (repeats(value), repeats(value)),
+    None => (true, true),
+  };
+}
+
+// 色の位置を、長さ length のグラデーションの線の上の 0 から 1 の位置にする。
+// 最初と最後の位置がなければ 0 と 1、途中のものは前後の位置の間に均等に置き、前の位置より前にはしない
+fn resolve_stops(stops: &[ColorStop], length: f32) -> Vec<(Color, f32)> {
+  let mut positions: Vec<Option<f32>> = stops
+    .iter()
+    .map(|stop| match stop.position {
+      Some(Value::Length(percent, Unit::Percent)) => Some(percent / 100.0),
+      Some(Value::Length(px, Unit::Px)) if length > 0.0 => Some(px / length),
+      _ => None,
+    })
+    .collect();
+  let last = positions.len() - 1;
+  positions[0] = positions[0].or(Some(0.0));
+  positions[last] = positions[last].or(Some(1.0));
+  let mut previous = 0;
+  let mut max = positions[0].unwrap();
+  for i in 1..positions.len() {
+    let position = match positions[i] {
+      Some(position) => position.max(max),
+      None => continue,
+    };
+    // previous と i の間の位置のないものを均等に置く
+    for j in previous + 1..i {
+      positions[j] = Some(max + (position - max) * (j - previous) as f32 / (i - previous) as f32);
+    }
+    positions[i] = Some(position);
+    previous = i;
+    max = position;
+  }
+  return stops.iter().zip(positions).map(|(stop, position)| (stop.color, position.unwrap())).collect();
+}
+
+// <img> の画像をコンテンツボックスに合わせて描く
+fn render_image(list: &mut DisplayList, layout_box: &LayoutBox) {
+  if let Some(ref image) = layout_box.image {
+    let content = layout_box.dimensions.content;
+    list.push(DisplayCommand::Image { image: image.clone(), rect: content, repeat: false, clip: content });
+  }
+}
+
+// レイアウトで行に分けたテキストと content の文字列（<img> の画像の場所と visibility: hidden の文字列は飛ばす）。
+// text-decoration の線は断片の幅の矩形にして、underline と overline は文字の下に、line-through は文字の上に描く
+fn render_text(list: &mut DisplayList, layout_box: &LayoutBox) {
+  for fragment in layout_box.fragments.iter().filter(|fragment| fragment.visible && !fragment.text.is_empty()) {
+    let rect = fragment.rect;
+    let line = |decoration: &TextDecoration| DisplayCommand::SolidColor(decoration.color, Rect { x: rect.x, y: rect.y + decoration.offset, width: rect.width, height: decoration.thickness });
+    let (through, under): (Vec<&TextDecoration>, Vec<&TextDecoration>) = fragment.decorations.iter().partition(|decoration| decoration.line == DecorationLine::LineThrough);
+    list.extend(under.into_iter().map(&line));
+    list.push(DisplayCommand::Text { text: fragment.text.clone(), glyphs: fragment.glyphs.clone(), color: fragment.color, x: rect.x, y: rect.y, font_size: fragment.font_size, clip: rect });
+    list.extend(through.into_iter().map(&line));
+  }
+}
+
+fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
+  match layout_box.box_type {
+    BlockNode(style) | InlineNode(style) | InlineBlockNode(style) | ReplacedNode(style) => match style.value(name) {
+      Some(Value::ColorValue(color)) => Some(color),
+      _ => None,
+    },
+    AnonymousBlock => None,
+  }
+}
+
+// 角の半径（左上、右上、右下、左下）。% はボーダーボックスの短い辺に対する割合。
+// 隣り合う角の半径の和が辺より長ければ、すべての角を同じ割合で小さくする
+fn border_radii(layout_box: &LayoutBox) -> [f32; 4] {
+  let style = match layout_box.box_type {
+    BlockNode(style) | InlineNode(style) | InlineBlockNode(style) | ReplacedNode(style) => style,
+    AnonymousBlock => return [0.0; 4],
+  };
+  let rect = layout_box.dimensions.border_box();
+  let zero = Value::Length(0.0, Unit::Px);
+  let radii = ["top-left", "top-right", "bottom-right", "bottom-left"].map(|corner| match style.lookup(&format!("border-{}-radius", corner), "border-radius", &zero) {
+    Value::Length(length, Unit::Px) => length.max(0.0),
+    Value::Length(percent, Unit::Percent) => (rect.width.min(rect.height) * percent / 100.0).max(0.0),
+    _ => 0.0,
+  });
+  let fit = |length: f32, a: f32, b: f32| if a + b > length { length / (a + b) } else { 1.0 };
+  let scale = fit(rect.width, radii[0], radii[1]).min(fit(rect.height, radii[1], radii[2])).min(fit(rect.width, radii[2], radii[3])).min(fit(rect.height, radii[3], radii[0]));
+  return radii.map(|radius| radius * scale);
+}
+
+fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
+  let style = match layout_box.box_type {
+    BlockNode(style) | InlineNode(style) | InlineBlockNode(style) | ReplacedNode(style) => style,
+    AnonymousBlock => return,
+  };
+  // 辺の色を書かなければ border-color、それもなければ currentcolor（color の値）。どの辺も色がなければ描かない
+  let color = |side: &str| get_color(layout_box, &format!("border-{}-color", side)).or_else(|| get_color(layout_box, "border-color")).or_else(|| get_color(layout_box, "color"));
+  let colors = match (color("left"), color("right"), color("top"), color("bottom")) {
+    (Some(left), Some(right), Some(top), Some(bottom)) => [left, right, top, bottom],
+    _ => return,
+  };
+
+  let d = &layout_box.dimensions;
+  let border_box = d.border_box();
+  let styles = ["left", "right", "top", "bottom"].map(|side| style.border_style(side));
+  list.push(DisplayCommand::Border { rect: border_box, widths: d.border, colors: colors, styles: styles, radii: border_radii(layout_box), clip: border_box });
+}
@@ -0,0 +1,770 @@
+use css::{Color, Matrix};
+use font::{self, GlyphRun};
+use images::Image;
+use layout::{EdgeSizes, LayoutBox, Rect};
+use parallel;
+use std::io::{self, Write};
+use style::BorderStyle;
+use text;
+use trace;
+use super::display_list::{build_display_list, transformed_rect, DisplayCommand, DisplayList};
+use super::{PaintError, RenderOptions};
+
+/**
+ * ディスプレイリストをキャンバス（ピクセルの並び）に描く。
+ *
+ * Canvas は DisplayCommand を 1 つずつ描き、PushOpacity と PushTransform では今の面を退けて新しい面に描く。
+ * paint_display_list_tiled はタイルに分けて、タイルごとのキャンバスを別々に（rayon フィーチャーなら同時に）描いてからつなげる
+ */
+
+// キャンバスの一辺の上限（これを超えるとメモリを確保しきれない）
+const MAX_CANVAS_SIZE: f32 = 32768.0;
+
+// チェックボックスとラジオボタンの色（ネイティブの見た目に近いもの）
+pub const CONTROL_BORDER: Color = Color { r: 0x76, g: 0x76, b: 0x76, a: 255 };
+pub const CONTROL_ACCENT: Color = Color { r: 0x00, g: 0x75, b: 0xff, a: 255 };
+pub const CONTROL_BACKGROUND: Color = Color { r: 255, g: 255, b: 255, a: 255 };
+
+// 透明な色（opacity と transform の中間の面の初期値）
+const TRANSPARENT: Color = Color { r: 0, g: 0, b: 0, a: 0 };
+
+// transform の中間の面の一辺の上限（scale(0.01) のように大きく縮めるときに、面を作りきれないので）
+const MAX_LAYER_SIZE: f32 = 8192.0;
+
+#[derive(Clone)]
+pub struct Canvas {
+  pub pixels: Vec<Color>,
+  pub width: usize,
+  pub height: usize,
+  background: Color, // 何も描いていないピクセルの色（RenderOptions::background）
+  pixel_snap: bool,  // repaint で描くコマンドの辺をピクセルの境目に合わせる（RenderOptions::pixel_snap）
+  pub(super) origin: (usize, usize), // 左上のピクセルの、描く画像全体での位置（タイルのキャンバスでなければ (0, 0)）
+  layers: Vec<Layer>, // PushOpacity と PushTransform で退けた下の面
+}
+
+// 退けた下の面と、PopOpacity か PopTransform でその上に今の面を重ねるときの効果
+#[derive(Clone)]
+struct Layer {
+  pixels: Vec<Color>,
+  width: usize,
+  height: usize,
+  origin: (usize, usize),
+  effect: Effect,
+}
+
+#[derive(Clone, Copy)]
+enum Effect {
+  Opacity(f32),
+  Transform(Matrix, Rect), // 今の面を変換して、下の面の座標の clip の中に重ねる
+}
+
+impl Canvas {
+  pub fn new(width: usize, height: usize) -> Canvas {
+    let white = Color {
+      r: 255,
+      g: 255,
+      b: 255,
+      a: 255,
+    };
+    return Canvas::filled(width, height, white);
+  }
+
+  // background で塗ったキャンバス
+  pub fn filled(width: usize, height: usize, background: Color) -> Canvas {
+    return Canvas {
+      pixels: vec![background; width * height],
+      width,
+      height,
+      background: background,
+      pixel_snap: false,
+      origin: (0, 0),
+      layers: Vec::new(),
+    };
+  }
+
+  // 画像全体のうち tile の範囲だけのキャンバス。コマンドは画像全体の座標のまま描ける
+  fn tile(tile: Rect, background: Color) -> Canvas {
+    let mut canvas = Canvas::filled(tile.width as usize, tile.height as usize, background);
+    canvas.origin = (tile.x as usize, tile.y as usize);
+    return canvas;
+  }
+
+  // キャンバスが覆う範囲
+  fn bounds(&self) -> Rect {
+    return Rect { x: self.origin.0 as f32, y: self.origin.1 as f32, width: self.width as f32, height: self.height as f32 };
+  }
+
+  // display_list（キャンバスの座標）を描いたときと同じになるように、damage の範囲だけを描き直す。
+  // 範囲はピクセルの境目まで広げて、キャンバスの中だけにする。描き直す面積がキャンバスの半分を超えるなら全体を描き直す。
+  // 背景の色と辺を合わせるかどうかは、このキャンバスを描いたときのまま
+  pub fn repaint(&mut self, display_list: &[DisplayCommand], damage: &[Rect]) {
+    let snapped: DisplayList;
+    let display_list = if self.pixel_snap {
+      snapped = display_list.iter().map(|item| item.snapped()).collect();
+      &snapped[..]
+    } else {
+      display_list
+    };
+    let bounds = self.bounds();
+    let regions: Vec<Rect> = damage
+      .iter()
+      .filter_map(|rect| {
+        let (x, y) = (rect.x.floor(), rect.y.floor());
+        let snapped = Rect { x: x, y: y, width: (rect.x + rect.width).ceil() - x, height: (rect.y + rect.height).ceil() - y };
+        return snapped.intersection(bounds);
+      })
+      .collect();
+    let area: f32 = regions.iter().map(|region| region.width * region.height).sum();
+    if area * 2.0 > bounds.width * bounds.height {
+      self.pixels = paint_tile(display_list, bounds, self.background).pixels;
+      return;
+    }
+    for region in regions {
+      let painted = paint_tile(display_list, region, self.background);
+      for (row, pixels) in painted.pixels.chunks(painted.width).enumerate() {
+        let start = self.index(painted.origin.0, painted.origin.1 + row);
+        self.pixels[start..start + pixels.len()].copy_from_slice(pixels);
+      }
+    }
+  }
+
+  // 描いてあるピクセルを右に dx、下に dy ずらす。返すのは、ずらして空いた（背景の色にした）範囲
+  pub(super) fn shift(&mut self, dx: isize, dy: isize) -> Vec<Rect> {
+    let (width, height) = (self.width as isize, self.height as isize);
+    let mut pixels = vec![self.background; self.pixels.len()];
+    let (from, to) = ((-dx).max(0), (width - dx).min(width));
+    if from < to {
+      for y in dy.max(0)..(height + dy).min(height) {
+        let (source, target) = ((y - dy) * width, y * width + dx.max(0));
+        pixels[target as usize..(target + to - from) as usize].copy_from_slice(&self.pixels[(source + from) as usize..(source + to) as usize]);
+      }
+    }
+    self.pixels = pixels;
+    let bounds = self.bounds();
+    let mut exposed = Vec::new();
+    if dx != 0 {
+      let x = if dx > 0 { bounds.x } else { bounds.x + (width + dx) as f32 };
+      exposed.push(Rect { x: x, width: dx.abs() as f32, ..bounds });
+    }
+    if dy != 0 {
+      let y = if dy > 0 { bounds.y } else { bounds.y + (height + dy) as f32 };
+      exposed.push(Rect { y: y, height: dy.abs() as f32, ..bounds });
+    }
+    return exposed;
+  }
+
+  // ピクセル (x, y) の self.pixels での位置
+  fn index(&self, x: usize, y: usize) -> usize {
+    return (y - self.origin.1) * self.width + x - self.origin.0;
+  }
+  pub fn paint_item(&mut self, item: &DisplayCommand) {
+    match *item {
+      DisplayCommand::SolidColor(color, rect) => self.fill_rect(color, rect),
+      DisplayCommand::RoundedRect { color, rect, radii, clip } => self.fill_rounded(color, rect, radii, clip),
+      DisplayCommand::Border { rect, widths, colors, styles, radii, clip } => {
+        if radii == [0.0; 4] && styles.iter().all(|style| *style == BorderStyle::Solid) {
+          for (side, color) in border_sides(rect, widths).iter().zip(&colors) {
+            self.fill_clipped(*color, *side, clip);
+          }
+        } else {
+          self.paint_border(rect, widths, colors, styles, radii, clip);
+        }
+      }
+      DisplayCommand::Text { ref text, ref glyphs, color, x, y, font_size, clip } => match *glyphs {
+        Some(ref run) => self.paint_glyphs(run, color, x, y, font_size, clip),
+        None => self.paint_text(text, color, x, y, font_size, clip),
+      },
+      DisplayCommand::Checkbox { rect, checked, clip } => self.paint_checkbox(rect, checked, clip),
+      DisplayCommand::Radio { rect, checked, clip } => self.paint_radio(rect, checked, clip),
+      DisplayCommand::Image { ref image, rect, repeat, clip } => self.paint_image(image, rect, repeat, clip),
+      DisplayCommand::Gradient { rect, angle, ref stops, clip } => self.paint_gradient(rect, angle, stops, clip),
+      DisplayCommand::PushOpacity(opacity) => {
+        // 透明な面に描いていき、PopOpacity で下の面に重ねる
+        let bounds = self.bounds();
+        self.push_layer(bounds, Effect::Opacity(opacity));
+      }
+      DisplayCommand::PushTransform { matrix, bounds, clip } => {
+        // 変換する前の座標のまま、下の面の clip に写る範囲（bounds の中だけ）を透明な面に描いていき、PopTransform で変換して重ねる
+        // 補間でまわりのピクセルも読むので、1 ピクセル広げる
+        let area = clip
+          .intersection(self.bounds())
+          .and_then(|area| Some(transformed_rect(&matrix.invert()?, area)))
+          .and_then(|area| Rect { x: area.x - 1.0, y: area.y - 1.0, width: area.width + 2.0, height: area.height + 2.0 }.intersection(bounds));
+        let layer = match area {
+          Some(area) => {
+            let (x, y) = (area.x.max(0.0).floor(), area.y.max(0.0).floor());
+            let width = ((area.x + area.width).ceil() - x).max(0.0).min(MAX_LAYER_SIZE);
+            let height = ((area.y + area.height).ceil() - y).max(0.0).min(MAX_LAYER_SIZE);
+            Rect { x: x, y: y, width: width, height: height }
+          }
+          None => Rect { x: 0.0, y: 0.0, width: 0.0, height: 0.0 }, // 何も写らない
+        };
+        self.push_layer(layer, Effect::Transform(matrix, clip));
+      }
+      DisplayCommand::PopOpacity | DisplayCommand::PopTransform => self.pop_layer(),
+    }
+  }
+
+  // 今の面を退けて、bounds の範囲の透明な面にする
+  fn push_layer(&mut self, bounds: Rect, effect: Effect) {
+    let (width, height) = (bounds.width as usize, bounds.height as usize);
+    let below = Layer {
+      pixels: std::mem::replace(&mut self.pixels, vec![TRANSPARENT; width * height]),
+      width: std::mem::replace(&mut self.width, width),
+      height: std::mem::replace(&mut self.height, height),
+      origin: std::mem::replace(&mut self.origin, (bounds.x as usize, bounds.y as usize)),
+      effect: effect,
+    };
+    self.layers.push(below);
+  }
+
+  // 退けた下の面に戻して、その上に今の面を重ねる
+  fn pop_layer(&mut self) {
+    let below = match self.layers.pop() {
+      Some(below) => below,
+      None => return,
+    };
+    let layer = Canvas { pixels: std::mem::replace(&mut self.pixels, below.pixels), width: self.width, height: self.height, background: TRANSPARENT, pixel_snap: self.pixel_snap, origin: self.origin, layers: Vec::new() };
+    self.width = below.width;
+    self.height = below.height;
+    self.origin = below.origin;
+    match below.effect {
+      Effect::Opacity(opacity) => {
+        for (pixel, color) in self.pixels.iter_mut().zip(layer.pixels) {
+          *pixel = blend(*pixel, color, opacity);
+        }
+      }
+      Effect::Transform(matrix, clip) => {
+        // 下の面のピクセルの中心を逆に変換した位置の色を、まわりの 4 つのピクセルから補間して取る（バイリニア）
+        let inverse = match matrix.invert() {
+          Some(inverse) => inverse,
+          None => return,
+        };
+        let area = match clip.intersection(self.bounds()).and_then(|area| area.intersection(transformed_rect(&matrix, layer.bounds()))) {
+          Some(area) => area,
+          None => return,
+        };
+        for y in area.y as usize..(area.y + area.height).ceil() as usize {
+          for x in area.x as usize..(area.x + area.width).ceil() as usize {
+            let (sx, sy) = inverse.apply(x as f32 + 0.5, y as f32 + 0.5);
+            let color = layer.sample(sx, sy);
+            if color.a > 0 {
+              let index = self.index(x, y);
+              self.pixels[index] = blend(self.pixels[index], color, 1.0);
+            }
+          }
+        }
+      }
+    }
+  }
+
+  // 点 (x, y) の色。まわりの 4 つのピクセルの中心からの距離で、不透明度を掛けた色を補間する（面の外は透明）
+  fn sample(&self, x: f32, y: f32) -> Color {
+    let (fx, fy) = (x - 0.5 - self.origin.0 as f32, y - 0.5 - self.origin.1 as f32);
+    let (x0, y0) = (fx.floor(), fy.floor());
+    let (tx, ty) = (fx - x0, fy - y0);
+    let mut sum = [0.0f32; 4];
+    for &(dx, dy, weight) in &[(0, 0, (1.0 - tx) * (1.0 - ty)), (1, 0, tx * (1.0 - ty)), (0, 1, (1.0 - tx) * ty), (1, 1, tx * ty)] {
+      let (px, py) = (x0 as i64 + dx, y0 as i64 + dy);
+      if weight <= 0.0 || px < 0 || py < 0 || px >= self.width as i64 || py >= self.height as i64 {
+        continue;
+      }
+      let color = self.pixels[py as usize * self.width + px as usize];
+      let alpha = color.a as f32 * weight;
+      sum[0] += color.r as f32 * alpha;
+      sum[1] += color.g as f32 * alpha;
+      sum[2] += color.b as f32 * alpha;
+      sum[3] += alpha;
+    }
+    if sum[3] <= 0.0 {
+      return TRANSPARENT;
+    }
+    let channel = |value: f32| (value / sum[3]).round().min(255.0) as u8;
+    return Color { r: channel(sum[0]), g: channel(sum[1]), b: channel(sum[2]), a: sum[3].round().min(255.0) as u8 };
+  }
+
+  fn fill_rect(&mut self, color: Color, rect: Rect) {
+    let bounds = self.bounds();
+    let x0 = rect.x.clamp(bounds.x, bounds.x + bounds.width) as usize;
+    let y0 = rect.y.clamp(bounds.y, bounds.y + bounds.height) as usize;
+    let x1 = (rect.x + rect.width).clamp(bounds.x, bounds.x + bounds.width) as usize;
+    let y1 = (rect.y + rect.height).clamp(bounds.y, bounds.y + bounds.height) as usize;
+
+    for y in y0..y1 {
+      for x in x0..x1 {
+        let index = self.index(x, y);
+        let pixel = &mut self.pixels[index];
+        // 不透明でなければ下の色と重ねる
+        *pixel = if color.a == 255 { color } else { blend(*pixel, color, 1.0) };
+      }
+    }
+  }
+
+  fn fill_clipped(&mut self, color: Color, rect: Rect, clip: Rect) {
+    if let Some(rect) = rect.intersection(clip) {
+      self.fill_rect(color, rect);
+    }
+  }
+
+  // 角を丸めた矩形。角のピクセルだけ 4x4 の点を取って、中にある割合で重ねる（アンチエイリアス）
+  fn fill_rounded(&mut self, color: Color, rect: Rect, radii: [f32; 4], clip: Rect) {
+    let area = match self.area(rect, clip) {
+      Some(area) => area,
+      None => return,
+    };
+    let largest = radii.iter().cloned().fold(0.0, f32::max);
+    for y in area.y as usize..(area.y + area.height).ceil() as usize {
+      for x in area.x as usize..(area.x + area.width).ceil() as usize {
+        let (fx, fy) = (x as f32, y as f32);
+        // 角から離れたピクセルはそのまま塗る
+        let inside = fx >= rect.x + largest && fx + 1.0 <= rect.x + rect.width - largest || fy >= rect.y + largest && fy + 1.0 <= rect.y + rect.height - largest;
+        let coverage = if inside { 1.0 } else { supersample(fx, fy, |sx, sy| in_rounded_rect(rect, radii, sx, sy)) };
+        if coverage > 0.0 {
+          let index = self.index(x, y);
+          self.pixels[index] = blend(self.pixels[index], color, coverage);
+        }
+      }
+    }
+  }
+
+  // 丸い角や破線、点線のボーダー。ピクセルごとに 4x4 の点を取って、外側の角丸矩形の中で内側の角丸矩形の外にある点を、
+  // 辺の太さに対していちばん近い辺の色で塗る。dashed は太さの 3 倍の長さの線を同じ間隔で、dotted は太さの直径の点を太さの 2 倍の間隔で置く
+  fn paint_border(&mut self, rect: Rect, widths: EdgeSizes, colors: [Color; 4], styles: [BorderStyle; 4], radii: [f32; 4], clip: Rect) {
+    let area = match self.area(rect, clip) {
+      Some(area) => area,
+      None => return,
+    };
+    let inner = Rect { x: rect.x + widths.left, y: rect.y + widths.top, width: rect.width - widths.left - widths.right, height: rect.height - widths.top - widths.bottom };
+    let inner_radii = [
+      (radii[0] - widths.left.max(widths.top)).max(0.0),
+      (radii[1] - widths.right.max(widths.top)).max(0.0),
+      (radii[2] - widths.right.max(widths.bottom)).max(0.0),
+      (radii[3] - widths.left.max(widths.bottom)).max(0.0),
+    ];
+    let largest = radii.iter().cloned().fold(0.0, f32::max);
+    let sides = [widths.left, widths.right, widths.top, widths.bottom];
+    for y in area.y as usize..(area.y + area.height).ceil() as usize {
+      for x in area.x as usize..(area.x + area.width).ceil() as usize {
+        let (fx, fy) = (x as f32, y as f32);
+        // 内側のボックスの奥のピクセルには何も描かない
+        if fx >= inner.x + largest && fx + 1.0 <= inner.x + inner.width - largest && fy >= inner.y + largest && fy + 1.0 <= inner.y + inner.height - largest {
+          continue;
+        }
+        let mut coverage = [0.0; 4];
+        for (sx, sy) in samples(fx, fy) {
+          if !in_rounded_rect(rect, radii, sx, sy) || (inner.width > 0.0 && inner.height > 0.0 && in_rounded_rect(inner, inner_radii, sx, sy)) {
+            continue;
+          }
+          // 左、右、上、下の辺からの距離（辺の太さに対する割合）がいちばん小さい辺
+          let distances = [sx - rect.x, rect.x + rect.width - sx, sy - rect.y, rect.y + rect.height - sy];
+          let side = (0..4).filter(|&i| sides[i] > 0.0).min_by(|&a, &b| (distances[a] / sides[a]).partial_cmp(&(distances[b] / sides[b])).unwrap());
+          let side = match side {
+            Some(side) => side,
+            None => continue,
+          };
+          let along = if side < 2 { sy - rect.y } else { sx - rect.x };
+          if styled_sample(styles[side], along, distances[side], sides[side]) {
+            coverage[side] += 1.0 / 16.0;
+          }
+        }
+        let index = self.index(x, y);
+        for side in 0..4 {
+          if coverage[side] > 0.0 {
+            self.pixels[index] = blend(self.pixels[index], colors[side], coverage[side]);
+          }
+        }
+      }
+    }
+  }
+
+  // rect と clip とキャンバスが重なるところ
+  fn area(&self, rect: Rect, clip: Rect) -> Option<Rect> {
+    return rect.intersection(clip).and_then(|area| area.intersection(self.bounds()));
+  }
+
+  fn paint_text(&mut self, text: &str, color: Color, x: f32, y: f32, font_size: f32, clip: Rect) {
+    for glyph in text_boxes(text, x, y, font_size) {
+      self.fill_clipped(color, glyph, clip);
+    }
+  }
+
+  // 画像を rect の大きさに拡大縮小して（いちばん近いピクセルを取って）重ねる。
+  // repeat なら rect を 1 枚分として、縦横に clip いっぱいまで敷きつめる
+  fn paint_image(&mut self, image: &Image, rect: Rect, repeat: bool, clip: Rect) {
+    if rect.width <= 0.0 || rect.height <= 0.0 {
+      return;
+    }
+    let canvas = self.bounds();
+    let area = match if repeat { Some(clip) } else { rect.intersection(clip) }.and_then(|area| area.intersection(canvas)) {
+      Some(area) => area,
+      None => return,
+    };
+    // 画像の中の位置（0 から 1）。敷きつめるなら 1 枚分ごとに繰り返す
+    let position = |offset: f32, length: f32| {
+      let t = offset / length;
+      if repeat { t - t.floor() } else { t }
+    };
+    for y in area.y as usize..(area.y + area.height) as usize {
+      let sy = (position(y as f32 + 0.5 - rect.y, rect.height) * image.height as f32) as usize;
+      for x in area.x as usize..(area.x + area.width) as usize {
+        let sx = (position(x as f32 + 0.5 - rect.x, rect.width) * image.width as f32) as usize;
+        let color = image.pixel(sx, sy);
+        let index = self.index(x, y);
+        let pixel = &mut self.pixels[index];
+        *pixel = if color.a == 255 { color } else { blend(*pixel, color, 1.0) };
+      }
+    }
+  }
+
+  // ピクセルの中心のグラデーションの線の上の位置の色を重ねる
+  fn paint_gradient(&mut self, rect: Rect, angle: f32, stops: &[(Color, f32)], clip: Rect) {
+    let area = match self.area(rect, clip) {
+      Some(area) => area,
+      None => return,
+    };
+    for y in area.y as usize..(area.y + area.height).ceil() as usize {
+      for x in area.x as usize..(area.x + area.width).ceil() as usize {
+        let color = gradient_color(stops, gradient_position(rect, angle, x as f32 + 0.5, y as f32 + 0.5));
+        let index = self.index(x, y);
+        self.pixels[index] = blend(self.pixels[index], color, 1.0);
+      }
+    }
+  }
+
+  // 1 ピクセル（を倍率に合わせた太さ）の枠。チェックされていれば塗りつぶして白いチェックの印
+  fn paint_checkbox(&mut self, rect: Rect, checked: bool, clip: Rect) {
+    let line = (rect.width / 13.0).max(1.0);
+    let (border, background) = if checked { (CONTROL_ACCENT, CONTROL_ACCENT) } else { (CONTROL_BORDER, CONTROL_BACKGROUND) };
+    self.fill_clipped(border, rect, clip);
+    self.fill_clipped(background, Rect { x: rect.x + line, y: rect.y + line, width: rect.width - line * 2.0, height: rect.height - line * 2.0 }, clip);
+    if checked {
+      // ✓ を 2 本の線分で描く（13px のときの座標を大きさに合わせる）
+      let point = |px: f32, py: f32| (rect.x + px * rect.width / 13.0, rect.y + py * rect.height / 13.0);
+      let (a, b, c) = (point(3.0, 6.5), point(5.5, 9.0), point(10.0, 4.0));
+      self.stroke_line(CONTROL_BACKGROUND, a, b, line * 1.5, clip);
+      self.stroke_line(CONTROL_BACKGROUND, b, c, line * 1.5, clip);
+    }
+  }
+
+  // 円の枠。チェックされていれば枠をアクセントの色にして、中に点を描く
+  fn paint_radio(&mut self, rect: Rect, checked: bool, clip: Rect) {
+    let line = (rect.width / 13.0).max(1.0);
+    let radius = rect.width.min(rect.height) / 2.0;
+    let (cx, cy) = (rect.x + rect.width / 2.0, rect.y + rect.height / 2.0);
+    let border = if checked { CONTROL_ACCENT } else { CONTROL_BORDER };
+    self.fill_circle(border, cx, cy, radius, clip);
+    self.fill_circle(CONTROL_BACKGROUND, cx, cy, radius - line, clip);
+    if checked {
+      self.fill_circle(CONTROL_ACCENT, cx, cy, radius * 0.5, clip);
+    }
+  }
+
+  // 字形のアウトラインを、被覆率を color の不透明度に掛けて重ねる。フォントにない文字は矩形
+  fn paint_glyphs(&mut self, run: &GlyphRun, color: Color, x: f32, y: f32, font_size: f32, clip: Rect) {
+    let area = match clip.intersection(self.bounds()) {
+      Some(area) => area,
+      None => return,
+    };
+    let mut missing = Vec::new();
+    font::rasterize(run, x, y, font_size, area, |px, py, coverage| {
+      let (fx, fy) = (px as f32 + 0.5, py as f32 + 0.5);
+      if fx < area.x || fy < area.y || fx >= area.x + area.width || fy >= area.y + area.height {
+        return;
+      }
+      let index = self.index(px as usize, py as usize);
+      self.pixels[index] = blend(self.pixels[index], color, coverage.min(1.0));
+    }, |gx, advance| missing.push((gx, advance)));
+    for (gx, advance) in missing {
+      self.fill_clipped(color, missing_glyph_box(gx, advance, y + run.baseline, font_size), clip);
+    }
+  }
+
+  // ピクセルの中心が円の中にあるところを塗る
+  fn fill_circle(&mut self, color: Color, cx: f32, cy: f32, radius: f32, clip: Rect) {
+    let bounds = Rect { x: cx - radius, y: cy - radius, width: radius * 2.0, height: radius * 2.0 };
+    let area = match bounds.intersection(clip).and_then(|area| area.intersection(self.bounds())) {
+      Some(area) => area,
+      None => return,
+    };
+    for y in area.y as usize..(area.y + area.height).ceil() as usize {
+      for x in area.x as usize..(area.x + area.width).ceil() as usize {
+        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        if dx * dx + dy * dy <= radius * radius && x < self.origin.0 + self.width && y < self.origin.1 + self.height {
+          let index = self.index(x, y);
+          self.pixels[index] = color;
+        }
+      }
+    }
+  }
+
+  // 太さ width の線分。点を少しずつずらして正方形を置く
+  fn stroke_line(&mut self, color: Color, from: (f32, f32), to: (f32, f32), width: f32, clip: Rect) {
+    let length = ((to.0 - from.0).powi(2) + (to.1 - from.1).powi(2)).sqrt();
+    let steps = (length * 2.0).ceil().max(1.0) as usize;
+    for step in 0..=steps {
+      let t = step as f32 / steps as f32;
+      let (x, y) = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+      self.fill_clipped(color, Rect { x: x - width / 2.0, y: y - width / 2.0, width: width, height: width }, clip);
+    }
+  }
+
+  // 1 ピクセル 4 バイト（R, G, B, A。アルファは乗算しない）の並びにする。wasm や FFI で外に渡すときや、
+  // ウィンドウのサーフェスや ffmpeg の rawvideo（rgba）にエンコードせずに渡すときに使う
+  pub fn to_rgba_bytes(&self) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(self.pixels.len() * 4);
+    for color in &self.pixels {
+      bytes.extend_from_slice(&[color.r, color.g, color.b, color.a]);
+    }
+    return bytes;
+  }
+
+  // バイナリの PPM（P6）で out に書く。アルファは捨てる
+  pub fn write_ppm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+    let mut bytes = Vec::with_capacity(self.pixels.len() * 3);
+    for color in &self.pixels {
+      bytes.extend_from_slice(&[color.r, color.g, color.b]);
+    }
+    return out.write_all(&bytes);
+  }
+}
+
+// ピクセル (x, y) の中に 4x4 に並べた点
+fn samples(x: f32, y: f32) -> Vec<(f32, f32)> {
+  return (0..16).map(|i| (x + ((i % 4) as f32 + 0.5) / 4.0, y + ((i / 4) as f32 + 0.5) / 4.0)).collect();
+}
+
+// ピクセル (x, y) の中の点のうち inside なものの割合
+fn supersample<F: Fn(f32, f32) -> bool>(x: f32, y: f32, inside: F) -> f32 {
+  return samples(x, y).into_iter().filter(|&(sx, sy)| inside(sx, sy)).count() as f32 / 16.0;
+}
+
+// 点 (x, y) が、角を radii（左上、右上、右下、左下）の半径で丸めた rect の中にあるか
+fn in_rounded_rect(rect: Rect, radii: [f32; 4], x: f32, y: f32) -> bool {
+  if x < rect.x || y < rect.y || x > rect.x + rect.width || y > rect.y + rect.height {
+    return false;
+  }
+  let corners = [
+    (rect.x + radii[0], rect.y + radii[0], radii[0]),
+    (rect.x + rect.width - radii[1], rect.y + radii[1], radii[1]),
+    (rect.x + rect.width - radii[2], rect.y + rect.height - radii[2], radii[2]),
+    (rect.x + radii[3], rect.y + rect.height - radii[3], radii[3]),
+  ];
+  for (i, &(cx, cy, radius)) in corners.iter().enumerate() {
+    // 角の円の中心より外側（その角の側）にある点だけ、円の中かを調べる
+    let outside_x = if i == 0 || i == 3 { x < cx } else { x > cx };
+    let outside_y = if i < 2 { y < cy } else { y > cy };
+    if radius > 0.0 && outside_x && outside_y && (x - cx).powi(2) + (y - cy).powi(2) > radius * radius {
+      return false;
+    }
+  }
+  return true;
+}
+
+// 辺の始まりから along、外側の縁から depth の点を、太さ width の style の線で塗るか
+fn styled_sample(style: BorderStyle, along: f32, depth: f32, width: f32) -> bool {
+  return match style {
+    BorderStyle::None => false,
+    BorderStyle::Solid => true,
+    BorderStyle::Dashed => (along / (width * 3.0)).floor() as i64 % 2 == 0,
+    BorderStyle::Dotted => {
+      let offset = along - ((along / (width * 2.0)).floor() + 0.5) * width * 2.0;
+      offset * offset + (depth - width / 2.0).powi(2) <= (width / 2.0).powi(2)
+    }
+  };
+}
+
+// dst の上に、src を不透明度 coverage × src.a で重ねる（source-over）。dst が透明なところは src の色がそのまま残る
+fn blend(dst: Color, src: Color, coverage: f32) -> Color {
+  let alpha = coverage * src.a as f32 / 255.0;
+  let below = dst.a as f32 / 255.0 * (1.0 - alpha);
+  let total = alpha + below;
+  if total <= 0.0 {
+    return TRANSPARENT;
+  }
+  let mix = |d: u8, s: u8| ((s as f32 * alpha + d as f32 * below) / total).round() as u8;
+  return Color { r: mix(dst.r, src.r), g: mix(dst.g, src.g), b: mix(dst.b, src.b), a: (total * 255.0).round() as u8 };
+}
+
+// rect の中の点 (x, y) の、グラデーションの線（rect の中心を通って angle の向きに、rect の角に届く長さ）の上の位置（0 から 1）
+fn gradient_position(rect: Rect, angle: f32, x: f32, y: f32) -> f32 {
+  let (dx, dy) = (angle.to_radians().sin(), -angle.to_radians().cos());
+  let length = (rect.width * dx).abs() + (rect.height * dy).abs();
+  if length <= 0.0 {
+    return 0.0;
+  }
+  let (cx, cy) = (rect.x + rect.width / 2.0, rect.y + rect.height / 2.0);
+  return ((x - cx) * dx + (y - cy) * dy) / length + 0.5;
+}
+
+// 線の上の位置 t の色。前後の色の間を線形に補間し、最初の色より前と最後の色より後はその色
+pub fn gradient_color(stops: &[(Color, f32)], t: f32) -> Color {
+  let next = match stops.iter().position(|&(_, position)| position > t) {
+    Some(0) => return stops[0].0,
+    Some(next) => next,
+    None => return stops.last().map_or(TRANSPARENT, |&(color, _)| color),
+  };
+  let ((from, start), (to, end)) = (stops[next - 1], stops[next]);
+  let ratio = (t - start) / (end - start);
+  let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * ratio).round() as u8;
+  return Color { r: mix(from.r, to.r), g: mix(from.g, to.g), b: mix(from.b, to.b), a: mix(from.a, to.a) };
+}
+
+// width x height の大きさのグラデーションの画像（SVG と PDF に埋め込む）
+pub fn gradient_image(width: usize, height: usize, angle: f32, stops: &[(Color, f32)]) -> Image {
+  let rect = Rect { x: 0.0, y: 0.0, width: width as f32, height: height as f32 };
+  let mut pixels = Vec::with_capacity(width * height);
+  for y in 0..height {
+    for x in 0..width {
+      pixels.push(gradient_color(stops, gradient_position(rect, angle, x as f32 + 0.5, y as f32 + 0.5)));
+    }
+  }
+  return Image { width: width, height: height, pixels: pixels };
+}
+
+// 字形がないときの文字の形。書記素クラスタごとに、その幅と x ハイトくらいの高さの矩形を置く（空白は空ける）
+pub fn text_boxes(text: &str, x: f32, y: f32, font_size: f32) -> Vec<Rect> {
+  let mut boxes = Vec::new();
+  let mut pen = x;
+  for grapheme in text::graphemes(text) {
+    let advance = text::monospace_width(grapheme, font_size);
+    if !grapheme.trim().is_empty() {
+      boxes.push(Rect { x: pen + advance * 0.15, y: y + font_size * 0.45, width: advance * 0.7, height: font_size * 0.55 });
+    }
+    pen += advance;
+  }
+  return boxes;
+}
+
+// フォントにない文字の矩形。x と advance はその字形の位置と幅、baseline はベースラインの y
+pub fn missing_glyph_box(x: f32, advance: f32, baseline: f32, font_size: f32) -> Rect {
+  return Rect { x: x + advance * 0.15, y: baseline - font_size * 0.55, width: advance * 0.7, height: font_size * 0.55 };
+}
+
+// ボーダーボックス rect の左、右、上、下の辺
+pub fn border_sides(rect: Rect, widths: EdgeSizes) -> [Rect; 4] {
+  return [
+    Rect { width: widths.left, ..rect },
+    Rect { x: rect.x + rect.width - widths.right, width: widths.right, ..rect },
+    Rect { height: widths.top, ..rect },
+    Rect { y: rect.y + rect.height - widths.bottom, height: widths.bottom, ..rect },
+  ];
+}
+
+// 描画。bounds の x, y がキャンバスの左上になる（スクロール位置）
+pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Result<Canvas, PaintError> {
+  return paint_display_list(&build_display_list(layout_root), bounds);
+}
+
+pub fn paint_display_list(display_list: &DisplayList, bounds: Rect) -> Result<Canvas, PaintError> {
+  return paint_display_list_scaled(display_list, bounds, 1.0);
+}
+
+// bounds の範囲を scale 倍に拡大して描く（キャンバスは bounds の scale 倍の大きさ）
+pub fn paint_display_list_scaled(display_list: &DisplayList, bounds: Rect, scale: f32) -> Result<Canvas, PaintError> {
+  return paint_display_list_tiled(display_list, bounds, scale, Tiling::NONE);
+}
+
+// ラスタライズのしかた。キャンバスを tile_size px 四方のタイルに分けて、parallelism のスレッドで同時に描いてからつなぐ。
+// tile_size が 0 ならタイルに分けない。どちらで描いても同じ画像になる
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tiling {
+  pub tile_size: usize,
+  pub parallelism: usize, // EngineOptions::parallelism と同じ
+}
+
+impl Tiling {
+  pub const NONE: Tiling = Tiling { tile_size: 0, parallelism: 1 };
+}
+
+// paint_display_list_scaled と同じだが、tiling のタイルに分けて描く
+pub fn paint_display_list_tiled(display_list: &DisplayList, bounds: Rect, scale: f32, tiling: Tiling) -> Result<Canvas, PaintError> {
+  return paint_display_list_with(display_list, bounds, scale, tiling, &RenderOptions::default());
+}
+
+// paint_display_list_tiled と同じだが、キャンバスを options の背景の色で塗っておき、pixel_snap ならコマンドの辺をピクセルの境目に合わせる
+// （拡大した後に合わせるので、境目はキャンバスのピクセル）
+pub fn paint_display_list_with(display_list: &DisplayList, bounds: Rect, scale: f32, tiling: Tiling, options: &RenderOptions) -> Result<Canvas, PaintError> {
+  let (width, height) = canvas_size(bounds, scale)?;
+  let (width, height) = (width as usize, height as usize);
+  let mut span = trace::span("paint", "raster");
+  span.arg("pixels", (width * height) as f64);
+  // キャンバスの座標にしたコマンド。どのタイルでも使う
+  let items: DisplayList = display_list
+    .iter()
+    .map(|item| {
+      let item = item.translated(-bounds.x, -bounds.y);
+      let item = if scale == 1.0 { item } else { item.scaled(scale) };
+      if options.pixel_snap { item.snapped() } else { item }
+    })
+    .collect();
+  if tiling.tile_size == 0 || (tiling.tile_size >= width && tiling.tile_size >= height) {
+    let mut canvas = paint_tile(&items, Rect { x: 0.0, y: 0.0, width: width as f32, height: height as f32 }, options.background);
+    canvas.pixel_snap = options.pixel_snap;
+    return Ok(canvas);
+  }
+
+  // 左上から行ごとに並べたタイル。右端と下端のものは小さい
+  let mut tiles = Vec::new();
+  for y in (0..height).step_by(tiling.tile_size) {
+    for x in (0..width).step_by(tiling.tile_size) {
+      tiles.push(Rect { x: x as f32, y: y as f32, width: tiling.tile_size.min(width - x) as f32, height: tiling.tile_size.min(height - y) as f32 });
+    }
+  }
+  let painted = parallel::map(tiling.parallelism, &tiles, |tile| {
+    let _span = trace::span("paint", "tile");
+    paint_tile(&items, *tile, options.background)
+  });
+  let mut canvas = Canvas::filled(width, height, options.background);
+  canvas.pixel_snap = options.pixel_snap;
+  for (tile, tile_canvas) in tiles.iter().zip(painted) {
+    let (x, y) = (tile.x as usize, tile.y as usize);
+    for (row, pixels) in tile_canvas.pixels.chunks(tile_canvas.width).enumerate() {
+      let start = (y + row) * width + x;
+      canvas.pixels[start..start + pixels.len()].copy_from_slice(pixels);
+    }
+  }
+  return Ok(canvas);
+}
+
+// tile（キャンバスの座標）の範囲だけを描いたキャンバス。tile にかからないコマンドは飛ばす。
+// コマンドを切り取ったりタイルの左上に合わせて動かしたりすると座標の丸めが変わるので、そのまま描く。
+// PushTransform の中のコマンドは変換する前の座標なので飛ばさない（変換した範囲が tile にかからなければ PopTransform までまとめて飛ばす）
+fn paint_tile(items: &[DisplayCommand], tile: Rect, background: Color) -> Canvas {
+  let mut canvas = Canvas::tile(tile, background);
+  let mut depth = 0; // 入っている PushTransform の数
+  let mut skipping = None; // 飛ばしている PushTransform の深さ
+  for item in items {
+    let inside = depth > 0;
+    match *item {
+      DisplayCommand::PushTransform { clip, .. } => {
+        depth += 1;
+        if skipping.is_none() && clip.intersection(tile).is_none() {
+          skipping = Some(depth);
+        }
+      }
+      DisplayCommand::PopTransform if depth > 0 => {
+        depth -= 1;
+        if skipping == Some(depth + 1) {
+          skipping = None;
+          continue;
+        }
+      }
+      _ => {}
+    }
+    if skipping.is_none() && (inside || item.clipped(tile).is_some()) {
+      canvas.paint_item(item);
+    }
+  }
+  return canvas;
+}
+
+// bounds を scale 倍にした大きさ。キャンバスにできない大きさならエラー
+pub(super) fn canvas_size(bounds: Rect, scale: f32) -> Result<(f32, f32), PaintError> {
+  let (width, height) = if scale == 1.0 { (bounds.width, bounds.height) } else { ((bounds.width * scale).round(), (bounds.height * scale).round()) };
+  let valid = |size: f32| size.is_finite() && size >= 0.0 && size <= MAX_CANVAS_SIZE;
+  if !valid(width) || !valid(height) {
+    return Err(PaintError::InvalidCanvasSize { width: width, height: height });
+  }
+  return Ok((width, height));
+}
@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/**
+ * 互いに関係ない仕事（サブリソースの読み込みとパース、iframe のレイアウトなど）を
+ * スレッドで同時に行うところ。スレッドは呼び出しの間だけ作る（scoped thread）。
+ * parallelism は EngineOptions::parallelism で、0 なら CPU の数、1 なら呼び出したスレッドだけで順番に行う。
+ * wasm32 ではスレッドを作れないので、常に順番に行う
+ */
+
+pub fn thread_count(parallelism: usize) -> usize {
+  if cfg!(target_arch = "wasm32") {
+    return 1;
+  }
+  if parallelism == 0 {
+    return thread::available_parallelism().map_or(1, |count| count.get());
+  }
+  return parallelism;
+}
+
+// items のそれぞれに f を適用する。結果は items の順
+pub fn map<T, R, F>(parallelism: usize, items: &[T], f: F) -> Vec<R>
+where
+  T: Sync,
+  R: Send,
+  F: Fn(&T) -> R + Sync,
+{
+  let threads = thread_count(parallelism).min(items.len());
+  if threads <= 1 {
+    return items.iter().map(f).collect();
+  }
+
+  // 空いたスレッドから次の項目を取っていく
+  let next = AtomicUsize::new(0);
+  let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+  thread::scope(|scope| {
+    for _ in 0..threads {
+      scope.spawn(|| loop {
+        let index = next.fetch_add(1, Ordering::Relaxed);
+        if index >= items.len() {
+          break;
+        }
+        let result = f(&items[index]);
+        results.lock().unwrap()[index] = Some(result);
+      });
+    }
+  });
+  return results.into_inner().unwrap().into_iter().map(|result| result.unwrap()).collect();
+}
+
+// a と b を同時に行う（b を別のスレッドで）
+pub fn join<A, B, RA, RB>(parallelism: usize, a: A, b: B) -> (RA, RB)
+where
+  A: FnOnce() -> RA + Send,
+  B: FnOnce() -> RB + Send,
+  RA: Send,
+  RB: Send,
+{
+  if thread_count(parallelism) <= 1 {
+    return (a(), b());
+  }
+  return thread::scope(|scope| {
+    let b = scope.spawn(b);
+    let a = a();
+    return (a, b.join().unwrap());
+  });
+}
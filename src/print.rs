@@ -0,0 +1,112 @@
+use css::Value::Keyword;
+use error::EngineError;
+use frame;
+use layout::{self, BlockNode, LayoutBox, Rect};
+use options::EngineOptions;
+use paint::{self, Canvas, DisplayList, Page, PaintError};
+use profile::{self, Counts, Timings};
+use std::cmp::Ordering;
+use style::{self, Float, StyledNode};
+use {count, initial_containing_block, LoadedDocument};
+
+/**
+ * 印刷（media = "print"）のページ分け。
+ *
+ * ページの幅から余白を引いた幅でレイアウトして、ディスプレイリストを余白の内側の高さごとに縦に切り分け、
+ * 1 ページずつ余白を付けたキャンバス（page_width × page_height）に描く。
+ * 流れの中のブロックの break-before と break-after（page-break-before と page-break-after も）で改ページを強制すれば、
+ * ページがいっぱいになる前でもそこで切って、残りは次のページの上から置く。
+ * <meta name="viewport"> は使わない。それ以外の改ページの位置はまだ選ばないので、ボックスの途中でも境目で切る
+ */
+
+// これより後のページは描かない
+const MAX_PAGES: usize = 1000;
+
+pub struct Printed {
+  pub pages: Vec<Canvas>,
+  pub display_lists: Vec<Page>, // ページごとに切ったディスプレイリスト（SVG と PDF に書き出すときに使う）
+  pub timings: Timings,
+  pub counts: Counts, // pixels はすべてのページの合計
+}
+
+// ページの余白の内側（ドキュメントを置く範囲）の大きさ
+pub fn page_area(options: &EngineOptions) -> Rect {
+  let margin = options.page_margin.max(0.0);
+  return Rect { x: 0.0, y: 0.0, width: options.page_width - margin * 2.0, height: options.page_height - margin * 2.0 };
+}
+
+pub fn render_pages(document: &LoadedDocument, options: &EngineOptions) -> Result<Printed, EngineError> {
+  let area = page_area(options);
+  if !(area.width >= 1.0 && area.height >= 1.0) {
+    return Err(PaintError::InvalidCanvasSize { width: area.width, height: area.height }.into());
+  }
+  let mut timings = Timings::default();
+  let style_root = profile::time(&mut timings.style, || style::style_tree_with_viewport(&document.root_node, &document.stylesheet, area));
+  let layout_root = profile::time(&mut timings.layout, || layout::layout_tree_with(&style_root, initial_containing_block(area), document.fonts, &document.images))?;
+  let display_list = profile::time(&mut timings.display_list, || frame::build_display_list(&layout_root, &document.root_node, &document.frames, document.parallelism, &document.render));
+
+  let overflow = layout_root.scrollable_overflow();
+  let mut breaks = Vec::new();
+  forced_breaks(&layout_root, &mut breaks);
+  let display_lists: Vec<Page> = page_slices(area, overflow.y + overflow.height, breaks).into_iter().map(|slice| page(&display_list, slice, options)).collect();
+  let pages = profile::time(&mut timings.raster, || display_lists.iter().map(|page| page.paint_with(document.tiling(), &document.render)).collect::<Result<Vec<_>, _>>())?;
+
+  let mut counts = count(&document.root_node, &document.stylesheet, &layout_root, &display_list, &pages[0]);
+  counts.pixels = pages.iter().map(|page| page.pixels.len()).sum();
+  return Ok(Printed { pages: pages, display_lists: display_lists, timings: timings, counts: counts });
+}
+
+// ページごとのドキュメントの範囲。上から area の高さずつ区切り、強制改ページの位置 breaks（y）があればその手前で区切る。
+// ページの上端から 1px 未満の改ページ（前のブロックの break-after と次のブロックの break-before など）は、空のページを作らないように使わない
+fn page_slices(area: Rect, bottom: f32, mut breaks: Vec<f32>) -> Vec<Rect> {
+  breaks.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+  let mut slices = Vec::new();
+  let mut top = 0.0;
+  loop {
+    let end = breaks.iter().find(|&&y| y >= top + 1.0).map_or(top + area.height, |&y| y.min(top + area.height));
+    slices.push(Rect { y: top, height: end - top, ..area });
+    top = end;
+    if !(top < bottom) || slices.len() >= MAX_PAGES {
+      return slices;
+    }
+  }
+}
+
+// 流れの中のブロックの break-before と break-after で改ページする位置を breaks に足す。break-after はボーダーボックスの下端、
+// break-before は前の兄弟のボーダーボックスの下端（最初の子ならマージンボックスの上端）で、間の margin は次のページに送る。
+// 前のブロックの break-after と次のブロックの break-before は同じ位置になる。float と絶対配置のもの、インラインの中は見ない
+fn forced_breaks(layout_box: &LayoutBox, breaks: &mut Vec<f32>) {
+  let mut previous: Option<f32> = None;
+  for child in &layout_box.children {
+    if child.float != Float::None || child.position.is_out_of_flow() {
+      continue;
+    }
+    let border_box = child.dimensions.border_box();
+    if let BlockNode(style) = child.box_type {
+      if forces_break(style, "before") {
+        breaks.push(previous.unwrap_or(child.dimensions.margin_box().y));
+      }
+      forced_breaks(child, breaks);
+      if forces_break(style, "after") {
+        breaks.push(border_box.y + border_box.height);
+      }
+    }
+    previous = Some(border_box.y + border_box.height);
+  }
+}
+
+// side（before か after）で改ページを強制するか。break-* の page、left、right、recto、verso と、page-break-* の always、left、right
+fn forces_break(style: &StyledNode, side: &str) -> bool {
+  let keyword = |name: String, keywords: &[&str]| match style.value(&name) {
+    Some(Keyword(ref keyword)) => keywords.contains(&&*keyword.to_ascii_lowercase()),
+    _ => false,
+  };
+  return keyword(format!("break-{}", side), &["page", "left", "right", "recto", "verso"]) || keyword(format!("page-break-{}", side), &["always", "left", "right"]);
+}
+
+// slice の範囲に切ったディスプレイリストを、余白の分ずらして置いたページ
+fn page(display_list: &DisplayList, slice: Rect, options: &EngineOptions) -> Page {
+  let items = paint::clip_display_list(display_list.iter().cloned(), slice);
+  let margin = options.page_margin.max(0.0);
+  return Page { display_list: items, bounds: Rect { x: slice.x - margin, y: slice.y - margin, width: options.page_width, height: options.page_height }, scale: 1.0 };
+}
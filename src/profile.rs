@@ -0,0 +1,71 @@
+use dom::Node;
+use layout::LayoutBox;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+use std::time::Duration;
+
+/**
+ * パイプラインの段階ごとにかかった時間と、扱った数（ノード、ルール、ボックスなど）。
+ * --profile で表示する
+ */
+
+// 段階ごとの時間。やり直さなかった段階は 0。
+// 同時に読み込んだスタイルシートや iframe の時間は、それぞれにかかった時間の合計
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timings {
+  pub fetch: Duration,        // ドキュメントとスタイルシートを読み込む
+  pub parse: Duration,        // HTML と CSS のパース
+  pub script: Duration,       // <script> の読み込みと実行
+  pub style: Duration,        // セレクターのマッチング
+  pub layout: Duration,       // レイアウトツリーを作ってレイアウトする
+  pub display_list: Duration, // ディスプレイリストを作る（iframe の中のレイアウトを含む）
+  pub raster: Duration,       // キャンバスに塗る
+}
+
+impl Timings {
+  pub fn total(&self) -> Duration {
+    return self.fetch + self.parse + self.script + self.style + self.layout + self.display_list + self.raster;
+  }
+
+  pub fn add(&mut self, other: &Timings) {
+    self.fetch += other.fetch;
+    self.parse += other.parse;
+    self.script += other.script;
+    self.style += other.style;
+    self.layout += other.layout;
+    self.display_list += other.display_list;
+    self.raster += other.raster;
+  }
+}
+
+// 段階ごとに扱った数
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counts {
+  pub dom_nodes: usize,
+  pub rules: usize, // 組み込みのスタイルシートを含む
+  pub layout_boxes: usize,
+  pub display_items: usize,
+  pub pixels: usize,
+}
+
+// f を実行して、かかった時間を slot に足す。wasm32-unknown-unknown には時計がないので測らない
+#[cfg(not(target_arch = "wasm32"))]
+pub fn time<T, F: FnOnce() -> T>(slot: &mut Duration, f: F) -> T {
+  let start = Instant::now();
+  let result = f();
+  *slot += start.elapsed();
+  return result;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn time<T, F: FnOnce() -> T>(_slot: &mut Duration, f: F) -> T {
+  return f();
+}
+
+pub fn count_nodes(node: &Node) -> usize {
+  return 1 + node.children.iter().map(count_nodes).sum::<usize>();
+}
+
+pub fn count_boxes(layout_box: &LayoutBox) -> usize {
+  return 1 + layout_box.children.iter().map(count_boxes).sum::<usize>();
+}
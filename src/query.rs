@@ -0,0 +1,76 @@
+use css::{self, CssError};
+use dom::{Node, NodeType};
+use style;
+
+/**
+ * パースしたドキュメントを調べるための、DOM の querySelector などにあたる関数。
+ * セレクターはスタイルシートと同じパーサーで読み、スタイルの計算と同じマッチングで一致させる。
+ * 呼んだノードをドキュメントのルートとみなすので、そのノード自身も対象になり、その祖先は見ない
+ */
+
+// 先行順（ドキュメント順）に自身と子孫をたどる
+pub struct Descendants<'a> {
+  stack: Vec<&'a Node>, // 次にたどるノードを末尾に積む
+}
+
+impl<'a> Iterator for Descendants<'a> {
+  type Item = &'a Node;
+
+  fn next(&mut self) -> Option<&'a Node> {
+    let node = self.stack.pop()?;
+    self.stack.extend(node.children.iter().rev());
+    return Some(node);
+  }
+}
+
+impl Node {
+  // 自身とすべての子孫（テキストとコメントも含む）
+  pub fn descendants<'a>(&'a self) -> Descendants<'a> {
+    return Descendants { stack: vec![self] };
+  }
+
+  // 自身と子孫の要素のうち、selector（カンマ区切りも可）に一致するものをドキュメント順に
+  pub fn query_selector_all(&self, selector: &str) -> Result<Vec<&Node>, CssError> {
+    let selectors = css::parse_selectors(selector)?;
+    // 番号は descendants と同じ先行順
+    let indices = style::query_selector_all(self, &selectors);
+    return Ok(self.descendants().enumerate().filter(|&(i, _)| indices.binary_search(&i).is_ok()).map(|(_, node)| node).collect());
+  }
+
+  // selector に一致する最初の要素
+  pub fn query_selector(&self, selector: &str) -> Result<Option<&Node>, CssError> {
+    return Ok(self.query_selector_all(selector)?.into_iter().next());
+  }
+
+  // id 属性が id の最初の要素
+  pub fn get_element_by_id(&self, id: &str) -> Option<&Node> {
+    return self.elements().find(|node| attribute(node, "id") == Some(id));
+  }
+
+  // class 属性に name を含む要素
+  pub fn get_elements_by_class_name(&self, name: &str) -> Vec<&Node> {
+    return self.elements().filter(|node| attribute(node, "class").map_or(false, |classes| classes.split_whitespace().any(|class| class == name))).collect();
+  }
+
+  // タグ名が tag_name（大文字小文字は区別しない）の要素
+  pub fn get_elements_by_tag_name(&self, tag_name: &str) -> Vec<&Node> {
+    return self
+      .elements()
+      .filter(|node| match node.node_type {
+        NodeType::Element(ref elem) => elem.tag_name.eq_ignore_ascii_case(tag_name),
+        _ => false,
+      })
+      .collect();
+  }
+
+  fn elements(&self) -> impl Iterator<Item = &Node> {
+    return self.descendants().filter(|node| if let NodeType::Element(_) = node.node_type { true } else { false });
+  }
+}
+
+fn attribute<'a>(node: &'a Node, name: &str) -> Option<&'a str> {
+  return match node.node_type {
+    NodeType::Element(ref elem) => elem.attributes.get(name).map(|value| &**value),
+    _ => None,
+  };
+}
@@ -0,0 +1,225 @@
+use dom::{ElementData, NodeType};
+use style::{Display, StyledNode};
+use url::Url;
+
+/**
+ * 読みやすいテキストを取り出すところ（リーダーモード）。
+ * スタイルツリーをたどって、見出し、段落、リスト、リンクなどをプレーンテキストか Markdown にする。
+ * display: none の要素と <script>、<style> などの中身は飛ばす
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReaderFormat {
+  Text,
+  Markdown,
+}
+
+// ブロックとして扱う要素（前後で段落を区切る）
+const BLOCK_ELEMENTS: [&'static str; 20] = [
+  "address", "article", "aside", "body", "dd", "div", "dl", "dt", "figcaption", "figure", "footer", "form", "header", "html", "main", "nav", "p", "section", "table", "tr",
+];
+
+// 中身を読まない要素（組み込みのスタイルシートを使わない場合も飛ばせるように）
+const SKIPPED_ELEMENTS: [&'static str; 7] = ["head", "iframe", "noscript", "script", "style", "template", "title"];
+
+// 相対 URL の href と src は base_url で解決する
+pub fn extract(root: &StyledNode, base_url: &Url, format: ReaderFormat) -> String {
+  let mut writer = Writer { format: format, base_url: base_url, out: String::new(), line: String::new(), lists: Vec::new(), marker: None, quote_depth: 0 };
+  writer.walk(root);
+  writer.end_block();
+  return writer.out.trim_end().to_string() + "\n";
+}
+
+struct Writer<'a> {
+  format: ReaderFormat,
+  base_url: &'a Url,
+  out: String,
+  line: String,            // 書きかけのブロックの中身（インライン）
+  lists: Vec<Option<usize>>, // 入れ子のリスト。番号付きなら次の番号
+  marker: Option<String>,  // 次のブロックの先頭に付ける "- " や "1. "
+  quote_depth: usize,
+}
+
+impl<'a> Writer<'a> {
+  fn markdown(&self) -> bool {
+    return self.format == ReaderFormat::Markdown;
+  }
+
+  fn walk(&mut self, node: &StyledNode) {
+    if node.display() == Display::None {
+      return;
+    }
+    let elem = match node.node.node_type {
+      NodeType::Text(ref text) => {
+        self.push_text(text);
+        return;
+      }
+      NodeType::Element(ref elem) => elem,
+      NodeType::Comment(_) => return,
+    };
+    let tag = &*elem.tag_name;
+    if SKIPPED_ELEMENTS.contains(&tag) {
+      return;
+    }
+
+    match tag {
+      "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+        self.end_block();
+        self.walk_children(node);
+        let level = tag[1..].parse().unwrap_or(1);
+        if self.markdown() && !self.line.trim().is_empty() {
+          self.line = format!("{} {}", "#".repeat(level), self.line.trim());
+        }
+        self.end_block();
+      }
+      "ul" | "ol" => {
+        self.end_block();
+        self.lists.push(if tag == "ol" { Some(1) } else { None });
+        self.walk_children(node);
+        self.end_block();
+        self.lists.pop();
+        if self.lists.is_empty() && !self.out.is_empty() && !self.out.ends_with("\n\n") {
+          self.out.push('\n');
+        }
+      }
+      "li" => {
+        self.end_block();
+        let marker = match self.lists.last_mut() {
+          Some(&mut Some(ref mut number)) => {
+            *number += 1;
+            format!("{}. ", *number - 1)
+          }
+          _ => "- ".to_string(),
+        };
+        self.marker = Some(marker);
+        self.walk_children(node);
+        self.end_block();
+      }
+      "blockquote" => {
+        self.end_block();
+        self.quote_depth += 1;
+        self.walk_children(node);
+        self.end_block();
+        self.quote_depth -= 1;
+      }
+      "pre" => {
+        self.end_block();
+        let text = raw_text(node);
+        if self.markdown() {
+          self.out.push_str(&format!("```\n{}\n```\n\n", text.trim_end_matches('\n')));
+        } else {
+          self.out.push_str(&format!("{}\n\n", text.trim_end_matches('\n')));
+        }
+      }
+      "hr" => {
+        self.end_block();
+        self.out.push_str(if self.markdown() { "---\n\n" } else { "----------\n\n" });
+      }
+      "br" => self.line.push('\n'),
+      "a" => {
+        let href = elem.attributes.get("href").and_then(|href| self.base_url.join(href));
+        match href {
+          Some(ref href) if self.markdown() => {
+            self.push_inline("[");
+            self.walk_children(node);
+            self.push_inline(&format!("]({})", href));
+          }
+          _ => self.walk_children(node),
+        }
+      }
+      "img" => self.push_image(elem),
+      "strong" | "b" if self.markdown() => self.wrap(node, "**"),
+      "em" | "i" if self.markdown() => self.wrap(node, "*"),
+      "code" if self.markdown() => self.wrap(node, "`"),
+      "td" | "th" => {
+        if !self.line.trim().is_empty() {
+          self.push_inline(" | ");
+        }
+        self.walk_children(node);
+      }
+      _ if BLOCK_ELEMENTS.contains(&tag) => {
+        self.end_block();
+        self.walk_children(node);
+        self.end_block();
+      }
+      _ => self.walk_children(node),
+    }
+  }
+
+  fn walk_children(&mut self, node: &StyledNode) {
+    for child in &node.children {
+      self.walk(child);
+    }
+  }
+
+  fn wrap(&mut self, node: &StyledNode, mark: &str) {
+    self.push_inline(mark);
+    self.walk_children(node);
+    self.push_inline(mark);
+  }
+
+  fn push_image(&mut self, elem: &ElementData) {
+    let alt = elem.attributes.get("alt").map_or("", |alt| &**alt);
+    let src = elem.attributes.get("src").and_then(|src| self.base_url.join(src));
+    match src {
+      Some(ref src) if self.markdown() => self.push_inline(&format!("![{}]({})", alt, src)),
+      _ => self.push_text(alt),
+    }
+  }
+
+  // 連続する空白は 1 つにまとめて足す
+  fn push_text(&mut self, text: &str) {
+    if text.starts_with(char::is_whitespace) && !self.line.is_empty() && !self.line.ends_with(char::is_whitespace) {
+      self.line.push(' ');
+    }
+    let words: Vec<&str> = text.split_whitespace().collect();
+    self.line.push_str(&words.join(" "));
+    if text.ends_with(char::is_whitespace) && !words.is_empty() {
+      self.line.push(' ');
+    }
+  }
+
+  fn push_inline(&mut self, text: &str) {
+    self.line.push_str(text);
+  }
+
+  // 書きかけのブロックを書き出す。リストの中なら項目ごとに改行だけ、それ以外は空行で区切る
+  fn end_block(&mut self) {
+    let line = self.line.trim().to_string();
+    self.line.clear();
+    if line.is_empty() {
+      return;
+    }
+
+    let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+    let quote = if self.markdown() { "> ".repeat(self.quote_depth) } else { "  ".repeat(self.quote_depth) };
+    let (first, rest) = match self.marker.take() {
+      Some(marker) => {
+        let continuation = " ".repeat(marker.len());
+        (format!("{}{}{}", quote, indent, marker), format!("{}{}{}", quote, indent, continuation))
+      }
+      None if !self.lists.is_empty() => {
+        let continuation = format!("{}{}  ", quote, indent);
+        (continuation.clone(), continuation)
+      }
+      None => (quote.clone(), quote),
+    };
+    for (i, text) in line.split('\n').enumerate() {
+      self.out.push_str(if i == 0 { &first } else { &rest });
+      self.out.push_str(text.trim());
+      self.out.push('\n');
+    }
+    if self.lists.is_empty() {
+      self.out.push('\n');
+    }
+  }
+}
+
+// <pre> の中身をそのまま（空白を残して）
+fn raw_text(node: &StyledNode) -> String {
+  return match node.node.node_type {
+    NodeType::Text(ref text) => text.clone(),
+    NodeType::Element(_) => node.children.iter().map(raw_text).collect(),
+    NodeType::Comment(_) => String::new(),
+  };
+}
@@ -0,0 +1,271 @@
+use boa_engine::object::builtins::JsArray;
+use boa_engine::object::ObjectInitializer;
+use boa_engine::{js_string, Context, JsArgs, JsError, JsNativeError, JsResult, JsString, JsValue, NativeFunction, Source};
+use dom::{self, Arena, AttrMap, DomError, NodeId, NodeType};
+use loader::{Loader, Resource};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use url::Url;
+
+/**
+ * <script> を実行するところ（boa を使う）。
+ * 実行している間だけ DOM を dom::Arena に移して、JS からはノードの番号（NodeId）で操作する。
+ * 全部のスクリプトを実行し終わったら元の木に戻すので、スタイル適用以降は最終的な状態を描画する
+ */
+
+const LOOP_ITERATION_LIMIT: u64 = 1_000_000; // 無限ループで止まらないように
+const RECURSION_LIMIT: usize = 512;
+
+// document や Element のラッパーを作る JS。引数はネイティブ関数をまとめたオブジェクト
+const PRELUDE: &'static str = include_str!("script_prelude.js");
+const DETERMINISTIC: &'static str = include_str!("script_deterministic.js");
+
+// ドキュメント順に <script> の中身（src があれば読み込んだもの）を集める
+fn collect_scripts(node: &dom::Node, base_url: &Url, loader: &Loader, scripts: &mut Vec<(String, String)>, sources: &mut Vec<Url>) {
+  if let NodeType::Element(ref elem) = node.node_type {
+    if elem.tag_name == "script" {
+      match elem.attributes.get("src") {
+        Some(src) => match load_script(src, base_url, loader) {
+          Ok(resource) => {
+            sources.push(resource.url.clone());
+            scripts.push((resource.url.to_string(), resource.text()));
+          }
+          Err(error) => warn!("Skipped script: {}", error),
+        },
+        None => {
+          let text = node.children.iter().filter_map(|child| match child.node_type {
+            NodeType::Text(ref data) => Some(data.as_str()),
+            NodeType::Element(_) | NodeType::Comment(_) => None,
+          });
+          scripts.push((base_url.to_string(), text.collect()));
+        }
+      }
+      return;
+    }
+  }
+  for child in &node.children {
+    collect_scripts(child, base_url, loader, scripts, sources);
+  }
+}
+
+fn load_script(src: &str, base_url: &Url, loader: &Loader) -> Result<Arc<Resource>, String> {
+  let url = base_url.join(src).ok_or_else(|| format!("invalid URL {}", src))?;
+  return loader.load(&url).map_err(|error| error.to_string());
+}
+
+// スクリプトを順に実行して、書き換えられた DOM を返す。
+// 例外が出てもそのスクリプトを止めるだけで、残りのスクリプトは実行する。読み込んだ外部スクリプトの URL を sources に足す。
+// deterministic なら Math.random と Date を固定して、毎回同じ結果になるようにする
+pub fn execute(root: dom::Node, base_url: &Url, loader: &Loader, deterministic: bool, sources: &mut Vec<Url>) -> dom::Node {
+  let mut scripts = Vec::new();
+  collect_scripts(&root, base_url, loader, &mut scripts, sources);
+  if scripts.is_empty() {
+    return root;
+  }
+
+  let document = Rc::new(RefCell::new(Arena::new(root)));
+  let mut context = Context::default();
+  context.runtime_limits_mut().set_loop_iteration_limit(LOOP_ITERATION_LIMIT);
+  context.runtime_limits_mut().set_recursion_limit(RECURSION_LIMIT);
+
+  if deterministic {
+    if let Err(error) = context.eval(Source::from_bytes(DETERMINISTIC)) {
+      warn!("Failed to set up deterministic scripting: {}", error);
+    }
+  }
+
+  match install_bindings(&mut context, &document) {
+    Ok(()) => {
+      for (location, source) in &scripts {
+        debug!("Running script from {}", location);
+        if let Err(error) = context.eval(Source::from_bytes(source)) {
+          warn!("Uncaught exception in script from {}: {}", location, error);
+        }
+      }
+    }
+    Err(error) => warn!("Failed to set up scripting: {}", error),
+  }
+
+  // ネイティブ関数は GC が回収するまで document を持っているので、中身だけ取り出す
+  let document = document.replace(Arena::new(dom::text(String::new())));
+  return document.into_node();
+}
+
+// PRELUDE にネイティブ関数を渡して document と console を作る
+fn install_bindings(context: &mut Context, document: &Rc<RefCell<Arena>>) -> JsResult<()> {
+  let mut functions: BTreeMap<&'static str, NativeFunction> = BTreeMap::new();
+
+  functions.insert("root", native(document, |document, _, _| Ok(node_value(Some(document.root())))));
+  functions.insert(
+    "getElementById",
+    native(document, |document, args, context| {
+      let id = string_arg(args, 0, context)?;
+      let root = document.root();
+      return Ok(node_value(document.find(root, &|elem| elem.id() == Some(&id))));
+    }),
+  );
+  functions.insert(
+    "firstByTagName",
+    native(document, |document, args, context| {
+      let tag_name = string_arg(args, 0, context)?;
+      let root = document.root();
+      return Ok(node_value(document.find(root, &|elem| elem.tag_name.eq_ignore_ascii_case(&tag_name))));
+    }),
+  );
+  functions.insert(
+    "tagName",
+    native(document, |document, args, _| {
+      let id = node_arg(document, args, 0)?;
+      return Ok(document.element(id).map_or(JsValue::null(), |elem| string_value(&elem.tag_name)));
+    }),
+  );
+  functions.insert(
+    "getAttribute",
+    native(document, |document, args, context| {
+      let (id, name) = (node_arg(document, args, 0)?, string_arg(args, 1, context)?.to_ascii_lowercase());
+      return Ok(document.element(id).and_then(|elem| elem.attributes.get(&name)).map_or(JsValue::null(), |value| string_value(value)));
+    }),
+  );
+  functions.insert(
+    "setAttribute",
+    native(document, |document, args, context| {
+      let (id, name, value) = (node_arg(document, args, 0)?, string_arg(args, 1, context)?.to_ascii_lowercase(), string_arg(args, 2, context)?);
+      document.set_attribute(id, &name, &value).map_err(dom_error)?;
+      return Ok(JsValue::undefined());
+    }),
+  );
+  functions.insert(
+    "removeAttribute",
+    native(document, |document, args, context| {
+      let (id, name) = (node_arg(document, args, 0)?, string_arg(args, 1, context)?.to_ascii_lowercase());
+      document.remove_attribute(id, &name).map_err(dom_error)?;
+      return Ok(JsValue::undefined());
+    }),
+  );
+  functions.insert(
+    "textContent",
+    native(document, |document, args, _| {
+      let id = node_arg(document, args, 0)?;
+      return Ok(string_value(&document.text_content(id)));
+    }),
+  );
+  functions.insert(
+    "setTextContent",
+    native(document, |document, args, context| {
+      let (id, text) = (node_arg(document, args, 0)?, string_arg(args, 1, context)?);
+      document.set_text_content(id, &text).map_err(dom_error)?;
+      return Ok(JsValue::undefined());
+    }),
+  );
+  functions.insert(
+    "createElement",
+    native(document, |document, args, context| {
+      let tag_name = string_arg(args, 0, context)?.to_ascii_lowercase();
+      return Ok(node_value(Some(document.create_element(&tag_name, AttrMap::new()))));
+    }),
+  );
+  functions.insert(
+    "createTextNode",
+    native(document, |document, args, context| {
+      let data = string_arg(args, 0, context)?;
+      return Ok(node_value(Some(document.create_text(&data))));
+    }),
+  );
+  functions.insert(
+    "insertBefore",
+    native(document, |document, args, _| {
+      let (parent, child) = (node_arg(document, args, 0)?, node_arg(document, args, 1)?);
+      let before = if args.get_or_undefined(2).is_null_or_undefined() { None } else { Some(node_arg(document, args, 2)?) };
+      document.insert_before(parent, child, before).map_err(dom_error)?;
+      return Ok(JsValue::undefined());
+    }),
+  );
+  functions.insert(
+    "removeChild",
+    native(document, |document, args, _| {
+      let (parent, child) = (node_arg(document, args, 0)?, node_arg(document, args, 1)?);
+      if document.parent(child) != Some(parent) {
+        return Err(dom_error(DomError::NotAChild(child)));
+      }
+      document.remove(child).map_err(dom_error)?;
+      return Ok(JsValue::undefined());
+    }),
+  );
+  functions.insert(
+    "parent",
+    native(document, |document, args, _| {
+      let id = node_arg(document, args, 0)?;
+      return Ok(node_value(document.parent(id)));
+    }),
+  );
+  functions.insert(
+    "children",
+    native(document, |document, args, context| {
+      let id = node_arg(document, args, 0)?;
+      let children = document.children(id).iter().map(|&child| node_value(Some(child)));
+      return Ok(JsArray::from_iter(children, context).into());
+    }),
+  );
+  functions.insert(
+    "log",
+    native(document, |_, args, context| {
+      info!("[console] {}", string_arg(args, 0, context)?);
+      return Ok(JsValue::undefined());
+    }),
+  );
+
+  let mut initializer = ObjectInitializer::new(context);
+  for (name, function) in functions {
+    initializer.function(function, JsString::from(name), 0);
+  }
+  let bindings = initializer.build();
+
+  let prelude = context.eval(Source::from_bytes(PRELUDE))?;
+  let prelude = prelude.as_callable().ok_or_else(|| JsNativeError::typ().with_message("prelude is not a function"))?;
+  prelude.call(&JsValue::undefined(), &[bindings.into()], context)?;
+  return Ok(());
+}
+
+// Arena を借りて呼ぶネイティブ関数を作る
+fn native<F>(document: &Rc<RefCell<Arena>>, function: F) -> NativeFunction
+where
+  F: Fn(&mut Arena, &[JsValue], &mut Context) -> JsResult<JsValue> + 'static,
+{
+  let document = document.clone();
+  // クロージャが持つのは Rc<RefCell<Arena>> だけで、GC で管理される値は持たないので安全
+  return unsafe {
+    NativeFunction::from_closure(move |_this, args, context| match document.try_borrow_mut() {
+      Ok(mut document) => function(&mut document, args, context),
+      Err(_) => Err(JsNativeError::error().with_message("the document is busy").into()),
+    })
+  };
+}
+
+fn node_arg(document: &Arena, args: &[JsValue], index: usize) -> JsResult<NodeId> {
+  return match args.get_or_undefined(index).as_number() {
+    Some(id) if id >= 0.0 && id.fract() == 0.0 && document.contains(id as NodeId) => Ok(id as NodeId),
+    _ => Err(JsNativeError::typ().with_message("argument is not a node").into()),
+  };
+}
+
+fn string_arg(args: &[JsValue], index: usize, context: &mut Context) -> JsResult<String> {
+  return Ok(args.get_or_undefined(index).to_string(context)?.to_std_string_escaped());
+}
+
+fn node_value(id: Option<NodeId>) -> JsValue {
+  return match id {
+    Some(id) => JsValue::from(id as f64),
+    None => JsValue::null(),
+  };
+}
+
+// DOM の操作の失敗は JS の TypeError にする
+fn dom_error(error: DomError) -> JsError {
+  return JsNativeError::typ().with_message(error.to_string()).into();
+}
+
+fn string_value(value: &str) -> JsValue {
+  return JsValue::from(js_string!(value));
+}
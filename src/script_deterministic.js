@@ -0,0 +1,29 @@
+// --deterministic のとき、実行するたびに変わる乱数と現在時刻を固定する
+(function () {
+  var seed = 0x2545f491;
+  Math.random = function () {
+    // xorshift32
+    seed ^= seed << 13;
+    seed ^= seed >>> 17;
+    seed ^= seed << 5;
+    return (seed >>> 0) / 4294967296;
+  };
+
+  var RealDate = Date;
+  var NOW = 0; // 1970-01-01T00:00:00Z
+  function FixedDate() {
+    if (!(this instanceof FixedDate)) {
+      return new RealDate(NOW).toString();
+    }
+    if (arguments.length === 0) {
+      return new RealDate(NOW);
+    }
+    var args = [null].concat(Array.prototype.slice.call(arguments));
+    return new (Function.prototype.bind.apply(RealDate, args))();
+  }
+  FixedDate.prototype = RealDate.prototype;
+  FixedDate.now = function () { return NOW; };
+  FixedDate.parse = RealDate.parse;
+  FixedDate.UTC = RealDate.UTC;
+  globalThis.Date = FixedDate;
+})();
@@ -0,0 +1,88 @@
+// スクリプトから見える document と console を作る。dom はノード番号で DOM を操作するネイティブ関数
+(function (dom) {
+  // ノードの番号はプロセスの中で一意な番号（dom::NodeId）なので、配列ではなく Map で引く
+  var wrappers = new Map();
+
+  // 同じノードには同じオブジェクトを返す
+  function wrap(id) {
+    if (id === null || id === undefined) {
+      return null;
+    }
+    var node = wrappers.get(id);
+    if (!node) {
+      node = Object.create(dom.tagName(id) === null ? Text.prototype : Element.prototype);
+      Object.defineProperty(node, "__id", { value: id });
+      wrappers.set(id, node);
+    }
+    return node;
+  }
+
+  function reflect(name) {
+    return {
+      get: function () { var value = dom.getAttribute(this.__id, name); return value === null ? "" : value; },
+      set: function (value) { dom.setAttribute(this.__id, name, String(value)); },
+    };
+  }
+
+  function Node() {}
+  Object.defineProperties(Node.prototype, {
+    parentNode: { get: function () { return wrap(dom.parent(this.__id)); } },
+    childNodes: { get: function () { return dom.children(this.__id).map(wrap); } },
+    firstChild: { get: function () { return this.childNodes[0] || null; } },
+    lastChild: { get: function () { var nodes = this.childNodes; return nodes[nodes.length - 1] || null; } },
+    textContent: {
+      get: function () { return dom.textContent(this.__id); },
+      set: function (value) { dom.setTextContent(this.__id, value === null ? "" : String(value)); },
+    },
+  });
+  Node.prototype.appendChild = function (child) {
+    dom.insertBefore(this.__id, child.__id, null);
+    return child;
+  };
+  Node.prototype.insertBefore = function (child, reference) {
+    dom.insertBefore(this.__id, child.__id, reference ? reference.__id : null);
+    return child;
+  };
+  Node.prototype.removeChild = function (child) {
+    dom.removeChild(this.__id, child.__id);
+    return child;
+  };
+  Node.prototype.remove = function () {
+    var parent = dom.parent(this.__id);
+    if (parent !== null) {
+      dom.removeChild(parent, this.__id);
+    }
+  };
+
+  function Element() {}
+  Element.prototype = Object.create(Node.prototype);
+  Object.defineProperties(Element.prototype, {
+    tagName: { get: function () { return dom.tagName(this.__id).toUpperCase(); } },
+    id: reflect("id"),
+    className: reflect("class"),
+    children: { get: function () { return this.childNodes.filter(function (node) { return node instanceof Element; }); } },
+  });
+  Element.prototype.getAttribute = function (name) { return dom.getAttribute(this.__id, String(name)); };
+  Element.prototype.setAttribute = function (name, value) { dom.setAttribute(this.__id, String(name), String(value)); };
+  Element.prototype.removeAttribute = function (name) { dom.removeAttribute(this.__id, String(name)); };
+  Element.prototype.hasAttribute = function (name) { return dom.getAttribute(this.__id, String(name)) !== null; };
+
+  function Text() {}
+  Text.prototype = Object.create(Node.prototype);
+  Object.defineProperty(Text.prototype, "data", Object.getOwnPropertyDescriptor(Node.prototype, "textContent"));
+
+  globalThis.window = globalThis;
+  globalThis.document = {
+    get documentElement() { return wrap(dom.root()); },
+    get head() { return wrap(dom.firstByTagName("head")); },
+    get body() { return wrap(dom.firstByTagName("body")); },
+    getElementById: function (id) { return wrap(dom.getElementById(String(id))); },
+    createElement: function (tagName) { return wrap(dom.createElement(String(tagName).toLowerCase())); },
+    createTextNode: function (data) { return wrap(dom.createTextNode(String(data))); },
+  };
+
+  function log() {
+    dom.log(Array.prototype.map.call(arguments, String).join(" "));
+  }
+  globalThis.console = { log: log, info: log, warn: log, error: log };
+})
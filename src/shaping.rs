@@ -0,0 +1,93 @@
+use rustybuzz::{Direction, Face, UnicodeBuffer};
+use std::ops::Range;
+use unicode_bidi::{BidiInfo, Level};
+use unicode_script::{Script, UnicodeScript};
+
+/**
+ * 文字列の字形を並べるところ（シェーピング）。
+ *
+ * フォントの GSUB と GPOS（合字、カーニング、結合文字の位置、アラビア文字の語頭形や語末形など）は rustybuzz で当てる。
+ * 文字列は Unicode の双方向アルゴリズム（UAX #9、unicode-bidi）で方向の同じランに分け、さらに用字（unicode-script）ごとに
+ * 分けてから形を作り、左から見た目の順に並べる。用字を持たない文字（空白、記号、結合文字）は前のランに含める。
+ * 段落の方向は左から右とする（direction プロパティはまだ見ない）。
+ * 並べ替えるのは 1 つの断片の中だけで、行の中の断片どうしは左から置いた順のまま
+ */
+
+// 形を作った字形。cluster は元の文字列の中のバイト位置、長さはフォントの単位（y は上向き）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShapedGlyph {
+  pub id: u16,
+  pub cluster: usize,
+  pub x_advance: i32,
+  pub x_offset: i32,
+  pub y_offset: i32,
+}
+
+// text を face で形にする。字形は左から見た目の順
+pub fn shape(face: &Face, text: &str) -> Vec<ShapedGlyph> {
+  let mut glyphs = Vec::new();
+  if text.is_empty() {
+    return glyphs;
+  }
+  let bidi = BidiInfo::new(text, Some(Level::ltr()));
+  for paragraph in &bidi.paragraphs {
+    let (levels, runs) = bidi.visual_runs(paragraph, paragraph.range.clone());
+    for run in runs {
+      let rtl = levels[run.start].is_rtl();
+      let mut scripts = script_runs(text, run);
+      // 右から左のランは、用字のランも右から並べる
+      if rtl {
+        scripts.reverse();
+      }
+      for range in scripts {
+        shape_run(face, text, range, rtl, &mut glyphs);
+      }
+    }
+  }
+  return glyphs;
+}
+
+// text の range を用字ごとに分ける
+fn script_runs(text: &str, range: Range<usize>) -> Vec<Range<usize>> {
+  let mut runs: Vec<Range<usize>> = Vec::new();
+  let mut current: Option<Script> = None;
+  for (offset, c) in text[range.clone()].char_indices() {
+    let index = range.start + offset;
+    let script = match c.script() {
+      Script::Common | Script::Inherited | Script::Unknown => None,
+      script => Some(script),
+    };
+    match (runs.last_mut(), script) {
+      (Some(run), Some(script)) if current.is_some() && current != Some(script) => {
+        run.end = index;
+        runs.push(index..range.end);
+        current = Some(script);
+      }
+      (Some(_), Some(script)) => current = Some(script),
+      (Some(_), None) => {}
+      (None, script) => {
+        runs.push(index..range.end);
+        current = script;
+      }
+    }
+  }
+  return runs;
+}
+
+// text の range を 1 つの方向と用字で形にして glyphs に足す
+fn shape_run(face: &Face, text: &str, range: Range<usize>, rtl: bool, glyphs: &mut Vec<ShapedGlyph>) {
+  let mut buffer = UnicodeBuffer::new();
+  buffer.push_str(&text[range.clone()]);
+  buffer.set_direction(if rtl { Direction::RightToLeft } else { Direction::LeftToRight });
+  buffer.guess_segment_properties();
+  let output = rustybuzz::shape(face, &[], buffer);
+  for (info, position) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+    glyphs.push(ShapedGlyph {
+      id: info.glyph_id as u16,
+      cluster: range.start + info.cluster as usize,
+      x_advance: position.x_advance,
+      x_offset: position.x_offset,
+      y_offset: position.y_offset,
+    });
+  }
+}
@@ -0,0 +1,762 @@
+use css::{AttributeSelector, Calc, Color, ColorStop, Combinator, ComplexSelector, Declaration, Gradient, Keyframe, Keyframes, Matrix, Operator, Origin, PseudoClass, PseudoElement, Rule, Selector, SimpleSelector, StyleSheet, Transform, Unit, Value};
+use dom::{self, AttrMap, Node, NodeType};
+use font::{self, FontContext};
+use images::Images;
+use options::EngineOptions;
+use paint::RenderOptions;
+use profile::Timings;
+use std::collections::HashMap;
+use style::PropertyMap;
+use thiserror::Error;
+use url::Url;
+use viewport;
+use LoadedDocument;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SnapshotError {
+  #[error("not a snapshot")]
+  InvalidMagic,
+  #[error("unsupported snapshot version {0}")]
+  UnsupportedVersion(u8),
+  #[error("snapshot is truncated")]
+  Truncated,
+  #[error("invalid tag {0} in snapshot")]
+  InvalidTag(u8),
+  #[error("invalid string in snapshot")]
+  InvalidString,
+  #[error("number too large in snapshot")]
+  Overflow,
+}
+
+/**
+ * パースした結果（DOM、スタイルシート、iframe の中身、あればノードごとの値）をバイナリのスナップショットにする。
+ * 読み込み直せばパースもスタイルシートの読み込みもしないので、同じドキュメントを
+ * いろいろなビューポートの大きさで何度も描画するときに使う。
+ *
+ *   形式: "SBSN"、バージョン（1 バイト）、ドキュメント、ノードごとの値（なければ 0）
+ *
+ * ノードのタグは 0 テキスト、1 要素、2 コメント。セレクターのタグは 0 単純セレクター、1 結合子でつないだもの（結合子は 0 子孫、1 子）。
+ * 単純セレクターの属性のタグは 0 [attr]、1 [attr=value]、2 [attr~=value]、疑似クラスのタグは 0 :first-child、1 :last-child、2 :nth-child（a と b は i32）、3 :hover、
+ * その後の疑似要素は 0 なし、1 ::before、2 ::after。
+ * 値のタグは 0 キーワード、1 長さ（px）、2 色、3 URL、4 文字列、5 数値、6 関数、7 リスト、8 px 以外の単位の付いた数値（単位は 1 s、2 ms、3 em、4 rem、5 %、6 vw、7 vh）。
+ * スタイルシートはルールの後に @keyframes を書く。ルールは宣言の後に出どころ（0 組み込み、1 ページ）、宣言は値の後に !important（0 か 1）を書く。
+ * 数と長さは LEB128。文字列は一度書いたものを番号で参照する（タグ名やクラス名、プロパティ名はほとんど同じものが続く）。
+ * @media の条件は保存しない（合わないルールは読み込んだときに取り除いてある）。
+ * 時間（timings）は保存しない。parallelism、tile_size、描き方（render）、ズーム、device_pixel_ratio と <meta name="viewport"> の扱いは読み込むときの設定にする
+ */
+
+const MAGIC: &'static [u8; 4] = b"SBSN";
+const VERSION: u8 = 6;
+
+// 読み込んだスナップショット。values は compute_values の結果（document.root_node の番号順）
+pub struct Snapshot {
+  pub document: LoadedDocument,
+  pub values: Option<Vec<PropertyMap>>,
+}
+
+// values は document.root_node と document.stylesheet から compute_values したもの
+pub fn encode(document: &LoadedDocument, values: Option<&[PropertyMap]>) -> Vec<u8> {
+  let mut writer = Writer { out: MAGIC.to_vec(), strings: HashMap::new() };
+  writer.out.push(VERSION);
+  writer.document(document);
+  match values {
+    Some(values) => {
+      writer.out.push(1);
+      writer.number(values.len());
+      for map in values {
+        writer.property_map(map);
+      }
+    }
+    None => writer.out.push(0),
+  }
+  return writer.out;
+}
+
+pub fn decode(bytes: &[u8], options: &EngineOptions) -> Result<Snapshot, SnapshotError> {
+  if bytes.len() < MAGIC.len() + 1 || &bytes[..MAGIC.len()] != MAGIC {
+    return Err(SnapshotError::InvalidMagic);
+  }
+  if bytes[MAGIC.len()] != VERSION {
+    return Err(SnapshotError::UnsupportedVersion(bytes[MAGIC.len()]));
+  }
+  let mut reader = Reader { bytes: bytes, pos: MAGIC.len() + 1, strings: Vec::new(), parallelism: options.parallelism, tile_size: options.tile_size, render: options.render_options(), fonts: font::for_document(options.deterministic) };
+  let mut document = reader.document()?;
+  if options.meta_viewport {
+    document.viewport_meta = viewport::find(&document.root_node);
+  }
+  document.zoom = viewport::zoom_factor(options.zoom);
+  document.device_pixel_ratio = viewport::zoom_factor(options.device_pixel_ratio);
+  let values = match reader.byte()? {
+    0 => None,
+    1 => {
+      let count = reader.number()?;
+      let mut values = Vec::with_capacity(reader.capacity(count));
+      for _ in 0..count {
+        values.push(reader.property_map()?);
+      }
+      Some(values)
+    }
+    tag => return Err(SnapshotError::InvalidTag(tag)),
+  };
+  return Ok(Snapshot { document: document, values: values });
+}
+
+struct Writer {
+  out: Vec<u8>,
+  strings: HashMap<String, usize>, // 書いた文字列と、その番号 + 1
+}
+
+impl Writer {
+  fn number(&mut self, mut value: usize) {
+    loop {
+      let byte = (value & 0x7f) as u8;
+      value >>= 7;
+      if value == 0 {
+        self.out.push(byte);
+        return;
+      }
+      self.out.push(byte | 0x80);
+    }
+  }
+
+  // 初めてのものは 0 と中身、2 回目からは番号 + 1
+  fn string(&mut self, value: &str) {
+    if let Some(&index) = self.strings.get(value) {
+      self.number(index);
+      return;
+    }
+    self.number(0);
+    self.number(value.len());
+    self.out.extend_from_slice(value.as_bytes());
+    let index = self.strings.len() + 1;
+    self.strings.insert(value.to_string(), index);
+  }
+
+  fn optional_string(&mut self, value: &Option<String>) {
+    match *value {
+      Some(ref value) => {
+        self.out.push(1);
+        self.string(value);
+      }
+      None => self.out.push(0),
+    }
+  }
+
+  fn simple_selector(&mut self, simple: &SimpleSelector) {
+    self.optional_string(&simple.tag_name);
+    self.optional_string(&simple.id);
+    self.number(simple.class.len());
+    for class in &simple.class {
+      self.string(class);
+    }
+    self.number(simple.attributes.len());
+    for attribute in &simple.attributes {
+      match *attribute {
+        AttributeSelector::Exists(ref name) => {
+          self.out.push(0);
+          self.string(name);
+        }
+        AttributeSelector::Equals(ref name, ref value) => {
+          self.out.push(1);
+          self.string(name);
+          self.string(value);
+        }
+        AttributeSelector::Includes(ref name, ref value) => {
+          self.out.push(2);
+          self.string(name);
+          self.string(value);
+        }
+      }
+    }
+    self.number(simple.pseudo_classes.len());
+    for pseudo_class in &simple.pseudo_classes {
+      match *pseudo_class {
+        PseudoClass::FirstChild => self.out.push(0),
+        PseudoClass::LastChild => self.out.push(1),
+        PseudoClass::NthChild(a, b) => {
+          self.out.push(2);
+          self.out.extend_from_slice(&a.to_le_bytes());
+          self.out.extend_from_slice(&b.to_le_bytes());
+        }
+        PseudoClass::Hover => self.out.push(3),
+        PseudoClass::Root => self.out.push(4),
+      }
+    }
+    self.out.push(match simple.pseudo_element {
+      None => 0,
+      Some(PseudoElement::Before) => 1,
+      Some(PseudoElement::After) => 2,
+    });
+  }
+
+  fn document(&mut self, document: &LoadedDocument) {
+    self.node(&document.root_node);
+    self.stylesheet(&document.stylesheet);
+    self.number(document.sources.len());
+    for url in &document.sources {
+      self.url(url);
+    }
+    self.url(&document.base_url);
+    self.number(document.frames.len());
+    for frame in &document.frames {
+      match *frame {
+        Some(ref frame) => {
+          self.out.push(1);
+          self.document(frame);
+        }
+        None => self.out.push(0),
+      }
+    }
+  }
+
+  fn node(&mut self, node: &Node) {
+    match node.node_type {
+      NodeType::Text(ref text) => {
+        self.out.push(0);
+        self.string(text);
+      }
+      NodeType::Element(ref elem) => {
+        self.out.push(1);
+        self.string(&elem.tag_name);
+        self.number(elem.attributes.len());
+        for (name, value) in &elem.attributes {
+          self.string(name);
+          self.string(value);
+        }
+      }
+      NodeType::Comment(ref data) => {
+        self.out.push(2);
+        self.string(data);
+      }
+    }
+    self.number(node.children.len());
+    for child in &node.children {
+      self.node(child);
+    }
+  }
+
+  fn stylesheet(&mut self, stylesheet: &StyleSheet) {
+    self.number(stylesheet.rules.len());
+    for rule in &stylesheet.rules {
+      self.number(rule.selectors.len());
+      for selector in &rule.selectors {
+        match *selector {
+          Selector::Simple(ref simple) => {
+            self.out.push(0);
+            self.simple_selector(simple);
+          }
+          Selector::Complex(ref complex) => {
+            self.out.push(1);
+            self.simple_selector(&complex.subject);
+            self.number(complex.ancestors.len());
+            for &(combinator, ref simple) in &complex.ancestors {
+              self.out.push(if combinator == Combinator::Child { 1 } else { 0 });
+              self.simple_selector(simple);
+            }
+          }
+        }
+      }
+      self.declarations(&rule.declarations);
+      self.out.push(if rule.origin == Origin::UserAgent { 0 } else { 1 });
+    }
+    self.number(stylesheet.keyframes.len());
+    for keyframes in &stylesheet.keyframes {
+      self.string(&keyframes.name);
+      self.number(keyframes.frames.len());
+      for frame in &keyframes.frames {
+        self.out.extend_from_slice(&frame.offset.to_le_bytes());
+        self.declarations(&frame.declarations);
+      }
+    }
+  }
+
+  fn declarations(&mut self, declarations: &[Declaration]) {
+    self.number(declarations.len());
+    for declaration in declarations {
+      self.string(&declaration.name);
+      self.value(&declaration.value);
+      self.out.push(declaration.important as u8);
+    }
+  }
+
+  fn value(&mut self, value: &Value) {
+    match *value {
+      Value::Keyword(ref keyword) => {
+        self.out.push(0);
+        self.string(keyword);
+      }
+      Value::Length(length, Unit::Px) => {
+        self.out.push(1);
+        self.out.extend_from_slice(&length.to_le_bytes());
+      }
+      Value::Length(length, ref unit) => {
+        self.out.push(8);
+        self.out.extend_from_slice(&length.to_le_bytes());
+        self.out.push(match *unit {
+          Unit::Px => 0,
+          Unit::S => 1,
+          Unit::Ms => 2,
+          Unit::Em => 3,
+          Unit::Rem => 4,
+          Unit::Percent => 5,
+          Unit::Vw => 6,
+          Unit::Vh => 7,
+          Unit::Fr => 8,
+          Unit::Deg => 9,
+        });
+      }
+      Value::ColorValue(color) => {
+        self.out.push(2);
+        self.out.extend_from_slice(&[color.r, color.g, color.b, color.a]);
+      }
+      Value::Url(ref url) => {
+        self.out.push(3);
+        self.string(url);
+      }
+      Value::Str(ref text) => {
+        self.out.push(4);
+        self.string(text);
+      }
+      Value::Number(number) => {
+        self.out.push(5);
+        self.out.extend_from_slice(&number.to_le_bytes());
+      }
+      Value::Function(ref name, ref args) => {
+        self.out.push(6);
+        self.string(name);
+        self.values(args);
+      }
+      Value::List(ref values) => {
+        self.out.push(7);
+        self.values(values);
+      }
+      Value::Gradient(ref gradient) => {
+        self.out.push(9);
+        self.out.extend_from_slice(&gradient.angle.to_le_bytes());
+        self.number(gradient.stops.len());
+        for stop in &gradient.stops {
+          self.out.extend_from_slice(&[stop.color.r, stop.color.g, stop.color.b, stop.color.a]);
+          match stop.position {
+            Some(ref position) => {
+              self.out.push(1);
+              self.value(position);
+            }
+            None => self.out.push(0),
+          }
+        }
+      }
+      Value::Var(ref name, ref fallback) => {
+        self.out.push(10);
+        self.string(name);
+        match *fallback {
+          Some(ref fallback) => {
+            self.out.push(1);
+            self.value(fallback);
+          }
+          None => self.out.push(0),
+        }
+      }
+      Value::Calc(ref calc) => {
+        self.out.push(11);
+        self.calc(calc);
+      }
+      Value::Transform(ref transform) => {
+        // 行列の 6 つの数と % の 4 つの係数
+        self.out.push(12);
+        let Matrix { a, b, c, d, e, f } = transform.matrix;
+        for number in [a, b, c, d, e, f].iter().chain(&transform.percent) {
+          self.out.extend_from_slice(&number.to_le_bytes());
+        }
+      }
+    }
+  }
+
+  // 値は 0、演算は 1 と演算子の後に左右の式
+  fn calc(&mut self, calc: &Calc) {
+    match *calc {
+      Calc::Value(ref value) => {
+        self.out.push(0);
+        self.value(value);
+      }
+      Calc::Operation(operator, ref left, ref right) => {
+        self.out.push(1);
+        self.out.push(match operator {
+          Operator::Add => 0,
+          Operator::Subtract => 1,
+          Operator::Multiply => 2,
+          Operator::Divide => 3,
+        });
+        self.calc(left);
+        self.calc(right);
+      }
+    }
+  }
+
+  fn values(&mut self, values: &[Value]) {
+    self.number(values.len());
+    for value in values {
+      self.value(value);
+    }
+  }
+
+  // HashMap の順番は毎回変わるので、名前順に書く（同じ入力からは同じスナップショットになる）
+  fn property_map(&mut self, map: &PropertyMap) {
+    let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    self.number(entries.len());
+    for (name, value) in entries {
+      self.string(name);
+      self.value(value);
+    }
+  }
+
+  fn url(&mut self, url: &Url) {
+    self.string(&url.scheme);
+    self.optional_string(&url.authority);
+    self.string(&url.path);
+    self.optional_string(&url.query);
+    self.optional_string(&url.fragment);
+  }
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+  strings: Vec<String>,
+  parallelism: usize,
+  tile_size: usize,
+  render: RenderOptions,
+  fonts: &'static FontContext,
+}
+
+impl<'a> Reader<'a> {
+  fn byte(&mut self) -> Result<u8, SnapshotError> {
+    let byte = *self.bytes.get(self.pos).ok_or(SnapshotError::Truncated)?;
+    self.pos += 1;
+    return Ok(byte);
+  }
+
+  fn take(&mut self, length: usize) -> Result<&'a [u8], SnapshotError> {
+    if self.bytes.len() - self.pos < length {
+      return Err(SnapshotError::Truncated);
+    }
+    let bytes = &self.bytes[self.pos..self.pos + length];
+    self.pos += length;
+    return Ok(bytes);
+  }
+
+  fn number(&mut self) -> Result<usize, SnapshotError> {
+    let mut value = 0usize;
+    let mut shift = 0;
+    loop {
+      let byte = self.byte()?;
+      if shift >= usize::BITS {
+        return Err(SnapshotError::Overflow);
+      }
+      value |= ((byte & 0x7f) as usize) << shift;
+      if byte & 0x80 == 0 {
+        return Ok(value);
+      }
+      shift += 7;
+    }
+  }
+
+  // 壊れたスナップショットの数で大きな領域を確保しないように、残りのバイト数で抑える
+  fn capacity(&self, count: usize) -> usize {
+    return count.min(self.bytes.len() - self.pos);
+  }
+
+  fn string(&mut self) -> Result<String, SnapshotError> {
+    let index = self.number()?;
+    if index > 0 {
+      return self.strings.get(index - 1).cloned().ok_or(SnapshotError::InvalidString);
+    }
+    let length = self.number()?;
+    let value = String::from_utf8(self.take(length)?.to_vec()).map_err(|_| SnapshotError::InvalidString)?;
+    self.strings.push(value.clone());
+    return Ok(value);
+  }
+
+  fn optional_string(&mut self) -> Result<Option<String>, SnapshotError> {
+    return match self.byte()? {
+      0 => Ok(None),
+      1 => Ok(Some(self.string()?)),
+      tag => Err(SnapshotError::InvalidTag(tag)),
+    };
+  }
+
+  fn simple_selector(&mut self) -> Result<SimpleSelector, SnapshotError> {
+    let tag_name = self.optional_string()?;
+    let id = self.optional_string()?;
+    let mut class = Vec::new();
+    for _ in 0..self.number()? {
+      class.push(self.string()?);
+    }
+    let mut attributes = Vec::new();
+    for _ in 0..self.number()? {
+      attributes.push(match self.byte()? {
+        0 => AttributeSelector::Exists(self.string()?),
+        1 => AttributeSelector::Equals(self.string()?, self.string()?),
+        2 => AttributeSelector::Includes(self.string()?, self.string()?),
+        tag => return Err(SnapshotError::InvalidTag(tag)),
+      });
+    }
+    let mut pseudo_classes = Vec::new();
+    for _ in 0..self.number()? {
+      pseudo_classes.push(match self.byte()? {
+        0 => PseudoClass::FirstChild,
+        1 => PseudoClass::LastChild,
+        2 => {
+          let bytes = self.take(8)?;
+          PseudoClass::NthChild(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]), i32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]))
+        }
+        3 => PseudoClass::Hover,
+        4 => PseudoClass::Root,
+        tag => return Err(SnapshotError::InvalidTag(tag)),
+      });
+    }
+    let pseudo_element = match self.byte()? {
+      0 => None,
+      1 => Some(PseudoElement::Before),
+      2 => Some(PseudoElement::After),
+      tag => return Err(SnapshotError::InvalidTag(tag)),
+    };
+    return Ok(SimpleSelector { tag_name: tag_name, id: id, class: class, attributes: attributes, pseudo_classes: pseudo_classes, pseudo_element: pseudo_element });
+  }
+
+  fn document(&mut self) -> Result<LoadedDocument, SnapshotError> {
+    let root_node = self.node()?;
+    let stylesheet = self.stylesheet()?;
+    let count = self.number()?;
+    let mut sources = Vec::with_capacity(self.capacity(count));
+    for _ in 0..count {
+      sources.push(self.url()?);
+    }
+    let base_url = self.url()?;
+    let count = self.number()?;
+    let mut frames = Vec::with_capacity(self.capacity(count));
+    for _ in 0..count {
+      frames.push(match self.byte()? {
+        0 => None,
+        1 => Some(self.document()?),
+        tag => return Err(SnapshotError::InvalidTag(tag)),
+      });
+    }
+    return Ok(LoadedDocument {
+      root_node: root_node,
+      stylesheet: stylesheet,
+      sources: sources,
+      base_url: base_url,
+      frames: frames,
+      images: Images::default(),
+      timings: Timings::default(),
+      parallelism: self.parallelism,
+      tile_size: self.tile_size,
+      viewport_meta: None,
+      zoom: 1.0,
+      device_pixel_ratio: 1.0,
+      render: self.render,
+      fonts: self.fonts,
+    });
+  }
+
+  fn node(&mut self) -> Result<Node, SnapshotError> {
+    let mut node = match self.byte()? {
+      0 => dom::text(self.string()?),
+      1 => {
+        let tag_name = self.string()?;
+        let mut attributes = AttrMap::new();
+        for _ in 0..self.number()? {
+          let name = self.string()?;
+          attributes.insert(name, self.string()?);
+        }
+        dom::elem(tag_name, attributes, Vec::new())
+      }
+      2 => dom::comment(self.string()?),
+      tag => return Err(SnapshotError::InvalidTag(tag)),
+    };
+    let count = self.number()?;
+    node.children.reserve(self.capacity(count));
+    for _ in 0..count {
+      node.children.push(self.node()?);
+    }
+    return Ok(node);
+  }
+
+  fn stylesheet(&mut self) -> Result<StyleSheet, SnapshotError> {
+    let count = self.number()?;
+    let mut rules = Vec::with_capacity(self.capacity(count));
+    for _ in 0..count {
+      let mut selectors = Vec::new();
+      for _ in 0..self.number()? {
+        match self.byte()? {
+          0 => selectors.push(Selector::Simple(self.simple_selector()?)),
+          1 => {
+            let subject = self.simple_selector()?;
+            let mut ancestors = Vec::new();
+            for _ in 0..self.number()? {
+              let combinator = match self.byte()? {
+                0 => Combinator::Descendant,
+                1 => Combinator::Child,
+                tag => return Err(SnapshotError::InvalidTag(tag)),
+              };
+              ancestors.push((combinator, self.simple_selector()?));
+            }
+            selectors.push(Selector::Complex(ComplexSelector { subject: subject, ancestors: ancestors }));
+          }
+          tag => return Err(SnapshotError::InvalidTag(tag)),
+        }
+      }
+      let declarations = self.declarations()?;
+      let origin = match self.byte()? {
+        0 => Origin::UserAgent,
+        1 => Origin::Author,
+        tag => return Err(SnapshotError::InvalidTag(tag)),
+      };
+      rules.push(Rule { selectors: selectors, declarations: declarations, media: Vec::new(), origin: origin });
+    }
+    let count = self.number()?;
+    let mut keyframes = Vec::with_capacity(self.capacity(count));
+    for _ in 0..count {
+      let name = self.string()?;
+      let mut frames = Vec::new();
+      for _ in 0..self.number()? {
+        let bytes = self.take(4)?;
+        let offset = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        frames.push(Keyframe { offset: offset, declarations: self.declarations()? });
+      }
+      keyframes.push(Keyframes { name: name, frames: frames });
+    }
+    return Ok(StyleSheet { rules: rules, keyframes: keyframes, imports: Vec::new() });
+  }
+
+  fn declarations(&mut self) -> Result<Vec<Declaration>, SnapshotError> {
+    let mut declarations = Vec::new();
+    for _ in 0..self.number()? {
+      let name = self.string()?;
+      let value = self.value()?;
+      let important = match self.byte()? {
+        0 => false,
+        1 => true,
+        tag => return Err(SnapshotError::InvalidTag(tag)),
+      };
+      declarations.push(Declaration { name: name, value: value, important: important });
+    }
+    return Ok(declarations);
+  }
+
+  fn value(&mut self) -> Result<Value, SnapshotError> {
+    return match self.byte()? {
+      0 => Ok(Value::Keyword(self.string()?)),
+      1 => {
+        let bytes = self.take(4)?;
+        Ok(Value::Length(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]), Unit::Px))
+      }
+      2 => {
+        let bytes = self.take(4)?;
+        Ok(Value::ColorValue(Color { r: bytes[0], g: bytes[1], b: bytes[2], a: bytes[3] }))
+      }
+      3 => Ok(Value::Url(self.string()?)),
+      4 => Ok(Value::Str(self.string()?)),
+      5 => {
+        let bytes = self.take(4)?;
+        Ok(Value::Number(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])))
+      }
+      6 => {
+        let name = self.string()?;
+        Ok(Value::Function(name, self.values()?))
+      }
+      7 => Ok(Value::List(self.values()?)),
+      8 => {
+        let bytes = self.take(5)?;
+        let length = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let unit = match bytes[4] {
+          0 => Unit::Px,
+          1 => Unit::S,
+          2 => Unit::Ms,
+          3 => Unit::Em,
+          4 => Unit::Rem,
+          5 => Unit::Percent,
+          6 => Unit::Vw,
+          7 => Unit::Vh,
+          8 => Unit::Fr,
+          9 => Unit::Deg,
+          tag => return Err(SnapshotError::InvalidTag(tag)),
+        };
+        Ok(Value::Length(length, unit))
+      }
+      9 => {
+        let bytes = self.take(4)?;
+        let angle = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let count = self.number()?;
+        let mut stops = Vec::with_capacity(self.capacity(count));
+        for _ in 0..count {
+          let bytes = self.take(4)?;
+          let color = Color { r: bytes[0], g: bytes[1], b: bytes[2], a: bytes[3] };
+          let position = if self.byte()? == 1 { Some(self.value()?) } else { None };
+          stops.push(ColorStop { color: color, position: position });
+        }
+        Ok(Value::Gradient(Gradient { angle: angle, stops: stops }))
+      }
+      10 => {
+        let name = self.string()?;
+        let fallback = if self.byte()? == 1 { Some(Box::new(self.value()?)) } else { None };
+        Ok(Value::Var(name, fallback))
+      }
+      11 => Ok(Value::Calc(Box::new(self.calc()?))),
+      12 => {
+        let bytes = self.take(40)?;
+        let n: Vec<f32> = bytes.chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        let matrix = Matrix { a: n[0], b: n[1], c: n[2], d: n[3], e: n[4], f: n[5] };
+        Ok(Value::Transform(Transform { matrix: matrix, percent: [n[6], n[7], n[8], n[9]] }))
+      }
+      tag => Err(SnapshotError::InvalidTag(tag)),
+    };
+  }
+
+  fn calc(&mut self) -> Result<Calc, SnapshotError> {
+    return match self.byte()? {
+      0 => Ok(Calc::Value(self.value()?)),
+      1 => {
+        let operator = match self.byte()? {
+          0 => Operator::Add,
+          1 => Operator::Subtract,
+          2 => Operator::Multiply,
+          3 => Operator::Divide,
+          tag => return Err(SnapshotError::InvalidTag(tag)),
+        };
+        let left = self.calc()?;
+        Ok(Calc::Operation(operator, Box::new(left), Box::new(self.calc()?)))
+      }
+      tag => Err(SnapshotError::InvalidTag(tag)),
+    };
+  }
+
+  fn values(&mut self) -> Result<Vec<Value>, SnapshotError> {
+    let count = self.number()?;
+    let mut values = Vec::with_capacity(self.capacity(count));
+    for _ in 0..count {
+      values.push(self.value()?);
+    }
+    return Ok(values);
+  }
+
+  fn property_map(&mut self) -> Result<PropertyMap, SnapshotError> {
+    let mut map = PropertyMap::new();
+    for _ in 0..self.number()? {
+      let name = self.string()?;
+      map.insert(name, self.value()?);
+    }
+    return Ok(map);
+  }
+
+  fn url(&mut self) -> Result<Url, SnapshotError> {
+    return Ok(Url {
+      scheme: self.string()?,
+      authority: self.optional_string()?,
+      path: self.string()?,
+      query: self.optional_string()?,
+      fragment: self.optional_string()?,
+    });
+  }
+}
@@ -1,14 +1,31 @@
+mod cascade;
+mod matcher;
+
+pub use self::cascade::{cascade_trace, CascadeEntry, CascadeTrace};
+pub use self::matcher::{query_selector_all, RuleIndex};
+use self::cascade::{inherit, resolve_font_relative, resolve_font_weight, resolve_transform, root_font_size, specified_values, cascade};
+use self::matcher::{matching_rules, AncestorFilter};
 use std::collections::HashMap;
-use dom::{Node, NodeType, ElementData};
-use css::{StyleSheet, Rule, Selector, SimpleSelector, Value, Specificity};
-use css::Value::Keyword;
+use std::ptr;
+use std::sync::OnceLock;
+use dom::{self, AttrMap, Node, NodeId, NodeType, ElementData};
+use css::{PseudoElement, parse_inline_style, StyleSheet, Unit, Value};
+use css::Value::{Keyword, Length};
+use counters;
+use form;
+use layout::Rect;
+use profile;
+use trace;
 
 /**
  * HTML Parser + CSS Parser から生成した DOM ツリー, Rules ツリーから Style ツリーを生成するところ
+ *
+ * セレクターマッチング（matcher.rs）で要素に一致したルールを探し、カスケード（cascade.rs）でその宣言から要素の値を決めて、
+ * 親の値を引き継ぐ。テキストとコメントのノードは、親の値のうち引き継ぐものだけを持つ。
+ * 兄弟の要素で一致するルールが同じなら、値を計算し直さずに使い回す
  */
 
-type PropertyMap = HashMap<String, Value>;
-type MatchedRule<'a> = (Specificity, &'a Rule);
+pub type PropertyMap = HashMap<String, Value>;
 
 #[derive(Debug)]
 pub struct StyledNode<'a> {
@@ -17,77 +34,509 @@ pub struct StyledNode<'a> {
   pub children: Vec<StyledNode<'a>>,
 }
 
-// セレクターマッチング（要素を見て simple_selector を探すだけ）
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
-  return match *selector {
-    Selector::Simple(ref simple_selector) => matches_simple_selector(elem, simple_selector)
+// セレクターに一致させる要素と、兄弟の要素の中での位置
+#[derive(Debug, Clone, Copy)]
+pub struct Element<'a> {
+  pub data: &'a ElementData,
+  pub index: usize, // 兄弟の要素の中で何番目か（1 から）
+  pub count: usize, // 兄弟の要素の数（自身を含む）
+}
+
+impl<'a> Element<'a> {
+  // ルート要素（兄弟はない）
+  pub fn root(data: &'a ElementData) -> Element<'a> {
+    return Element { data: data, index: 1, count: 1 };
   }
 }
 
-// 要素に対して一致するスタイルを探す(TODO: ハッシュ探索で高速化できる)
-fn matching_rules<'a>(elem: &ElementData, stylesheet: &'a StyleSheet) -> Vec<MatchedRule<'a>> {
-  return stylesheet.rules.iter().filter_map(|rule| match_rule(elem, rule)).collect();
+// children のそれぞれの、兄弟の要素の中での位置（何番目か、兄弟の要素の数）。要素でないノードは 0 番目
+fn sibling_positions(children: &[Node]) -> Vec<(usize, usize)> {
+  let count = children.iter().filter(|child| is_element(child)).count();
+  let mut index = 0;
+  return children
+    .iter()
+    .map(|child| {
+      if !is_element(child) {
+        return (0, count);
+      }
+      index += 1;
+      (index, count)
+    })
+    .collect();
 }
-fn match_rule<'a>(elem:&ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
-  return rule.selectors.iter()
-    .find(|selector| matches(elem, *selector))
-    .map(|selector| (selector.specificity(), rule))
+
+fn is_element(node: &Node) -> bool {
+  return match node.node_type {
+    NodeType::Element(_) => true,
+    NodeType::Text(_) | NodeType::Comment(_) => false,
+  };
 }
 
-// セレクターが要素と一致するかどうか調べる
-fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> bool {
+// node が要素なら、兄弟の中での位置 position と組にした Element
+fn element<'a>(node: &'a Node, position: (usize, usize)) -> Option<Element<'a>> {
+  return match node.node_type {
+    NodeType::Element(ref data) => Some(Element { data: data, index: position.0, count: position.1 }),
+    NodeType::Text(_) | NodeType::Comment(_) => None,
+  };
+}
 
-  // タグ名
-  if selector.tag_name.iter().any(|name| elem.tag_name != *name) {
-    return false;
+// node が要素なら ancestors に足して f を呼ぶ（呼んだ後に取り除く）
+fn with_ancestor<'a, R, F: FnOnce(&mut Vec<Element<'a>>) -> R>(node: &'a Node, position: (usize, usize), ancestors: &mut Vec<Element<'a>>, f: F) -> R {
+  if let Some(elem) = element(node, position) {
+    ancestors.push(elem);
+    let result = f(ancestors);
+    ancestors.pop();
+    return result;
   }
+  return f(ancestors);
+}
 
-  // ID
-  if selector.id.iter().any(|id| elem.id() != Some(id)) {
-    return false;
+// ドキュメント順（先行順）で index 番目のノードの祖先の要素（ルートから親まで）と、そのノード自身（要素なら）
+pub fn element_path<'a>(root: &'a Node, index: usize) -> (Vec<Element<'a>>, Option<Element<'a>>) {
+  fn find<'a>(node: &'a Node, position: (usize, usize), target: usize, index: &mut usize, ancestors: &mut Vec<Element<'a>>) -> Option<(Vec<Element<'a>>, Option<Element<'a>>)> {
+    if *index == target {
+      return Some((ancestors.clone(), element(node, position)));
+    }
+    *index += 1;
+    return with_ancestor(node, position, ancestors, |ancestors| {
+      node.children.iter().zip(sibling_positions(&node.children)).filter_map(|(child, position)| find(child, position, target, index, ancestors)).next()
+    });
   }
+  return find(root, (1, 1), index, &mut 0, &mut Vec::new()).unwrap_or((Vec::new(), None));
+}
+
+// ノードの値。テキストとコメントは親から引き継ぐものだけ。
+// フォームのコントロールなら、どのルールも指定していないプロパティに既定の値を足す（font-size を px にしてから）。
+// 要素に ::before か ::after があれば、その値も入れておく（pseudo_element_values）。filter は ancestors の AncestorFilter
+fn node_values(node: &Node, position: (usize, usize), ancestors: &[Element], filter: &AncestorFilter, rules: &RuleIndex, parent: Option<&PropertyMap>, root_font_size: Option<&Value>) -> PropertyMap {
+  return match node.node_type {
+    NodeType::Element(ref data) => {
+      let elem = Element { data: data, index: position.0, count: position.1 };
+      let mut values = specified_values(elem, ancestors, filter, rules, parent);
+      resolve_font_relative(&mut values, parent, root_font_size);
+      resolve_font_weight(&mut values, parent);
+      resolve_transform(&mut values);
+      form::default_style(node, &mut values);
+      if !rules.pseudo_element_rules.is_empty() {
+        let root_font_size = root_font_size.cloned().unwrap_or_else(|| self::root_font_size(&values));
+        for &pseudo_element in &[PseudoElement::Before, PseudoElement::After] {
+          if let Some(pseudo_values) = pseudo_element_values(elem, pseudo_element, ancestors, filter, rules, &values, &root_font_size) {
+            values.insert(pseudo_element.name().to_string(), pack_values(pseudo_values));
+          }
+        }
+      }
+      values
+    }
+    NodeType::Text(_) | NodeType::Comment(_) => {
+      let mut values = HashMap::new();
+      inherit(&mut values, parent);
+      values
+    }
+  };
+}
+
+// ルートとなる Node から StyleSheet を適用して、 Style ツリーを生成する。
+// content の counter() などは、ツリーができてから数える。@media のメディア特性（min-width など）のあるルールは使わない
+pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a StyleSheet) -> StyledNode<'a> {
+  return build_root(root, stylesheet, None);
+}
+
+// style_tree と同じで、@media のメディア特性を viewport（レイアウトに使う大きさ）と比べて、合うルールも使う
+pub fn style_tree_with_viewport<'a>(root: &'a Node, stylesheet: &'a StyleSheet, viewport: Rect) -> StyledNode<'a> {
+  return build_root(root, stylesheet, Some(viewport));
+}
+
+fn build_root<'a>(root: &'a Node, stylesheet: &'a StyleSheet, viewport: Option<Rect>) -> StyledNode<'a> {
+  let mut style_root = build_tree(root, &RuleIndex::new(stylesheet, viewport));
+  generate_pseudo_elements(&mut style_root);
+  counters::apply(&mut style_root);
+  return style_root;
+}
 
-  // Class
-  let elem_classes = elem.classes();
-  if selector.class.iter().any(|class| !elem_classes.contains(&**class)) {
-    return false;
+// root をルートにしたスタイルツリー（カウンターはまだ数えない）
+fn build_tree<'a>(root: &'a Node, rules: &RuleIndex) -> StyledNode<'a> {
+  let mut span = trace::span("style", "style");
+  if span.recording() {
+    span.arg("nodes", profile::count_nodes(root) as f64);
   }
+  let values = node_values(root, (1, 1), &[], &AncestorFilter::new(&[]), rules, None, None);
+  return build_style_tree(root, (1, 1), rules, &mut Vec::new(), values, None);
+}
 
-  return true;
+// 値が values の root のスタイルツリー。position は root の兄弟の要素の中での位置
+fn build_style_tree<'a>(root: &'a Node, position: (usize, usize), rules: &RuleIndex, ancestors: &mut Vec<Element<'a>>, values: PropertyMap, root_font_size: Option<&Value>) -> StyledNode<'a> {
+  let root_font_size = root_font_size.cloned().unwrap_or_else(|| self::root_font_size(&values));
+  let children_values = with_ancestor(root, position, ancestors, |ancestors| children_values(root, ancestors, rules, &values, &root_font_size));
+  let children = map_children(root, position, ancestors, children_values, |child, position, values, ancestors| build_style_tree(child, position, rules, ancestors, values, Some(&root_font_size)));
+  return StyledNode {
+    node: root,
+    specified_values: values,
+    children: children,
+  }
 }
 
-// 要素にスタイルを適用して、指定されたスタイルを返す
-fn specified_values(elem: &ElementData, stylesheet: &StyleSheet) -> PropertyMap {
-  let mut values = HashMap::new();
-  let mut rules = matching_rules(elem, stylesheet);
+// font-size の上限（px）。これより大きいものはこの大きさにする（字形を描くときにメモリを確保しきれないので）
+pub const MAX_FONT_SIZE: f32 = 10000.0;
+
+// 兄弟の要素の値を使い回せるか調べる、前の兄弟の要素の数（直前のものから）
+const STYLE_SHARING_CANDIDATES: usize = 8;
 
-  rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b)); // 詳細度の高いルールが後ろに行く（上書きされる）
-  for (_, rule) in rules {
-    for declaration in &rule.declarations {
-      values.insert(declaration.name.clone(), declaration.value.clone());
+// node の子それぞれの値（子の順）。ancestors は node までの祖先の要素、parent は node の値。
+// 同じ値になる前の兄弟の要素（RuleIndex::same_style）があれば、セレクターのマッチングをせずにその値を使う（style sharing）
+fn children_values(node: &Node, ancestors: &[Element], rules: &RuleIndex, parent: &PropertyMap, root_font_size: &Value) -> Vec<PropertyMap> {
+  let mut values: Vec<PropertyMap> = Vec::with_capacity(node.children.len());
+  let filter = AncestorFilter::new(ancestors); // 兄弟で同じ祖先
+  let mut candidates: Vec<(&ElementData, usize)> = Vec::new(); // 前の兄弟の要素と、values の中のその値の位置（新しいものが後ろ）
+  for (child, position) in node.children.iter().zip(sibling_positions(&node.children)) {
+    let shareable = match child.node_type {
+      NodeType::Element(ref data) if rules.can_share(child, data) => Some(data),
+      NodeType::Element(_) | NodeType::Text(_) | NodeType::Comment(_) => None,
+    };
+    if let Some(data) = shareable {
+      if let Some(&(_, index)) = candidates.iter().rev().find(|&&(candidate, _)| rules.same_style(candidate, data)) {
+        let shared = values[index].clone();
+        values.push(shared);
+        continue;
+      }
+    }
+    values.push(node_values(child, position, ancestors, &filter, rules, Some(parent), Some(root_font_size)));
+    if let Some(data) = shareable {
+      if candidates.len() == STYLE_SHARING_CANDIDATES {
+        candidates.remove(0);
+      }
+      candidates.push((data, values.len() - 1));
     }
   }
   return values;
 }
 
-// ルートとなる Node から StyleSheet を適用して、 Style ツリーを生成する。
-pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a StyleSheet) -> StyledNode<'a> {
-  return StyledNode {
-    node: root,
-    specified_values: match root.node_type {
-      NodeType::Element(ref elem) => specified_values(elem, stylesheet),
-      NodeType::Text(_) => HashMap::new(),
+// elem の疑似要素 pseudo_element の値。親は elem（値は parent）。content が none か normal（指定なし）なら、疑似要素は作らないので None
+fn pseudo_element_values(elem: Element, pseudo_element: PseudoElement, ancestors: &[Element], filter: &AncestorFilter, rules: &RuleIndex, parent: &PropertyMap, root_font_size: &Value) -> Option<PropertyMap> {
+  let matched = matching_rules(elem, ancestors, filter, rules, Some(pseudo_element));
+  if matched.is_empty() {
+    return None;
+  }
+  let mut values = cascade(matched, &[], Some(parent));
+  match values.get("content") {
+    None => return None,
+    Some(&Keyword(ref keyword)) if keyword == "none" || keyword == "normal" => return None,
+    Some(_) => {}
+  }
+  resolve_font_relative(&mut values, Some(parent), Some(root_font_size));
+  resolve_font_weight(&mut values, Some(parent));
+  resolve_transform(&mut values);
+  return Some(values);
+}
+
+// 値の並び（compute_values）はドキュメントのノードごとなので、疑似要素の値は要素の値の中に、疑似要素の名前（::before など）で入れておく。
+// 1 つの値にするために、プロパティごとに名前を関数名にした関数の値にして、名前順（比べられるように）のリストにする
+fn pack_values(values: PropertyMap) -> Value {
+  let mut properties: Vec<(String, Value)> = values.into_iter().collect();
+  properties.sort_by(|a, b| a.0.cmp(&b.0));
+  return Value::List(properties.into_iter().map(|(name, value)| Value::Function(name, vec![value])).collect());
+}
+
+fn unpack_values(packed: Value) -> PropertyMap {
+  let properties = match packed {
+    Value::List(properties) => properties,
+    _ => return HashMap::new(),
+  };
+  return properties
+    .into_iter()
+    .filter_map(|property| match property {
+      Value::Function(name, mut args) if args.len() == 1 => Some((name, args.remove(0))),
+      _ => None,
+    })
+    .collect();
+}
+
+// 疑似要素のスタイルツリーのノードが指すノード。DOM にはないので、タグ名が ::before か ::after の、属性も子もない要素を
+// 1 つずつ作っておいて、どの要素の疑似要素もそれを指す
+fn pseudo_element_node(pseudo_element: PseudoElement) -> &'static Node {
+  static BEFORE: OnceLock<Node> = OnceLock::new();
+  static AFTER: OnceLock<Node> = OnceLock::new();
+  let node = match pseudo_element {
+    PseudoElement::Before => &BEFORE,
+    PseudoElement::After => &AFTER,
+  };
+  return node.get_or_init(|| dom::elem(pseudo_element.name().to_string(), AttrMap::new(), Vec::new()));
+}
+
+// 要素の値に入れておいた ::before と ::after の値を取り出して、要素の最初と最後の子にする（子は持たない）。
+// content の attr() は、疑似要素を作った要素の属性の値にする（counter() は counters::apply で数える）
+fn generate_pseudo_elements(styled_node: &mut StyledNode) {
+  for child in &mut styled_node.children {
+    generate_pseudo_elements(child);
+  }
+  let elem = match styled_node.node.node_type {
+    NodeType::Element(ref elem) => elem,
+    NodeType::Text(_) | NodeType::Comment(_) => return,
+  };
+  for &pseudo_element in &[PseudoElement::Before, PseudoElement::After] {
+    let mut values = match styled_node.specified_values.remove(pseudo_element.name()) {
+      Some(packed) => unpack_values(packed),
+      None => continue,
+    };
+    if let Some(content) = values.get_mut("content") {
+      resolve_attr(content, elem);
+    }
+    let pseudo = StyledNode { node: pseudo_element_node(pseudo_element), specified_values: values, children: Vec::new() };
+    match pseudo_element {
+      PseudoElement::Before => styled_node.children.insert(0, pseudo),
+      PseudoElement::After => styled_node.children.push(pseudo),
+    }
+  }
+}
+
+// content の中の attr(name) を、elem の属性 name の値（なければ空）の文字列にする
+fn resolve_attr(content: &mut Value, elem: &ElementData) {
+  let attribute = match *content {
+    Value::Function(ref name, ref args) if name == "attr" => match args.first() {
+      Some(&Keyword(ref attribute)) => attribute.clone(),
+      _ => return,
     },
-    children: root.children.iter().map(|child| style_tree(child, stylesheet)).collect(),
+    Value::List(ref mut values) => {
+      for value in values {
+        resolve_attr(value, elem);
+      }
+      return;
+    }
+    _ => return,
+  };
+  *content = Value::Str(elem.attributes.get(&attribute).cloned().unwrap_or_default());
+}
+
+// スタイルツリーの代わりに、各ノードの値だけをドキュメント順（先行順）に並べて返す。
+// DOM とスタイルシートが変わらなければ、style_tree_from_values で何度でもスタイルツリーを組み立て直せる
+pub fn compute_values(root: &Node, stylesheet: &StyleSheet) -> Vec<PropertyMap> {
+  return compute_values_with_viewport(root, stylesheet, None);
+}
+
+// viewport があれば、@media のメディア特性をその大きさと比べる（style_tree_with_viewport と同じ）
+pub fn compute_values_with_viewport(root: &Node, stylesheet: &StyleSheet, viewport: Option<Rect>) -> Vec<PropertyMap> {
+  let mut values = Vec::new();
+  collect_values(build_tree(root, &RuleIndex::new(stylesheet, viewport)), &mut values);
+  return values;
+}
+
+// スタイルツリーの値を、先行順に values に移す
+fn collect_values(styled_node: StyledNode, values: &mut Vec<PropertyMap>) {
+  values.push(styled_node.specified_values);
+  for child in styled_node.children {
+    collect_values(child, values);
   }
 }
 
+// node の子それぞれに f を呼んだ結果（子の順）。f には子と、兄弟の中での位置と、items の中のその子のものと、node を足した祖先を渡す
+#[cfg(not(feature = "rayon"))]
+fn map_children<'a, T, R, F>(node: &'a Node, position: (usize, usize), ancestors: &mut Vec<Element<'a>>, items: Vec<T>, f: F) -> Vec<R>
+where
+  F: Fn(&'a Node, (usize, usize), T, &mut Vec<Element<'a>>) -> R,
+{
+  return with_ancestor(node, position, ancestors, |ancestors| {
+    node.children.iter().zip(sibling_positions(&node.children)).zip(items).map(|((child, position), item)| f(child, position, item, ancestors)).collect()
+  });
+}
+
+// rayon フィーチャーでは、兄弟の部分木を別々のスレッドで作る（祖先はスレッドごとに複製する）。結果は子の順なので、順番に作ったものと同じ
+#[cfg(feature = "rayon")]
+fn map_children<'a, T, R, F>(node: &'a Node, position: (usize, usize), ancestors: &mut Vec<Element<'a>>, items: Vec<T>, f: F) -> Vec<R>
+where
+  T: Send,
+  R: Send,
+  F: Fn(&'a Node, (usize, usize), T, &mut Vec<Element<'a>>) -> R + Sync,
+{
+  use rayon::prelude::*;
+  return with_ancestor(node, position, ancestors, |ancestors| {
+    node
+      .children
+      .par_iter()
+      .zip(sibling_positions(&node.children))
+      .zip(items)
+      .map_with(ancestors.clone(), |ancestors, ((child, position), item)| f(child, position, item, ancestors))
+      .collect()
+  });
+}
+
+pub fn style_tree_from_values<'a>(root: &'a Node, values: &[PropertyMap]) -> StyledNode<'a> {
+  let mut index = 0;
+  let mut style_root = build_from_values(root, values, &mut index);
+  generate_pseudo_elements(&mut style_root);
+  counters::apply(&mut style_root);
+  return style_root;
+}
+
+fn build_from_values<'a>(node: &'a Node, values: &[PropertyMap], index: &mut usize) -> StyledNode<'a> {
+  let specified_values = values.get(*index).cloned().unwrap_or_default();
+  *index += 1;
+  return StyledNode {
+    node: node,
+    specified_values: specified_values,
+    children: node.children.iter().map(|child| build_from_values(child, values, index)).collect(),
+  };
+}
+
+// 計算した値をノードの番号ごとに残しておくスタイル。DOM を書き換えたら、変わったノードの restyle で
+// その子孫だけを計算し直して、style_tree でセレクターのマッチングをせずにスタイルツリーを組み立てる。
+// viewport があれば、@media のメディア特性をその大きさと比べる（style_tree_with_viewport と同じ）
+#[derive(Debug, Default)]
+pub struct StyledTree {
+  values: HashMap<NodeId, PropertyMap>,
+  viewport: Option<Rect>,
+}
+
+impl StyledTree {
+  pub fn new(root: &Node, stylesheet: &StyleSheet) -> StyledTree {
+    let mut tree = StyledTree::default();
+    tree.restyle(root, stylesheet, root.id);
+    return tree;
+  }
+
+  pub fn with_viewport(root: &Node, stylesheet: &StyleSheet, viewport: Rect) -> StyledTree {
+    let mut tree = StyledTree { values: HashMap::new(), viewport: Some(viewport) };
+    tree.restyle(root, stylesheet, root.id);
+    return tree;
+  }
+
+  // ビューポートの大きさが変わったら、合う @media のルールが変わるので、すべて計算し直して変わったノードの番号を返す
+  pub fn set_viewport(&mut self, root: &Node, stylesheet: &StyleSheet, viewport: Rect) -> Vec<NodeId> {
+    if self.viewport == Some(viewport) {
+      return Vec::new();
+    }
+    self.viewport = Some(viewport);
+    return self.restyle(root, stylesheet, root.id);
+  }
+
+  // node_id のノードと子孫の値を計算し直して、値が変わった（新しくできた）ノードの番号を返す（LayoutTree::mark_dirty に渡す）。
+  // 子を足したり取り除いたりしたときは、兄弟の位置（:first-child など）が変わるので親を渡す
+  pub fn restyle(&mut self, root: &Node, stylesheet: &StyleSheet, node_id: NodeId) -> Vec<NodeId> {
+    let mut changed = Vec::new();
+    let rules = RuleIndex::new(stylesheet, self.viewport);
+    if root.id == node_id {
+      let values = node_values(root, (1, 1), &[], &AncestorFilter::new(&[]), &rules, None, None);
+      self.restyle_subtree(root, (1, 1), values, &rules, &mut Vec::new(), None, &mut changed);
+      return changed;
+    }
+    let root_font_size = self.values.get(&root.id).map(root_font_size);
+    if let Some((node, position, mut ancestors, parent)) = find_node(root, (1, 1), node_id, &mut Vec::new()) {
+      let parent = self.values.get(&parent).cloned();
+      let values = node_values(node, position, &ancestors, &AncestorFilter::new(&ancestors), &rules, parent.as_ref(), root_font_size.as_ref());
+      self.restyle_subtree(node, position, values, &rules, &mut ancestors, root_font_size.as_ref(), &mut changed);
+    }
+    return changed;
+  }
+
+  // 値を計算し直した node（値は values）と、その子孫
+  fn restyle_subtree<'a>(&mut self, node: &'a Node, position: (usize, usize), values: PropertyMap, rules: &RuleIndex, ancestors: &mut Vec<Element<'a>>, root_font_size: Option<&Value>, changed: &mut Vec<NodeId>) {
+    let root_font_size = root_font_size.cloned().unwrap_or_else(|| self::root_font_size(&values));
+    with_ancestor(node, position, ancestors, |ancestors| {
+      let children_values = children_values(node, ancestors, rules, &values, &root_font_size);
+      for ((child, position), child_values) in node.children.iter().zip(sibling_positions(&node.children)).zip(children_values) {
+        self.restyle_subtree(child, position, child_values, rules, ancestors, Some(&root_font_size), changed);
+      }
+    });
+    if self.values.get(&node.id) != Some(&values) {
+      changed.push(node.id);
+      self.values.insert(node.id, values);
+    }
+  }
+
+  // 残しておいた値からスタイルツリーを組み立てる。値のないノード（restyle していない新しいノード）は空の値
+  pub fn style_tree<'a>(&self, root: &'a Node) -> StyledNode<'a> {
+    fn build<'a>(node: &'a Node, values: &HashMap<NodeId, PropertyMap>) -> StyledNode<'a> {
+      return StyledNode {
+        node: node,
+        specified_values: values.get(&node.id).cloned().unwrap_or_default(),
+        children: node.children.iter().map(|child| build(child, values)).collect(),
+      };
+    }
+    let mut style_root = build(root, &self.values);
+    generate_pseudo_elements(&mut style_root);
+    counters::apply(&mut style_root);
+    return style_root;
+  }
+}
+
+// 番号が id のノードと、兄弟の中での位置、祖先の要素、親の番号
+fn find_node<'a>(node: &'a Node, position: (usize, usize), id: NodeId, ancestors: &mut Vec<Element<'a>>) -> Option<(&'a Node, (usize, usize), Vec<Element<'a>>, NodeId)> {
+  return with_ancestor(node, position, ancestors, |ancestors| {
+    for (child, position) in node.children.iter().zip(sibling_positions(&node.children)) {
+      if child.id == id {
+        return Some((child, position, ancestors.clone(), node.id));
+      }
+      if let Some(found) = find_node(child, position, id, ancestors) {
+        return Some(found);
+      }
+    }
+    return None;
+  });
+}
+
 // display: block
 #[derive(PartialEq)]
 pub enum Display{
   Inline,
   Block,
+  ListItem,    // マーカー（counters::apply で作った文字列）を左に描くブロック
+  Table,       // 子を行にして、セルを列に並べるブロック
+  TableRowGroup, // 表の中では中の行を表の行にする（<tbody> など）。表の外ではブロック
+  TableRow,    // 表の行。表の外ではブロック
+  TableCell,   // 表のセル。表の外では新しいブロック整形コンテキストを作るブロック
+  InlineBlock, // 行に並べる、中をブロックとしてレイアウトするボックス
+  FlowRoot,    // 新しいブロック整形コンテキストを作るブロック
+  Flex,        // 子を flex アイテムにして並べるブロック
+  Grid,        // 子をグリッドのセルに並べるブロック
+  None,
+}
+
+// float: left
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Float {
+  None,
+  Left,
+  Right,
+}
+
+// clear: both
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Clear {
+  None,
+  Left,
+  Right,
+  Both,
+}
+
+// position: absolute
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Position {
+  Static,
+  Relative,
+  Absolute,
+  Fixed,
+}
+
+// overflow: hidden。scroll と auto は（スクロールできないので）hidden と同じ
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overflow {
+  Visible,
+  Hidden,
+  Clip,
+}
+
+// border-top-style: dashed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BorderStyle {
   None,
+  Solid,
+  Dashed,
+  Dotted,
+}
+
+impl Position {
+  // 流れから外して、包含ブロックの中に top、right、bottom、left で置くもの
+  pub fn is_out_of_flow(self) -> bool {
+    return self == Position::Absolute || self == Position::Fixed;
+  }
 }
 
 impl<'a> StyledNode<'a> {
@@ -101,15 +550,247 @@ impl<'a> StyledNode<'a> {
     return self.value(name).unwrap_or_else(|| self.value(fallback_name).unwrap_or_else(|| default.clone()));
   }
 
-  // display を設定
+  // ::before か ::after の疑似要素（generate_pseudo_elements で作ったもの）なら、その種類
+  pub fn pseudo_element(&self) -> Option<PseudoElement> {
+    return [PseudoElement::Before, PseudoElement::After].iter().cloned().find(|&pseudo_element| ptr::eq(self.node, pseudo_element_node(pseudo_element)));
+  }
+
+  // 子の代わりに描く文字列（counters::apply で content を文字列にしたもの）
+  pub fn content(&self) -> Option<&str> {
+    return match self.specified_values.get("content") {
+      Some(&Value::Str(ref text)) => Some(text),
+      _ => None,
+    };
+  }
+
+  // display: list-item のマーカーの文字列（counters::apply で list-item カウンターと list-style-type から作ったもの）
+  pub fn marker(&self) -> Option<&str> {
+    return match self.specified_values.get(counters::MARKER) {
+      Some(&Value::Str(ref text)) => Some(text),
+      _ => None,
+    };
+  }
+
+  // list-style-type。初期値は disc
+  pub fn list_style_type(&self) -> String {
+    return match self.value("list-style-type") {
+      Some(Keyword(keyword)) => keyword,
+      _ => "disc".to_string(),
+    };
+  }
+
+  // display を設定。コメントは描かない。float するものと絶対配置のものはブロックにする（inline-block は block）
   pub fn display(&self) -> Display {
+    if let NodeType::Comment(_) = self.node.node_type {
+      return Display::None;
+    }
+    let blockified = self.float() != Float::None || self.position().is_out_of_flow();
     match self.value("display") {
       Some(Keyword(s)) => match &*s {
         "block" => Display::Block,
+        "flow-root" => Display::FlowRoot,
+        "list-item" => Display::ListItem,
+        "table" => Display::Table,
+        "table-row-group" | "table-header-group" | "table-footer-group" => Display::TableRowGroup,
+        "table-row" => Display::TableRow,
+        "table-cell" => Display::TableCell,
+        "flex" => Display::Flex,
+        "grid" => Display::Grid,
         "none" => Display::None,
+        "inline-block" if !blockified => Display::InlineBlock,
+        _ if blockified => Display::Block,
         _ => Display::Inline, // 初期値は inline
       },
+      _ if blockified => Display::Block,
       _ => Display::Inline
     }
   }
+
+  // float。初期値は none。絶対配置のものは float しない
+  pub fn float(&self) -> Float {
+    if self.position().is_out_of_flow() {
+      return Float::None;
+    }
+    return match self.value("float") {
+      Some(Keyword(ref s)) if s == "left" => Float::Left,
+      Some(Keyword(ref s)) if s == "right" => Float::Right,
+      _ => Float::None,
+    };
+  }
+
+  // clear。初期値は none
+  pub fn clear(&self) -> Clear {
+    return match self.value("clear") {
+      Some(Keyword(ref s)) => match &**s {
+        "left" => Clear::Left,
+        "right" => Clear::Right,
+        "both" => Clear::Both,
+        _ => Clear::None,
+      },
+      _ => Clear::None,
+    };
+  }
+
+  // z-index。auto（初期値）なら None
+  pub fn z_index(&self) -> Option<i32> {
+    return match self.value("z-index") {
+      Some(Value::Number(number)) => Some(number as i32),
+      _ => None,
+    };
+  }
+
+  // position。初期値は static
+  pub fn position(&self) -> Position {
+    return match self.value("position") {
+      Some(Keyword(ref s)) => match &**s {
+        "relative" => Position::Relative,
+        "absolute" => Position::Absolute,
+        "fixed" => Position::Fixed,
+        _ => Position::Static,
+      },
+      _ => Position::Static,
+    };
+  }
+
+  // visibility。hidden と collapse なら描かない（場所は取る）。引き継ぐプロパティなので、
+  // hidden の要素の中でも visible を指定した子孫は描く
+  pub fn visible(&self) -> bool {
+    return match self.value("visibility") {
+      Some(Keyword(ref s)) => s != "hidden" && s != "collapse",
+      _ => true,
+    };
+  }
+
+  // overflow。初期値は visible
+  pub fn overflow(&self) -> Overflow {
+    return match self.value("overflow") {
+      Some(Keyword(ref s)) => match &**s {
+        "hidden" | "scroll" | "auto" => Overflow::Hidden,
+        "clip" => Overflow::Clip,
+        _ => Overflow::Visible,
+      },
+      _ => Overflow::Visible,
+    };
+  }
+
+  // side（top、right、bottom、left）の border-style。書かなければ幅だけで線を描くので solid。
+  // none と hidden は線を描かない。double などはまだ描けないので solid にする
+  pub fn border_style(&self, side: &str) -> BorderStyle {
+    return match self.value(&format!("border-{}-style", side)).or_else(|| self.value("border-style")) {
+      Some(Keyword(ref s)) => match &**s {
+        "none" | "hidden" => BorderStyle::None,
+        "dashed" => BorderStyle::Dashed,
+        "dotted" => BorderStyle::Dotted,
+        _ => BorderStyle::Solid,
+      },
+      _ => BorderStyle::Solid,
+    };
+  }
+
+  // side の border の幅。線を描かない border-style なら 0
+  pub fn border_width(&self, side: &str) -> Value {
+    if self.border_style(side) == BorderStyle::None {
+      return Length(0.0, Unit::Px);
+    }
+    return self.lookup(&format!("border-{}-width", side), "border-width", &Length(0.0, Unit::Px));
+  }
+}
+
+// 値がないときの初期値（CSS の仕様のもの。border の幅だけは、このエンジンでは書かなければ 0）。
+// computed_style で使い、computed_values はここのプロパティをすべて並べる
+const INITIAL_VALUES: &str = "
+  align-items: stretch; background-color: transparent; background-image: none;
+  background-position: 0% 0%; background-repeat: repeat; background-size: auto;
+  border-top-color: currentcolor; border-right-color: currentcolor; border-bottom-color: currentcolor; border-left-color: currentcolor;
+  border-top-style: none; border-right-style: none; border-bottom-style: none; border-left-style: none;
+  border-top-width: 0px; border-right-width: 0px; border-bottom-width: 0px; border-left-width: 0px;
+  border-top-left-radius: 0px; border-top-right-radius: 0px; border-bottom-right-radius: 0px; border-bottom-left-radius: 0px;
+  border-spacing: 0px; bottom: auto; box-sizing: content-box; clear: none; color: black; column-gap: normal; direction: ltr; display: inline;
+  flex-basis: auto; flex-direction: row; flex-grow: 0; flex-shrink: 1; flex-wrap: nowrap; float: none;
+  font-size: 16px; font-style: normal; font-weight: 400; height: auto; hyphens: manual; justify-content: flex-start; left: auto;
+  letter-spacing: normal; line-height: normal; list-style-position: outside; list-style-type: disc;
+  margin-top: 0px; margin-right: 0px; margin-bottom: 0px; margin-left: 0px;
+  max-height: none; max-width: none; min-height: 0px; min-width: 0px; opacity: 1; overflow: visible; overflow-wrap: normal;
+  padding-top: 0px; padding-right: 0px; padding-bottom: 0px; padding-left: 0px;
+  position: static; right: auto; row-gap: normal; table-layout: auto; text-align: start;
+  text-decoration-color: currentcolor; text-decoration-line: none; text-decoration-style: solid; text-indent: 0px; text-transform: none;
+  top: auto; transform: none; transform-origin: 50% 50%; vertical-align: baseline; visibility: visible; white-space: normal; width: auto; word-break: normal; word-spacing: normal; z-index: auto;
+";
+
+fn initial_values() -> &'static PropertyMap {
+  static INITIAL: OnceLock<PropertyMap> = OnceLock::new();
+  return INITIAL.get_or_init(|| parse_inline_style(INITIAL_VALUES).into_iter().map(|declaration| (declaration.name, declaration.value)).collect());
+}
+
+// node の property の計算値（getComputedStyle のようなもの）。引き継ぎと em、rem はスタイルの計算で済んでいるので、
+// 指定がなければ初期値にして（initial も）、currentcolor を color の値に、border の幅を線を描かなければ 0 に、
+// thin などのキーワードと vw、vh を px にする（% はレイアウトまで決まらないのでそのまま）。知らないプロパティで指定もなければ None
+pub fn computed_style(node: &StyledNode, property: &str) -> Option<Value> {
+  return computed(node, property, None);
+}
+
+// computed_style と同じで、vw と vh を viewport（レイアウトに使う大きさ）で px にする
+pub fn computed_style_with_viewport(node: &StyledNode, property: &str, viewport: Rect) -> Option<Value> {
+  return computed(node, property, Some(viewport));
+}
+
+// node のすべてのプロパティ（初期値のあるものと指定したもの）の計算値を名前順に並べる（--dump-computed-style 用）
+pub fn computed_values(node: &StyledNode, viewport: Option<Rect>) -> Vec<(String, Value)> {
+  let mut names: Vec<&String> = initial_values().keys().chain(node.specified_values.keys()).collect();
+  names.sort();
+  names.dedup();
+  return names.into_iter().filter_map(|name| computed(node, name, viewport).map(|value| (name.clone(), value))).collect();
+}
+
+fn computed(node: &StyledNode, property: &str, viewport: Option<Rect>) -> Option<Value> {
+  let value = match node.specified_values.get(property) {
+    Some(&Keyword(ref keyword)) if keyword == "initial" => initial_values().get(property)?.clone(),
+    Some(value) => value.clone(),
+    // 書かなければ幅だけで線を描くので、幅があれば solid（StyledNode::border_style）
+    None if property.starts_with("border-") && property.ends_with("-style") => {
+      let side = &property["border-".len()..property.len() - "-style".len()];
+      match node.border_style(side) {
+        BorderStyle::Solid if node.specified_values.contains_key(&format!("border-{}-width", side)) => Keyword("solid".to_string()),
+        _ => initial_values().get(property)?.clone(),
+      }
+    }
+    None => initial_values().get(property)?.clone(),
+  };
+  if property.starts_with("border-") && property.ends_with("-width") {
+    let side = &property["border-".len()..property.len() - "-width".len()];
+    if node.border_style(side) == BorderStyle::None {
+      return Some(Length(0.0, Unit::Px));
+    }
+  }
+  return Some(absolutize(value, node, property, viewport));
+}
+
+fn absolutize(value: Value, node: &StyledNode, property: &str, viewport: Option<Rect>) -> Value {
+  return match value {
+    Keyword(ref keyword) if keyword == "currentcolor" && property != "color" => computed(node, "color", viewport).unwrap_or_else(|| value.clone()),
+    Keyword(ref keyword) if property.ends_with("-width") => match &**keyword {
+      "thin" => Length(1.0, Unit::Px),
+      "medium" => Length(3.0, Unit::Px),
+      "thick" => Length(5.0, Unit::Px),
+      _ => value.clone(),
+    },
+    Length(length, Unit::Vw) => match viewport {
+      Some(viewport) => Length(length * viewport.width / 100.0, Unit::Px),
+      None => Length(length, Unit::Vw),
+    },
+    Length(length, Unit::Vh) => match viewport {
+      Some(viewport) => Length(length * viewport.height / 100.0, Unit::Px),
+      None => Length(length, Unit::Vh),
+    },
+    Value::List(values) => Value::List(values.into_iter().map(|value| absolutize(value, node, property, viewport)).collect()),
+    // px と数値だけになれば計算する
+    Value::Calc(calc) => {
+      let resolve = |value: &Value| match absolutize(value.clone(), node, property, viewport) {
+        value @ Length(_, Unit::Px) | value @ Value::Number(_) => Some(value),
+        _ => None,
+      };
+      calc.evaluate(&resolve).unwrap_or(Value::Calc(calc))
+    }
+    value => value,
+  };
 }
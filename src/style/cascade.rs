@@ -0,0 +1,393 @@
+use css::{self, Declaration, Origin, Selector, Specificity, StyleSheet, Rule, Unit, Value, parse_inline_style};
+use css::Value::{Keyword, Length};
+use layout::Rect;
+use std::collections::HashMap;
+
+use super::matcher::{matching_rules, matching_selector, AncestorFilter, MatchedRule, RuleIndex};
+use super::{Element, PropertyMap, MAX_FONT_SIZE};
+
+/**
+ * カスケードと値の引き継ぎ。
+ *
+ * 要素に一致したルールの宣言を、出どころと !important、詳細度、ソースの順で並べて、後のものほど優先して値を決める。
+ * INHERITED_PROPERTIES のプロパティは、どのルールも指定していなければ親の値を引き継ぐ（値が inherit なら、ほかのプロパティも親の値にする）
+ *
+ * カスタムプロパティ（--main-color など）も親から引き継ぐ。ほかのプロパティの値の var() は、カスケードの後にその要素のカスタムプロパティの値に置き換える
+ * （margin などの一括指定は、置き換えてから個別のプロパティに分ける）。置き換えられなければ、そのプロパティは指定しなかったことにする
+ *
+ * em と rem の長さは、ここで font-size の単位（ふつうは px）にする。% と vw、vh は包含ブロックやビューポートの大きさで決まるので、
+ * レイアウトで px にする
+ */
+
+// font-size がないときの大きさ（rem の基準にもなる）
+const INITIAL_FONT_SIZE: f32 = 16.0;
+
+// font-size のキーワードの大きさ（medium が INITIAL_FONT_SIZE）
+const FONT_SIZE_KEYWORDS: &[(&str, f32)] = &[
+  ("xx-small", 9.0),
+  ("x-small", 10.0),
+  ("small", 13.0),
+  ("medium", 16.0),
+  ("large", 18.0),
+  ("x-large", 24.0),
+  ("xx-large", 32.0),
+  ("xxx-large", 48.0),
+];
+
+// font-size: larger と smaller で親の大きさに掛ける倍率
+const FONT_SIZE_STEP: f32 = 1.2;
+
+// 親から引き継ぐプロパティ
+const INHERITED_PROPERTIES: &[&str] = &[
+  "border-spacing",
+  "color",
+  "cursor",
+  "direction",
+  "font",
+  "font-family",
+  "font-size",
+  "font-style",
+  "font-weight",
+  "hyphens",
+  "letter-spacing",
+  "line-height",
+  "list-style",
+  "list-style-position",
+  "list-style-type",
+  "overflow-wrap",
+  "quotes",
+  "text-align",
+  "text-indent",
+  "text-transform",
+  "visibility",
+  "white-space",
+  "word-break",
+  "word-spacing",
+  "word-wrap",
+];
+
+// カスケードの記録。要素に一致したルール 1 つ分
+#[derive(Debug)]
+pub struct CascadeEntry<'a> {
+  pub rule_index: usize,      // スタイルシートの中でのルールの番号（組み込みのスタイルシートが先）
+  pub selector: &'a Selector, // 一致したセレクター（一致したものの中で詳細度が一番高いもの）
+  pub rule: &'a Rule,
+  pub applied: Vec<bool>,     // rule.declarations のそれぞれが、最終的な値になったかどうか
+}
+
+// 要素のカスケードの記録。rules は一致したルール、inline は style="" の宣言と、それが最終的な値になったかどうか
+#[derive(Debug)]
+pub struct CascadeTrace<'a> {
+  pub rules: Vec<CascadeEntry<'a>>,
+  pub inline: Vec<(Declaration, bool)>,
+}
+
+// 要素に一致したルールを、適用する順（出どころ、詳細度の順、同じならスタイルシートの順）に返す。
+// ほかの宣言で上書きされた宣言は applied が false になる（!important の宣言は前のルールにあっても勝つ）。
+// viewport は @media のメディア特性と比べる大きさ（RuleIndex と同じ）
+pub fn cascade_trace<'a>(elem: Element, ancestors: &[Element], stylesheet: &'a StyleSheet, viewport: Option<Rect>) -> CascadeTrace<'a> {
+  let size = viewport.map(|viewport| (viewport.width, viewport.height));
+  let mut matched: Vec<(Specificity, usize, &Selector, &Rule)> = stylesheet
+    .rules
+    .iter()
+    .enumerate()
+    .filter(|&(_, rule)| rule.matches_viewport(size))
+    .filter_map(|(index, rule)| matching_selector(elem, ancestors, rule).map(|selector| (selector.specificity(), index, selector, rule)))
+    .collect();
+  matched.sort_by(|a, b| (a.3.origin, a.0, a.1).cmp(&(b.3.origin, b.0, b.1)));
+  let inline = inline_style(elem);
+
+  // specified_values と同じ順に並べて、後ろから見て初めて出てきたプロパティが最終的な値。
+  // style="" の宣言は i が matched.len() のものとして、同じ優先順位のルールの後ろに置く
+  let mut order: Vec<(u8, usize, usize)> = Vec::new();
+  for (i, &(_, _, _, rule)) in matched.iter().enumerate() {
+    for (j, declaration) in rule.declarations.iter().enumerate() {
+      order.push((precedence(rule.origin, declaration), i, j));
+    }
+  }
+  for (j, declaration) in inline.iter().enumerate() {
+    order.push((precedence(Origin::Author, declaration), matched.len(), j));
+  }
+  order.sort_by_key(|&(precedence, _, _)| precedence);
+  let mut applied: Vec<Vec<bool>> = matched.iter().map(|&(_, _, _, rule)| vec![false; rule.declarations.len()]).collect();
+  applied.push(vec![false; inline.len()]);
+  let mut seen = Vec::new();
+  for &(_, i, j) in order.iter().rev() {
+    let name = if i < matched.len() { &matched[i].3.declarations[j].name } else { &inline[j].name };
+    if !seen.contains(&name) {
+      seen.push(name);
+      applied[i][j] = true;
+    }
+  }
+
+  let inline_applied = applied.pop().unwrap_or_default();
+  return CascadeTrace {
+    rules: matched
+      .into_iter()
+      .zip(applied)
+      .map(|((_, index, selector, rule), applied)| CascadeEntry { rule_index: index, selector: selector, rule: rule, applied: applied })
+      .collect(),
+    inline: inline.iter().cloned().zip(inline_applied).collect(),
+  };
+}
+
+// style="" 属性の宣言
+fn inline_style(elem: Element) -> Vec<Declaration> {
+  return elem.data.attributes.get("style").map(|style| parse_inline_style(style)).unwrap_or_default();
+}
+
+// カスケードでの優先順位。大きいほど優先する
+// （通常の組み込み < 通常のページ < ページの !important < 組み込みの !important）
+fn precedence(origin: Origin, declaration: &Declaration) -> u8 {
+  return match (declaration.important, origin) {
+    (false, Origin::UserAgent) => 0,
+    (false, Origin::Author) => 1,
+    (true, Origin::Author) => 2,
+    (true, Origin::UserAgent) => 3,
+  };
+}
+
+// 要素にスタイルを適用して、指定されたスタイルを返す
+pub(super) fn specified_values(elem: Element, ancestors: &[Element], filter: &AncestorFilter, rules: &RuleIndex, parent: Option<&PropertyMap>) -> PropertyMap {
+  return cascade(matching_rules(elem, ancestors, filter, rules, None), &inline_style(elem), parent);
+}
+
+// 一致したルール matched と style="" の宣言 inline から、指定されたスタイルを決める
+pub(super) fn cascade(matched: Vec<MatchedRule>, inline: &[Declaration], parent: Option<&PropertyMap>) -> PropertyMap {
+  let mut values = HashMap::new();
+  let mut declarations: Vec<(u8, bool, Specificity, usize, &Declaration)> = matched
+    .into_iter()
+    .flat_map(|(specificity, index, rule)| rule.declarations.iter().map(move |declaration| (precedence(rule.origin, declaration), false, specificity, index, declaration)))
+    .collect();
+  // style="" はページの宣言で、どのセレクターよりも詳細度が高い
+  declarations.extend(inline.iter().map(|declaration| (precedence(Origin::Author, declaration), true, (0, 0, 0), 0, declaration)));
+
+  // 優先順位、詳細度、スタイルシートの順に並べる（同じルールの中は書いた順のまま）。後ろのものが上書きする
+  declarations.sort_by(|a, b| (a.0, a.1, a.2, a.3).cmp(&(b.0, b.1, b.2, b.3)));
+  let custom = custom_properties(declarations.iter().map(|&(_, _, _, _, declaration)| declaration), parent);
+  for (_, _, _, _, declaration) in declarations {
+    if css::is_custom_property(&declaration.name) {
+      continue;
+    }
+    if !declaration.value.contains_var() {
+      values.insert(declaration.name.clone(), declaration.value.clone());
+      continue;
+    }
+    // 置き換えられなければ、前の宣言の値も消す（一括指定ならその個別のプロパティの値を）
+    let (value, unset) = match substitute(&declaration.value, &custom, &mut Vec::new()) {
+      Some(value) => (value, false),
+      None => (Keyword("unset".to_string()), true),
+    };
+    for longhand in css::expand_shorthand(Declaration { name: declaration.name.clone(), value: value, important: declaration.important }) {
+      if unset {
+        values.remove(&longhand.name);
+      } else {
+        values.insert(longhand.name, longhand.value);
+      }
+    }
+  }
+  values.extend(custom);
+  inherit(&mut values, parent);
+  return values;
+}
+
+// カスタムプロパティの値。親から引き継いだものを declarations（カスケードの順）で上書きして、値の var() を置き換える。
+// 置き換えられないもの（循環しているものも）は除く
+fn custom_properties<'a, I: Iterator<Item = &'a Declaration>>(declarations: I, parent: Option<&PropertyMap>) -> PropertyMap {
+  let mut specified: PropertyMap = parent.map_or_else(HashMap::new, |parent| parent.iter().filter(|&(name, _)| css::is_custom_property(name)).map(|(name, value)| (name.clone(), value.clone())).collect());
+  for declaration in declarations.filter(|declaration| css::is_custom_property(&declaration.name)) {
+    specified.insert(declaration.name.clone(), declaration.value.clone());
+  }
+  return specified
+    .iter()
+    .filter_map(|(name, value)| substitute(value, &specified, &mut vec![name.clone()]).map(|value| (name.clone(), value)))
+    .collect();
+}
+
+// value の var() をカスタムプロパティ custom の値（なければ代わりの値）に置き換える。どちらもなければ None。
+// visiting は置き換えている途中のカスタムプロパティで、もう一度出てきたら循環しているのでないものとする
+fn substitute(value: &Value, custom: &PropertyMap, visiting: &mut Vec<String>) -> Option<Value> {
+  return match *value {
+    Value::Var(ref name, ref fallback) => {
+      let found = match custom.get(name) {
+        Some(value) if !visiting.contains(name) => {
+          visiting.push(name.clone());
+          let found = substitute(value, custom, visiting);
+          visiting.pop();
+          found
+        }
+        _ => None,
+      };
+      match found {
+        Some(value) => Some(value),
+        None => substitute(fallback.as_ref()?, custom, visiting),
+      }
+    }
+    // 置き換えた値が空白区切りなら、その値を並べる
+    Value::List(ref values) => {
+      let mut list = Vec::new();
+      for value in values {
+        match substitute(value, custom, visiting)? {
+          Value::List(values) => list.extend(values),
+          value => list.push(value),
+        }
+      }
+      Some(if list.len() == 1 { list.remove(0) } else { Value::List(list) })
+    }
+    Value::Function(ref name, ref args) => {
+      let args = args.iter().map(|arg| substitute(arg, custom, visiting)).collect::<Option<Vec<Value>>>()?;
+      css::function_value(name.clone(), args)
+    }
+    Value::Calc(ref calc) => {
+      let mut calc = calc.clone();
+      let mut found = true;
+      calc.for_each_value_mut(&mut |value| match substitute(value, custom, visiting) {
+        Some(substituted) => *value = substituted,
+        None => found = false,
+      });
+      if found && calc.is_valid() { Some(Value::Calc(calc)) } else { None }
+    }
+    ref value => Some(value.clone()),
+  };
+}
+
+// 親の値を引き継ぐ。inherit と書いたプロパティは、親に値がなければ指定しなかったことにする。
+// unset は引き継ぐプロパティなら inherit、ほかは指定しなかったことにする。カスタムプロパティはすべて引き継ぐ
+pub(super) fn inherit(values: &mut PropertyMap, parent: Option<&PropertyMap>) {
+  let unset: Vec<String> = values.iter().filter(|&(name, value)| *value == Keyword("unset".to_string()) && !INHERITED_PROPERTIES.contains(&&**name)).map(|(name, _)| name.clone()).collect();
+  for name in unset {
+    values.remove(&name);
+  }
+  let explicit: Vec<String> = values.iter().filter(|&(_, value)| *value == Keyword("inherit".to_string()) || *value == Keyword("unset".to_string())).map(|(name, _)| name.clone()).collect();
+  for name in explicit {
+    match parent.and_then(|parent| parent.get(&name)) {
+      Some(value) => values.insert(name, value.clone()),
+      None => values.remove(&name),
+    };
+  }
+  if let Some(parent) = parent {
+    for name in INHERITED_PROPERTIES {
+      if !values.contains_key(*name) {
+        if let Some(value) = parent.get(*name) {
+          values.insert(name.to_string(), value.clone());
+        }
+      }
+    }
+    for (name, value) in parent.iter().filter(|&(name, _)| css::is_custom_property(name)) {
+      if !values.contains_key(name) {
+        values.insert(name.clone(), value.clone());
+      }
+    }
+  }
+}
+
+// em、rem（font-size と line-height では % も）の長さを、基準にする font-size に掛ける。font-size のキーワードも長さにする。
+// root_font_size はルート要素の font-size で、ルート要素自身なら None
+pub(super) fn resolve_font_relative(values: &mut PropertyMap, parent: Option<&PropertyMap>, root_font_size: Option<&Value>) {
+  let initial = Length(INITIAL_FONT_SIZE, Unit::Px);
+  let parent_font_size = parent.and_then(|parent| parent.get("font-size")).cloned().unwrap_or_else(|| initial.clone());
+  let root_font_size = root_font_size.cloned().unwrap_or_else(|| initial.clone());
+  let font_size = match values.get("font-size") {
+    Some(&Length(factor, Unit::Em)) => scale(&parent_font_size, factor),
+    Some(&Length(percent, Unit::Percent)) => scale(&parent_font_size, percent / 100.0),
+    Some(&Length(factor, Unit::Rem)) => scale(&root_font_size, factor),
+    // calc() の em と % も親の font-size に対する倍率。px と数値だけになれば計算する
+    Some(&Value::Calc(ref calc)) => {
+      let mut calc = calc.clone();
+      calc.for_each_value_mut(&mut |value| match *value {
+        Length(percent, Unit::Percent) => *value = scale(&parent_font_size, percent / 100.0),
+        _ => resolve_em(value, &parent_font_size, &root_font_size),
+      });
+      let resolve = |value: &Value| match *value {
+        Length(_, Unit::Px) | Value::Number(_) => Some(value.clone()),
+        _ => None,
+      };
+      calc.evaluate(&resolve).unwrap_or(Value::Calc(calc))
+    }
+    Some(&Keyword(ref keyword)) if keyword == "larger" => scale(&parent_font_size, FONT_SIZE_STEP),
+    Some(&Keyword(ref keyword)) if keyword == "smaller" => scale(&parent_font_size, 1.0 / FONT_SIZE_STEP),
+    Some(&Keyword(ref keyword)) => match FONT_SIZE_KEYWORDS.iter().find(|&&(name, _)| name == keyword) {
+      Some(&(_, size)) => Length(size, Unit::Px),
+      None => Keyword(keyword.clone()),
+    },
+    Some(value) => value.clone(),
+    None => parent_font_size.clone(),
+  };
+  let font_size = match font_size {
+    Length(size, Unit::Px) if size > MAX_FONT_SIZE => Length(MAX_FONT_SIZE, Unit::Px),
+    font_size => font_size,
+  };
+  for value in values.values_mut() {
+    resolve_em(value, &font_size, &root_font_size);
+  }
+  // line-height の % も要素の font-size に対する割合にする（子は割合でなく長さを引き継ぐ）
+  if let Some(&Length(percent, Unit::Percent)) = values.get("line-height") {
+    values.insert("line-height".to_string(), scale(&font_size, percent / 100.0));
+  }
+  if values.contains_key("font-size") {
+    values.insert("font-size".to_string(), font_size);
+  }
+}
+
+// font-weight を 1 から 1000 の数値にする。bolder と lighter は親の太さから決める
+pub(super) fn resolve_font_weight(values: &mut PropertyMap, parent: Option<&PropertyMap>) {
+  let parent_weight = match parent.and_then(|parent| parent.get("font-weight")) {
+    Some(&Value::Number(weight)) => weight,
+    _ => 400.0,
+  };
+  let weight = match values.get("font-weight") {
+    Some(&Value::Number(weight)) => weight.max(1.0).min(1000.0),
+    Some(&Keyword(ref keyword)) => match &**keyword {
+      "normal" => 400.0,
+      "bold" => 700.0,
+      "bolder" if parent_weight < 350.0 => 400.0,
+      "bolder" if parent_weight < 550.0 => 700.0,
+      "bolder" => parent_weight.max(900.0),
+      "lighter" if parent_weight < 550.0 => parent_weight.min(100.0),
+      "lighter" if parent_weight < 750.0 => 400.0,
+      "lighter" => 700.0,
+      _ => return,
+    },
+    _ => return,
+  };
+  values.insert("font-weight".to_string(), Value::Number(weight));
+}
+
+fn resolve_em(value: &mut Value, font_size: &Value, root_font_size: &Value) {
+  match *value {
+    Length(factor, Unit::Em) => *value = scale(font_size, factor),
+    Length(factor, Unit::Rem) => *value = scale(root_font_size, factor),
+    Value::List(ref mut values) | Value::Function(_, ref mut values) => {
+      for value in values {
+        resolve_em(value, font_size, root_font_size);
+      }
+    }
+    Value::Calc(ref mut calc) => calc.for_each_value_mut(&mut |value| resolve_em(value, font_size, root_font_size)),
+    _ => {}
+  }
+}
+
+// font-size の長さの factor 倍（vw などはそのままの単位で）。長さでなければ初期の大きさの factor 倍
+fn scale(font_size: &Value, factor: f32) -> Value {
+  return match *font_size {
+    Length(size, ref unit) => Length(size * factor, unit.clone()),
+    _ => Length(INITIAL_FONT_SIZE * factor, Unit::Px),
+  };
+}
+
+// transform の関数の並びを掛け合わせて 1 つの Transform にする（em は px にしてから）。読めなければ指定しなかったことにする
+pub(super) fn resolve_transform(values: &mut PropertyMap) {
+  let transform = match values.get("transform") {
+    Some(&Keyword(_)) | Some(&Value::Transform(_)) | None => return,
+    Some(value) => css::transform(value),
+  };
+  match transform {
+    Some(transform) => values.insert("transform".to_string(), Value::Transform(transform)),
+    None => values.remove("transform"),
+  };
+}
+
+// ルート要素の font-size（rem の基準）
+pub(super) fn root_font_size(values: &PropertyMap) -> Value {
+  return values.get("font-size").cloned().unwrap_or(Length(INITIAL_FONT_SIZE, Unit::Px));
+}
@@ -0,0 +1,335 @@
+use css::{AttributeSelector, Combinator, ComplexSelector, PseudoClass, PseudoElement, Rule, Selector, SimpleSelector, Specificity, StyleSheet};
+use dom::{ElementData, Node};
+use form;
+use layout::Rect;
+use std::collections::{HashMap, HashSet};
+
+use super::{element, sibling_positions, with_ancestor, Element};
+
+/**
+ * セレクターマッチング。
+ *
+ * 結合子（div p、ul > li）のあるセレクターは祖先の要素を見るので、ツリーをたどるときにルートから親までの要素（ancestors）を渡す。
+ * :first-child などは兄弟の中での位置を見るので、要素はその位置と一緒に Element で渡す。
+ * 要素ごとに試すルールは RuleIndex で主体の ID、クラス、タグ名から絞り、結合子のあるものは先に AncestorFilter で祖先を確かめる
+ */
+
+// 一致したルール。詳細度と、スタイルシートの中でのルールの番号（ソースの順。StyleSheet::append したものは後ろに続く）
+pub(super) type MatchedRule<'a> = (Specificity, usize, &'a Rule);
+
+// セレクターマッチング。ancestors は elem の祖先の要素（ルートから親まで）。
+// pseudo_element があれば elem のその疑似要素に、なければ elem 自身に一致させる
+fn matches(elem: Element, ancestors: &[Element], selector: &Selector, pseudo_element: Option<PseudoElement>) -> bool {
+  if selector.subject().pseudo_element != pseudo_element {
+    return false;
+  }
+  return match *selector {
+    Selector::Simple(ref simple_selector) => matches_simple_selector(elem, ancestors.is_empty(), simple_selector),
+    Selector::Complex(ref complex) => matches_complex_selector(elem, ancestors, complex),
+  }
+}
+
+fn matches_complex_selector(elem: Element, ancestors: &[Element], selector: &ComplexSelector) -> bool {
+  return matches_simple_selector(elem, ancestors.is_empty(), &selector.subject) && matches_ancestors(ancestors, &selector.ancestors);
+}
+
+// parts（右から順）を、ancestors の親の側から一致させていく。子孫結合子は一致する祖先をすべて試す
+fn matches_ancestors(ancestors: &[Element], parts: &[(Combinator, SimpleSelector)]) -> bool {
+  let (&(combinator, ref selector), rest) = match parts.split_first() {
+    Some(first) => first,
+    None => return true,
+  };
+  return match combinator {
+    Combinator::Child => match ancestors.split_last() {
+      Some((&parent, above)) => matches_simple_selector(parent, above.is_empty(), selector) && matches_ancestors(above, rest),
+      None => false,
+    },
+    Combinator::Descendant => (0..ancestors.len()).rev().any(|i| matches_simple_selector(ancestors[i], i == 0, selector) && matches_ancestors(&ancestors[..i], rest)),
+  };
+}
+
+// selectors のどれかに一致する要素を、ドキュメント順（先行順）の番号で返す（compute_values と同じ番号）
+pub fn query_selector_all(root: &Node, selectors: &[Selector]) -> Vec<usize> {
+  let mut indices = Vec::new();
+  let mut index = 0;
+  collect_matches(root, (1, 1), selectors, &mut Vec::new(), &mut index, &mut indices);
+  return indices;
+}
+
+fn collect_matches<'a>(node: &'a Node, position: (usize, usize), selectors: &[Selector], ancestors: &mut Vec<Element<'a>>, index: &mut usize, indices: &mut Vec<usize>) {
+  if let Some(elem) = element(node, position) {
+    if selectors.iter().any(|selector| matches(elem, ancestors, selector, None)) {
+      indices.push(*index);
+    }
+  }
+  *index += 1;
+  with_ancestor(node, position, ancestors, |ancestors| {
+    for (child, position) in node.children.iter().zip(sibling_positions(&node.children)) {
+      collect_matches(child, position, selectors, ancestors, index, indices);
+    }
+  });
+}
+
+// スタイルシートのルールを、セレクターの主体（いちばん右の単純セレクター）の ID、クラス、タグ名で引けるようにしたもの。
+// 要素は自分の ID、クラス、タグ名のところと、どれもないセレクター（* など）のルールだけを試せばよい。
+// ID があれば ID、なければ最初のクラス、なければタグ名のところに入れる（セレクターが複数あれば、それぞれのところに入れる）。
+// viewport の大きさに合わない @media のメディア特性の中のルールは入れない（viewport がなければメディア特性のあるものは入れない）
+pub struct RuleIndex<'s> {
+  stylesheet: &'s StyleSheet,
+  by_id: HashMap<&'s str, Vec<usize>>,
+  by_class: HashMap<&'s str, Vec<usize>>,
+  by_tag: HashMap<&'s str, Vec<usize>>,
+  universal: Vec<usize>, // ルールの番号（スタイルシートの順）
+  positional: HashSet<usize>, // 主体に :first-child、:last-child、:nth-child() のあるルール
+  pub(super) pseudo_element_rules: HashSet<usize>, // 主体に ::before か ::after のあるルール
+  attribute_names: Vec<&'s str>, // 主体の属性セレクターの属性名
+  ancestor_hashes: Vec<Vec<Vec<u64>>>, // ルールのセレクターごとの、祖先の部分のタグ名、ID、クラスのハッシュ（AncestorFilter で調べる）
+}
+
+impl<'s> RuleIndex<'s> {
+  pub fn new(stylesheet: &'s StyleSheet, viewport: Option<Rect>) -> RuleIndex<'s> {
+    let mut index = RuleIndex {
+      stylesheet: stylesheet,
+      by_id: HashMap::new(),
+      by_class: HashMap::new(),
+      by_tag: HashMap::new(),
+      universal: Vec::new(),
+      positional: HashSet::new(),
+      pseudo_element_rules: HashSet::new(),
+      attribute_names: Vec::new(),
+      ancestor_hashes: vec![Vec::new(); stylesheet.rules.len()],
+    };
+    let size = viewport.map(|viewport| (viewport.width, viewport.height));
+    for (i, rule) in stylesheet.rules.iter().enumerate().filter(|&(_, rule)| rule.matches_viewport(size)) {
+      index.ancestor_hashes[i] = rule.selectors.iter().map(ancestor_hashes).collect();
+      for selector in &rule.selectors {
+        let subject = selector.subject();
+        if subject.pseudo_classes.iter().any(|&pseudo_class| pseudo_class != PseudoClass::Hover) {
+          index.positional.insert(i);
+        }
+        if subject.pseudo_element.is_some() {
+          index.pseudo_element_rules.insert(i);
+        }
+        for attribute in &subject.attributes {
+          let name = match *attribute {
+            AttributeSelector::Exists(ref name) | AttributeSelector::Equals(ref name, _) | AttributeSelector::Includes(ref name, _) => &**name,
+          };
+          if !index.attribute_names.contains(&name) {
+            index.attribute_names.push(name);
+          }
+        }
+        let bucket = if let Some(ref id) = subject.id {
+          index.by_id.entry(id).or_insert_with(Vec::new)
+        } else if let Some(class) = subject.class.first() {
+          index.by_class.entry(class).or_insert_with(Vec::new)
+        } else if let Some(ref tag_name) = subject.tag_name {
+          index.by_tag.entry(tag_name).or_insert_with(Vec::new)
+        } else {
+          &mut index.universal
+        };
+        // 同じルールの別のセレクターが同じところに入っていれば足さない
+        if bucket.last() != Some(&i) {
+          bucket.push(i);
+        }
+      }
+    }
+    return index;
+  }
+
+  // elem に一致するかもしれないルールの番号（スタイルシートの順）
+  fn candidates(&self, elem: &ElementData) -> Vec<usize> {
+    let mut candidates = self.universal.clone();
+    if let Some(rules) = elem.id().and_then(|id| self.by_id.get(&**id)) {
+      candidates.extend(rules);
+    }
+    for class in elem.classes() {
+      if let Some(rules) = self.by_class.get(class) {
+        candidates.extend(rules);
+      }
+    }
+    if let Some(rules) = self.by_tag.get(&*elem.tag_name) {
+      candidates.extend(rules);
+    }
+    candidates.sort();
+    candidates.dedup();
+    return candidates;
+  }
+
+  // elem の値を兄弟の要素と使い回してよいか。フォームのコントロールは属性や中身で既定の値が変わり、
+  // 主体に :first-child などのあるルールが候補にあれば、兄弟の中の位置で一致するかが変わるので使い回さない
+  pub(super) fn can_share(&self, node: &Node, elem: &ElementData) -> bool {
+    if form::Control::from_node(node).is_some() {
+      return false;
+    }
+    return self.positional.is_empty() || !self.candidates(elem).iter().any(|i| self.positional.contains(i));
+  }
+
+  // 同じ親の要素 a と b（どちらも can_share のもの）が、同じルールに一致して同じ値になるか。
+  // タグ名と :hover、id、class、style 属性と、属性セレクターが見る属性が同じなら同じ
+  pub(super) fn same_style(&self, a: &ElementData, b: &ElementData) -> bool {
+    return a.tag_name == b.tag_name && a.hover == b.hover && ["id", "class", "style"].iter().chain(&self.attribute_names).all(|name| a.attributes.get(*name) == b.attributes.get(*name));
+  }
+}
+
+// 祖先の要素のタグ名（小文字にしたもの）、ID、クラスのハッシュを入れたブルームフィルター。
+// 結合子のあるセレクターは、祖先の部分のタグ名、ID、クラスがどれかの祖先になければ一致しないので、祖先をたどる前に除ける。
+// 入っていないと分かれば確かにないが、入っていそうでもないことがあるので、そのときはたどって確かめる
+#[derive(Clone)]
+pub(super) struct AncestorFilter {
+  bits: [u64; ANCESTOR_FILTER_WORDS],
+}
+
+// AncestorFilter の大きさ（64 ビットの数。1024 ビット）
+const ANCESTOR_FILTER_WORDS: usize = 16;
+
+impl AncestorFilter {
+  pub(super) fn new(ancestors: &[Element]) -> AncestorFilter {
+    let mut filter = AncestorFilter { bits: [0; ANCESTOR_FILTER_WORDS] };
+    for ancestor in ancestors {
+      for hash in element_hashes(ancestor.data) {
+        filter.insert(hash);
+      }
+    }
+    return filter;
+  }
+
+  // ハッシュの下位 10 ビットずつ 2 つの位置のビット
+  fn positions(hash: u64) -> [usize; 2] {
+    let size = (ANCESTOR_FILTER_WORDS * 64) as u64;
+    return [(hash % size) as usize, ((hash >> 10) % size) as usize];
+  }
+
+  fn insert(&mut self, hash: u64) {
+    for position in AncestorFilter::positions(hash) {
+      self.bits[position / 64] |= 1 << (position % 64);
+    }
+  }
+
+  fn may_contain(&self, hash: u64) -> bool {
+    return AncestorFilter::positions(hash).iter().all(|&position| self.bits[position / 64] & (1 << (position % 64)) != 0);
+  }
+}
+
+// AncestorFilter に入れる名前の種類（同じ文字列のタグ名、ID、クラスを別のものにする）
+const TAG_HASH: u8 = 0;
+const ID_HASH: u8 = 1;
+const CLASS_HASH: u8 = 2;
+
+// 種類 kind の名前 name のハッシュ（FNV-1a）。タグ名は大文字小文字を区別しないので、小文字にしてから渡す
+fn name_hash(kind: u8, name: &str) -> u64 {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for byte in Some(kind).into_iter().chain(name.bytes()) {
+    hash ^= byte as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  return hash;
+}
+
+// 要素のタグ名、ID、クラスのハッシュ
+fn element_hashes(elem: &ElementData) -> Vec<u64> {
+  let mut hashes = vec![name_hash(TAG_HASH, &elem.tag_name.to_ascii_lowercase())];
+  hashes.extend(elem.id().map(|id| name_hash(ID_HASH, id)));
+  hashes.extend(elem.classes().into_iter().filter(|class| !class.is_empty()).map(|class| name_hash(CLASS_HASH, class)));
+  return hashes;
+}
+
+// セレクターの祖先の部分（結合子の左）のタグ名、ID、クラスのハッシュ。一致するなら、どれも祖先のどれかにある
+fn ancestor_hashes(selector: &Selector) -> Vec<u64> {
+  let mut hashes = Vec::new();
+  if let Selector::Complex(ref complex) = *selector {
+    for &(_, ref simple) in &complex.ancestors {
+      hashes.extend(simple.tag_name.iter().map(|tag_name| name_hash(TAG_HASH, &tag_name.to_ascii_lowercase())));
+      hashes.extend(simple.id.iter().map(|id| name_hash(ID_HASH, id)));
+      hashes.extend(simple.class.iter().map(|class| name_hash(CLASS_HASH, class)));
+    }
+  }
+  return hashes;
+}
+
+// 要素（pseudo_element があればその疑似要素）に対して一致するスタイルを探す。RuleIndex で候補にしたルールだけを試し、
+// 祖先の部分が ancestors（filter はそのブルームフィルター）に一致しないと分かるセレクターは、祖先をたどらずに飛ばす
+pub(super) fn matching_rules<'a>(elem: Element, ancestors: &[Element], filter: &AncestorFilter, rules: &RuleIndex<'a>, pseudo_element: Option<PseudoElement>) -> Vec<MatchedRule<'a>> {
+  let stylesheet = rules.stylesheet;
+  return rules
+    .candidates(elem.data)
+    .into_iter()
+    .filter(|i| pseudo_element.is_none() || rules.pseudo_element_rules.contains(i))
+    .filter_map(|i| {
+      let rule = &stylesheet.rules[i];
+      let selectors = rule.selectors.iter().zip(&rules.ancestor_hashes[i]).filter(|&(_, hashes)| hashes.iter().all(|&hash| filter.may_contain(hash)));
+      most_specific(selectors.map(|(selector, _)| selector).filter(|selector| matches(elem, ancestors, *selector, pseudo_element))).map(|selector| (selector.specificity(), i, rule))
+    })
+    .collect();
+}
+
+// rule のセレクターのうち、要素に一致するものの中で詳細度がいちばん高いもの（同じなら前のもの）
+pub(super) fn matching_selector<'a>(elem: Element, ancestors: &[Element], rule: &'a Rule) -> Option<&'a Selector> {
+  return most_specific(rule.selectors.iter().filter(|selector| matches(elem, ancestors, *selector, None)));
+}
+
+// selectors の中で詳細度がいちばん高いもの（同じなら前のもの）
+fn most_specific<'a, I: Iterator<Item = &'a Selector>>(selectors: I) -> Option<&'a Selector> {
+  return selectors.fold(None, |best: Option<&Selector>, selector| match best {
+    Some(best) if best.specificity() >= selector.specificity() => Some(best),
+    _ => Some(selector),
+  });
+}
+
+// セレクターが要素と一致するかどうか調べる。root は element がルート要素（祖先の要素がない）か
+fn matches_simple_selector(element: Element, root: bool, selector: &SimpleSelector) -> bool {
+  let elem = element.data;
+
+  // タグ名（大文字小文字は区別しない）
+  if selector.tag_name.iter().any(|name| !elem.tag_name.eq_ignore_ascii_case(name)) {
+    return false;
+  }
+
+  // ID
+  if selector.id.iter().any(|id| elem.id() != Some(id)) {
+    return false;
+  }
+
+  // Class
+  let elem_classes = elem.classes();
+  if selector.class.iter().any(|class| !elem_classes.contains(&**class)) {
+    return false;
+  }
+
+  // 属性
+  if !selector.attributes.iter().all(|attribute| matches_attribute(elem, attribute)) {
+    return false;
+  }
+
+  // 疑似クラス
+  if !selector.pseudo_classes.iter().all(|&pseudo_class| matches_pseudo_class(element, root, pseudo_class)) {
+    return false;
+  }
+
+  return true;
+}
+
+fn matches_attribute(elem: &ElementData, selector: &AttributeSelector) -> bool {
+  return match *selector {
+    AttributeSelector::Exists(ref name) => elem.attributes.contains_key(name),
+    AttributeSelector::Equals(ref name, ref value) => elem.attributes.get(name) == Some(value),
+    AttributeSelector::Includes(ref name, ref value) => elem.attributes.get(name).map_or(false, |values| values.split_whitespace().any(|item| item == value)),
+  };
+}
+
+fn matches_pseudo_class(elem: Element, root: bool, pseudo_class: PseudoClass) -> bool {
+  return match pseudo_class {
+    PseudoClass::Root => root,
+    PseudoClass::FirstChild => elem.index == 1,
+    PseudoClass::LastChild => elem.index == elem.count,
+    // index = an + b となる 0 以上の n があるか
+    PseudoClass::NthChild(a, b) => {
+      let offset = elem.index as i32 - b;
+      if a == 0 {
+        offset == 0
+      } else {
+        offset % a == 0 && offset / a >= 0
+      }
+    }
+    PseudoClass::Hover => elem.data.hover,
+  };
+}
@@ -0,0 +1,243 @@
+use hyphenation::Patterns;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/**
+ * テキストを書記素クラスタ（ユーザーから見た 1 文字。結合文字や異体字セレクタ、絵文字の ZWJ 列を含む）単位で扱うところ。
+ * char 単位だと「か」+ 濁点や国旗の絵文字が途中で切れるので、幅の計算と改行はすべてここを通す。
+ *
+ * 改行できる位置は UAX #14 を簡単にしたもの:
+ *   - 空白の後（空白の前では改行しない。行末の空白はぶら下げる）
+ *   - 漢字、かな、ハングル、全角の文字の前後（日本語は単語の間に空白がない）
+ *   - ただし閉じ括弧や句読点、小書きのかな、長音符の前と、開き括弧の後では改行しない（禁則処理）
+ *   - 英字の間のハイフンの後、ゼロ幅スペースの後
+ *   - ソフトハイフン（U+00AD、&shy;）の後。改行したときだけ行末にハイフンを出す
+ *   - 改行文字の後は必ず改行する
+ *   - hyphens: auto なら、ハイフネーションのパターンで単語の中（hyphenate_breaks）
+ *
+ * word-break は line_breaks_with に渡す。break-all なら英数字も漢字と同じく文字の間で、keep-all なら漢字やかなの間でも改行しない
+ */
+
+pub const SOFT_HYPHEN: char = '\u{ad}';
+
+// 改行の種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Break {
+  Allowed,   // ここで改行してもよい
+  Mandatory, // 改行文字の後。必ず改行する
+  Hyphen,    // 単語の中。改行するなら行末にハイフンを足す
+}
+
+// word-break の値
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WordBreak {
+  Normal,
+  BreakAll, // 英数字の間でも改行する
+  KeepAll,  // 漢字、かな、ハングルの間でも改行しない（空白や句読点の後だけ）
+}
+
+// wrap で分けた行。hyphen なら行末にハイフンを足して描く
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Line<'a> {
+  pub text: &'a str,
+  pub hyphen: bool,
+}
+
+// 改行の規則で使う、書記素クラスタの分類
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Class {
+  Newline,
+  Space,
+  ZeroWidthSpace,
+  SoftHyphen,
+  Glue,        // ノーブレークスペース、ワードジョイナー。前後で改行しない
+  Open,        // 開き括弧。後で改行しない
+  Close,       // 全角の閉じ括弧と句読点。前で改行しない、後で改行できる
+  CloseNarrow, // 半角の閉じ括弧と句読点。前で改行しない
+  NonStarter,  // 小書きのかな、長音符など。前で改行しない
+  Hyphen,
+  Ideographic, // 漢字、かな、ハングル、全角の英数字、絵文字
+  Numeric,
+  Other,       // 英字など。同じものが続く間は改行しない
+}
+
+// 書記素クラスタに分ける
+pub fn graphemes(text: &str) -> Vec<&str> {
+  return text.graphemes(true).collect();
+}
+
+// 書記素クラスタの数（見た目の文字数）
+pub fn grapheme_count(text: &str) -> usize {
+  return text.graphemes(true).count();
+}
+
+// 等幅の端末で使う桁数（全角と絵文字は 2。結合文字などはクラスタの中で数えない）
+pub fn columns(text: &str) -> usize {
+  return text.graphemes(true).map(|grapheme| grapheme.width().min(2)).sum();
+}
+
+// 半角を font_size の半分、全角を font_size とする固定幅のフォントで測った幅
+pub fn monospace_width(text: &str, font_size: f32) -> f32 {
+  return columns(text) as f32 * font_size / 2.0;
+}
+
+// 改行できる位置（次の行の先頭のバイト位置）と種類。先頭と末尾は含まない
+pub fn line_breaks(text: &str) -> Vec<(usize, Break)> {
+  return line_breaks_with(text, WordBreak::Normal);
+}
+
+// line_breaks と同じだが、word_break で文字の間の扱いを変える
+pub fn line_breaks_with(text: &str, word_break: WordBreak) -> Vec<(usize, Break)> {
+  let mut breaks = Vec::new();
+  let mut previous: Option<(Class, Class)> = None; // 直前のクラスタと、空白が続く前のクラスタ
+  for (index, grapheme) in text.grapheme_indices(true) {
+    let class = match (classify(grapheme), word_break) {
+      (Class::Other, WordBreak::BreakAll) | (Class::Numeric, WordBreak::BreakAll) => Class::Ideographic,
+      (Class::Ideographic, WordBreak::KeepAll) => Class::Other,
+      (class, _) => class,
+    };
+    if let Some((before, before_spaces)) = previous {
+      if let Some(kind) = break_between(before, before_spaces, class) {
+        breaks.push((index, kind));
+      }
+    }
+    let before_spaces = match (class, previous) {
+      (Class::Space, Some((_, before_spaces))) => before_spaces,
+      _ => class,
+    };
+    previous = Some((class, before_spaces));
+  }
+  return breaks;
+}
+
+// 改行できる位置 breaks に、patterns で単語の中に見つけた位置を足したもの（位置の順。同じ位置なら breaks のものを残す）
+pub fn hyphenate_breaks(text: &str, mut breaks: Vec<(usize, Break)>, patterns: &Patterns) -> Vec<(usize, Break)> {
+  for (start, word) in text.split_word_bound_indices() {
+    for offset in patterns.hyphenate(word) {
+      breaks.push((start + offset, Break::Hyphen));
+    }
+  }
+  breaks.sort_by_key(|&(position, _)| position);
+  breaks.dedup_by_key(|&mut (position, _)| position);
+  return breaks;
+}
+
+// before と after の間で改行できるか。before_spaces は before から空白をさかのぼったクラスタ
+fn break_between(before: Class, before_spaces: Class, after: Class) -> Option<Break> {
+  if before == Class::Newline {
+    return Some(Break::Mandatory);
+  }
+  if after == Class::Space || after == Class::Newline || after == Class::ZeroWidthSpace {
+    return None;
+  }
+  if before == Class::Glue || after == Class::Glue {
+    return None;
+  }
+  if after == Class::Close || after == Class::CloseNarrow || after == Class::NonStarter {
+    return None;
+  }
+  if before == Class::Open || (before == Class::Space && before_spaces == Class::Open) {
+    return None;
+  }
+  if before == Class::Space || before == Class::ZeroWidthSpace {
+    return Some(Break::Allowed);
+  }
+  if before == Class::SoftHyphen {
+    return Some(Break::Hyphen);
+  }
+  if before == Class::Hyphen {
+    return if after == Class::Other { Some(Break::Allowed) } else { None };
+  }
+  if before == Class::Ideographic || after == Class::Ideographic || before == Class::Close || before == Class::NonStarter {
+    return Some(Break::Allowed);
+  }
+  return None;
+}
+
+fn classify(grapheme: &str) -> Class {
+  let c = match grapheme.chars().next() {
+    Some(c) => c,
+    None => return Class::Other,
+  };
+  return match c {
+    '\n' | '\r' | '\u{0b}' | '\u{0c}' | '\u{85}' | '\u{2028}' | '\u{2029}' => Class::Newline,
+    ' ' | '\t' | '\u{3000}' => Class::Space,
+    '\u{200b}' => Class::ZeroWidthSpace,
+    SOFT_HYPHEN => Class::SoftHyphen,
+    '\u{a0}' | '\u{2060}' | '\u{feff}' | '\u{202f}' => Class::Glue,
+    '(' | '[' | '{' | '«' | '‘' | '“' | '「' | '『' | '（' | '［' | '｛' | '〔' | '〈' | '《' | '【' | '〘' | '〖' | '〝' | '｟' => Class::Open,
+    '、' | '。' | '，' | '．' | '：' | '；' | '？' | '！' | '」' | '』' | '）' | '］' | '｝' | '〕' | '〉' | '》' | '】' | '〙' | '〗' | '〟' | '｠' => Class::Close,
+    ')' | ']' | '}' | '»' | '’' | '”' | ',' | '.' | '!' | '?' | ':' | ';' | '%' => Class::CloseNarrow,
+    'ぁ' | 'ぃ' | 'ぅ' | 'ぇ' | 'ぉ' | 'っ' | 'ゃ' | 'ゅ' | 'ょ' | 'ゎ' | 'ゕ' | 'ゖ' | 'ァ' | 'ィ' | 'ゥ' | 'ェ' | 'ォ' | 'ッ' | 'ャ' | 'ュ' | 'ョ' | 'ヮ' | 'ヵ'
+    | 'ヶ' | 'ー' | 'ヽ' | 'ヾ' | 'ゝ' | 'ゞ' | '々' | '〻' | '・' | '゛' | '゜' => Class::NonStarter,
+    '-' | '\u{2010}' => Class::Hyphen,
+    '0'..='9' => Class::Numeric,
+    _ if is_ideographic(c) => Class::Ideographic,
+    _ => Class::Other,
+  };
+}
+
+fn is_ideographic(c: char) -> bool {
+  return match c as u32 {
+    0x1100..=0x115f // ハングルの字母
+    | 0x2e80..=0x2fff // 部首
+    | 0x3040..=0x30ff // ひらがな、カタカナ
+    | 0x3100..=0x31ff
+    | 0x3400..=0x4dbf // 漢字
+    | 0x4e00..=0x9fff
+    | 0xa960..=0xa97f
+    | 0xac00..=0xd7af // ハングル
+    | 0xf900..=0xfaff
+    | 0xff01..=0xff60 // 全角の英数字と記号
+    | 0xffe0..=0xffe6
+    | 0x1f300..=0x1faff // 絵文字
+    | 0x20000..=0x3fffd => true,
+    _ => false,
+  };
+}
+
+// max_width に収まるように行に分ける（行末の空白と改行文字は含めない）。
+// 改行できる位置の間が max_width より長ければ、その行ははみ出す
+pub fn wrap<'a, F: Fn(&str) -> f32>(text: &'a str, max_width: f32, measure: F) -> Vec<Line<'a>> {
+  return wrap_at(text, line_breaks(text), max_width, measure);
+}
+
+fn wrap_at<'a, F: Fn(&str) -> f32>(text: &'a str, mut breaks: Vec<(usize, Break)>, max_width: f32, measure: F) -> Vec<Line<'a>> {
+  let mut lines = Vec::new();
+  if text.is_empty() {
+    return lines;
+  }
+  let mut start = 0;
+  let mut last_fit: Option<(usize, Break)> = None;
+  breaks.push((text.len(), Break::Mandatory));
+
+  let mut i = 0;
+  while i < breaks.len() {
+    let (position, kind) = breaks[i];
+    let line = Line { text: line_text(&text[start..position]), hyphen: kind == Break::Hyphen };
+    let fits = if line.hyphen { measure(&format!("{}-", line.text)) } else { measure(line.text) } <= max_width;
+    if !fits {
+      if let Some((fit, fit_kind)) = last_fit {
+        // 前の改行できる位置で折り返して、同じ位置をもう一度試す
+        lines.push(Line { text: line_text(&text[start..fit]), hyphen: fit_kind == Break::Hyphen });
+        start = fit;
+        last_fit = None;
+        continue;
+      }
+    }
+    if kind == Break::Mandatory {
+      lines.push(line);
+      start = position;
+      last_fit = None;
+    } else {
+      last_fit = Some((position, kind));
+    }
+    i += 1;
+  }
+  return lines;
+}
+
+// 行の末尾の空白と改行文字を除いたもの（ソフトハイフンで改行した行はソフトハイフンも除く）
+fn line_text(line: &str) -> &str {
+  return line.trim_end_matches(|c: char| c == ' ' || c == SOFT_HYPHEN || c == '\t' || c == '\u{3000}' || c == '\u{200b}' || c == '\r' || c == '\n' || c == '\u{2028}' || c == '\u{2029}');
+}
@@ -0,0 +1,191 @@
+use dump::Json;
+
+/**
+ * パイプラインの段階（パース、スタイル、レイアウト、ペイント）にかかった時間を、Chrome のトレースイベントの形式
+ * （about:tracing や Perfetto で開ける JSON）で記録する。
+ *
+ * 記録するのは tracing フィーチャーのときだけで、それ以外ではどの関数も何もしない（enabled はいつも false）。
+ * start から finish までの間に span で作った Span が落ちると、その区間を 1 つのイベント（ph が "X"）にする。
+ * スレッドごとに tid を分けるので、同時に描いたタイルなどは別の行に並ぶ。
+ * レイアウトはボックスごとにも区間を作って、部分木のボックスの数を args に入れる。
+ * ただし深い部分木は親の区間にまとめる（スレッドごとに MAX_DEPTH 段まで）
+ */
+
+// 1 つのスレッドで入れ子にして記録する区間の数
+pub const MAX_DEPTH: usize = 8;
+
+// 記録した区間。時間は start からのマイクロ秒
+struct Event {
+  category: &'static str,
+  name: String,
+  start: f64,
+  duration: f64,
+  thread: usize,
+  args: Vec<(&'static str, f64)>,
+}
+
+// 落ちるときに、作ってからの区間を記録する。記録していないとき（enabled が false か、入れ子が深すぎる）は何もしない
+pub struct Span {
+  recording: Option<recorder::Recording>,
+  args: Vec<(&'static str, f64)>,
+}
+
+impl Span {
+  // 記録するかどうか。args に入れる値を数えるのが重いときに確かめる
+  pub fn recording(&self) -> bool {
+    return self.recording.is_some();
+  }
+
+  // 区間の args に key の値を入れる
+  pub fn arg(&mut self, key: &'static str, value: f64) {
+    if self.recording() {
+      self.args.push((key, value));
+    }
+  }
+}
+
+impl Drop for Span {
+  fn drop(&mut self) {
+    if let Some(recording) = self.recording.take() {
+      recorder::end(recording, std::mem::take(&mut self.args));
+    }
+  }
+}
+
+// 記録を始める（前の記録は捨てる）
+pub fn start() {
+  recorder::start();
+}
+
+// 記録しているか
+pub fn enabled() -> bool {
+  return recorder::enabled();
+}
+
+// category（段階）の name という区間
+pub fn span(category: &'static str, name: &str) -> Span {
+  return span_with(category, || name.to_string());
+}
+
+// span と同じだが、名前は記録するときだけ作る
+pub fn span_with<F: FnOnce() -> String>(category: &'static str, name: F) -> Span {
+  return Span { recording: recorder::begin(category, name), args: Vec::new() };
+}
+
+// 記録をやめて、start からのイベントを JSON（{"traceEvents": [...]}）にする。start していなければ None
+pub fn finish() -> Option<String> {
+  let mut events = recorder::finish()?;
+  events.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap_or(std::cmp::Ordering::Equal));
+  let events = events
+    .into_iter()
+    .map(|event| {
+      Json::Object(vec![
+        ("name".to_string(), Json::String(event.name)),
+        ("cat".to_string(), Json::String(event.category.to_string())),
+        ("ph".to_string(), Json::String("X".to_string())),
+        ("ts".to_string(), Json::Number(event.start)),
+        ("dur".to_string(), Json::Number(event.duration)),
+        ("pid".to_string(), Json::Number(1.0)),
+        ("tid".to_string(), Json::Number(event.thread as f64)),
+        ("args".to_string(), Json::Object(event.args.into_iter().map(|(key, value)| (key.to_string(), Json::Number(value))).collect())),
+      ])
+    })
+    .collect();
+  let trace = Json::Object(vec![("traceEvents".to_string(), Json::Array(events)), ("displayTimeUnit".to_string(), Json::String("ms".to_string()))]);
+  return Some(trace.to_pretty_string());
+}
+
+#[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
+mod recorder {
+  use super::{Event, MAX_DEPTH};
+  use std::cell::Cell;
+  use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+  use std::sync::Mutex;
+  use std::time::Instant;
+
+  pub struct Recording {
+    category: &'static str,
+    name: String,
+    start: Instant,
+  }
+
+  // 記録を始めた時刻と、それからのイベント
+  struct Trace {
+    origin: Instant,
+    events: Vec<Event>,
+  }
+
+  static ENABLED: AtomicBool = AtomicBool::new(false);
+  static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+  static NEXT_THREAD: AtomicUsize = AtomicUsize::new(1);
+
+  thread_local! {
+    static THREAD: usize = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+    static DEPTH: Cell<usize> = Cell::new(0); // このスレッドで記録している区間の入れ子の数
+  }
+
+  pub fn start() {
+    *TRACE.lock().unwrap_or_else(|error| error.into_inner()) = Some(Trace { origin: Instant::now(), events: Vec::new() });
+    ENABLED.store(true, Ordering::Relaxed);
+  }
+
+  pub fn enabled() -> bool {
+    return ENABLED.load(Ordering::Relaxed);
+  }
+
+  pub fn begin<F: FnOnce() -> String>(category: &'static str, name: F) -> Option<Recording> {
+    if !enabled() || DEPTH.with(|depth| depth.get()) >= MAX_DEPTH {
+      return None;
+    }
+    DEPTH.with(|depth| depth.set(depth.get() + 1));
+    return Some(Recording { category: category, name: name(), start: Instant::now() });
+  }
+
+  pub fn end(recording: Recording, args: Vec<(&'static str, f64)>) {
+    let end = Instant::now();
+    DEPTH.with(|depth| depth.set(depth.get() - 1));
+    if let Some(ref mut trace) = *TRACE.lock().unwrap_or_else(|error| error.into_inner()) {
+      // start より前に始まった区間は start からにする
+      let start = recording.start.max(trace.origin);
+      trace.events.push(Event {
+        category: recording.category,
+        name: recording.name,
+        start: start.duration_since(trace.origin).as_secs_f64() * 1e6,
+        duration: end.duration_since(start).as_secs_f64() * 1e6,
+        thread: THREAD.with(|thread| *thread),
+        args: args,
+      });
+    }
+  }
+
+  pub fn finish() -> Option<Vec<Event>> {
+    ENABLED.store(false, Ordering::Relaxed);
+    return TRACE.lock().unwrap_or_else(|error| error.into_inner()).take().map(|trace| trace.events);
+  }
+}
+
+// tracing フィーチャーでないとき（wasm32-unknown-unknown には時計がないので、そこでも）は何も記録しない
+#[cfg(not(all(feature = "tracing", not(target_arch = "wasm32"))))]
+mod recorder {
+  use super::Event;
+
+  pub enum Recording {}
+
+  pub fn start() {}
+
+  pub fn enabled() -> bool {
+    return false;
+  }
+
+  pub fn begin<F: FnOnce() -> String>(_category: &'static str, _name: F) -> Option<Recording> {
+    return None;
+  }
+
+  pub fn end(recording: Recording, _args: Vec<(&'static str, f64)>) {
+    match recording {}
+  }
+
+  pub fn finish() -> Option<Vec<Event>> {
+    return None;
+  }
+}
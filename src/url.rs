@@ -0,0 +1,278 @@
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/**
+ * URL の表現と相対参照の解決（RFC 3986）
+ * ファイルパスも file:// の URL として扱う
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Url {
+  pub scheme: String,            // 小文字にそろえる
+  pub authority: Option<String>, // //host:port の部分。file:/// では空文字
+  pub path: String,
+  pub query: Option<String>,
+  pub fragment: Option<String>,
+}
+
+impl Url {
+  // 絶対 URL をパースする（スキームがなければ None）
+  pub fn parse(input: &str) -> Option<Url> {
+    let input = input.trim();
+    let colon = scheme_end(input)?;
+    let scheme = input[..colon].to_ascii_lowercase();
+    let rest = &input[colon + 1..];
+
+    // data: などの階層を持たない URL は、残り全体をパスとして持つ
+    if scheme == "data" {
+      return Some(Url { scheme: scheme, authority: None, path: rest.to_string(), query: None, fragment: None });
+    }
+
+    let mut reference = split_reference(rest);
+    if reference.authority.is_some() {
+      reference.path = remove_dot_segments(&reference.path);
+      if reference.path.is_empty() {
+        reference.path = "/".to_string();
+      }
+    }
+    return Some(Url {
+      scheme: scheme,
+      authority: reference.authority,
+      path: reference.path,
+      query: reference.query,
+      fragment: reference.fragment,
+    });
+  }
+
+  // ファイルパスから file:// の URL を作る。相対パスはカレントディレクトリ基準
+  pub fn from_file_path(path: &Path) -> Url {
+    let absolute = if path.is_absolute() {
+      path.to_path_buf()
+    } else {
+      env::current_dir().map(|dir| dir.join(path)).unwrap_or_else(|_| path.to_path_buf())
+    };
+    let mut encoded = String::new();
+    for component in absolute.to_string_lossy().replace('\\', "/").split('/') {
+      if !component.is_empty() {
+        encoded.push('/');
+        encoded.push_str(&percent_encode(component));
+      }
+    }
+    if encoded.is_empty() {
+      encoded.push('/');
+    }
+    return Url {
+      scheme: "file".to_string(),
+      authority: Some(String::new()),
+      path: remove_dot_segments(&encoded),
+      query: None,
+      fragment: None,
+    };
+  }
+
+  // コマンドラインなどで渡された場所（URL でもファイルパスでもよい）
+  pub fn from_location(location: &str) -> Url {
+    // 1 文字のスキームは Windows のドライブレターとみなす
+    return match Url::parse(location) {
+      Some(ref url) if url.scheme.len() > 1 => url.clone(),
+      _ => Url::from_file_path(Path::new(location)),
+    };
+  }
+
+  // この URL を基準に相対参照を解決する（RFC 3986 5.2.2）
+  pub fn join(&self, href: &str) -> Option<Url> {
+    let href = href.trim();
+    if let Some(url) = Url::parse(href) {
+      return Some(url);
+    }
+    if self.authority.is_none() && !self.path.starts_with('/') {
+      return None; // data: などからは解決できない
+    }
+
+    let reference = split_reference(href);
+    let mut url = Url {
+      scheme: self.scheme.clone(),
+      authority: self.authority.clone(),
+      path: self.path.clone(),
+      query: self.query.clone(),
+      fragment: reference.fragment,
+    };
+    if reference.authority.is_some() {
+      url.authority = reference.authority;
+      url.path = remove_dot_segments(&reference.path);
+      url.query = reference.query;
+    } else if reference.path.is_empty() {
+      if reference.query.is_some() {
+        url.query = reference.query;
+      }
+    } else {
+      url.path = if reference.path.starts_with('/') {
+        remove_dot_segments(&reference.path)
+      } else {
+        remove_dot_segments(&merge(self, &reference.path))
+      };
+      url.query = reference.query;
+    }
+    return Some(url);
+  }
+
+  pub fn is_file(&self) -> bool {
+    return self.scheme == "file";
+  }
+
+  pub fn is_http(&self) -> bool {
+    return self.scheme == "http" || self.scheme == "https";
+  }
+
+  pub fn is_data(&self) -> bool {
+    return self.scheme == "data";
+  }
+
+  // file:// の URL をファイルパスに戻す
+  pub fn to_file_path(&self) -> Option<PathBuf> {
+    if !self.is_file() {
+      return None;
+    }
+    let bytes = percent_decode(&self.path);
+    return Some(PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()));
+  }
+}
+
+impl fmt::Display for Url {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}:", self.scheme)?;
+    if let Some(ref authority) = self.authority {
+      write!(f, "//{}", authority)?;
+    }
+    write!(f, "{}", self.path)?;
+    if let Some(ref query) = self.query {
+      write!(f, "?{}", query)?;
+    }
+    if let Some(ref fragment) = self.fragment {
+      write!(f, "#{}", fragment)?;
+    }
+    return Ok(());
+  }
+}
+
+// スキーム末尾の `:` の位置。ALPHA *( ALPHA / DIGIT / "+" / "-" / "." )
+fn scheme_end(input: &str) -> Option<usize> {
+  for (i, c) in input.char_indices() {
+    match c {
+      'a'..='z' | 'A'..='Z' => {}
+      '0'..='9' | '+' | '-' | '.' if i > 0 => {}
+      ':' if i > 0 => return Some(i),
+      _ => return None,
+    }
+  }
+  return None;
+}
+
+// スキームを除いた参照
+struct Reference {
+  authority: Option<String>,
+  path: String,
+  query: Option<String>,
+  fragment: Option<String>,
+}
+
+fn split_reference(input: &str) -> Reference {
+  let (rest, fragment) = match input.find('#') {
+    Some(i) => (&input[..i], Some(input[i + 1..].to_string())),
+    None => (input, None),
+  };
+  let (rest, query) = match rest.find('?') {
+    Some(i) => (&rest[..i], Some(rest[i + 1..].to_string())),
+    None => (rest, None),
+  };
+  let (authority, path) = if rest.starts_with("//") {
+    let end = rest[2..].find('/').map(|i| i + 2).unwrap_or(rest.len());
+    (Some(rest[2..end].to_string()), &rest[end..])
+  } else {
+    (None, rest)
+  };
+  return Reference { authority: authority, path: path.to_string(), query: query, fragment: fragment };
+}
+
+// 基準 URL のパスの最後のセグメントを相対パスで置き換える（RFC 3986 5.2.3）
+fn merge(base: &Url, path: &str) -> String {
+  if base.authority.is_some() && base.path.is_empty() {
+    return format!("/{}", path);
+  }
+  return match base.path.rfind('/') {
+    Some(i) => format!("{}{}", &base.path[..i + 1], path),
+    None => path.to_string(),
+  };
+}
+
+// `.` と `..` のセグメントを取り除く（RFC 3986 5.2.4）
+fn remove_dot_segments(path: &str) -> String {
+  let mut output: Vec<&str> = Vec::new();
+  let segments: Vec<&str> = path.split('/').collect();
+  for (i, segment) in segments.iter().enumerate() {
+    let is_last = i == segments.len() - 1;
+    match *segment {
+      "." => {
+        if is_last {
+          output.push("");
+        }
+      }
+      ".." => {
+        if output.len() > 1 || (output.len() == 1 && !output[0].is_empty()) {
+          output.pop();
+        }
+        if is_last {
+          output.push("");
+        }
+      }
+      _ => output.push(segment),
+    }
+  }
+  let result = output.join("/");
+  if path.starts_with('/') && !result.starts_with('/') {
+    return format!("/{}", result);
+  }
+  return result;
+}
+
+fn percent_encode(segment: &str) -> String {
+  let mut result = String::new();
+  for b in segment.bytes() {
+    match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' | b'@' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' => {
+        result.push(b as char)
+      }
+      _ => result.push_str(&format!("%{:02X}", b)),
+    }
+  }
+  return result;
+}
+
+// %XX をバイトに戻す。不正な % はそのまま残す
+pub fn percent_decode(input: &str) -> Vec<u8> {
+  let bytes = input.as_bytes();
+  let mut result = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    if bytes[i] == b'%' && i + 2 < bytes.len() {
+      if let (Some(hi), Some(lo)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+        result.push(hi << 4 | lo);
+        i += 3;
+        continue;
+      }
+    }
+    result.push(bytes[i]);
+    i += 1;
+  }
+  return result;
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+  return match b {
+    b'0'..=b'9' => Some(b - b'0'),
+    b'a'..=b'f' => Some(b - b'a' + 10),
+    b'A'..=b'F' => Some(b - b'A' + 10),
+    _ => None,
+  };
+}
@@ -0,0 +1,568 @@
+use css::{Color, Matrix};
+use data_url;
+use font::{self, PathSegment};
+use images::Image;
+use layout::Rect;
+use output;
+use paint::{self, DisplayCommand, Page, PaintError};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use style::BorderStyle;
+
+/**
+ * ディスプレイリストを SVG と PDF に書き出す（ラスタライズしない出力）。
+ *
+ * コマンドをまず塗りつぶす図形（矩形とパス）と画像に分けて（Canvas の paint_item と同じ形になるように）、それを形式ごとに書く。
+ * 文字はフォントの字形のアウトラインをパスにする（文字としては選べない）。グラデーションはピクセルの画像にする。画像は PNG にして埋め込み、
+ * 敷きつめる背景の画像は SVG の <pattern> と PDF のタイリングパターンにする。
+ * 1 px を SVG では 1 ユーザー単位、PDF では 0.75 pt（96 dpi）にする。ページの背景はキャンバスと同じく白。
+ * opacity（PushOpacity と PopOpacity の間）はまとめて重ねずに、中の図形の色の不透明度に掛ける（画像には掛けない）。
+ * transform（PushTransform と PopTransform の間）は中の図形と画像に変換を付けて書く（SVG の transform 属性と PDF の cm）。
+ * 変換した後に祖先の overflow で切り取る範囲（PushTransform の clip）は書かない
+ */
+
+// 塗りつぶす図形
+enum Shape {
+  Rect(Rect),
+  Path(Vec<PathSegment>), // 非ゼロ規則で塗る
+}
+
+// 書き出すもの。clip があればその外には描かない
+enum Item {
+  Fill { color: Color, shape: Shape, clip: Option<Rect> },
+  Image { image: Arc<Image>, rect: Rect, repeat: bool, clip: Rect },
+}
+
+// ページの原点に合わせたコマンドを、描く順に図形と画像と、それに当てる変換にする（ページの外にあるものは書かない）
+fn items(page: &Page, width: f32, height: f32) -> Vec<(Item, Option<Matrix>)> {
+  let area = Rect { x: 0.0, y: 0.0, width: width, height: height };
+  let mut items = Vec::new();
+  let mut opacities = vec![1.0];
+  let mut transforms: Vec<Matrix> = Vec::new(); // 入っている PushTransform の変換を掛け合わせたもの
+  for command in page.commands() {
+    // 変換の中のコマンドは変換する前の座標なので、ページの外かどうかでは飛ばさない
+    let command = match if transforms.is_empty() { command.clipped(area) } else { Some(command) } {
+      Some(command) => command,
+      None => continue,
+    };
+    let opacity = *opacities.last().unwrap();
+    match command {
+      DisplayCommand::PushOpacity(own) => opacities.push(opacity * own),
+      DisplayCommand::PopOpacity if opacities.len() > 1 => {
+        opacities.pop();
+      }
+      DisplayCommand::PushTransform { matrix, .. } => {
+        let outer = transforms.last().cloned().unwrap_or(Matrix::IDENTITY);
+        transforms.push(outer.multiply(&matrix));
+      }
+      DisplayCommand::PopTransform => {
+        transforms.pop();
+      }
+      command => {
+        let mut lowered = Vec::new();
+        lower(command, opacity, &mut lowered);
+        let transform = transforms.last().cloned();
+        items.extend(lowered.into_iter().map(|item| (item, transform)));
+      }
+    }
+  }
+  return items;
+}
+
+// opacity は色の不透明度に掛けるもの
+fn lower(command: DisplayCommand, opacity: f32, items: &mut Vec<Item>) {
+  let mut fill = |color: Color, shape: Shape, clip: Option<Rect>| {
+    let color = Color { a: (color.a as f32 * opacity).round() as u8, ..color };
+    items.push(Item::Fill { color: color, shape: shape, clip: clip });
+  };
+  match command {
+    DisplayCommand::SolidColor(color, rect) => fill(color, Shape::Rect(rect), None),
+    DisplayCommand::RoundedRect { color, rect, radii, clip } => fill(color, Shape::Path(rounded_rect(rect, radii, false)), Some(clip)),
+    DisplayCommand::Border { rect, widths, colors, styles, radii, clip } => {
+      if radii != [0.0; 4] {
+        // 角を丸めたボーダーは、外側の角丸矩形から内側を逆向きのパスで抜いた輪にして上の辺の色で塗る（破線と点線にはしない）
+        let inner = Rect { x: rect.x + widths.left, y: rect.y + widths.top, width: rect.width - widths.left - widths.right, height: rect.height - widths.top - widths.bottom };
+        let inner_radii = [
+          (radii[0] - widths.left.max(widths.top)).max(0.0),
+          (radii[1] - widths.right.max(widths.top)).max(0.0),
+          (radii[2] - widths.right.max(widths.bottom)).max(0.0),
+          (radii[3] - widths.left.max(widths.bottom)).max(0.0),
+        ];
+        let mut path = rounded_rect(rect, radii, false);
+        if inner.width > 0.0 && inner.height > 0.0 {
+          path.extend(rounded_rect(inner, inner_radii, true));
+        }
+        fill(colors[2], Shape::Path(path), Some(clip));
+        return;
+      }
+      let sides = paint::border_sides(rect, widths);
+      for i in 0..4 {
+        let side = sides[i];
+        if side.width <= 0.0 || side.height <= 0.0 {
+          continue;
+        }
+        // 左右の辺は縦に、上下の辺は横に並べる
+        let vertical = i < 2;
+        let (start, length, width) = if vertical { (side.y, side.height, side.width) } else { (side.x, side.width, side.height) };
+        match styles[i] {
+          BorderStyle::None => {}
+          BorderStyle::Solid => fill(colors[i], Shape::Rect(side), Some(clip)),
+          BorderStyle::Dashed => {
+            let mut offset = 0.0;
+            while offset < length {
+              let dash = (width * 3.0).min(length - offset);
+              let rect = if vertical { Rect { y: start + offset, height: dash, ..side } } else { Rect { x: start + offset, width: dash, ..side } };
+              fill(colors[i], Shape::Rect(rect), Some(clip));
+              offset += width * 6.0;
+            }
+          }
+          BorderStyle::Dotted => {
+            let mut path = Vec::new();
+            let mut offset = width;
+            while offset - width / 2.0 < length {
+              let (cx, cy) = if vertical { (side.x + width / 2.0, start + offset) } else { (start + offset, side.y + width / 2.0) };
+              path.extend(circle(cx, cy, width / 2.0));
+              offset += width * 2.0;
+            }
+            fill(colors[i], Shape::Path(path), Some(clip));
+          }
+        }
+      }
+    }
+    DisplayCommand::Text { text, glyphs, color, x, y, font_size, clip } => match glyphs {
+      Some(run) => {
+        let mut missing = Vec::new();
+        let path = font::outline(&run, x, y, font_size, |gx, advance| missing.push(paint::missing_glyph_box(gx, advance, y + run.baseline, font_size)));
+        if !path.is_empty() {
+          fill(color, Shape::Path(path), Some(clip));
+        }
+        for glyph in missing {
+          fill(color, Shape::Rect(glyph), Some(clip));
+        }
+      }
+      None => {
+        for glyph in paint::text_boxes(&text, x, y, font_size) {
+          fill(color, Shape::Rect(glyph), Some(clip));
+        }
+      }
+    },
+    DisplayCommand::Checkbox { rect, checked, clip } => {
+      let line = (rect.width / 13.0).max(1.0);
+      let (border, background) = if checked { (paint::CONTROL_ACCENT, paint::CONTROL_ACCENT) } else { (paint::CONTROL_BORDER, paint::CONTROL_BACKGROUND) };
+      fill(border, Shape::Rect(rect), Some(clip));
+      fill(background, Shape::Rect(Rect { x: rect.x + line, y: rect.y + line, width: rect.width - line * 2.0, height: rect.height - line * 2.0 }), Some(clip));
+      if checked {
+        let point = |px: f32, py: f32| (rect.x + px * rect.width / 13.0, rect.y + py * rect.height / 13.0);
+        let (a, b, c) = (point(3.0, 6.5), point(5.5, 9.0), point(10.0, 4.0));
+        let mut path = stroke(a, b, line * 1.5);
+        path.extend(stroke(b, c, line * 1.5));
+        fill(paint::CONTROL_BACKGROUND, Shape::Path(path), Some(clip));
+      }
+    }
+    DisplayCommand::Radio { rect, checked, clip } => {
+      let line = (rect.width / 13.0).max(1.0);
+      let radius = rect.width.min(rect.height) / 2.0;
+      let (cx, cy) = (rect.x + rect.width / 2.0, rect.y + rect.height / 2.0);
+      fill(if checked { paint::CONTROL_ACCENT } else { paint::CONTROL_BORDER }, Shape::Path(circle(cx, cy, radius)), Some(clip));
+      fill(paint::CONTROL_BACKGROUND, Shape::Path(circle(cx, cy, radius - line)), Some(clip));
+      if checked {
+        fill(paint::CONTROL_ACCENT, Shape::Path(circle(cx, cy, radius * 0.5)), Some(clip));
+      }
+    }
+    DisplayCommand::Image { image, rect, repeat, clip } => {
+      if rect.width > 0.0 && rect.height > 0.0 {
+        items.push(Item::Image { image: image, rect: rect, repeat: repeat, clip: clip });
+      }
+    }
+    DisplayCommand::Gradient { rect, angle, stops, clip } => {
+      // 大きさの分のピクセルの画像にして埋め込む
+      let (width, height) = (rect.width.ceil() as usize, rect.height.ceil() as usize);
+      if width > 0 && height > 0 {
+        items.push(Item::Image { image: Arc::new(paint::gradient_image(width, height, angle, &stops)), rect: rect, repeat: false, clip: clip });
+      }
+    }
+    DisplayCommand::PushOpacity(_) | DisplayCommand::PopOpacity | DisplayCommand::PushTransform { .. } | DisplayCommand::PopTransform => {}
+  }
+}
+
+// 太さ width の線分を、両端を width / 2 伸ばした長方形にする
+fn stroke(from: (f32, f32), to: (f32, f32), width: f32) -> Vec<PathSegment> {
+  let length = ((to.0 - from.0).powi(2) + (to.1 - from.1).powi(2)).sqrt().max(f32::EPSILON);
+  let (dx, dy) = ((to.0 - from.0) / length * width / 2.0, (to.1 - from.1) / length * width / 2.0);
+  return vec![
+    PathSegment::MoveTo(from.0 - dx - dy, from.1 - dy + dx),
+    PathSegment::LineTo(to.0 + dx - dy, to.1 + dy + dx),
+    PathSegment::LineTo(to.0 + dx + dy, to.1 + dy - dx),
+    PathSegment::LineTo(from.0 - dx + dy, from.1 - dy - dx),
+    PathSegment::Close,
+  ];
+}
+
+// 角を radii（左上、右上、右下、左下）の半径で丸めた矩形。角は円弧を 3 次ベジェ曲線で近似する。
+// reverse なら逆回りにする（非ゼロ規則で、同じ向きの図形から抜くため）
+fn rounded_rect(rect: Rect, radii: [f32; 4], reverse: bool) -> Vec<PathSegment> {
+  let (x, y, w, h) = (rect.x, rect.y, rect.width, rect.height);
+  let k = radii.map(|radius| radius * 0.5523);
+  let r = radii;
+  // 時計回りに見た、角ごとの円弧の始点、2 つの制御点、終点
+  let corners = [
+    [(x, y + r[0]), (x, y + r[0] - k[0]), (x + r[0] - k[0], y), (x + r[0], y)],
+    [(x + w - r[1], y), (x + w - r[1] + k[1], y), (x + w, y + r[1] - k[1]), (x + w, y + r[1])],
+    [(x + w, y + h - r[2]), (x + w, y + h - r[2] + k[2]), (x + w - r[2] + k[2], y + h), (x + w - r[2], y + h)],
+    [(x + r[3], y + h), (x + r[3] - k[3], y + h), (x, y + h - r[3] + k[3]), (x, y + h - r[3])],
+  ];
+  let mut path = Vec::new();
+  if reverse {
+    path.push(PathSegment::MoveTo(corners[0][3].0, corners[0][3].1));
+    for corner in [corners[0], corners[3], corners[2], corners[1]] {
+      let [start, c1, c2, end] = corner;
+      path.push(PathSegment::LineTo(end.0, end.1));
+      path.push(PathSegment::CubicTo(c2.0, c2.1, c1.0, c1.1, start.0, start.1));
+    }
+  } else {
+    path.push(PathSegment::MoveTo(corners[0][0].0, corners[0][0].1));
+    for corner in corners {
+      let [start, c1, c2, end] = corner;
+      path.push(PathSegment::LineTo(start.0, start.1));
+      path.push(PathSegment::CubicTo(c1.0, c1.1, c2.0, c2.1, end.0, end.1));
+    }
+  }
+  path.push(PathSegment::Close);
+  return path;
+}
+
+// 円を 4 本の 3 次ベジェ曲線で近似する
+fn circle(cx: f32, cy: f32, radius: f32) -> Vec<PathSegment> {
+  let (r, k) = (radius.max(0.0), radius.max(0.0) * 0.5523);
+  return vec![
+    PathSegment::MoveTo(cx + r, cy),
+    PathSegment::CubicTo(cx + r, cy + k, cx + k, cy + r, cx, cy + r),
+    PathSegment::CubicTo(cx - k, cy + r, cx - r, cy + k, cx - r, cy),
+    PathSegment::CubicTo(cx - r, cy - k, cx - k, cy - r, cx, cy - r),
+    PathSegment::CubicTo(cx + k, cy - r, cx + r, cy - k, cx + r, cy),
+    PathSegment::Close,
+  ];
+}
+
+// 小数点以下 3 桁まで（末尾の 0 は書かない）
+fn number(value: f32) -> String {
+  let text = format!("{:.3}", value);
+  let text = text.trim_end_matches('0').trim_end_matches('.');
+  return if text == "-0" { "0".to_string() } else { text.to_string() };
+}
+
+// 画像を PNG にする。alpha なら不透明度だけのグレースケール、そうでなければ RGB（透明度は捨てる）
+fn encode_png(image: &Image, alpha: bool) -> Result<Vec<u8>, PaintError> {
+  let (width, height) = (image.width as u32, image.height as u32);
+  if alpha {
+    let mask = ::image::ImageBuffer::from_fn(width, height, |x, y| ::image::Luma([image.pixels[(y * width + x) as usize].a]));
+    return output::encode_png(::image::ImageLuma8(mask));
+  }
+  return output::encode_png(::image::ImageRgb8(output::pixels_to_image(image.width, image.height, &image.pixels).to_rgb()));
+}
+
+pub fn encode_svg(page: &Page) -> Result<String, PaintError> {
+  let (width, height) = page.size()?;
+  let mut defs = String::new();
+  let mut body = String::new();
+  let mut clips: HashMap<String, usize> = HashMap::new();
+  let mut images: HashMap<*const Image, usize> = HashMap::new();
+  let mut patterns = 0;
+  for (item, transform) in items(page, width, height) {
+    // 変換があれば <g> で囲む（clip-path も変換した座標で当たる）
+    if let Some(ref matrix) = transform {
+      let _ = writeln!(body, "<g transform=\"{}\">", svg_matrix(matrix));
+    }
+    match item {
+      Item::Fill { color, shape, clip } => {
+        let clip = clip.map_or(String::new(), |clip| svg_clip(&mut defs, &mut clips, clip));
+        let mut fill = format!(" fill=\"#{:02x}{:02x}{:02x}\"", color.r, color.g, color.b);
+        if color.a != 255 {
+          let _ = write!(fill, " fill-opacity=\"{}\"", number(color.a as f32 / 255.0));
+        }
+        let _ = match shape {
+          Shape::Rect(rect) => writeln!(body, "<rect {}{}{}/>", svg_rect(rect), fill, clip),
+          Shape::Path(ref path) => writeln!(body, "<path d=\"{}\"{}{}/>", svg_path(path), fill, clip),
+        };
+      }
+      Item::Image { image, rect, repeat, clip } => {
+        let id = svg_image(&mut defs, &mut images, &image)?;
+        if repeat {
+          // 1 枚分の大きさのパターンで clip を塗る
+          let _ = writeln!(
+            defs,
+            "<pattern id=\"p{}\" patternUnits=\"userSpaceOnUse\" {}><use xlink:href=\"#i{}\" transform=\"scale({} {})\"/></pattern>",
+            patterns,
+            svg_rect(rect),
+            id,
+            number(rect.width),
+            number(rect.height)
+          );
+          let _ = writeln!(body, "<rect {} fill=\"url(#p{})\"/>", svg_rect(clip), patterns);
+          patterns += 1;
+        } else {
+          let clip = svg_clip(&mut defs, &mut clips, clip);
+          let transform = format!("translate({} {}) scale({} {})", number(rect.x), number(rect.y), number(rect.width), number(rect.height));
+          let _ = writeln!(body, "<use xlink:href=\"#i{}\" transform=\"{}\"{}/>", id, transform, clip);
+        }
+      }
+    }
+    if transform.is_some() {
+      body.push_str("</g>\n");
+    }
+  }
+
+  let mut out = String::new();
+  let _ = writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+  let _ = writeln!(
+    out,
+    "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">",
+    number(width),
+    number(height)
+  );
+  if !defs.is_empty() {
+    let _ = write!(out, "<defs>\n{}</defs>\n", defs);
+  }
+  let _ = writeln!(out, "<rect width=\"100%\" height=\"100%\" fill=\"#ffffff\"/>");
+  out.push_str(&body);
+  out.push_str("</svg>\n");
+  return Ok(out);
+}
+
+fn svg_matrix(matrix: &Matrix) -> String {
+  let Matrix { a, b, c, d, e, f } = *matrix;
+  return format!("matrix({} {} {} {} {} {})", number(a), number(b), number(c), number(d), number(e), number(f));
+}
+
+fn svg_rect(rect: Rect) -> String {
+  return format!("x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"", number(rect.x), number(rect.y), number(rect.width), number(rect.height));
+}
+
+fn svg_path(path: &[PathSegment]) -> String {
+  let mut d = String::new();
+  for segment in path {
+    let _ = match *segment {
+      PathSegment::MoveTo(x, y) => write!(d, "M{} {}", number(x), number(y)),
+      PathSegment::LineTo(x, y) => write!(d, "L{} {}", number(x), number(y)),
+      PathSegment::CubicTo(x1, y1, x2, y2, x, y) => write!(d, "C{} {} {} {} {} {}", number(x1), number(y1), number(x2), number(y2), number(x), number(y)),
+      PathSegment::Close => write!(d, "Z"),
+    };
+  }
+  return d;
+}
+
+// clip の <clipPath>（同じ矩形は 1 つにまとめる）を参照する属性
+fn svg_clip(defs: &mut String, clips: &mut HashMap<String, usize>, clip: Rect) -> String {
+  let rect = svg_rect(clip);
+  let count = clips.len();
+  let id = *clips.entry(rect.clone()).or_insert_with(|| {
+    let _ = writeln!(defs, "<clipPath id=\"c{}\"><rect {}/></clipPath>", count, rect);
+    count
+  });
+  return format!(" clip-path=\"url(#c{})\"", id);
+}
+
+// 画像を 1 x 1 の大きさの <image> として（同じ画像は 1 回だけ）埋め込んで、その番号を返す。使うときに拡大する
+fn svg_image(defs: &mut String, images: &mut HashMap<*const Image, usize>, image: &Arc<Image>) -> Result<usize, PaintError> {
+  if let Some(&id) = images.get(&Arc::as_ptr(image)) {
+    return Ok(id);
+  }
+  let id = images.len();
+  let href = data_url::encode("image/png", &output::encode_png(output::pixels_to_image(image.width, image.height, &image.pixels))?);
+  let _ = writeln!(defs, "<image id=\"i{}\" width=\"1\" height=\"1\" preserveAspectRatio=\"none\" xlink:href=\"{}\"/>", id, href);
+  images.insert(Arc::as_ptr(image), id);
+  return Ok(id);
+}
+
+// PDF のオブジェクトを番号（1 から）の順に持っておいて、最後に相互参照表と一緒に書き出す
+struct PdfWriter {
+  objects: Vec<Vec<u8>>,
+}
+
+impl PdfWriter {
+  fn add(&mut self, object: Vec<u8>) -> usize {
+    self.objects.push(object);
+    return self.objects.len();
+  }
+
+  // 中身を後で set するオブジェクトの番号
+  fn reserve(&mut self) -> usize {
+    return self.add(Vec::new());
+  }
+
+  fn set(&mut self, id: usize, object: Vec<u8>) {
+    self.objects[id - 1] = object;
+  }
+
+  fn finish(self, root: usize) -> Vec<u8> {
+    let mut out = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::new();
+    for (index, object) in self.objects.iter().enumerate() {
+      offsets.push(out.len());
+      out.extend_from_slice(format!("{} 0 obj\n", index + 1).as_bytes());
+      out.extend_from_slice(object);
+      out.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = out.len();
+    out.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", self.objects.len() + 1).as_bytes());
+    for offset in offsets {
+      out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    out.extend_from_slice(format!("trailer\n<< /Size {} /Root {} 0 R >>\nstartxref\n{}\n%%EOF\n", self.objects.len() + 1, root, xref).as_bytes());
+    return out;
+  }
+}
+
+fn pdf_stream(dictionary: &str, data: &[u8]) -> Vec<u8> {
+  let dictionary = format!("{} /Length {}", dictionary, data.len());
+  let mut out = format!("<< {} >>\nstream\n", dictionary.trim_start()).into_bytes();
+  out.extend_from_slice(data);
+  out.extend_from_slice(b"\nendstream");
+  return out;
+}
+
+// 1 つのページに 1 つの Page を描いた PDF。
+// 座標は px のまま、ページの左上を原点に y を下向きにする変換（0.75 倍）の中で書く
+pub fn encode_pdf(pages: &[&Page]) -> Result<Vec<u8>, PaintError> {
+  let mut pdf = PdfWriter { objects: Vec::new() };
+  let catalog = pdf.reserve();
+  let tree = pdf.reserve();
+  pdf.set(catalog, format!("<< /Type /Catalog /Pages {} 0 R >>", tree).into_bytes());
+  let mut images: HashMap<*const Image, usize> = HashMap::new();
+  let mut kids = Vec::new();
+  for page in pages {
+    let (width, height) = page.size()?;
+    let page_height = height * 0.75;
+    let mut content = String::new();
+    let mut states: Vec<u8> = Vec::new();
+    let mut x_objects: Vec<usize> = Vec::new();
+    let mut patterns: Vec<usize> = Vec::new();
+    let _ = writeln!(content, "0.75 0 0 -0.75 0 {} cm", number(page_height));
+    let _ = writeln!(content, "1 1 1 rg 0 0 {} {} re f", number(width), number(height));
+    // ページの座標（px、y が下向き）から PDF の既定の座標へ
+    let page_matrix = Matrix { a: 0.75, b: 0.0, c: 0.0, d: -0.75, e: 0.0, f: page_height };
+    for (item, transform) in items(page, width, height) {
+      content.push_str("q\n");
+      if let Some(ref matrix) = transform {
+        let _ = writeln!(content, "{} cm", pdf_matrix(matrix));
+      }
+      match item {
+        Item::Fill { color, shape, clip } => {
+          if let Some(clip) = clip {
+            let _ = writeln!(content, "{} re W n", pdf_rect(clip));
+          }
+          if color.a != 255 {
+            if !states.contains(&color.a) {
+              states.push(color.a);
+            }
+            let _ = writeln!(content, "/GS{} gs", color.a);
+          }
+          let _ = writeln!(content, "{} {} {} rg", number(color.r as f32 / 255.0), number(color.g as f32 / 255.0), number(color.b as f32 / 255.0));
+          match shape {
+            Shape::Rect(rect) => {
+              let _ = writeln!(content, "{} re f", pdf_rect(rect));
+            }
+            Shape::Path(ref path) => {
+              pdf_path(&mut content, path);
+              content.push_str("f\n");
+            }
+          }
+        }
+        Item::Image { image, rect, repeat, clip } => {
+          let id = pdf_image(&mut pdf, &mut images, &image)?;
+          if repeat {
+            // 1 枚分を (0, 0) に置いたタイリングパターン。Matrix はパターンの座標からページの（変換する前の）座標へで、
+            // cm の変換は当たらないので transform もここで掛ける
+            let cell = format!("q {} 0 0 {} 0 {} cm /Im{} Do Q", number(rect.width), number(-rect.height), number(rect.height), id);
+            let matrix = page_matrix.multiply(&transform.unwrap_or(Matrix::IDENTITY)).multiply(&Matrix::translate(rect.x, rect.y));
+            let dictionary = format!(
+              "/Type /Pattern /PatternType 1 /PaintType 1 /TilingType 1 /BBox [0 0 {0} {1}] /XStep {0} /YStep {1} /Matrix [{2}] /Resources << /XObject << /Im{3} {3} 0 R >> >>",
+              number(rect.width),
+              number(rect.height),
+              pdf_matrix(&matrix),
+              id
+            );
+            let pattern = pdf.add(pdf_stream(&dictionary, cell.as_bytes()));
+            patterns.push(pattern);
+            let _ = writeln!(content, "/Pattern cs /P{} scn {} re f", pattern, pdf_rect(clip));
+          } else {
+            if !x_objects.contains(&id) {
+              x_objects.push(id);
+            }
+            let _ = writeln!(content, "{} re W n", pdf_rect(clip));
+            let _ = writeln!(content, "{} 0 0 {} {} {} cm /Im{} Do", number(rect.width), number(-rect.height), number(rect.x), number(rect.y + rect.height), id);
+          }
+        }
+      }
+      content.push_str("Q\n");
+    }
+
+    let contents = pdf.add(pdf_stream("", content.as_bytes()));
+    let mut resources = String::new();
+    if !x_objects.is_empty() {
+      let _ = write!(resources, "/XObject << {} >> ", x_objects.iter().map(|id| format!("/Im{0} {0} 0 R", id)).collect::<Vec<_>>().join(" "));
+    }
+    if !patterns.is_empty() {
+      let _ = write!(resources, "/Pattern << {} >> ", patterns.iter().map(|id| format!("/P{0} {0} 0 R", id)).collect::<Vec<_>>().join(" "));
+    }
+    if !states.is_empty() {
+      let states: Vec<String> = states.iter().map(|alpha| format!("/GS{} << /ca {} >>", alpha, number(*alpha as f32 / 255.0))).collect();
+      let _ = write!(resources, "/ExtGState << {} >> ", states.join(" "));
+    }
+    let page = format!(
+      "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources << {}>> /Contents {} 0 R >>",
+      tree,
+      number(width * 0.75),
+      number(page_height),
+      resources,
+      contents
+    );
+    kids.push(pdf.add(page.into_bytes()));
+  }
+  let kids: Vec<String> = kids.iter().map(|id| format!("{} 0 R", id)).collect();
+  pdf.set(tree, format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), pages.len()).into_bytes());
+  return Ok(pdf.finish(catalog));
+}
+
+fn pdf_matrix(matrix: &Matrix) -> String {
+  let Matrix { a, b, c, d, e, f } = *matrix;
+  return format!("{} {} {} {} {} {}", number(a), number(b), number(c), number(d), number(e), number(f));
+}
+
+fn pdf_rect(rect: Rect) -> String {
+  return format!("{} {} {} {}", number(rect.x), number(rect.y), number(rect.width), number(rect.height));
+}
+
+fn pdf_path(content: &mut String, path: &[PathSegment]) {
+  for segment in path {
+    let _ = match *segment {
+      PathSegment::MoveTo(x, y) => writeln!(content, "{} {} m", number(x), number(y)),
+      PathSegment::LineTo(x, y) => writeln!(content, "{} {} l", number(x), number(y)),
+      PathSegment::CubicTo(x1, y1, x2, y2, x, y) => writeln!(content, "{} {} {} {} {} {} c", number(x1), number(y1), number(x2), number(y2), number(x), number(y)),
+      PathSegment::Close => writeln!(content, "h"),
+    };
+  }
+}
+
+// 画像の XObject（同じ画像は 1 回だけ）を追加して、その番号を返す。
+// ピクセルは PNG の IDAT をそのまま使い（FlateDecode と PNG の予測子）、透明なピクセルがあれば不透明度を SMask にする
+fn pdf_image(pdf: &mut PdfWriter, images: &mut HashMap<*const Image, usize>, image: &Arc<Image>) -> Result<usize, PaintError> {
+  if let Some(&id) = images.get(&Arc::as_ptr(image)) {
+    return Ok(id);
+  }
+  let image_dictionary = |color_space: &str, colors: usize| {
+    format!(
+      "/Type /XObject /Subtype /Image /Width {0} /Height {1} /ColorSpace /{2} /BitsPerComponent 8 /Filter /FlateDecode /DecodeParms << /Predictor 15 /Colors {3} /BitsPerComponent 8 /Columns {0} >>",
+      image.width, image.height, color_space, colors
+    )
+  };
+  let mut dictionary = image_dictionary("DeviceRGB", 3);
+  if image.pixels.iter().any(|pixel| pixel.a != 255) {
+    let mask = pdf.add(pdf_stream(&image_dictionary("DeviceGray", 1), &output::png_image_data(&encode_png(image, true)?)?));
+    let _ = write!(dictionary, " /SMask {} 0 R", mask);
+  }
+  let id = pdf.add(pdf_stream(&dictionary, &output::png_image_data(&encode_png(image, false)?)?));
+  images.insert(Arc::as_ptr(image), id);
+  return Ok(id);
+}
@@ -0,0 +1,410 @@
+use dump;
+use engine::Engine;
+use error::EngineError;
+use event::{self, Event};
+use history::History;
+use inspect;
+use options::EngineOptions;
+use paint::Canvas;
+use std::num::NonZeroU32;
+use std::rc::Rc;
+use std::time::Instant;
+use thiserror::Error;
+use url::Url;
+use watch::{self, Watcher};
+use winit::application::ApplicationHandler;
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
+use winit::keyboard::{Key, ModifiersState, NamedKey};
+use winit::window::{Window, WindowId};
+
+/**
+ * ページをウィンドウに表示するビューア。
+ * ウィンドウの大きさをビューポートにして、リサイズのたびにレイアウトと描画をやり直す（スクロールでは描画だけ）。
+ * ホイールや矢印キーでドキュメント全体のレイアウトの中をスクロールできる。
+ * --watch のときは、入力が変わるたびに読み込み直す（スクロール位置はそのまま）。
+ * カーソルの下の要素とその祖先は :hover になる（event::dispatch）。
+ * リンク（<a href>）をクリックするとその URL を読み込んで表示し、Alt+←/→（マウスの戻る・進むボタン）で履歴を戻る・進む。
+ * ほかの要素をクリックすると、そのボックスの content、padding、border、margin を色分けして重ね（Esc で消す）、
+ * 一致したルールと最終的な値を --inspect と同じ形で標準出力に書き出す。
+ * ウィンドウのタイトルはドキュメントの <title>
+ */
+
+const LINE_HEIGHT: f32 = 40.0; // ホイール 1 行、矢印キー 1 回ぶんのスクロール量
+const PAGE_OVERLAP: f32 = 40.0; // PageUp/Down で前のページを残す量
+
+#[derive(Debug, Error)]
+pub enum ViewerError {
+  #[error("failed to create event loop: {0}")]
+  EventLoop(String),
+  #[error("failed to create window: {0}")]
+  Window(String),
+  #[error("failed to draw to window: {0}")]
+  Surface(String),
+}
+
+type Surface = softbuffer::Surface<Rc<Window>, Rc<Window>>;
+
+// 監視しているファイルが変わったときにイベントループに送る
+#[derive(Debug)]
+struct Reload;
+
+struct Viewer {
+  engine: Engine,
+  watcher: Option<Watcher>,
+  reload_at: Option<Instant>, // 変更が落ち着くのを待って読み込み直す時刻
+  initial_size: LogicalSize<f32>,
+  window: Option<Rc<Window>>,
+  surface: Option<Surface>,
+  scroll_x: f32,
+  scroll_y: f32,
+  cursor: (f32, f32), // ウィンドウの中のカーソルの位置（ピクセル）
+  modifiers: ModifiersState,
+  history: History,
+  error: Option<EngineError>, // イベントループを抜けた後に返す
+}
+
+impl Viewer {
+  fn create_window(&mut self, event_loop: &ActiveEventLoop) -> Result<(), ViewerError> {
+    let attributes = Window::default_attributes()
+      .with_title("browser-engine-suburi")
+      .with_inner_size(self.initial_size);
+    let window = Rc::new(event_loop.create_window(attributes).map_err(|e| ViewerError::Window(e.to_string()))?);
+    let context = softbuffer::Context::new(window.clone()).map_err(|e| ViewerError::Surface(e.to_string()))?;
+    let surface = softbuffer::Surface::new(&context, window.clone()).map_err(|e| ViewerError::Surface(e.to_string()))?;
+    self.window = Some(window);
+    self.surface = Some(surface);
+    self.update_title();
+    return Ok(());
+  }
+
+  // ウィンドウのタイトルをドキュメントの <title> にする（なければ読み込んだ URL）
+  fn update_title(&self) {
+    if let (Some(ref window), Some(document)) = (self.window.as_ref(), self.engine.document()) {
+      let title = match document.root_node.title() {
+        Some(ref title) if !title.is_empty() => title.clone(),
+        _ => document.base_url.to_string(),
+      };
+      window.set_title(&format!("{} - browser-engine-suburi", title));
+    }
+  }
+
+  // 今のウィンドウの大きさでレイアウトし直して描画する
+  fn redraw(&mut self) -> Result<(), EngineError> {
+    let (width, height) = self.viewport_size();
+    let (buffer_width, buffer_height) = match (NonZeroU32::new(width as u32), NonZeroU32::new(height as u32)) {
+      (Some(width), Some(height)) => (width, height),
+      _ => return Ok(()), // 最小化中など
+    };
+
+    self.engine.set_viewport(width, height);
+    self.engine.set_scroll(self.scroll_x, self.scroll_y);
+    self.engine.update()?;
+
+    // リサイズでスクロールできる範囲が縮んだら、範囲内に戻して描き直す
+    let (max_x, max_y) = self.max_scroll();
+    if self.scroll_x > max_x || self.scroll_y > max_y {
+      self.scroll_to(max_x.min(self.scroll_x), max_y.min(self.scroll_y));
+    }
+
+    if let (Some(ref mut surface), Some(canvas)) = (self.surface.as_mut(), self.engine.canvas()) {
+      surface.resize(buffer_width, buffer_height).map_err(|e| ViewerError::Surface(e.to_string()))?;
+      let mut buffer = surface.buffer_mut().map_err(|e| ViewerError::Surface(e.to_string()))?;
+      blit(canvas, &mut buffer);
+      buffer.present().map_err(|e| ViewerError::Surface(e.to_string()))?;
+    }
+    return Ok(());
+  }
+
+  // 失敗したら前のドキュメントを表示したままにする
+  fn reload(&mut self) {
+    if let Err(error) = self.engine.reload() {
+      error!("{}", error);
+      return;
+    }
+    self.document_changed();
+    if let Some(document) = self.engine.document() {
+      info!("Reloaded {}", document.sources[0]);
+    }
+  }
+
+  // 読み込み直したり別のドキュメントにしたりした後に、監視するファイルとタイトルを変えて描き直す
+  fn document_changed(&mut self) {
+    // ドキュメント順の番号が変わっているかもしれないので、ハイライトは消す
+    self.engine.set_highlight(None);
+    if let (Some(ref mut watcher), Some(document)) = (self.watcher.as_mut(), self.engine.document()) {
+      if let Err(error) = watcher.watch(&document.sources) {
+        warn!("{}", error);
+      }
+    }
+    self.update_title();
+    if let Some(ref window) = self.window {
+      window.request_redraw();
+    }
+  }
+
+  // url のドキュメントを表示して、fragment の id の要素（なければ先頭）までスクロールする。
+  // 失敗したら前のドキュメントを表示したままにして false
+  fn navigate(&mut self, url: &Url) -> bool {
+    if let Err(error) = self.engine.navigate(url) {
+      error!("{}", error);
+      return false;
+    }
+    info!("Navigated to {}", url);
+    self.document_changed();
+    let target = url.fragment.as_ref().and_then(|id| self.engine.element_rect(&format!("[id=\"{}\"]", id)).ok());
+    self.scroll_x = 0.0;
+    self.scroll_y = 0.0;
+    if let Some(target) = target {
+      self.scroll_to(target.x, target.y);
+    }
+    return true;
+  }
+
+  // 履歴を delta だけ戻るか進む
+  fn go(&mut self, delta: isize) {
+    let url = match self.history.entry(delta) {
+      Some(url) => url.clone(),
+      None => return,
+    };
+    if self.navigate(&url) {
+      self.history.go(delta);
+    }
+  }
+
+  // カーソルの下がリンクならたどる。リンクでなければ false
+  fn follow_link_at_cursor(&mut self) -> bool {
+    let (x, y) = self.cursor;
+    let url = match self.engine.link_at(x, y) {
+      Ok(Some(url)) => url,
+      Ok(None) => return false,
+      Err(error) => {
+        error!("{}", error);
+        return false;
+      }
+    };
+    if self.navigate(&url) {
+      self.history.push(url);
+    }
+    return true;
+  }
+
+  fn viewport_size(&self) -> (f32, f32) {
+    return match self.window {
+      Some(ref window) => {
+        let size = window.inner_size();
+        (size.width as f32, size.height as f32)
+      }
+      None => (0.0, 0.0),
+    };
+  }
+
+  // スクロールできる最大位置。ドキュメントがビューポートより小さければ 0
+  fn max_scroll(&self) -> (f32, f32) {
+    let (width, height) = self.viewport_size();
+    let overflow = self.engine.scrollable_overflow();
+    return ((overflow.x + overflow.width - width).max(0.0), (overflow.y + overflow.height - height).max(0.0));
+  }
+
+  fn scroll_to(&mut self, x: f32, y: f32) {
+    let (max_x, max_y) = self.max_scroll();
+    let (x, y) = (x.max(0.0).min(max_x), y.max(0.0).min(max_y));
+    if x != self.scroll_x || y != self.scroll_y {
+      self.scroll_x = x;
+      self.scroll_y = y;
+      if let Some(ref window) = self.window {
+        window.request_redraw();
+      }
+    }
+  }
+
+  fn scroll_by(&mut self, dx: f32, dy: f32) {
+    let (x, y) = (self.scroll_x + dx, self.scroll_y + dy);
+    self.scroll_to(x, y);
+  }
+
+  // event をドキュメントに届けて、:hover が変わったら描き直す
+  fn dispatch(&mut self, event: Event) {
+    match event::dispatch(&mut self.engine, event) {
+      Ok(true) => {
+        if let Some(ref window) = self.window {
+          window.request_redraw();
+        }
+      }
+      Ok(false) => {}
+      Err(error) => error!("{}", error),
+    }
+  }
+
+  // カーソルの下の要素をハイライトして、一致したルールと値を書き出す。要素がなければハイライトを消す
+  fn inspect_at_cursor(&mut self) {
+    let (x, y) = self.cursor;
+    let index = match self.engine.element_at(x, y) {
+      Ok(index) => index,
+      Err(error) => {
+        error!("{}", error);
+        return;
+      }
+    };
+    self.engine.set_highlight(index);
+    if let (Some(index), Some(document)) = (index, self.engine.document()) {
+      match inspect::inspect_nodes(document, &[index], self.engine.viewport_rect()) {
+        Ok(inspections) => print!("{}", dump::inspect(&inspections, dump::Format::Text)),
+        Err(error) => error!("{}", error),
+      }
+    }
+    if let Some(ref window) = self.window {
+      window.request_redraw();
+    }
+  }
+
+  fn handle_key(&mut self, event: &KeyEvent) {
+    if event.state != ElementState::Pressed {
+      return;
+    }
+    let page = (self.viewport_size().1 - PAGE_OVERLAP).max(LINE_HEIGHT);
+    match event.logical_key {
+      Key::Named(NamedKey::ArrowUp) => self.scroll_by(0.0, -LINE_HEIGHT),
+      Key::Named(NamedKey::ArrowDown) => self.scroll_by(0.0, LINE_HEIGHT),
+      Key::Named(NamedKey::ArrowLeft) if self.modifiers.alt_key() => self.go(-1),
+      Key::Named(NamedKey::ArrowRight) if self.modifiers.alt_key() => self.go(1),
+      Key::Named(NamedKey::BrowserBack) => self.go(-1),
+      Key::Named(NamedKey::BrowserForward) => self.go(1),
+      Key::Named(NamedKey::ArrowLeft) => self.scroll_by(-LINE_HEIGHT, 0.0),
+      Key::Named(NamedKey::ArrowRight) => self.scroll_by(LINE_HEIGHT, 0.0),
+      Key::Named(NamedKey::PageUp) => self.scroll_by(0.0, -page),
+      Key::Named(NamedKey::PageDown) | Key::Named(NamedKey::Space) => self.scroll_by(0.0, page),
+      Key::Named(NamedKey::Home) => {
+        let x = self.scroll_x;
+        self.scroll_to(x, 0.0)
+      }
+      Key::Named(NamedKey::End) => {
+        let (x, y) = (self.scroll_x, self.max_scroll().1);
+        self.scroll_to(x, y)
+      }
+      Key::Named(NamedKey::Escape) => {
+        self.engine.set_highlight(None);
+        if let Some(ref window) = self.window {
+          window.request_redraw();
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+impl ApplicationHandler<Reload> for Viewer {
+  fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+    if self.window.is_some() {
+      return;
+    }
+    if let Err(error) = self.create_window(event_loop) {
+      self.error = Some(error.into());
+      event_loop.exit();
+    }
+  }
+
+  fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+    match event {
+      WindowEvent::CloseRequested => event_loop.exit(),
+      WindowEvent::Resized(_) => {
+        if let Some(ref window) = self.window {
+          window.request_redraw();
+        }
+      }
+      WindowEvent::MouseWheel { delta, .. } => {
+        // 下に回すと delta は負になる
+        let (dx, dy) = match delta {
+          MouseScrollDelta::LineDelta(x, y) => (x * LINE_HEIGHT, y * LINE_HEIGHT),
+          MouseScrollDelta::PixelDelta(position) => (position.x as f32, position.y as f32),
+        };
+        self.scroll_by(-dx, -dy);
+      }
+      WindowEvent::KeyboardInput { event, .. } => self.handle_key(&event),
+      WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
+      WindowEvent::CursorMoved { position, .. } => {
+        self.cursor = (position.x as f32, position.y as f32);
+        self.dispatch(Event::PointerMove { x: self.cursor.0, y: self.cursor.1 });
+      }
+      WindowEvent::CursorLeft { .. } => self.dispatch(Event::PointerLeave),
+      WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. } => {
+        if !self.follow_link_at_cursor() {
+          self.inspect_at_cursor();
+        }
+      }
+      WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Back, .. } => self.go(-1),
+      WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Forward, .. } => self.go(1),
+      WindowEvent::RedrawRequested => {
+        if let Err(error) = self.redraw() {
+          self.error = Some(error);
+          event_loop.exit();
+        }
+      }
+      _ => {}
+    }
+  }
+
+  fn user_event(&mut self, event_loop: &ActiveEventLoop, _event: Reload) {
+    let reload_at = Instant::now() + watch::DEBOUNCE;
+    self.reload_at = Some(reload_at);
+    event_loop.set_control_flow(ControlFlow::WaitUntil(reload_at));
+  }
+
+  fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+    if let Some(reload_at) = self.reload_at {
+      if Instant::now() >= reload_at {
+        self.reload_at = None;
+        event_loop.set_control_flow(ControlFlow::Wait);
+        self.reload();
+      }
+    }
+  }
+}
+
+// Canvas の RGBA を softbuffer の 0RGB に詰め替える
+fn blit(canvas: &Canvas, buffer: &mut [u32]) {
+  for (dst, color) in buffer.iter_mut().zip(canvas.pixels.iter()) {
+    *dst = (color.r as u32) << 16 | (color.g as u32) << 8 | color.b as u32;
+  }
+}
+
+// input と stylesheets は load_document と同じ。ウィンドウの初期サイズは options のビューポート。
+// watch が true なら変更のたびに読み込み直す
+pub fn run(input: &str, stylesheets: &[String], options: &EngineOptions, watch: bool) -> Result<(), EngineError> {
+  let mut engine = stylesheets.iter().fold(Engine::new(options.clone()).url(input), |engine, location| engine.stylesheet_url(location));
+  engine.update()?;
+  let sources = engine.document().map_or(Vec::new(), |document| document.sources.clone());
+  let event_loop = EventLoop::<Reload>::with_user_event().build().map_err(|e| ViewerError::EventLoop(e.to_string()))?;
+
+  let watcher = if watch {
+    let proxy = event_loop.create_proxy();
+    let mut watcher = Watcher::new(move || {
+      let _ = proxy.send_event(Reload);
+    })?;
+    watcher.watch(&sources)?;
+    Some(watcher)
+  } else {
+    None
+  };
+
+  let mut viewer = Viewer {
+    engine: engine,
+    watcher: watcher,
+    reload_at: None,
+    initial_size: LogicalSize::new(options.viewport_width, options.viewport_height),
+    window: None,
+    surface: None,
+    scroll_x: 0.0,
+    scroll_y: 0.0,
+    cursor: (0.0, 0.0),
+    modifiers: ModifiersState::empty(),
+    history: History::new(Url::from_location(input)),
+    error: None,
+  };
+  event_loop.run_app(&mut viewer).map_err(|e| ViewerError::EventLoop(e.to_string()))?;
+  return match viewer.error {
+    Some(error) => Err(error),
+    None => Ok(()),
+  };
+}
@@ -0,0 +1,97 @@
+use dom::{Node, NodeType};
+use layout::Rect;
+
+/**
+ * <meta name="viewport" content="width=device-width, initial-scale=1"> を読むところ。
+ * モバイル向けのページは、端末の幅（-W、viewport_width）とは違う幅でレイアウトして、拡大か縮小して描くことを求める。
+ *
+ *   width=N           N px の幅でレイアウトして、端末の幅に合わせて拡大か縮小する
+ *   width=device-width 端末の幅でレイアウトする
+ *   initial-scale=S   S 倍で描く（width がなければ、端末の幅 / S でレイアウトする）
+ *   minimum-scale、maximum-scale で S を制限する
+ *
+ * トップレベルのドキュメントだけに使う（iframe の中では無視する）。EngineOptions::meta_viewport が false なら使わない。
+ * ページのズーム（EngineOptions::zoom）は、これで決まったビューポートと倍率にさらに掛ける
+ */
+
+const MIN_SCALE: f32 = 0.1;
+const MAX_SCALE: f32 = 10.0;
+const MAX_WIDTH: f32 = 10000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViewportWidth {
+  DeviceWidth,
+  Px(f32),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ViewportMeta {
+  pub width: Option<ViewportWidth>,
+  pub initial_scale: Option<f32>,
+  pub minimum_scale: Option<f32>,
+  pub maximum_scale: Option<f32>,
+}
+
+impl ViewportMeta {
+  // content 属性。"," か ";" で区切った name=value で、知らないものや読めない値は無視する
+  pub fn parse(content: &str) -> ViewportMeta {
+    let mut meta = ViewportMeta::default();
+    for part in content.split(|c| c == ',' || c == ';') {
+      let mut pair = part.splitn(2, '=');
+      let name = pair.next().unwrap_or("").trim().to_ascii_lowercase();
+      let value = pair.next().unwrap_or("").trim().to_ascii_lowercase();
+      let number = value.trim_end_matches("px").parse::<f32>().ok().filter(|number| number.is_finite() && *number > 0.0);
+      match &*name {
+        "width" if value == "device-width" => meta.width = Some(ViewportWidth::DeviceWidth),
+        "width" => meta.width = number.map(|width| ViewportWidth::Px(width.max(1.0).min(MAX_WIDTH))),
+        "initial-scale" => meta.initial_scale = number,
+        "minimum-scale" => meta.minimum_scale = number,
+        "maximum-scale" => meta.maximum_scale = number,
+        _ => {}
+      }
+    }
+    return meta;
+  }
+
+  // device（端末のビューポート。x, y はスクロール位置）から、レイアウトに使うビューポートと描画の倍率を決める
+  pub fn resolve(&self, device: Rect) -> (Rect, f32) {
+    let clamp_scale = |scale: f32| {
+      let scale = scale.max(self.minimum_scale.unwrap_or(MIN_SCALE)).min(self.maximum_scale.unwrap_or(MAX_SCALE));
+      scale.max(MIN_SCALE).min(MAX_SCALE)
+    };
+    let initial_scale = self.initial_scale.map(clamp_scale);
+    let width = match (self.width, initial_scale) {
+      (Some(ViewportWidth::DeviceWidth), _) => device.width,
+      (Some(ViewportWidth::Px(width)), _) => width,
+      (None, Some(scale)) => device.width / scale,
+      (None, None) => device.width,
+    };
+    let scale = match initial_scale {
+      Some(scale) => scale,
+      None if width > 0.0 => clamp_scale(device.width / width),
+      None => 1.0,
+    };
+    return (Rect { x: device.x, y: device.y, width: width, height: device.height / scale }, scale);
+  }
+}
+
+// EngineOptions::zoom や device_pixel_ratio を描画に使える倍率にする（0 以下や NaN なら 1）
+pub fn zoom_factor(zoom: f32) -> f32 {
+  if !(zoom.is_finite() && zoom > 0.0) {
+    return 1.0;
+  }
+  return zoom.clamp(MIN_SCALE, MAX_SCALE);
+}
+
+// ドキュメント順で最初の <meta name="viewport" content="...">
+pub fn find(node: &Node) -> Option<ViewportMeta> {
+  if let NodeType::Element(ref elem) = node.node_type {
+    let is_viewport = elem.attributes.get("name").map_or(false, |name| name.eq_ignore_ascii_case("viewport"));
+    if elem.tag_name == "meta" && is_viewport {
+      if let Some(content) = elem.attributes.get("content") {
+        return Some(ViewportMeta::parse(content));
+      }
+    }
+  }
+  return node.children.iter().filter_map(find).next();
+}
@@ -0,0 +1,23 @@
+use layout::Rect;
+use options::EngineOptions;
+use render_source;
+use wasm_bindgen::prelude::*;
+
+/**
+ * JavaScript から呼ぶためのバインディング（wasm32 向け）
+ */
+
+// HTML と CSS を描画して、RGBA の並び（width * height * 4 バイト）を返す。
+// JS 側では new ImageData(new Uint8ClampedArray(bytes), width, height) で <canvas> に描ける
+#[wasm_bindgen]
+pub fn render(html: &str, css: &str, width: u32, height: u32) -> Result<Vec<u8>, JsValue> {
+  return render_scrolled(html, css, width, height, 0.0, 0.0);
+}
+
+// render と同じだが、ドキュメントを (scroll_x, scroll_y) までスクロールしたところを描く
+#[wasm_bindgen]
+pub fn render_scrolled(html: &str, css: &str, width: u32, height: u32, scroll_x: f32, scroll_y: f32) -> Result<Vec<u8>, JsValue> {
+  let viewport = Rect { x: scroll_x, y: scroll_y, width: width as f32, height: height as f32 };
+  let rendered = render_source(html, css, viewport, &EngineOptions::default()).map_err(|error| JsValue::from_str(&error.to_string()))?;
+  return Ok(rendered.canvas.to_rgba_bytes());
+}
@@ -0,0 +1,100 @@
+use notify::Watcher as NotifyWatcher;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use url::Url;
+
+/**
+ * --watch で入力ファイルの変更を監視する。
+ * エディタは保存時にファイルを置き換えることがあるので、ファイルではなく親ディレクトリを監視して、
+ * 読み込んだファイルへのイベントだけを拾う
+ */
+
+// 保存時にはイベントがまとめて来るので、この間待ってから読み込み直す
+pub const DEBOUNCE: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Error)]
+pub enum WatchError {
+  #[error("failed to start file watcher: {0}")]
+  Init(String),
+  #[error("failed to watch {path}: {message}")]
+  Watch { path: String, message: String },
+}
+
+pub struct Watcher {
+  watcher: RecommendedWatcher,
+  files: Arc<Mutex<HashSet<PathBuf>>>, // イベントのスレッドと共有する
+  dirs: HashSet<PathBuf>,
+}
+
+impl Watcher {
+  // 監視しているファイルが変わるたびに on_change を（監視用のスレッドから）呼ぶ
+  pub fn new<F>(on_change: F) -> Result<Watcher, WatchError>
+  where
+    F: Fn() + Send + 'static,
+  {
+    let files = Arc::new(Mutex::new(HashSet::new()));
+    let watched = files.clone();
+    let watcher = notify::recommended_watcher(move |result: notify::Result<Event>| {
+      let event = match result {
+        Ok(event) => event,
+        Err(error) => {
+          warn!("watch error: {}", error);
+          return;
+        }
+      };
+      if let EventKind::Access(_) = event.kind {
+        return;
+      }
+      let changed = match watched.lock() {
+        Ok(files) => event.paths.iter().any(|path| files.contains(path)),
+        Err(_) => false,
+      };
+      if changed {
+        debug!("changed: {:?}", event.paths);
+        on_change();
+      }
+    })
+    .map_err(|error| WatchError::Init(error.to_string()))?;
+    return Ok(Watcher { watcher: watcher, files: files, dirs: HashSet::new() });
+  }
+
+  // 監視する対象を sources のうちローカルのファイルに置き換える
+  pub fn watch(&mut self, sources: &[Url]) -> Result<(), WatchError> {
+    let mut files = HashSet::new();
+    let mut dirs = HashSet::new();
+    for path in sources.iter().filter_map(|url| url.to_file_path()) {
+      let (dir, name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => (dir, name),
+        _ => continue,
+      };
+      // イベントのパスと比べられるように、ディレクトリはシンボリックリンクを解決しておく
+      let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+      files.insert(dir.join(name));
+      dirs.insert(dir);
+    }
+
+    if let Ok(mut watched) = self.files.lock() {
+      *watched = files;
+    }
+    let stale: Vec<PathBuf> = self.dirs.difference(&dirs).cloned().collect();
+    for dir in stale {
+      let _ = self.watcher.unwatch(&dir);
+      self.dirs.remove(&dir);
+    }
+    for dir in dirs {
+      if self.dirs.contains(&dir) {
+        continue;
+      }
+      self.watcher.watch(&dir, RecursiveMode::NonRecursive).map_err(|error| WatchError::Watch {
+        path: dir.display().to_string(),
+        message: error.to_string(),
+      })?;
+      self.dirs.insert(dir);
+    }
+    return Ok(());
+  }
+}
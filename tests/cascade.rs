@@ -0,0 +1,201 @@
+extern crate browser_engine_suburi;
+
+use browser_engine_suburi::css::{self, StyleSheet, Value};
+use browser_engine_suburi::dom::NodeType;
+use browser_engine_suburi::style::{self, StyledNode, StyledTree};
+use browser_engine_suburi::{html, load_document_source, EngineOptions, Rect};
+
+/**
+ * カスケードで、どのルールの宣言が値になるかを確かめる。
+ * 詳細度の高いものが勝ち、詳細度が同じならスタイルシートの後ろ（append したスタイルシートは前のものの後ろ）のものが勝つ
+ */
+
+// sheets を順に append して source に当て、id の要素の property の値を返す
+fn value_of(source: &str, sheets: &[&str], id: &str, property: &str) -> Option<Value> {
+  let root_node = html::parse(source.to_string()).unwrap();
+  let mut stylesheet = StyleSheet::default();
+  for sheet in sheets {
+    stylesheet.append(css::parse(sheet.to_string()).unwrap());
+  }
+  let style_root = style::style_tree(&root_node, &stylesheet);
+  return find(&style_root, id).unwrap_or_else(|| panic!("no element #{}", id)).value(property);
+}
+
+fn find<'a, 'b>(node: &'b StyledNode<'a>, id: &str) -> Option<&'b StyledNode<'a>> {
+  if let NodeType::Element(ref elem) = node.node.node_type {
+    if elem.attributes.get("id").map(|value| &**value) == Some(id) {
+      return Some(node);
+    }
+  }
+  return node.children.iter().filter_map(|child| find(child, id)).next();
+}
+
+fn keyword(keyword: &str) -> Option<Value> {
+  return Some(Value::Keyword(keyword.to_string()));
+}
+
+#[test]
+fn later_rule_wins_with_equal_specificity() {
+  let value = value_of("<p id=a class=x title=t></p>", &[".x { float: left; } [title] { float: right; }"], "a", "float");
+  assert_eq!(value, keyword("right"));
+  let value = value_of("<p id=a class=x title=t></p>", &["[title] { float: right; } .x { float: left; }"], "a", "float");
+  assert_eq!(value, keyword("left"));
+}
+
+#[test]
+fn later_stylesheet_wins_with_equal_specificity() {
+  // 前のスタイルシートのルールは ID ではなくクラスのところ、後のものは * のところから探すが、順番は変わらない
+  let value = value_of("<p id=a class=x title=t></p>", &["p.x { float: left; }", "p[title] { float: right; }"], "a", "float");
+  assert_eq!(value, keyword("right"));
+  let value = value_of("<p id=a class=x title=t></p>", &["p[title] { float: right; }", "p.x { float: left; }", "p { float: none; }"], "a", "float");
+  assert_eq!(value, keyword("left"));
+}
+
+#[test]
+fn higher_specificity_wins_regardless_of_order() {
+  let value = value_of("<div><p id=a class=x></p></div>", &["div p.x { float: left; }", "p.x { float: right; }", ".x { float: none; }"], "a", "float");
+  assert_eq!(value, keyword("left"));
+}
+
+#[test]
+fn selector_list_uses_the_most_specific_matching_selector() {
+  // p, #a は #a の詳細度（1, 0, 0）で、後ろの p.x（0, 1, 1）より強い
+  let value = value_of("<p id=a class=x></p>", &["p, #a { float: left; } p.x { float: right; }"], "a", "float");
+  assert_eq!(value, keyword("left"));
+}
+
+#[test]
+fn custom_properties_on_root_reach_var() {
+  // :root はクラスと同じ詳細度なので、後ろにある html のルールより強い。ルート要素にしか一致しない
+  let sheet = ":root { --side: left; float: right; } html { --side: right; } p { float: var(--side); }";
+  assert_eq!(value_of("<html><body><p id=a></p></body></html>", &[sheet], "a", "float"), keyword("left"));
+  let value = value_of("<html><body id=b><p></p></body></html>", &[":root { float: left; } :root > body { float: right; }"], "b", "float");
+  assert_eq!(value, keyword("right"));
+}
+
+#[test]
+fn media_type_is_checked_without_retain_media() {
+  // css::parse したままのスタイルシートは画面に描くものとして、@media print の中のルールは使わない
+  let sheet = "@media print { #a { float: left; } } @media not print { #a { clear: both; } } @media screen { #a { display: inline; } }";
+  let root_node = html::parse("<p id=a></p>".to_string()).unwrap();
+  let stylesheet = css::parse(sheet.to_string()).unwrap();
+  let viewport = Rect { x: 0.0, y: 0.0, width: 800.0, height: 600.0 };
+  for style_root in [style::style_tree(&root_node, &stylesheet), style::style_tree_with_viewport(&root_node, &stylesheet, viewport)] {
+    let styled = find(&style_root, "a").unwrap();
+    assert_eq!(styled.value("float"), None);
+    assert_eq!(styled.value("clear"), keyword("both"));
+    assert_eq!(styled.value("display"), keyword("inline"));
+  }
+  let stylesheet = css::parse("@media print and (min-width: 1px) { #a { float: left; } }".to_string()).unwrap();
+  assert_eq!(find(&style::style_tree_with_viewport(&root_node, &stylesheet, viewport), "a").unwrap().value("float"), None);
+}
+
+#[test]
+fn siblings_share_styles_only_when_the_same_rules_match() {
+  // <li> は前の兄弟と値を使い回せるかもしれないが、属性セレクターや位置の疑似クラスで一致するルールが違えば別の値になる（子が引き継ぐ値で確かめる）
+  let source = "<ul><li data-k=1><p id=a></p></li><li data-k=2><p id=b></p></li><li data-k=1><p id=c></p></li></ul>";
+  let sheet = "li { text-align: left; } li[data-k=\"2\"] { text-align: right; }";
+  assert_eq!(value_of(source, &[sheet], "a", "text-align"), keyword("left"));
+  assert_eq!(value_of(source, &[sheet], "b", "text-align"), keyword("right"));
+  assert_eq!(value_of(source, &[sheet], "c", "text-align"), keyword("left"));
+  let sheet = "li { text-align: left; } li:last-child { text-align: center; }";
+  assert_eq!(value_of(source, &[sheet], "b", "text-align"), keyword("left"));
+  assert_eq!(value_of(source, &[sheet], "c", "text-align"), keyword("center"));
+}
+
+#[test]
+fn before_and_after_become_the_first_and_last_children() {
+  let root_node = html::parse("<p id=a><b>x</b></p>".to_string()).unwrap();
+  let stylesheet = css::parse("p::after { content: \"!\"; } #a:before { content: \"[\"; } p::before { content: \"(\"; color: red; }".to_string()).unwrap();
+  let style_root = style::style_tree(&root_node, &stylesheet);
+  let p = find(&style_root, "a").unwrap();
+  // #a:before（1, 0, 1）が p::before（0, 0, 2）に勝つが、color は p::before のものが残る
+  assert_eq!(p.children.len(), 3);
+  assert!(p.children[0].pseudo_element().is_some() && p.children[2].pseudo_element().is_some());
+  assert_eq!(p.children[0].value("content"), Some(Value::Str("[".to_string())));
+  assert!(p.children[0].value("color").is_some());
+  assert_eq!(p.children[2].value("content"), Some(Value::Str("!".to_string())));
+  assert_eq!(p.value("content"), None);
+}
+
+#[test]
+fn missing_linked_stylesheet_is_skipped() {
+  let source = "<link rel=stylesheet href=\"missing-stylesheet.css\"><style>#a { color: red; }</style><p id=a>x</p>";
+  let options = EngineOptions { user_agent_stylesheet: false, ..EngineOptions::default() };
+  let document = load_document_source(source, &[], &options).unwrap();
+  let style_root = style::style_tree(&document.root_node, &document.stylesheet);
+  assert!(find(&style_root, "a").unwrap().value("color").is_some());
+  // 引数で渡したスタイルシートが読み込めなければエラーにする
+  assert!(load_document_source("<p>x</p>", &["missing-stylesheet.css".to_string()], &options).is_err());
+}
+
+#[test]
+fn negative_values_are_dropped_where_not_allowed() {
+  // 捨てた宣言の前の値が残る
+  let sheet = |declarations: &str| format!("#a {{ {} }}", declarations);
+  let root_node = html::parse("<p id=a></p>".to_string()).unwrap();
+  let value = |declarations: &str, property: &str| {
+    let (stylesheet, _) = css::parse_with_diagnostics(sheet(declarations));
+    return find(&style::style_tree(&root_node, &stylesheet), "a").unwrap().value(property);
+  };
+  assert_eq!(value("padding-left: 5px; padding-left: -5px", "padding-left"), Some(Value::Length(5.0, css::Unit::Px)));
+  assert_eq!(value("padding: 1px; padding: 2px -3px", "padding-right"), Some(Value::Length(1.0, css::Unit::Px)));
+  assert_eq!(value("width: -10%", "width"), None);
+  assert_eq!(value("font-size: -2em", "font-size"), None);
+  assert_eq!(value("line-height: -1.5", "line-height"), None);
+  assert_eq!(value("border-top-width: -1px", "border-top-width"), None);
+  assert_eq!(value("border: -1px solid red", "border-top-style"), None);
+  // margin とオフセットは負でもよい
+  assert_eq!(value("margin-top: -5px", "margin-top"), Some(Value::Length(-5.0, css::Unit::Px)));
+  assert_eq!(value("left: -1px", "left"), Some(Value::Length(-1.0, css::Unit::Px)));
+  let (_, errors) = css::parse_with_diagnostics(sheet("width: 1px; height: -1px"));
+  assert_eq!(errors, vec![css::CssError::NegativeValue { property: "height".to_string(), pos: 17 }]);
+}
+
+#[test]
+fn media_attribute_features_are_checked_against_the_viewport() {
+  let source = concat!(
+    "<style media=\"screen and (min-width: 1px)\">#a { float: left; }</style>",
+    "<style media=\"(max-width: 2000px)\">#a { clear: both; }</style>",
+    "<style media=\"(min-width: 2000px)\">#a { display: inline; }</style>",
+    "<style media=\"print and (min-width: 1px)\">#a { visibility: hidden; }</style>",
+    "<link rel=stylesheet media=\"(max-width: 2000px)\" href=\"data:text/css,%23a%7Bcolor:red%7D\">",
+    "<p id=a>x</p>"
+  );
+  let options = EngineOptions { user_agent_stylesheet: false, ..EngineOptions::default() };
+  let document = load_document_source(source, &[], &options).unwrap();
+  let value = |width: f32, property: &str| {
+    let viewport = Rect { x: 0.0, y: 0.0, width: width, height: 600.0 };
+    let style_root = style::style_tree_with_viewport(&document.root_node, &document.stylesheet, viewport);
+    return find(&style_root, "a").unwrap().value(property);
+  };
+  assert_eq!(value(800.0, "float"), keyword("left"));
+  assert_eq!(value(800.0, "clear"), keyword("both"));
+  assert!(value(800.0, "color").is_some());
+  assert_eq!(value(800.0, "display"), None);
+  assert_eq!(value(800.0, "visibility"), None);
+  // 幅が変われば、同じドキュメントでも合うものが変わる
+  assert_eq!(value(2400.0, "display"), keyword("inline"));
+  assert_eq!(value(2400.0, "clear"), None);
+  assert!(value(2400.0, "color").is_none());
+}
+
+#[test]
+fn restyle_keeps_media_rules_of_the_viewport() {
+  let mut root_node = html::parse("<p id=a>x</p>".to_string()).unwrap();
+  let stylesheet = css::parse("@media (min-width: 600px) { #a { float: left; } .b { clear: both; } }".to_string()).unwrap();
+  let viewport = Rect { x: 0.0, y: 0.0, width: 800.0, height: 600.0 };
+  let mut tree = StyledTree::with_viewport(&root_node, &stylesheet, viewport);
+  let id = find(&tree.style_tree(&root_node), "a").unwrap().node.id;
+  if let NodeType::Element(ref mut elem) = root_node.find_mut(id).unwrap().node_type {
+    elem.attributes.insert("class".to_string(), "b".to_string());
+  }
+  tree.restyle(&root_node, &stylesheet, id);
+  let style_root = tree.style_tree(&root_node);
+  assert_eq!(find(&style_root, "a").unwrap().value("float"), keyword("left"));
+  assert_eq!(find(&style_root, "a").unwrap().value("clear"), keyword("both"));
+  // 狭くすると @media のルールは使わない
+  let changed = tree.set_viewport(&root_node, &stylesheet, Rect { width: 400.0, ..viewport });
+  assert!(changed.contains(&id));
+  assert_eq!(find(&tree.style_tree(&root_node), "a").unwrap().value("float"), None);
+}
@@ -0,0 +1,39 @@
+extern crate browser_engine_suburi;
+
+use browser_engine_suburi::css;
+use browser_engine_suburi::diagnostic::{Diagnostic, Severity};
+use browser_engine_suburi::html;
+
+/**
+ * パーサーのエラーを、入力の中の行と列と、^ で印を付けた行にできるかを確かめる
+ */
+
+fn html_diagnostics(source: &str) -> Vec<Diagnostic> {
+  let (_, errors) = html::parse_with_diagnostics(source.to_string());
+  return errors.iter().map(|error| Diagnostic::from_html(source, error)).collect();
+}
+
+fn css_diagnostics(source: &str) -> Vec<Diagnostic> {
+  let (_, errors) = css::parse_with_diagnostics(source.to_string());
+  return errors.iter().map(|error| Diagnostic::from_css(source, error)).collect();
+}
+
+#[test]
+fn html_errors_point_at_the_tag_name() {
+  let source = "<div>\n  <p>日本語</b>\n<section>";
+  let diagnostics = html_diagnostics(source);
+  let spans: Vec<(Severity, usize, usize, &str)> = diagnostics.iter().map(|d| (d.severity, d.span.line, d.span.column, &source[d.span.start..d.span.end])).collect();
+  assert_eq!(spans, vec![(Severity::Warning, 2, 11, "b"), (Severity::Warning, 3, 2, "section"), (Severity::Warning, 1, 2, "div")]);
+  assert_eq!(diagnostics[0].message, "closing tag </b> has no open element");
+  assert_eq!(diagnostics[0].render("index.html", source), "warning: closing tag </b> has no open element\n --> index.html:2:11\n  |\n2 |   <p>日本語</b>\n  |              ^");
+}
+
+#[test]
+fn css_errors_cover_the_unreadable_text() {
+  let source = "p {\n  width: 10qq;\n  color: red\n}\n}{";
+  let diagnostics = css_diagnostics(source);
+  let spans: Vec<(usize, usize, &str)> = diagnostics.iter().map(|d| (d.span.line, d.span.column, &source[d.span.start..d.span.end])).collect();
+  assert_eq!(spans, vec![(2, 12, "qq"), (5, 1, "}"), (5, 3, "")]);
+  assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+  assert_eq!(diagnostics[0].message, "unrecognized unit \"qq\"");
+}
@@ -0,0 +1,120 @@
+extern crate browser_engine_suburi;
+
+use browser_engine_suburi::dom::NodeType;
+use browser_engine_suburi::images::Images;
+use browser_engine_suburi::layout::{self, FontMetrics, LayoutBox, Rect};
+use browser_engine_suburi::{css, html, initial_containing_block, style};
+
+/**
+ * ブロックの上下の margin が重なる位置を、手で計算した位置と比べる。
+ * 位置は id のある要素のボーダーボックスの上端
+ */
+
+// 文字を使わないので、大きさは何でもよい
+struct FixedMetrics;
+
+impl FontMetrics for FixedMetrics {
+  fn text_width(&self, text: &str, font_size: f32) -> f32 {
+    return text.chars().count() as f32 * font_size / 2.0;
+  }
+  fn line_height(&self, font_size: f32) -> f32 {
+    return font_size;
+  }
+  fn baseline(&self, font_size: f32) -> f32 {
+    return font_size * 0.8;
+  }
+}
+
+const BASE: &str = "html, body, div { display: block; } body { margin: 0; } div { height: 10px; } .parent { height: auto; } ";
+
+// source を sheet でレイアウトして、ids の要素のボーダーボックスを返す
+fn border_boxes(source: &str, sheet: &str, ids: &[&str]) -> Vec<Rect> {
+  let root_node = html::parse(source.to_string()).unwrap();
+  let stylesheet = css::parse(format!("{}{}", BASE, sheet)).unwrap();
+  let style_root = style::style_tree(&root_node, &stylesheet);
+  let viewport = Rect { x: 0.0, y: 0.0, width: 200.0, height: 200.0 };
+  let layout_root = layout::layout_tree_with(&style_root, initial_containing_block(viewport), &FixedMetrics, &Images::default()).unwrap();
+  return ids.iter().map(|id| find(&layout_root, id).unwrap_or_else(|| panic!("no box for #{}", id)).dimensions.border_box()).collect();
+}
+
+fn border_tops(source: &str, sheet: &str, ids: &[&str]) -> Vec<f32> {
+  return border_boxes(source, sheet, ids).iter().map(|rect| rect.y).collect();
+}
+
+fn heights(source: &str, sheet: &str, ids: &[&str]) -> Vec<f32> {
+  return border_boxes(source, sheet, ids).iter().map(|rect| rect.height).collect();
+}
+
+fn find<'a, 'b>(layout_box: &'b LayoutBox<'a>, id: &str) -> Option<&'b LayoutBox<'a>> {
+  if let Some(style) = layout_box.box_type.style_node() {
+    if let NodeType::Element(ref elem) = style.node.node_type {
+      if elem.attributes.get("id").map(|value| &**value) == Some(id) {
+        return Some(layout_box);
+      }
+    }
+  }
+  return layout_box.children.iter().filter_map(|child| find(child, id)).next();
+}
+
+#[test]
+fn sibling_margins_use_the_larger_one() {
+  // a: 0 から 10、a の下の 20 と b の上の 30 で 30 空ける
+  let tops = border_tops("<html><body><div id=a></div><div id=b></div></body></html>", "#a { margin-bottom: 20px; } #b { margin-top: 30px; }", &["a", "b"]);
+  assert_eq!(tops, vec![0.0, 40.0]);
+}
+
+#[test]
+fn negative_margin_is_added_to_the_positive_one() {
+  // 20 と -5 で 15、-10 と -4 で -10
+  let tops = border_tops(
+    "<html><body><div id=a></div><div id=b></div><div id=c></div></body></html>",
+    "#a { margin-bottom: 20px; } #b { margin-top: -5px; margin-bottom: -10px; } #c { margin-top: -4px; }",
+    &["a", "b", "c"],
+  );
+  assert_eq!(tops, vec![0.0, 25.0, 25.0]);
+}
+
+#[test]
+fn parent_and_first_child_margins_collapse() {
+  // before の下の 5、parent の上の 10、child の上の 25 で 25。child は parent の上端に付く
+  let tops = border_tops(
+    "<html><body><div id=before></div><div id=parent class=parent><div id=child></div></div></body></html>",
+    "#before { margin-bottom: 5px; } #parent { margin-top: 10px; } #child { margin-top: 25px; }",
+    &["parent", "child"],
+  );
+  assert_eq!(tops, vec![35.0, 35.0]);
+}
+
+#[test]
+fn padding_border_and_flow_root_keep_child_margin_inside() {
+  let source = "<html><body><div id=parent class=parent><div id=child></div></div></body></html>";
+  let sheet = "#parent { margin-top: 10px; } #child { margin-top: 25px; } ";
+  assert_eq!(border_tops(source, &format!("{}#parent {{ padding-top: 1px; }}", sheet), &["parent", "child"]), vec![10.0, 36.0]);
+  assert_eq!(border_tops(source, &format!("{}#parent {{ border-top: 2px solid; }}", sheet), &["parent", "child"]), vec![10.0, 37.0]);
+  assert_eq!(border_tops(source, &format!("{}#parent {{ display: flow-root; }}", sheet), &["parent", "child"]), vec![10.0, 35.0]);
+}
+
+#[test]
+fn negative_margin_of_last_child_does_not_make_height_negative() {
+  // child の下端（10）から 20 戻ると -10 になるが、auto の高さは 0 で止める
+  let source = "<html id=html><body id=body><div id=parent class=parent><div id=child></div></div></body></html>";
+  let sheet = "html { height: auto; } body { height: auto; } #child { margin-bottom: -20px; }";
+  assert_eq!(heights(source, sheet, &["parent", "body", "html"]), vec![0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn negative_margin_of_block_in_inline_does_not_make_height_negative() {
+  // span の中の a は行を閉じて置くブロック。a の下端から 30 戻っても、行を並べる anonymous ブロックの高さは 0 で、b はその下
+  let source = "<html><body><span><div id=a></div></span><div id=b></div></body></html>";
+  let sheet = "#a { margin-bottom: -30px; }";
+  assert_eq!(border_tops(source, sheet, &["a", "b"]), vec![0.0, 0.0]);
+}
+
+#[test]
+fn negative_margin_of_first_child_does_not_make_height_negative() {
+  // a の上の -20 は body の上の margin とまとまる。html は padding があるのでまとめず、中身の下端が上端より 9 上になるが、高さは 0（padding だけ）
+  let source = "<html id=html><body id=body><div id=a></div></body></html>";
+  let sheet = "html { height: auto; padding-top: 1px; } body { height: auto; } #a { margin-top: -20px; }";
+  assert_eq!(border_tops(source, sheet, &["body", "a"]), vec![-19.0, -19.0]);
+  assert_eq!(heights(source, sheet, &["body", "html"]), vec![10.0, 1.0]);
+}
@@ -0,0 +1,141 @@
+extern crate browser_engine_suburi;
+
+use browser_engine_suburi::css::{self, CssError};
+use browser_engine_suburi::error::EngineError;
+use browser_engine_suburi::generate::{self, GeneratorOptions, Rng, SelectorMix};
+use browser_engine_suburi::images::Images;
+use browser_engine_suburi::layout::{self, LayoutBox, LayoutError, Rect};
+use browser_engine_suburi::options::EngineOptions;
+use browser_engine_suburi::{html, initial_containing_block, render_source, style, user_agent_stylesheet};
+
+/**
+ * generate で作ったランダムな入力に対して、どんな入力でも成り立つはずの性質を確かめる。
+ * 失敗したら、メッセージのシードで同じ入力を作り直せる
+ */
+
+const CASES: u64 = 200;
+
+fn options_for(seed: u64) -> GeneratorOptions {
+  // 深くて細いものと、浅くて広いものを混ぜる
+  let mut options = GeneratorOptions::default();
+  if seed % 3 == 1 {
+    options.max_depth = 12;
+    options.max_children = 2;
+  } else if seed % 3 == 2 {
+    options.max_depth = 2;
+    options.max_children = 12;
+    options.selector_mix = SelectorMix { tag: 1, class: 1, id: 1, universal: 0, compound: 6 };
+  }
+  return options;
+}
+
+fn viewport_for(rng: &mut Rng) -> Rect {
+  return Rect { x: rng.below(50) as f32, y: rng.below(50) as f32, width: 1.0 + rng.below(400) as f32, height: 1.0 + rng.below(300) as f32 };
+}
+
+#[test]
+fn generated_inputs_parse() {
+  for seed in 0..CASES {
+    let mut rng = Rng::new(seed);
+    let options = options_for(seed);
+    let source = generate::document(&mut rng, &options);
+    let sheet = generate::stylesheet(&mut rng, &options);
+    assert!(html::parse(source.clone()).is_ok(), "seed {}: HTML did not parse\n{}", seed, source);
+    // 負の値を使えないプロパティの負の値だけは、宣言ごと捨てる
+    let (_, errors) = css::parse_with_diagnostics(sheet.clone());
+    assert!(errors.iter().all(|error| matches!(*error, CssError::NegativeValue { .. })), "seed {}: CSS did not parse: {:?}\n{}", seed, errors, sheet);
+  }
+}
+
+#[test]
+fn layout_never_produces_nan_or_negative_sizes() {
+  for seed in 0..CASES {
+    let mut rng = Rng::new(seed);
+    let options = options_for(seed);
+    let root_node = html::parse(generate::document(&mut rng, &options)).unwrap();
+    let mut stylesheet = user_agent_stylesheet(&EngineOptions::default()).unwrap();
+    stylesheet.rules.extend(css::parse_with_diagnostics(generate::stylesheet(&mut rng, &options)).0.rules);
+
+    let style_root = style::style_tree(&root_node, &stylesheet);
+    match layout::layout_tree(&style_root, initial_containing_block(viewport_for(&mut rng)), &Images::default()) {
+      Ok(layout_root) => check_box(&layout_root, seed),
+      Err(LayoutError::RootDisplayNone) => {} // * { display: none; } など。描画するものがない
+      Err(error) => panic!("seed {}: {}", seed, error),
+    }
+  }
+}
+
+// 内容、パディング、ボーダーは有限で 0 以上。マージンは負になりうるが有限
+fn check_box(layout_box: &LayoutBox, seed: u64) {
+  let d = layout_box.dimensions;
+  let sizes = [
+    d.content.width,
+    d.content.height,
+    d.padding.top,
+    d.padding.right,
+    d.padding.bottom,
+    d.padding.left,
+    d.border.top,
+    d.border.right,
+    d.border.bottom,
+    d.border.left,
+  ];
+  for &size in sizes.iter() {
+    assert!(size.is_finite() && size >= 0.0, "seed {}: invalid size {} in {:?}", seed, size, d);
+  }
+  let positions = [d.content.x, d.content.y, d.margin.top, d.margin.right, d.margin.bottom, d.margin.left];
+  for &position in positions.iter() {
+    assert!(position.is_finite(), "seed {}: invalid position {} in {:?}", seed, position, d);
+  }
+  for child in &layout_box.children {
+    check_box(child, seed);
+  }
+}
+
+#[test]
+fn paint_never_indexes_out_of_bounds() {
+  for seed in 0..CASES {
+    let mut rng = Rng::new(seed);
+    let options = options_for(seed);
+    let source = generate::document(&mut rng, &options);
+    let sheet = generate::stylesheet(&mut rng, &options);
+    let viewport = viewport_for(&mut rng);
+
+    // 範囲外に書き込もうとすればパニックする
+    let rendered = match render_source(&source, &sheet, viewport, &EngineOptions::default()) {
+      Ok(rendered) => rendered,
+      Err(EngineError::Layout(LayoutError::RootDisplayNone)) => continue,
+      Err(error) => panic!("seed {}: {}", seed, error),
+    };
+    assert_eq!(rendered.canvas.width, viewport.width as usize, "seed {}", seed);
+    assert_eq!(rendered.canvas.height, viewport.height as usize, "seed {}", seed);
+    assert_eq!(rendered.canvas.pixels.len(), rendered.canvas.width * rendered.canvas.height, "seed {}", seed);
+  }
+}
+
+#[test]
+fn huge_font_size_does_not_exhaust_memory() {
+  // 字形全体を描こうとすると、見えるのは一部だけでも被覆率のバッファに数十 GB を確保しようとして落ちていた
+  let viewport = Rect { x: 0.0, y: 0.0, width: 200.0, height: 100.0 };
+  let rendered = render_source("<p>x</p>", "p { font-size: 200000px; margin-top: -160000px; }", viewport, &EngineOptions::default()).unwrap();
+  assert_eq!(rendered.canvas.pixels.len(), 200 * 100);
+}
+
+#[test]
+fn deterministic_text_does_not_use_system_fonts() {
+  // deterministic なら、どのフォントが入っている環境でも文字は Monospace の幅で並ぶ（1 文字 font-size の半分）
+  let viewport = Rect { x: 0.0, y: 0.0, width: 200.0, height: 100.0 };
+  let options = EngineOptions { deterministic: true, ..EngineOptions::default() };
+  let rendered = render_source("<p>xxxx</p>", "p { display: block; font-size: 20px; margin: 0; }", viewport, &options).unwrap();
+  let dark = |x: usize| (0..rendered.canvas.height).any(|y| rendered.canvas.pixels[y * rendered.canvas.width + x].r < 128);
+  assert!(dark(35));
+  assert!(!dark(45));
+}
+
+#[test]
+fn same_seed_generates_same_input() {
+  let options = GeneratorOptions::default();
+  let (mut a, mut b) = (Rng::new(7), Rng::new(7));
+  assert_eq!(generate::document(&mut a, &options), generate::document(&mut b, &options));
+  assert_eq!(generate::stylesheet(&mut a, &options), generate::stylesheet(&mut b, &options));
+}
@@ -0,0 +1,19 @@
+use std::env;
+use std::process::Command;
+
+/**
+ * `cargo test` から reftests/ のリファレンステストを実行する（コマンドラインの --reftest をそのまま呼ぶ）。
+ * BLESS=1 を付けて実行すると、比較せずにリファレンス画像を書き直す
+ */
+
+#[test]
+fn reftests_match_reference_images() {
+  let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/reftests");
+  let mut command = Command::new(env!("CARGO_BIN_EXE_browser-engine-suburi"));
+  command.arg("--reftest").arg(dir); // --reftest の既定と同じビューポート
+  if env::var_os("BLESS").is_some() {
+    command.arg("--bless");
+  }
+  let status = command.status().expect("failed to run browser-engine-suburi");
+  assert!(status.success(), "some reftests failed (see the log, or run `cargo run -- --reftest reftests`)");
+}
@@ -0,0 +1,50 @@
+extern crate browser_engine_suburi;
+
+use browser_engine_suburi::css::Value;
+use browser_engine_suburi::dom::{Node, NodeType};
+use browser_engine_suburi::style::{self, StyledNode};
+use browser_engine_suburi::{load_document_source, EngineOptions};
+
+/**
+ * <script> の DOM API（getElementById、setAttribute、textContent、appendChild）で書き換えたドキュメントに、
+ * スタイルを当てることを確かめる（js フィーチャー）
+ */
+
+fn find<'a, 'b>(node: &'b StyledNode<'a>, id: &str) -> Option<&'b StyledNode<'a>> {
+  if let NodeType::Element(ref elem) = node.node.node_type {
+    if elem.attributes.get("id").map(|value| &**value) == Some(id) {
+      return Some(node);
+    }
+  }
+  return node.children.iter().filter_map(|child| find(child, id)).next();
+}
+
+fn text(node: &Node) -> String {
+  return match node.node_type {
+    NodeType::Text(ref text) => text.clone(),
+    _ => node.children.iter().map(text).collect(),
+  };
+}
+
+#[test]
+fn scripts_mutate_the_document_before_styling() {
+  let source = concat!(
+    "<style>.on { float: left; }</style>",
+    "<p id=a>old</p><div id=list></div>",
+    "<script>",
+    "var a = document.getElementById('a');",
+    "a.setAttribute('class', 'on');",
+    "a.textContent = 'new';",
+    "var item = document.createElement('span');",
+    "item.setAttribute('id', 'item');",
+    "document.getElementById('list').appendChild(item);",
+    "</script>"
+  );
+  let document = load_document_source(source, &[], &EngineOptions::default()).unwrap();
+  let style_root = style::style_tree(&document.root_node, &document.stylesheet);
+  let a = find(&style_root, "a").unwrap();
+  assert_eq!(text(a.node), "new");
+  // スクリプトが付けたクラスのルールが当たる
+  assert_eq!(a.value("float"), Some(Value::Keyword("left".to_string())));
+  assert!(find(find(&style_root, "list").unwrap(), "item").is_some());
+}
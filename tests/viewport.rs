@@ -0,0 +1,31 @@
+extern crate browser_engine_suburi;
+
+use browser_engine_suburi::layout::{self, Rect};
+use browser_engine_suburi::{initial_containing_block, load_document_source, style, EngineOptions};
+
+/**
+ * <meta name="viewport"> を読んで、端末の大きさと倍率から初期包含ブロックを決めることを確かめる。
+ * 端末は 800x600 で、幅はルートのボックスの幅
+ */
+
+const DEVICE: Rect = Rect { x: 0.0, y: 0.0, width: 800.0, height: 600.0 };
+
+// meta の content で空のドキュメントをレイアウトして、ルートのボックスの幅と描画の倍率を返す
+fn root_width_and_scale(content: Option<&str>) -> (f32, f32) {
+  let meta = content.map_or(String::new(), |content| format!("<meta name=\"viewport\" content=\"{}\">", content));
+  let document = load_document_source(&format!("<html><head>{}</head><body></body></html>", meta), &[], &EngineOptions::default()).unwrap();
+  let (viewport, scale) = document.layout_viewport(DEVICE);
+  let style_root = style::style_tree_with_viewport(&document.root_node, &document.stylesheet, viewport);
+  let layout_root = layout::layout_tree(&style_root, initial_containing_block(viewport), &document.images).unwrap();
+  return (layout_root.dimensions.content.width, scale);
+}
+
+#[test]
+fn viewport_meta_sets_the_initial_containing_block() {
+  assert_eq!(root_width_and_scale(None), (800.0, 1.0));
+  assert_eq!(root_width_and_scale(Some("width=device-width, initial-scale=1")), (800.0, 1.0));
+  // 400px でレイアウトして、端末の幅に合わせて 2 倍で描く
+  assert_eq!(root_width_and_scale(Some("width=400")), (400.0, 2.0));
+  assert_eq!(root_width_and_scale(Some("initial-scale=2")), (400.0, 2.0));
+  assert_eq!(root_width_and_scale(Some("width=device-width, initial-scale=20, maximum-scale=4")), (800.0, 4.0));
+}